@@ -0,0 +1,565 @@
+use super::{Record, Sink};
+use generic::counter::{Count, Frames, MessageCounters};
+use redact::{is_redacted, REDACTED};
+use headers;
+use {timeout, Code};
+
+use futures::{Future, Poll, Async};
+use h2;
+use http::{self, HeaderMap};
+use http::header::{HeaderName, HeaderValue};
+use tower::{NewService, Service};
+use tower_h2::Body;
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Writes a record of each call served by the inner service.
+///
+/// `AccessLog` may wrap either a `Service` or the `NewService` given to
+/// `tower_h2::Server`. As the peer's address is only known per connection,
+/// it is set with `peer` on the log built for each accepted connection.
+pub struct AccessLog<S> {
+    inner: S,
+    config: Arc<Config>,
+    peer: Option<SocketAddr>,
+}
+
+/// The response future returned by `AccessLog`.
+pub struct ResponseFuture<F> {
+    inner: F,
+    call: Option<Call>,
+}
+
+/// The response body returned by `AccessLog`.
+pub struct ResponseBody<B> {
+    inner: B,
+    call: Call,
+    frames: Frames,
+}
+
+/// Creates `AccessLog` services.
+pub struct NewServiceFuture<F> {
+    inner: F,
+    config: Arc<Config>,
+    peer: Option<SocketAddr>,
+}
+
+struct Config {
+    sink: Arc<Sink>,
+    metadata: Vec<HeaderName>,
+    redacted: Vec<HeaderName>,
+    slow_threshold: Option<Duration>,
+}
+
+/// A call being served; written as `CANCELED` if dropped before its status
+/// is known.
+struct Call {
+    config: Arc<Config>,
+    record: Record,
+    start: Instant,
+
+    /// The request headers, kept when slow calls are logged with all of
+    /// their metadata.
+    headers: Option<HeaderMap>,
+    request_bytes: Arc<RequestBytes>,
+    response_bytes: usize,
+    response_messages: usize,
+    done: bool,
+}
+
+/// Sums the length of the request messages decoded by the server.
+#[derive(Debug, Default)]
+struct RequestBytes(AtomicUsize);
+
+// ===== impl AccessLog =====
+
+impl<S> AccessLog<S> {
+    /// Write a record of each call served by `inner` to `sink`.
+    pub fn new<K>(inner: S, sink: K) -> Self
+    where K: Sink + 'static,
+    {
+        AccessLog {
+            inner,
+            config: Arc::new(Config {
+                sink: Arc::new(sink),
+                metadata: vec![],
+                redacted: vec![],
+                slow_threshold: None,
+            }),
+            peer: None,
+        }
+    }
+
+    /// Include the request metadata `key` in records.
+    ///
+    /// # Panics
+    ///
+    /// If `key` is not a valid header name, or the log has been cloned.
+    pub fn metadata(mut self, key: &str) -> Self {
+        let key = HeaderName::from_bytes(key.as_bytes())
+            .expect("metadata key is a valid header name");
+
+        self.config_mut().metadata.push(key);
+        self
+    }
+
+    /// Write `<redacted>` in place of the value of the request metadata
+    /// `key`, such as an API key.
+    ///
+    /// Values marked sensitive with `HeaderValue::set_sensitive` are always
+    /// redacted, as are credentials such as `authorization`, cookies, and
+    /// keys ending in `-token` or `-key`.
+    ///
+    /// # Panics
+    ///
+    /// If `key` is not a valid header name, or the log has been cloned.
+    pub fn redact(mut self, key: &str) -> Self {
+        let key = HeaderName::from_bytes(key.as_bytes())
+            .expect("metadata key is a valid header name");
+
+        self.config_mut().redacted.push(key);
+        self
+    }
+
+    /// Only write records of calls that take at least `threshold`.
+    ///
+    /// These records are marked `slow` and include all of the request's
+    /// metadata, rather than only the keys configured with `metadata`.
+    ///
+    /// # Panics
+    ///
+    /// If the log has been cloned.
+    pub fn slow_threshold(mut self, threshold: Duration) -> Self {
+        self.config_mut().slow_threshold = Some(threshold);
+        self
+    }
+
+    /// Record `peer` as the address of the client.
+    pub fn peer(mut self, peer: SocketAddr) -> Self {
+        self.peer = Some(peer);
+        self
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner service.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    fn config_mut(&mut self) -> &mut Config {
+        Arc::get_mut(&mut self.config)
+            .expect("access log is configured before it is cloned")
+    }
+}
+
+impl<S, A, B> Service for AccessLog<S>
+where S: Service<Request = http::Request<A>, Response = http::Response<B>>,
+      B: Body,
+      B::Data: AsRef<[u8]>,
+{
+    type Request = S::Request;
+    type Response = http::Response<ResponseBody<B>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, mut request: Self::Request) -> Self::Future {
+        let call = Call::new(self.config.clone(), self.peer, &request);
+
+        // Request bytes are counted as the server decodes messages.
+        MessageCounters::register(&mut request, call.request_bytes.clone());
+
+        ResponseFuture {
+            inner: self.inner.call(request),
+            call: Some(call),
+        }
+    }
+}
+
+impl<S, A, B> NewService for AccessLog<S>
+where S: NewService<Request = http::Request<A>, Response = http::Response<B>>,
+      B: Body,
+      B::Data: AsRef<[u8]>,
+{
+    type Request = S::Request;
+    type Response = http::Response<ResponseBody<B>>;
+    type Error = S::Error;
+    type Service = AccessLog<S::Service>;
+    type InitError = S::InitError;
+    type Future = NewServiceFuture<S::Future>;
+
+    fn new_service(&self) -> Self::Future {
+        NewServiceFuture {
+            inner: self.inner.new_service(),
+            config: self.config.clone(),
+            peer: self.peer,
+        }
+    }
+}
+
+impl<S> Clone for AccessLog<S>
+where S: Clone,
+{
+    fn clone(&self) -> Self {
+        AccessLog {
+            inner: self.inner.clone(),
+            config: self.config.clone(),
+            peer: self.peer,
+        }
+    }
+}
+
+impl<S> fmt::Debug for AccessLog<S>
+where S: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("AccessLog")
+            .field("inner", &self.inner)
+            .field("metadata", &self.config.metadata)
+            .field("slow_threshold", &self.config.slow_threshold)
+            .field("peer", &self.peer)
+            .finish()
+    }
+}
+
+// ===== impl NewServiceFuture =====
+
+impl<F> Future for NewServiceFuture<F>
+where F: Future,
+{
+    type Item = AccessLog<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        Ok(Async::Ready(AccessLog {
+            inner,
+            config: self.config.clone(),
+            peer: self.peer,
+        }))
+    }
+}
+
+impl<F> fmt::Debug for NewServiceFuture<F>
+where F: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("accesslog::NewServiceFuture")
+            .field("inner", &self.inner)
+            .field("peer", &self.peer)
+            .finish()
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F, B> Future for ResponseFuture<F>
+where F: Future<Item = http::Response<B>>,
+{
+    type Item = http::Response<ResponseBody<B>>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let response = match self.inner.poll() {
+            Ok(Async::Ready(response)) => response,
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Err(e) => {
+                if let Some(mut call) = self.call.take() {
+                    call.finish(Code::UNKNOWN, None);
+                }
+                return Err(e);
+            }
+        };
+
+        let mut call = self.call.take().expect("polled after complete");
+
+        // Trailers-only responses carry the status in the headers.
+        if let Some(code) = headers::status_code(response.headers()) {
+            call.finish(code, status_message(response.headers()));
+        }
+
+        let (head, inner) = response.into_parts();
+        let body = ResponseBody {
+            inner,
+            call,
+            frames: Frames::default(),
+        };
+
+        Ok(Async::Ready(http::Response::from_parts(head, body)))
+    }
+}
+
+impl<F> fmt::Debug for ResponseFuture<F>
+where F: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("accesslog::ResponseFuture")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+// ===== impl ResponseBody =====
+
+impl<B> Body for ResponseBody<B>
+where B: Body,
+      B::Data: AsRef<[u8]>,
+{
+    type Data = B::Data;
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, h2::Error> {
+        let data = try_ready!(self.inner.poll_data());
+
+        if let Some(ref data) = data {
+            let data = data.as_ref();
+            self.call.response_messages += self.frames.count(data);
+            self.call.response_bytes += data.len();
+        }
+
+        Ok(Async::Ready(data))
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, h2::Error> {
+        let trailers = try_ready!(self.inner.poll_trailers());
+
+        match trailers {
+            Some(ref trailers) => {
+                let code = headers::status_code(trailers).unwrap_or(Code::UNKNOWN);
+                self.call.finish(code, status_message(trailers));
+            }
+            None => self.call.finish(Code::UNKNOWN, None),
+        }
+
+        Ok(Async::Ready(trailers))
+    }
+}
+
+impl<B> fmt::Debug for ResponseBody<B>
+where B: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("accesslog::ResponseBody")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+// ===== impl Config =====
+
+impl Config {
+    /// Returns the value of `key` as written to records, if it is text.
+    fn loggable(&self, key: &HeaderName, value: &HeaderValue) -> Option<String> {
+        if is_redacted(&self.redacted, key, value) {
+            return Some(REDACTED.to_string());
+        }
+
+        value.to_str().ok().map(|value| value.to_string())
+    }
+}
+
+// ===== impl Call =====
+
+impl Call {
+    fn new<A>(config: Arc<Config>, peer: Option<SocketAddr>, request: &http::Request<A>) -> Self {
+        let headers = request.headers();
+
+        let metadata = config.metadata.iter()
+            .filter_map(|key| {
+                let value = headers.get(key)?;
+                Some((key.as_str().to_string(), config.loggable(key, value)?))
+            })
+            .collect();
+
+        let record = Record {
+            method: request.uri().path().to_string(),
+            peer,
+            deadline: headers.get("grpc-timeout").and_then(timeout::decode),
+            code: Code::UNKNOWN,
+            message: None,
+            duration: Default::default(),
+            request_bytes: 0,
+            response_bytes: 0,
+            metadata,
+            slow: false,
+        };
+
+        let headers = config.slow_threshold.map(|_| headers.clone());
+
+        Call {
+            config,
+            record,
+            start: Instant::now(),
+            headers,
+            request_bytes: Arc::new(RequestBytes::default()),
+            response_bytes: 0,
+            response_messages: 0,
+            done: false,
+        }
+    }
+
+    fn finish(&mut self, code: Code, message: Option<String>) {
+        if self.done {
+            return;
+        }
+
+        self.done = true;
+
+        let duration = self.start.elapsed();
+
+        if let Some(threshold) = self.config.slow_threshold {
+            if duration < threshold {
+                return;
+            }
+
+            self.record.slow = true;
+
+            if let Some(ref headers) = self.headers {
+                let config = &self.config;
+                self.record.metadata = headers.iter()
+                    .filter_map(|(key, value)| {
+                        Some((key.as_str().to_string(), config.loggable(key, value)?))
+                    })
+                    .collect();
+            }
+        }
+
+        // Each message sent is preceded by a five byte header.
+        let response_bytes = self.response_bytes
+            .saturating_sub(5 * self.response_messages);
+
+        self.record.code = code;
+        self.record.message = message;
+        self.record.duration = duration;
+        self.record.request_bytes = self.request_bytes.0.load(Ordering::Relaxed) as u64;
+        self.record.response_bytes = response_bytes as u64;
+
+        self.config.sink.write(&self.record);
+    }
+}
+
+impl Drop for Call {
+    fn drop(&mut self) {
+        self.finish(Code::CANCELED, None);
+    }
+}
+
+// ===== impl RequestBytes =====
+
+impl Count for RequestBytes {
+    fn message(&self, len: usize) {
+        self.0.fetch_add(len, Ordering::Relaxed);
+    }
+}
+
+// ===== utility fns =====
+
+fn status_message(headers: &HeaderMap) -> Option<String> {
+    headers.get("grpc-message")
+        .and_then(|s| s.to_str().ok())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use accesslog::{Formatter, KeyValue};
+
+    use bytes::Bytes;
+    use futures::future::{self, FutureResult};
+    use http::header::HeaderValue;
+
+    use std::sync::Mutex;
+
+    /// Answers every request with a trailers-only `OK` response.
+    struct MockService;
+
+    struct MockBody;
+
+    impl Service for MockService {
+        type Request = http::Request<()>;
+        type Response = http::Response<MockBody>;
+        type Error = ();
+        type Future = FutureResult<Self::Response, ()>;
+
+        fn poll_ready(&mut self) -> Poll<(), ()> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, _: Self::Request) -> Self::Future {
+            let mut response = http::Response::new(MockBody);
+            response.headers_mut()
+                .insert(headers::GRPC_STATUS.clone(), HeaderValue::from_static("0"));
+
+            future::ok(response)
+        }
+    }
+
+    impl Body for MockBody {
+        type Data = Bytes;
+
+        fn is_end_stream(&self) -> bool {
+            true
+        }
+
+        fn poll_data(&mut self) -> Poll<Option<Bytes>, h2::Error> {
+            Ok(Async::Ready(None))
+        }
+
+        fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, h2::Error> {
+            Ok(Async::Ready(None))
+        }
+    }
+
+    #[test]
+    fn slow_records_redact_credentials() {
+        let records = Arc::new(Mutex::new(vec![]));
+        let sink = {
+            let records = records.clone();
+            move |record: &Record| records.lock().unwrap().push(record.clone())
+        };
+
+        let mut log = AccessLog::new(MockService, sink)
+            .slow_threshold(Duration::from_secs(0));
+
+        let request = http::Request::builder()
+            .uri("/test.Service/Method")
+            .header("authorization", "Bearer secret-bearer")
+            .header("x-api-key", "secret-api-key")
+            .header("user-agent", "test-client")
+            .body(())
+            .unwrap();
+
+        log.call(request).wait().unwrap();
+
+        let records = records.lock().unwrap();
+        assert_eq!(records.len(), 1);
+
+        let record = &records[0];
+        assert!(record.slow);
+
+        let redacted = ("authorization".to_string(), REDACTED.to_string());
+        assert!(record.metadata.contains(&redacted));
+
+        let kept = ("user-agent".to_string(), "test-client".to_string());
+        assert!(record.metadata.contains(&kept));
+
+        let line = KeyValue.format(record);
+        assert!(!line.contains("secret"), "credential logged: {}", line);
+    }
+}
@@ -0,0 +1,270 @@
+//! Access logging.
+//!
+//! `AccessLog` wraps a server's HTTP/2.0 service and writes a `Record` for
+//! each call once it completes: its method, peer, deadline, status,
+//! duration and the bytes of the messages it carried, along with any request
+//! metadata the log was configured to include. Records are written to a
+//! `Sink`; `LogSink` and `WriterSink` render them with a `Formatter`, by
+//! default `KeyValue`.
+//!
+//! To investigate tail latency without logging every call, a log with a
+//! `slow_threshold` only writes the calls that take at least that long,
+//! with all of their request metadata. `LogSink` logs these at `WARN`.
+
+mod layer;
+
+pub use self::layer::{AccessLog, ResponseFuture, ResponseBody, NewServiceFuture};
+
+use Code;
+
+use log::LogLevel;
+
+use std::fmt::{self, Write};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The target `LogSink` logs records to.
+pub const TARGET: &'static str = "tower_grpc::access";
+
+/// A completed call.
+#[derive(Debug, Clone)]
+pub struct Record {
+    /// The method's path, such as `/helloworld.Greeter/SayHello`.
+    pub method: String,
+
+    /// The address of the client, if known.
+    pub peer: Option<SocketAddr>,
+
+    /// The timeout the client set with `grpc-timeout`.
+    pub deadline: Option<Duration>,
+
+    /// The call's status code. Calls dropped before completing are
+    /// `CANCELED`.
+    pub code: Code,
+
+    /// The status message sent in `grpc-message`.
+    pub message: Option<String>,
+
+    /// The time from receiving the request to completing the call.
+    pub duration: Duration,
+
+    /// Bytes of request messages received, excluding framing.
+    pub request_bytes: u64,
+
+    /// Bytes of response messages sent, excluding framing.
+    pub response_bytes: u64,
+
+    /// The request metadata the log was configured to include, in the order
+    /// configured. Slow calls include all of the request's metadata.
+    pub metadata: Vec<(String, String)>,
+
+    /// Set if the call took at least the log's slow threshold.
+    pub slow: bool,
+}
+
+/// Receives the records written by `AccessLog`.
+pub trait Sink: Send + Sync {
+    /// Record a completed call.
+    ///
+    /// This is called while the call is being polled, so it should not
+    /// block for long.
+    fn write(&self, record: &Record);
+}
+
+/// Renders a record as a single line.
+pub trait Formatter: Send + Sync {
+    fn format(&self, record: &Record) -> String;
+}
+
+/// Formats records as `key=value` pairs.
+///
+/// Request metadata is written with an `md.` prefix, and values that are
+/// not plain words are quoted.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct KeyValue;
+
+/// Writes records to the `log` crate under `TARGET`.
+///
+/// Slow calls are logged at `WARN`, regardless of the sink's level.
+#[derive(Debug, Clone)]
+pub struct LogSink<F = KeyValue> {
+    formatter: F,
+    level: LogLevel,
+}
+
+/// Writes one record per line to an `io::Write`.
+pub struct WriterSink<W, F = KeyValue> {
+    writer: Mutex<W>,
+    formatter: F,
+}
+
+// ===== impl Sink =====
+
+impl<K> Sink for Arc<K>
+where K: Sink + ?Sized,
+{
+    fn write(&self, record: &Record) {
+        (**self).write(record)
+    }
+}
+
+impl<F> Sink for F
+where F: Fn(&Record) + Send + Sync,
+{
+    fn write(&self, record: &Record) {
+        self(record)
+    }
+}
+
+// ===== impl KeyValue =====
+
+impl Formatter for KeyValue {
+    fn format(&self, record: &Record) -> String {
+        let mut line = String::new();
+
+        let _ = write!(line, "method={}", quote(&record.method));
+
+        if let Some(ref peer) = record.peer {
+            let _ = write!(line, " peer={}", peer);
+        }
+
+        if let Some(deadline) = record.deadline {
+            let _ = write!(line, " deadline_ms={:.3}", millis(deadline));
+        }
+
+        let _ = write!(line, " code={:?}", record.code);
+
+        if let Some(ref message) = record.message {
+            let _ = write!(line, " message={}", quote(message));
+        }
+
+        let _ = write!(line, " duration_ms={:.3} request_bytes={} response_bytes={}",
+                       millis(record.duration),
+                       record.request_bytes,
+                       record.response_bytes);
+
+        if record.slow {
+            line.push_str(" slow=true");
+        }
+
+        for &(ref key, ref value) in &record.metadata {
+            let _ = write!(line, " md.{}={}", key, quote(value));
+        }
+
+        line
+    }
+}
+
+// ===== impl LogSink =====
+
+impl LogSink {
+    /// Log `KeyValue` records at `INFO`.
+    pub fn new() -> Self {
+        LogSink::with_formatter(KeyValue)
+    }
+}
+
+impl<F> LogSink<F>
+where F: Formatter,
+{
+    /// Log records rendered by `formatter` at `INFO`.
+    pub fn with_formatter(formatter: F) -> Self {
+        LogSink {
+            formatter,
+            level: LogLevel::Info,
+        }
+    }
+
+    /// Log records at `level`.
+    pub fn level(mut self, level: LogLevel) -> Self {
+        self.level = level;
+        self
+    }
+}
+
+impl<F> Sink for LogSink<F>
+where F: Formatter,
+{
+    fn write(&self, record: &Record) {
+        let level = if record.slow { LogLevel::Warn } else { self.level };
+
+        if log_enabled!(target: TARGET, level) {
+            log!(target: TARGET, level, "{}", self.formatter.format(record));
+        }
+    }
+}
+
+// ===== impl WriterSink =====
+
+impl<W> WriterSink<W>
+where W: io::Write + Send,
+{
+    pub fn new(writer: W) -> Self {
+        WriterSink::with_formatter(writer, KeyValue)
+    }
+}
+
+impl<W, F> WriterSink<W, F>
+where W: io::Write + Send,
+      F: Formatter,
+{
+    pub fn with_formatter(writer: W, formatter: F) -> Self {
+        WriterSink {
+            writer: Mutex::new(writer),
+            formatter,
+        }
+    }
+
+    /// Consume the sink, returning the inner writer.
+    pub fn into_inner(self) -> W {
+        self.writer.into_inner().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<W, F> Sink for WriterSink<W, F>
+where W: io::Write + Send,
+      F: Formatter,
+{
+    fn write(&self, record: &Record) {
+        let mut line = self.formatter.format(record);
+        line.push('\n');
+
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Err(e) = writer.write_all(line.as_bytes()) {
+            warn!("failed to write access log record; err={:?}", e);
+        }
+    }
+}
+
+impl<W, F> fmt::Debug for WriterSink<W, F>
+where F: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("WriterSink")
+            .field("formatter", &self.formatter)
+            .finish()
+    }
+}
+
+// ===== utility fns =====
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs() as f64 * 1e3 + duration.subsec_nanos() as f64 / 1e6
+}
+
+/// Quote `value` unless it is made only of characters that need no
+/// escaping.
+fn quote(value: &str) -> String {
+    let plain = !value.is_empty() && value.chars().all(|c| {
+        c.is_ascii_alphanumeric() || "/._-:".contains(c)
+    });
+
+    if plain {
+        value.to_string()
+    } else {
+        format!("{:?}", value)
+    }
+}
@@ -0,0 +1,201 @@
+//! Operational services bundled together.
+//!
+//! `services()` returns a single service answering health checks and server
+//! reflection requests, so that every server can offer the same
+//! operational baseline. Calls to any other path fail with `UNIMPLEMENTED`;
+//! use `Admin::handles` to route only admin calls to it.
+
+use Status;
+use health::{self, Health, HealthReporter};
+use reflection::{self, DecodeError, ServerReflection};
+
+use bytes::Bytes;
+use futures::{future, Future, Poll};
+use h2;
+use http;
+use tower::{NewService, Service};
+use tower_h2::{Body, RecvBody};
+
+use std::fmt;
+
+/// Serves the health and reflection services.
+#[derive(Debug, Clone)]
+pub struct Admin {
+    health: Health,
+    reflection: ServerReflection,
+}
+
+/// The response future returned by `Admin`.
+pub struct ResponseFuture {
+    kind: Kind<health::ResponseFuture, reflection::ResponseFuture>,
+}
+
+/// The response body returned by `Admin`.
+pub struct ResponseBody {
+    kind: Kind<health::ResponseBody, reflection::ResponseBody>,
+}
+
+enum Kind<H, R> {
+    Health(H),
+    Reflection(R),
+    Unimplemented,
+}
+
+/// Returns the admin services, with the server reported as serving and no
+/// services available for reflection.
+pub fn services() -> Admin {
+    Admin {
+        health: Health::new(),
+        reflection: ServerReflection::new(),
+    }
+}
+
+// ===== impl Admin =====
+
+impl Admin {
+    /// Returns a handle used to set the statuses reported by health checks.
+    pub fn health_reporter(&self) -> HealthReporter {
+        self.health.reporter()
+    }
+
+    /// Make the files of an encoded `FileDescriptorSet` available through
+    /// reflection.
+    pub fn register_file_descriptor_set(mut self, encoded: &[u8]) -> Result<Self, DecodeError> {
+        self.reflection = self.reflection.register_file_descriptor_set(encoded)?;
+        Ok(self)
+    }
+
+    /// Returns true if calls to `path` are served by `Admin`.
+    pub fn handles(path: &str) -> bool {
+        Admin::route(path).is_some()
+    }
+
+    fn route(path: &str) -> Option<Kind<(), ()>> {
+        match path {
+            health::CHECK_PATH | health::WATCH_PATH => Some(Kind::Health(())),
+            reflection::V1_PATH | reflection::V1ALPHA_PATH => Some(Kind::Reflection(())),
+            _ => None,
+        }
+    }
+}
+
+impl Service for Admin {
+    type Request = http::Request<RecvBody>;
+    type Response = http::Response<ResponseBody>;
+    type Error = h2::Error;
+    type Future = ResponseFuture;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        Ok(().into())
+    }
+
+    fn call(&mut self, request: Self::Request) -> Self::Future {
+        let kind = match Admin::route(request.uri().path()) {
+            Some(Kind::Health(())) => Kind::Health(self.health.call(request)),
+            Some(Kind::Reflection(())) => Kind::Reflection(self.reflection.call(request)),
+            _ => Kind::Unimplemented,
+        };
+
+        ResponseFuture { kind }
+    }
+}
+
+impl NewService for Admin {
+    type Request = http::Request<RecvBody>;
+    type Response = http::Response<ResponseBody>;
+    type Error = h2::Error;
+    type Service = Self;
+    type InitError = h2::Error;
+    type Future = future::FutureResult<Self::Service, Self::InitError>;
+
+    fn new_service(&self) -> Self::Future {
+        future::ok(self.clone())
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl Future for ResponseFuture {
+    type Item = http::Response<ResponseBody>;
+    type Error = h2::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let (head, kind) = match self.kind {
+            Kind::Health(ref mut fut) => {
+                let (head, body) = try_ready!(fut.poll()).into_parts();
+                (head, Kind::Health(body))
+            }
+            Kind::Reflection(ref mut fut) => {
+                let (head, body) = try_ready!(fut.poll()).into_parts();
+                (head, Kind::Reflection(body))
+            }
+            Kind::Unimplemented => {
+                let body = ResponseBody { kind: Kind::Unimplemented };
+                let response = ::Response::trailers_only(&Status::UNIMPLEMENTED, body);
+                return Ok(response.into_http().into());
+            }
+        };
+
+        let body = ResponseBody { kind };
+        Ok(http::Response::from_parts(head, body).into())
+    }
+}
+
+impl fmt::Debug for ResponseFuture {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("admin::ResponseFuture")
+            .field("kind", &self.kind.name())
+            .finish()
+    }
+}
+
+// ===== impl ResponseBody =====
+
+impl Body for ResponseBody {
+    type Data = Bytes;
+
+    fn is_end_stream(&self) -> bool {
+        match self.kind {
+            Kind::Health(ref body) => body.is_end_stream(),
+            Kind::Reflection(ref body) => body.is_end_stream(),
+            Kind::Unimplemented => true,
+        }
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Bytes>, h2::Error> {
+        match self.kind {
+            Kind::Health(ref mut body) => body.poll_data(),
+            Kind::Reflection(ref mut body) => body.poll_data(),
+            Kind::Unimplemented => Ok(None.into()),
+        }
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, h2::Error> {
+        match self.kind {
+            Kind::Health(ref mut body) => body.poll_trailers(),
+            Kind::Reflection(ref mut body) => body.poll_trailers(),
+            // The status was sent in the headers.
+            Kind::Unimplemented => Ok(None.into()),
+        }
+    }
+}
+
+impl fmt::Debug for ResponseBody {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("admin::ResponseBody")
+            .field("kind", &self.kind.name())
+            .finish()
+    }
+}
+
+// ===== impl Kind =====
+
+impl<H, R> Kind<H, R> {
+    fn name(&self) -> &'static str {
+        match *self {
+            Kind::Health(_) => "Health",
+            Kind::Reflection(_) => "Reflection",
+            Kind::Unimplemented => "Unimplemented",
+        }
+    }
+}
@@ -0,0 +1,205 @@
+//! Arena allocation of decoded messages.
+//!
+//! A stream decoding many small messages makes an allocation for each of
+//! them. When decoding into an arena, the bytes of each message are instead
+//! carved from a larger chunk owned by the stream, and the chunk is reused
+//! once every message carved from it has been dropped. The arena is dropped
+//! with the stream, once the handler is done with it.
+//!
+//! Only decoders that keep the bytes of their messages, by calling
+//! `DecodeBuf::take_bytes` like `SharedBytesCodec`, decode into the arena.
+//! Protobuf messages own their fields, and are decoded as before.
+//!
+//! Servers decode into an arena by wrapping their service with
+//! `DecodeArena`:
+//!
+//! ```ignore
+//! let new_service = DecodeArena::new(new_service).chunk_size(64 * 1024);
+//! let h2 = Server::new(new_service, Default::default(), handle.clone());
+//! ```
+
+use bytes::{Buf, Bytes, BytesMut};
+use futures::{Future, Poll, Async};
+use http;
+use tower::{NewService, Service};
+
+use std::cmp;
+
+/// The default size of the chunks an `Arena` allocates.
+const DEFAULT_CHUNK_SIZE: usize = 16 * 1024;
+
+/// Per-stream storage that the bytes of decoded messages are carved from.
+#[derive(Debug)]
+pub struct Arena {
+    /// The unused part of the current chunk.
+    chunk: BytesMut,
+
+    /// The size of the chunks allocated.
+    chunk_size: usize,
+}
+
+/// Decodes the requests of the inner service into an arena per stream.
+///
+/// `DecodeArena` may wrap either a `Service` or the `NewService` given to
+/// `tower_h2::Server`.
+#[derive(Debug, Clone)]
+pub struct DecodeArena<S> {
+    inner: S,
+    config: ArenaConfig,
+}
+
+/// Creates `DecodeArena` services.
+#[derive(Debug)]
+pub struct NewServiceFuture<F> {
+    inner: F,
+    config: ArenaConfig,
+}
+
+/// Request extension asking the server's codec to decode into an arena.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ArenaConfig {
+    pub(crate) chunk_size: usize,
+}
+
+// ===== impl Arena =====
+
+impl Arena {
+    /// An arena allocating chunks of `chunk_size` bytes.
+    ///
+    /// Messages larger than a chunk get a chunk of their own.
+    pub fn new(chunk_size: usize) -> Self {
+        Arena {
+            chunk: BytesMut::new(),
+            chunk_size,
+        }
+    }
+
+    /// Copy the next `len` bytes of `src` into the arena.
+    pub fn copy<B: Buf>(&mut self, src: &mut B, len: usize) -> Bytes {
+        if self.chunk.capacity() < len {
+            // This reclaims the current chunk if nothing carved from it is
+            // still alive, and allocates a new one otherwise.
+            self.chunk.reserve(cmp::max(len, self.chunk_size));
+        }
+
+        copy_into(&mut self.chunk, src, len);
+        self.chunk.split_to(len).freeze()
+    }
+}
+
+impl Default for Arena {
+    fn default() -> Self {
+        Arena::new(DEFAULT_CHUNK_SIZE)
+    }
+}
+
+// ===== impl DecodeArena =====
+
+impl<S> DecodeArena<S> {
+    /// Decode the requests of `inner` into arenas of 16 KiB chunks.
+    pub fn new(inner: S) -> Self {
+        DecodeArena {
+            inner,
+            config: ArenaConfig {
+                chunk_size: DEFAULT_CHUNK_SIZE,
+            },
+        }
+    }
+
+    /// Allocate chunks of `size` bytes.
+    pub fn chunk_size(mut self, size: usize) -> Self {
+        self.config.chunk_size = size;
+        self
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner service.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+}
+
+impl<S, A> Service for DecodeArena<S>
+where S: Service<Request = http::Request<A>>,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, mut request: Self::Request) -> Self::Future {
+        request.extensions_mut().insert(self.config);
+        self.inner.call(request)
+    }
+}
+
+impl<S, A> NewService for DecodeArena<S>
+where S: NewService<Request = http::Request<A>>,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Service = DecodeArena<S::Service>;
+    type InitError = S::InitError;
+    type Future = NewServiceFuture<S::Future>;
+
+    fn new_service(&self) -> Self::Future {
+        NewServiceFuture {
+            inner: self.inner.new_service(),
+            config: self.config,
+        }
+    }
+}
+
+// ===== impl NewServiceFuture =====
+
+impl<F> Future for NewServiceFuture<F>
+where F: Future,
+{
+    type Item = DecodeArena<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+
+        Ok(Async::Ready(DecodeArena {
+            inner,
+            config: self.config,
+        }))
+    }
+}
+
+// ===== impl ArenaConfig =====
+
+impl ArenaConfig {
+    pub(crate) fn arena(&self) -> Arena {
+        Arena::new(self.chunk_size)
+    }
+}
+
+// ===== utility fns =====
+
+/// Append the next `len` bytes of `src` to `dst`.
+pub(crate) fn copy_into<B: Buf>(dst: &mut BytesMut, src: &mut B, len: usize) {
+    let mut rem = len;
+
+    while rem > 0 {
+        let n = {
+            let bytes = src.bytes();
+            let n = cmp::min(bytes.len(), rem);
+            dst.extend_from_slice(&bytes[..n]);
+            n
+        };
+
+        src.advance(n);
+        rem -= n;
+    }
+}
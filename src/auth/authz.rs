@@ -0,0 +1,568 @@
+//! Per-method authorization.
+//!
+//! `Authorize` checks each call against a `Policy` of rules, matched against
+//! the call's method in order. The first matching rule decides whether the
+//! call is admitted, using the identity that an authentication layer placed
+//! in the request's extensions:
+//!
+//! ```ignore
+//! let policy = Policy::new()
+//!     .rule(Rule::new("/grpc.health.v1.Health/*").allow_unauthenticated())
+//!     .rule(Rule::new("/admin.Admin/*").require_role("admin"))
+//!     .rule(Rule::new("*").require_claim("tenant", "acme"));
+//!
+//! let authorize = Authorize::<_, Claims>::new(server, policy);
+//! let new_service = ValidateJwt::new(authorize, validator).optional();
+//! ```
+//!
+//! The authentication layer must pass calls without credentials through, as
+//! `ValidateJwt::optional` does, for `allow_unauthenticated` rules to take
+//! effect.
+//!
+//! Calls without an identity fail with `UNAUTHENTICATED` and calls whose
+//! identity does not satisfy the rule fail with `PERMISSION_DENIED`. Calls
+//! to methods that match no rule are denied, unless the policy is built
+//! with `default_allow`.
+
+use super::{AuthBody, AuthFuture};
+use Status;
+
+use futures::{Future, Poll, Async};
+use http;
+use tower::{NewService, Service};
+use tower_h2::Body;
+
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// An authenticated caller, as found in request extensions.
+pub trait Identity: Send + Sync + 'static {
+    /// Returns the values of the claim `name`, or none if the identity does
+    /// not have it.
+    fn claim(&self, name: &str) -> Vec<String>;
+}
+
+/// Ordered authorization rules.
+#[derive(Debug, Clone)]
+pub struct Policy {
+    rules: Vec<Rule>,
+    roles_claim: String,
+    default_allow: bool,
+}
+
+/// An authorization rule for the methods matching a pattern.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pattern: Pattern,
+    access: Access,
+}
+
+/// Authorizes the calls to the inner service with a `Policy`.
+///
+/// `Authorize` may wrap either a `Service` or the `NewService` given to
+/// `tower_h2::Server`. The caller's identity is the request extension of
+/// type `I`.
+pub struct Authorize<S, I> {
+    inner: S,
+    policy: Arc<Policy>,
+    _p: PhantomData<fn(I)>,
+}
+
+/// Creates `Authorize` services.
+pub struct NewServiceFuture<F, I> {
+    inner: F,
+    policy: Arc<Policy>,
+    _p: PhantomData<fn(I)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Pattern {
+    /// Every method.
+    Any,
+
+    /// Every method of a service, by the `/package.Service/` prefix.
+    Service(String),
+
+    /// A single method.
+    Method(String),
+}
+
+#[derive(Debug, Clone)]
+enum Access {
+    /// Admit every call.
+    Unauthenticated,
+
+    /// Admit calls whose identity has every claim.
+    Require(Vec<Requirement>),
+
+    /// Refuse every call.
+    Deny,
+}
+
+#[derive(Debug, Clone)]
+enum Requirement {
+    Claim(String, String),
+    Role(String),
+}
+
+// ===== impl Policy =====
+
+impl Policy {
+    /// A policy without rules, denying every call.
+    pub fn new() -> Self {
+        Policy {
+            rules: vec![],
+            roles_claim: "roles".to_string(),
+            default_allow: false,
+        }
+    }
+
+    /// Add `rule`, checked after the rules added before it.
+    pub fn rule(mut self, rule: Rule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Read roles from the claim `name`, rather than `roles`.
+    pub fn roles_claim(mut self, name: &str) -> Self {
+        self.roles_claim = name.to_string();
+        self
+    }
+
+    /// Admit calls to methods that match no rule.
+    pub fn default_allow(mut self) -> Self {
+        self.default_allow = true;
+        self
+    }
+
+    /// Decide whether a call to `method` by `identity` is admitted.
+    pub fn check<I: Identity>(&self, method: &str, identity: Option<&I>) -> Result<(), Status> {
+        let rule = match self.rules.iter().find(|rule| rule.pattern.matches(method)) {
+            Some(rule) => rule,
+            None if self.default_allow => return Ok(()),
+            None => return Err(Status::PERMISSION_DENIED),
+        };
+
+        let requirements = match rule.access {
+            Access::Unauthenticated => return Ok(()),
+            Access::Deny => return Err(Status::PERMISSION_DENIED),
+            Access::Require(ref requirements) => requirements,
+        };
+
+        let identity = match identity {
+            Some(identity) => identity,
+            None => return Err(Status::UNAUTHENTICATED),
+        };
+
+        let satisfied = requirements.iter().all(|requirement| {
+            let (claim, value) = match *requirement {
+                Requirement::Claim(ref claim, ref value) => (&claim[..], value),
+                Requirement::Role(ref role) => (&self.roles_claim[..], role),
+            };
+
+            identity.claim(claim).iter().any(|v| v == value)
+        });
+
+        if satisfied {
+            Ok(())
+        } else {
+            Err(Status::PERMISSION_DENIED)
+        }
+    }
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy::new()
+    }
+}
+
+// ===== impl Rule =====
+
+impl Rule {
+    /// A rule for the methods matching `pattern`, admitting any
+    /// authenticated caller until requirements are added.
+    ///
+    /// The pattern is either `*`, a service such as `/helloworld.Greeter/*`,
+    /// or a method such as `/helloworld.Greeter/SayHello`.
+    pub fn new(pattern: &str) -> Self {
+        Rule {
+            pattern: Pattern::parse(pattern),
+            access: Access::Require(vec![]),
+        }
+    }
+
+    /// Require the caller's identity to have the claim `name` with `value`.
+    pub fn require_claim(mut self, name: &str, value: &str) -> Self {
+        let requirement = Requirement::Claim(name.to_string(), value.to_string());
+        self.require(requirement);
+        self
+    }
+
+    /// Require the caller to have `role`, read from the policy's roles
+    /// claim.
+    pub fn require_role(mut self, role: &str) -> Self {
+        self.require(Requirement::Role(role.to_string()));
+        self
+    }
+
+    /// Admit every call, including calls without an identity.
+    pub fn allow_unauthenticated(mut self) -> Self {
+        self.access = Access::Unauthenticated;
+        self
+    }
+
+    /// Refuse every call.
+    pub fn deny(mut self) -> Self {
+        self.access = Access::Deny;
+        self
+    }
+
+    fn require(&mut self, requirement: Requirement) {
+        match self.access {
+            Access::Require(ref mut requirements) => requirements.push(requirement),
+            _ => self.access = Access::Require(vec![requirement]),
+        }
+    }
+}
+
+// ===== impl Pattern =====
+
+impl Pattern {
+    fn parse(pattern: &str) -> Self {
+        if pattern == "*" {
+            Pattern::Any
+        } else if pattern.ends_with("/*") {
+            Pattern::Service(pattern[..pattern.len() - 1].to_string())
+        } else {
+            Pattern::Method(pattern.to_string())
+        }
+    }
+
+    fn matches(&self, method: &str) -> bool {
+        match *self {
+            Pattern::Any => true,
+            Pattern::Service(ref prefix) => method.starts_with(&prefix[..]),
+            Pattern::Method(ref m) => m == method,
+        }
+    }
+}
+
+// ===== impl Authorize =====
+
+impl<S, I> Authorize<S, I> {
+    pub fn new(inner: S, policy: Policy) -> Self {
+        Authorize {
+            inner,
+            policy: Arc::new(policy),
+            _p: PhantomData,
+        }
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner service.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+}
+
+impl<S, I, A, B> Service for Authorize<S, I>
+where S: Service<Request = http::Request<A>, Response = http::Response<B>>,
+      I: Identity,
+      B: Body,
+{
+    type Request = S::Request;
+    type Response = http::Response<AuthBody<B>>;
+    type Error = S::Error;
+    type Future = AuthFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, request: Self::Request) -> Self::Future {
+        let result = self.policy.check(request.uri().path(), request.extensions().get::<I>());
+
+        match result {
+            Ok(()) => AuthFuture::admitted(self.inner.call(request)),
+            Err(status) => {
+                debug!("call not authorized; path={}; code={:?}", request.uri().path(), status.code());
+                AuthFuture::rejected(status)
+            }
+        }
+    }
+}
+
+impl<S, I, A, B> NewService for Authorize<S, I>
+where S: NewService<Request = http::Request<A>, Response = http::Response<B>>,
+      I: Identity,
+      B: Body,
+{
+    type Request = S::Request;
+    type Response = http::Response<AuthBody<B>>;
+    type Error = S::Error;
+    type Service = Authorize<S::Service, I>;
+    type InitError = S::InitError;
+    type Future = NewServiceFuture<S::Future, I>;
+
+    fn new_service(&self) -> Self::Future {
+        NewServiceFuture {
+            inner: self.inner.new_service(),
+            policy: self.policy.clone(),
+            _p: PhantomData,
+        }
+    }
+}
+
+impl<S, I> Clone for Authorize<S, I>
+where S: Clone,
+{
+    fn clone(&self) -> Self {
+        Authorize {
+            inner: self.inner.clone(),
+            policy: self.policy.clone(),
+            _p: PhantomData,
+        }
+    }
+}
+
+impl<S, I> fmt::Debug for Authorize<S, I>
+where S: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Authorize")
+            .field("inner", &self.inner)
+            .field("policy", &self.policy)
+            .finish()
+    }
+}
+
+// ===== impl NewServiceFuture =====
+
+impl<F, I> Future for NewServiceFuture<F, I>
+where F: Future,
+{
+    type Item = Authorize<F::Item, I>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+
+        Ok(Async::Ready(Authorize {
+            inner,
+            policy: self.policy.clone(),
+            _p: PhantomData,
+        }))
+    }
+}
+
+impl<F, I> fmt::Debug for NewServiceFuture<F, I>
+where F: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("NewServiceFuture")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {headers, Code};
+
+    use bytes::Bytes;
+    use futures::future::{self, FutureResult};
+    use h2;
+    use http::HeaderMap;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// An identity holding a fixed set of claims.
+    struct MockIdentity(Vec<(&'static str, &'static str)>);
+
+    /// Answers every call with an empty response, counting them.
+    #[derive(Clone, Default)]
+    struct MockService {
+        calls: Arc<AtomicUsize>,
+    }
+
+    struct EmptyBody;
+
+    fn alice() -> MockIdentity {
+        MockIdentity(vec![("sub", "alice"), ("roles", "admin"), ("tenant", "acme")])
+    }
+
+    fn bob() -> MockIdentity {
+        MockIdentity(vec![("sub", "bob"), ("roles", "viewer"), ("groups", "admin")])
+    }
+
+    /// Returns the code `method` is refused with, if it is.
+    fn check(policy: &Policy, method: &str, identity: Option<&MockIdentity>) -> Option<Code> {
+        policy.check(method, identity).err().map(|status| status.code())
+    }
+
+    #[test]
+    fn allow_admits_calls_satisfying_the_rule() {
+        let policy = Policy::new()
+            .rule(Rule::new("/pkg.Public/*").allow_unauthenticated())
+            .rule(Rule::new("/pkg.Tenant/Get").require_claim("tenant", "acme"));
+
+        assert_eq!(check(&policy, "/pkg.Public/List", None), None);
+        assert_eq!(check(&policy, "/pkg.Public/List", Some(&bob())), None);
+        assert_eq!(check(&policy, "/pkg.Tenant/Get", Some(&alice())), None);
+    }
+
+    #[test]
+    fn deny_refuses_every_caller() {
+        let policy = Policy::new()
+            .rule(Rule::new("/pkg.Admin/Drop").deny())
+            .rule(Rule::new("/pkg.Admin/*").require_role("admin"));
+
+        assert_eq!(check(&policy, "/pkg.Admin/Drop", Some(&alice())), Some(Code::PERMISSION_DENIED));
+        assert_eq!(check(&policy, "/pkg.Admin/Drop", None), Some(Code::PERMISSION_DENIED));
+
+        // The first matching rule decides, so later rules do not admit it.
+        assert_eq!(check(&policy, "/pkg.Admin/List", Some(&alice())), None);
+    }
+
+    #[test]
+    fn unmatched_methods_are_denied_by_default() {
+        let policy = Policy::new()
+            .rule(Rule::new("/pkg.Public/*").allow_unauthenticated());
+
+        assert_eq!(check(&policy, "/pkg.Other/Get", Some(&alice())), Some(Code::PERMISSION_DENIED));
+        assert_eq!(check(&policy, "/pkg.Other/Get", None), Some(Code::PERMISSION_DENIED));
+        assert_eq!(check(&Policy::default(), "/pkg.Public/Get", None), Some(Code::PERMISSION_DENIED));
+
+        let policy = policy.default_allow();
+        assert_eq!(check(&policy, "/pkg.Other/Get", None), None);
+    }
+
+    #[test]
+    fn patterns_match_methods_services_and_everything() {
+        let policy = Policy::new()
+            .rule(Rule::new("/pkg.Greeter/SayHello").allow_unauthenticated())
+            .rule(Rule::new("/pkg.Health/*").allow_unauthenticated())
+            .rule(Rule::new("*").deny());
+
+        assert_eq!(check(&policy, "/pkg.Greeter/SayHello", None), None);
+        assert_eq!(check(&policy, "/pkg.Greeter/SayGoodbye", None), Some(Code::PERMISSION_DENIED));
+
+        assert_eq!(check(&policy, "/pkg.Health/Check", None), None);
+        assert_eq!(check(&policy, "/pkg.Health/Watch", None), None);
+
+        // A service pattern only matches whole service names.
+        assert_eq!(check(&policy, "/pkg.HealthAdmin/Check", None), Some(Code::PERMISSION_DENIED));
+        assert_eq!(check(&policy, "/other.pkg.Health/Check", None), Some(Code::PERMISSION_DENIED));
+    }
+
+    #[test]
+    fn requirements_match_the_callers_claims() {
+        let policy = Policy::new()
+            .rule(Rule::new("/pkg.Profile/*").require_claim("sub", "alice"))
+            .rule(Rule::new("/pkg.Admin/*").require_role("admin").require_claim("tenant", "acme"));
+
+        assert_eq!(check(&policy, "/pkg.Profile/Get", Some(&alice())), None);
+        assert_eq!(check(&policy, "/pkg.Profile/Get", Some(&bob())), Some(Code::PERMISSION_DENIED));
+        assert_eq!(check(&policy, "/pkg.Profile/Get", None), Some(Code::UNAUTHENTICATED));
+
+        // Every requirement must be satisfied.
+        assert_eq!(check(&policy, "/pkg.Admin/List", Some(&alice())), None);
+        let admin = MockIdentity(vec![("roles", "admin"), ("tenant", "other")]);
+        assert_eq!(check(&policy, "/pkg.Admin/List", Some(&admin)), Some(Code::PERMISSION_DENIED));
+    }
+
+    #[test]
+    fn roles_are_read_from_the_roles_claim() {
+        let policy = Policy::new()
+            .rule(Rule::new("/pkg.Admin/*").require_role("admin"));
+
+        assert_eq!(check(&policy, "/pkg.Admin/List", Some(&alice())), None);
+        assert_eq!(check(&policy, "/pkg.Admin/List", Some(&bob())), Some(Code::PERMISSION_DENIED));
+
+        let policy = policy.roles_claim("groups");
+        assert_eq!(check(&policy, "/pkg.Admin/List", Some(&alice())), Some(Code::PERMISSION_DENIED));
+        assert_eq!(check(&policy, "/pkg.Admin/List", Some(&bob())), None);
+    }
+
+    #[test]
+    fn authorize_reads_the_identity_from_request_extensions() {
+        let inner = MockService::default();
+        let policy = Policy::new()
+            .rule(Rule::new("/pkg.Admin/*").require_role("admin"));
+        let mut authorize = Authorize::<_, MockIdentity>::new(inner.clone(), policy);
+
+        let mut call = |identity: Option<MockIdentity>| {
+            let mut request = http::Request::builder()
+                .uri("/pkg.Admin/List")
+                .body(())
+                .unwrap();
+            if let Some(identity) = identity {
+                request.extensions_mut().insert(identity);
+            }
+
+            let response = authorize.call(request).wait().unwrap();
+            headers::status_code(response.headers())
+        };
+
+        assert_eq!(call(Some(alice())), None);
+        assert_eq!(call(Some(bob())), Some(Code::PERMISSION_DENIED));
+        assert_eq!(call(None), Some(Code::UNAUTHENTICATED));
+
+        // Only the admitted call reached the inner service.
+        assert_eq!(inner.calls.load(Ordering::SeqCst), 1);
+    }
+
+    // ===== impl MockIdentity =====
+
+    impl Identity for MockIdentity {
+        fn claim(&self, name: &str) -> Vec<String> {
+            self.0.iter()
+                .filter(|&&(claim, _)| claim == name)
+                .map(|&(_, value)| value.to_string())
+                .collect()
+        }
+    }
+
+    // ===== impl MockService =====
+
+    impl Service for MockService {
+        type Request = http::Request<()>;
+        type Response = http::Response<EmptyBody>;
+        type Error = ();
+        type Future = FutureResult<Self::Response, ()>;
+
+        fn poll_ready(&mut self) -> Poll<(), ()> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, _: http::Request<()>) -> Self::Future {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            future::ok(http::Response::new(EmptyBody))
+        }
+    }
+
+    // ===== impl EmptyBody =====
+
+    impl Body for EmptyBody {
+        type Data = Bytes;
+
+        fn is_end_stream(&self) -> bool {
+            true
+        }
+
+        fn poll_data(&mut self) -> Poll<Option<Bytes>, h2::Error> {
+            Ok(Async::Ready(None))
+        }
+
+        fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, h2::Error> {
+            Ok(Async::Ready(None))
+        }
+    }
+}
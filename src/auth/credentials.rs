@@ -0,0 +1,209 @@
+use Status;
+
+use futures::{Future, Poll, Async};
+use http::{self, HeaderMap};
+use http::uri::Scheme;
+use tower::Service;
+use tower_h2::HttpService;
+
+use std::fmt;
+
+/// Provides the metadata that authenticates each call, in the manner of
+/// grpc-go's `PerRPCCredentials`.
+///
+/// Implementations fetch and refresh credentials, such as OAuth2 access
+/// tokens, as needed; the future returned by `get_metadata` resolves once
+/// metadata is available for the call.
+pub trait CallCredentials {
+    /// Resolves to the metadata to attach to a call.
+    ///
+    /// A credentials error fails the call with the returned status, usually
+    /// `UNAUTHENTICATED`.
+    type Future: Future<Item = HeaderMap, Error = Status>;
+
+    /// Get the metadata for a call to `method`, a path such as
+    /// `/helloworld.Greeter/SayHello`.
+    fn get_metadata(&mut self, method: &str) -> Self::Future;
+
+    /// Returns true if the credentials may only be sent over a secure
+    /// transport.
+    ///
+    /// Calls whose URI has the `http` scheme then fail with
+    /// `UNAUTHENTICATED` rather than leaking the credentials.
+    fn require_transport_security(&self) -> bool {
+        true
+    }
+}
+
+/// Attaches the metadata of `CallCredentials` to the calls made through the
+/// inner service.
+///
+/// A call is only sent once its metadata is available, on a clone of the
+/// inner service, so a channel should be wrapped in a shareable service
+/// (such as a buffer) first.
+#[derive(Debug, Clone)]
+pub struct WithCredentials<S, C> {
+    inner: S,
+    credentials: C,
+}
+
+/// The response future returned by `WithCredentials`.
+pub struct ResponseFuture<S, F>
+where S: HttpService,
+{
+    state: State<S, F>,
+}
+
+enum State<S, F>
+where S: HttpService,
+{
+    /// Waiting for the call's metadata.
+    Metadata(F, Option<Pending<S>>),
+
+    /// Waiting for the service to accept the call.
+    Ready(Pending<S>),
+
+    /// Waiting for the response.
+    Calling(S::Future),
+
+    /// The call was refused before being sent.
+    Failed(Option<Status>),
+}
+
+struct Pending<S>
+where S: HttpService,
+{
+    service: S,
+    request: Option<http::Request<S::RequestBody>>,
+}
+
+// ===== impl WithCredentials =====
+
+impl<S, C> WithCredentials<S, C> {
+    pub fn new(inner: S, credentials: C) -> Self {
+        WithCredentials { inner, credentials }
+    }
+
+    /// Returns a reference to the credentials.
+    pub fn credentials(&self) -> &C {
+        &self.credentials
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner service.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+}
+
+impl<S, C, E> Service for WithCredentials<S, C>
+where S: HttpService<Error = ::Error<E>> + Clone,
+      C: CallCredentials,
+{
+    type Request = http::Request<S::RequestBody>;
+    type Response = http::Response<S::ResponseBody>;
+    type Error = ::Error<E>;
+    type Future = ResponseFuture<S, C::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, request: Self::Request) -> Self::Future {
+        let insecure = request.uri().scheme_part() == Some(&Scheme::HTTP);
+
+        if insecure && self.credentials.require_transport_security() {
+            debug!("refusing to send credentials over an insecure transport; uri={}", request.uri());
+
+            return ResponseFuture {
+                state: State::Failed(Some(Status::UNAUTHENTICATED)),
+            };
+        }
+
+        let metadata = self.credentials.get_metadata(request.uri().path());
+        let pending = Pending {
+            service: self.inner.clone(),
+            request: Some(request),
+        };
+
+        ResponseFuture {
+            state: State::Metadata(metadata, Some(pending)),
+        }
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<S, F, E> Future for ResponseFuture<S, F>
+where S: HttpService<Error = ::Error<E>>,
+      F: Future<Item = HeaderMap, Error = Status>,
+{
+    type Item = http::Response<S::ResponseBody>;
+    type Error = ::Error<E>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                State::Metadata(ref mut metadata, ref mut pending) => {
+                    let metadata = match metadata.poll() {
+                        Ok(Async::Ready(metadata)) => metadata,
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Err(status) => {
+                            debug!("failed to get call credentials; code={:?}", status.code());
+                            return Err(::Error::Grpc(status));
+                        }
+                    };
+
+                    let mut pending = pending.take().expect("polled after ready");
+
+                    if let Some(ref mut request) = pending.request {
+                        let headers = request.headers_mut();
+
+                        // Credentials are kept out of HPACK tables and logs.
+                        for (key, value) in metadata.iter() {
+                            let mut value = value.clone();
+                            value.set_sensitive(true);
+                            headers.append(key.clone(), value);
+                        }
+                    }
+
+                    State::Ready(pending)
+                }
+                State::Ready(ref mut pending) => {
+                    try_ready!(pending.service.poll_ready());
+
+                    let request = pending.request.take().expect("polled after ready");
+                    State::Calling(pending.service.call(request))
+                }
+                State::Calling(ref mut inner) => return inner.poll(),
+                State::Failed(ref mut status) => {
+                    let status = status.take().expect("polled after error");
+                    return Err(::Error::Grpc(status));
+                }
+            };
+
+            self.state = next;
+        }
+    }
+}
+
+impl<S, F> fmt::Debug for ResponseFuture<S, F>
+where S: HttpService,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let state = match self.state {
+            State::Metadata(..) => "Metadata",
+            State::Ready(..) => "Ready",
+            State::Calling(..) => "Calling",
+            State::Failed(..) => "Failed",
+        };
+
+        fmt.debug_struct("ResponseFuture")
+            .field("state", &state)
+            .finish()
+    }
+}
@@ -0,0 +1,1084 @@
+//! Validation of JSON Web Tokens sent as bearer tokens.
+//!
+//! `ValidateJwt` admits calls whose `authorization` metadata holds a valid
+//! `Bearer` JWT, signed with RS256 or ES256 by one of the keys of a
+//! `Validator`, and rejects the others with `UNAUTHENTICATED`. The claims of
+//! the token are placed in the request's extensions as `Claims`.
+//!
+//! In front of an `authz::Authorize` layer, `optional` admits calls without
+//! a token, so the policy decides which methods may be called without one.
+//!
+//! The validator's keys are either fixed, or fetched from a JWKS endpoint and
+//! refreshed periodically:
+//!
+//! ```ignore
+//! let validator = Validator::fetch(http_client, jwks_uri)
+//!     .issuer("https://issuer.example.com")
+//!     .audience("my-service");
+//!
+//! let new_service = ValidateJwt::new(GreeterServer::new(Greet), validator);
+//! ```
+
+use super::{AuthBody, AuthFuture};
+use super::authz::Identity;
+use {base64, Status};
+
+use bytes::Bytes;
+use futures::{Future, Poll, Async};
+use futures::future::{self, Shared};
+use http::{self, Method, Uri};
+use http::header::{self, HeaderMap};
+use ring::signature;
+use serde_json::{self, Value};
+use tower::{NewService, Service};
+use tower_h2::Body;
+use untrusted::Input;
+
+use std::{error, fmt};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Keys are refetched at most this often, in seconds, when a token names an
+/// unknown key.
+const MIN_REFETCH_SECS: u64 = 30;
+
+/// A failed fetch is retried after this many seconds.
+const RETRY_FETCH_SECS: u64 = 10;
+
+/// Keys are refetched after this many seconds.
+const REFRESH_SECS: u64 = 60 * 60;
+
+/// A set of JSON Web Keys.
+///
+/// Only RSA keys and EC keys on the P-256 curve are used.
+#[derive(Debug, Clone, Default)]
+pub struct Jwks {
+    keys: Vec<Jwk>,
+}
+
+/// The verified claims of a call's token.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Claims {
+    value: Value,
+}
+
+/// Validates JSON Web Tokens.
+#[derive(Clone)]
+pub struct Validator {
+    inner: Arc<Inner>,
+}
+
+/// Validates the bearer token of each call to the inner service.
+///
+/// `ValidateJwt` may wrap either a `Service` or the `NewService` given to
+/// `tower_h2::Server`. While a validator's keys are first being fetched,
+/// the service is not ready.
+#[derive(Debug, Clone)]
+pub struct ValidateJwt<S> {
+    inner: S,
+    validator: Validator,
+    optional: bool,
+}
+
+/// Creates `ValidateJwt` services.
+#[derive(Debug)]
+pub struct NewServiceFuture<F> {
+    inner: F,
+    validator: Validator,
+    optional: bool,
+}
+
+/// Error produced when parsing a JWKS document.
+#[derive(Debug)]
+pub struct JwksError {
+    message: String,
+}
+
+/// Why a token was rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    /// The token is not a well formed JWS in compact form.
+    Malformed,
+
+    /// The token is signed with an algorithm other than RS256 or ES256.
+    UnsupportedAlgorithm(String),
+
+    /// No key matches the token.
+    UnknownKey,
+
+    /// The signature does not match.
+    InvalidSignature,
+
+    /// The token has no `exp` claim, or it has passed.
+    Expired,
+
+    /// The token's `nbf` claim has not yet passed.
+    NotYetValid,
+
+    /// The token's `iss` claim does not match.
+    InvalidIssuer,
+
+    /// The token's `aud` claim does not include the audience.
+    InvalidAudience,
+}
+
+#[derive(Debug, Clone)]
+struct Jwk {
+    kid: Option<String>,
+    key: Key,
+}
+
+#[derive(Debug, Clone)]
+enum Key {
+    Rsa { n: Vec<u8>, e: Vec<u8> },
+
+    /// An uncompressed P-256 point.
+    EcP256(Vec<u8>),
+}
+
+type FetchFuture = Box<Future<Item = Jwks, Error = ()> + Send>;
+type FetchFn = Box<Fn() -> FetchFuture + Send + Sync>;
+
+struct Inner {
+    issuer: Option<String>,
+    audience: Option<String>,
+    leeway: Duration,
+    fetch: Option<FetchFn>,
+    keys: Mutex<Keys>,
+}
+
+struct Keys {
+    jwks: Option<Jwks>,
+    fetched_at: Option<Instant>,
+    next_fetch: Instant,
+    fetching: Option<Shared<FetchFuture>>,
+}
+
+// ===== impl Jwks =====
+
+impl Jwks {
+    /// Parse a JWKS document, such as served by an OpenID provider.
+    ///
+    /// Keys of unsupported types are skipped.
+    pub fn from_json(json: &[u8]) -> Result<Self, JwksError> {
+        let value: Value = serde_json::from_slice(json)
+            .map_err(|e| JwksError::new(e.to_string()))?;
+
+        let keys = value["keys"].as_array()
+            .ok_or_else(|| JwksError::new("missing keys"))?;
+
+        let keys = keys.iter()
+            .filter_map(Jwk::from_json)
+            .collect();
+
+        Ok(Jwks { keys })
+    }
+
+    /// Returns the number of usable keys.
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Returns the keys that may have signed a token.
+    fn candidates(&self, kid: Option<&str>) -> Vec<&Jwk> {
+        self.keys.iter()
+            .filter(|jwk| kid.is_none() || jwk.kid.as_ref().map(|k| &k[..]) == kid)
+            .collect()
+    }
+}
+
+// ===== impl Jwk =====
+
+impl Jwk {
+    fn from_json(value: &Value) -> Option<Self> {
+        let field = |name: &str| {
+            value[name].as_str()
+                .and_then(|s| base64::decode_url_safe(s.as_bytes()))
+        };
+
+        if let Some(usage) = value["use"].as_str() {
+            if usage != "sig" {
+                return None;
+            }
+        }
+
+        let key = match (value["kty"].as_str()?, value["crv"].as_str()) {
+            ("RSA", _) => Key::Rsa { n: field("n")?, e: field("e")? },
+            ("EC", Some("P-256")) => {
+                let (x, y) = (field("x")?, field("y")?);
+                if x.len() != 32 || y.len() != 32 {
+                    return None;
+                }
+
+                let mut point = Vec::with_capacity(65);
+                point.push(4);
+                point.extend_from_slice(&x);
+                point.extend_from_slice(&y);
+                Key::EcP256(point)
+            }
+            _ => return None,
+        };
+
+        Some(Jwk {
+            kid: value["kid"].as_str().map(|s| s.to_string()),
+            key,
+        })
+    }
+
+    fn verify(&self, alg: &str, message: &[u8], sig: &[u8]) -> bool {
+        let message = Input::from(message);
+        let sig = Input::from(sig);
+
+        match (alg, &self.key) {
+            ("RS256", &Key::Rsa { ref n, ref e }) => {
+                signature::primitive::verify_rsa(&signature::RSA_PKCS1_2048_8192_SHA256,
+                                                 (Input::from(n), Input::from(e)),
+                                                 message,
+                                                 sig).is_ok()
+            }
+            ("ES256", &Key::EcP256(ref point)) => {
+                signature::verify(&signature::ECDSA_P256_SHA256_FIXED,
+                                  Input::from(point),
+                                  message,
+                                  sig).is_ok()
+            }
+            _ => false,
+        }
+    }
+}
+
+// ===== impl Claims =====
+
+impl Claims {
+    /// Returns the `iss` claim.
+    pub fn issuer(&self) -> Option<&str> {
+        self.value["iss"].as_str()
+    }
+
+    /// Returns the `sub` claim.
+    pub fn subject(&self) -> Option<&str> {
+        self.value["sub"].as_str()
+    }
+
+    /// Returns the audiences of the `aud` claim.
+    pub fn audiences(&self) -> Vec<&str> {
+        match self.value["aud"] {
+            Value::String(ref aud) => vec![aud],
+            Value::Array(ref auds) => auds.iter().filter_map(|a| a.as_str()).collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Returns the claim named `name`.
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.value.get(name)
+    }
+
+    /// Returns every claim, as a JSON object.
+    pub fn as_json(&self) -> &Value {
+        &self.value
+    }
+}
+
+impl Identity for Claims {
+    /// Strings and the strings in arrays are values of the claim; numbers
+    /// and booleans are formatted.
+    fn claim(&self, name: &str) -> Vec<String> {
+        let value = |value: &Value| match *value {
+            Value::String(ref s) => Some(s.clone()),
+            Value::Number(ref n) => Some(n.to_string()),
+            Value::Bool(b) => Some(b.to_string()),
+            _ => None,
+        };
+
+        match self.value.get(name) {
+            Some(&Value::Array(ref values)) => values.iter().filter_map(value).collect(),
+            Some(v) => value(v).into_iter().collect(),
+            None => vec![],
+        }
+    }
+}
+
+// ===== impl Validator =====
+
+impl Validator {
+    /// Validate tokens signed by one of `keys`.
+    pub fn new(keys: Jwks) -> Self {
+        Validator::build(Some(keys), None)
+    }
+
+    /// Validate tokens signed by one of the keys served at `jwks_uri`,
+    /// fetched with `client`, an HTTP client whose requests and responses
+    /// carry complete bodies.
+    ///
+    /// Keys are refetched every hour, and when a token names a key that is
+    /// not known.
+    pub fn fetch<T>(client: T, jwks_uri: Uri) -> Self
+    where T: Service<Request = http::Request<Bytes>, Response = http::Response<Bytes>> + Clone + Send + Sync + 'static,
+          T::Future: Send,
+          T::Error: fmt::Debug,
+    {
+        let fetch = move || -> FetchFuture {
+            let mut request = http::Request::new(Bytes::new());
+            *request.method_mut() = Method::GET;
+            *request.uri_mut() = jwks_uri.clone();
+
+            let uri = jwks_uri.clone();
+            let mut client = client.clone();
+            let mut request = Some(request);
+
+            let fetch = future::poll_fn(move || {
+                    try_ready!(client.poll_ready());
+                    let request = request.take().expect("polled after ready");
+                    Ok(Async::Ready(client.call(request)))
+                })
+                .and_then(|response| response)
+                .map_err(move |e| warn!("failed to fetch JWKS; uri={}; error={:?}", uri, e))
+                .and_then(|response| {
+                    if !response.status().is_success() {
+                        warn!("failed to fetch JWKS; status={}", response.status());
+                        return Err(());
+                    }
+
+                    Jwks::from_json(response.body())
+                        .map_err(|e| warn!("invalid JWKS; error={}", e))
+                });
+
+            Box::new(fetch)
+        };
+
+        Validator::build(None, Some(Box::new(fetch)))
+    }
+
+    fn build(jwks: Option<Jwks>, fetch: Option<FetchFn>) -> Self {
+        Validator {
+            inner: Arc::new(Inner {
+                issuer: None,
+                audience: None,
+                leeway: Duration::from_secs(60),
+                fetch,
+                keys: Mutex::new(Keys {
+                    jwks,
+                    fetched_at: None,
+                    next_fetch: Instant::now(),
+                    fetching: None,
+                }),
+            }),
+        }
+    }
+
+    /// Require the `iss` claim to be `issuer`.
+    pub fn issuer(mut self, issuer: &str) -> Self {
+        self.inner_mut().issuer = Some(issuer.to_string());
+        self
+    }
+
+    /// Require the `aud` claim to include `audience`.
+    pub fn audience(mut self, audience: &str) -> Self {
+        self.inner_mut().audience = Some(audience.to_string());
+        self
+    }
+
+    /// Set the clock skew tolerated when checking `exp` and `nbf`.
+    ///
+    /// Defaults to one minute.
+    pub fn leeway(mut self, leeway: Duration) -> Self {
+        self.inner_mut().leeway = leeway;
+        self
+    }
+
+    /// Validate `token`, returning its claims.
+    pub fn validate(&self, token: &str) -> Result<Claims, ValidationError> {
+        let mut parts = token.split('.');
+        let (header, payload, sig) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(header), Some(payload), Some(sig), None) => (header, payload, sig),
+            _ => return Err(ValidationError::Malformed),
+        };
+
+        let header = decode_json(header)?;
+        let claims = decode_json(payload)?;
+        let sig = base64::decode_url_safe(sig.as_bytes())
+            .ok_or(ValidationError::Malformed)?;
+
+        let alg = header["alg"].as_str().ok_or(ValidationError::Malformed)?;
+        if alg != "RS256" && alg != "ES256" {
+            return Err(ValidationError::UnsupportedAlgorithm(alg.to_string()));
+        }
+
+        let kid = header["kid"].as_str();
+        let message = &token.as_bytes()[..token.rfind('.').expect("token has 3 parts")];
+
+        let verified = {
+            let mut keys = self.inner.lock();
+
+            let (found, verified) = match keys.jwks {
+                Some(ref jwks) => {
+                    let candidates = jwks.candidates(kid);
+                    let verified = candidates.iter().any(|jwk| jwk.verify(alg, message, &sig));
+                    (!candidates.is_empty(), verified)
+                }
+                None => (false, false),
+            };
+
+            if !found {
+                // The keys may have been rotated since they were fetched.
+                keys.refetch_soon();
+                return Err(ValidationError::UnknownKey);
+            }
+
+            verified
+        };
+
+        if !verified {
+            return Err(ValidationError::InvalidSignature);
+        }
+
+        self.check_claims(&claims)?;
+
+        Ok(Claims { value: claims })
+    }
+
+    fn check_claims(&self, claims: &Value) -> Result<(), ValidationError> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let leeway = self.inner.leeway.as_secs();
+
+        match claims["exp"].as_u64() {
+            Some(exp) if now <= exp.saturating_add(leeway) => {}
+            _ => return Err(ValidationError::Expired),
+        }
+
+        if let Some(nbf) = claims["nbf"].as_u64() {
+            if now.saturating_add(leeway) < nbf {
+                return Err(ValidationError::NotYetValid);
+            }
+        }
+
+        if let Some(ref issuer) = self.inner.issuer {
+            if claims["iss"].as_str() != Some(issuer) {
+                return Err(ValidationError::InvalidIssuer);
+            }
+        }
+
+        if let Some(ref audience) = self.inner.audience {
+            let claims = Claims { value: claims.clone() };
+            if !claims.audiences().contains(&&audience[..]) {
+                return Err(ValidationError::InvalidAudience);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Fetch keys when they are due, returning `NotReady` until the first
+    /// keys have been fetched.
+    fn poll_keys(&self) -> Async<()> {
+        let fetch = match self.inner.fetch {
+            Some(ref fetch) => fetch,
+            None => return Async::Ready(()),
+        };
+
+        let mut keys = self.inner.lock();
+
+        if keys.fetching.is_none() && Instant::now() >= keys.next_fetch {
+            trace!("fetching JWKS");
+            keys.fetching = Some(fetch().shared());
+        }
+
+        let result = match keys.fetching {
+            Some(ref mut fetching) => fetching.poll(),
+            None => return Async::Ready(()),
+        };
+
+        let now = Instant::now();
+
+        match result {
+            Ok(Async::Ready(jwks)) => {
+                debug!("fetched JWKS; keys={}", jwks.len());
+                keys.jwks = Some((*jwks).clone());
+                keys.fetched_at = Some(now);
+                keys.next_fetch = now + Duration::from_secs(REFRESH_SECS);
+                keys.fetching = None;
+            }
+            Ok(Async::NotReady) if keys.jwks.is_none() => return Async::NotReady,
+            Ok(Async::NotReady) => {}
+            Err(_) => {
+                keys.next_fetch = now + Duration::from_secs(RETRY_FETCH_SECS);
+                keys.fetching = None;
+            }
+        }
+
+        Async::Ready(())
+    }
+
+    fn inner_mut(&mut self) -> &mut Inner {
+        Arc::get_mut(&mut self.inner)
+            .expect("validator configured after use")
+    }
+}
+
+impl fmt::Debug for Validator {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Validator")
+            .field("issuer", &self.inner.issuer)
+            .field("audience", &self.inner.audience)
+            .field("leeway", &self.inner.leeway)
+            .field("fetch", &self.inner.fetch.is_some())
+            .finish()
+    }
+}
+
+// ===== impl Inner =====
+
+impl Inner {
+    fn lock(&self) -> MutexGuard<Keys> {
+        match self.keys.lock() {
+            Ok(keys) => keys,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+// ===== impl Keys =====
+
+impl Keys {
+    fn refetch_soon(&mut self) {
+        let earliest = self.fetched_at
+            .map(|at| at + Duration::from_secs(MIN_REFETCH_SECS))
+            .unwrap_or_else(Instant::now);
+
+        if earliest < self.next_fetch {
+            self.next_fetch = earliest;
+        }
+    }
+}
+
+// ===== impl ValidateJwt =====
+
+impl<S> ValidateJwt<S> {
+    pub fn new(inner: S, validator: Validator) -> Self {
+        ValidateJwt {
+            inner,
+            validator,
+            optional: false,
+        }
+    }
+
+    /// Admit calls without a bearer token, with no `Claims` in their
+    /// extensions.
+    ///
+    /// Calls with an invalid token are still rejected. The inner service,
+    /// usually `authz::Authorize`, must refuse the calls it does not admit
+    /// without an identity.
+    pub fn optional(mut self) -> Self {
+        self.optional = true;
+        self
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner service.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+}
+
+impl<S, A, B> Service for ValidateJwt<S>
+where S: Service<Request = http::Request<A>, Response = http::Response<B>>,
+      B: Body,
+{
+    type Request = S::Request;
+    type Response = http::Response<AuthBody<B>>;
+    type Error = S::Error;
+    type Future = AuthFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        if self.validator.poll_keys().is_not_ready() {
+            return Ok(Async::NotReady);
+        }
+
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, mut request: Self::Request) -> Self::Future {
+        let claims = match bearer_token(request.headers()) {
+            Some(token) => self.validator.validate(token),
+            None if self.optional => {
+                trace!("call without bearer token; path={}", request.uri().path());
+                return AuthFuture::admitted(self.inner.call(request));
+            }
+            None => {
+                debug!("call without bearer token; path={}", request.uri().path());
+                return AuthFuture::rejected(Status::UNAUTHENTICATED);
+            }
+        };
+
+        match claims {
+            Ok(claims) => {
+                request.extensions_mut().insert(claims);
+                AuthFuture::admitted(self.inner.call(request))
+            }
+            Err(e) => {
+                debug!("rejected bearer token; path={}; error={}", request.uri().path(), e);
+                AuthFuture::rejected(Status::UNAUTHENTICATED)
+            }
+        }
+    }
+}
+
+impl<S, A, B> NewService for ValidateJwt<S>
+where S: NewService<Request = http::Request<A>, Response = http::Response<B>>,
+      B: Body,
+{
+    type Request = S::Request;
+    type Response = http::Response<AuthBody<B>>;
+    type Error = S::Error;
+    type Service = ValidateJwt<S::Service>;
+    type InitError = S::InitError;
+    type Future = NewServiceFuture<S::Future>;
+
+    fn new_service(&self) -> Self::Future {
+        NewServiceFuture {
+            inner: self.inner.new_service(),
+            validator: self.validator.clone(),
+            optional: self.optional,
+        }
+    }
+}
+
+// ===== impl NewServiceFuture =====
+
+impl<F> Future for NewServiceFuture<F>
+where F: Future,
+{
+    type Item = ValidateJwt<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+
+        Ok(Async::Ready(ValidateJwt {
+            inner,
+            validator: self.validator.clone(),
+            optional: self.optional,
+        }))
+    }
+}
+
+// ===== impl JwksError =====
+
+impl JwksError {
+    fn new<T: Into<String>>(message: T) -> Self {
+        JwksError { message: message.into() }
+    }
+}
+
+impl fmt::Display for JwksError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "invalid JWKS: {}", self.message)
+    }
+}
+
+impl error::Error for JwksError {
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
+
+// ===== impl ValidationError =====
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ValidationError::UnsupportedAlgorithm(ref alg) => {
+                write!(fmt, "unsupported algorithm: {}", alg)
+            }
+            _ => fmt.write_str(error::Error::description(self)),
+        }
+    }
+}
+
+impl error::Error for ValidationError {
+    fn description(&self) -> &str {
+        match *self {
+            ValidationError::Malformed => "malformed token",
+            ValidationError::UnsupportedAlgorithm(_) => "unsupported algorithm",
+            ValidationError::UnknownKey => "unknown signing key",
+            ValidationError::InvalidSignature => "invalid signature",
+            ValidationError::Expired => "token expired",
+            ValidationError::NotYetValid => "token not yet valid",
+            ValidationError::InvalidIssuer => "invalid issuer",
+            ValidationError::InvalidAudience => "invalid audience",
+        }
+    }
+}
+
+// ===== utility fns =====
+
+/// Returns the token of an `authorization: Bearer <token>` header.
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+
+    if value.len() > 7 && value[..7].eq_ignore_ascii_case("bearer ") {
+        Some(value[7..].trim())
+    } else {
+        None
+    }
+}
+
+fn decode_json(part: &str) -> Result<Value, ValidationError> {
+    let bytes = base64::decode_url_safe(part.as_bytes())
+        .ok_or(ValidationError::Malformed)?;
+
+    match serde_json::from_slice(&bytes) {
+        Ok(value @ Value::Object(_)) => Ok(value),
+        _ => Err(ValidationError::Malformed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use auth::authz::{Authorize, Policy, Rule};
+    use {headers, Code};
+
+    use futures::future::FutureResult;
+    use h2;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// The RSA key that signed `TOKEN`.
+    const N: &'static str = concat!(
+        "pJuAVJz_CzVtHQCeA0OCwtH3dEGaum9EjCVIIRzO0XIPR83ajhTJVBmKGlkuBHXi",
+        "FxN_M6RHKDrujF3-HrrCCurH9tXKLoNO_HkT_oiO7p45dBg0JNyzXTPHQwK7w6h7",
+        "GaSLMXgYewedMptSuo0NtWejm31ywUr4ycJlExGAfAvLMTyr1FTRuHcMc-Non9wh",
+        "A6Fig1l9jqVJbfBG3Mt9_hCamHDvccC7033Aj3NHD8Axg71j7Pk7XaVBI26L3e45",
+        "cX0mG5Dl1yj8yAY1deBz6BRkMfmiMqVUD5q9nEXtyVn7cyWkm3wjWKU2ozTwyJyR",
+        "4MSYHRezI8yNfgq5BBK3Vw",
+    );
+    const E: &'static str = "AQAB";
+
+    /// Signed with RS256 by the key `test`, expiring in 2100, with the
+    /// claims `{"iss":"https://issuer.example.com","aud":"my-service",
+    /// "sub":"alice","exp":4102444800,"roles":["admin"]}`.
+    const TOKEN: &'static str = concat!(
+        "eyJhbGciOiJSUzI1NiIsImtpZCI6InRlc3QifQ.eyJpc3MiOiJodHRwczovL2lzc",
+        "3Vlci5leGFtcGxlLmNvbSIsImF1ZCI6Im15LXNlcnZpY2UiLCJzdWIiOiJhbGljZ",
+        "SIsImV4cCI6NDEwMjQ0NDgwMCwicm9sZXMiOlsiYWRtaW4iXX0.oOMsz2Do2k0Uv",
+        "5EvnuUqkuNQWeiFOv4UuAkAB8redDlOKLxIVxpCY-2XRBksJxM7apIRNVWiE3c4u",
+        "xiBOjh85DoAS4XBkjnojY1RLmaNLbb44dXR1WB5k4cRLJDe0L9WmAO8k3xHhlg5u",
+        "Dg-9TZpAmI5KIwwxFa5MppfRiizcytPk6M2GlCGBkOsK1JRJvFz-IkDOso5gpKDK",
+        "gC2w3H4w-xx9ZSdO5p-ldaiTGHRIMtiT5aH3yHh7U5eCx7tAaV8Lg0t0dkKpNvYb",
+        "4cImp94tdoLNT5_ZONmwkariuTwebahFvye_LZ6yfGzaex59aaypEK71ArHVdwbT",
+        "gnaZIBikA",
+    );
+
+    /// Serves a JWKS document, counting the requests for it.
+    #[derive(Clone)]
+    struct MockJwks {
+        fetches: Arc<AtomicUsize>,
+    }
+
+    /// Answers every call with an empty response, counting them.
+    #[derive(Clone, Default)]
+    struct MockService {
+        calls: Arc<AtomicUsize>,
+    }
+
+    struct EmptyBody;
+
+    type Layered = ValidateJwt<Authorize<MockService, Claims>>;
+
+    fn jwks_json() -> String {
+        format!(r#"{{"keys":[
+            {{"kty":"RSA","kid":"test","use":"sig","n":"{}","e":"{}"}},
+            {{"kty":"EC","kid":"ec","crv":"P-256","x":"{}","y":"{}"}}
+        ]}}"#, N, E, "A".repeat(43), "A".repeat(43))
+    }
+
+    fn validator() -> Validator {
+        let jwks = Jwks::from_json(jwks_json().as_bytes()).unwrap();
+        Validator::new(jwks)
+    }
+
+    /// Encode `json` as a token segment.
+    fn segment(json: &str) -> String {
+        base64::encode(json.as_bytes())
+            .replace('+', "-")
+            .replace('/', "_")
+    }
+
+    /// Returns `TOKEN` with its header replaced by `header`.
+    fn with_header(header: &str) -> String {
+        let rest = &TOKEN[TOKEN.find('.').unwrap()..];
+        format!("{}{}", segment(header), rest)
+    }
+
+    fn claims(json: &str) -> Value {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn layered(inner: MockService) -> Layered {
+        let policy = Policy::new()
+            .rule(Rule::new("/grpc.health.v1.Health/*").allow_unauthenticated())
+            .rule(Rule::new("/admin.Admin/*").require_role("admin"));
+
+        ValidateJwt::new(Authorize::new(inner, policy), validator())
+    }
+
+    /// Call `path` on `service`, returning the status it was refused with.
+    fn call(service: &mut Layered, path: &str, token: Option<&str>) -> Option<Code> {
+        let mut request = http::Request::builder();
+        request.uri(path);
+        if let Some(token) = token {
+            request.header("authorization", format!("Bearer {}", token));
+        }
+
+        let response = service.call(request.body(()).unwrap()).wait().unwrap();
+        headers::status_code(response.headers())
+    }
+
+    fn now() -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+    }
+
+    impl Service for MockJwks {
+        type Request = http::Request<Bytes>;
+        type Response = http::Response<Bytes>;
+        type Error = ();
+        type Future = FutureResult<Self::Response, ()>;
+
+        fn poll_ready(&mut self) -> Poll<(), ()> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, _: http::Request<Bytes>) -> Self::Future {
+            self.fetches.fetch_add(1, Ordering::SeqCst);
+            future::ok(http::Response::new(Bytes::from(jwks_json())))
+        }
+    }
+
+    impl Service for MockService {
+        type Request = http::Request<()>;
+        type Response = http::Response<EmptyBody>;
+        type Error = ();
+        type Future = FutureResult<Self::Response, ()>;
+
+        fn poll_ready(&mut self) -> Poll<(), ()> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, _: http::Request<()>) -> Self::Future {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            future::ok(http::Response::new(EmptyBody))
+        }
+    }
+
+    impl Body for EmptyBody {
+        type Data = Bytes;
+
+        fn is_end_stream(&self) -> bool {
+            true
+        }
+
+        fn poll_data(&mut self) -> Poll<Option<Bytes>, h2::Error> {
+            Ok(Async::Ready(None))
+        }
+
+        fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, h2::Error> {
+            Ok(Async::Ready(None))
+        }
+    }
+
+    #[test]
+    fn token_signed_by_a_known_key_is_valid() {
+        let validator = validator()
+            .issuer("https://issuer.example.com")
+            .audience("my-service");
+
+        let claims = validator.validate(TOKEN).unwrap();
+        assert_eq!(claims.subject(), Some("alice"));
+        assert_eq!(claims.claim("roles"), vec!["admin".to_string()]);
+    }
+
+    #[test]
+    fn algorithm_must_match_the_key() {
+        let validator = validator();
+
+        // An RSA signature checked as ECDSA.
+        let token = with_header(r#"{"alg":"ES256","kid":"test"}"#);
+        assert_eq!(validator.validate(&token), Err(ValidationError::InvalidSignature));
+
+        // An RSA signature checked with an EC key.
+        let token = with_header(r#"{"alg":"RS256","kid":"ec"}"#);
+        assert_eq!(validator.validate(&token), Err(ValidationError::InvalidSignature));
+
+        let token = with_header(r#"{"alg":"HS256","kid":"test"}"#);
+        assert_eq!(validator.validate(&token),
+                   Err(ValidationError::UnsupportedAlgorithm("HS256".to_string())));
+    }
+
+    #[test]
+    fn unsigned_token_is_rejected() {
+        let validator = validator();
+
+        let payload = &TOKEN[TOKEN.find('.').unwrap() + 1..TOKEN.rfind('.').unwrap()];
+        for header in &[r#"{"alg":"none"}"#, r#"{"alg":"none","kid":"test"}"#] {
+            let token = format!("{}.{}.", segment(header), payload);
+            assert_eq!(validator.validate(&token),
+                       Err(ValidationError::UnsupportedAlgorithm("none".to_string())));
+        }
+    }
+
+    #[test]
+    fn expiry_and_not_before_are_checked_with_leeway() {
+        let strict = validator().leeway(Duration::from_secs(0));
+        let lenient = validator();
+        let now = now();
+
+        let expired = claims(&format!(r#"{{"exp":{}}}"#, now - 10));
+        assert_eq!(strict.check_claims(&expired), Err(ValidationError::Expired));
+        assert_eq!(lenient.check_claims(&expired), Ok(()));
+
+        let never_expires = claims(r#"{"sub":"alice"}"#);
+        assert_eq!(lenient.check_claims(&never_expires), Err(ValidationError::Expired));
+
+        let not_yet_valid = claims(&format!(r#"{{"exp":{},"nbf":{}}}"#, now + 3600, now + 10));
+        assert_eq!(strict.check_claims(&not_yet_valid), Err(ValidationError::NotYetValid));
+        assert_eq!(lenient.check_claims(&not_yet_valid), Ok(()));
+
+        let far_future = claims(&format!(r#"{{"exp":{},"nbf":{}}}"#, now + 3600, now + 600));
+        assert_eq!(lenient.check_claims(&far_future), Err(ValidationError::NotYetValid));
+    }
+
+    #[test]
+    fn issuer_and_audience_must_match() {
+        let validator = validator()
+            .issuer("https://issuer.example.com")
+            .audience("my-service");
+        let issued = |iss: &str, aud: &str| {
+            claims(&format!(r#"{{"exp":{},"iss":{},"aud":{}}}"#, now() + 3600, iss, aud))
+        };
+
+        let value = issued(r#""https://issuer.example.com""#, r#""my-service""#);
+        assert_eq!(validator.check_claims(&value), Ok(()));
+
+        let value = issued(r#""https://issuer.example.com""#, r#"["other", "my-service"]"#);
+        assert_eq!(validator.check_claims(&value), Ok(()));
+
+        let value = issued(r#""https://other.example.com""#, r#""my-service""#);
+        assert_eq!(validator.check_claims(&value), Err(ValidationError::InvalidIssuer));
+
+        let value = issued("null", r#""my-service""#);
+        assert_eq!(validator.check_claims(&value), Err(ValidationError::InvalidIssuer));
+
+        let value = issued(r#""https://issuer.example.com""#, r#"["other"]"#);
+        assert_eq!(validator.check_claims(&value), Err(ValidationError::InvalidAudience));
+
+        let value = issued(r#""https://issuer.example.com""#, "null");
+        assert_eq!(validator.check_claims(&value), Err(ValidationError::InvalidAudience));
+    }
+
+    #[test]
+    fn malformed_tokens_are_rejected() {
+        let validator = validator();
+        let mut parts = TOKEN.split('.');
+        let header = parts.next().unwrap();
+        let payload = parts.next().unwrap();
+        let sig = parts.next().unwrap();
+
+        let tokens = vec![
+            "".to_string(),
+            format!("{}.{}", header, payload),
+            format!("{}.x", TOKEN),
+            format!("!!.{}.{}", payload, sig),
+            format!("{}.!!.{}", header, sig),
+            format!("{}.{}.!!", header, payload),
+            format!("{}.{}.{}", segment("[1]"), payload, sig),
+            format!("{}.{}.{}", segment("{}"), payload, sig),
+        ];
+
+        for token in tokens {
+            assert_eq!(validator.validate(&token), Err(ValidationError::Malformed), "{}", token);
+        }
+    }
+
+    #[test]
+    fn unknown_key_triggers_a_refetch() {
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let client = MockJwks { fetches: fetches.clone() };
+        let validator = Validator::fetch(client, "https://issuer.example.com/jwks".parse().unwrap());
+
+        let token = with_header(r#"{"alg":"RS256","kid":"rotated"}"#);
+
+        future::lazy(move || {
+            assert!(validator.poll_keys().is_ready());
+            assert_eq!(fetches.load(Ordering::SeqCst), 1);
+            assert!(validator.validate(TOKEN).is_ok());
+
+            // Keys are refetched no sooner than 30 seconds after the last fetch.
+            assert_eq!(validator.validate(&token), Err(ValidationError::UnknownKey));
+            {
+                let keys = validator.inner.lock();
+                let fetched_at = keys.fetched_at.unwrap();
+                assert_eq!(keys.next_fetch, fetched_at + Duration::from_secs(MIN_REFETCH_SECS));
+            }
+
+            validator.poll_keys();
+            assert_eq!(fetches.load(Ordering::SeqCst), 1);
+
+            // Once they are, the next unknown key fetches them right away.
+            {
+                let mut keys = validator.inner.lock();
+                keys.fetched_at = Some(Instant::now() - Duration::from_secs(MIN_REFETCH_SECS + 1));
+                keys.next_fetch = Instant::now() + Duration::from_secs(REFRESH_SECS);
+            }
+
+            assert_eq!(validator.validate(&token), Err(ValidationError::UnknownKey));
+            validator.poll_keys();
+            assert_eq!(fetches.load(Ordering::SeqCst), 2);
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn calls_without_a_token_are_rejected_unless_optional() {
+        let inner = MockService::default();
+        let mut service = layered(inner.clone());
+
+        assert_eq!(call(&mut service, "/grpc.health.v1.Health/Check", None),
+                   Some(Code::UNAUTHENTICATED));
+        assert_eq!(inner.calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn optional_validation_leaves_calls_without_a_token_to_the_policy() {
+        let inner = MockService::default();
+        let mut service = layered(inner.clone()).optional();
+
+        // Admitted by an `allow_unauthenticated` rule.
+        assert_eq!(call(&mut service, "/grpc.health.v1.Health/Check", None), None);
+        assert_eq!(inner.calls.load(Ordering::SeqCst), 1);
+
+        // Refused by `Authorize`, for want of an identity.
+        assert_eq!(call(&mut service, "/admin.Admin/Reset", None), Some(Code::UNAUTHENTICATED));
+        assert_eq!(inner.calls.load(Ordering::SeqCst), 1);
+
+        assert_eq!(call(&mut service, "/admin.Admin/Reset", Some(TOKEN)), None);
+        assert_eq!(inner.calls.load(Ordering::SeqCst), 2);
+
+        // An invalid token is rejected, even where none is needed.
+        assert_eq!(call(&mut service, "/grpc.health.v1.Health/Check", Some("invalid")),
+                   Some(Code::UNAUTHENTICATED));
+        assert_eq!(inner.calls.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,105 @@
+use futures::Poll;
+use http::{self, HeaderMap};
+use http::header::{HeaderName, HeaderValue};
+use tower::Service;
+
+/// Adds fixed metadata, such as an API key or tenant id, to every call made
+/// through the inner service.
+///
+/// Calls that already carry one of the keys keep their own value.
+#[derive(Debug, Clone)]
+pub struct StaticMetadata<S> {
+    inner: S,
+    metadata: HeaderMap,
+}
+
+// ===== impl StaticMetadata =====
+
+impl<S> StaticMetadata<S> {
+    pub fn new(inner: S) -> Self {
+        StaticMetadata {
+            inner,
+            metadata: HeaderMap::new(),
+        }
+    }
+
+    /// Add `key: value` to every call.
+    ///
+    /// # Panics
+    ///
+    /// If `key` is not a valid header name or `value` is not a valid header
+    /// value.
+    pub fn insert(mut self, key: &str, value: &str) -> Self {
+        let (key, value) = parse(key, value);
+        self.metadata.append(key, value);
+        self
+    }
+
+    /// Add `key: value` to every call, marking the value as sensitive.
+    ///
+    /// Sensitive values are never indexed by HPACK, and are redacted by the
+    /// crate's logging layers.
+    ///
+    /// # Panics
+    ///
+    /// If `key` is not a valid header name or `value` is not a valid header
+    /// value.
+    pub fn insert_sensitive(mut self, key: &str, value: &str) -> Self {
+        let (key, mut value) = parse(key, value);
+        value.set_sensitive(true);
+        self.metadata.append(key, value);
+        self
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner service.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+}
+
+impl<S, A> Service for StaticMetadata<S>
+where S: Service<Request = http::Request<A>>,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, mut request: Self::Request) -> Self::Future {
+        {
+            let headers = request.headers_mut();
+
+            for key in self.metadata.keys() {
+                if headers.contains_key(key) {
+                    continue;
+                }
+
+                for value in self.metadata.get_all(key).iter() {
+                    headers.append(key.clone(), value.clone());
+                }
+            }
+        }
+
+        self.inner.call(request)
+    }
+}
+
+// ===== utility fns =====
+
+fn parse(key: &str, value: &str) -> (HeaderName, HeaderValue) {
+    let key = HeaderName::from_bytes(key.as_bytes())
+        .expect("metadata key is a valid header name");
+    let value = HeaderValue::from_str(value)
+        .expect("metadata value is a valid header value");
+
+    (key, value)
+}
@@ -0,0 +1,28 @@
+//! Authentication of calls.
+//!
+//! `WithCredentials` attaches the metadata produced by `CallCredentials`,
+//! such as a bearer token, to each call made through a client service, and
+//! `StaticMetadata` attaches fixed metadata such as API keys. Credentials,
+//! and values added with `insert_sensitive`, are marked sensitive, so the
+//! crate's logging layers redact them.
+//!
+//! With the `oauth2` feature, `oauth2::ClientCredentials` provides call
+//! credentials using the OAuth2 client credentials grant.
+//!
+//! On the server side, calls are authenticated by layers that place the
+//! caller's identity in the request's extensions, such as `jwt::ValidateJwt`
+//! with the `jwt` feature. `authz::Authorize` then admits or refuses each
+//! call according to the claims of that identity.
+
+pub mod authz;
+mod credentials;
+mod metadata;
+#[cfg(feature = "jwt")]
+pub mod jwt;
+#[cfg(feature = "oauth2")]
+pub mod oauth2;
+mod response;
+
+pub use self::credentials::{CallCredentials, ResponseFuture, WithCredentials};
+pub use self::metadata::StaticMetadata;
+pub use self::response::{AuthBody, AuthFuture};
@@ -0,0 +1,594 @@
+use super::CallCredentials;
+use {base64, Status};
+
+use bytes::Bytes;
+use futures::{Future, Poll, Async};
+use futures::future::Shared;
+use http::{self, HeaderMap, Method, Uri};
+use http::header::{self, HeaderValue};
+use serde_json::{self, Value};
+use tower::Service;
+
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Call credentials using the OAuth2 client credentials grant.
+///
+/// Access tokens are requested from the token endpoint with `client`, an
+/// HTTP client whose requests and responses carry complete bodies. Tokens
+/// are cached and shared by every clone of the credentials; a new token is
+/// fetched once the cached one is within the refresh margin of expiring.
+///
+/// Concurrent calls share a single token request.
+pub struct ClientCredentials<T>
+where T: Service<Request = http::Request<Bytes>, Response = http::Response<Bytes>>,
+      T::Error: fmt::Debug,
+{
+    client: T,
+    config: Arc<Config>,
+    state: Arc<Mutex<State<T>>>,
+}
+
+/// The future returned by `ClientCredentials::get_metadata`.
+pub struct TokenFuture<T>
+where T: Service<Request = http::Request<Bytes>, Response = http::Response<Bytes>>,
+      T::Error: fmt::Debug,
+{
+    fetch: Option<Shared<Fetch<T>>>,
+    token: Option<Token>,
+    state: Arc<Mutex<State<T>>>,
+}
+
+#[derive(Debug)]
+struct Config {
+    token_uri: Uri,
+    client_id: String,
+    client_secret: String,
+    scopes: Vec<String>,
+    refresh_margin: Duration,
+}
+
+struct State<T>
+where T: Service<Request = http::Request<Bytes>, Response = http::Response<Bytes>>,
+      T::Error: fmt::Debug,
+{
+    token: Option<Token>,
+    fetch: Option<Shared<Fetch<T>>>,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    authorization: HeaderValue,
+    refresh_at: Option<Instant>,
+}
+
+/// Requests a token from the token endpoint.
+struct Fetch<T>
+where T: Service<Request = http::Request<Bytes>, Response = http::Response<Bytes>>,
+      T::Error: fmt::Debug,
+{
+    state: FetchState<T>,
+    refresh_margin: Duration,
+}
+
+enum FetchState<T>
+where T: Service<Request = http::Request<Bytes>, Response = http::Response<Bytes>>,
+      T::Error: fmt::Debug,
+{
+    Ready(T, Option<http::Request<Bytes>>),
+    Calling(T::Future),
+}
+
+// ===== impl ClientCredentials =====
+
+impl<T> ClientCredentials<T>
+where T: Service<Request = http::Request<Bytes>, Response = http::Response<Bytes>> + Clone,
+      T::Error: fmt::Debug,
+{
+    /// Authenticate as `client_id` to the token endpoint at `token_uri`.
+    pub fn new(client: T, token_uri: Uri, client_id: &str, client_secret: &str) -> Self {
+        ClientCredentials {
+            client,
+            config: Arc::new(Config {
+                token_uri,
+                client_id: client_id.to_string(),
+                client_secret: client_secret.to_string(),
+                scopes: Vec::new(),
+                refresh_margin: Duration::from_secs(60),
+            }),
+            state: Arc::new(Mutex::new(State {
+                token: None,
+                fetch: None,
+            })),
+        }
+    }
+
+    /// Request a token for `scope`, in addition to any set before.
+    pub fn scope(mut self, scope: &str) -> Self {
+        self.config_mut().scopes.push(scope.to_string());
+        self
+    }
+
+    /// Set how long before a token expires it is replaced.
+    ///
+    /// Defaults to one minute. Tokens that expire sooner than twice the
+    /// margin are replaced halfway through their lifetime.
+    pub fn refresh_margin(mut self, margin: Duration) -> Self {
+        self.config_mut().refresh_margin = margin;
+        self
+    }
+
+    fn config_mut(&mut self) -> &mut Config {
+        Arc::get_mut(&mut self.config)
+            .expect("credentials configured after use")
+    }
+
+    fn token_request(&self) -> http::Request<Bytes> {
+        let config = &self.config;
+
+        let mut body = "grant_type=client_credentials".to_string();
+
+        if !config.scopes.is_empty() {
+            body.push_str("&scope=");
+            body.push_str(&form_encode(&config.scopes.join(" ")));
+        }
+
+        // RFC 6749 section 2.3.1 form encodes the client's credentials
+        // before they are used with HTTP Basic authentication.
+        let credentials = format!("{}:{}",
+                                  form_encode(&config.client_id),
+                                  form_encode(&config.client_secret));
+        let authorization = format!("Basic {}", base64::encode_padded(credentials.as_bytes()));
+
+        let mut request = http::Request::new(Bytes::from(body));
+        *request.method_mut() = Method::POST;
+        *request.uri_mut() = config.token_uri.clone();
+
+        let headers = request.headers_mut();
+        headers.insert(header::CONTENT_TYPE,
+                       HeaderValue::from_static("application/x-www-form-urlencoded"));
+        headers.insert(header::ACCEPT, HeaderValue::from_static("application/json"));
+        headers.insert(header::AUTHORIZATION,
+                       HeaderValue::from_str(&authorization).expect("base64 is a valid header value"));
+
+        request
+    }
+}
+
+impl<T> CallCredentials for ClientCredentials<T>
+where T: Service<Request = http::Request<Bytes>, Response = http::Response<Bytes>> + Clone,
+      T::Error: fmt::Debug,
+{
+    type Future = TokenFuture<T>;
+
+    fn get_metadata(&mut self, _method: &str) -> Self::Future {
+        let mut state = lock(&self.state);
+
+        let fresh = state.token.as_ref()
+            .map(|token| token.refresh_at.map(|at| Instant::now() < at).unwrap_or(true))
+            .unwrap_or(false);
+
+        if fresh {
+            return TokenFuture {
+                fetch: None,
+                token: state.token.clone(),
+                state: self.state.clone(),
+            };
+        }
+
+        if state.fetch.is_none() {
+            trace!("fetching OAuth2 access token; uri={}", self.config.token_uri);
+
+            let fetch = Fetch {
+                state: FetchState::Ready(self.client.clone(), Some(self.token_request())),
+                refresh_margin: self.config.refresh_margin,
+            };
+
+            state.fetch = Some(fetch.shared());
+        }
+
+        TokenFuture {
+            fetch: state.fetch.clone(),
+            token: None,
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<T> Clone for ClientCredentials<T>
+where T: Service<Request = http::Request<Bytes>, Response = http::Response<Bytes>> + Clone,
+      T::Error: fmt::Debug,
+{
+    fn clone(&self) -> Self {
+        ClientCredentials {
+            client: self.client.clone(),
+            config: self.config.clone(),
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<T> fmt::Debug for ClientCredentials<T>
+where T: Service<Request = http::Request<Bytes>, Response = http::Response<Bytes>>,
+      T::Error: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("ClientCredentials")
+            .field("token_uri", &self.config.token_uri)
+            .field("client_id", &self.config.client_id)
+            .field("scopes", &self.config.scopes)
+            .finish()
+    }
+}
+
+// ===== impl TokenFuture =====
+
+impl<T> Future for TokenFuture<T>
+where T: Service<Request = http::Request<Bytes>, Response = http::Response<Bytes>>,
+      T::Error: fmt::Debug,
+{
+    type Item = HeaderMap;
+    type Error = Status;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Some(token) = self.token.take() {
+            return Ok(Async::Ready(token.metadata()));
+        }
+
+        let result = self.fetch.as_mut()
+            .expect("polled after ready")
+            .poll();
+
+        let token = match result {
+            Ok(Async::Ready(token)) => (*token).clone(),
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Err(status) => {
+                self.finish(None);
+                return Err((*status).clone());
+            }
+        };
+
+        self.finish(Some(token.clone()));
+        Ok(Async::Ready(token.metadata()))
+    }
+}
+
+impl<T> TokenFuture<T>
+where T: Service<Request = http::Request<Bytes>, Response = http::Response<Bytes>>,
+      T::Error: fmt::Debug,
+{
+    /// Clear the finished fetch, caching its token, so the next call either
+    /// uses the token or retries.
+    fn finish(&mut self, token: Option<Token>) {
+        self.fetch = None;
+
+        let mut state = lock(&self.state);
+        state.fetch = None;
+
+        if token.is_some() {
+            state.token = token;
+        }
+    }
+}
+
+impl<T> fmt::Debug for TokenFuture<T>
+where T: Service<Request = http::Request<Bytes>, Response = http::Response<Bytes>>,
+      T::Error: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("TokenFuture")
+            .field("cached", &self.token.is_some())
+            .finish()
+    }
+}
+
+// ===== impl Token =====
+
+impl Token {
+    fn metadata(&self) -> HeaderMap {
+        let mut metadata = HeaderMap::new();
+        metadata.insert(header::AUTHORIZATION, self.authorization.clone());
+        metadata
+    }
+}
+
+// ===== impl Fetch =====
+
+impl<T> Future for Fetch<T>
+where T: Service<Request = http::Request<Bytes>, Response = http::Response<Bytes>>,
+      T::Error: fmt::Debug,
+{
+    type Item = Token;
+    type Error = Status;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                FetchState::Ready(ref mut client, ref mut request) => {
+                    match client.poll_ready() {
+                        Ok(Async::Ready(())) => {}
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Err(e) => {
+                            warn!("OAuth2 token endpoint unavailable; error={:?}", e);
+                            return Err(Status::UNAVAILABLE);
+                        }
+                    }
+
+                    let request = request.take().expect("polled after ready");
+                    FetchState::Calling(client.call(request))
+                }
+                FetchState::Calling(ref mut response) => {
+                    let response = match response.poll() {
+                        Ok(Async::Ready(response)) => response,
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Err(e) => {
+                            warn!("OAuth2 token request failed; error={:?}", e);
+                            return Err(Status::UNAVAILABLE);
+                        }
+                    };
+
+                    return parse_token(response, self.refresh_margin)
+                        .map(Async::Ready);
+                }
+            };
+
+            self.state = next;
+        }
+    }
+}
+
+// ===== utility fns =====
+
+fn lock<T>(state: &Mutex<State<T>>) -> MutexGuard<State<T>>
+where T: Service<Request = http::Request<Bytes>, Response = http::Response<Bytes>>,
+      T::Error: fmt::Debug,
+{
+    match state.lock() {
+        Ok(state) => state,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+/// Parse a token response, as described in RFC 6749 section 5.
+fn parse_token(response: http::Response<Bytes>, refresh_margin: Duration) -> Result<Token, Status> {
+    let value: Value = match serde_json::from_slice(response.body()) {
+        Ok(value) => value,
+        Err(e) => {
+            warn!("invalid OAuth2 token response; status={}; error={}", response.status(), e);
+            return Err(Status::UNAUTHENTICATED);
+        }
+    };
+
+    if !response.status().is_success() {
+        warn!("OAuth2 token request rejected; status={}; error={}",
+              response.status(), value["error"]);
+        return Err(Status::UNAUTHENTICATED);
+    }
+
+    let access_token = match value["access_token"].as_str() {
+        Some(token) => token,
+        None => {
+            warn!("OAuth2 token response has no access_token");
+            return Err(Status::UNAUTHENTICATED);
+        }
+    };
+
+    // Bearer is the only token type used with gRPC, and its name is case
+    // insensitive in responses.
+    let token_type = value["token_type"].as_str().unwrap_or("Bearer");
+    if !token_type.eq_ignore_ascii_case("bearer") {
+        warn!("unsupported OAuth2 token type; token_type={}", token_type);
+        return Err(Status::UNAUTHENTICATED);
+    }
+
+    let authorization = HeaderValue::from_str(&format!("Bearer {}", access_token))
+        .map_err(|_| Status::UNAUTHENTICATED)?;
+
+    let refresh_at = value["expires_in"].as_u64().map(|secs| {
+        let lifetime = Duration::from_secs(secs);
+
+        let margin = if lifetime < refresh_margin * 2 {
+            lifetime / 2
+        } else {
+            refresh_margin
+        };
+
+        Instant::now() + (lifetime - margin)
+    });
+
+    debug!("fetched OAuth2 access token; expires_in={}", value["expires_in"]);
+
+    Ok(Token { authorization, refresh_at })
+}
+
+/// Encode `value` for an `application/x-www-form-urlencoded` body.
+fn form_encode(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+
+    for &b in value.as_bytes() {
+        match b {
+            b'A'...b'Z' | b'a'...b'z' | b'0'...b'9' | b'-' | b'.' | b'_' | b'*' => out.push(b as char),
+            b' ' => out.push('+'),
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Code;
+
+    use futures::future::{self, FutureResult};
+
+    use std::collections::VecDeque;
+
+    /// A token endpoint answering with the responses queued by a test.
+    #[derive(Clone, Default)]
+    struct MockEndpoint {
+        requests: Arc<Mutex<Vec<http::Request<Bytes>>>>,
+        responses: Arc<Mutex<VecDeque<http::Response<Bytes>>>>,
+    }
+
+    fn credentials(endpoint: &MockEndpoint) -> ClientCredentials<MockEndpoint> {
+        let uri = "https://auth.example.com/token".parse().unwrap();
+        ClientCredentials::new(endpoint.clone(), uri, "client", "secret")
+    }
+
+    fn response(status: u16, body: &str) -> http::Response<Bytes> {
+        let mut response = http::Response::new(Bytes::from(body));
+        *response.status_mut() = http::StatusCode::from_u16(status).unwrap();
+        response
+    }
+
+    fn authorization(credentials: &mut ClientCredentials<MockEndpoint>) -> Result<HeaderValue, Status> {
+        credentials.get_metadata("/test.Service/Method")
+            .wait()
+            .map(|metadata| metadata[header::AUTHORIZATION].clone())
+    }
+
+    impl MockEndpoint {
+        fn respond(&self, response: http::Response<Bytes>) {
+            self.responses.lock().unwrap().push_back(response);
+        }
+
+        fn requests(&self) -> usize {
+            self.requests.lock().unwrap().len()
+        }
+    }
+
+    impl Service for MockEndpoint {
+        type Request = http::Request<Bytes>;
+        type Response = http::Response<Bytes>;
+        type Error = ();
+        type Future = FutureResult<Self::Response, ()>;
+
+        fn poll_ready(&mut self) -> Poll<(), ()> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, request: Self::Request) -> Self::Future {
+            self.requests.lock().unwrap().push(request);
+
+            match self.responses.lock().unwrap().pop_front() {
+                Some(response) => future::ok(response),
+                None => future::err(()),
+            }
+        }
+    }
+
+    #[test]
+    fn token_request_form_encodes_credentials_and_scopes() {
+        let endpoint = MockEndpoint::default();
+        let credentials = ClientCredentials::new(
+            endpoint,
+            "https://auth.example.com/token".parse().unwrap(),
+            "my client",
+            "s3cr3t:&",
+        );
+        let credentials = credentials
+            .scope("read")
+            .scope("write:all");
+
+        let request = credentials.token_request();
+
+        assert_eq!(*request.method(), Method::POST);
+        assert_eq!(request.uri(), "https://auth.example.com/token");
+        assert_eq!(request.body(), &Bytes::from("grant_type=client_credentials&scope=read+write%3Aall"));
+        assert_eq!(request.headers()[header::CONTENT_TYPE], "application/x-www-form-urlencoded");
+
+        let expected = format!("Basic {}", base64::encode_padded(b"my+client:s3cr3t%3A%26"));
+        assert_eq!(request.headers()[header::AUTHORIZATION], expected.as_str());
+    }
+
+    #[test]
+    fn token_is_cached() {
+        let endpoint = MockEndpoint::default();
+        endpoint.respond(response(200, r#"{"access_token":"abc","token_type":"bearer","expires_in":3600}"#));
+
+        let mut credentials = credentials(&endpoint);
+
+        assert_eq!(authorization(&mut credentials).unwrap(), "Bearer abc");
+        assert_eq!(authorization(&mut credentials.clone()).unwrap(), "Bearer abc");
+        assert_eq!(endpoint.requests(), 1);
+    }
+
+    #[test]
+    fn concurrent_calls_share_a_fetch() {
+        let endpoint = MockEndpoint::default();
+        endpoint.respond(response(200, r#"{"access_token":"abc"}"#));
+
+        let mut credentials = credentials(&endpoint);
+        let first = credentials.get_metadata("/test.Service/Method");
+        let second = credentials.get_metadata("/test.Service/Method");
+
+        assert!(first.wait().is_ok());
+        assert!(second.wait().is_ok());
+        assert_eq!(endpoint.requests(), 1);
+    }
+
+    #[test]
+    fn expiring_token_is_replaced() {
+        let endpoint = MockEndpoint::default();
+        endpoint.respond(response(200, r#"{"access_token":"abc","expires_in":0}"#));
+        endpoint.respond(response(200, r#"{"access_token":"def","expires_in":3600}"#));
+
+        let mut credentials = credentials(&endpoint);
+
+        assert_eq!(authorization(&mut credentials).unwrap(), "Bearer abc");
+        assert_eq!(authorization(&mut credentials).unwrap(), "Bearer def");
+        assert_eq!(authorization(&mut credentials).unwrap(), "Bearer def");
+        assert_eq!(endpoint.requests(), 2);
+    }
+
+    #[test]
+    fn failed_fetch_is_retried_by_next_call() {
+        let endpoint = MockEndpoint::default();
+        let mut credentials = credentials(&endpoint);
+
+        // The endpoint has no response queued, so the request fails.
+        assert_eq!(authorization(&mut credentials).unwrap_err().code(), Code::UNAVAILABLE);
+
+        endpoint.respond(response(200, r#"{"access_token":"abc"}"#));
+        assert_eq!(authorization(&mut credentials).unwrap(), "Bearer abc");
+        assert_eq!(endpoint.requests(), 2);
+    }
+
+    #[test]
+    fn invalid_token_responses_are_unauthenticated() {
+        let invalid = [
+            (401, r#"{"error":"invalid_client"}"#),
+            (200, "not json"),
+            (200, r#"{"token_type":"bearer"}"#),
+            (200, r#"{"access_token":"abc","token_type":"mac"}"#),
+            (200, r#"{"access_token":"a\nb"}"#),
+        ];
+
+        for &(status, body) in invalid.iter() {
+            let status = parse_token(response(status, body), Duration::from_secs(60)).unwrap_err();
+            assert_eq!(status.code(), Code::UNAUTHENTICATED, "{}", body);
+        }
+    }
+
+    #[test]
+    fn short_lived_tokens_are_refreshed_halfway() {
+        let response = response(200, r#"{"access_token":"abc","expires_in":60}"#);
+
+        let before = Instant::now();
+        let token = parse_token(response, Duration::from_secs(60)).unwrap();
+        let refresh_at = token.refresh_at.unwrap();
+
+        assert!(refresh_at >= before + Duration::from_secs(30));
+        assert!(refresh_at <= Instant::now() + Duration::from_secs(30));
+    }
+
+    #[test]
+    fn form_encode_escapes_reserved_bytes() {
+        assert_eq!(form_encode("a-Z_0.9*"), "a-Z_0.9*");
+        assert_eq!(form_encode("a b"), "a+b");
+        assert_eq!(form_encode("a+b/c=é"), "a%2Bb%2Fc%3D%C3%A9");
+    }
+}
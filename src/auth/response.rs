@@ -0,0 +1,113 @@
+use Status;
+
+use futures::{Future, Poll, Async};
+use h2;
+use http::{self, HeaderMap};
+use tower_h2::Body;
+
+use std::fmt;
+
+/// The response future returned by the server-side authentication and
+/// authorization layers.
+pub struct AuthFuture<F> {
+    kind: Kind<F>,
+}
+
+/// The response body returned by the server-side authentication and
+/// authorization layers.
+pub struct AuthBody<B> {
+    kind: Kind<B>,
+}
+
+#[derive(Debug)]
+enum Kind<T> {
+    /// The call was admitted.
+    Inner(T),
+
+    /// The call was refused with a status.
+    Rejected(Option<Status>),
+}
+
+// ===== impl AuthFuture =====
+
+impl<F> AuthFuture<F> {
+    pub(crate) fn admitted(inner: F) -> Self {
+        AuthFuture { kind: Kind::Inner(inner) }
+    }
+
+    pub(crate) fn rejected(status: Status) -> Self {
+        AuthFuture { kind: Kind::Rejected(Some(status)) }
+    }
+}
+
+impl<F, B> Future for AuthFuture<F>
+where F: Future<Item = http::Response<B>>,
+{
+    type Item = http::Response<AuthBody<B>>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.kind {
+            Kind::Inner(ref mut inner) => {
+                let (head, body) = try_ready!(inner.poll()).into_parts();
+                let body = AuthBody { kind: Kind::Inner(body) };
+                Ok(Async::Ready(http::Response::from_parts(head, body)))
+            }
+            Kind::Rejected(ref mut status) => {
+                let status = status.take().expect("polled after complete");
+                let body = AuthBody { kind: Kind::Rejected(None) };
+                Ok(Async::Ready(::Response::trailers_only(&status, body).into_http()))
+            }
+        }
+    }
+}
+
+impl<F> fmt::Debug for AuthFuture<F>
+where F: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("AuthFuture")
+            .field("kind", &self.kind)
+            .finish()
+    }
+}
+
+// ===== impl AuthBody =====
+
+impl<B> Body for AuthBody<B>
+where B: Body,
+{
+    type Data = B::Data;
+
+    fn is_end_stream(&self) -> bool {
+        match self.kind {
+            Kind::Inner(ref body) => body.is_end_stream(),
+            Kind::Rejected(_) => true,
+        }
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, h2::Error> {
+        match self.kind {
+            Kind::Inner(ref mut body) => body.poll_data(),
+            Kind::Rejected(_) => Ok(Async::Ready(None)),
+        }
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, h2::Error> {
+        match self.kind {
+            Kind::Inner(ref mut body) => body.poll_trailers(),
+            // The status was sent in the headers.
+            Kind::Rejected(_) => Ok(Async::Ready(None)),
+        }
+    }
+}
+
+impl<B> fmt::Debug for AuthBody<B>
+where B: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("AuthBody")
+            .field("kind", &self.kind)
+            .finish()
+    }
+}
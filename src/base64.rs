@@ -0,0 +1,166 @@
+//! Base64 coding of binary metadata, whose `-bin` header values may be sent
+//! with or without padding, of HTTP credentials and of JSON Web Tokens.
+//!
+//! With the `simd-base64` feature, `encode` and `decode` use the SIMD
+//! implementation of the `base64-simd` crate instead, which pays off on
+//! large values such as text-encoded message payloads. Values it rejects are
+//! decoded by the portable decoder, so both accept the same encodings, such
+//! as those whose unused trailing bits are set.
+
+/// Encode `bytes` as unpadded base64.
+#[cfg(feature = "simd-base64")]
+pub(crate) fn encode(bytes: &[u8]) -> String {
+    ::base64_simd::STANDARD_NO_PAD.encode_to_string(bytes)
+}
+
+/// Encode `bytes` as unpadded base64.
+#[cfg(not(feature = "simd-base64"))]
+pub(crate) fn encode(bytes: &[u8]) -> String {
+    portable::encode(bytes)
+}
+
+/// Encode `bytes` as padded base64, as required outside of metadata.
+#[cfg(any(feature = "oauth2", feature = "transcoding"))]
+pub(crate) fn encode_padded(bytes: &[u8]) -> String {
+    let mut out = encode(bytes);
+
+    while out.len() % 4 != 0 {
+        out.push('=');
+    }
+
+    out
+}
+
+/// Decode padded or unpadded base64.
+#[cfg(feature = "simd-base64")]
+pub(crate) fn decode(value: &[u8]) -> Option<Vec<u8>> {
+    // Encodings the SIMD decoder rejects, such as those with unused bits
+    // set, are decoded as the portable decoder would.
+    ::base64_simd::STANDARD_NO_PAD.decode_to_vec(unpadded(value)).ok()
+        .or_else(|| portable::decode(value))
+}
+
+/// Decode padded or unpadded base64.
+#[cfg(not(feature = "simd-base64"))]
+pub(crate) fn decode(value: &[u8]) -> Option<Vec<u8>> {
+    portable::decode(value)
+}
+
+/// Decode the unpadded URL-safe base64 used by JSON Web Tokens.
+#[cfg(feature = "jwt")]
+pub(crate) fn decode_url_safe(value: &[u8]) -> Option<Vec<u8>> {
+    let value: Vec<u8> = value.iter()
+        .map(|&b| match b {
+            b'-' => b'+',
+            b'_' => b'/',
+            b'+' | b'/' => b'!',
+            b => b,
+        })
+        .collect();
+
+    decode(&value)
+}
+
+/// Strip the padding from `value`.
+fn unpadded(value: &[u8]) -> &[u8] {
+    let end = value.iter().rposition(|&b| b != b'=').map_or(0, |i| i + 1);
+    &value[..end]
+}
+
+/// The portable implementation, and the fallback of the SIMD one.
+mod portable {
+    use super::unpadded;
+
+    const ALPHABET: &'static [u8; 64] =
+        b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    #[cfg(any(not(feature = "simd-base64"), test))]
+    pub fn encode(bytes: &[u8]) -> String {
+        let mut out = String::with_capacity((bytes.len() * 4 + 2) / 3);
+
+        for chunk in bytes.chunks(3) {
+            let n = chunk.iter()
+                .enumerate()
+                .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+
+            for i in 0..chunk.len() + 1 {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            }
+        }
+
+        out
+    }
+
+    pub fn decode(value: &[u8]) -> Option<Vec<u8>> {
+        let value = unpadded(value);
+
+        let mut out = Vec::with_capacity(value.len() * 3 / 4);
+
+        for chunk in value.chunks(4) {
+            if chunk.len() == 1 {
+                return None;
+            }
+
+            let mut n = 0u32;
+            for (i, &b) in chunk.iter().enumerate() {
+                let digit = ALPHABET.iter().position(|&c| c == b)? as u32;
+                n |= digit << (18 - 6 * i);
+            }
+
+            for i in 0..chunk.len() - 1 {
+                out.push((n >> (16 - 8 * i)) as u8);
+            }
+        }
+
+        Some(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns `len` bytes covering every value, in a scrambled order.
+    fn bytes(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 167 + 13) as u8).collect()
+    }
+
+    #[test]
+    fn round_trips_with_and_without_padding() {
+        for len in 0..100 {
+            let bytes = bytes(len);
+            let encoded = encode(&bytes);
+
+            assert!(!encoded.ends_with('='));
+            assert_eq!(decode(encoded.as_bytes()), Some(bytes.clone()));
+
+            let padded = format!("{}{}", encoded, &"=="[..(4 - encoded.len() % 4) % 4]);
+            assert_eq!(decode(padded.as_bytes()), Some(bytes));
+        }
+    }
+
+    #[test]
+    fn matches_the_portable_implementation() {
+        for len in 0..300 {
+            let bytes = bytes(len);
+            let encoded = encode(&bytes);
+
+            assert_eq!(encoded, portable::encode(&bytes), "len={}", len);
+            assert_eq!(decode(encoded.as_bytes()), portable::decode(encoded.as_bytes()));
+        }
+
+        // Encodings with unused bits set, and invalid ones.
+        let values: &[&[u8]] = &[
+            b"QR", b"QR==", b"QUJ", b"QUJD", b"QUJDRB", b"QUJDRB==",
+            b"Q", b"QUJDR", b"Q===", b"QU!D", b"QU-D", b"QUJD\n", b"",
+        ];
+
+        for value in values {
+            assert_eq!(decode(value), portable::decode(value),
+                       "value={:?}", String::from_utf8_lossy(value));
+        }
+
+        assert_eq!(decode(b"QR"), Some(b"A".to_vec()));
+        assert_eq!(decode(b"Q"), None);
+    }
+}
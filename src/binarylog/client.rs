@@ -0,0 +1,498 @@
+use super::{
+    ClientHeader,
+    Duration,
+    EventType,
+    GrpcLogEntry,
+    Logger,
+    Message,
+    Metadata,
+    MetadataEntry,
+    ServerHeader,
+    Sink,
+    Timestamp,
+    Trailer,
+};
+use redact::{is_redacted, REDACTED};
+use {timeout, Status};
+
+use bytes::{Bytes, BytesMut};
+use futures::{Future, Poll, Async};
+use h2;
+use http::{self, HeaderMap};
+use http::header::HeaderName;
+use tower::Service;
+use tower_h2::{Body, BoxBody, HttpService};
+
+use std::{cmp, fmt, usize};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Call ids are unique within the process.
+static NEXT_CALL_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Records the calls made through the inner service.
+pub struct BinaryLog<S> {
+    inner: S,
+    sink: Arc<Sink>,
+    limits: Limits,
+    redacted: Arc<Vec<HeaderName>>,
+}
+
+/// The response future returned by `BinaryLog`.
+pub struct ResponseFuture<F> {
+    inner: F,
+    call: Arc<Call>,
+}
+
+/// A request or response body whose messages are logged.
+pub struct LoggedBody<B> {
+    inner: B,
+    call: Arc<Call>,
+    side: Side,
+
+    /// Data received for messages that are not yet complete.
+    frames: BytesMut,
+
+    /// Set once the end of the body has been seen.
+    ended: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Limits {
+    header_bytes: usize,
+    message_bytes: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Side {
+    Client,
+    Server,
+}
+
+/// The state of a single logged call.
+struct Call {
+    id: u64,
+    sequence: AtomicUsize,
+    sink: Arc<Sink>,
+    limits: Limits,
+    redacted: Arc<Vec<HeaderName>>,
+
+    /// Set once the call's status has been logged.
+    finished: AtomicBool,
+}
+
+// ===== impl BinaryLog =====
+
+impl<S> BinaryLog<S> {
+    /// Log the calls made through `inner` to `sink`.
+    ///
+    /// By default, headers and messages are logged in full.
+    pub fn new<K>(inner: S, sink: K) -> Self
+    where K: Sink + 'static,
+    {
+        BinaryLog {
+            inner,
+            sink: Arc::new(sink),
+            limits: Limits {
+                header_bytes: usize::MAX,
+                message_bytes: usize::MAX,
+            },
+            redacted: Arc::new(vec![]),
+        }
+    }
+
+    /// Log at most `max` bytes of each call's headers and trailers.
+    ///
+    /// Entries past the limit are left out.
+    pub fn max_header_bytes(mut self, max: usize) -> Self {
+        self.limits.header_bytes = max;
+        self
+    }
+
+    /// Log at most `max` bytes of each message.
+    ///
+    /// The full length of longer messages is still recorded.
+    pub fn max_message_bytes(mut self, max: usize) -> Self {
+        self.limits.message_bytes = max;
+        self
+    }
+
+    /// Log `<redacted>` in place of the value of the metadata `key`, such
+    /// as an API key.
+    ///
+    /// Values marked sensitive with `HeaderValue::set_sensitive` are always
+    /// redacted, as are credentials such as `authorization`, cookies, and
+    /// keys ending in `-token` or `-key`.
+    ///
+    /// # Panics
+    ///
+    /// If `key` is not a valid header name.
+    pub fn redact(mut self, key: &str) -> Self {
+        let key = HeaderName::from_bytes(key.as_bytes())
+            .expect("metadata key is a valid header name");
+
+        Arc::make_mut(&mut self.redacted).push(key);
+        self
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner service.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+}
+
+impl<S> Service for BinaryLog<S>
+where S: HttpService<RequestBody = BoxBody>,
+      <S::ResponseBody as Body>::Data: Into<Bytes>,
+{
+    type Request = http::Request<BoxBody>;
+    type Response = http::Response<LoggedBody<S::ResponseBody>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, request: Self::Request) -> Self::Future {
+        let call = Arc::new(Call::new(self.sink.clone(), self.limits, self.redacted.clone()));
+
+        let (head, body) = request.into_parts();
+        call.client_header(&head);
+
+        let body = LoggedBody::new(body, call.clone(), Side::Client);
+        let request = http::Request::from_parts(head, BoxBody::new(Box::new(body)));
+
+        ResponseFuture {
+            inner: self.inner.call(request),
+            call,
+        }
+    }
+}
+
+impl<S> fmt::Debug for BinaryLog<S>
+where S: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("BinaryLog")
+            .field("inner", &self.inner)
+            .field("limits", &self.limits)
+            .field("redacted", &self.redacted)
+            .finish()
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F, B> Future for ResponseFuture<F>
+where F: Future<Item = http::Response<B>>,
+      B: Body,
+      B::Data: Into<Bytes>,
+{
+    type Item = http::Response<LoggedBody<B>>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let (head, body) = try_ready!(self.inner.poll()).into_parts();
+
+        // Trailers-only responses carry the status in the headers.
+        if head.headers.contains_key("grpc-status") {
+            self.call.trailer(&head.headers);
+        } else {
+            self.call.server_header(&head.headers);
+        }
+
+        let body = LoggedBody::new(body, self.call.clone(), Side::Server);
+        Ok(Async::Ready(http::Response::from_parts(head, body)))
+    }
+}
+
+impl<F> fmt::Debug for ResponseFuture<F>
+where F: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("binarylog::ResponseFuture")
+            .field("inner", &self.inner)
+            .field("call_id", &self.call.id)
+            .finish()
+    }
+}
+
+// ===== impl LoggedBody =====
+
+impl<B> LoggedBody<B> {
+    fn new(inner: B, call: Arc<Call>, side: Side) -> Self {
+        LoggedBody {
+            inner,
+            call,
+            side,
+            frames: BytesMut::new(),
+            ended: false,
+        }
+    }
+
+    /// Log each message completed by the buffered data.
+    fn log_messages(&mut self) {
+        loop {
+            if self.frames.len() < 5 {
+                return;
+            }
+
+            let len = (self.frames[1] as usize) << 24
+                | (self.frames[2] as usize) << 16
+                | (self.frames[3] as usize) << 8
+                | (self.frames[4] as usize);
+
+            if self.frames.len() < 5 + len {
+                return;
+            }
+
+            let frame = self.frames.split_to(5 + len);
+            self.call.message(self.side, &frame[5..]);
+        }
+    }
+
+    fn end(&mut self) {
+        if self.ended {
+            return;
+        }
+
+        self.ended = true;
+
+        if self.side == Side::Client {
+            self.call.half_close();
+        }
+    }
+}
+
+impl<B> Body for LoggedBody<B>
+where B: Body,
+      B::Data: Into<Bytes>,
+{
+    type Data = Bytes;
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Bytes>, h2::Error> {
+        let data: Option<Bytes> = try_ready!(self.inner.poll_data()).map(Into::into);
+
+        match data {
+            Some(ref data) => {
+                self.frames.extend_from_slice(data);
+                self.log_messages();
+
+                if self.inner.is_end_stream() {
+                    self.end();
+                }
+            }
+            None => self.end(),
+        }
+
+        Ok(Async::Ready(data))
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, h2::Error> {
+        let trailers = try_ready!(self.inner.poll_trailers());
+
+        if self.side == Side::Server {
+            if let Some(ref trailers) = trailers {
+                self.call.trailer(trailers);
+            }
+        }
+
+        Ok(Async::Ready(trailers))
+    }
+}
+
+impl<B> fmt::Debug for LoggedBody<B>
+where B: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("LoggedBody")
+            .field("inner", &self.inner)
+            .field("call_id", &self.call.id)
+            .field("side", &self.side)
+            .finish()
+    }
+}
+
+// ===== impl Call =====
+
+impl Call {
+    fn new(sink: Arc<Sink>, limits: Limits, redacted: Arc<Vec<HeaderName>>) -> Self {
+        Call {
+            id: NEXT_CALL_ID.fetch_add(1, Ordering::Relaxed) as u64 + 1,
+            sequence: AtomicUsize::new(0),
+            sink,
+            limits,
+            redacted,
+            finished: AtomicBool::new(false),
+        }
+    }
+
+    fn client_header(&self, head: &http::request::Parts) {
+        let (metadata, truncated) = self.metadata(&head.headers);
+        let timeout = head.headers.get("grpc-timeout")
+            .and_then(timeout::decode)
+            .map(|timeout| Duration {
+                seconds: timeout.as_secs() as i64,
+                nanos: timeout.subsec_nanos() as i32,
+            });
+
+        let header = ClientHeader {
+            metadata: Some(metadata),
+            method_name: head.uri.path().to_string(),
+            authority: head.uri.authority_part()
+                .map(|authority| authority.to_string())
+                .unwrap_or_default(),
+            timeout,
+        };
+
+        self.log(EventType::ClientHeader, GrpcLogEntry {
+            client_header: Some(header),
+            payload_truncated: truncated,
+            ..Default::default()
+        });
+    }
+
+    fn server_header(&self, headers: &HeaderMap) {
+        let (metadata, truncated) = self.metadata(headers);
+
+        self.log(EventType::ServerHeader, GrpcLogEntry {
+            server_header: Some(ServerHeader { metadata: Some(metadata) }),
+            payload_truncated: truncated,
+            ..Default::default()
+        });
+    }
+
+    fn message(&self, side: Side, data: &[u8]) {
+        let event = match side {
+            Side::Client => EventType::ClientMessage,
+            Side::Server => EventType::ServerMessage,
+        };
+
+        let logged = cmp::min(data.len(), self.limits.message_bytes);
+        let message = Message {
+            length: data.len() as u32,
+            data: data[..logged].to_vec(),
+        };
+
+        self.log(event, GrpcLogEntry {
+            message: Some(message),
+            payload_truncated: logged < data.len(),
+            ..Default::default()
+        });
+    }
+
+    fn half_close(&self) {
+        self.log(EventType::ClientHalfClose, GrpcLogEntry::default());
+    }
+
+    fn trailer(&self, headers: &HeaderMap) {
+        if self.finished.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        let (metadata, truncated) = self.metadata(headers);
+        let status_code = headers.get("grpc-status")
+            .map(|s| Status::from_bytes(s.as_ref()).code().as_i32() as u32)
+            .unwrap_or(0);
+        let status_message = headers.get("grpc-message")
+            .and_then(|s| s.to_str().ok())
+            .unwrap_or("")
+            .to_string();
+
+        let trailer = Trailer {
+            metadata: Some(metadata),
+            status_code,
+            status_message,
+            status_details: vec![],
+        };
+
+        self.log(EventType::ServerTrailer, GrpcLogEntry {
+            trailer: Some(trailer),
+            payload_truncated: truncated,
+            ..Default::default()
+        });
+    }
+
+    /// Returns the metadata of `headers` that is logged, and whether any was
+    /// left out because of the header limit.
+    fn metadata(&self, headers: &HeaderMap) -> (Metadata, bool) {
+        let mut entry = vec![];
+        let mut size = 0;
+
+        for (name, value) in headers.iter() {
+            let key = name.as_str();
+
+            if !is_logged(key) {
+                continue;
+            }
+
+            let value = if is_redacted(&self.redacted, name, value) {
+                REDACTED.as_bytes()
+            } else {
+                value.as_bytes()
+            };
+
+            size += key.len() + value.len();
+            if size > self.limits.header_bytes {
+                return (Metadata { entry }, true);
+            }
+
+            entry.push(MetadataEntry {
+                key: key.to_string(),
+                value: value.to_vec(),
+            });
+        }
+
+        (Metadata { entry }, false)
+    }
+
+    fn log(&self, event: EventType, mut entry: GrpcLogEntry) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        entry.timestamp = Some(Timestamp {
+            seconds: now.as_secs() as i64,
+            nanos: now.subsec_nanos() as i32,
+        });
+        entry.call_id = self.id;
+        entry.sequence_id_within_call = self.sequence.fetch_add(1, Ordering::Relaxed) as u64 + 1;
+        entry.type_ = event as i32;
+        entry.logger = Logger::Client as i32;
+
+        self.sink.write(&entry);
+    }
+}
+
+impl Drop for Call {
+    fn drop(&mut self) {
+        // The call ended without a status, so it was canceled or the
+        // connection failed.
+        if !self.finished.load(Ordering::Acquire) {
+            self.log(EventType::Cancel, GrpcLogEntry::default());
+        }
+    }
+}
+
+/// Returns true if the header `key` is logged.
+///
+/// Transport headers and gRPC's reserved headers are left out, except for
+/// the tracing context.
+fn is_logged(key: &str) -> bool {
+    match key {
+        "grpc-trace-bin" => true,
+        "content-type" | "te" | "user-agent" => false,
+        key => !key.starts_with("grpc-"),
+    }
+}
@@ -0,0 +1,273 @@
+use super::{GrpcLogEntry, Sink};
+
+use prost::Message as ProstMessage;
+
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// The default size at which `RotatingFileSink` starts a new file.
+const DEFAULT_MAX_BYTES: u64 = 64 * 1024 * 1024;
+
+/// The default number of rotated files kept by `RotatingFileSink`.
+const DEFAULT_MAX_FILES: usize = 4;
+
+/// Writes length-delimited entries to a file, rotating it once it grows
+/// too large.
+///
+/// Entries are appended to the file at `path`. Once it holds more than
+/// `max_bytes`, it is renamed to `path.1`, older files are shifted to
+/// `path.2` and so on, and the oldest file beyond `max_files` is removed.
+/// Entries are never split across files, so each file can be read on its
+/// own.
+pub struct RotatingFileSink {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    current: Mutex<Current>,
+}
+
+/// Reads the entries written by `WriterSink` or `RotatingFileSink`.
+///
+/// ```ignore
+/// for path in RotatingFileSink::files("calls.binlog") {
+///     for entry in LogReader::new(File::open(path)?) {
+///         let entry = entry?;
+///         println!("{} {:?}", entry.call_id, EventType::from_i32(entry.type_));
+///     }
+/// }
+/// ```
+pub struct LogReader<R> {
+    reader: BufReader<R>,
+}
+
+/// The file currently written to.
+struct Current {
+    file: Option<File>,
+    written: u64,
+}
+
+// ===== impl RotatingFileSink =====
+
+impl RotatingFileSink {
+    /// Append entries to the file at `path`, creating it if needed.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let written = file.metadata()?.len();
+
+        Ok(RotatingFileSink {
+            path,
+            max_bytes: DEFAULT_MAX_BYTES,
+            max_files: DEFAULT_MAX_FILES,
+            current: Mutex::new(Current {
+                file: Some(file),
+                written,
+            }),
+        })
+    }
+
+    /// Start a new file once the current one holds more than `max` bytes.
+    ///
+    /// Defaults to 64 MiB.
+    pub fn max_bytes(mut self, max: u64) -> Self {
+        self.max_bytes = max;
+        self
+    }
+
+    /// Keep at most `max` rotated files, besides the current one.
+    ///
+    /// Defaults to 4.
+    pub fn max_files(mut self, max: usize) -> Self {
+        self.max_files = max;
+        self
+    }
+
+    /// Returns the files written for `path` that exist, oldest first, so
+    /// their entries can be read in the order they were written.
+    pub fn files<P: AsRef<Path>>(path: P) -> Vec<PathBuf> {
+        let path = path.as_ref();
+        let mut files = vec![];
+
+        for i in 1.. {
+            let rotated = rotated(path, i);
+
+            if !rotated.exists() {
+                break;
+            }
+
+            files.push(rotated);
+        }
+
+        files.reverse();
+
+        if path.exists() {
+            files.push(path.to_path_buf());
+        }
+
+        files
+    }
+
+    fn lock(&self) -> ::std::sync::MutexGuard<Current> {
+        self.current.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Move the current file to `path.1`, shifting older files, and start
+    /// a new one.
+    fn rotate(&self, current: &mut Current) -> io::Result<()> {
+        current.file = None;
+
+        if self.max_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let oldest = rotated(&self.path, self.max_files);
+
+            if oldest.exists() {
+                fs::remove_file(&oldest)?;
+            }
+
+            for i in (1..self.max_files).rev() {
+                let from = rotated(&self.path, i);
+
+                if from.exists() {
+                    fs::rename(&from, rotated(&self.path, i + 1))?;
+                }
+            }
+
+            fs::rename(&self.path, rotated(&self.path, 1))?;
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        current.file = Some(file);
+        current.written = 0;
+
+        Ok(())
+    }
+}
+
+impl Sink for RotatingFileSink {
+    fn write(&self, entry: &GrpcLogEntry) {
+        let mut buf = Vec::with_capacity(entry.encoded_len() + 10);
+
+        if let Err(e) = entry.encode_length_delimited(&mut buf) {
+            warn!("failed to encode binary log entry; err={:?}", e);
+            return;
+        }
+
+        let mut current = self.lock();
+
+        if current.written > 0 && current.written + buf.len() as u64 > self.max_bytes {
+            if let Err(e) = self.rotate(&mut current) {
+                warn!("failed to rotate binary log; path={}, err={:?}", self.path.display(), e);
+            }
+        }
+
+        let result = match current.file {
+            Some(ref mut file) => file.write_all(&buf),
+            None => return,
+        };
+
+        match result {
+            Ok(()) => current.written += buf.len() as u64,
+            Err(e) => warn!("failed to write binary log entry; err={:?}", e),
+        }
+    }
+}
+
+impl fmt::Debug for RotatingFileSink {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("RotatingFileSink")
+            .field("path", &self.path)
+            .field("max_bytes", &self.max_bytes)
+            .field("max_files", &self.max_files)
+            .finish()
+    }
+}
+
+// ===== impl LogReader =====
+
+impl<R: Read> LogReader<R> {
+    pub fn new(reader: R) -> Self {
+        LogReader {
+            reader: BufReader::new(reader),
+        }
+    }
+
+    /// Consume the reader, returning the inner reader.
+    pub fn into_inner(self) -> R {
+        self.reader.into_inner()
+    }
+
+    /// Read the length prefix of the next entry, or `None` at the end of
+    /// the log.
+    fn read_len(&mut self) -> io::Result<Option<usize>> {
+        let mut len = 0;
+
+        for i in 0..10 {
+            let mut b = [0];
+
+            match self.reader.read_exact(&mut b) {
+                Ok(()) => {}
+                Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof && i == 0 => {
+                    return Ok(None);
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    return Err(invalid("truncated binary log entry"));
+                }
+                Err(e) => return Err(e),
+            }
+
+            len |= u64::from(b[0] & 0x7f) << (7 * i);
+
+            if b[0] < 0x80 {
+                return Ok(Some(len as usize));
+            }
+        }
+
+        Err(invalid("invalid binary log entry length"))
+    }
+}
+
+impl<R: Read> Iterator for LogReader<R> {
+    type Item = io::Result<GrpcLogEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let len = match self.read_len() {
+            Ok(Some(len)) => len,
+            Ok(None) => return None,
+            Err(e) => return Some(Err(e)),
+        };
+
+        let mut buf = vec![0; len];
+
+        if let Err(e) = self.reader.read_exact(&mut buf) {
+            if e.kind() == io::ErrorKind::UnexpectedEof {
+                return Some(Err(invalid("truncated binary log entry")));
+            }
+
+            return Some(Err(e));
+        }
+
+        Some(GrpcLogEntry::decode(&buf[..]).map_err(|_| invalid("invalid binary log entry")))
+    }
+}
+
+impl<R> fmt::Debug for LogReader<R> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("LogReader").finish()
+    }
+}
+
+// ===== utility fns =====
+
+fn rotated(path: &Path, i: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(format!(".{}", i));
+    PathBuf::from(name)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,273 @@
+//! Binary logging in the `grpc.binarylog.v1` format.
+//!
+//! `BinaryLog` wraps an HTTP/2.0 client service and records the headers,
+//! messages and trailers of every call made through it as `GrpcLogEntry`s,
+//! which are written to a `Sink`. `WriterSink` writes length-delimited
+//! entries to any `io::Write`, which is the format read by the upstream
+//! binary log tools.
+//!
+//! Header and message sizes may be capped; entries that were cut short have
+//! `payload_truncated` set. Only calls made by clients are logged: generated
+//! servers read `tower_h2::RecvBody` request bodies directly, so their
+//! messages cannot be observed by a wrapping service.
+//!
+//! To capture the traffic of a long-running process for offline inspection,
+//! `RotatingFileSink` writes the same format to a file, starting a new one
+//! once it grows too large and keeping a bounded number of older files.
+//! `LogReader` iterates over the entries of a file, with their timestamps:
+//!
+//! ```ignore
+//! let sink = RotatingFileSink::open("/var/log/app/calls.binlog")?
+//!     .max_bytes(16 * 1024 * 1024)
+//!     .max_files(8);
+//! let client = Greeter::new(BinaryLog::new(conn, sink), uri)?;
+//!
+//! // Later, offline.
+//! for path in RotatingFileSink::files("/var/log/app/calls.binlog") {
+//!     for entry in LogReader::new(File::open(path)?) {
+//!         println!("{:?}", entry?);
+//!     }
+//! }
+//! ```
+//!
+//! Calls recorded to a file can be replayed by `Replay`, a stub server that
+//! answers each call with the response of a recorded call to the same
+//! method, so tests run deterministically against captured traffic:
+//!
+//! ```ignore
+//! // During an integration run.
+//! let sink = WriterSink::new(File::create("calls.binlog")?);
+//! let client = Greeter::new(BinaryLog::new(conn, sink), uri)?;
+//!
+//! // In tests.
+//! let replay = Replay::read(File::open("calls.binlog")?)?;
+//! let conn = core.run(inprocess::connect(replay, &handle))?;
+//! let client = Greeter::new(conn, uri)?;
+//! ```
+//!
+//! Each recorded call is replayed once, preferring calls made with the same
+//! request messages, in the order they were recorded.
+//!
+//! See the [binary logging design][spec] for details.
+//!
+//! [spec]: https://github.com/grpc/proposal/blob/master/A16-binary-logging.md
+
+#![allow(missing_docs)]
+
+mod client;
+mod file;
+mod replay;
+
+pub use self::client::{BinaryLog, ResponseFuture, LoggedBody};
+pub use self::file::{LogReader, RotatingFileSink};
+pub use self::replay::{Replay, ReplayBody, ReplayFuture};
+
+use prost::Message as ProstMessage;
+
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+/// Receives the entries recorded by `BinaryLog`.
+pub trait Sink: Send + Sync {
+    /// Record `entry`.
+    ///
+    /// This is called while the call is being polled, so it should not
+    /// block for long.
+    fn write(&self, entry: &GrpcLogEntry);
+}
+
+/// Writes length-delimited entries to an `io::Write`.
+pub struct WriterSink<W> {
+    writer: Mutex<W>,
+}
+
+// ===== Protocol =====
+
+// The entry's `payload` oneof is declared as optional fields, which have
+// the same encoding.
+
+#[derive(Clone, PartialEq, Message)]
+pub struct GrpcLogEntry {
+    #[prost(message, optional, tag="1")]
+    pub timestamp: Option<Timestamp>,
+    #[prost(uint64, tag="2")]
+    pub call_id: u64,
+    #[prost(uint64, tag="3")]
+    pub sequence_id_within_call: u64,
+    #[prost(enumeration="EventType", tag="4")]
+    pub type_: i32,
+    #[prost(enumeration="Logger", tag="5")]
+    pub logger: i32,
+    #[prost(message, optional, tag="6")]
+    pub client_header: Option<ClientHeader>,
+    #[prost(message, optional, tag="7")]
+    pub server_header: Option<ServerHeader>,
+    #[prost(message, optional, tag="8")]
+    pub message: Option<Message>,
+    #[prost(message, optional, tag="9")]
+    pub trailer: Option<Trailer>,
+    #[prost(bool, tag="10")]
+    pub payload_truncated: bool,
+    #[prost(message, optional, tag="11")]
+    pub peer: Option<Address>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Enumeration)]
+pub enum EventType {
+    Unknown = 0,
+    ClientHeader = 1,
+    ServerHeader = 2,
+    ClientMessage = 3,
+    ServerMessage = 4,
+    ClientHalfClose = 5,
+    ServerTrailer = 6,
+    Cancel = 7,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Enumeration)]
+pub enum Logger {
+    Unknown = 0,
+    Client = 1,
+    Server = 2,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ClientHeader {
+    #[prost(message, optional, tag="1")]
+    pub metadata: Option<Metadata>,
+    #[prost(string, tag="2")]
+    pub method_name: String,
+    #[prost(string, tag="3")]
+    pub authority: String,
+    #[prost(message, optional, tag="4")]
+    pub timeout: Option<Duration>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ServerHeader {
+    #[prost(message, optional, tag="1")]
+    pub metadata: Option<Metadata>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Trailer {
+    #[prost(message, optional, tag="1")]
+    pub metadata: Option<Metadata>,
+    #[prost(uint32, tag="2")]
+    pub status_code: u32,
+    #[prost(string, tag="3")]
+    pub status_message: String,
+    #[prost(bytes, tag="4")]
+    pub status_details: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Message {
+    #[prost(uint32, tag="1")]
+    pub length: u32,
+    #[prost(bytes, tag="2")]
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Metadata {
+    #[prost(message, repeated, tag="1")]
+    pub entry: Vec<MetadataEntry>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct MetadataEntry {
+    #[prost(string, tag="1")]
+    pub key: String,
+    #[prost(bytes, tag="2")]
+    pub value: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Address {
+    #[prost(enumeration="address::Type", tag="1")]
+    pub type_: i32,
+    #[prost(string, tag="2")]
+    pub address: String,
+    #[prost(uint32, tag="3")]
+    pub ip_port: u32,
+}
+
+pub mod address {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Enumeration)]
+    pub enum Type {
+        Unknown = 0,
+        Ipv4 = 1,
+        Ipv6 = 2,
+        Unix = 3,
+    }
+}
+
+/// A `google.protobuf.Timestamp`.
+#[derive(Clone, PartialEq, Message)]
+pub struct Timestamp {
+    #[prost(int64, tag="1")]
+    pub seconds: i64,
+    #[prost(int32, tag="2")]
+    pub nanos: i32,
+}
+
+/// A `google.protobuf.Duration`.
+#[derive(Clone, PartialEq, Message)]
+pub struct Duration {
+    #[prost(int64, tag="1")]
+    pub seconds: i64,
+    #[prost(int32, tag="2")]
+    pub nanos: i32,
+}
+
+impl<K> Sink for Arc<K>
+where K: Sink + ?Sized,
+{
+    fn write(&self, entry: &GrpcLogEntry) {
+        (**self).write(entry)
+    }
+}
+
+// ===== impl WriterSink =====
+
+impl<W> WriterSink<W>
+where W: io::Write + Send,
+{
+    pub fn new(writer: W) -> Self {
+        WriterSink {
+            writer: Mutex::new(writer),
+        }
+    }
+
+    /// Consume the sink, returning the inner writer.
+    pub fn into_inner(self) -> W {
+        self.writer.into_inner().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<W> Sink for WriterSink<W>
+where W: io::Write + Send,
+{
+    fn write(&self, entry: &GrpcLogEntry) {
+        let mut buf = Vec::with_capacity(entry.encoded_len() + 10);
+
+        if let Err(e) = entry.encode_length_delimited(&mut buf) {
+            warn!("failed to encode binary log entry; err={:?}", e);
+            return;
+        }
+
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Err(e) = writer.write_all(&buf) {
+            warn!("failed to write binary log entry; err={:?}", e);
+        }
+    }
+}
+
+impl<W> fmt::Debug for WriterSink<W> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("WriterSink").finish()
+    }
+}
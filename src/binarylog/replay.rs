@@ -0,0 +1,335 @@
+use super::{EventType, GrpcLogEntry, LogReader, Metadata};
+use generic::{BytesCodec, FrameEncoder, Streaming};
+use Status;
+
+use bytes::Bytes;
+use futures::{future, Future, Stream, Poll, Async};
+use h2;
+use http::{self, header, HeaderMap};
+use http::header::{HeaderName, HeaderValue};
+use tower::{NewService, Service};
+use tower_h2::Body;
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+/// Serves the calls recorded in a binary log, as a stub server.
+#[derive(Clone)]
+pub struct Replay {
+    exchanges: Arc<Mutex<Vec<Exchange>>>,
+}
+
+/// The response future returned by `Replay`.
+pub struct ReplayFuture<B> {
+    exchanges: Arc<Mutex<Vec<Exchange>>>,
+    path: String,
+    messages: Streaming<BytesCodec, B>,
+    received: Vec<Vec<u8>>,
+}
+
+/// The response body returned by `Replay`.
+pub struct ReplayBody {
+    messages: VecDeque<Bytes>,
+    trailers: Option<HeaderMap>,
+}
+
+/// A recorded call.
+#[derive(Debug, Default)]
+struct Exchange {
+    method: String,
+    requests: Vec<Vec<u8>>,
+    headers: HeaderMap,
+    responses: Vec<Vec<u8>>,
+    trailers: HeaderMap,
+
+    /// Set once the call has a status.
+    finished: bool,
+
+    /// Set if part of the call wasn't logged.
+    truncated: bool,
+
+    /// Set once the call has been replayed.
+    replayed: bool,
+}
+
+// ===== impl Replay =====
+
+impl Replay {
+    /// Read the calls recorded by a `WriterSink` from `reader`.
+    ///
+    /// Calls that were canceled, or whose headers or messages were cut
+    /// short by the logging limits, are left out.
+    pub fn read<R>(reader: R) -> io::Result<Self>
+    where R: io::Read,
+    {
+        let mut calls: HashMap<u64, usize> = HashMap::new();
+        let mut exchanges: Vec<Exchange> = vec![];
+
+        for entry in LogReader::new(reader) {
+            let entry: GrpcLogEntry = entry?;
+
+            let i = *calls.entry(entry.call_id).or_insert_with(|| {
+                exchanges.push(Exchange::default());
+                exchanges.len() - 1
+            });
+
+            exchanges[i].add(entry);
+        }
+
+        let exchanges: Vec<_> = exchanges.into_iter()
+            .filter(|exchange| {
+                let complete = exchange.finished && !exchange.truncated;
+
+                if !complete {
+                    debug!("skipping incomplete call; method={}", exchange.method);
+                }
+
+                complete
+            })
+            .collect();
+
+        Ok(Replay {
+            exchanges: Arc::new(Mutex::new(exchanges)),
+        })
+    }
+
+    /// Returns the number of recorded calls not yet replayed.
+    pub fn remaining(&self) -> usize {
+        self.exchanges.lock().unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .filter(|exchange| !exchange.replayed)
+            .count()
+    }
+}
+
+impl<B> Service for Replay
+where B: Body,
+      B::Data: Into<Bytes>,
+{
+    type Request = http::Request<B>;
+    type Response = http::Response<ReplayBody>;
+    type Error = h2::Error;
+    type Future = ReplayFuture<B>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        Ok(Async::Ready(()))
+    }
+
+    fn call(&mut self, request: Self::Request) -> Self::Future {
+        let path = request.uri().path().to_string();
+
+        ReplayFuture {
+            exchanges: self.exchanges.clone(),
+            path,
+            messages: Streaming::new(BytesCodec, request.into_parts().1, false),
+            received: vec![],
+        }
+    }
+}
+
+impl<B> NewService for Replay
+where B: Body,
+      B::Data: Into<Bytes>,
+{
+    type Request = http::Request<B>;
+    type Response = http::Response<ReplayBody>;
+    type Error = h2::Error;
+    type Service = Self;
+    type InitError = h2::Error;
+    type Future = future::FutureResult<Self::Service, Self::InitError>;
+
+    fn new_service(&self) -> Self::Future {
+        future::ok(self.clone())
+    }
+}
+
+impl fmt::Debug for Replay {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Replay")
+            .field("remaining", &self.remaining())
+            .finish()
+    }
+}
+
+// ===== impl ReplayFuture =====
+
+impl<B> ReplayFuture<B> {
+    /// Returns the recorded response to the call, preferring a call made
+    /// with the same request messages.
+    fn replay(&self) -> http::Response<ReplayBody> {
+        let mut exchanges = self.exchanges.lock().unwrap_or_else(|e| e.into_inner());
+
+        let i = {
+            let mut unplayed = exchanges.iter()
+                .enumerate()
+                .filter(|&(_, e)| !e.replayed && e.method == self.path);
+
+            let first = unplayed.clone().next().map(|(i, _)| i);
+
+            unplayed.find(|&(_, e)| e.requests == self.received)
+                .map(|(i, _)| i)
+                .or(first)
+        };
+
+        let exchange = match i {
+            Some(i) => &mut exchanges[i],
+            None => {
+                debug!("no recorded call to replay; path={}", self.path);
+
+                let mut trailers = HeaderMap::new();
+                trailers.insert("grpc-status", Status::UNIMPLEMENTED.to_header_value());
+
+                return response(HeaderMap::new(), VecDeque::new(), trailers);
+            }
+        };
+
+        exchange.replayed = true;
+
+        let mut frames = FrameEncoder::new(BytesCodec);
+        let messages = exchange.responses.iter()
+            .map(|message| frames.encode(message.clone()).expect("recorded messages are framed"))
+            .collect();
+
+        response(exchange.headers.clone(), messages, exchange.trailers.clone())
+    }
+}
+
+impl<B> Future for ReplayFuture<B>
+where B: Body,
+      B::Data: Into<Bytes>,
+{
+    type Item = http::Response<ReplayBody>;
+    type Error = h2::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        while let Some(message) = try_ready!(self.messages.poll().map_err(|e| {
+            debug!("request message could not be read; error={:?}", e);
+            h2::Error::from(h2::Reason::INTERNAL_ERROR)
+        })) {
+            self.received.push(message);
+        }
+
+        Ok(Async::Ready(self.replay()))
+    }
+}
+
+impl<B> fmt::Debug for ReplayFuture<B> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("binarylog::ReplayFuture")
+            .field("path", &self.path)
+            .field("received", &self.received.len())
+            .finish()
+    }
+}
+
+// ===== impl ReplayBody =====
+
+impl Body for ReplayBody {
+    type Data = Bytes;
+
+    fn is_end_stream(&self) -> bool {
+        self.messages.is_empty() && self.trailers.is_none()
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Bytes>, h2::Error> {
+        Ok(Async::Ready(self.messages.pop_front()))
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, h2::Error> {
+        Ok(Async::Ready(self.trailers.take()))
+    }
+}
+
+impl fmt::Debug for ReplayBody {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("binarylog::ReplayBody")
+            .field("messages", &self.messages.len())
+            .field("trailers", &self.trailers)
+            .finish()
+    }
+}
+
+// ===== impl Exchange =====
+
+impl Exchange {
+    fn add(&mut self, entry: GrpcLogEntry) {
+        self.truncated |= entry.payload_truncated;
+
+        match EventType::from_i32(entry.type_) {
+            Some(EventType::ClientHeader) => {
+                if let Some(header) = entry.client_header {
+                    self.method = header.method_name;
+                }
+            }
+            Some(EventType::ServerHeader) => {
+                if let Some(header) = entry.server_header {
+                    self.headers = header_map(header.metadata);
+                }
+            }
+            Some(EventType::ClientMessage) => {
+                if let Some(message) = entry.message {
+                    self.requests.push(message.data);
+                }
+            }
+            Some(EventType::ServerMessage) => {
+                if let Some(message) = entry.message {
+                    self.responses.push(message.data);
+                }
+            }
+            Some(EventType::ServerTrailer) => {
+                if let Some(trailer) = entry.trailer {
+                    let mut trailers = header_map(trailer.metadata);
+
+                    let status = HeaderValue::from_str(&trailer.status_code.to_string())
+                        .expect("integers are valid header values");
+                    trailers.insert("grpc-status", status);
+
+                    if let Ok(message) = HeaderValue::from_str(&trailer.status_message) {
+                        if !message.is_empty() {
+                            trailers.insert("grpc-message", message);
+                        }
+                    }
+
+                    self.trailers = trailers;
+                    self.finished = true;
+                }
+            }
+            // A canceled call can't be replayed.
+            Some(EventType::Cancel) => self.truncated = true,
+            _ => {}
+        }
+    }
+}
+
+// ===== utility fns =====
+
+fn response(mut headers: HeaderMap, messages: VecDeque<Bytes>, trailers: HeaderMap)
+    -> http::Response<ReplayBody>
+{
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/grpc+proto"));
+
+    let mut response = http::Response::new(ReplayBody {
+        messages,
+        trailers: Some(trailers),
+    });
+    *response.headers_mut() = headers;
+    response
+}
+
+/// Returns the recorded `metadata` as headers, leaving out invalid entries.
+fn header_map(metadata: Option<Metadata>) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+
+    for entry in metadata.into_iter().flat_map(|metadata| metadata.entry) {
+        let name = HeaderName::from_bytes(entry.key.as_bytes());
+        let value = HeaderValue::from_bytes(&entry.value);
+
+        if let (Ok(name), Ok(value)) = (name, value) {
+            headers.append(name, value);
+        }
+    }
+
+    headers
+}
@@ -0,0 +1,114 @@
+use super::{Policy, PickFirst, RoundRobin, WeightedRoundRobin};
+use channel::{Connect, Endpoint, Subchannel};
+use channel::config::ServiceConfig;
+
+use http;
+
+/// The names of the policies `Configured` can switch to.
+#[cfg(feature = "service-config")]
+pub(crate) const SUPPORTED_POLICIES: &[&str] = &["pick_first", "round_robin", "weighted_round_robin"];
+
+/// Uses the load balancing policy selected by the service config.
+///
+/// Supports `pick_first`, `round_robin` and `weighted_round_robin`. Until a
+/// service config selects a policy, or if it selects one that is not
+/// supported, `pick_first` is used.
+#[derive(Debug)]
+pub struct Configured {
+    policy: Inner,
+    endpoints: Vec<Endpoint>,
+}
+
+#[derive(Debug)]
+enum Inner {
+    PickFirst(PickFirst),
+    RoundRobin(RoundRobin),
+    WeightedRoundRobin(WeightedRoundRobin),
+}
+
+impl Configured {
+    /// Returns a new `Configured` policy.
+    pub fn new() -> Self {
+        Configured {
+            policy: Inner::PickFirst(PickFirst::new()),
+            endpoints: vec![],
+        }
+    }
+
+    /// Returns the name of the policy in use.
+    pub fn name(&self) -> &'static str {
+        match self.policy {
+            Inner::PickFirst(_) => "pick_first",
+            Inner::RoundRobin(_) => "round_robin",
+            Inner::WeightedRoundRobin(_) => "weighted_round_robin",
+        }
+    }
+}
+
+impl Default for Configured {
+    fn default() -> Self {
+        Configured::new()
+    }
+}
+
+macro_rules! with_policy {
+    ($policy:expr, $p:ident => $e:expr) => {
+        match $policy {
+            Inner::PickFirst(ref mut $p) => $e,
+            Inner::RoundRobin(ref mut $p) => $e,
+            Inner::WeightedRoundRobin(ref mut $p) => $e,
+        }
+    };
+}
+
+impl Policy for Configured {
+    fn update(&mut self, endpoints: &[Endpoint]) {
+        self.endpoints = endpoints.to_vec();
+        with_policy!(self.policy, p => p.update(endpoints))
+    }
+
+    fn configure(&mut self, config: &ServiceConfig) {
+        let name = config.load_balancing_policy.as_ref()
+            .map(|s| &s[..])
+            .unwrap_or("pick_first");
+
+        if name == self.name() {
+            return;
+        }
+
+        let policy = match name {
+            "pick_first" => Inner::PickFirst(PickFirst::new()),
+            "round_robin" => Inner::RoundRobin(RoundRobin::new()),
+            "weighted_round_robin" => Inner::WeightedRoundRobin(WeightedRoundRobin::new()),
+            _ => {
+                warn!("unsupported load balancing policy; policy={}", name);
+                return;
+            }
+        };
+
+        debug!("switching load balancing policy; from={}; to={}", self.name(), name);
+
+        self.policy = policy;
+        with_policy!(self.policy, p => p.update(&self.endpoints))
+    }
+
+    fn refresh<C>(&mut self, subchannels: &[Subchannel<C>])
+    where C: Connect,
+    {
+        with_policy!(self.policy, p => p.refresh(subchannels))
+    }
+
+    fn should_connect<C>(&mut self, subchannels: &[Subchannel<C>], index: usize) -> bool
+    where C: Connect,
+    {
+        with_policy!(self.policy, p => p.should_connect(subchannels, index))
+    }
+
+    fn pick<C, B>(&mut self,
+                  subchannels: &[Subchannel<C>],
+                  request: &http::Request<B>) -> Option<usize>
+    where C: Connect,
+    {
+        with_policy!(self.policy, p => p.pick(subchannels, request))
+    }
+}
@@ -0,0 +1,71 @@
+//! Load balancing policies.
+//!
+//! A policy decides which of a channel's subchannels should be connected and
+//! which ready subchannel each request is dispatched to.
+
+mod configured;
+mod outlier;
+mod pick_first;
+mod ring_hash;
+mod round_robin;
+mod weighted;
+
+pub use self::configured::Configured;
+pub use self::outlier::OutlierDetection;
+pub use self::pick_first::PickFirst;
+pub use self::ring_hash::RingHash;
+pub use self::round_robin::RoundRobin;
+pub use self::weighted::WeightedRoundRobin;
+
+#[cfg(feature = "service-config")]
+pub(crate) use self::configured::SUPPORTED_POLICIES;
+
+use super::{Connect, Endpoint, Subchannel};
+use super::config::ServiceConfig;
+use clock::Clock;
+
+use http;
+
+/// A load balancing policy.
+pub trait Policy {
+    /// Called whenever the resolver produces a new set of endpoints.
+    ///
+    /// After this is called, the channel's subchannels correspond, in order,
+    /// to `endpoints`.
+    fn update(&mut self, endpoints: &[Endpoint]) {
+        let _ = endpoints;
+    }
+
+    /// Called when the resolver provides a new service config.
+    fn configure(&mut self, config: &ServiceConfig) {
+        let _ = config;
+    }
+
+    /// Called when the channel is given a clock.
+    ///
+    /// Policies that measure time should use `clock`, so that they can be
+    /// tested with a `MockClock`.
+    fn set_clock(&mut self, clock: &Clock) {
+        let _ = clock;
+    }
+
+    /// Called each time the channel is polled, after the subchannels have
+    /// been driven.
+    fn refresh<C>(&mut self, subchannels: &[Subchannel<C>])
+    where C: Connect,
+    {
+        let _ = subchannels;
+    }
+
+    /// Returns true if the idle subchannel at `index` should start
+    /// connecting.
+    fn should_connect<C>(&mut self, subchannels: &[Subchannel<C>], index: usize) -> bool
+    where C: Connect;
+
+    /// Returns the index of the ready subchannel that should handle
+    /// `request`, or `None` if no subchannel is able to.
+    fn pick<C, B>(&mut self,
+                  subchannels: &[Subchannel<C>],
+                  request: &http::Request<B>) -> Option<usize>
+    where C: Connect;
+}
@@ -0,0 +1,318 @@
+use super::Policy;
+use channel::{Connect, Endpoint, Subchannel};
+use channel::config::ServiceConfig;
+use clock::{Clock, Sleep};
+
+use futures::{Async, Future};
+use futures::task;
+use http;
+
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// Temporarily ejects endpoints whose requests fail too often.
+///
+/// Wraps another policy. Every `interval`, the outcomes recorded on each
+/// subchannel are examined; subchannels whose failure rate exceeds the
+/// configured threshold are ejected for `base_ejection_time` multiplied by the
+/// number of times they have been ejected in a row. The multiplier decreases
+/// again for every interval the endpoint stays healthy, so a recovered
+/// endpoint is gradually trusted again.
+///
+/// A request's outcome is its final status, which is usually only known once
+/// the trailers of its response have been received. Time is measured with the
+/// channel's clock, which wakes the channel whenever an ejection expires or
+/// an interval elapses.
+#[derive(Debug)]
+pub struct OutlierDetection<P> {
+    inner: P,
+
+    interval: Duration,
+    base_ejection_time: Duration,
+    max_ejection_time: Duration,
+    failure_percentage: u32,
+    minimum_requests: usize,
+    max_ejection_percent: u32,
+
+    timer: Clock,
+
+    /// When the outcomes were last examined.
+    last_sweep: Option<Instant>,
+
+    /// Completes when the next ejection expires or the next interval
+    /// elapses, whichever is first.
+    wakeup: Option<(Instant, Sleep)>,
+
+    /// Per-endpoint ejection state, kept across resolver updates.
+    endpoints: Vec<Ejection>,
+}
+
+#[derive(Debug)]
+struct Ejection {
+    addr: SocketAddr,
+    multiplier: u32,
+    until: Option<Instant>,
+
+    /// Set when the endpoint is reinstated, until its outcomes are next
+    /// examined. The interval it spent ejected does not count as healthy.
+    reinstated: bool,
+}
+
+impl<P> OutlierDetection<P>
+where P: Policy,
+{
+    /// Wrap `inner` with outlier detection using default settings.
+    ///
+    /// By default, outcomes are examined every 10 seconds and an endpoint
+    /// is ejected for 30 seconds when more than 50% of at least 10 requests
+    /// failed. No more than 10% of endpoints are ejected at once.
+    pub fn new(inner: P) -> Self {
+        OutlierDetection {
+            inner,
+            interval: Duration::from_secs(10),
+            base_ejection_time: Duration::from_secs(30),
+            max_ejection_time: Duration::from_secs(300),
+            failure_percentage: 50,
+            minimum_requests: 10,
+            max_ejection_percent: 10,
+            timer: Clock::default(),
+            last_sweep: None,
+            wakeup: None,
+            endpoints: vec![],
+        }
+    }
+
+    /// Set how often request outcomes are examined.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Set how long an endpoint is ejected for the first time.
+    pub fn base_ejection_time(mut self, duration: Duration) -> Self {
+        self.base_ejection_time = duration;
+        self
+    }
+
+    /// Set the longest an endpoint may be ejected.
+    pub fn max_ejection_time(mut self, duration: Duration) -> Self {
+        self.max_ejection_time = duration;
+        self
+    }
+
+    /// Set the failure percentage above which an endpoint is ejected.
+    pub fn failure_percentage(mut self, percent: u32) -> Self {
+        self.failure_percentage = percent;
+        self
+    }
+
+    /// Set the number of requests an endpoint must have handled during an
+    /// interval before it may be ejected.
+    pub fn minimum_requests(mut self, requests: usize) -> Self {
+        self.minimum_requests = requests;
+        self
+    }
+
+    /// Set the largest percentage of endpoints that may be ejected at once.
+    ///
+    /// At least one endpoint may always be ejected, but the last remaining
+    /// endpoint never is.
+    pub fn max_ejection_percent(mut self, percent: u32) -> Self {
+        self.max_ejection_percent = percent;
+        self
+    }
+
+    /// Reinstate endpoints whose ejection has expired, and examine outcomes
+    /// if an interval has elapsed.
+    ///
+    /// Returns true if any endpoint was ejected or reinstated.
+    fn sweep<C>(&mut self, subchannels: &[Subchannel<C>]) -> bool
+    where C: Connect,
+    {
+        let now = self.timer.now();
+        let mut changed = false;
+
+        for (sub, ejection) in subchannels.iter().zip(&mut self.endpoints) {
+            if let Some(until) = ejection.until {
+                if until <= now {
+                    debug!("outlier reinstated; addr={}", ejection.addr);
+                    ejection.until = None;
+                    ejection.reinstated = true;
+                    changed = true;
+                }
+            }
+
+            sub.set_ejected(ejection.until.is_some());
+        }
+
+        let due = match self.last_sweep {
+            Some(last) => now.duration_since(last) >= self.interval,
+            None => true,
+        };
+
+        if !due {
+            return changed;
+        }
+
+        self.last_sweep = Some(now);
+
+        let max_ejected = ::std::cmp::min(
+            subchannels.len().saturating_sub(1),
+            ::std::cmp::max(1, subchannels.len() * self.max_ejection_percent as usize / 100));
+
+        let mut ejected = self.endpoints.iter()
+            .filter(|e| e.until.is_some())
+            .count();
+
+        for (sub, ejection) in subchannels.iter().zip(&mut self.endpoints) {
+            let (successes, failures) = sub.stats().take();
+            let total = successes + failures;
+
+            if ejection.until.is_some() {
+                continue;
+            }
+
+            let reinstated = ::std::mem::replace(&mut ejection.reinstated, false);
+
+            let failing = total >= self.minimum_requests &&
+                failures * 100 > total * self.failure_percentage as usize;
+
+            if !failing {
+                if ejection.multiplier > 0 && !reinstated {
+                    ejection.multiplier -= 1;
+                }
+                continue;
+            }
+
+            if ejected >= max_ejected {
+                trace!("outlier not ejected, limit reached; addr={}", ejection.addr);
+                continue;
+            }
+
+            ejection.multiplier += 1;
+
+            let duration = ::std::cmp::min(
+                self.base_ejection_time * ejection.multiplier,
+                ::std::cmp::max(self.base_ejection_time, self.max_ejection_time));
+
+            debug!("outlier ejected; addr={}; failures={}/{}; duration={:?}",
+                   ejection.addr, failures, total, duration);
+
+            ejection.until = Some(now + duration);
+            ejected += 1;
+            changed = true;
+            sub.set_ejected(true);
+        }
+
+        changed
+    }
+
+    /// Keep `wakeup` set to the next time `sweep` has work to do, so that
+    /// the channel is polled again then.
+    fn schedule(&mut self) {
+        let next = self.endpoints.iter()
+            .filter_map(|e| e.until)
+            .chain(self.last_sweep.map(|last| last + self.interval))
+            .min();
+
+        let next = match next {
+            Some(next) => next,
+            None => {
+                self.wakeup = None;
+                return;
+            }
+        };
+
+        let stale = match self.wakeup {
+            Some((at, _)) => at != next,
+            None => true,
+        };
+
+        if stale {
+            let now = self.timer.now();
+            let delay = if next > now { next - now } else { Duration::from_secs(0) };
+            self.wakeup = Some((next, self.timer.sleep(delay)));
+        }
+
+        let polled = match self.wakeup {
+            Some((_, ref mut sleep)) => sleep.poll(),
+            None => return,
+        };
+
+        match polled {
+            Ok(Async::NotReady) => {}
+            Ok(Async::Ready(())) => {
+                // A timer may fire slightly early; sweep on the next poll
+                // and sleep again for whatever remains.
+                self.wakeup = None;
+                task::current().notify();
+            }
+            Err(e) => {
+                debug!("outlier detection timer failed; err={:?}", e);
+                self.wakeup = None;
+            }
+        }
+    }
+}
+
+impl<P> Policy for OutlierDetection<P>
+where P: Policy,
+{
+    fn update(&mut self, endpoints: &[Endpoint]) {
+        let mut previous = ::std::mem::replace(&mut self.endpoints, vec![]);
+
+        for endpoint in endpoints {
+            let pos = previous.iter()
+                .position(|e| e.addr == *endpoint.addr());
+
+            let ejection = match pos {
+                Some(i) => previous.swap_remove(i),
+                None => Ejection {
+                    addr: *endpoint.addr(),
+                    multiplier: 0,
+                    until: None,
+                    reinstated: false,
+                },
+            };
+
+            self.endpoints.push(ejection);
+        }
+
+        self.inner.update(endpoints);
+    }
+
+    fn configure(&mut self, config: &ServiceConfig) {
+        self.inner.configure(config);
+    }
+
+    fn set_clock(&mut self, clock: &Clock) {
+        self.timer = clock.clone();
+        self.inner.set_clock(clock);
+    }
+
+    fn refresh<C>(&mut self, subchannels: &[Subchannel<C>])
+    where C: Connect,
+    {
+        if self.sweep(subchannels) {
+            // Which subchannels may be picked changed after the channel
+            // decided which to connect, so have it drive them again.
+            task::current().notify();
+        }
+        self.schedule();
+        self.inner.refresh(subchannels);
+    }
+
+    fn should_connect<C>(&mut self, subchannels: &[Subchannel<C>], index: usize) -> bool
+    where C: Connect,
+    {
+        self.inner.should_connect(subchannels, index)
+    }
+
+    fn pick<C, B>(&mut self,
+                  subchannels: &[Subchannel<C>],
+                  request: &http::Request<B>) -> Option<usize>
+    where C: Connect,
+    {
+        self.inner.pick(subchannels, request)
+    }
+}
@@ -0,0 +1,162 @@
+use super::Policy;
+use channel::{Connect, Connectivity, Subchannel};
+
+use http;
+
+use std::net::SocketAddr;
+
+/// Sends all requests to the first endpoint that can be connected to.
+///
+/// Endpoints are tried in the order provided by the resolver. Once a
+/// connection is established, the channel sticks with it until it is lost, at
+/// which point the next endpoint is tried. An endpoint ejected by
+/// `OutlierDetection` is passed over in the same way.
+#[derive(Debug, Default)]
+pub struct PickFirst {
+    /// Address of the endpoint currently in use.
+    selected: Option<SocketAddr>,
+}
+
+impl PickFirst {
+    /// Returns a new `PickFirst` policy.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the index of the subchannel that should be in use.
+    fn current<C>(&mut self, subchannels: &[Subchannel<C>]) -> Option<usize>
+    where C: Connect,
+    {
+        let usable = |sub: &Subchannel<C>| {
+            sub.connectivity() != Connectivity::TransientFailure && !sub.is_ejected()
+        };
+
+        // Stick with the selected endpoint for as long as it is usable.
+        if let Some(addr) = self.selected {
+            let pos = subchannels.iter()
+                .position(|sub| *sub.endpoint().addr() == addr);
+
+            if let Some(i) = pos {
+                if usable(&subchannels[i]) {
+                    return Some(i);
+                }
+            }
+        }
+
+        let i = subchannels.iter().position(usable)?;
+
+        trace!("pick_first selected; addr={}", subchannels[i].endpoint().addr());
+        self.selected = Some(*subchannels[i].endpoint().addr());
+
+        Some(i)
+    }
+}
+
+impl Policy for PickFirst {
+    fn should_connect<C>(&mut self, subchannels: &[Subchannel<C>], index: usize) -> bool
+    where C: Connect,
+    {
+        self.current(subchannels) == Some(index)
+    }
+
+    fn pick<C, B>(&mut self,
+                  subchannels: &[Subchannel<C>],
+                  _request: &http::Request<B>) -> Option<usize>
+    where C: Connect,
+    {
+        self.current(subchannels)
+            .and_then(|i| {
+                if subchannels[i].is_ready() {
+                    Some(i)
+                } else {
+                    None
+                }
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use channel::Endpoint;
+    use channel::mock::{addr, request, subchannels, MockConnect};
+    use clock::MockClock;
+
+    use futures::Future;
+    use futures::future;
+
+    fn endpoints() -> Vec<Endpoint> {
+        vec![addr(1).into(), addr(2).into(), addr(3).into()]
+    }
+
+    #[test]
+    fn only_first_endpoint_connected() {
+        let clock = MockClock::new();
+        let mut net = MockConnect::default();
+        let mut subs = subchannels(&endpoints(), &clock);
+        let mut policy = PickFirst::new();
+        policy.update(&endpoints());
+
+        assert!(policy.should_connect(&subs, 0));
+        assert!(!policy.should_connect(&subs, 1));
+        assert!(!policy.should_connect(&subs, 2));
+
+        // Nothing is picked until the connection is established.
+        assert_eq!(policy.pick(&subs, &request()), None);
+
+        net.up(&mut subs[0]);
+        for _ in 0..3 {
+            assert_eq!(policy.pick(&subs, &request()), Some(0));
+        }
+    }
+
+    #[test]
+    fn failed_endpoint_passed_over() {
+        let clock = MockClock::new();
+        let mut net = MockConnect::default();
+        let mut subs = subchannels(&endpoints(), &clock);
+        let mut policy = PickFirst::new();
+        policy.update(&endpoints());
+
+        future::lazy(move || {
+            net.down(&mut subs[0]);
+            assert!(policy.should_connect(&subs, 1));
+            assert!(!policy.should_connect(&subs, 2));
+
+            net.down(&mut subs[1]);
+            assert!(policy.should_connect(&subs, 2));
+
+            net.up(&mut subs[2]);
+            assert_eq!(policy.pick(&subs, &request()), Some(2));
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn sticks_with_selected_endpoint_until_it_fails() {
+        let clock = MockClock::new();
+        let mut net = MockConnect::default();
+        let mut subs = subchannels(&endpoints(), &clock);
+        let mut policy = PickFirst::new();
+        policy.update(&endpoints());
+
+        future::lazy(move || {
+            net.down(&mut subs[0]);
+            net.up(&mut subs[1]);
+            assert_eq!(policy.pick(&subs, &request()), Some(1));
+
+            // The first endpoint recovering does not move requests back.
+            net.up(&mut subs[0]);
+            assert_eq!(policy.pick(&subs, &request()), Some(1));
+            assert!(!policy.should_connect(&subs, 2));
+
+            // Once the selected endpoint fails, the first usable one is
+            // selected again.
+            net.down(&mut subs[1]);
+            assert_eq!(policy.pick(&subs, &request()), Some(0));
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+}
@@ -0,0 +1,252 @@
+use super::Policy;
+use channel::{Connect, Endpoint, Subchannel};
+
+use http;
+use http::header::HeaderName;
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// Number of points each unit of endpoint weight occupies on the ring.
+const DEFAULT_REPLICAS: usize = 100;
+
+/// Consistently maps requests onto subchannels by hashing a request header.
+///
+/// Requests with the same value for the configured header (for example a
+/// `session-id`) are sent to the same endpoint for as long as it is ready.
+/// When endpoints are added or removed, only the keys that mapped to those
+/// endpoints move. If the endpoint a key maps to is not ready, the next
+/// endpoint on the ring is used instead.
+///
+/// Requests without the header are spread across the ring.
+#[derive(Debug)]
+pub struct RingHash {
+    /// Request header used as the hash key.
+    key: HeaderName,
+
+    /// Number of ring points per unit of weight.
+    replicas: usize,
+
+    /// Sorted `(hash, subchannel index)` points.
+    ring: Vec<(u64, usize)>,
+
+    /// Used in place of a hash for requests without the key.
+    unkeyed: u64,
+}
+
+impl RingHash {
+    /// Returns a new `RingHash` policy keyed on the header `key`.
+    pub fn new(key: HeaderName) -> Self {
+        RingHash {
+            key,
+            replicas: DEFAULT_REPLICAS,
+            ring: vec![],
+            unkeyed: 0,
+        }
+    }
+
+    /// Set the number of points placed on the ring for each unit of endpoint
+    /// weight.
+    ///
+    /// More points spread keys more evenly at the cost of memory.
+    pub fn replicas(mut self, replicas: usize) -> Self {
+        self.replicas = replicas;
+        self
+    }
+}
+
+impl Policy for RingHash {
+    fn update(&mut self, endpoints: &[Endpoint]) {
+        self.ring.clear();
+
+        for (i, endpoint) in endpoints.iter().enumerate() {
+            let points = self.replicas * endpoint.weight() as usize;
+
+            for replica in 0..points {
+                let mut hasher = DefaultHasher::new();
+                endpoint.addr().hash(&mut hasher);
+                replica.hash(&mut hasher);
+
+                self.ring.push((hasher.finish(), i));
+            }
+        }
+
+        self.ring.sort();
+    }
+
+    fn should_connect<C>(&mut self, _subchannels: &[Subchannel<C>], _index: usize) -> bool
+    where C: Connect,
+    {
+        true
+    }
+
+    fn pick<C, B>(&mut self,
+                  subchannels: &[Subchannel<C>],
+                  request: &http::Request<B>) -> Option<usize>
+    where C: Connect,
+    {
+        if self.ring.is_empty() {
+            return None;
+        }
+
+        let hash = match request.headers().get(&self.key) {
+            Some(value) => {
+                let mut hasher = DefaultHasher::new();
+                value.as_bytes().hash(&mut hasher);
+                hasher.finish()
+            }
+            None => {
+                self.unkeyed = self.unkeyed.wrapping_add(::std::u64::MAX / 7);
+                self.unkeyed
+            }
+        };
+
+        let start = match self.ring.binary_search_by(|&(h, _)| h.cmp(&hash)) {
+            Ok(i) | Err(i) => i,
+        };
+
+        let len = self.ring.len();
+
+        (0..len)
+            .map(|offset| self.ring[(start + offset) % len].1)
+            .find(|&i| subchannels[i].is_ready())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use channel::mock::{addr, request, subchannels, MockConnect};
+    use clock::MockClock;
+
+    use futures::Future;
+    use futures::future;
+
+    use std::net::SocketAddr;
+
+    fn endpoints(ports: &[u16]) -> Vec<Endpoint> {
+        ports.iter().map(|&port| addr(port).into()).collect()
+    }
+
+    fn keyed(key: &str) -> http::Request<()> {
+        let mut request = request();
+        request.headers_mut().insert("session-id", key.parse().unwrap());
+        request
+    }
+
+    /// Returns a policy and subchannels for `ports`, connected by `net`.
+    fn ring(ports: &[u16], net: &mut MockConnect, clock: &MockClock) -> (RingHash, Vec<Subchannel<MockConnect>>) {
+        let mut subs = subchannels(&endpoints(ports), clock);
+
+        for sub in &mut subs {
+            net.up(sub);
+        }
+
+        let mut policy = RingHash::new(HeaderName::from_static("session-id"));
+        policy.update(&endpoints(ports));
+
+        (policy, subs)
+    }
+
+    /// Returns the address each of 200 keys is sent to.
+    fn owners<C: Connect>(policy: &mut RingHash, subs: &[Subchannel<C>]) -> Vec<SocketAddr> {
+        (0..200)
+            .map(|key| {
+                let i = policy.pick(subs, &keyed(&key.to_string())).expect("no subchannel picked");
+                *subs[i].endpoint().addr()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn same_key_picks_same_endpoint() {
+        let clock = MockClock::new();
+        let mut net = MockConnect::default();
+        let (mut policy, subs) = ring(&[1, 2, 3], &mut net, &clock);
+
+        let first = policy.pick(&subs, &keyed("a")).unwrap();
+        for _ in 0..10 {
+            assert_eq!(policy.pick(&subs, &keyed("a")), Some(first));
+        }
+
+        // Keys are spread over every endpoint.
+        let owners = owners(&mut policy, &subs);
+        for port in 1..4 {
+            assert!(owners.contains(&addr(port)), "no keys on {}", addr(port));
+        }
+    }
+
+    #[test]
+    fn only_keys_of_added_endpoint_move() {
+        let clock = MockClock::new();
+        let mut net = MockConnect::default();
+        let (mut policy, subs) = ring(&[1, 2, 3], &mut net, &clock);
+        let before = owners(&mut policy, &subs);
+
+        let (mut policy, subs) = ring(&[1, 2, 3, 4], &mut net, &clock);
+        let after = owners(&mut policy, &subs);
+
+        let mut moved = 0;
+        for (before, after) in before.iter().zip(&after) {
+            if before != after {
+                assert_eq!(*after, addr(4));
+                moved += 1;
+            }
+        }
+        assert!(moved > 0);
+    }
+
+    #[test]
+    fn only_keys_of_removed_endpoint_move() {
+        let clock = MockClock::new();
+        let mut net = MockConnect::default();
+        let (mut policy, subs) = ring(&[1, 2, 3], &mut net, &clock);
+        let before = owners(&mut policy, &subs);
+
+        let (mut policy, subs) = ring(&[1, 3], &mut net, &clock);
+        let after = owners(&mut policy, &subs);
+
+        for (before, after) in before.iter().zip(&after) {
+            if *before != addr(2) {
+                assert_eq!(before, after);
+            }
+        }
+    }
+
+    #[test]
+    fn unready_endpoint_passed_over_for_next_on_ring() {
+        let clock = MockClock::new();
+        let mut net = MockConnect::default();
+        let (mut policy, mut subs) = ring(&[1, 2, 3], &mut net, &clock);
+
+        future::lazy(move || {
+            let owner = policy.pick(&subs, &keyed("a")).unwrap();
+
+            net.down(&mut subs[owner]);
+            let fallback = policy.pick(&subs, &keyed("a")).unwrap();
+            assert!(fallback != owner);
+            assert_eq!(policy.pick(&subs, &keyed("a")), Some(fallback));
+
+            net.up(&mut subs[owner]);
+            assert_eq!(policy.pick(&subs, &keyed("a")), Some(owner));
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn unkeyed_requests_spread() {
+        let clock = MockClock::new();
+        let mut net = MockConnect::default();
+        let (mut policy, subs) = ring(&[1, 2, 3], &mut net, &clock);
+
+        let mut picked = vec![];
+        for _ in 0..30 {
+            picked.push(policy.pick(&subs, &request()).unwrap());
+        }
+
+        picked.sort();
+        picked.dedup();
+        assert!(picked.len() > 1);
+    }
+}
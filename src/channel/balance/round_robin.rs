@@ -0,0 +1,144 @@
+use super::Policy;
+use channel::{Connect, Endpoint, Subchannel};
+
+use http;
+
+/// Distributes requests across all ready subchannels in turn.
+///
+/// A connection is maintained to every resolved endpoint. Each subchannel's
+/// connectivity is tracked independently, so requests only go to the
+/// subchannels that are able to accept them.
+#[derive(Debug, Default)]
+pub struct RoundRobin {
+    /// Index of the next subchannel to consider.
+    next: usize,
+}
+
+impl RoundRobin {
+    /// Returns a new `RoundRobin` policy.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Policy for RoundRobin {
+    fn update(&mut self, endpoints: &[Endpoint]) {
+        if self.next >= endpoints.len() {
+            self.next = 0;
+        }
+    }
+
+    fn should_connect<C>(&mut self, _subchannels: &[Subchannel<C>], _index: usize) -> bool
+    where C: Connect,
+    {
+        true
+    }
+
+    fn pick<C, B>(&mut self,
+                  subchannels: &[Subchannel<C>],
+                  _request: &http::Request<B>) -> Option<usize>
+    where C: Connect,
+    {
+        let len = subchannels.len();
+
+        for offset in 0..len {
+            let i = (self.next + offset) % len;
+
+            if subchannels[i].is_ready() {
+                self.next = (i + 1) % len;
+                return Some(i);
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use channel::mock::{addr, request, subchannels, MockConnect};
+    use clock::MockClock;
+
+    use futures::Future;
+    use futures::future;
+
+    fn endpoints(ports: &[u16]) -> Vec<Endpoint> {
+        ports.iter().map(|&port| addr(port).into()).collect()
+    }
+
+    fn picks<C: Connect>(policy: &mut RoundRobin, subs: &[Subchannel<C>], n: usize) -> Vec<Option<usize>> {
+        (0..n).map(|_| policy.pick(subs, &request())).collect()
+    }
+
+    #[test]
+    fn every_endpoint_connected() {
+        let clock = MockClock::new();
+        let subs = subchannels(&endpoints(&[1, 2, 3]), &clock);
+        let mut policy = RoundRobin::new();
+        policy.update(&endpoints(&[1, 2, 3]));
+
+        for i in 0..3 {
+            assert!(policy.should_connect(&subs, i));
+        }
+    }
+
+    #[test]
+    fn ready_subchannels_picked_in_turn() {
+        let clock = MockClock::new();
+        let mut net = MockConnect::default();
+        let mut subs = subchannels(&endpoints(&[1, 2, 3]), &clock);
+        let mut policy = RoundRobin::new();
+        policy.update(&endpoints(&[1, 2, 3]));
+
+        assert_eq!(picks(&mut policy, &subs, 1), vec![None]);
+
+        for sub in &mut subs {
+            net.up(sub);
+        }
+
+        assert_eq!(picks(&mut policy, &subs, 6),
+                   vec![Some(0), Some(1), Some(2), Some(0), Some(1), Some(2)]);
+    }
+
+    #[test]
+    fn subchannels_not_ready_skipped() {
+        let clock = MockClock::new();
+        let mut net = MockConnect::default();
+        let mut subs = subchannels(&endpoints(&[1, 2, 3]), &clock);
+        let mut policy = RoundRobin::new();
+        policy.update(&endpoints(&[1, 2, 3]));
+
+        future::lazy(move || {
+            net.up(&mut subs[0]);
+            net.down(&mut subs[1]);
+            net.up(&mut subs[2]);
+
+            assert_eq!(picks(&mut policy, &subs, 4), vec![Some(0), Some(2), Some(0), Some(2)]);
+
+            // Each subchannel's connectivity is tracked on its own.
+            net.up(&mut subs[1]);
+            assert_eq!(picks(&mut policy, &subs, 3), vec![Some(0), Some(1), Some(2)]);
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn turn_restarts_when_endpoints_shrink() {
+        let clock = MockClock::new();
+        let mut net = MockConnect::default();
+        let mut subs = subchannels(&endpoints(&[1, 2, 3]), &clock);
+        let mut policy = RoundRobin::new();
+        policy.update(&endpoints(&[1, 2, 3]));
+
+        for sub in &mut subs {
+            net.up(sub);
+        }
+        assert_eq!(picks(&mut policy, &subs, 2), vec![Some(0), Some(1)]);
+
+        subs.truncate(2);
+        policy.update(&endpoints(&[1, 2]));
+        assert_eq!(picks(&mut policy, &subs, 3), vec![Some(0), Some(1), Some(0)]);
+    }
+}
@@ -0,0 +1,148 @@
+use super::Policy;
+use channel::{Connect, Endpoint, Subchannel};
+
+use http;
+
+/// Distributes requests across ready subchannels in proportion to the weight
+/// of their endpoints.
+///
+/// Uses smooth weighted round-robin, so an endpoint's requests are spread out
+/// rather than sent in bursts. Weights are read from the endpoints on every
+/// pick, so a resolver update that only changes weights takes effect
+/// immediately, without reconnecting.
+#[derive(Debug, Default)]
+pub struct WeightedRoundRobin {
+    /// Current effective weight of each subchannel.
+    current: Vec<i64>,
+}
+
+impl WeightedRoundRobin {
+    /// Returns a new `WeightedRoundRobin` policy.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Policy for WeightedRoundRobin {
+    fn update(&mut self, endpoints: &[Endpoint]) {
+        self.current.clear();
+        self.current.resize(endpoints.len(), 0);
+    }
+
+    fn should_connect<C>(&mut self, _subchannels: &[Subchannel<C>], _index: usize) -> bool
+    where C: Connect,
+    {
+        true
+    }
+
+    fn pick<C, B>(&mut self,
+                  subchannels: &[Subchannel<C>],
+                  _request: &http::Request<B>) -> Option<usize>
+    where C: Connect,
+    {
+        let mut total = 0;
+        let mut best: Option<usize> = None;
+
+        for (i, sub) in subchannels.iter().enumerate() {
+            let weight = sub.endpoint().weight() as i64;
+
+            if !sub.is_ready() || weight == 0 {
+                continue;
+            }
+
+            self.current[i] += weight;
+            total += weight;
+
+            match best {
+                Some(b) if self.current[b] >= self.current[i] => {}
+                _ => best = Some(i),
+            }
+        }
+
+        if let Some(b) = best {
+            self.current[b] -= total;
+        }
+
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use channel::mock::{addr, request, subchannels, MockConnect};
+    use clock::MockClock;
+
+    fn weighted(weights: &[u32]) -> Vec<Endpoint> {
+        weights.iter()
+            .enumerate()
+            .map(|(i, &weight)| Endpoint::new(addr(i as u16 + 1)).with_weight(weight))
+            .collect()
+    }
+
+    fn picks<C: Connect>(policy: &mut WeightedRoundRobin, subs: &[Subchannel<C>], n: usize) -> Vec<usize> {
+        (0..n).map(|_| policy.pick(subs, &request()).expect("no subchannel picked")).collect()
+    }
+
+    #[test]
+    fn picks_spread_in_proportion_to_weight() {
+        let clock = MockClock::new();
+        let mut net = MockConnect::default();
+        let mut subs = subchannels(&weighted(&[5, 1, 1]), &clock);
+        let mut policy = WeightedRoundRobin::new();
+        policy.update(&weighted(&[5, 1, 1]));
+
+        for sub in &mut subs {
+            net.up(sub);
+        }
+
+        // The heavy endpoint's picks are interleaved with the others, and
+        // the sequence repeats once every weight has been served.
+        assert_eq!(picks(&mut policy, &subs, 7), vec![0, 0, 1, 0, 2, 0, 0]);
+        assert_eq!(picks(&mut policy, &subs, 7), vec![0, 0, 1, 0, 2, 0, 0]);
+    }
+
+    #[test]
+    fn zero_weight_and_unready_endpoints_skipped() {
+        let clock = MockClock::new();
+        let mut net = MockConnect::default();
+        let mut subs = subchannels(&weighted(&[0, 1, 2]), &clock);
+        let mut policy = WeightedRoundRobin::new();
+        policy.update(&weighted(&[0, 1, 2]));
+
+        net.up(&mut subs[0]);
+        net.up(&mut subs[2]);
+
+        assert_eq!(picks(&mut policy, &subs, 4), vec![2, 2, 2, 2]);
+
+        net.up(&mut subs[1]);
+        let picked = picks(&mut policy, &subs, 6);
+        assert_eq!(picked.iter().filter(|&&i| i == 1).count(), 2);
+        assert_eq!(picked.iter().filter(|&&i| i == 2).count(), 4);
+    }
+
+    #[test]
+    fn updated_weights_apply_without_reconnecting() {
+        let clock = MockClock::new();
+        let mut net = MockConnect::default();
+        let mut subs = subchannels(&weighted(&[1, 1]), &clock);
+        let mut policy = WeightedRoundRobin::new();
+        policy.update(&weighted(&[1, 1]));
+
+        for sub in &mut subs {
+            net.up(sub);
+        }
+        assert_eq!(picks(&mut policy, &subs, 4), vec![0, 1, 0, 1]);
+
+        // The channel keeps the subchannels of endpoints that are still
+        // resolved, and only updates their endpoints.
+        let endpoints = weighted(&[1, 3]);
+        for (sub, endpoint) in subs.iter_mut().zip(&endpoints) {
+            sub.set_endpoint(endpoint.clone());
+        }
+        policy.update(&endpoints);
+
+        assert!(subs.iter().all(|sub| sub.is_ready()));
+        assert_eq!(picks(&mut policy, &subs, 4), vec![1, 0, 1, 1]);
+    }
+}
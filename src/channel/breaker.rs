@@ -0,0 +1,553 @@
+//! Circuit breaking.
+//!
+//! `CircuitBreaker` stops sending calls to a service that keeps failing.
+//! After `failure_threshold` consecutive calls fail with one of the tripping
+//! status codes (`UNAVAILABLE` and `DEADLINE_EXCEEDED` by default), the
+//! circuit opens and every call fails immediately with `UNAVAILABLE`. Once
+//! `open_duration` has passed, a limited number of probe calls are let
+//! through; the circuit closes again if they succeed, and reopens if they
+//! fail.
+//!
+//! A call's outcome is its final status, which is usually received in the
+//! trailers of its response, so a call only counts once its response body
+//! has been read to the end.
+//!
+//! When combined with `Retry`, the breaker should wrap the channel, below the
+//! retry layer, so each attempt is counted and retries fail fast while the
+//! circuit is open.
+
+use clock::Clock;
+use {Code, Status};
+use headers;
+
+use futures::{Future, Poll, Async};
+use h2;
+use http::{self, HeaderMap};
+use tower::Service;
+use tower_h2::{Body, HttpService};
+
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Fails calls fast while the inner service is failing.
+pub struct CircuitBreaker<S> {
+    inner: S,
+    shared: Arc<Shared>,
+}
+
+/// The response future returned by `CircuitBreaker`.
+pub struct ResponseFuture<F> {
+    inner: Option<F>,
+    shared: Option<Arc<Shared>>,
+    probe: bool,
+}
+
+/// The response body returned by `CircuitBreaker`.
+pub struct ResponseBody<B> {
+    inner: B,
+
+    /// Records the outcome once the trailers are received.
+    shared: Option<Arc<Shared>>,
+    probe: bool,
+}
+
+#[derive(Debug)]
+struct Shared {
+    failure_threshold: u32,
+    open_duration: Duration,
+    half_open_probes: u32,
+    tripping_codes: Vec<Code>,
+    timer: Clock,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    /// Calls are sent; counts consecutive failures.
+    Closed { failures: u32 },
+
+    /// Calls fail fast until the deadline passes.
+    Open { until: Instant },
+
+    /// Up to `half_open_probes` calls are let through.
+    HalfOpen { probes: u32 },
+}
+
+// ===== impl CircuitBreaker =====
+
+impl<S> CircuitBreaker<S> {
+    /// Wrap `inner` with a circuit breaker using default settings.
+    ///
+    /// By default, the circuit opens after 5 consecutive failures, stays
+    /// open for 30 seconds, and lets a single probe through when half-open.
+    pub fn new(inner: S) -> Self {
+        CircuitBreaker {
+            inner,
+            shared: Arc::new(Shared {
+                failure_threshold: 5,
+                open_duration: Duration::from_secs(30),
+                half_open_probes: 1,
+                tripping_codes: vec![Code::UNAVAILABLE, Code::DEADLINE_EXCEEDED],
+                timer: Clock::default(),
+                state: Mutex::new(State::Closed { failures: 0 }),
+            }),
+        }
+    }
+
+    /// Set how many consecutive failures open the circuit.
+    pub fn failure_threshold(mut self, threshold: u32) -> Self {
+        self.shared_mut().failure_threshold = threshold;
+        self
+    }
+
+    /// Set how long the circuit stays open before probing the service.
+    pub fn open_duration(mut self, duration: Duration) -> Self {
+        self.shared_mut().open_duration = duration;
+        self
+    }
+
+    /// Set how many probe calls are let through while half-open.
+    pub fn half_open_probes(mut self, probes: u32) -> Self {
+        self.shared_mut().half_open_probes = probes;
+        self
+    }
+
+    /// Set the status codes counted as failures.
+    pub fn tripping_codes(mut self, codes: Vec<Code>) -> Self {
+        self.shared_mut().tripping_codes = codes;
+        self
+    }
+
+    /// Measure the open duration with `timer`, which may be a `Timer` or a
+    /// `MockClock`.
+    pub fn timer<T: Into<Clock>>(mut self, timer: T) -> Self {
+        self.shared_mut().timer = timer.into();
+        self
+    }
+
+    /// Returns true if calls are currently failing fast.
+    pub fn is_open(&self) -> bool {
+        match *self.shared.lock() {
+            State::Open { until } => self.shared.timer.now() < until,
+            _ => false,
+        }
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner service.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    fn shared_mut(&mut self) -> &mut Shared {
+        Arc::get_mut(&mut self.shared)
+            .expect("circuit breaker configured after use")
+    }
+}
+
+impl<S, E> Service for CircuitBreaker<S>
+where S: HttpService<Error = ::Error<E>>,
+{
+    type Request = http::Request<S::RequestBody>;
+    type Response = http::Response<ResponseBody<S::ResponseBody>>;
+    type Error = ::Error<E>;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        // While open, the breaker is always ready so that calls fail fast
+        // rather than waiting for the inner service.
+        if self.shared.admits() {
+            self.inner.poll_ready()
+        } else {
+            Ok(Async::Ready(()))
+        }
+    }
+
+    fn call(&mut self, request: Self::Request) -> Self::Future {
+        match self.shared.acquire() {
+            Some(probe) => ResponseFuture {
+                inner: Some(self.inner.call(request)),
+                shared: Some(self.shared.clone()),
+                probe,
+            },
+            None => {
+                trace!("circuit open; failing call");
+                ResponseFuture {
+                    inner: None,
+                    shared: None,
+                    probe: false,
+                }
+            }
+        }
+    }
+}
+
+impl<S> fmt::Debug for CircuitBreaker<S>
+where S: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("CircuitBreaker")
+            .field("inner", &self.inner)
+            .field("shared", &self.shared)
+            .finish()
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F> ResponseFuture<F> {
+    fn record(&mut self, code: Code) {
+        if let Some(shared) = self.shared.take() {
+            shared.record(code, self.probe);
+        }
+    }
+}
+
+impl<F, B, E> Future for ResponseFuture<F>
+where F: Future<Item = http::Response<B>, Error = ::Error<E>>,
+{
+    type Item = http::Response<ResponseBody<B>>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let result = match self.inner {
+            Some(ref mut inner) => inner.poll(),
+            None => return Err(::Error::Grpc(Status::UNAVAILABLE)),
+        };
+
+        match result {
+            Ok(Async::Ready(response)) => {
+                // The status of a trailers-only response is in its head.
+                // Otherwise, the body records it once the trailers are
+                // received.
+                let code = response.headers().get(&*headers::GRPC_STATUS)
+                    .map(|s| Status::from_bytes(s.as_ref()).code());
+
+                let shared = match code {
+                    Some(code) => {
+                        self.record(code);
+                        None
+                    }
+                    None => self.shared.take(),
+                };
+
+                let (head, inner) = response.into_parts();
+                let body = ResponseBody {
+                    inner,
+                    shared,
+                    probe: self.probe,
+                };
+
+                Ok(Async::Ready(http::Response::from_parts(head, body)))
+            }
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(::Error::Grpc(status)) => {
+                self.record(status.code());
+                Err(::Error::Grpc(status))
+            }
+            Err(e) => {
+                // Transport errors are treated as UNAVAILABLE.
+                self.record(Code::UNAVAILABLE);
+                Err(e)
+            }
+        }
+    }
+}
+
+impl<F> Drop for ResponseFuture<F> {
+    fn drop(&mut self) {
+        // A canceled probe must not hold the circuit half-open forever.
+        if self.probe {
+            if let Some(shared) = self.shared.take() {
+                shared.release();
+            }
+        }
+    }
+}
+
+impl<F> fmt::Debug for ResponseFuture<F>
+where F: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("ResponseFuture")
+            .field("inner", &self.inner)
+            .field("probe", &self.probe)
+            .finish()
+    }
+}
+
+// ===== impl ResponseBody =====
+
+impl<B> ResponseBody<B> {
+    fn record(&mut self, code: Code) {
+        if let Some(shared) = self.shared.take() {
+            shared.record(code, self.probe);
+        }
+    }
+}
+
+impl<B> Body for ResponseBody<B>
+where B: Body,
+{
+    type Data = B::Data;
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, h2::Error> {
+        self.inner.poll_data()
+            .map_err(|e| {
+                // Transport errors are treated as UNAVAILABLE.
+                self.record(Code::UNAVAILABLE);
+                e
+            })
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, h2::Error> {
+        match self.inner.poll_trailers() {
+            Ok(Async::Ready(trailers)) => {
+                let code = trailers.as_ref()
+                    .and_then(|t| t.get(&*headers::GRPC_STATUS))
+                    .map(|s| Status::from_bytes(s.as_ref()).code())
+                    .unwrap_or(Code::UNKNOWN);
+
+                self.record(code);
+                Ok(Async::Ready(trailers))
+            }
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(e) => {
+                self.record(Code::UNAVAILABLE);
+                Err(e)
+            }
+        }
+    }
+}
+
+impl<B> Drop for ResponseBody<B> {
+    fn drop(&mut self) {
+        // A probe whose response is dropped before its trailers must not
+        // hold the circuit half-open forever.
+        if self.probe {
+            if let Some(shared) = self.shared.take() {
+                shared.release();
+            }
+        }
+    }
+}
+
+impl<B> fmt::Debug for ResponseBody<B>
+where B: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("ResponseBody")
+            .field("inner", &self.inner)
+            .field("probe", &self.probe)
+            .finish()
+    }
+}
+
+// ===== impl Shared =====
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<State> {
+        match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Returns true if a call would currently be sent.
+    fn admits(&self) -> bool {
+        match *self.lock() {
+            State::Closed { .. } => true,
+            State::Open { until } => self.timer.now() >= until,
+            State::HalfOpen { probes } => probes < self.half_open_probes,
+        }
+    }
+
+    /// Admit a call, returning whether it is a probe, or `None` if it must
+    /// fail fast.
+    fn acquire(&self) -> Option<bool> {
+        let mut state = self.lock();
+        let current = *state;
+
+        match current {
+            State::Closed { .. } => Some(false),
+            State::Open { until } => {
+                if self.timer.now() < until {
+                    return None;
+                }
+
+                debug!("circuit half-open; probing");
+                *state = State::HalfOpen { probes: 1 };
+                Some(true)
+            }
+            State::HalfOpen { probes } => {
+                if probes >= self.half_open_probes {
+                    return None;
+                }
+
+                *state = State::HalfOpen { probes: probes + 1 };
+                Some(true)
+            }
+        }
+    }
+
+    /// Record the outcome of a call.
+    fn record(&self, code: Code, probe: bool) {
+        let failed = self.tripping_codes.contains(&code);
+        let mut state = self.lock();
+        let current = *state;
+
+        let next = match current {
+            State::Closed { failures } if failed => {
+                if failures + 1 >= self.failure_threshold {
+                    debug!("circuit opened; failures={}", failures + 1);
+                    self.open()
+                } else {
+                    State::Closed { failures: failures + 1 }
+                }
+            }
+            State::Closed { .. } => State::Closed { failures: 0 },
+            State::HalfOpen { .. } if probe && failed => {
+                debug!("circuit probe failed; reopening");
+                self.open()
+            }
+            State::HalfOpen { .. } if probe => {
+                debug!("circuit closed");
+                State::Closed { failures: 0 }
+            }
+            // Outcomes of calls sent before the circuit opened are ignored.
+            other => other,
+        };
+
+        *state = next;
+    }
+
+    /// Return the probe slot of a call that ended without an outcome.
+    fn release(&self) {
+        let mut state = self.lock();
+        let current = *state;
+
+        if let State::HalfOpen { probes } = current {
+            *state = State::HalfOpen { probes: probes.saturating_sub(1) };
+        }
+    }
+
+    fn open(&self) -> State {
+        State::Open { until: self.timer.now() + self.open_duration }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clock::MockClock;
+
+    use bytes::Bytes;
+    use futures::future::{self, FutureResult};
+    use http::header::HeaderValue;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Responds with the status code it is set to, in the trailers.
+    struct MockService {
+        code: Arc<AtomicUsize>,
+    }
+
+    struct MockBody {
+        trailers: Option<HeaderMap>,
+    }
+
+    impl Service for MockService {
+        type Request = http::Request<MockBody>;
+        type Response = http::Response<MockBody>;
+        type Error = ::Error<()>;
+        type Future = FutureResult<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, _: Self::Request) -> Self::Future {
+            let code = self.code.load(Ordering::SeqCst);
+
+            let mut trailers = HeaderMap::new();
+            trailers.insert("grpc-status", HeaderValue::from_str(&code.to_string()).unwrap());
+
+            future::ok(http::Response::new(MockBody { trailers: Some(trailers) }))
+        }
+    }
+
+    impl Body for MockBody {
+        type Data = Bytes;
+
+        fn is_end_stream(&self) -> bool {
+            self.trailers.is_none()
+        }
+
+        fn poll_data(&mut self) -> Poll<Option<Bytes>, h2::Error> {
+            Ok(Async::Ready(None))
+        }
+
+        fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, h2::Error> {
+            Ok(Async::Ready(self.trailers.take()))
+        }
+    }
+
+    fn request() -> http::Request<MockBody> {
+        http::Request::new(MockBody { trailers: None })
+    }
+
+    /// Read a response to its end.
+    fn drain<B: Body>(response: http::Response<B>) {
+        let mut body = response.into_body();
+
+        while let Async::Ready(Some(_)) = body.poll_data().unwrap() {}
+        body.poll_trailers().unwrap();
+    }
+
+    #[test]
+    fn opens_on_statuses_in_trailers_and_probes_after_open_duration() {
+        let clock = MockClock::new();
+        let code = Arc::new(AtomicUsize::new(Code::UNAVAILABLE.as_i32() as usize));
+
+        let mut breaker = CircuitBreaker::new(MockService { code: code.clone() })
+            .failure_threshold(2)
+            .open_duration(Duration::from_secs(5))
+            .timer(&clock);
+
+        for _ in 0..2 {
+            let response = breaker.call(request()).wait().unwrap();
+
+            // Nothing is recorded until the trailers are received.
+            assert!(!breaker.is_open());
+            drain(response);
+        }
+
+        assert!(breaker.is_open());
+
+        match breaker.call(request()).wait() {
+            Err(::Error::Grpc(status)) => assert_eq!(status.code(), Code::UNAVAILABLE),
+            other => panic!("call sent while open; ok={:?}", other.is_ok()),
+        }
+
+        code.store(Code::OK.as_i32() as usize, Ordering::SeqCst);
+        clock.advance(Duration::from_secs(5));
+        assert!(!breaker.is_open());
+
+        // The successful probe closes the circuit.
+        drain(breaker.call(request()).wait().unwrap());
+
+        match *breaker.shared.lock() {
+            State::Closed { failures: 0 } => {}
+            state => panic!("circuit not closed; state={:?}", state),
+        }
+    }
+}
@@ -0,0 +1,929 @@
+//! Service config.
+//!
+//! A service config lets the operator of a service control how clients
+//! call it: which load balancing policy to use, and per-method timeouts,
+//! message size limits and retry policies. It is usually delivered by the
+//! resolver, but may also be built directly, for example with
+//! `ServiceConfig::with_method`.
+//!
+//! See the [service config documentation][spec] for details.
+//!
+//! [spec]: https://github.com/grpc/grpc/blob/master/doc/service_config.md
+
+use headers;
+use Code;
+
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Client behavior configured by a service's operator.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ServiceConfig {
+    /// Name of the load balancing policy to use, such as `round_robin`.
+    pub load_balancing_policy: Option<String>,
+
+    /// Per-method configuration.
+    pub method_config: Vec<MethodConfig>,
+
+    /// Limits retries when many calls are failing.
+    pub retry_throttling: Option<RetryThrottling>,
+}
+
+/// A service config shared between a channel and the layers that use it.
+///
+/// The channel replaces the config whenever the resolver provides a new one;
+/// readers always see the latest version.
+#[derive(Debug, Clone, Default)]
+pub struct SharedConfig {
+    inner: Arc<RwLock<Arc<ServiceConfig>>>,
+}
+
+/// Configuration applied to a set of methods.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MethodConfig {
+    /// The methods this configuration applies to.
+    pub names: Vec<Name>,
+
+    /// Whether requests should wait for the channel to become ready, rather
+    /// than fail immediately, when no connection is available.
+    pub wait_for_ready: Option<bool>,
+
+    /// The default timeout for calls to these methods.
+    pub timeout: Option<Duration>,
+
+    /// The largest request message that may be sent.
+    pub max_request_message_bytes: Option<usize>,
+
+    /// The largest response message that may be received.
+    pub max_response_message_bytes: Option<usize>,
+
+    /// How failed calls are retried.
+    pub retry_policy: Option<RetryPolicy>,
+
+    /// How calls are hedged. A method may not have both a retry and a
+    /// hedging policy.
+    pub hedging_policy: Option<HedgingPolicy>,
+}
+
+/// Identifies the methods a `MethodConfig` applies to.
+///
+/// A name without a method matches every method of the service. A name
+/// without a service matches every method that isn't matched otherwise.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Name {
+    /// Fully qualified service name, such as `helloworld.Greeter`.
+    pub service: Option<String>,
+
+    /// Method name, such as `SayHello`.
+    pub method: Option<String>,
+}
+
+/// How failed calls are retried.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// The maximum number of attempts, including the original one.
+    pub max_attempts: u32,
+
+    /// Backoff before the first retry.
+    pub initial_backoff: Duration,
+
+    /// Upper bound on the backoff between attempts.
+    pub max_backoff: Duration,
+
+    /// Factor the backoff is multiplied by after each attempt.
+    pub backoff_multiplier: f64,
+
+    /// Status codes that may be retried.
+    pub retryable_status_codes: Vec<Code>,
+}
+
+/// How calls are hedged.
+///
+/// A hedged call sends up to `max_attempts` copies of the request, each
+/// `hedging_delay` after the previous one, and uses the first response that
+/// does not have a non-fatal status code.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HedgingPolicy {
+    /// The maximum number of attempts, including the original one.
+    pub max_attempts: u32,
+
+    /// Delay between sending attempts.
+    pub hedging_delay: Duration,
+
+    /// Status codes that don't end the call while other attempts may still
+    /// succeed.
+    pub non_fatal_status_codes: Vec<Code>,
+}
+
+/// Limits retries when many calls are failing.
+///
+/// Each channel keeps a token count, starting at `max_tokens`. Every failed
+/// call removes a token and every successful call adds `token_ratio`
+/// tokens. Calls are not retried while the count is at or below half of
+/// `max_tokens`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryThrottling {
+    /// The maximum number of tokens.
+    pub max_tokens: u32,
+
+    /// The number of tokens added for each successful call.
+    pub token_ratio: f64,
+}
+
+// ===== impl ServiceConfig =====
+
+impl ServiceConfig {
+    /// Returns the configuration for the method with the given path, such as
+    /// `/helloworld.Greeter/SayHello`.
+    pub fn method(&self, path: &str) -> Option<&MethodConfig> {
+        let path = path.trim_start_matches('/');
+        let (service, method) = headers::split_method_path(path).unwrap_or((path, ""));
+
+        let is = |name: &Option<String>, value: &str| {
+            name.as_ref().map(|n| n == value).unwrap_or(false)
+        };
+        let is_empty = |name: &Option<String>| {
+            name.as_ref().map(|n| n.is_empty()).unwrap_or(true)
+        };
+
+        // Prefer an exact match, then a match on the service, then the
+        // default configuration.
+        self.find(|name| is(&name.service, service) && is(&name.method, method))
+            .or_else(|| self.find(|name| is(&name.service, service) && is_empty(&name.method)))
+            .or_else(|| self.find(|name| is_empty(&name.service)))
+    }
+
+    /// Configure the methods matched by `path`.
+    ///
+    /// `path` is either a full method path, such as
+    /// `/helloworld.Greeter/SayHello`, a service, such as
+    /// `/helloworld.Greeter/`, or `/` for the default configuration. Any
+    /// configuration previously set for the same path is replaced.
+    pub fn with_method(mut self, path: &str, mut config: MethodConfig) -> Self {
+        let name = Name::from_path(path);
+
+        for existing in &mut self.method_config {
+            existing.names.retain(|n| *n != name);
+        }
+        self.method_config.retain(|c| !c.names.is_empty());
+
+        config.names = vec![name];
+        self.method_config.push(config);
+        self
+    }
+
+    fn find<F>(&self, f: F) -> Option<&MethodConfig>
+    where F: Fn(&Name) -> bool,
+    {
+        self.method_config.iter()
+            .find(|config| config.names.iter().any(|name| f(name)))
+    }
+}
+
+// ===== impl SharedConfig =====
+
+impl SharedConfig {
+    /// Share `config`.
+    pub fn new(config: ServiceConfig) -> Self {
+        SharedConfig {
+            inner: Arc::new(RwLock::new(Arc::new(config))),
+        }
+    }
+
+    /// Returns the current config.
+    pub fn get(&self) -> Arc<ServiceConfig> {
+        match self.inner.read() {
+            Ok(config) => config.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    /// Replace the current config.
+    pub fn set(&self, config: ServiceConfig) {
+        let config = Arc::new(config);
+
+        match self.inner.write() {
+            Ok(mut current) => *current = config,
+            Err(poisoned) => *poisoned.into_inner() = config,
+        }
+    }
+}
+
+// ===== impl MethodConfig =====
+
+impl MethodConfig {
+    /// Set whether calls wait for the channel to become ready.
+    pub fn wait_for_ready(mut self, wait: bool) -> Self {
+        self.wait_for_ready = Some(wait);
+        self
+    }
+
+    /// Set the default timeout for calls.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Set the largest request message that may be sent.
+    pub fn max_request_message_bytes(mut self, limit: usize) -> Self {
+        self.max_request_message_bytes = Some(limit);
+        self
+    }
+
+    /// Set the largest response message that may be received.
+    pub fn max_response_message_bytes(mut self, limit: usize) -> Self {
+        self.max_response_message_bytes = Some(limit);
+        self
+    }
+
+    /// Retry failed calls, replacing any hedging policy.
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(policy);
+        self.hedging_policy = None;
+        self
+    }
+
+    /// Hedge calls, replacing any retry policy.
+    pub fn hedging(mut self, policy: HedgingPolicy) -> Self {
+        self.hedging_policy = Some(policy);
+        self.retry_policy = None;
+        self
+    }
+}
+
+// ===== impl Name =====
+
+impl Name {
+    /// Returns the name matching a method path, such as
+    /// `/helloworld.Greeter/SayHello`.
+    ///
+    /// A path without a method matches the whole service, and an empty path
+    /// matches every method.
+    pub fn from_path(path: &str) -> Self {
+        let path = path.trim_start_matches('/');
+        let (service, method) = headers::split_method_path(path).unwrap_or((path, ""));
+
+        let non_empty = |s: &str| {
+            if s.is_empty() {
+                None
+            } else {
+                Some(s.to_string())
+            }
+        };
+
+        let service = non_empty(service);
+        let method = match service {
+            Some(_) => non_empty(method),
+            None => None,
+        };
+
+        Name { service, method }
+    }
+
+    /// Match every method of `service`.
+    pub fn service(service: &str) -> Self {
+        Name {
+            service: Some(service.to_string()),
+            method: None,
+        }
+    }
+
+    /// Match a single method.
+    pub fn method(service: &str, method: &str) -> Self {
+        Name {
+            service: Some(service.to_string()),
+            method: Some(method.to_string()),
+        }
+    }
+}
+
+// ===== impl RetryPolicy =====
+
+impl RetryPolicy {
+    /// Retry calls failing with any of `codes`, up to `max_attempts`
+    /// attempts in total.
+    ///
+    /// The backoff starts at 100 milliseconds and doubles with every
+    /// attempt, up to one second.
+    pub fn new(max_attempts: u32, codes: Vec<Code>) -> Self {
+        RetryPolicy {
+            max_attempts,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+            backoff_multiplier: 2.0,
+            retryable_status_codes: codes,
+        }
+    }
+
+    /// Set the backoff before the first retry.
+    pub fn initial_backoff(mut self, backoff: Duration) -> Self {
+        self.initial_backoff = backoff;
+        self
+    }
+
+    /// Set the upper bound on the backoff between attempts.
+    pub fn max_backoff(mut self, backoff: Duration) -> Self {
+        self.max_backoff = backoff;
+        self
+    }
+
+    /// Set the factor the backoff is multiplied by after each attempt.
+    pub fn backoff_multiplier(mut self, multiplier: f64) -> Self {
+        self.backoff_multiplier = multiplier;
+        self
+    }
+
+    /// Returns true if a call that failed with `code` may be retried.
+    pub fn is_retryable(&self, code: Code) -> bool {
+        self.retryable_status_codes.contains(&code)
+    }
+}
+
+// ===== impl HedgingPolicy =====
+
+impl HedgingPolicy {
+    /// Send up to `max_attempts` attempts, `delay` apart.
+    pub fn new(max_attempts: u32, delay: Duration) -> Self {
+        HedgingPolicy {
+            max_attempts,
+            hedging_delay: delay,
+            non_fatal_status_codes: vec![],
+        }
+    }
+
+    /// Set the status codes that don't end the call.
+    pub fn non_fatal_status_codes(mut self, codes: Vec<Code>) -> Self {
+        self.non_fatal_status_codes = codes;
+        self
+    }
+
+    /// Returns true if an attempt that failed with `code` should not end the
+    /// call.
+    pub fn is_non_fatal(&self, code: Code) -> bool {
+        self.non_fatal_status_codes.contains(&code)
+    }
+}
+
+// ===== JSON =====
+
+#[cfg(feature = "service-config")]
+pub use self::json::ParseError;
+
+#[cfg(feature = "service-config")]
+mod json {
+    use super::*;
+    use channel::balance::SUPPORTED_POLICIES;
+
+    use serde_json::{self, Value};
+
+    use std::{error, fmt};
+
+    /// Error returned when a service config is invalid.
+    #[derive(Debug)]
+    pub struct ParseError {
+        message: String,
+    }
+
+    impl ServiceConfig {
+        /// Parse a service config from its JSON representation.
+        pub fn from_json(json: &str) -> Result<Self, ParseError> {
+            let value: Value = serde_json::from_str(json)
+                .map_err(|e| ParseError::new(e.to_string()))?;
+
+            ServiceConfig::from_value(&value)
+        }
+
+        fn from_value(value: &Value) -> Result<Self, ParseError> {
+            let obj = value.as_object()
+                .ok_or_else(|| ParseError::new("service config must be an object"))?;
+
+            let mut config = ServiceConfig::default();
+
+            // The first supported entry of `loadBalancingConfig` takes
+            // precedence over the deprecated `loadBalancingPolicy`.
+            if let Some(configs) = obj.get("loadBalancingConfig") {
+                let configs = configs.as_array()
+                    .ok_or_else(|| ParseError::new("loadBalancingConfig must be an array"))?;
+
+                config.load_balancing_policy = configs.iter()
+                    .filter_map(|c| c.as_object())
+                    .filter_map(|c| c.keys().next())
+                    .find(|name| SUPPORTED_POLICIES.contains(&name.as_str()))
+                    .cloned();
+            }
+
+            if config.load_balancing_policy.is_none() {
+                if let Some(policy) = obj.get("loadBalancingPolicy") {
+                    let policy = policy.as_str()
+                        .ok_or_else(|| ParseError::new("loadBalancingPolicy must be a string"))?;
+                    config.load_balancing_policy = Some(policy.to_lowercase());
+                }
+            }
+
+            if let Some(methods) = obj.get("methodConfig") {
+                let methods = methods.as_array()
+                    .ok_or_else(|| ParseError::new("methodConfig must be an array"))?;
+
+                for method in methods {
+                    let method = MethodConfig::from_value(method)?;
+
+                    // A method may only be configured once.
+                    for (i, name) in method.names.iter().enumerate() {
+                        let duplicate = method.names[..i].contains(name) ||
+                            config.method_config.iter().any(|c| c.names.contains(name));
+
+                        if duplicate {
+                            return Err(ParseError::new(format!(
+                                "duplicate methodConfig name: {}/{}",
+                                name.service.as_ref().map(String::as_str).unwrap_or(""),
+                                name.method.as_ref().map(String::as_str).unwrap_or(""))));
+                        }
+                    }
+
+                    config.method_config.push(method);
+                }
+            }
+
+            config.retry_throttling = match obj.get("retryThrottling") {
+                Some(v) => Some(RetryThrottling::from_value(v)?),
+                None => None,
+            };
+
+            Ok(config)
+        }
+    }
+
+    impl MethodConfig {
+        fn from_value(value: &Value) -> Result<Self, ParseError> {
+            let obj = value.as_object()
+                .ok_or_else(|| ParseError::new("methodConfig entries must be objects"))?;
+
+            let mut config = MethodConfig::default();
+
+            if let Some(names) = obj.get("name") {
+                let names = names.as_array()
+                    .ok_or_else(|| ParseError::new("name must be an array"))?;
+
+                for name in names {
+                    // An empty string is the same as an absent field.
+                    let field = |key: &str| {
+                        name.get(key)
+                            .and_then(Value::as_str)
+                            .filter(|s| !s.is_empty())
+                            .map(str::to_string)
+                    };
+
+                    let service = field("service");
+                    let method = field("method");
+
+                    if service.is_none() && method.is_some() {
+                        return Err(ParseError::new("name.method requires name.service"));
+                    }
+
+                    config.names.push(Name { service, method });
+                }
+            }
+
+            config.wait_for_ready = match obj.get("waitForReady") {
+                Some(v) => Some(v.as_bool()
+                    .ok_or_else(|| ParseError::new("waitForReady must be a boolean"))?),
+                None => None,
+            };
+
+            config.timeout = match obj.get("timeout") {
+                Some(v) => Some(duration(v)?),
+                None => None,
+            };
+
+            config.max_request_message_bytes = match obj.get("maxRequestMessageBytes") {
+                Some(v) => Some(integer(v)? as usize),
+                None => None,
+            };
+
+            config.max_response_message_bytes = match obj.get("maxResponseMessageBytes") {
+                Some(v) => Some(integer(v)? as usize),
+                None => None,
+            };
+
+            config.retry_policy = match obj.get("retryPolicy") {
+                Some(v) => Some(RetryPolicy::from_value(v)?),
+                None => None,
+            };
+
+            config.hedging_policy = match obj.get("hedgingPolicy") {
+                Some(v) => Some(HedgingPolicy::from_value(v)?),
+                None => None,
+            };
+
+            if config.retry_policy.is_some() && config.hedging_policy.is_some() {
+                return Err(ParseError::new("retryPolicy and hedgingPolicy are mutually exclusive"));
+            }
+
+            Ok(config)
+        }
+    }
+
+    impl RetryPolicy {
+        fn from_value(value: &Value) -> Result<Self, ParseError> {
+            let field = |name: &str| {
+                value.get(name)
+                    .ok_or_else(|| ParseError::new(format!("retryPolicy.{} is required", name)))
+            };
+
+            let max_attempts = integer(field("maxAttempts")?)?;
+
+            if max_attempts < 2 {
+                return Err(ParseError::new("retryPolicy.maxAttempts must be at least 2"));
+            }
+
+            let backoff_multiplier = field("backoffMultiplier")?
+                .as_f64()
+                .ok_or_else(|| ParseError::new("retryPolicy.backoffMultiplier must be a number"))?;
+
+            if backoff_multiplier <= 0.0 {
+                return Err(ParseError::new("retryPolicy.backoffMultiplier must be positive"));
+            }
+
+            let codes = field("retryableStatusCodes")?
+                .as_array()
+                .ok_or_else(|| ParseError::new("retryPolicy.retryableStatusCodes must be an array"))?;
+
+            if codes.is_empty() {
+                return Err(ParseError::new("retryPolicy.retryableStatusCodes must not be empty"));
+            }
+
+            let retryable_status_codes = codes.iter()
+                .map(code)
+                .collect::<Result<_, _>>()?;
+
+            let initial_backoff = duration(field("initialBackoff")?)?;
+            let max_backoff = duration(field("maxBackoff")?)?;
+
+            if initial_backoff == Duration::from_secs(0) || max_backoff == Duration::from_secs(0) {
+                return Err(ParseError::new("retryPolicy backoffs must be positive"));
+            }
+
+            Ok(RetryPolicy {
+                // Attempts above 5 are treated as 5, per the spec.
+                max_attempts: ::std::cmp::min(max_attempts, 5) as u32,
+                initial_backoff,
+                max_backoff,
+                backoff_multiplier,
+                retryable_status_codes,
+            })
+        }
+    }
+
+    impl HedgingPolicy {
+        fn from_value(value: &Value) -> Result<Self, ParseError> {
+            let max_attempts = value.get("maxAttempts")
+                .ok_or_else(|| ParseError::new("hedgingPolicy.maxAttempts is required"))?;
+            let max_attempts = integer(max_attempts)?;
+
+            if max_attempts < 2 {
+                return Err(ParseError::new("hedgingPolicy.maxAttempts must be at least 2"));
+            }
+
+            let hedging_delay = match value.get("hedgingDelay") {
+                Some(v) => duration(v)?,
+                None => Duration::from_secs(0),
+            };
+
+            let non_fatal_status_codes = match value.get("nonFatalStatusCodes") {
+                Some(codes) => codes.as_array()
+                    .ok_or_else(|| ParseError::new("hedgingPolicy.nonFatalStatusCodes must be an array"))?
+                    .iter()
+                    .map(code)
+                    .collect::<Result<_, _>>()?,
+                None => vec![],
+            };
+
+            Ok(HedgingPolicy {
+                // Attempts above 5 are treated as 5, per the spec.
+                max_attempts: ::std::cmp::min(max_attempts, 5) as u32,
+                hedging_delay,
+                non_fatal_status_codes,
+            })
+        }
+    }
+
+    impl RetryThrottling {
+        fn from_value(value: &Value) -> Result<Self, ParseError> {
+            let field = |name: &str| {
+                value.get(name)
+                    .ok_or_else(|| ParseError::new(format!("retryThrottling.{} is required", name)))
+            };
+
+            let max_tokens = integer(field("maxTokens")?)?;
+
+            if max_tokens == 0 || max_tokens > 1000 {
+                return Err(ParseError::new("retryThrottling.maxTokens must be in (0, 1000]"));
+            }
+
+            let token_ratio = field("tokenRatio")?
+                .as_f64()
+                .ok_or_else(|| ParseError::new("retryThrottling.tokenRatio must be a number"))?;
+
+            if token_ratio <= 0.0 {
+                return Err(ParseError::new("retryThrottling.tokenRatio must be positive"));
+            }
+
+            Ok(RetryThrottling {
+                max_tokens: max_tokens as u32,
+                token_ratio,
+            })
+        }
+    }
+
+    /// The longest protobuf `Duration`, of about 10,000 years.
+    const MAX_DURATION_SECS: f64 = 315_576_000_000.0;
+
+    /// Parse a JSON protobuf `Duration`, such as `"1.5s"`.
+    ///
+    /// Durations longer than a protobuf `Duration` can hold are capped.
+    pub(super) fn duration(value: &Value) -> Result<Duration, ParseError> {
+        let s = value.as_str()
+            .ok_or_else(|| ParseError::new("durations must be strings"))?;
+
+        if !s.ends_with('s') {
+            return Err(ParseError::new(format!("invalid duration: {:?}", s)));
+        }
+
+        let secs: f64 = s[..s.len() - 1].parse()
+            .map_err(|_| ParseError::new(format!("invalid duration: {:?}", s)))?;
+
+        // "NaN" and "inf" parse as floats, but are not durations.
+        if !secs.is_finite() {
+            return Err(ParseError::new(format!("invalid duration: {:?}", s)));
+        }
+
+        if secs < 0.0 {
+            return Err(ParseError::new(format!("negative duration: {:?}", s)));
+        }
+
+        if secs > MAX_DURATION_SECS {
+            return Ok(Duration::from_secs(MAX_DURATION_SECS as u64));
+        }
+
+        let whole = secs.trunc();
+        let nanos = ((secs - whole) * 1_000_000_000.0) as u32;
+
+        Ok(Duration::new(whole as u64, nanos))
+    }
+
+    /// Parse a non-negative integer, which may be encoded as a string.
+    pub(super) fn integer(value: &Value) -> Result<u64, ParseError> {
+        value.as_u64()
+            .or_else(|| value.as_str().and_then(|s| s.parse().ok()))
+            .ok_or_else(|| ParseError::new(format!("invalid integer: {}", value)))
+    }
+
+    /// Parse a status code, given either by name or number.
+    pub(super) fn code(value: &Value) -> Result<Code, ParseError> {
+        let code = match value.as_str() {
+            Some("OK") => Code::OK,
+            Some("CANCELLED") => Code::CANCELED,
+            Some("UNKNOWN") => Code::UNKNOWN,
+            Some("INVALID_ARGUMENT") => Code::INVALID_ARGUMENT,
+            Some("DEADLINE_EXCEEDED") => Code::DEADLINE_EXCEEDED,
+            Some("NOT_FOUND") => Code::NOT_FOUND,
+            Some("ALREADY_EXISTS") => Code::ALREADY_EXISTS,
+            Some("PERMISSION_DENIED") => Code::PERMISSION_DENIED,
+            Some("RESOURCE_EXHAUSTED") => Code::RESOURCE_EXHAUSTED,
+            Some("FAILED_PRECONDITION") => Code::FAILED_PRECONDITION,
+            Some("ABORTED") => Code::ABORTED,
+            Some("OUT_OF_RANGE") => Code::OUT_OF_RANGE,
+            Some("UNIMPLEMENTED") => Code::UNIMPLEMENTED,
+            Some("INTERNAL") => Code::INTERNAL,
+            Some("UNAVAILABLE") => Code::UNAVAILABLE,
+            Some("DATA_LOSS") => Code::DATA_LOSS,
+            Some("UNAUTHENTICATED") => Code::UNAUTHENTICATED,
+            Some(name) => return Err(ParseError::new(format!("unknown status code: {}", name))),
+            None => {
+                let n = value.as_u64()
+                    .ok_or_else(|| ParseError::new(format!("invalid status code: {}", value)))?;
+
+                Code::from_i32(n as i32)
+                    .ok_or_else(|| ParseError::new(format!("unknown status code: {}", n)))?
+            }
+        };
+
+        Ok(code)
+    }
+
+    // ===== impl ParseError =====
+
+    impl ParseError {
+        pub(super) fn new<T: Into<String>>(message: T) -> Self {
+            ParseError { message: message.into() }
+        }
+    }
+
+    impl fmt::Display for ParseError {
+        fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+            write!(fmt, "invalid service config: {}", self.message)
+        }
+    }
+
+    impl error::Error for ParseError {
+        fn description(&self) -> &str {
+            &self.message
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn load_balancing_config_skips_unsupported_policies() {
+            let config = ServiceConfig::from_json(r#"{
+                "loadBalancingConfig": [
+                    { "grpclb": {} },
+                    { "round_robin": {} },
+                    { "pick_first": {} }
+                ]
+            }"#).unwrap();
+
+            assert_eq!(config.load_balancing_policy, Some("round_robin".to_owned()));
+        }
+
+        #[test]
+        fn load_balancing_config_takes_precedence_over_policy() {
+            let config = ServiceConfig::from_json(r#"{
+                "loadBalancingConfig": [{ "pick_first": {} }],
+                "loadBalancingPolicy": "ROUND_ROBIN"
+            }"#).unwrap();
+
+            assert_eq!(config.load_balancing_policy, Some("pick_first".to_owned()));
+        }
+
+        #[test]
+        fn unsupported_load_balancing_config_falls_back_to_policy() {
+            let config = ServiceConfig::from_json(r#"{
+                "loadBalancingConfig": [{ "grpclb": {} }],
+                "loadBalancingPolicy": "ROUND_ROBIN"
+            }"#).unwrap();
+
+            assert_eq!(config.load_balancing_policy, Some("round_robin".to_owned()));
+        }
+
+        #[test]
+        fn method_config_parses_every_field() {
+            let config = ServiceConfig::from_json(r#"{
+                "methodConfig": [{
+                    "name": [{ "service": "test.Service", "method": "Method" }],
+                    "waitForReady": true,
+                    "timeout": "1.5s",
+                    "maxRequestMessageBytes": 1024,
+                    "maxResponseMessageBytes": "2048",
+                    "retryPolicy": {
+                        "maxAttempts": 7,
+                        "initialBackoff": "0.1s",
+                        "maxBackoff": "1s",
+                        "backoffMultiplier": 2,
+                        "retryableStatusCodes": ["UNAVAILABLE", 4]
+                    }
+                }]
+            }"#).unwrap();
+
+            let expected = MethodConfig::default()
+                .wait_for_ready(true)
+                .timeout(Duration::from_millis(1500))
+                .max_request_message_bytes(1024)
+                .max_response_message_bytes(2048)
+                .retry(RetryPolicy::new(5, vec![Code::UNAVAILABLE, Code::DEADLINE_EXCEEDED]));
+
+            let mut method = config.method("/test.Service/Method").unwrap().clone();
+            assert_eq!(method.names, vec![Name::method("test.Service", "Method")]);
+
+            method.names.clear();
+            assert_eq!(method, expected);
+        }
+
+        #[test]
+        fn method_lookup_prefers_the_most_specific_name() {
+            let config = ServiceConfig::from_json(r#"{
+                "methodConfig": [
+                    { "name": [{}], "timeout": "1s" },
+                    { "name": [{ "service": "test.Service" }], "timeout": "2s" },
+                    { "name": [{ "service": "test.Service", "method": "Method" }], "timeout": "3s" }
+                ]
+            }"#).unwrap();
+
+            let timeout = |path| config.method(path).and_then(|c| c.timeout);
+
+            assert_eq!(timeout("/test.Service/Method"), Some(Duration::from_secs(3)));
+            assert_eq!(timeout("/test.Service/Other"), Some(Duration::from_secs(2)));
+            assert_eq!(timeout("/other.Service/Method"), Some(Duration::from_secs(1)));
+        }
+
+        #[test]
+        fn duplicate_method_names_are_rejected() {
+            let result = ServiceConfig::from_json(r#"{
+                "methodConfig": [
+                    { "name": [{ "service": "test.Service" }], "timeout": "1s" },
+                    { "name": [{ "service": "test.Service" }], "timeout": "2s" }
+                ]
+            }"#);
+
+            assert!(result.is_err());
+        }
+
+        #[test]
+        fn empty_service_is_the_default_name() {
+            let result = ServiceConfig::from_json(r#"{
+                "methodConfig": [
+                    { "name": [{}], "timeout": "1s" },
+                    { "name": [{ "service": "" }], "timeout": "2s" }
+                ]
+            }"#);
+
+            assert!(result.is_err());
+        }
+
+        #[test]
+        fn zero_and_negative_backoffs_are_rejected() {
+            for &(initial, max) in &[("0s", "1s"), ("1s", "0s"), ("-1s", "1s"), ("1s", "-1s")] {
+                let json = format!(r#"{{
+                    "methodConfig": [{{
+                        "name": [{{}}],
+                        "retryPolicy": {{
+                            "maxAttempts": 2,
+                            "initialBackoff": "{}",
+                            "maxBackoff": "{}",
+                            "backoffMultiplier": 1,
+                            "retryableStatusCodes": ["UNAVAILABLE"]
+                        }}
+                    }}]
+                }}"#, initial, max);
+
+                assert!(ServiceConfig::from_json(&json).is_err(), "accepted {} and {}", initial, max);
+            }
+        }
+
+        #[test]
+        fn non_positive_backoff_multipliers_are_rejected() {
+            for multiplier in &["0", "0.0", "-1.5"] {
+                let json = format!(r#"{{
+                    "methodConfig": [{{
+                        "name": [{{}}],
+                        "retryPolicy": {{
+                            "maxAttempts": 2,
+                            "initialBackoff": "1s",
+                            "maxBackoff": "1s",
+                            "backoffMultiplier": {},
+                            "retryableStatusCodes": ["UNAVAILABLE"]
+                        }}
+                    }}]
+                }}"#, multiplier);
+
+                assert!(ServiceConfig::from_json(&json).is_err(), "accepted {}", multiplier);
+            }
+        }
+
+        #[test]
+        fn non_finite_durations_are_rejected() {
+            for s in &["NaNs", "infs", "-infs", "1e400s"] {
+                assert!(duration(&Value::from(*s)).is_err(), "accepted {}", s);
+            }
+        }
+
+        #[test]
+        fn long_durations_are_capped() {
+            let max = Duration::from_secs(MAX_DURATION_SECS as u64);
+
+            assert_eq!(duration(&Value::from("1.5s")).unwrap(), Duration::from_millis(1500));
+            assert_eq!(duration(&Value::from("315576000000s")).unwrap(), max);
+            assert_eq!(duration(&Value::from("1e300s")).unwrap(), max);
+        }
+
+        #[test]
+        fn method_name_without_service_is_rejected() {
+            let result = ServiceConfig::from_json(r#"{
+                "methodConfig": [{ "name": [{ "method": "Method" }] }]
+            }"#);
+
+            assert!(result.is_err());
+        }
+
+        #[test]
+        fn retry_and_hedging_policies_are_exclusive() {
+            let result = ServiceConfig::from_json(r#"{
+                "methodConfig": [{
+                    "name": [{}],
+                    "retryPolicy": {
+                        "maxAttempts": 2,
+                        "initialBackoff": "1s",
+                        "maxBackoff": "1s",
+                        "backoffMultiplier": 1,
+                        "retryableStatusCodes": ["UNAVAILABLE"]
+                    },
+                    "hedgingPolicy": { "maxAttempts": 2 }
+                }]
+            }"#);
+
+            assert!(result.is_err());
+        }
+    }
+}
@@ -0,0 +1,250 @@
+use super::{ChannelStats, Connect, CountedIo, Endpoint};
+use http2::Http2Settings;
+use local::LocalBoxBody;
+
+use futures::{Future, IntoFuture, Poll, Async};
+use tokio_core::reactor::Handle;
+use tokio_io::{AsyncRead, AsyncWrite};
+use tower_h2::{Body, BoxBody};
+use tower_h2::client::{Connection, Handshake, HandshakeError};
+
+use std::fmt;
+use std::marker::PhantomData;
+
+/// Dials connections to endpoints over any transport.
+///
+/// Implement `Connector` to run channels over transports the crate does not
+/// provide, such as vsock, serial links or SSH tunnels, and connect the
+/// channel with `Http2Connect`. Closures returning a future of the
+/// connection are connectors:
+///
+/// ```ignore
+/// let connector = |endpoint: &Endpoint| VsockStream::connect(cid, endpoint.addr().port());
+/// let channel = Channel::new(resolver, Http2Connect::new(connector, handle.clone()), policy);
+/// ```
+///
+/// `Http2Connect::local` connects channels whose request bodies need not be
+/// `Send` (see the `local` module).
+pub trait Connector {
+    /// The connection.
+    type Io: AsyncRead + AsyncWrite;
+
+    /// Error produced when dialing fails.
+    type Error: fmt::Debug;
+
+    /// The dial future.
+    type Future: Future<Item = Self::Io, Error = Self::Error>;
+
+    /// Dial `endpoint`.
+    fn dial(&mut self, endpoint: &Endpoint) -> Self::Future;
+}
+
+/// Connects a `Channel` over connections dialed by a `Connector`, on which
+/// it runs the HTTP/2.0 handshake.
+///
+/// `B` is the request body of the connections, `BoxBody` unless the channel
+/// was created with `Http2Connect::local`.
+pub struct Http2Connect<C, B = BoxBody> {
+    connector: C,
+    handle: Handle,
+    stats: ChannelStats,
+    http2: Http2Settings,
+    _body: PhantomData<fn(B)>,
+}
+
+/// Future returned by `Http2Connect`.
+pub struct Http2ConnectFuture<C, B = BoxBody>
+where C: Connector,
+      B: Body,
+{
+    state: State<C, B>,
+    handle: Handle,
+    http2: Http2Settings,
+    stats: ChannelStats,
+}
+
+/// Error produced by `Http2Connect`.
+#[derive(Debug)]
+pub enum Http2ConnectError<E> {
+    /// Dialing failed.
+    Dial(E),
+
+    /// The HTTP/2.0 handshake failed.
+    Http2(HandshakeError),
+}
+
+enum State<C, B>
+where C: Connector,
+      B: Body,
+{
+    Dialing(C::Future),
+    Http2(Handshake<CountedIo<C::Io>, Handle, B>),
+}
+
+// ===== impl Connector =====
+
+impl<F, T> Connector for F
+where F: FnMut(&Endpoint) -> T,
+      T: IntoFuture,
+      T::Item: AsyncRead + AsyncWrite,
+      T::Error: fmt::Debug,
+{
+    type Io = T::Item;
+    type Error = T::Error;
+    type Future = T::Future;
+
+    fn dial(&mut self, endpoint: &Endpoint) -> Self::Future {
+        (self)(endpoint).into_future()
+    }
+}
+
+// ===== impl Http2Connect =====
+
+impl<C> Http2Connect<C> {
+    /// Dial endpoints with `connector`.
+    ///
+    /// Connections run on the reactor of `handle`.
+    pub fn new(connector: C, handle: Handle) -> Self {
+        Http2Connect {
+            connector,
+            handle,
+            stats: ChannelStats::new(),
+            http2: Http2Settings::new(),
+            _body: PhantomData,
+        }
+    }
+}
+
+impl<C> Http2Connect<C, LocalBoxBody> {
+    /// Dial endpoints with `connector`, for a channel whose request bodies
+    /// need not be `Send`.
+    ///
+    /// Connections run on the reactor of `handle`.
+    pub fn local(connector: C, handle: Handle) -> Self {
+        Http2Connect {
+            connector,
+            handle,
+            stats: ChannelStats::new(),
+            http2: Http2Settings::new(),
+            _body: PhantomData,
+        }
+    }
+}
+
+impl<C, B> Http2Connect<C, B> {
+    /// Count the bytes of each connection in `stats`.
+    ///
+    /// Pass the same `ChannelStats` to `Channel::with_stats`.
+    pub fn with_stats(mut self, stats: ChannelStats) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    /// Negotiate each connection with `settings`.
+    pub fn http2_settings(mut self, settings: Http2Settings) -> Self {
+        self.http2 = settings;
+        self
+    }
+
+    /// Returns a reference to the connector.
+    pub fn get_ref(&self) -> &C {
+        &self.connector
+    }
+
+    /// Returns a mutable reference to the connector.
+    pub fn get_mut(&mut self) -> &mut C {
+        &mut self.connector
+    }
+}
+
+impl<C, B> Clone for Http2Connect<C, B>
+where C: Clone,
+{
+    fn clone(&self) -> Self {
+        Http2Connect {
+            connector: self.connector.clone(),
+            handle: self.handle.clone(),
+            stats: self.stats.clone(),
+            http2: self.http2.clone(),
+            _body: PhantomData,
+        }
+    }
+}
+
+impl<C, B> Connect for Http2Connect<C, B>
+where C: Connector,
+      C::Io: 'static,
+      B: Body + 'static,
+{
+    type Service = Connection<CountedIo<C::Io>, Handle, B>;
+    type Error = Http2ConnectError<C::Error>;
+    type Future = Http2ConnectFuture<C, B>;
+
+    fn connect(&mut self, endpoint: &Endpoint) -> Self::Future {
+        Http2ConnectFuture {
+            state: State::Dialing(self.connector.dial(endpoint)),
+            handle: self.handle.clone(),
+            http2: self.http2.clone(),
+            stats: self.stats.clone(),
+        }
+    }
+}
+
+impl<C, B> fmt::Debug for Http2Connect<C, B>
+where C: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Http2Connect")
+            .field("connector", &self.connector)
+            .field("handle", &self.handle)
+            .field("stats", &self.stats)
+            .field("http2", &self.http2)
+            .finish()
+    }
+}
+
+// ===== impl Http2ConnectFuture =====
+
+impl<C, B> Future for Http2ConnectFuture<C, B>
+where C: Connector,
+      C::Io: 'static,
+      B: Body + 'static,
+{
+    type Item = Connection<CountedIo<C::Io>, Handle, B>;
+    type Error = Http2ConnectError<C::Error>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                State::Dialing(ref mut dial) => {
+                    let io = try_ready!(dial.poll().map_err(Http2ConnectError::Dial));
+
+                    let builder = self.http2.client_builder();
+                    State::Http2(Handshake::new(self.stats.io(io), self.handle.clone(), &builder))
+                }
+                State::Http2(ref mut h2) => {
+                    let conn = try_ready!(h2.poll().map_err(Http2ConnectError::Http2));
+                    return Ok(Async::Ready(conn));
+                }
+            };
+
+            self.state = next;
+        }
+    }
+}
+
+impl<C, B> fmt::Debug for Http2ConnectFuture<C, B>
+where C: Connector,
+      B: Body,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let state = match self.state {
+            State::Dialing(..) => "Dialing",
+            State::Http2(..) => "Http2",
+        };
+
+        fmt.debug_struct("Http2ConnectFuture")
+            .field("state", &state)
+            .finish()
+    }
+}
@@ -0,0 +1,366 @@
+//! Health-checked subchannels.
+//!
+//! Wrapping a channel's connector with `HealthCheck` runs the
+//! `grpc.health.v1.Health/Watch` method on every connection. Subchannels
+//! whose endpoint does not report `SERVING` are never ready, so the channel's
+//! policy will not pick them. Once an endpoint reports any other status, or
+//! its watch fails, its subchannel is in `TransientFailure`, and calls fail
+//! fast when no other endpoint is serving.
+
+use super::{Connect, Endpoint};
+use client::{self, Encodable};
+use client::unary::Once;
+use clock::{Clock, Sleep};
+use codec::Streaming;
+use health::{HealthClient, HealthCheckRequest, HealthCheckResponse, ServingStatus};
+use Code;
+
+use futures::{Future, Stream, Poll, Async};
+use http::{self, Uri};
+use tower::Service;
+use tower_h2::{Body, Data, HttpService};
+
+use std::fmt;
+use std::time::Duration;
+
+/// Connects to endpoints and watches their health.
+#[derive(Clone)]
+pub struct HealthCheck<C> {
+    inner: C,
+    service: String,
+    timer: Clock,
+    retry: Duration,
+}
+
+/// Connect future returned by `HealthCheck`.
+pub struct ConnectFuture<F> {
+    inner: F,
+    uri: Option<Uri>,
+    service: String,
+    timer: Clock,
+    retry: Duration,
+}
+
+/// A connection whose readiness depends on its endpoint's health.
+pub struct Checked<S>
+where S: HttpService,
+{
+    client: HealthClient<S>,
+    service: String,
+    timer: Clock,
+    retry: Duration,
+    state: Watch<S>,
+    serving: bool,
+
+    /// Set once the endpoint reports that it is not serving, or the watch
+    /// fails, until it serves again.
+    failing: bool,
+}
+
+enum Watch<S>
+where S: HttpService,
+{
+    /// The watch call must be (re)started.
+    Start,
+
+    /// Waiting for the watch response head.
+    Pending(client::server_streaming::ResponseFuture<HealthCheckResponse, S::Future>),
+
+    /// Receiving health updates.
+    Streaming(Streaming<HealthCheckResponse, S::ResponseBody>),
+
+    /// Waiting to restart the watch after it failed.
+    Retry(Sleep),
+
+    /// The endpoint does not implement health checking.
+    Disabled,
+}
+
+// ===== impl HealthCheck =====
+
+impl<C> HealthCheck<C>
+where C: Connect,
+{
+    /// Watch the health of `service` on every connection made by `inner`.
+    ///
+    /// An empty service name checks the health of the server as a whole.
+    pub fn new(inner: C, service: &str) -> Self {
+        HealthCheck {
+            inner,
+            service: service.to_string(),
+            timer: Clock::default(),
+            retry: Duration::from_secs(1),
+        }
+    }
+
+    /// Set how long to wait before restarting a failed watch.
+    pub fn retry_after(mut self, duration: Duration) -> Self {
+        self.retry = duration;
+        self
+    }
+
+    /// Use the provided timer, or a `MockClock`, to schedule restarts.
+    pub fn timer<T: Into<Clock>>(mut self, timer: T) -> Self {
+        self.timer = timer.into();
+        self
+    }
+}
+
+impl<C> Connect for HealthCheck<C>
+where C: Connect,
+      Once<HealthCheckRequest>: Encodable<<C::Service as HttpService>::RequestBody>,
+      <C::Service as HttpService>::ResponseBody: Body<Data = Data>,
+{
+    type Service = Checked<C::Service>;
+    type Error = C::Error;
+    type Future = ConnectFuture<C::Future>;
+
+    fn is_failing(service: &Self::Service) -> bool {
+        service.failing
+    }
+
+    fn connect(&mut self, endpoint: &Endpoint) -> Self::Future {
+        let uri = format!("http://{}", endpoint.addr())
+            .parse()
+            .ok();
+
+        ConnectFuture {
+            inner: self.inner.connect(endpoint),
+            uri,
+            service: self.service.clone(),
+            timer: self.timer.clone(),
+            retry: self.retry,
+        }
+    }
+}
+
+impl<C> fmt::Debug for HealthCheck<C>
+where C: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("HealthCheck")
+            .field("inner", &self.inner)
+            .field("service", &self.service)
+            .field("retry", &self.retry)
+            .finish()
+    }
+}
+
+// ===== impl ConnectFuture =====
+
+impl<F> Future for ConnectFuture<F>
+where F: Future,
+      F::Item: HttpService,
+{
+    type Item = Checked<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let service = try_ready!(self.inner.poll());
+
+        let uri = self.uri.take().expect("polled after complete");
+        let client = HealthClient::new(service, uri)
+            .expect("socket address URIs have a scheme and authority");
+
+        Ok(Async::Ready(Checked {
+            client,
+            service: self.service.clone(),
+            timer: self.timer.clone(),
+            retry: self.retry,
+            state: Watch::Start,
+            serving: false,
+            failing: false,
+        }))
+    }
+}
+
+impl<F> fmt::Debug for ConnectFuture<F>
+where F: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("ConnectFuture")
+            .field("inner", &self.inner)
+            .field("service", &self.service)
+            .finish()
+    }
+}
+
+// ===== impl Checked =====
+
+impl<S> Checked<S>
+where S: HttpService,
+      Once<HealthCheckRequest>: Encodable<S::RequestBody>,
+      S::ResponseBody: Body<Data = Data>,
+{
+    /// Returns true if the endpoint last reported that it is serving.
+    pub fn is_serving(&self) -> bool {
+        self.serving
+    }
+
+    /// Returns true if the endpoint last reported that it is not serving,
+    /// or the watch failed.
+    ///
+    /// Neither is true before the endpoint's first report.
+    pub fn is_failing(&self) -> bool {
+        self.failing
+    }
+
+    /// Drive the watch call, updating `serving`.
+    fn poll_health(&mut self) -> Poll<(), S::Error> {
+        loop {
+            let next = match self.state {
+                Watch::Start => {
+                    try_ready!(self.client.get_mut().poll_ready());
+                    Watch::Pending(self.client.watch(&self.service))
+                }
+                Watch::Pending(ref mut fut) => {
+                    match fut.poll() {
+                        Ok(Async::Ready(response)) => Watch::Streaming(response.into_inner()),
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Err(::Error::Grpc(ref status)) if status.code() == Code::UNIMPLEMENTED => {
+                            debug!("health checking not implemented by endpoint; disabling");
+                            self.serving = true;
+                            self.failing = false;
+                            Watch::Disabled
+                        }
+                        Err(::Error::Inner(e)) => return Err(e),
+                        Err(::Error::Grpc(status)) => {
+                            debug!("health watch failed; status={:?}", status);
+                            self.serving = false;
+                            self.failing = true;
+                            Watch::Retry(self.timer.sleep(self.retry))
+                        }
+                    }
+                }
+                Watch::Streaming(ref mut stream) => {
+                    match stream.poll() {
+                        Ok(Async::Ready(Some(response))) => {
+                            let status = response.serving_status();
+                            trace!("health update; status={:?}", status);
+                            self.serving = status == ServingStatus::Serving;
+                            self.failing = !self.serving;
+                            continue;
+                        }
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Ok(Async::Ready(None)) => {
+                            debug!("health watch ended");
+                            self.serving = false;
+                            self.failing = true;
+                            Watch::Retry(self.timer.sleep(self.retry))
+                        }
+                        Err(e) => {
+                            debug!("health watch failed; err={:?}", e);
+                            self.serving = false;
+                            self.failing = true;
+                            Watch::Retry(self.timer.sleep(self.retry))
+                        }
+                    }
+                }
+                Watch::Retry(ref mut sleep) => {
+                    match sleep.poll() {
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        _ => Watch::Start,
+                    }
+                }
+                Watch::Disabled => return Ok(Async::Ready(())),
+            };
+
+            self.state = next;
+        }
+    }
+}
+
+impl<S> Service for Checked<S>
+where S: HttpService,
+      Once<HealthCheckRequest>: Encodable<S::RequestBody>,
+      S::ResponseBody: Body<Data = Data>,
+{
+    type Request = http::Request<S::RequestBody>;
+    type Response = http::Response<S::ResponseBody>;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        // Polling the watch registers interest in health updates, so the
+        // task is notified when the endpoint starts serving again.
+        self.poll_health()?;
+
+        if !self.serving {
+            return Ok(Async::NotReady);
+        }
+
+        self.client.get_mut().poll_ready()
+    }
+
+    fn call(&mut self, request: Self::Request) -> Self::Future {
+        self.client.get_mut().call(request)
+    }
+}
+
+impl<S> fmt::Debug for Checked<S>
+where S: HttpService + fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Checked")
+            .field("client", &self.client)
+            .field("service", &self.service)
+            .field("serving", &self.serving)
+            .field("failing", &self.failing)
+            .finish()
+    }
+}
+
+#[cfg(all(test, feature = "in-process"))]
+mod tests {
+    use super::*;
+    use channel::{Channel, Connectivity};
+    use channel::balance::RoundRobin;
+    use channel::resolve::Fixed;
+    use health::Health;
+    use inprocess::{Harness, InProcessConnect};
+
+    use futures::{future, stream};
+    use tower_h2::BoxBody;
+
+    use std::net::SocketAddr;
+
+    fn addr(port: u16) -> SocketAddr {
+        ([127, 0, 0, 1], port).into()
+    }
+
+    fn request() -> http::Request<BoxBody> {
+        let body = stream::once::<_, ::Error>(Ok(HealthCheckRequest::default())).into_encode();
+
+        http::Request::builder()
+            .uri("/grpc.health.v1.Health/Check")
+            .body(body)
+            .unwrap()
+    }
+
+    #[test]
+    fn call_fails_fast_when_every_endpoint_is_not_serving() {
+        let mut harness = Harness::new().unwrap();
+
+        let health = Health::new();
+        health.reporter().set_not_serving("");
+
+        let connect = HealthCheck::new(InProcessConnect::new(health, harness.handle()), "")
+            .timer(harness.clock());
+        let resolver = Fixed::new(vec![addr(1), addr(2)]);
+        let mut channel = Channel::new(resolver, connect, RoundRobin::new())
+            .timer(harness.clock());
+
+        // The channel waits for the endpoints' first reports.
+        harness.run(future::poll_fn(|| channel.poll_ready())).unwrap();
+
+        for sub in channel.subchannels().iter() {
+            assert_eq!(sub.connectivity(), Connectivity::TransientFailure);
+            assert!(!sub.is_ready());
+        }
+
+        match harness.run(channel.call(request())) {
+            Err(::Error::Grpc(status)) => assert_eq!(status.code(), Code::UNAVAILABLE),
+            other => panic!("call not failed; ok={:?}", other.is_ok()),
+        }
+    }
+}
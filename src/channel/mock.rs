@@ -0,0 +1,106 @@
+//! Subchannels for testing policies and backoff without a `Channel`.
+
+use super::{Connect, Endpoint, Subchannel};
+use clock::{Clock, MockClock};
+
+use futures::{Async, Poll};
+use futures::future::{self, FutureResult};
+use h2;
+use http;
+use tower::Service;
+use tower_h2::BoxBody;
+
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Connects to `MockService`s, unless their address is down.
+#[derive(Debug, Default)]
+pub(crate) struct MockConnect {
+    down: Arc<Mutex<Vec<SocketAddr>>>,
+}
+
+/// A connection that is ready until its address goes down, and never
+/// answers.
+#[derive(Debug)]
+pub(crate) struct MockService {
+    addr: SocketAddr,
+    down: Arc<Mutex<Vec<SocketAddr>>>,
+}
+
+pub(crate) fn addr(port: u16) -> SocketAddr {
+    ([127, 0, 0, 1], port).into()
+}
+
+/// Returns idle subchannels for `endpoints`, measuring time with `clock`.
+pub(crate) fn subchannels(endpoints: &[Endpoint], clock: &MockClock) -> Vec<Subchannel<MockConnect>> {
+    endpoints.iter()
+        .map(|endpoint| Subchannel::new(endpoint.clone(), Clock::from(clock)))
+        .collect()
+}
+
+pub(crate) fn request() -> http::Request<()> {
+    http::Request::new(())
+}
+
+// ===== impl MockConnect =====
+
+impl MockConnect {
+    /// Connect `subchannel`, which is then ready.
+    pub(crate) fn up(&mut self, subchannel: &mut Subchannel<MockConnect>) {
+        self.addrs_down().retain(|addr| addr != subchannel.endpoint().addr());
+        subchannel.connect(self);
+        subchannel.poll();
+    }
+
+    /// Fail `subchannel`'s connection, or its next connection attempt,
+    /// after which it backs off.
+    ///
+    /// Must be called from a task, which the backoff wakes.
+    pub(crate) fn down(&mut self, subchannel: &mut Subchannel<MockConnect>) {
+        self.addrs_down().push(*subchannel.endpoint().addr());
+        subchannel.connect(self);
+        subchannel.poll();
+    }
+
+    fn addrs_down(&self) -> MutexGuard<Vec<SocketAddr>> {
+        self.down.lock().unwrap()
+    }
+}
+
+impl Connect for MockConnect {
+    type Service = MockService;
+    type Error = ();
+    type Future = FutureResult<MockService, ()>;
+
+    fn connect(&mut self, endpoint: &Endpoint) -> Self::Future {
+        if self.addrs_down().contains(endpoint.addr()) {
+            return future::err(());
+        }
+
+        future::ok(MockService {
+            addr: *endpoint.addr(),
+            down: self.down.clone(),
+        })
+    }
+}
+
+// ===== impl MockService =====
+
+impl Service for MockService {
+    type Request = http::Request<BoxBody>;
+    type Response = http::Response<BoxBody>;
+    type Error = h2::Error;
+    type Future = future::Empty<Self::Response, h2::Error>;
+
+    fn poll_ready(&mut self) -> Poll<(), h2::Error> {
+        if self.down.lock().unwrap().contains(&self.addr) {
+            return Err(h2::Reason::INTERNAL_ERROR.into());
+        }
+
+        Ok(Async::Ready(()))
+    }
+
+    fn call(&mut self, _: http::Request<BoxBody>) -> Self::Future {
+        future::empty()
+    }
+}
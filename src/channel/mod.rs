@@ -0,0 +1,1328 @@
+//! A client channel that balances requests across the endpoints of a target.
+//!
+//! A `Channel` is an HTTP/2.0 service, so it may be used anywhere a single
+//! connection would be, such as with `client::Grpc` or generated clients.
+//!
+//! A subchannel whose connection fails is connected again after a jittered,
+//! exponentially growing backoff of its own, as in gRPC's connection backoff
+//! protocol.
+//!
+//! When every endpoint has failed, calls fail immediately with `UNAVAILABLE`
+//! by default. Calls that wait for ready are instead queued until a
+//! connection is available or their `grpc-timeout` expires. Whether a call
+//! waits is decided by, in order, a `WaitForReady` request extension, the
+//! method's `waitForReady` service config, and the channel's default.
+//! Queued calls drive the channel while they are polled, so they are sent
+//! as soon as a subchannel is ready, even if `poll_ready` is not called
+//! again.
+//!
+//! Each channel counts its streams, and the bytes of connections wrapped with
+//! `ChannelStats::io`, in a `ChannelStats`.
+
+pub mod balance;
+pub mod breaker;
+pub mod config;
+#[cfg(feature = "protobuf")]
+pub mod health;
+pub mod resolve;
+pub mod retry;
+#[cfg(feature = "xds")]
+pub mod xds;
+
+#[cfg(any(feature = "in-process", feature = "tcp"))]
+mod connector;
+#[cfg(test)]
+mod mock;
+mod stats;
+mod subchannel;
+
+pub use self::balance::Policy;
+pub use self::config::{ServiceConfig, SharedConfig, MethodConfig, RetryPolicy, HedgingPolicy};
+#[cfg(any(feature = "in-process", feature = "tcp"))]
+pub use self::connector::{Connector, Http2Connect, Http2ConnectError, Http2ConnectFuture};
+pub use self::resolve::{Endpoint, Resolve, Update};
+pub use self::stats::{ChannelStats, ChannelStatsSnapshot, CountedIo};
+pub use self::subchannel::{Connectivity, Stats, Subchannel};
+
+use self::stats::Stream;
+use clock::{Clock, Sleep};
+use headers;
+use limit::{ReceiveLimit, SendLimit};
+use strictness::Strictness;
+use Status;
+use timeout;
+
+use futures::{Future, Poll, Async};
+use futures::sync::oneshot;
+use h2;
+use http::{self, HeaderMap};
+use tower::Service;
+use tower_h2::{Body, HttpService};
+
+use std::{fmt, mem};
+use std::collections::VecDeque;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::time::Duration;
+
+/// Establishes HTTP/2.0 connections to endpoints.
+///
+/// To run channels over another transport, implement `Connector`, which only
+/// dials connections, and use it with `Http2Connect`.
+pub trait Connect {
+    /// The connection's HTTP/2.0 service
+    type Service: HttpService;
+
+    /// Error produced when connecting fails.
+    type Error: fmt::Debug;
+
+    /// The connect future
+    type Future: Future<Item = Self::Service, Error = Self::Error>;
+
+    /// Connect to `endpoint`.
+    fn connect(&mut self, endpoint: &Endpoint) -> Self::Future;
+
+    /// Returns true if `service` is connected, but its endpoint has reported
+    /// that it cannot serve requests.
+    ///
+    /// The subchannel of a failing connection is in `TransientFailure`,
+    /// though it is not reconnected.
+    fn is_failing(_service: &Self::Service) -> bool {
+        false
+    }
+}
+
+/// Balances requests across connections to the endpoints of a target.
+pub struct Channel<R, C, P>
+where C: Connect,
+{
+    /// Shared with queued calls, which drive the channel while they wait.
+    inner: Arc<Mutex<Inner<R, C, P>>>,
+}
+
+/// The channel's subchannels, borrowed from a `Channel`.
+pub struct Subchannels<'a, R: 'a, C: Connect + 'a, P: 'a> {
+    inner: MutexGuard<'a, Inner<R, C, P>>,
+}
+
+struct Inner<R, C, P>
+where C: Connect,
+{
+    /// Provides the target's endpoints.
+    resolver: R,
+
+    /// Establishes connections to endpoints.
+    connect: C,
+
+    /// Selects subchannels.
+    policy: P,
+
+    /// One subchannel per resolved endpoint, in resolver order.
+    subchannels: Vec<Subchannel<C>>,
+
+    /// Set once the resolver has produced its first update.
+    resolved: bool,
+
+    /// The most recent service config provided by the resolver.
+    config: SharedConfig,
+
+    /// Whether calls wait for a ready subchannel unless configured otherwise.
+    wait_for_ready: bool,
+
+    /// Calls waiting for a ready subchannel, in arrival order.
+    queued: VecDeque<Queued<C>>,
+
+    /// Counts the channel's streams and bytes.
+    stats: ChannelStats,
+
+    timer: Clock,
+
+    /// How strictly responses are decoded, if set.
+    strictness: Option<Strictness>,
+}
+
+/// Request extension that overrides whether a call waits for the channel to
+/// become ready.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WaitForReady(pub bool);
+
+/// The response future returned by `Channel`.
+pub struct ResponseFuture<R, C, P>
+where C: Connect,
+{
+    inner: Option<<C::Service as HttpService>::Future>,
+
+    /// Records the outcome on the subchannel the request was sent on.
+    stats: Option<Arc<Stats>>,
+
+    /// The stream the request was sent on.
+    stream: Option<Stream>,
+
+    /// Set while the request is queued.
+    waiting: Option<Waiting<R, C, P>>,
+
+    /// Added to the response, for the codec.
+    strictness: Option<Strictness>,
+
+    /// The largest response message, added to the response for the codec.
+    max_response_size: Option<usize>,
+}
+
+/// The response body returned by `Channel`.
+pub struct ResponseBody<B> {
+    inner: B,
+    stream: Stream,
+
+    /// Records the outcome once the trailers are received.
+    stats: Option<Arc<Stats>>,
+}
+
+/// A call waiting for a ready subchannel.
+struct Queued<C>
+where C: Connect,
+{
+    request: Request<C>,
+    tx: oneshot::Sender<Dispatched<C>>,
+}
+
+/// A queued call once it has been sent on a subchannel.
+type Dispatched<C> = (<<C as Connect>::Service as HttpService>::Future, Arc<Stats>, Stream);
+
+struct Waiting<R, C, P>
+where C: Connect,
+{
+    rx: oneshot::Receiver<Dispatched<C>>,
+    deadline: Option<Sleep>,
+
+    /// Nothing else is bound to poll the channel while a call waits, so
+    /// waiting calls drive it themselves.
+    channel: Weak<Mutex<Inner<R, C, P>>>,
+}
+
+type Request<C> = http::Request<<<C as Connect>::Service as HttpService>::RequestBody>;
+type Response<C> = http::Response<ResponseBody<<<C as Connect>::Service as HttpService>::ResponseBody>>;
+type Error<C> = ::Error<<<C as Connect>::Service as HttpService>::Error>;
+
+// ===== impl Channel =====
+
+impl<R, C, P> Channel<R, C, P>
+where R: Resolve,
+      C: Connect,
+      P: Policy,
+{
+    /// Create a new channel.
+    pub fn new(resolver: R, connect: C, policy: P) -> Self {
+        let inner = Inner {
+            resolver,
+            connect,
+            policy,
+            subchannels: vec![],
+            resolved: false,
+            config: SharedConfig::default(),
+            wait_for_ready: false,
+            queued: VecDeque::new(),
+            stats: ChannelStats::new(),
+            timer: Clock::default(),
+            strictness: None,
+        };
+
+        Channel {
+            inner: Arc::new(Mutex::new(inner)),
+        }
+    }
+
+    /// Count the channel's streams in `stats`.
+    ///
+    /// This allows the same `ChannelStats` to be given to the connector, to
+    /// count the bytes of its connections.
+    pub fn with_stats(mut self, stats: ChannelStats) -> Self {
+        self.inner_mut().stats = stats;
+        self
+    }
+
+    /// Returns a handle to the channel's stream and byte counters.
+    pub fn stats(&self) -> ChannelStats {
+        self.lock().stats.clone()
+    }
+
+    /// Set whether calls wait for a ready subchannel, rather than failing,
+    /// when every endpoint has failed.
+    ///
+    /// The method's service config and the `WaitForReady` request extension
+    /// take precedence.
+    pub fn wait_for_ready(mut self, wait: bool) -> Self {
+        self.inner_mut().wait_for_ready = wait;
+        self
+    }
+
+    /// Configure the methods matched by `path`, as with
+    /// `ServiceConfig::with_method`.
+    ///
+    /// A service config provided by the resolver replaces this
+    /// configuration.
+    pub fn with_method_config(mut self, path: &str, config: MethodConfig) -> Self {
+        {
+            let inner = self.inner_mut();
+            let current = (*inner.config.get()).clone();
+            inner.config.set(current.with_method(path, config));
+        }
+        self
+    }
+
+    /// Use the provided timer, or a `MockClock`, to enforce the deadlines
+    /// of queued calls and the reconnection backoff of subchannels.
+    ///
+    /// The clock is also given to the policy.
+    pub fn timer<T: Into<Clock>>(mut self, timer: T) -> Self {
+        {
+            let inner = self.inner_mut();
+            inner.timer = timer.into();
+            inner.policy.set_clock(&inner.timer);
+        }
+        self
+    }
+
+    /// Decode the responses of the channel's calls with `strictness`.
+    ///
+    /// Responses are decoded strictly by default.
+    pub fn strictness(mut self, strictness: Strictness) -> Self {
+        self.inner_mut().strictness = Some(strictness);
+        self
+    }
+
+    /// Returns the service config currently in use.
+    pub fn service_config(&self) -> Arc<ServiceConfig> {
+        self.lock().config.get()
+    }
+
+    /// Returns a handle to the channel's service config that stays up to
+    /// date as the resolver provides new configs.
+    pub fn shared_config(&self) -> SharedConfig {
+        self.lock().config.clone()
+    }
+
+    /// Returns the configuration that applies to the method with the given
+    /// path.
+    pub fn method_config(&self, path: &str) -> Option<MethodConfig> {
+        self.lock().config.get().method(path).cloned()
+    }
+
+    /// Returns the channel's subchannels, in resolver order.
+    ///
+    /// Queued calls cannot drive the channel until the returned value is
+    /// dropped.
+    pub fn subchannels(&self) -> Subchannels<R, C, P> {
+        Subchannels { inner: self.lock() }
+    }
+
+    fn lock(&self) -> MutexGuard<Inner<R, C, P>> {
+        lock(&self.inner)
+    }
+
+    fn inner_mut(&mut self) -> &mut Inner<R, C, P> {
+        Arc::get_mut(&mut self.inner)
+            .expect("channel configured after use")
+            .get_mut()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<R, C, P> Service for Channel<R, C, P>
+where R: Resolve,
+      C: Connect,
+      P: Policy,
+{
+    type Request = Request<C>;
+    type Response = Response<C>;
+    type Error = Error<C>;
+    type Future = ResponseFuture<R, C, P>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        let mut inner = self.lock();
+
+        if !inner.drive() {
+            return Ok(Async::NotReady);
+        }
+
+        let ready = inner.subchannels.iter()
+            .any(|sub| sub.is_ready());
+
+        let pending = inner.subchannels.iter()
+            .any(|sub| {
+                let connectivity = sub.connectivity();
+                connectivity == Connectivity::Connecting ||
+                    connectivity == Connectivity::Ready
+            });
+
+        if ready {
+            return Ok(Async::Ready(()));
+        }
+
+        if pending {
+            return Ok(Async::NotReady);
+        }
+
+        // Every endpoint has failed. Accept the request so that it may fail
+        // fast or be queued; each subchannel is connected again once its
+        // own backoff has elapsed.
+        debug!("no subchannels available; endpoints={}", inner.subchannels.len());
+
+        Ok(Async::Ready(()))
+    }
+
+    fn call(&mut self, request: Self::Request) -> Self::Future {
+        let channel = Arc::downgrade(&self.inner);
+        self.lock().call(request, channel)
+    }
+}
+
+impl<R, C, P> fmt::Debug for Channel<R, C, P>
+where R: fmt::Debug,
+      C: Connect,
+      P: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let inner = lock(&self.inner);
+
+        fmt.debug_struct("Channel")
+            .field("resolver", &inner.resolver)
+            .field("policy", &inner.policy)
+            .field("subchannels", &inner.subchannels)
+            .field("wait_for_ready", &inner.wait_for_ready)
+            .field("queued", &inner.queued.len())
+            .finish()
+    }
+}
+
+// ===== impl Subchannels =====
+
+impl<'a, R, C, P> Deref for Subchannels<'a, R, C, P>
+where C: Connect,
+{
+    type Target = [Subchannel<C>];
+
+    fn deref(&self) -> &[Subchannel<C>] {
+        &self.inner.subchannels
+    }
+}
+
+impl<'a, R, C, P> fmt::Debug for Subchannels<'a, R, C, P>
+where C: Connect,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_list()
+            .entries(self.iter())
+            .finish()
+    }
+}
+
+// ===== impl Inner =====
+
+impl<R, C, P> Inner<R, C, P>
+where R: Resolve,
+      C: Connect,
+      P: Policy,
+{
+    /// Drive resolution and the subchannels' connections, and dispatch the
+    /// queued calls that can now be sent.
+    ///
+    /// Returns false until the resolver has produced its first update.
+    fn drive(&mut self) -> bool {
+        self.poll_resolve();
+
+        if !self.resolved {
+            return false;
+        }
+
+        for i in 0..self.subchannels.len() {
+            let connectivity = self.subchannels[i].poll();
+
+            if connectivity == Connectivity::Idle &&
+                self.policy.should_connect(&self.subchannels, i)
+            {
+                self.subchannels[i].connect(&mut self.connect);
+                self.subchannels[i].poll();
+            }
+        }
+
+        self.policy.refresh(&self.subchannels);
+
+        self.dispatch_queued();
+
+        true
+    }
+
+    /// Apply all pending resolver updates.
+    fn poll_resolve(&mut self) {
+        loop {
+            match self.resolver.poll() {
+                Ok(Async::Ready(update)) => self.update(update),
+                Ok(Async::NotReady) => return,
+                Err(e) => {
+                    // Keep using the last known endpoints.
+                    debug!("resolver error; err={:?}", e);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Replace the subchannels with those for `update`, keeping existing
+    /// connections to endpoints that are still present.
+    fn update(&mut self, update: Update) {
+        let (endpoints, config) = update.into_parts();
+        let mut old = mem::replace(&mut self.subchannels, Vec::with_capacity(endpoints.len()));
+
+        for endpoint in &endpoints {
+            let pos = old.iter()
+                .position(|sub| sub.endpoint().addr() == endpoint.addr());
+
+            let subchannel = match pos {
+                Some(i) => {
+                    let mut subchannel = old.swap_remove(i);
+                    subchannel.set_endpoint(endpoint.clone());
+                    subchannel
+                }
+                None => Subchannel::new(endpoint.clone(), self.timer.clone()),
+            };
+
+            self.subchannels.push(subchannel);
+        }
+
+        trace!("channel updated; endpoints={}; removed={}", endpoints.len(), old.len());
+
+        self.resolved = true;
+        self.policy.update(&endpoints);
+
+        if let Some(config) = config {
+            trace!("service config updated; config={:?}", config);
+            self.policy.configure(&config);
+            self.config.set(config);
+        }
+    }
+
+    /// Returns true if `request` should be queued rather than fail when no
+    /// subchannel is ready.
+    fn waits_for_ready(&self, request: &Request<C>) -> bool {
+        if let Some(&WaitForReady(wait)) = request.extensions().get::<WaitForReady>() {
+            return wait;
+        }
+
+        match self.config.get().method(request.uri().path()) {
+            Some(&MethodConfig { wait_for_ready: Some(wait), .. }) => wait,
+            _ => self.wait_for_ready,
+        }
+    }
+
+    /// Dispatch queued calls, in order, while subchannels are ready.
+    fn dispatch_queued(&mut self) {
+        while let Some(queued) = self.queued.pop_front() {
+            if queued.tx.is_canceled() {
+                continue;
+            }
+
+            let pick = match self.policy.pick(&self.subchannels, &queued.request) {
+                Some(i) if self.subchannels[i].is_ready() => i,
+                _ => {
+                    self.queued.push_front(queued);
+                    return;
+                }
+            };
+
+            trace!("dispatching queued call; queued={}", self.queued.len());
+
+            let stats = self.subchannels[pick].stats_handle();
+            let stream = self.stats.stream();
+            let fut = self.subchannels[pick].call(queued.request);
+            let _ = queued.tx.send((fut, stats, stream));
+
+            self.subchannels[pick].poll();
+        }
+    }
+
+    /// Apply the method's configured defaults to an outbound request.
+    ///
+    /// Returns the size of the largest response message the call may
+    /// receive.
+    fn apply_method_config(&self, request: &mut Request<C>) -> Option<usize> {
+        let config = self.config.get();
+        let method = config.method(request.uri().path())?;
+
+        // The client's codec reads the limit as it encodes each message.
+        if let Some(max) = method.max_request_message_bytes {
+            if let Some(limit) = request.extensions().get::<SendLimit>() {
+                limit.set(max);
+            }
+        }
+
+        if let Some(timeout) = method.timeout {
+            // A timeout set on the request itself is only ever shortened.
+            let current = request.headers()
+                .get(&*headers::GRPC_TIMEOUT)
+                .and_then(timeout::decode);
+
+            match current {
+                Some(current) if current <= timeout => {}
+                _ => {
+                    request.headers_mut()
+                        .insert(headers::GRPC_TIMEOUT.clone(), timeout::encode(timeout));
+                }
+            }
+        }
+
+        method.max_response_message_bytes
+    }
+
+    fn call(&mut self,
+            mut request: Request<C>,
+            channel: Weak<Mutex<Inner<R, C, P>>>) -> ResponseFuture<R, C, P>
+    {
+        let max_response_size = self.apply_method_config(&mut request);
+
+        let pick = match self.policy.pick(&self.subchannels, &request) {
+            Some(i) if self.subchannels[i].is_ready() => Some(i),
+            _ => None,
+        };
+
+        let mut future = match pick {
+            Some(i) => {
+                let stats = self.subchannels[i].stats_handle();
+                let stream = self.stats.stream();
+                ResponseFuture::new(self.subchannels[i].call(request), stats, stream)
+            }
+            None if self.waits_for_ready(&request) => {
+                trace!("no ready subchannel picked; queueing call");
+
+                let deadline = request.headers()
+                    .get(&*headers::GRPC_TIMEOUT)
+                    .and_then(timeout::decode)
+                    .map(|timeout| self.timer.sleep(timeout));
+
+                let (tx, rx) = oneshot::channel();
+                self.queued.push_back(Queued { request, tx });
+
+                ResponseFuture::waiting(Waiting { rx, deadline, channel })
+            }
+            None => {
+                debug!("no ready subchannel picked");
+                ResponseFuture::unavailable()
+            }
+        };
+
+        future.strictness = self.strictness;
+        future.max_response_size = max_response_size;
+        future
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<R, C, P> ResponseFuture<R, C, P>
+where C: Connect,
+{
+    fn new(inner: <C::Service as HttpService>::Future, stats: Arc<Stats>, stream: Stream) -> Self {
+        ResponseFuture {
+            inner: Some(inner),
+            stats: Some(stats),
+            stream: Some(stream),
+            waiting: None,
+            strictness: None,
+            max_response_size: None,
+        }
+    }
+
+    fn unavailable() -> Self {
+        ResponseFuture {
+            inner: None,
+            stats: None,
+            stream: None,
+            waiting: None,
+            strictness: None,
+            max_response_size: None,
+        }
+    }
+
+    fn waiting(waiting: Waiting<R, C, P>) -> Self {
+        ResponseFuture {
+            inner: None,
+            stats: None,
+            stream: None,
+            waiting: Some(waiting),
+            strictness: None,
+            max_response_size: None,
+        }
+    }
+
+    fn record(&mut self, success: bool) {
+        if let Some(stats) = self.stats.take() {
+            stats.record(success);
+        }
+    }
+}
+
+impl<R, C, P> ResponseFuture<R, C, P>
+where R: Resolve,
+      C: Connect,
+      P: Policy,
+{
+    /// Wait for a queued request to be dispatched.
+    fn poll_waiting(&mut self) -> Poll<(), Error<C>> {
+        let dispatched = match self.waiting {
+            Some(ref mut waiting) => {
+                if let Some(channel) = waiting.channel.upgrade() {
+                    lock(&channel).drive();
+                }
+
+                match waiting.rx.poll() {
+                    Ok(Async::Ready(dispatched)) => dispatched,
+                    Ok(Async::NotReady) => {
+                        if let Some(ref mut deadline) = waiting.deadline {
+                            if let Ok(Async::Ready(())) = deadline.poll() {
+                                debug!("deadline passed while waiting for a ready subchannel");
+                                return Err(::Error::Grpc(Status::DEADLINE_EXCEEDED));
+                            }
+                        }
+
+                        return Ok(Async::NotReady);
+                    }
+                    // The channel was dropped.
+                    Err(_) => return Err(::Error::Grpc(Status::UNAVAILABLE)),
+                }
+            }
+            None => return Ok(Async::Ready(())),
+        };
+
+        let (inner, stats, stream) = dispatched;
+        self.waiting = None;
+        self.inner = Some(inner);
+        self.stats = Some(stats);
+        self.stream = Some(stream);
+
+        Ok(Async::Ready(()))
+    }
+}
+
+impl<R, C, P> Future for ResponseFuture<R, C, P>
+where R: Resolve,
+      C: Connect,
+      P: Policy,
+{
+    type Item = Response<C>;
+    type Error = Error<C>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        try_ready!(self.poll_waiting());
+
+        let result = match self.inner {
+            Some(ref mut inner) => inner.poll(),
+            None => return Err(::Error::Grpc(Status::UNAVAILABLE)),
+        };
+
+        match result {
+            Ok(Async::Ready(response)) => {
+                // The status of a trailers-only response is in its head.
+                // Otherwise, the body records the outcome once the trailers
+                // are received.
+                let outcome = if response.status().is_success() {
+                    response.headers().get(&*headers::GRPC_STATUS)
+                        .map(|s| Status::from_bytes(s.as_ref()).code() == ::Code::OK)
+                } else {
+                    Some(false)
+                };
+
+                let stats = match outcome {
+                    Some(success) => {
+                        self.record(success);
+                        None
+                    }
+                    None => self.stats.take(),
+                };
+
+                let stream = self.stream.take().expect("polled after complete");
+                let (mut head, inner) = response.into_parts();
+                let body = ResponseBody { inner, stream, stats };
+
+                if let Some(strictness) = self.strictness {
+                    head.extensions.insert(strictness);
+                }
+
+                if let Some(max) = self.max_response_size {
+                    head.extensions.insert(ReceiveLimit(max));
+                }
+
+                Ok(Async::Ready(http::Response::from_parts(head, body)))
+            }
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(e) => {
+                self.record(false);
+                Err(::Error::Inner(e))
+            }
+        }
+    }
+}
+
+impl<R, C, P> fmt::Debug for ResponseFuture<R, C, P>
+where C: Connect,
+      <C::Service as HttpService>::Future: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("ResponseFuture")
+            .field("inner", &self.inner)
+            .field("waiting", &self.waiting.is_some())
+            .finish()
+    }
+}
+
+// ===== impl ResponseBody =====
+
+impl<B> ResponseBody<B> {
+    fn record(&mut self, success: bool) {
+        if let Some(stats) = self.stats.take() {
+            stats.record(success);
+        }
+    }
+}
+
+impl<B> Body for ResponseBody<B>
+where B: Body,
+{
+    type Data = B::Data;
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, h2::Error> {
+        self.inner.poll_data()
+            .map_err(|e| {
+                self.stream.end(false);
+                self.record(false);
+                e
+            })
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, h2::Error> {
+        match self.inner.poll_trailers() {
+            Ok(Async::Ready(trailers)) => {
+                let success = trailers.as_ref()
+                    .and_then(|t| t.get(&*headers::GRPC_STATUS))
+                    .map(|s| Status::from_bytes(s.as_ref()).code() == ::Code::OK)
+                    .unwrap_or(false);
+
+                self.stream.end(true);
+                self.record(success);
+                Ok(Async::Ready(trailers))
+            }
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(e) => {
+                self.stream.end(false);
+                self.record(false);
+                Err(e)
+            }
+        }
+    }
+}
+
+impl<B> fmt::Debug for ResponseBody<B>
+where B: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("ResponseBody")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+// ===== utility fns =====
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Returns `duration` in fractional seconds, for scaling backoffs.
+fn duration_secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1_000_000_000.0
+}
+
+/// Returns the duration of `secs` fractional seconds.
+fn secs_duration(secs: f64) -> Duration {
+    let whole = secs.trunc();
+    Duration::new(whole as u64, ((secs - whole) * 1_000_000_000.0) as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::balance::{OutlierDetection, PickFirst, RoundRobin};
+    use super::resolve::Fixed;
+    #[cfg(feature = "protobuf")]
+    use client::Builder;
+    use clock::MockClock;
+    #[cfg(feature = "protobuf")]
+    use Code;
+
+    use bytes::Bytes;
+    use futures::future::{self, FutureResult};
+    #[cfg(feature = "protobuf")]
+    use futures::Stream;
+    use http::header::HeaderValue;
+    #[cfg(feature = "protobuf")]
+    use http::uri::{PathAndQuery, Uri};
+    use tower_h2::BoxBody;
+
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    /// Connects to `MockService`s.
+    #[derive(Clone, Default)]
+    struct MockConnect {
+        shared: Arc<Mutex<Backends>>,
+    }
+
+    #[derive(Default)]
+    struct Backends {
+        /// Connection attempts do not complete while set.
+        pending: bool,
+
+        /// Addresses refusing connections, whose connections are lost.
+        down: Vec<SocketAddr>,
+
+        /// The address of every connection attempt, in order.
+        attempts: Vec<SocketAddr>,
+
+        /// The framed messages responses carry.
+        response: Option<Bytes>,
+
+        /// Addresses whose responses end with `UNAVAILABLE`.
+        failing: Vec<SocketAddr>,
+    }
+
+    struct Connecting {
+        addr: SocketAddr,
+        backends: Arc<Mutex<Backends>>,
+    }
+
+    /// Answers every request, with a status in the trailers.
+    struct MockService {
+        addr: SocketAddr,
+        backends: Arc<Mutex<Backends>>,
+    }
+
+    struct MockBody {
+        data: Option<Bytes>,
+        trailers: Option<HeaderMap>,
+    }
+
+    fn addr(port: u16) -> SocketAddr {
+        ([127, 0, 0, 1], port).into()
+    }
+
+    #[cfg(feature = "protobuf")]
+    #[derive(Clone, PartialEq, Message)]
+    struct Payload {
+        #[prost(bytes, tag = "1")]
+        data: Vec<u8>,
+    }
+
+    fn request() -> http::Request<BoxBody> {
+        let body = MockBody { data: None, trailers: None };
+
+        http::Request::builder()
+            .uri("/test.Service/Method")
+            .body(BoxBody::new(Box::new(body)))
+            .unwrap()
+    }
+
+    /// Read a response to its end.
+    fn drain<B: Body>(response: http::Response<B>) {
+        let mut body = response.into_body();
+
+        while let Async::Ready(Some(_)) = body.poll_data().unwrap() {}
+        body.poll_trailers().unwrap();
+    }
+
+    impl MockConnect {
+        fn backends(&self) -> MutexGuard<Backends> {
+            lock(&self.shared)
+        }
+    }
+
+    impl Connect for MockConnect {
+        type Service = MockService;
+        type Error = ();
+        type Future = Connecting;
+
+        fn connect(&mut self, endpoint: &Endpoint) -> Connecting {
+            self.backends().attempts.push(*endpoint.addr());
+
+            Connecting {
+                addr: *endpoint.addr(),
+                backends: self.shared.clone(),
+            }
+        }
+    }
+
+    impl Future for Connecting {
+        type Item = MockService;
+        type Error = ();
+
+        fn poll(&mut self) -> Poll<MockService, ()> {
+            let backends = lock(&self.backends);
+
+            if backends.pending {
+                return Ok(Async::NotReady);
+            }
+
+            if backends.down.contains(&self.addr) {
+                return Err(());
+            }
+
+            Ok(Async::Ready(MockService {
+                addr: self.addr,
+                backends: self.backends.clone(),
+            }))
+        }
+    }
+
+    impl Service for MockService {
+        type Request = http::Request<BoxBody>;
+        type Response = http::Response<MockBody>;
+        type Error = h2::Error;
+        type Future = FutureResult<Self::Response, h2::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), h2::Error> {
+            if lock(&self.backends).down.contains(&self.addr) {
+                return Err(h2::Reason::INTERNAL_ERROR.into());
+            }
+
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, _: http::Request<BoxBody>) -> Self::Future {
+            let backends = lock(&self.backends);
+            let code = if backends.failing.contains(&self.addr) { "14" } else { "0" };
+
+            let mut trailers = HeaderMap::new();
+            trailers.insert("grpc-status", HeaderValue::from_static(code));
+
+            future::ok(http::Response::new(MockBody {
+                data: backends.response.clone(),
+                trailers: Some(trailers),
+            }))
+        }
+    }
+
+    impl Body for MockBody {
+        type Data = Bytes;
+
+        fn is_end_stream(&self) -> bool {
+            self.data.is_none() && self.trailers.is_none()
+        }
+
+        fn poll_data(&mut self) -> Poll<Option<Bytes>, h2::Error> {
+            Ok(Async::Ready(self.data.take()))
+        }
+
+        fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, h2::Error> {
+            Ok(Async::Ready(self.trailers.take()))
+        }
+    }
+
+    #[test]
+    fn queued_call_dispatched_without_poll_ready() {
+        let connect = MockConnect::default();
+        connect.backends().pending = true;
+
+        let mut channel = Channel::new(Fixed::new(vec![addr(1)]), connect.clone(), PickFirst::new())
+            .wait_for_ready(true);
+
+        future::lazy(move || {
+            assert!(channel.poll_ready().unwrap().is_not_ready());
+
+            let mut response = channel.call(request());
+            assert!(response.poll().unwrap().is_not_ready());
+
+            // The connection completes, but nothing polls the channel.
+            connect.backends().pending = false;
+
+            match response.poll() {
+                Ok(Async::Ready(response)) => assert!(response.status().is_success()),
+                other => panic!("queued call not dispatched; ready={:?}", other.map(|a| a.is_ready())),
+            }
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn pick_first_fails_over_to_next_endpoint() {
+        let clock = MockClock::new();
+        let connect = MockConnect::default();
+        connect.backends().down.push(addr(1));
+
+        let resolver = Fixed::new(vec![addr(1), addr(2), addr(3)]);
+        let mut channel = Channel::new(resolver, connect.clone(), PickFirst::new())
+            .timer(&clock);
+
+        future::lazy(move || {
+            assert!(channel.poll_ready().unwrap().is_ready());
+            assert_eq!(connect.backends().attempts, vec![addr(1), addr(2)]);
+            drain(channel.call(request()).wait().unwrap());
+
+            // The connection in use is lost.
+            connect.backends().down.push(addr(2));
+
+            assert!(channel.poll_ready().unwrap().is_ready());
+            assert_eq!(channel.subchannels()[1].connectivity(), Connectivity::TransientFailure);
+            assert!(channel.subchannels()[2].is_ready());
+            assert_eq!(connect.backends().attempts, vec![addr(1), addr(2), addr(3)]);
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn call_fails_fast_when_every_endpoint_is_down() {
+        let connect = MockConnect::default();
+        connect.backends().down.push(addr(1));
+
+        let mut channel = Channel::new(Fixed::new(vec![addr(1)]), connect.clone(), PickFirst::new());
+
+        future::lazy(move || {
+            assert!(channel.poll_ready().unwrap().is_ready());
+
+            match channel.call(request()).poll() {
+                Err(::Error::Grpc(status)) => assert_eq!(status.code(), ::Code::UNAVAILABLE),
+                other => panic!("call not failed; ok={:?}", other.is_ok()),
+            }
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+
+        assert_eq!(connect.backends().attempts, vec![addr(1)]);
+    }
+
+    #[test]
+    #[cfg(feature = "protobuf")]
+    fn oversized_request_message_fails_with_resource_exhausted() {
+        let config = MethodConfig::default().max_request_message_bytes(4);
+        let channel = Channel::new(Fixed::new(vec![addr(1)]), MockConnect::default(), PickFirst::new())
+            .with_method_config("/test.Service/", config);
+
+        let mut grpc = Builder::new()
+            .uri(Uri::from_static("http://test.example"))
+            .build(channel)
+            .unwrap();
+
+        future::lazy(move || {
+            assert!(grpc.poll_ready().unwrap().is_ready());
+
+            let request = ::Request::new(Payload { data: vec![0; 16] });
+            let path = PathAndQuery::from_static("/test.Service/Method");
+
+            match grpc.unary::<Payload, Payload>(request, path).wait() {
+                Err(::Error::Grpc(status)) => assert_eq!(status.code(), Code::RESOURCE_EXHAUSTED),
+                other => panic!("oversized request message sent; ok={:?}", other.is_ok()),
+            }
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "protobuf")]
+    fn oversized_response_message_fails_with_resource_exhausted() {
+        // A frame holding a `Payload` with 16 bytes of data.
+        let mut message = vec![0, 0, 0, 0, 18, 0x0a, 16];
+        message.extend_from_slice(&[0; 16]);
+
+        let connect = MockConnect::default();
+        connect.backends().response = Some(message.into());
+
+        let config = MethodConfig::default().max_response_message_bytes(4);
+        let channel = Channel::new(Fixed::new(vec![addr(1)]), connect, PickFirst::new())
+            .with_method_config("/test.Service/", config);
+
+        let mut grpc = Builder::new()
+            .uri(Uri::from_static("http://test.example"))
+            .build(channel)
+            .unwrap();
+
+        future::lazy(move || {
+            assert!(grpc.poll_ready().unwrap().is_ready());
+
+            let request = ::Request::new(Payload { data: vec![] });
+            let path = PathAndQuery::from_static("/test.Service/Method");
+            let response = grpc.server_streaming::<Payload, Payload>(request, path)
+                .wait()
+                .unwrap();
+
+            match response.into_inner().poll() {
+                Err(::Error::Grpc(status)) => assert_eq!(status.code(), Code::RESOURCE_EXHAUSTED),
+                other => panic!("oversized response message received; ok={:?}", other.is_ok()),
+            }
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn failed_subchannel_reconnects_after_its_backoff() {
+        let clock = MockClock::new();
+        let connect = MockConnect::default();
+        connect.backends().down.push(addr(1));
+
+        let resolver = Fixed::new(vec![addr(1), addr(2)]);
+        let mut channel = Channel::new(resolver, connect.clone(), RoundRobin::new())
+            .timer(&clock);
+
+        future::lazy(move || {
+            assert!(channel.poll_ready().unwrap().is_ready());
+            assert_eq!(channel.subchannels()[0].connectivity(), Connectivity::TransientFailure);
+            assert_eq!(channel.subchannels()[1].connectivity(), Connectivity::Ready);
+
+            // The failed backend is not retried before its backoff elapses.
+            channel.poll_ready().unwrap();
+            assert_eq!(connect.backends().attempts, vec![addr(1), addr(2)]);
+
+            // The initial backoff is one second, with 20% jitter.
+            connect.backends().down.clear();
+            clock.advance(Duration::from_millis(1200));
+
+            assert!(channel.poll_ready().unwrap().is_ready());
+            assert_eq!(channel.subchannels()[0].connectivity(), Connectivity::Ready);
+
+            // The healthy backend kept its connection.
+            assert_eq!(connect.backends().attempts, vec![addr(1), addr(2), addr(1)]);
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn outlier_detection_ejects_endpoint_failing_in_trailers() {
+        let clock = MockClock::new();
+        let connect = MockConnect::default();
+        connect.backends().failing.push(addr(1));
+
+        let policy = OutlierDetection::new(RoundRobin::new())
+            .minimum_requests(1)
+            .max_ejection_percent(50);
+        let resolver = Fixed::new(vec![addr(1), addr(2)]);
+        let mut channel = Channel::new(resolver, connect, policy)
+            .timer(&clock);
+
+        future::lazy(move || {
+            for _ in 0..4 {
+                assert!(channel.poll_ready().unwrap().is_ready());
+                drain(channel.call(request()).wait().unwrap());
+            }
+
+            // Outcomes are only examined every interval.
+            channel.poll_ready().unwrap();
+            assert!(!channel.subchannels()[0].is_ejected());
+
+            clock.advance(Duration::from_secs(10));
+            channel.poll_ready().unwrap();
+            assert!(channel.subchannels()[0].is_ejected());
+            assert!(!channel.subchannels()[1].is_ejected());
+
+            // The first ejection lasts for the base ejection time.
+            clock.advance(Duration::from_secs(30));
+            channel.poll_ready().unwrap();
+            assert!(!channel.subchannels()[0].is_ejected());
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn pick_first_moves_off_ejected_endpoint() {
+        let clock = MockClock::new();
+        let connect = MockConnect::default();
+        connect.backends().failing.push(addr(1));
+
+        let policy = OutlierDetection::new(PickFirst::new())
+            .minimum_requests(1)
+            .max_ejection_percent(50);
+        let resolver = Fixed::new(vec![addr(1), addr(2)]);
+        let mut channel = Channel::new(resolver, connect.clone(), policy)
+            .timer(&clock);
+
+        future::lazy(move || {
+            for _ in 0..4 {
+                assert!(channel.poll_ready().unwrap().is_ready());
+                drain(channel.call(request()).wait().unwrap());
+            }
+            assert_eq!(connect.backends().attempts, vec![addr(1)]);
+
+            // The channel is woken when the next interval elapses.
+            assert_eq!(clock.next_sleep(), Some(Duration::from_secs(10)));
+            clock.advance(Duration::from_secs(10));
+
+            // The selected endpoint is ejected while connected, after the
+            // channel decided which endpoints to connect.
+            assert!(channel.poll_ready().unwrap().is_not_ready());
+            assert!(channel.subchannels()[0].is_ejected());
+            assert_eq!(channel.subchannels()[0].connectivity(), Connectivity::Ready);
+
+            // Polled again, the channel connects to the next endpoint.
+            assert!(channel.poll_ready().unwrap().is_ready());
+            assert!(channel.subchannels()[1].is_ready());
+            assert_eq!(connect.backends().attempts, vec![addr(1), addr(2)]);
+
+            // The channel is woken when the ejection expires.
+            clock.advance(Duration::from_secs(10));
+            channel.poll_ready().unwrap();
+            assert_eq!(clock.next_sleep(), Some(Duration::from_secs(10)));
+            clock.advance(Duration::from_secs(10));
+            channel.poll_ready().unwrap();
+            assert_eq!(clock.next_sleep(), Some(Duration::from_secs(10)));
+            clock.advance(Duration::from_secs(10));
+            channel.poll_ready().unwrap();
+            assert!(!channel.subchannels()[0].is_ejected());
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn outlier_detection_ejects_repeat_offender_for_longer() {
+        let clock = MockClock::new();
+        let connect = MockConnect::default();
+        connect.backends().failing.push(addr(1));
+
+        let policy = OutlierDetection::new(RoundRobin::new())
+            .minimum_requests(1)
+            .max_ejection_percent(50);
+        let resolver = Fixed::new(vec![addr(1), addr(2)]);
+        let mut channel = Channel::new(resolver, connect, policy)
+            .timer(&clock);
+
+        future::lazy(move || {
+            for _ in 0..2 {
+                for _ in 0..4 {
+                    assert!(channel.poll_ready().unwrap().is_ready());
+                    drain(channel.call(request()).wait().unwrap());
+                }
+
+                clock.advance(Duration::from_secs(10));
+                channel.poll_ready().unwrap();
+                assert!(channel.subchannels()[0].is_ejected());
+
+                clock.advance(Duration::from_secs(30));
+                channel.poll_ready().unwrap();
+            }
+
+            // Failing again right after being reinstated doubles the
+            // ejection time.
+            assert!(channel.subchannels()[0].is_ejected());
+
+            clock.advance(Duration::from_secs(30));
+            channel.poll_ready().unwrap();
+            assert!(!channel.subchannels()[0].is_ejected());
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+}
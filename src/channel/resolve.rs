@@ -0,0 +1,312 @@
+use super::config::ServiceConfig;
+
+use futures::{Async, Poll, Stream};
+use tokio_timer::{Interval, Timer, TimerError};
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// Resolves a target into the set of endpoints that serve it.
+///
+/// A resolver is polled by the `Channel` whenever it is driven. Each time the
+/// set of endpoints changes, the resolver should yield an `Update` containing
+/// the **complete** list of endpoints, in priority order.
+pub trait Resolve {
+    /// Error produced when resolution fails.
+    type Error: ::std::fmt::Debug;
+
+    /// Poll for a new set of endpoints.
+    ///
+    /// Returns `NotReady` when the endpoints are unchanged since the last
+    /// update.
+    fn poll(&mut self) -> Poll<Update, Self::Error>;
+}
+
+/// A set of endpoints produced by a resolver.
+#[derive(Debug, Clone)]
+pub struct Update {
+    endpoints: Vec<Endpoint>,
+    service_config: Option<ServiceConfig>,
+}
+
+/// An address that serves the channel's target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Endpoint {
+    addr: SocketAddr,
+    alternatives: Vec<SocketAddr>,
+    weight: u32,
+}
+
+/// Resolves to a fixed list of addresses.
+#[derive(Debug)]
+pub struct Fixed {
+    update: Option<Update>,
+}
+
+/// Re-resolves endpoints at a fixed interval.
+///
+/// Useful with discovery systems that don't push changes, such as refreshing
+/// weights from DNS SRV records. Because the channel keeps the subchannels of
+/// endpoints that are still present, refreshing does not drop connections.
+pub struct Periodic<F> {
+    resolve: F,
+    interval: Interval,
+    first: bool,
+}
+
+// ===== impl Update =====
+
+impl Update {
+    /// Create a new update from the full list of endpoints.
+    pub fn new(endpoints: Vec<Endpoint>) -> Self {
+        Update {
+            endpoints,
+            service_config: None,
+        }
+    }
+
+    /// Attach the target's service config to the update.
+    ///
+    /// An update without a service config leaves the channel's current
+    /// config in place.
+    pub fn with_service_config(mut self, config: ServiceConfig) -> Self {
+        self.service_config = Some(config);
+        self
+    }
+
+    /// Returns the endpoints, in the order provided by the resolver.
+    pub fn endpoints(&self) -> &[Endpoint] {
+        &self.endpoints
+    }
+
+    /// Returns the service config, if the resolver provided one.
+    pub fn service_config(&self) -> Option<&ServiceConfig> {
+        self.service_config.as_ref()
+    }
+
+    pub(crate) fn into_parts(self) -> (Vec<Endpoint>, Option<ServiceConfig>) {
+        (self.endpoints, self.service_config)
+    }
+}
+
+// ===== impl Endpoint =====
+
+impl Endpoint {
+    /// Create a new endpoint for the given address.
+    pub fn new(addr: SocketAddr) -> Self {
+        Endpoint {
+            addr,
+            alternatives: vec![],
+            weight: 1,
+        }
+    }
+
+    /// Set the endpoint's relative weight.
+    ///
+    /// Weighted policies send traffic to endpoints in proportion to their
+    /// weight. Endpoints have a weight of 1 by default.
+    pub fn with_weight(mut self, weight: u32) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    /// Set other addresses of the same server, such as its IPv4 address
+    /// when the endpoint's address is IPv6.
+    ///
+    /// TCP connectors dial them with staggered attempts when the endpoint's
+    /// address is slow to connect or fails (happy eyeballs), and use the
+    /// first connection to succeed.
+    pub fn with_alternatives(mut self, addrs: Vec<SocketAddr>) -> Self {
+        self.alternatives = addrs;
+        self
+    }
+
+    /// Returns the endpoint's socket address.
+    pub fn addr(&self) -> &SocketAddr {
+        &self.addr
+    }
+
+    /// Returns the endpoint's other addresses.
+    pub fn alternatives(&self) -> &[SocketAddr] {
+        &self.alternatives
+    }
+
+    /// Returns the endpoint's relative weight.
+    pub fn weight(&self) -> u32 {
+        self.weight
+    }
+}
+
+impl From<SocketAddr> for Endpoint {
+    fn from(addr: SocketAddr) -> Self {
+        Endpoint::new(addr)
+    }
+}
+
+// ===== impl Fixed =====
+
+impl Fixed {
+    /// Returns a resolver that always yields `addrs`.
+    pub fn new<I>(addrs: I) -> Self
+    where I: IntoIterator<Item = SocketAddr>,
+    {
+        let endpoints = addrs.into_iter()
+            .map(Endpoint::new)
+            .collect();
+
+        Fixed {
+            update: Some(Update::new(endpoints)),
+        }
+    }
+}
+
+impl Resolve for Fixed {
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Update, ()> {
+        match self.update.take() {
+            Some(update) => Ok(Async::Ready(update)),
+            None => Ok(Async::NotReady),
+        }
+    }
+}
+
+// ===== impl Periodic =====
+
+impl<F, E> Periodic<F>
+where F: FnMut() -> Result<Vec<Endpoint>, E>,
+{
+    /// Returns a resolver that calls `resolve` immediately and then once
+    /// every `period`.
+    pub fn new(resolve: F, period: Duration) -> Self {
+        Periodic::with_timer(resolve, period, &Timer::default())
+    }
+
+    /// Like `new`, but uses the provided timer.
+    pub fn with_timer(resolve: F, period: Duration, timer: &Timer) -> Self {
+        Periodic {
+            resolve,
+            interval: timer.interval(period),
+            first: true,
+        }
+    }
+}
+
+impl<F, E> Resolve for Periodic<F>
+where F: FnMut() -> Result<Vec<Endpoint>, E>,
+      E: ::std::fmt::Debug,
+{
+    type Error = PeriodicError<E>;
+
+    fn poll(&mut self) -> Poll<Update, Self::Error> {
+        if self.first {
+            self.first = false;
+        } else {
+            match self.interval.poll() {
+                Ok(Async::Ready(Some(()))) => {}
+                Ok(Async::Ready(None)) | Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(e) => return Err(PeriodicError::Timer(e)),
+            }
+        }
+
+        let endpoints = (self.resolve)()
+            .map_err(PeriodicError::Resolve)?;
+
+        Ok(Async::Ready(Update::new(endpoints)))
+    }
+}
+
+impl<F> ::std::fmt::Debug for Periodic<F> {
+    fn fmt(&self, fmt: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        fmt.debug_struct("Periodic")
+            .field("first", &self.first)
+            .finish()
+    }
+}
+
+/// Error produced by a `Periodic` resolver.
+#[derive(Debug)]
+pub enum PeriodicError<E> {
+    /// The resolve function failed.
+    Resolve(E),
+
+    /// The interval timer failed.
+    Timer(TimerError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::Future;
+    use futures::future;
+
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    fn addr(port: u16) -> SocketAddr {
+        ([127, 0, 0, 1], port).into()
+    }
+
+    #[test]
+    fn fixed_yields_its_endpoints_once() {
+        let mut resolver = Fixed::new(vec![addr(1), addr(2)]);
+
+        match resolver.poll() {
+            Ok(Async::Ready(update)) => {
+                let addrs: Vec<_> = update.endpoints().iter()
+                    .map(|endpoint| *endpoint.addr())
+                    .collect();
+                assert_eq!(addrs, vec![addr(1), addr(2)]);
+                assert!(update.service_config().is_none());
+            }
+            other => panic!("no update; ok={:?}", other.is_ok()),
+        }
+
+        assert!(resolver.poll().unwrap().is_not_ready());
+    }
+
+    #[test]
+    fn endpoints_default_to_unit_weight() {
+        let endpoint = Endpoint::new(addr(1));
+        assert_eq!(endpoint.weight(), 1);
+        assert!(endpoint.alternatives().is_empty());
+
+        assert_eq!(endpoint.with_weight(5).weight(), 5);
+    }
+
+    #[test]
+    fn periodic_resolves_immediately_then_each_period() {
+        let calls = Rc::new(Cell::new(0));
+        let counted = calls.clone();
+
+        let mut resolver = Periodic::new(move || {
+            counted.set(counted.get() + 1);
+            Ok::<_, ()>(vec![Endpoint::new(addr(1)).with_weight(2)])
+        }, Duration::from_secs(3600));
+
+        future::lazy(move || {
+            match resolver.poll() {
+                Ok(Async::Ready(update)) => assert_eq!(update.endpoints()[0].weight(), 2),
+                other => panic!("no update; ok={:?}", other.is_ok()),
+            }
+
+            // The next resolution waits for the period to elapse.
+            assert!(resolver.poll().unwrap().is_not_ready());
+            assert_eq!(calls.get(), 1);
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn periodic_resolve_errors_returned() {
+        let mut resolver = Periodic::new(|| Err::<Vec<Endpoint>, _>("unreachable"),
+                                         Duration::from_secs(3600));
+
+        match resolver.poll() {
+            Err(PeriodicError::Resolve(e)) => assert_eq!(e, "unreachable"),
+            other => panic!("error not returned; ok={:?}", other.is_ok()),
+        }
+    }
+}
@@ -0,0 +1,518 @@
+//! Hedged calls.
+//!
+//! A hedged call sends a new attempt every `hedgingDelay` until one of them
+//! produces a final result, up to `maxAttempts` in total. Outstanding
+//! attempts are canceled, by dropping them, as soon as a result is chosen.
+
+use super::{pushback, trailers_only_code, Head, Pushback, Recording, ReplayBody, Throttle};
+use channel::config::HedgingPolicy;
+use clock::{Clock, Sleep};
+use Code;
+
+use bytes::Bytes;
+use futures::{Future, Poll, Async};
+use http;
+use tower::Service;
+use tower_h2::{Body, HttpService};
+
+use std::fmt;
+
+/// Drives the attempts of a hedged call.
+pub(super) struct Hedging<S, B>
+where S: HttpService,
+{
+    service: S,
+    head: Head,
+    body: Recording<B>,
+    policy: HedgingPolicy,
+    timer: Clock,
+    throttle: Throttle,
+
+    /// The number of attempts sent so far.
+    attempts: u32,
+
+    /// Attempts awaiting a response.
+    pending: Vec<S::Future>,
+
+    /// Time until the next attempt is sent. If `None`, it is sent as soon as
+    /// the service is ready.
+    delay: Option<Sleep>,
+
+    /// Set when the server asked for no further attempts.
+    stopped: bool,
+
+    /// The result of the last attempt that failed with a non-fatal status.
+    last: Option<Result<http::Response<S::ResponseBody>, S::Error>>,
+}
+
+// ===== impl Hedging =====
+
+impl<S, B, E> Hedging<S, B>
+where S: HttpService<RequestBody = ReplayBody<B>, Error = ::Error<E>>,
+      B: Body<Data = Bytes>,
+{
+    pub(super) fn new(
+        service: S,
+        head: Head,
+        body: Recording<B>,
+        first: S::Future,
+        policy: HedgingPolicy,
+        timer: Clock,
+        throttle: Throttle,
+    ) -> Self {
+        let delay = timer.sleep(policy.hedging_delay);
+
+        Hedging {
+            service,
+            head,
+            body,
+            policy,
+            timer,
+            throttle,
+            attempts: 1,
+            pending: vec![first],
+            delay: Some(delay),
+            stopped: false,
+            last: None,
+        }
+    }
+
+    /// Returns true if another attempt may be sent.
+    fn can_hedge(&self) -> bool {
+        !self.stopped &&
+            !self.head.is_expired() &&
+            self.attempts < self.policy.max_attempts &&
+            self.body.is_replayable()
+    }
+
+    /// Send attempts that are due.
+    fn poll_hedge(&mut self) -> Result<(), S::Error> {
+        while self.can_hedge() {
+            if let Some(ref mut delay) = self.delay {
+                if let Ok(Async::NotReady) = delay.poll() {
+                    return Ok(());
+                }
+            }
+
+            if self.service.poll_ready()?.is_not_ready() {
+                return Ok(());
+            }
+
+            if !self.throttle.allows_retry() {
+                debug!("hedging throttled; not sending more attempts");
+                self.stopped = true;
+                return Ok(());
+            }
+
+            self.attempts += 1;
+            debug!("sending hedged attempt; attempt={}", self.attempts);
+
+            let request = self.head.request(self.body.replay());
+            let fut = self.service.call(request);
+            self.pending.push(fut);
+
+            self.delay = Some(self.timer.sleep(self.policy.hedging_delay));
+        }
+
+        Ok(())
+    }
+
+    /// Handle the result of an attempt, returning it if it ends the call.
+    fn complete(&mut self, result: Result<http::Response<S::ResponseBody>, S::Error>)
+        -> Option<Result<http::Response<S::ResponseBody>, S::Error>>
+    {
+        let (code, pushback) = match result {
+            Ok(ref response) => (trailers_only_code(response), pushback(response)),
+            Err(::Error::Grpc(ref status)) => (Some(status.code()), None),
+            // Transport errors are treated as UNAVAILABLE.
+            Err(_) => (Some(Code::UNAVAILABLE), None),
+        };
+
+        let code = match code {
+            Some(Code::OK) => {
+                self.throttle.success();
+                return Some(result);
+            }
+            Some(code) => code,
+            None => return Some(result),
+        };
+
+        if !self.policy.is_non_fatal(code) {
+            trace!("hedged attempt failed with fatal status; code={:?}", code);
+            return Some(result);
+        }
+
+        debug!("hedged attempt failed; code={:?}; pending={}", code, self.pending.len());
+        self.throttle.failure();
+
+        // A non-fatal failure sends the next attempt right away, unless the
+        // server asked for a different delay.
+        match pushback {
+            Some(Pushback::Stop) => self.stopped = true,
+            Some(Pushback::Delay(delay)) => self.delay = Some(self.timer.sleep(delay)),
+            None => self.delay = None,
+        }
+
+        self.last = Some(result);
+        None
+    }
+}
+
+impl<S, B, E> Future for Hedging<S, B>
+where S: HttpService<RequestBody = ReplayBody<B>, Error = ::Error<E>>,
+      B: Body<Data = Bytes>,
+{
+    type Item = http::Response<S::ResponseBody>;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            self.poll_hedge()?;
+
+            let mut failed = false;
+            let mut i = 0;
+
+            while i < self.pending.len() {
+                let result = match self.pending[i].poll() {
+                    Ok(Async::NotReady) => {
+                        i += 1;
+                        continue;
+                    }
+                    Ok(Async::Ready(response)) => Ok(response),
+                    Err(e) => Err(e),
+                };
+
+                self.pending.swap_remove(i);
+
+                match self.complete(result) {
+                    Some(result) => return result.map(Async::Ready),
+                    None => failed = true,
+                }
+            }
+
+            if self.pending.is_empty() && !self.can_hedge() {
+                let last = self.last.take().expect("hedged call without a result");
+                return last.map(Async::Ready);
+            }
+
+            // A failure may have made the next attempt due.
+            if !failed {
+                return Ok(Async::NotReady);
+            }
+        }
+    }
+}
+
+impl<S, B> fmt::Debug for Hedging<S, B>
+where S: HttpService,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Hedging")
+            .field("attempts", &self.attempts)
+            .field("pending", &self.pending.len())
+            .field("stopped", &self.stopped)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Retry;
+    use channel::config::{MethodConfig, RetryThrottling, ServiceConfig, SharedConfig};
+    use clock::MockClock;
+
+    use futures::future;
+    use futures::sync::oneshot;
+    use http::HeaderMap;
+
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    /// Answers each attempt with the response the test sends it.
+    #[derive(Clone, Default)]
+    struct MockService {
+        attempts: Arc<Mutex<Vec<Option<oneshot::Sender<http::Response<MockBody>>>>>>,
+    }
+
+    /// An attempt; dropping its sender fails it with a transport error.
+    struct Attempt(oneshot::Receiver<http::Response<MockBody>>);
+
+    /// A body with optional trailers.
+    struct MockBody(Option<HeaderMap>);
+
+    fn hedged(policy: HedgingPolicy, clock: &MockClock) -> (MockService, Retry<MockService>) {
+        let config = ServiceConfig::default()
+            .with_method("/test.Service/", MethodConfig::default().hedging(policy));
+
+        let service = MockService::default();
+        let retry = Retry::new(service.clone(), SharedConfig::new(config))
+            .timer(clock);
+
+        (service, retry)
+    }
+
+    fn request() -> http::Request<MockBody> {
+        http::Request::builder()
+            .uri("/test.Service/Method")
+            .body(MockBody(None))
+            .unwrap()
+    }
+
+    /// Returns a trailers-only response with `code`, or a response whose
+    /// status is in its trailers.
+    fn response(code: Option<Code>) -> http::Response<MockBody> {
+        let mut response = http::Response::new(MockBody(None));
+
+        if let Some(code) = code {
+            let status = ::Status::with_code(code).to_header_value();
+            response.headers_mut().insert("grpc-status", status);
+        }
+
+        response
+    }
+
+    /// Returns a response whose status is in its trailers.
+    fn trailers(code: Code) -> http::Response<MockBody> {
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", ::Status::with_code(code).to_header_value());
+
+        http::Response::new(MockBody(Some(trailers)))
+    }
+
+    impl MockService {
+        fn attempts(&self) -> usize {
+            self.attempts.lock().unwrap().len()
+        }
+
+        fn respond(&self, attempt: usize, response: http::Response<MockBody>) {
+            let tx = self.attempts.lock().unwrap()[attempt].take()
+                .expect("attempt already answered");
+            let _ = tx.send(response);
+        }
+    }
+
+    impl Service for MockService {
+        type Request = http::Request<ReplayBody<MockBody>>;
+        type Response = http::Response<MockBody>;
+        type Error = ::Error<()>;
+        type Future = Attempt;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, _: Self::Request) -> Self::Future {
+            let (tx, rx) = oneshot::channel();
+            self.attempts.lock().unwrap().push(Some(tx));
+            Attempt(rx)
+        }
+    }
+
+    impl Future for Attempt {
+        type Item = http::Response<MockBody>;
+        type Error = ::Error<()>;
+
+        fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+            self.0.poll().map_err(|_| ::Error::Inner(()))
+        }
+    }
+
+    impl Body for MockBody {
+        type Data = Bytes;
+
+        fn is_end_stream(&self) -> bool {
+            self.0.is_none()
+        }
+
+        fn poll_data(&mut self) -> Poll<Option<Bytes>, ::h2::Error> {
+            Ok(Async::Ready(None))
+        }
+
+        fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, ::h2::Error> {
+            Ok(Async::Ready(self.0.take()))
+        }
+    }
+
+    #[test]
+    fn attempt_sent_every_hedging_delay() {
+        let clock = MockClock::new();
+        let policy = HedgingPolicy::new(3, Duration::from_millis(10));
+        let (service, mut retry) = hedged(policy, &clock);
+
+        future::lazy(move || {
+            let mut call = retry.call(request());
+            assert!(call.poll().unwrap().is_not_ready());
+            assert_eq!(service.attempts(), 1);
+
+            clock.advance(Duration::from_millis(10));
+            assert!(call.poll().unwrap().is_not_ready());
+            assert_eq!(service.attempts(), 2);
+
+            clock.advance(Duration::from_millis(10));
+            assert!(call.poll().unwrap().is_not_ready());
+            assert_eq!(service.attempts(), 3);
+
+            // No more than `max_attempts` are sent.
+            clock.advance(Duration::from_millis(10));
+            assert!(call.poll().unwrap().is_not_ready());
+            assert_eq!(service.attempts(), 3);
+
+            // The first result ends the call.
+            service.respond(1, response(None));
+            assert!(call.poll().unwrap().is_ready());
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn non_fatal_failure_sends_next_attempt_at_once() {
+        let clock = MockClock::new();
+        let policy = HedgingPolicy::new(3, Duration::from_secs(1))
+            .non_fatal_status_codes(vec![Code::UNAVAILABLE]);
+        let (service, mut retry) = hedged(policy, &clock);
+
+        future::lazy(move || {
+            let mut call = retry.call(request());
+            assert!(call.poll().unwrap().is_not_ready());
+
+            service.respond(0, response(Some(Code::UNAVAILABLE)));
+            assert!(call.poll().unwrap().is_not_ready());
+            assert_eq!(service.attempts(), 2);
+
+            // A fatal status ends the call.
+            service.respond(1, response(Some(Code::INVALID_ARGUMENT)));
+            match call.poll().unwrap() {
+                Async::Ready(response) => {
+                    assert_eq!(trailers_only_code(&response), Some(Code::INVALID_ARGUMENT));
+                }
+                Async::NotReady => panic!("fatal status did not end the call"),
+            }
+            assert_eq!(service.attempts(), 2);
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn last_failure_returned_once_attempts_are_exhausted() {
+        let clock = MockClock::new();
+        let policy = HedgingPolicy::new(2, Duration::from_secs(1))
+            .non_fatal_status_codes(vec![Code::UNAVAILABLE]);
+        let (service, mut retry) = hedged(policy, &clock);
+
+        future::lazy(move || {
+            let mut call = retry.call(request());
+            assert!(call.poll().unwrap().is_not_ready());
+
+            service.respond(0, response(Some(Code::UNAVAILABLE)));
+            assert!(call.poll().unwrap().is_not_ready());
+
+            // Transport errors count as UNAVAILABLE.
+            drop(service.attempts.lock().unwrap()[1].take());
+            match call.poll() {
+                Err(::Error::Inner(())) => {}
+                _ => panic!("last failure not returned"),
+            }
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn pushback_delays_next_attempt() {
+        let clock = MockClock::new();
+        let policy = HedgingPolicy::new(3, Duration::from_secs(1))
+            .non_fatal_status_codes(vec![Code::UNAVAILABLE]);
+        let (service, mut retry) = hedged(policy, &clock);
+
+        future::lazy(move || {
+            let mut call = retry.call(request());
+            assert!(call.poll().unwrap().is_not_ready());
+
+            let mut failed = response(Some(Code::UNAVAILABLE));
+            failed.headers_mut().insert("grpc-retry-pushback-ms", "50".parse().unwrap());
+            service.respond(0, failed);
+
+            assert!(call.poll().unwrap().is_not_ready());
+            assert_eq!(service.attempts(), 1);
+
+            clock.advance(Duration::from_millis(50));
+            assert!(call.poll().unwrap().is_not_ready());
+            assert_eq!(service.attempts(), 2);
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn negative_pushback_stops_hedging() {
+        let clock = MockClock::new();
+        let policy = HedgingPolicy::new(3, Duration::from_secs(1))
+            .non_fatal_status_codes(vec![Code::UNAVAILABLE]);
+        let (service, mut retry) = hedged(policy, &clock);
+
+        future::lazy(move || {
+            let mut call = retry.call(request());
+            assert!(call.poll().unwrap().is_not_ready());
+
+            let mut failed = response(Some(Code::UNAVAILABLE));
+            failed.headers_mut().insert("grpc-retry-pushback-ms", "-1".parse().unwrap());
+            service.respond(0, failed);
+
+            match call.poll().unwrap() {
+                Async::Ready(response) => {
+                    assert_eq!(trailers_only_code(&response), Some(Code::UNAVAILABLE));
+                }
+                Async::NotReady => panic!("pushback did not stop hedging"),
+            }
+            assert_eq!(service.attempts(), 1);
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn ok_trailers_refill_the_throttle() {
+        let clock = MockClock::new();
+        let policy = HedgingPolicy::new(3, Duration::from_secs(1));
+
+        let mut config = ServiceConfig::default()
+            .with_method("/test.Service/", MethodConfig::default().hedging(policy));
+        config.retry_throttling = Some(RetryThrottling {
+            max_tokens: 10,
+            token_ratio: 1.0,
+        });
+
+        let service = MockService::default();
+        let mut retry = Retry::new(service.clone(), SharedConfig::new(config))
+            .timer(&clock);
+
+        future::lazy(move || {
+            let mut call = retry.call(request());
+            assert!(call.poll().unwrap().is_not_ready());
+
+            // Hedging stops once the bucket is down to half its tokens.
+            for _ in 0..5 {
+                retry.throttle.failure();
+            }
+            assert!(!retry.throttle.allows_retry());
+
+            service.respond(0, trailers(Code::OK));
+            let response = match call.poll().unwrap() {
+                Async::Ready(response) => response,
+                Async::NotReady => panic!("response did not end the call"),
+            };
+
+            // The call is credited by its trailers, not its response head.
+            assert!(!retry.throttle.allows_retry());
+
+            let mut body = response.into_body();
+            body.poll_trailers().unwrap();
+            assert!(retry.throttle.allows_retry());
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+}
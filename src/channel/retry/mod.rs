@@ -0,0 +1,1332 @@
+//! Automatic retries.
+//!
+//! `Retry` retries failed calls according to the `retryPolicy` of the
+//! method's service config, as described in the [gRPC retry design][spec].
+//! Methods with a `hedgingPolicy` are hedged instead.
+//!
+//! Request bodies are buffered as they are sent so they can be replayed on a
+//! new attempt. If a request exceeds the buffer limit, it is sent as usual
+//! but is no longer retried.
+//!
+//! If the service config sets `retryThrottling`, retries stop while a large
+//! share of recent calls have failed, so that retries do not add load to a
+//! struggling backend.
+//!
+//! Servers may override the backoff of a trailers-only response with the
+//! `grpc-retry-pushback-ms` header. A negative or malformed value stops the
+//! call from being retried.
+//!
+//! Attempts share the call's deadline, taken from its `grpc-timeout` header
+//! or the method's configured timeout. Each attempt is sent with the time
+//! remaining, and no attempt is made that would start after the deadline.
+//!
+//! Independently of any policy, a call that fails with a transport error
+//! before any of its request body was sent is retried once, as it cannot
+//! have been processed by the server.
+//!
+//! Methods without a retry or hedging policy that are marked as having no
+//! side effects, with the `Idempotency` request extension set by generated
+//! clients, are retried on `UNAVAILABLE` using a conservative default
+//! policy. Other methods are never retried without a policy.
+//!
+//! A call is committed, and never retried, once response headers that are
+//! not a trailers-only error have been received.
+//!
+//! Each retry is dispatched on a clone of the inner service, so the channel
+//! should be wrapped in a shareable service (such as a buffer) first.
+//!
+//! [spec]: https://github.com/grpc/proposal/blob/master/A6-client-retries.md
+
+mod hedge;
+
+use self::hedge::Hedging;
+use super::config::{RetryPolicy, RetryThrottling, SharedConfig};
+use super::{duration_secs, secs_duration, WaitForReady};
+use client::Idempotency;
+use clock::{Clock, Sleep};
+use limit::SendLimit;
+use {Code, Status};
+use headers;
+use timeout;
+
+use bytes::Bytes;
+use futures::{Future, Poll, Async};
+use h2;
+use http::{self, HeaderMap, Method, Uri, Version};
+use rand;
+use tower::Service;
+use tower_h2::{Body, HttpService};
+
+use std::{cmp, fmt};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// The default number of request body bytes buffered for replay.
+const DEFAULT_BUFFER_LIMIT: usize = 256 * 1024;
+
+/// The default number of attempts for methods without side effects.
+const DEFAULT_SAFE_ATTEMPTS: u32 = 3;
+
+/// Retries failed calls according to the service config.
+pub struct Retry<S> {
+    inner: S,
+    config: SharedConfig,
+    timer: Clock,
+    buffer_limit: usize,
+    throttle: Throttle,
+
+    /// Used for methods without side effects that have no policy.
+    safe_policy: Option<RetryPolicy>,
+}
+
+/// The response future returned by `Retry`.
+pub struct ResponseFuture<S, B>
+where S: HttpService,
+{
+    state: State<S, B>,
+    retry: Option<Retrying<S, B>>,
+    throttle: Throttle,
+}
+
+/// The response body returned by `Retry`.
+///
+/// Credits the retry throttle once trailers with an `OK` status arrive.
+pub struct ResponseBody<B> {
+    inner: B,
+    throttle: Option<Throttle>,
+}
+
+enum State<S, B>
+where S: HttpService,
+{
+    /// Waiting for the response to an attempt.
+    Calling(S::Future),
+
+    /// Waiting for the result of a hedged call.
+    Hedging(Hedging<S, B>),
+
+    /// Waiting before the next attempt.
+    Backoff(Sleep),
+
+    /// Waiting for the service to accept the next attempt.
+    Ready,
+}
+
+struct Retrying<S, B> {
+    service: S,
+    head: Head,
+    body: Recording<B>,
+    policy: Option<RetryPolicy>,
+    timer: Clock,
+    throttle: Throttle,
+    attempts: u32,
+    backoff: Duration,
+
+    /// Set once the call has been retried transparently.
+    transparent: bool,
+}
+
+/// The retry token bucket shared by all calls through a `Retry`.
+#[derive(Debug, Clone, Default)]
+struct Throttle {
+    bucket: Arc<Mutex<Option<Bucket>>>,
+}
+
+/// Token counts are kept in thousandths of a token, as the spec allows token
+/// ratios with up to three decimal places.
+#[derive(Debug)]
+struct Bucket {
+    config: RetryThrottling,
+    tokens: u64,
+    max: u64,
+    ratio: u64,
+}
+
+/// Server pushback, from the `grpc-retry-pushback-ms` header.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Pushback {
+    /// Retry after exactly this delay.
+    Delay(Duration),
+
+    /// Do not retry.
+    Stop,
+}
+
+/// The parts of a request needed to send it again.
+#[derive(Debug, Clone)]
+struct Head {
+    method: Method,
+    uri: Uri,
+    version: Version,
+    headers: HeaderMap,
+
+    /// The request extensions read below this layer. `Extensions` cannot
+    /// be cloned as a whole, so only these are carried over to each
+    /// attempt.
+    send_limit: Option<SendLimit>,
+    wait_for_ready: Option<WaitForReady>,
+    idempotency: Option<Idempotency>,
+
+    /// When the call as a whole times out.
+    deadline: Option<Instant>,
+
+    /// Measures the time remaining until the deadline.
+    clock: Clock,
+}
+
+/// A request body that can be replayed.
+pub struct ReplayBody<B> {
+    inner: Inner<B>,
+}
+
+/// The recorded body of a call, from which the body of each attempt is
+/// made.
+struct Recording<B> {
+    buffer: Arc<Mutex<Buffer<B>>>,
+}
+
+enum Inner<B> {
+    /// The body is sent as-is.
+    Direct(B),
+
+    /// The body is recorded as it is sent.
+    Shared {
+        buffer: Arc<Mutex<Buffer<B>>>,
+        pos: usize,
+    },
+}
+
+struct Buffer<B> {
+    source: B,
+    chunks: Vec<Bytes>,
+    read: usize,
+    len: usize,
+    limit: usize,
+    overflowed: bool,
+    source_done: bool,
+    trailers: Option<Option<HeaderMap>>,
+}
+
+// ===== impl Retry =====
+
+impl<S> Retry<S> {
+    /// Retry calls dispatched on `inner`, using the retry policies of
+    /// `config`.
+    pub fn new(inner: S, config: SharedConfig) -> Self {
+        Retry {
+            inner,
+            config,
+            timer: Clock::default(),
+            buffer_limit: DEFAULT_BUFFER_LIMIT,
+            throttle: Throttle::default(),
+            safe_policy: Some(RetryPolicy::new(DEFAULT_SAFE_ATTEMPTS, vec![Code::UNAVAILABLE])),
+        }
+    }
+
+    /// Set the number of request body bytes buffered per call.
+    ///
+    /// Calls with larger requests are not retried.
+    pub fn buffer_limit(mut self, limit: usize) -> Self {
+        self.buffer_limit = limit;
+        self
+    }
+
+    /// Set the policy used to retry methods without side effects that have
+    /// no configured retry or hedging policy.
+    ///
+    /// By default, such calls are retried up to 3 attempts on `UNAVAILABLE`.
+    /// `None` disables these retries.
+    pub fn safe_retry_policy(mut self, policy: Option<RetryPolicy>) -> Self {
+        self.safe_policy = policy;
+        self
+    }
+
+    /// Use the provided timer, or a `MockClock`, to schedule backoffs and
+    /// measure deadlines.
+    pub fn timer<T: Into<Clock>>(mut self, timer: T) -> Self {
+        self.timer = timer.into();
+        self
+    }
+}
+
+impl<S, B, E> Service for Retry<S>
+where S: HttpService<RequestBody = ReplayBody<B>, Error = ::Error<E>> + Clone,
+      B: Body<Data = Bytes>,
+{
+    type Request = http::Request<B>;
+    type Response = http::Response<ResponseBody<S::ResponseBody>>;
+    type Error = ::Error<E>;
+    type Future = ResponseFuture<S, B>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, request: Self::Request) -> Self::Future {
+        let config = self.config.get();
+        self.throttle.configure(config.retry_throttling.as_ref());
+
+        let method = config.method(request.uri().path());
+        let mut retry_policy = method.and_then(|m| m.retry_policy.clone());
+        let hedging_policy = method.and_then(|m| m.hedging_policy.clone());
+
+        let idempotency = request.extensions()
+            .get::<Idempotency>()
+            .cloned()
+            .unwrap_or_default();
+
+        // Calls of unknown idempotency may be mutations, so they are only
+        // retried if the operator asked for it.
+        if retry_policy.is_none() && hedging_policy.is_none() && idempotency == Idempotency::NoSideEffects {
+            retry_policy = self.safe_policy.clone();
+        }
+        let method_timeout = method.and_then(|m| m.timeout);
+
+        let (parts, body) = request.into_parts();
+
+        let request_timeout = parts.headers
+            .get(&*headers::GRPC_TIMEOUT)
+            .and_then(timeout::decode);
+
+        let timeout = match (request_timeout, method_timeout) {
+            (Some(a), Some(b)) => Some(cmp::min(a, b)),
+            (a, b) => a.or(b),
+        };
+
+        let head = Head {
+            method: parts.method,
+            uri: parts.uri,
+            version: parts.version,
+            headers: parts.headers,
+            send_limit: parts.extensions.get::<SendLimit>().cloned(),
+            wait_for_ready: parts.extensions.get::<WaitForReady>().cloned(),
+            idempotency: parts.extensions.get::<Idempotency>().cloned(),
+            deadline: timeout.map(|timeout| self.timer.now() + timeout),
+            clock: self.timer.clone(),
+        };
+
+        // Without a policy nothing is recorded, but the call may still be
+        // retried transparently until its body is first polled.
+        let limit = if retry_policy.is_some() || hedging_policy.is_some() {
+            self.buffer_limit
+        } else {
+            0
+        };
+        let body = Recording::new(body, limit);
+
+        let first = self.inner.call(head.request(body.replay()));
+
+        if let (None, Some(policy)) = (retry_policy.as_ref(), hedging_policy) {
+            let hedging = Hedging::new(
+                self.inner.clone(),
+                head,
+                body,
+                first,
+                policy,
+                self.timer.clone(),
+                self.throttle.clone(),
+            );
+
+            return ResponseFuture {
+                state: State::Hedging(hedging),
+                retry: None,
+                throttle: self.throttle.clone(),
+            };
+        }
+
+        let backoff = retry_policy.as_ref()
+            .map(|policy| policy.initial_backoff)
+            .unwrap_or(Duration::from_secs(0));
+
+        ResponseFuture {
+            state: State::Calling(first),
+            retry: Some(Retrying {
+                service: self.inner.clone(),
+                head,
+                body,
+                policy: retry_policy,
+                timer: self.timer.clone(),
+                throttle: self.throttle.clone(),
+                attempts: 1,
+                backoff,
+                transparent: false,
+            }),
+            throttle: self.throttle.clone(),
+        }
+    }
+}
+
+impl<S> fmt::Debug for Retry<S>
+where S: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Retry")
+            .field("inner", &self.inner)
+            .field("config", &self.config)
+            .field("buffer_limit", &self.buffer_limit)
+            .field("safe_policy", &self.safe_policy)
+            .finish()
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<S, B, E> ResponseFuture<S, B>
+where S: HttpService<RequestBody = ReplayBody<B>, Error = ::Error<E>>,
+      B: Body<Data = Bytes>,
+{
+    /// If the attempt that failed with `code` should be retried, start the
+    /// backoff before the next attempt.
+    fn should_retry(&mut self, code: Code, pushback: Option<Pushback>, transport: bool) -> bool {
+        let retry = match self.retry {
+            Some(ref mut retry) => retry,
+            None => return false,
+        };
+
+        // An attempt that failed before any of the request was sent never
+        // reached the server, so it is always safe to send again.
+        if transport && !retry.transparent && retry.body.is_unsent() && !retry.head.is_expired() {
+            debug!("request not sent; retrying transparently");
+            retry.transparent = true;
+            self.state = State::Ready;
+            return true;
+        }
+
+        let policy = match retry.policy {
+            Some(ref policy) => policy.clone(),
+            None => return false,
+        };
+
+        if !policy.is_retryable(code) {
+            trace!("status not retryable; code={:?}", code);
+            return false;
+        }
+
+        retry.throttle.failure();
+
+        if !retry.throttle.allows_retry() {
+            debug!("retries throttled; not retrying");
+            return false;
+        }
+
+        if retry.attempts >= policy.max_attempts {
+            debug!("retry attempts exhausted; attempts={}", retry.attempts);
+            return false;
+        }
+
+        if !retry.body.is_replayable() {
+            debug!("request body exceeded the retry buffer; not retrying");
+            return false;
+        }
+
+        let delay = match pushback {
+            Some(Pushback::Stop) => {
+                debug!("server pushback; not retrying");
+                return false;
+            }
+            Some(Pushback::Delay(delay)) => {
+                // Backoff starts over after an explicit delay.
+                retry.backoff = policy.initial_backoff;
+                delay
+            }
+            None => retry.next_backoff(),
+        };
+
+        if let Some(remaining) = retry.head.remaining() {
+            if delay >= remaining {
+                debug!("retry would start after the deadline; not retrying");
+                return false;
+            }
+        }
+
+        debug!("retrying; attempt={}; code={:?}; backoff={:?}",
+               retry.attempts + 1, code, delay);
+
+        retry.attempts += 1;
+        self.state = State::Backoff(retry.timer.sleep(delay));
+
+        true
+    }
+
+    /// Wrap the response that ends the call.
+    ///
+    /// A response whose status is only known once its trailers arrive
+    /// credits the throttle from its body.
+    fn respond(&self, response: http::Response<S::ResponseBody>)
+        -> http::Response<ResponseBody<S::ResponseBody>>
+    {
+        let throttle = match trailers_only_code(&response) {
+            Some(_) => None,
+            None => Some(self.throttle.clone()),
+        };
+
+        response.map(|inner| ResponseBody { inner, throttle })
+    }
+}
+
+impl<S, B, E> Future for ResponseFuture<S, B>
+where S: HttpService<RequestBody = ReplayBody<B>, Error = ::Error<E>>,
+      B: Body<Data = Bytes>,
+{
+    type Item = http::Response<ResponseBody<S::ResponseBody>>;
+    type Error = ::Error<E>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                State::Calling(ref mut fut) => {
+                    match fut.poll() {
+                        Ok(Async::Ready(response)) => Err(Ok(response)),
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Err(e) => Err(Err(e)),
+                    }
+                }
+                State::Hedging(ref mut hedging) => {
+                    let response = try_ready!(hedging.poll());
+                    return Ok(Async::Ready(self.respond(response)));
+                }
+                State::Backoff(ref mut sleep) => {
+                    match sleep.poll() {
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        _ => Ok(State::Ready),
+                    }
+                }
+                State::Ready => {
+                    let retry = self.retry.as_mut().expect("retrying without a policy");
+                    try_ready!(retry.service.poll_ready());
+
+                    let request = retry.head.request(retry.body.replay());
+                    Ok(State::Calling(retry.service.call(request)))
+                }
+            };
+
+            match next {
+                Ok(state) => self.state = state,
+                Err(result) => {
+                    let (code, pushback, transport) = match result {
+                        Ok(ref response) => (trailers_only_code(response), pushback(response), false),
+                        Err(::Error::Grpc(ref status)) => (Some(status.code()), None, false),
+                        // Transport errors are treated as UNAVAILABLE.
+                        Err(_) => (Some(Code::UNAVAILABLE), None, true),
+                    };
+
+                    // A response whose status is only known once its
+                    // trailers arrive is credited by its body.
+                    let retrying = match code {
+                        Some(Code::OK) => {
+                            self.throttle.success();
+                            false
+                        }
+                        Some(code) => self.should_retry(code, pushback, transport),
+                        None => false,
+                    };
+
+                    if !retrying {
+                        return result.map(|response| Async::Ready(self.respond(response)));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<S, B> fmt::Debug for ResponseFuture<S, B>
+where S: HttpService,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let state = match self.state {
+            State::Calling(_) => "Calling",
+            State::Hedging(_) => "Hedging",
+            State::Backoff(_) => "Backoff",
+            State::Ready => "Ready",
+        };
+
+        fmt.debug_struct("ResponseFuture")
+            .field("state", &state)
+            .field("attempts", &self.retry.as_ref().map(|r| r.attempts))
+            .finish()
+    }
+}
+
+// ===== impl ResponseBody =====
+
+impl<B> Body for ResponseBody<B>
+where B: Body,
+{
+    type Data = B::Data;
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, h2::Error> {
+        self.inner.poll_data()
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, h2::Error> {
+        let trailers = try_ready!(self.inner.poll_trailers());
+
+        if let Some(throttle) = self.throttle.take() {
+            let code = trailers.as_ref()
+                .and_then(headers::status_code);
+
+            if code == Some(Code::OK) {
+                throttle.success();
+            }
+        }
+
+        Ok(Async::Ready(trailers))
+    }
+}
+
+impl<B> fmt::Debug for ResponseBody<B>
+where B: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("ResponseBody")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+// ===== impl Retrying =====
+
+impl<S, B> Retrying<S, B> {
+    /// Returns a randomized delay before the next attempt, and increases
+    /// the backoff for the one after.
+    fn next_backoff(&mut self) -> Duration {
+        let (multiplier, max_backoff) = match self.policy {
+            Some(ref policy) => (policy.backoff_multiplier, policy.max_backoff),
+            None => return Duration::from_secs(0),
+        };
+
+        let max = duration_secs(self.backoff);
+        let delay = rand::random::<f64>() * max;
+
+        let next = max * multiplier;
+        self.backoff = cmp::min(secs_duration(next), max_backoff);
+
+        secs_duration(delay)
+    }
+}
+
+// ===== impl Throttle =====
+
+impl Throttle {
+    /// Apply the throttling settings of the current service config.
+    ///
+    /// The bucket is refilled whenever the settings change.
+    fn configure(&self, config: Option<&RetryThrottling>) {
+        let mut bucket = self.lock();
+
+        let changed = match (bucket.as_ref(), config) {
+            (Some(bucket), Some(config)) => bucket.config != *config,
+            (None, None) => false,
+            _ => true,
+        };
+
+        if changed {
+            *bucket = config.map(|config| {
+                let max = config.max_tokens as u64 * 1000;
+                Bucket {
+                    config: config.clone(),
+                    tokens: max,
+                    max,
+                    ratio: (config.token_ratio * 1000.0) as u64,
+                }
+            });
+        }
+    }
+
+    /// Record a failed attempt.
+    fn failure(&self) {
+        if let Some(ref mut bucket) = *self.lock() {
+            bucket.tokens = bucket.tokens.saturating_sub(1000);
+        }
+    }
+
+    /// Record a successful call.
+    fn success(&self) {
+        if let Some(ref mut bucket) = *self.lock() {
+            bucket.tokens = cmp::min(bucket.tokens + bucket.ratio, bucket.max);
+        }
+    }
+
+    /// Returns true if calls may currently be retried.
+    fn allows_retry(&self) -> bool {
+        match *self.lock() {
+            Some(ref bucket) => bucket.tokens > bucket.max / 2,
+            None => true,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<Option<Bucket>> {
+        match self.bucket.lock() {
+            Ok(bucket) => bucket,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+// ===== impl Head =====
+
+impl Head {
+    /// Build a request for the next attempt, with a `grpc-timeout` of the
+    /// time remaining until the deadline.
+    fn request<B>(&self, body: B) -> http::Request<B> {
+        let mut request = http::Request::new(body);
+
+        *request.method_mut() = self.method.clone();
+        *request.uri_mut() = self.uri.clone();
+        *request.version_mut() = self.version;
+        *request.headers_mut() = self.headers.clone();
+
+        if let Some(ref limit) = self.send_limit {
+            request.extensions_mut().insert(limit.clone());
+        }
+        if let Some(wait_for_ready) = self.wait_for_ready {
+            request.extensions_mut().insert(wait_for_ready);
+        }
+        if let Some(idempotency) = self.idempotency {
+            request.extensions_mut().insert(idempotency);
+        }
+
+        if let Some(remaining) = self.remaining() {
+            request.headers_mut()
+                .insert(headers::GRPC_TIMEOUT.clone(), timeout::encode(remaining));
+        }
+
+        request
+    }
+
+    /// Returns true if the deadline has passed.
+    fn is_expired(&self) -> bool {
+        self.remaining() == Some(Duration::from_secs(0))
+    }
+
+    /// Returns the time left until the deadline, if the call has one.
+    fn remaining(&self) -> Option<Duration> {
+        self.deadline.map(|deadline| {
+            let now = self.clock.now();
+
+            if deadline > now {
+                deadline - now
+            } else {
+                Duration::from_secs(0)
+            }
+        })
+    }
+}
+
+// ===== impl Recording =====
+
+impl<B> Recording<B>
+where B: Body<Data = Bytes>,
+{
+    /// Record up to `limit` bytes of `body` as it is sent.
+    fn new(body: B, limit: usize) -> Self {
+        let buffer = Buffer {
+            source: body,
+            chunks: vec![],
+            read: 0,
+            len: 0,
+            limit,
+            overflowed: false,
+            source_done: false,
+            trailers: None,
+        };
+
+        Recording {
+            buffer: Arc::new(Mutex::new(buffer)),
+        }
+    }
+
+    /// Returns a body that sends the recorded data from the start.
+    fn replay(&self) -> ReplayBody<B> {
+        ReplayBody {
+            inner: Inner::Shared {
+                buffer: self.buffer.clone(),
+                pos: 0,
+            },
+        }
+    }
+
+    /// Returns true if no data has been sent yet.
+    fn is_unsent(&self) -> bool {
+        let buffer = self.buffer.lock().expect("replay buffer poisoned");
+        buffer.read == 0 && buffer.trailers.is_none()
+    }
+
+    /// Returns true if every byte sent so far has been recorded.
+    fn is_replayable(&self) -> bool {
+        !self.buffer.lock().expect("replay buffer poisoned").overflowed
+    }
+}
+
+// ===== impl ReplayBody =====
+
+impl<B> ReplayBody<B>
+where B: Body<Data = Bytes>,
+{
+    /// Send `body` without recording it.
+    pub fn direct(body: B) -> Self {
+        ReplayBody { inner: Inner::Direct(body) }
+    }
+}
+
+impl<B> Body for ReplayBody<B>
+where B: Body<Data = Bytes>,
+{
+    type Data = Bytes;
+
+    fn is_end_stream(&self) -> bool {
+        match self.inner {
+            Inner::Direct(ref body) => body.is_end_stream(),
+            Inner::Shared { .. } => false,
+        }
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Bytes>, h2::Error> {
+        let (buffer, pos) = match self.inner {
+            Inner::Direct(ref mut body) => return body.poll_data(),
+            Inner::Shared { ref buffer, ref mut pos } => (buffer, pos),
+        };
+
+        let mut buffer = buffer.lock().expect("replay buffer poisoned");
+
+        if *pos < buffer.read {
+            if buffer.overflowed {
+                // Another attempt has sent data that was not recorded, so
+                // this attempt can't catch up.
+                return Err(h2::Reason::CANCEL.into());
+            }
+
+            *pos += 1;
+            return Ok(Async::Ready(Some(buffer.chunks[*pos - 1].clone())));
+        }
+
+        if buffer.source_done {
+            return Ok(Async::Ready(None));
+        }
+
+        match try_ready!(buffer.source.poll_data()) {
+            Some(chunk) => {
+                buffer.len += chunk.len();
+                buffer.read += 1;
+                *pos += 1;
+
+                if !buffer.overflowed {
+                    if buffer.len <= buffer.limit {
+                        buffer.chunks.push(chunk.clone());
+                    } else {
+                        // Drop what was recorded; it will never be replayed.
+                        buffer.overflowed = true;
+                        buffer.chunks.clear();
+                    }
+                }
+
+                Ok(Async::Ready(Some(chunk)))
+            }
+            None => {
+                buffer.source_done = true;
+                Ok(Async::Ready(None))
+            }
+        }
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, h2::Error> {
+        let buffer = match self.inner {
+            Inner::Direct(ref mut body) => return body.poll_trailers(),
+            Inner::Shared { ref buffer, .. } => buffer,
+        };
+
+        let mut buffer = buffer.lock().expect("replay buffer poisoned");
+
+        if let Some(ref trailers) = buffer.trailers {
+            return Ok(Async::Ready(trailers.clone()));
+        }
+
+        let trailers = try_ready!(buffer.source.poll_trailers());
+        buffer.trailers = Some(trailers.clone());
+
+        Ok(Async::Ready(trailers))
+    }
+}
+
+impl<B> fmt::Debug for ReplayBody<B> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.inner {
+            Inner::Direct(_) => "Direct",
+            Inner::Shared { .. } => "Shared",
+        };
+
+        fmt.debug_struct("ReplayBody")
+            .field("kind", &kind)
+            .finish()
+    }
+}
+
+// ===== utility fns =====
+
+/// Returns the status code of a trailers-only response.
+fn trailers_only_code<B>(response: &http::Response<B>) -> Option<Code> {
+    response.headers()
+        .get("grpc-status")
+        .map(|s| Status::from_bytes(s.as_ref()).code())
+}
+
+/// Returns the server pushback of a response, if any.
+fn pushback<B>(response: &http::Response<B>) -> Option<Pushback> {
+    let value = response.headers().get("grpc-retry-pushback-ms")?;
+
+    let millis = value.to_str().ok()
+        .and_then(|s| s.parse::<i64>().ok());
+
+    match millis {
+        Some(millis) if millis >= 0 => Some(Pushback::Delay(Duration::from_millis(millis as u64))),
+        _ => Some(Pushback::Stop),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use channel::config::{MethodConfig, ServiceConfig, SharedConfig};
+    use clock::MockClock;
+
+    use futures::future::{self, FutureResult};
+
+    use std::collections::VecDeque;
+
+    /// Answers each attempt with the next queued result.
+    #[derive(Clone, Default)]
+    struct MockService {
+        results: Arc<Mutex<VecDeque<Result<http::Response<MockBody>, ::Error<()>>>>>,
+
+        /// The headers of each attempt, in order.
+        requests: Arc<Mutex<Vec<HeaderMap>>>,
+
+        /// The wait-for-ready and send limit extensions of each attempt.
+        extensions: Arc<Mutex<Vec<(Option<WaitForReady>, Option<SendLimit>)>>>,
+    }
+
+    /// A body with optional data and trailers.
+    #[derive(Default)]
+    struct MockBody {
+        data: Option<Bytes>,
+        trailers: Option<HeaderMap>,
+    }
+
+    fn request() -> http::Request<MockBody> {
+        http::Request::builder()
+            .uri("/test.Service/Method")
+            .body(MockBody::default())
+            .unwrap()
+    }
+
+    /// Returns a `Retry` calling a `MockService`, with `config` for every
+    /// method.
+    fn retrying(config: Option<MethodConfig>, clock: &MockClock) -> (MockService, Retry<MockService>) {
+        let config = match config {
+            Some(config) => ServiceConfig::default().with_method("/", config),
+            None => ServiceConfig::default(),
+        };
+
+        let service = MockService::default();
+        let retry = Retry::new(service.clone(), SharedConfig::new(config))
+            .timer(clock);
+
+        (service, retry)
+    }
+
+    fn unavailable() -> RetryPolicy {
+        RetryPolicy::new(3, vec![Code::UNAVAILABLE])
+    }
+
+    /// Returns a trailers-only response with `code`.
+    fn trailers_only(code: Code) -> http::Response<MockBody> {
+        let mut response = http::Response::new(MockBody::default());
+        response.headers_mut()
+            .insert(headers::GRPC_STATUS.clone(), Status::with_code(code).to_header_value());
+        response
+    }
+
+    /// Returns a trailers-only response with `code` and the pushback
+    /// `millis`.
+    fn pushback(code: Code, millis: &'static str) -> http::Response<MockBody> {
+        let mut response = trailers_only(code);
+        response.headers_mut()
+            .insert("grpc-retry-pushback-ms", millis.parse().unwrap());
+        response
+    }
+
+    /// Polls a call that is expected to have completed.
+    fn ready<F: Future>(call: &mut F) -> Result<F::Item, F::Error> {
+        match call.poll() {
+            Ok(Async::Ready(item)) => Ok(item),
+            Ok(Async::NotReady) => panic!("call not complete"),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Returns a response whose status is in its trailers.
+    fn trailers(code: Code) -> http::Response<MockBody> {
+        let mut trailers = HeaderMap::new();
+        trailers.insert(headers::GRPC_STATUS.clone(), Status::with_code(code).to_header_value());
+
+        http::Response::new(MockBody {
+            data: None,
+            trailers: Some(trailers),
+        })
+    }
+
+    /// Read a response to its end.
+    fn drain<B: Body>(response: http::Response<B>) {
+        let mut body = response.into_body();
+
+        while let Async::Ready(Some(_)) = body.poll_data().unwrap() {}
+        body.poll_trailers().unwrap();
+    }
+
+    impl MockService {
+        fn push(&self, result: Result<http::Response<MockBody>, ::Error<()>>) {
+            self.results.lock().unwrap().push_back(result);
+        }
+
+        fn attempts(&self) -> usize {
+            self.requests.lock().unwrap().len()
+        }
+
+        /// Returns the `grpc-timeout` of attempt `i`.
+        fn timeout(&self, i: usize) -> Option<Duration> {
+            self.requests.lock().unwrap()[i]
+                .get(&*headers::GRPC_TIMEOUT)
+                .and_then(timeout::decode)
+        }
+    }
+
+    impl Service for MockService {
+        type Request = http::Request<ReplayBody<MockBody>>;
+        type Response = http::Response<MockBody>;
+        type Error = ::Error<()>;
+        type Future = FutureResult<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, request: Self::Request) -> Self::Future {
+            self.requests.lock().unwrap().push(request.headers().clone());
+            self.extensions.lock().unwrap().push((
+                request.extensions().get::<WaitForReady>().cloned(),
+                request.extensions().get::<SendLimit>().cloned(),
+            ));
+
+            let result = self.results.lock().unwrap().pop_front()
+                .expect("unexpected attempt");
+            future::result(result)
+        }
+    }
+
+    impl Body for MockBody {
+        type Data = Bytes;
+
+        fn is_end_stream(&self) -> bool {
+            self.data.is_none() && self.trailers.is_none()
+        }
+
+        fn poll_data(&mut self) -> Poll<Option<Bytes>, h2::Error> {
+            Ok(Async::Ready(self.data.take()))
+        }
+
+        fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, h2::Error> {
+            Ok(Async::Ready(self.trailers.take()))
+        }
+    }
+
+    #[test]
+    fn ok_trailers_refill_the_throttle() {
+        let config = ServiceConfig {
+            retry_throttling: Some(RetryThrottling {
+                max_tokens: 10,
+                token_ratio: 1.0,
+            }),
+            ..ServiceConfig::default()
+        };
+
+        let service = MockService::default();
+        let mut retry = Retry::new(service.clone(), SharedConfig::new(config));
+
+        service.push(Ok(trailers(Code::OK)));
+        service.push(Ok(trailers(Code::OK)));
+
+        let first = retry.call(request()).wait().unwrap();
+        let second = retry.call(request()).wait().unwrap();
+
+        // Retries stop once the bucket is down to half its tokens.
+        for _ in 0..5 {
+            retry.throttle.failure();
+        }
+        assert!(!retry.throttle.allows_retry());
+
+        drain(first);
+        assert!(retry.throttle.allows_retry());
+
+        // The call is credited by its trailers, not its response head.
+        retry.throttle.failure();
+        assert!(!retry.throttle.allows_retry());
+
+        let mut body = second.into_body();
+        body.poll_data().unwrap();
+        assert!(!retry.throttle.allows_retry());
+
+        body.poll_trailers().unwrap();
+        assert!(retry.throttle.allows_retry());
+    }
+
+    #[test]
+    fn retryable_status_retried_after_backoff() {
+        let clock = MockClock::new();
+        let (service, mut retry) = retrying(Some(MethodConfig::default().retry(unavailable())), &clock);
+
+        future::lazy(move || {
+            service.push(Ok(trailers_only(Code::UNAVAILABLE)));
+            service.push(Ok(trailers(Code::OK)));
+
+            let mut call = retry.call(request());
+            assert!(call.poll().unwrap().is_not_ready());
+            assert_eq!(service.attempts(), 1);
+
+            // The first backoff is at most the initial backoff.
+            clock.advance(Duration::from_millis(100));
+            let response = ready(&mut call).unwrap();
+            assert_eq!(trailers_only_code(&response), None);
+            assert_eq!(service.attempts(), 2);
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn last_failure_returned_once_attempts_are_exhausted() {
+        let clock = MockClock::new();
+        let policy = RetryPolicy::new(2, vec![Code::UNAVAILABLE]);
+        let (service, mut retry) = retrying(Some(MethodConfig::default().retry(policy)), &clock);
+
+        future::lazy(move || {
+            service.push(Ok(trailers_only(Code::UNAVAILABLE)));
+            service.push(Ok(trailers_only(Code::UNAVAILABLE)));
+
+            let mut call = retry.call(request());
+            assert!(call.poll().unwrap().is_not_ready());
+
+            clock.advance(Duration::from_millis(100));
+            let response = ready(&mut call).unwrap();
+            assert_eq!(trailers_only_code(&response), Some(Code::UNAVAILABLE));
+            assert_eq!(service.attempts(), 2);
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn extensions_carried_to_every_attempt() {
+        let clock = MockClock::new();
+        let (service, mut retry) = retrying(Some(MethodConfig::default().retry(unavailable())), &clock);
+
+        future::lazy(move || {
+            service.push(Ok(trailers_only(Code::UNAVAILABLE)));
+            service.push(Ok(trailers(Code::OK)));
+
+            let limit = SendLimit::new();
+            let mut request = request();
+            request.extensions_mut().insert(WaitForReady(true));
+            request.extensions_mut().insert(limit.clone());
+
+            let mut call = retry.call(request);
+            assert!(call.poll().unwrap().is_not_ready());
+
+            clock.advance(Duration::from_millis(100));
+            drain(ready(&mut call).unwrap());
+            assert_eq!(service.attempts(), 2);
+
+            let extensions = service.extensions.lock().unwrap();
+            for &(wait_for_ready, _) in extensions.iter() {
+                assert_eq!(wait_for_ready, Some(WaitForReady(true)));
+            }
+
+            // The limit the channel applies to the last attempt is the one
+            // the client's codec reads.
+            extensions[1].1.as_ref().expect("send limit").set(4);
+            assert_eq!(limit.get(), Some(4));
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn non_retryable_status_ends_the_call() {
+        let clock = MockClock::new();
+        let (service, mut retry) = retrying(Some(MethodConfig::default().retry(unavailable())), &clock);
+
+        future::lazy(move || {
+            service.push(Ok(trailers_only(Code::INVALID_ARGUMENT)));
+
+            let mut call = retry.call(request());
+            let response = ready(&mut call).unwrap();
+            assert_eq!(trailers_only_code(&response), Some(Code::INVALID_ARGUMENT));
+            assert_eq!(service.attempts(), 1);
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn pushback_replaces_the_backoff() {
+        let clock = MockClock::new();
+        let (service, mut retry) = retrying(Some(MethodConfig::default().retry(unavailable())), &clock);
+
+        future::lazy(move || {
+            service.push(Ok(pushback(Code::UNAVAILABLE, "500")));
+            service.push(Ok(trailers(Code::OK)));
+
+            let mut call = retry.call(request());
+            assert!(call.poll().unwrap().is_not_ready());
+
+            clock.advance(Duration::from_millis(100));
+            assert!(call.poll().unwrap().is_not_ready());
+            assert_eq!(service.attempts(), 1);
+
+            clock.advance(Duration::from_millis(400));
+            ready(&mut call).unwrap();
+            assert_eq!(service.attempts(), 2);
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn negative_pushback_stops_retries() {
+        let clock = MockClock::new();
+        let (service, mut retry) = retrying(Some(MethodConfig::default().retry(unavailable())), &clock);
+
+        future::lazy(move || {
+            service.push(Ok(pushback(Code::UNAVAILABLE, "-1")));
+
+            let mut call = retry.call(request());
+            let response = ready(&mut call).unwrap();
+            assert_eq!(trailers_only_code(&response), Some(Code::UNAVAILABLE));
+            assert_eq!(service.attempts(), 1);
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn unsent_call_retried_transparently_once() {
+        let clock = MockClock::new();
+        let (service, mut retry) = retrying(None, &clock);
+
+        future::lazy(move || {
+            service.push(Err(::Error::Inner(())));
+            service.push(Err(::Error::Inner(())));
+
+            // Without a policy, only the transparent retry is made.
+            let mut call = retry.call(request());
+            match ready(&mut call) {
+                Err(::Error::Inner(())) => {}
+                _ => panic!("transport error not returned"),
+            }
+            assert_eq!(service.attempts(), 2);
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn methods_without_side_effects_retried_without_policy() {
+        let clock = MockClock::new();
+        let (service, mut retry) = retrying(None, &clock);
+
+        future::lazy(move || {
+            service.push(Ok(trailers_only(Code::UNAVAILABLE)));
+            service.push(Ok(trailers(Code::OK)));
+
+            let mut request = request();
+            request.extensions_mut().insert(Idempotency::NoSideEffects);
+
+            let mut call = retry.call(request);
+            assert!(call.poll().unwrap().is_not_ready());
+
+            clock.advance(Duration::from_millis(100));
+            ready(&mut call).unwrap();
+            assert_eq!(service.attempts(), 2);
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn idempotent_methods_not_retried_without_policy() {
+        let clock = MockClock::new();
+        let (service, mut retry) = retrying(None, &clock);
+
+        future::lazy(move || {
+            service.push(Ok(trailers_only(Code::UNAVAILABLE)));
+
+            let mut request = request();
+            request.extensions_mut().insert(Idempotency::Idempotent);
+
+            let mut call = retry.call(request);
+            let response = ready(&mut call).unwrap();
+            assert_eq!(trailers_only_code(&response), Some(Code::UNAVAILABLE));
+            assert_eq!(service.attempts(), 1);
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn attempts_sent_with_the_time_remaining() {
+        let clock = MockClock::new();
+        let config = MethodConfig::default()
+            .timeout(Duration::from_secs(1))
+            .retry(unavailable());
+        let (service, mut retry) = retrying(Some(config), &clock);
+
+        future::lazy(move || {
+            service.push(Ok(pushback(Code::UNAVAILABLE, "300")));
+            service.push(Ok(trailers(Code::OK)));
+
+            let mut call = retry.call(request());
+            assert!(call.poll().unwrap().is_not_ready());
+            assert_eq!(service.timeout(0), Some(Duration::from_secs(1)));
+
+            clock.advance(Duration::from_millis(300));
+            ready(&mut call).unwrap();
+            assert_eq!(service.timeout(1), Some(Duration::from_millis(700)));
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn no_attempt_started_after_the_deadline() {
+        let clock = MockClock::new();
+        let config = MethodConfig::default()
+            .timeout(Duration::from_millis(50))
+            .retry(unavailable());
+        let (service, mut retry) = retrying(Some(config), &clock);
+
+        future::lazy(move || {
+            service.push(Ok(pushback(Code::UNAVAILABLE, "100")));
+
+            let mut call = retry.call(request());
+            let response = ready(&mut call).unwrap();
+            assert_eq!(trailers_only_code(&response), Some(Code::UNAVAILABLE));
+            assert_eq!(service.attempts(), 1);
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+}
@@ -0,0 +1,196 @@
+use futures::Poll;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Counts the bytes and streams of a channel.
+///
+/// Streams are counted by the channel itself. Bytes are counted at the
+/// transport, so connectors that want them must wrap the I/O of each
+/// connection they make with `io`:
+///
+/// ```ignore
+/// let stats = ChannelStats::new();
+/// let connect = MyConnect { stats: stats.clone() };
+/// let channel = Channel::new(resolver, connect, policy).with_stats(stats);
+///
+/// // In `MyConnect::connect`:
+/// let io = self.stats.io(tcp_stream);
+/// ```
+///
+/// The counters mirror the socket data reported by channelz.
+#[derive(Debug, Clone, Default)]
+pub struct ChannelStats {
+    counters: Arc<Counters>,
+}
+
+/// The counters of a channel at a point in time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChannelStatsSnapshot {
+    /// Bytes written to the channel's connections.
+    pub bytes_sent: u64,
+
+    /// Bytes read from the channel's connections.
+    pub bytes_received: u64,
+
+    /// Streams started and not yet ended.
+    pub active_streams: u64,
+
+    /// Streams started.
+    pub streams_started: u64,
+
+    /// Streams that ended with trailers.
+    pub streams_succeeded: u64,
+
+    /// Streams that were reset, failed or dropped before ending.
+    pub streams_failed: u64,
+}
+
+/// Wraps a connection's I/O, counting the bytes read and written.
+#[derive(Debug)]
+pub struct CountedIo<T> {
+    io: T,
+    stats: ChannelStats,
+}
+
+/// A stream counted as active until it ends; failed if dropped before then.
+#[derive(Debug)]
+pub(crate) struct Stream {
+    stats: ChannelStats,
+    ended: bool,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    bytes_sent: AtomicUsize,
+    bytes_received: AtomicUsize,
+    active_streams: AtomicUsize,
+    streams_started: AtomicUsize,
+    streams_succeeded: AtomicUsize,
+    streams_failed: AtomicUsize,
+}
+
+// ===== impl ChannelStats =====
+
+impl ChannelStats {
+    pub fn new() -> Self {
+        ChannelStats::default()
+    }
+
+    /// Returns the current value of every counter.
+    pub fn snapshot(&self) -> ChannelStatsSnapshot {
+        let c = &self.counters;
+        let load = |counter: &AtomicUsize| counter.load(Ordering::Relaxed) as u64;
+
+        ChannelStatsSnapshot {
+            bytes_sent: load(&c.bytes_sent),
+            bytes_received: load(&c.bytes_received),
+            active_streams: load(&c.active_streams),
+            streams_started: load(&c.streams_started),
+            streams_succeeded: load(&c.streams_succeeded),
+            streams_failed: load(&c.streams_failed),
+        }
+    }
+
+    /// Count the bytes read from and written to `io`.
+    pub fn io<T>(&self, io: T) -> CountedIo<T> {
+        CountedIo {
+            io,
+            stats: self.clone(),
+        }
+    }
+
+    pub(crate) fn stream(&self) -> Stream {
+        self.counters.streams_started.fetch_add(1, Ordering::Relaxed);
+        self.counters.active_streams.fetch_add(1, Ordering::Relaxed);
+
+        Stream {
+            stats: self.clone(),
+            ended: false,
+        }
+    }
+}
+
+// ===== impl CountedIo =====
+
+impl<T> CountedIo<T> {
+    /// Returns a reference to the inner I/O.
+    pub fn get_ref(&self) -> &T {
+        &self.io
+    }
+
+    /// Returns a mutable reference to the inner I/O.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.io
+    }
+}
+
+impl<T> io::Read for CountedIo<T>
+where T: io::Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.io.read(buf)?;
+        self.stats.counters.bytes_received.fetch_add(n, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+impl<T> io::Write for CountedIo<T>
+where T: io::Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.io.write(buf)?;
+        self.stats.counters.bytes_sent.fetch_add(n, Ordering::Relaxed);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+impl<T> AsyncRead for CountedIo<T>
+where T: AsyncRead,
+{
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        self.io.prepare_uninitialized_buffer(buf)
+    }
+}
+
+impl<T> AsyncWrite for CountedIo<T>
+where T: AsyncWrite,
+{
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.io.shutdown()
+    }
+}
+
+// ===== impl Stream =====
+
+impl Stream {
+    /// End the stream, successfully if it ended with trailers.
+    pub(crate) fn end(&mut self, success: bool) {
+        if self.ended {
+            return;
+        }
+
+        self.ended = true;
+
+        let counters = &self.stats.counters;
+        counters.active_streams.fetch_sub(1, Ordering::Relaxed);
+
+        if success {
+            counters.streams_succeeded.fetch_add(1, Ordering::Relaxed);
+        } else {
+            counters.streams_failed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Drop for Stream {
+    fn drop(&mut self) {
+        self.end(false);
+    }
+}
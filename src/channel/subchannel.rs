@@ -0,0 +1,422 @@
+use super::{duration_secs, secs_duration, Connect, Endpoint};
+use clock::{Clock, Sleep};
+
+use futures::{Future, Async};
+use http;
+use rand;
+use tower_h2::HttpService;
+
+use std::cell::Cell;
+use std::{cmp, fmt};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+// Reconnection backoff, as specified by the [gRPC connection backoff
+// protocol][spec].
+//
+// [spec]: https://github.com/grpc/grpc/blob/master/doc/connection-backoff.md
+const INITIAL_BACKOFF_SECS: u64 = 1;
+const MAX_BACKOFF_SECS: u64 = 120;
+const BACKOFF_MULTIPLIER: f64 = 1.6;
+const BACKOFF_JITTER: f64 = 0.2;
+
+/// A connection to a single endpoint of the channel's target.
+pub struct Subchannel<C>
+where C: Connect,
+{
+    endpoint: Endpoint,
+    state: State<C>,
+    connectivity: Connectivity,
+    ready: bool,
+    ejected: Cell<bool>,
+    stats: Arc<Stats>,
+
+    timer: Clock,
+
+    /// The backoff before the attempt following the next failure.
+    backoff: Duration,
+
+    /// Set while a failed subchannel waits to be connected again.
+    retry: Option<Sleep>,
+}
+
+/// Counts the outcomes of requests dispatched on a subchannel.
+#[derive(Debug, Default)]
+pub struct Stats {
+    successes: AtomicUsize,
+    failures: AtomicUsize,
+}
+
+/// The connectivity state of a subchannel.
+///
+/// See the [gRPC connectivity semantics][spec] for details.
+///
+/// [spec]: https://github.com/grpc/grpc/blob/master/doc/connectivity-semantics-and-api.md
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Connectivity {
+    /// No connection has been attempted.
+    Idle,
+
+    /// A connection is being established.
+    Connecting,
+
+    /// The subchannel is connected.
+    Ready,
+
+    /// The last connection attempt failed, or the connection was lost.
+    ///
+    /// The subchannel becomes `Idle` again once its backoff has elapsed.
+    /// A connection whose endpoint reports that it is not serving is also
+    /// in this state, until the endpoint serves again.
+    TransientFailure,
+}
+
+enum State<C>
+where C: Connect,
+{
+    Idle,
+    Connecting(C::Future),
+    Connected(C::Service),
+    Failed,
+}
+
+// ===== impl Subchannel =====
+
+impl<C> Subchannel<C>
+where C: Connect,
+{
+    pub(crate) fn new(endpoint: Endpoint, timer: Clock) -> Self {
+        Subchannel {
+            endpoint,
+            state: State::Idle,
+            connectivity: Connectivity::Idle,
+            ready: false,
+            ejected: Cell::new(false),
+            stats: Arc::new(Stats::default()),
+            timer,
+            backoff: Duration::from_secs(INITIAL_BACKOFF_SECS),
+            retry: None,
+        }
+    }
+
+    /// Returns the endpoint this subchannel connects to.
+    pub fn endpoint(&self) -> &Endpoint {
+        &self.endpoint
+    }
+
+    /// Returns the subchannel's connectivity as of the last time the channel
+    /// was polled.
+    pub fn connectivity(&self) -> Connectivity {
+        self.connectivity
+    }
+
+    /// Returns true if the subchannel can accept a request.
+    ///
+    /// A subchannel may be `Ready` but unable to accept more requests, for
+    /// example when the connection's concurrency limit is reached.
+    pub fn is_ready(&self) -> bool {
+        self.ready && !self.ejected.get()
+    }
+
+    /// Returns true if the subchannel has been ejected by a policy.
+    pub fn is_ejected(&self) -> bool {
+        self.ejected.get()
+    }
+
+    /// Exclude the subchannel from, or return it to, request picking.
+    ///
+    /// An ejected subchannel stays connected but never reports itself as
+    /// ready.
+    pub fn set_ejected(&self, ejected: bool) {
+        self.ejected.set(ejected);
+    }
+
+    /// Returns the outcomes of requests dispatched on this subchannel.
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    pub(crate) fn stats_handle(&self) -> Arc<Stats> {
+        self.stats.clone()
+    }
+
+    pub(crate) fn set_endpoint(&mut self, endpoint: Endpoint) {
+        self.endpoint = endpoint;
+    }
+
+    /// Start connecting if the subchannel is idle or failed.
+    pub(crate) fn connect(&mut self, connect: &mut C) {
+        match self.state {
+            State::Idle | State::Failed => {
+                trace!("subchannel connecting; addr={}", self.endpoint.addr());
+                self.state = State::Connecting(connect.connect(&self.endpoint));
+                self.connectivity = Connectivity::Connecting;
+                self.retry = None;
+            }
+            _ => {}
+        }
+    }
+
+    /// Drive the subchannel's connection, updating its connectivity.
+    pub(crate) fn poll(&mut self) -> Connectivity {
+        loop {
+            let next = match self.state {
+                State::Idle => {
+                    self.connectivity = Connectivity::Idle;
+                    self.ready = false;
+                    break;
+                }
+                State::Failed => {
+                    if !self.poll_backoff() {
+                        self.connectivity = Connectivity::TransientFailure;
+                        self.ready = false;
+                        break;
+                    }
+
+                    trace!("subchannel backoff elapsed; addr={}", self.endpoint.addr());
+                    State::Idle
+                }
+                State::Connecting(ref mut fut) => {
+                    match fut.poll() {
+                        Ok(Async::Ready(service)) => {
+                            debug!("subchannel connected; addr={}", self.endpoint.addr());
+                            self.backoff = Duration::from_secs(INITIAL_BACKOFF_SECS);
+                            State::Connected(service)
+                        }
+                        Ok(Async::NotReady) => {
+                            self.connectivity = Connectivity::Connecting;
+                            self.ready = false;
+                            break;
+                        }
+                        Err(e) => {
+                            debug!("subchannel connect failed; addr={}; err={:?}",
+                                   self.endpoint.addr(), e);
+                            State::Failed
+                        }
+                    }
+                }
+                State::Connected(ref mut service) => {
+                    match service.poll_ready() {
+                        Ok(Async::Ready(())) => {
+                            self.connectivity = Connectivity::Ready;
+                            self.ready = true;
+                            break;
+                        }
+                        Ok(Async::NotReady) => {
+                            self.connectivity = if C::is_failing(service) {
+                                Connectivity::TransientFailure
+                            } else {
+                                Connectivity::Ready
+                            };
+                            self.ready = false;
+                            break;
+                        }
+                        Err(e) => {
+                            debug!("subchannel disconnected; addr={}; err={:?}",
+                                   self.endpoint.addr(), e);
+                            State::Failed
+                        }
+                    }
+                }
+            };
+
+            if let State::Failed = next {
+                let backoff = self.next_backoff();
+                debug!("subchannel backing off; addr={}; backoff={:?}",
+                       self.endpoint.addr(), backoff);
+                self.retry = Some(self.timer.sleep(backoff));
+            }
+
+            self.state = next;
+        }
+
+        self.connectivity
+    }
+
+    /// Returns true once a failed subchannel's backoff has elapsed.
+    fn poll_backoff(&mut self) -> bool {
+        let elapsed = match self.retry {
+            Some(ref mut retry) => match retry.poll() {
+                Ok(Async::NotReady) => false,
+                Ok(Async::Ready(())) => true,
+                Err(e) => {
+                    debug!("subchannel backoff timer failed; error={:?}", e);
+                    true
+                }
+            },
+            None => true,
+        };
+
+        if elapsed {
+            self.retry = None;
+        }
+
+        elapsed
+    }
+
+    /// Returns a jittered delay before the next connection attempt, and
+    /// increases the backoff for the one after.
+    fn next_backoff(&mut self) -> Duration {
+        let backoff = duration_secs(self.backoff);
+        let jitter = 1.0 + BACKOFF_JITTER * (rand::random::<f64>() * 2.0 - 1.0);
+
+        self.backoff = cmp::min(
+            secs_duration(backoff * BACKOFF_MULTIPLIER),
+            Duration::from_secs(MAX_BACKOFF_SECS));
+
+        secs_duration(backoff * jitter)
+    }
+
+    /// Dispatch a request on the subchannel.
+    ///
+    /// The subchannel must be ready.
+    pub(crate) fn call(&mut self,
+                       request: http::Request<<C::Service as HttpService>::RequestBody>)
+        -> <C::Service as HttpService>::Future
+    {
+        assert!(self.ready, "subchannel called before it was ready");
+
+        // The service must be polled again before the next request.
+        self.ready = false;
+
+        match self.state {
+            State::Connected(ref mut service) => service.call(request),
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl<C> fmt::Debug for Subchannel<C>
+where C: Connect,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Subchannel")
+            .field("endpoint", &self.endpoint)
+            .field("connectivity", &self.connectivity)
+            .field("ready", &self.ready)
+            .field("ejected", &self.ejected.get())
+            .field("backoff", &self.backoff)
+            .finish()
+    }
+}
+
+// ===== impl Stats =====
+
+impl Stats {
+    pub(crate) fn record(&self, success: bool) {
+        if success {
+            self.successes.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the number of successful requests recorded.
+    pub fn successes(&self) -> usize {
+        self.successes.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of failed requests recorded.
+    pub fn failures(&self) -> usize {
+        self.failures.load(Ordering::Relaxed)
+    }
+
+    /// Returns `(successes, failures)` and resets both counters to zero.
+    pub fn take(&self) -> (usize, usize) {
+        let successes = self.successes.swap(0, Ordering::Relaxed);
+        let failures = self.failures.swap(0, Ordering::Relaxed);
+        (successes, failures)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use channel::mock::{addr, subchannels, MockConnect};
+    use clock::MockClock;
+
+    use futures::future;
+
+    /// Returns true if `backoff` is within the jitter of `secs`.
+    fn jittered(backoff: Duration, secs: f64) -> bool {
+        let backoff = duration_secs(backoff);
+        let error = 1e-6;
+
+        backoff >= secs * (1.0 - BACKOFF_JITTER) - error &&
+            backoff <= secs * (1.0 + BACKOFF_JITTER) + error
+    }
+
+    #[test]
+    fn backoff_grows_to_its_maximum() {
+        let clock = MockClock::new();
+        let mut sub = subchannels(&[Endpoint::from(addr(1))], &clock).remove(0);
+
+        let mut secs = 1.0;
+        for _ in 0..20 {
+            let backoff = sub.next_backoff();
+            assert!(jittered(backoff, secs), "backoff={:?}; expected={}s", backoff, secs);
+
+            secs = f64::min(secs * 1.6, 120.0);
+        }
+
+        assert_eq!(sub.backoff, Duration::from_secs(120));
+    }
+
+    #[test]
+    fn failed_subchannel_idle_once_backoff_elapses() {
+        let clock = MockClock::new();
+        let mut net = MockConnect::default();
+        let mut sub = subchannels(&[Endpoint::from(addr(1))], &clock).remove(0);
+
+        future::lazy(move || {
+            net.down(&mut sub);
+            assert_eq!(sub.connectivity(), Connectivity::TransientFailure);
+            assert!(jittered(clock.next_sleep().unwrap(), 1.0));
+
+            clock.advance(Duration::from_millis(799));
+            assert_eq!(sub.poll(), Connectivity::TransientFailure);
+
+            clock.advance(Duration::from_millis(401));
+            assert_eq!(sub.poll(), Connectivity::Idle);
+
+            // Each failure in a row backs off for longer.
+            net.down(&mut sub);
+            assert!(jittered(clock.next_sleep().unwrap(), 1.6));
+
+            clock.advance(Duration::from_millis(1920));
+            assert_eq!(sub.poll(), Connectivity::Idle);
+
+            net.down(&mut sub);
+            assert!(jittered(clock.next_sleep().unwrap(), 2.56));
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn connecting_resets_the_backoff() {
+        let clock = MockClock::new();
+        let mut net = MockConnect::default();
+        let mut sub = subchannels(&[Endpoint::from(addr(1))], &clock).remove(0);
+
+        future::lazy(move || {
+            net.down(&mut sub);
+            clock.advance(Duration::from_millis(1200));
+            net.down(&mut sub);
+            clock.advance(Duration::from_millis(1920));
+
+            net.up(&mut sub);
+            assert_eq!(sub.connectivity(), Connectivity::Ready);
+            assert!(sub.is_ready());
+
+            // The connection is lost, and the next attempt backs off as
+            // if it were the first.
+            net.down(&mut sub);
+            assert_eq!(sub.connectivity(), Connectivity::TransientFailure);
+            assert!(jittered(clock.next_sleep().unwrap(), 1.0));
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+}
@@ -0,0 +1,849 @@
+//! An xDS resolver.
+//!
+//! Resolves `xds:///name` targets by subscribing to an xDS management server
+//! over the Aggregated Discovery Service. The resolver follows the chain of
+//! resources that gRPC clients use:
+//!
+//! 1. The `Listener` named by the target, whose API listener holds an HTTP
+//!    connection manager.
+//! 2. The `RouteConfiguration`, inline or fetched through RDS, whose default
+//!    route names a cluster.
+//! 3. The `Cluster`, which selects the load balancing policy and names the
+//!    endpoint assignment.
+//! 4. The `ClusterLoadAssignment`, whose healthy endpoints (weighted by
+//!    endpoint and locality weight) become the channel's endpoints.
+//!
+//! Only the highest priority locality group with healthy endpoints is used.
+//!
+//! Each response is acknowledged with a request carrying its version and
+//! nonce. A response with a resource that cannot be decoded is rejected
+//! instead: the request carries its nonce, the last version accepted for
+//! its type, and the error.
+
+pub mod proto;
+
+use self::proto::*;
+use super::config::ServiceConfig;
+use super::resolve::{Resolve, Update};
+use client::{self, Encodable};
+use codec::Streaming;
+use {Code, Request};
+
+use futures::{Future, Stream, Poll, Async};
+use futures::stream::MapErr;
+use futures::sync::mpsc;
+use http::uri::{PathAndQuery, Uri};
+use tower_h2::{Body, Data, HttpService};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+
+/// Stream of discovery requests sent to the management server.
+pub type Requests = MapErr<mpsc::UnboundedReceiver<DiscoveryRequest>, fn(()) -> ::Error>;
+
+/// Resolves a target using an xDS management server.
+pub struct Xds<S>
+where S: HttpService,
+{
+    /// Connection to the management server.
+    grpc: client::Grpc<S>,
+
+    node: Node,
+    listener: String,
+    tx: mpsc::UnboundedSender<DiscoveryRequest>,
+    state: State<S>,
+
+    /// Resources the resolver is currently subscribed to.
+    route_config: Option<String>,
+    cluster: Option<String>,
+    assignment: Option<String>,
+
+    /// Policy selected by the current cluster.
+    lb_policy: Option<String>,
+
+    /// The last version accepted of each resource type.
+    versions: HashMap<String, String>,
+}
+
+enum State<S>
+where S: HttpService,
+{
+    Connecting(client::streaming::ResponseFuture<DiscoveryResponse, S::Future>),
+    Streaming(Streaming<DiscoveryResponse, S::ResponseBody>),
+}
+
+/// Error produced by the xDS resolver.
+#[derive(Debug)]
+pub enum Error<E> {
+    /// The xDS target was invalid.
+    InvalidTarget,
+
+    /// The ADS stream failed.
+    Stream(::Error<E>),
+
+    /// The management server closed the ADS stream.
+    Closed,
+}
+
+// ===== impl Xds =====
+
+impl<S> Xds<S>
+where S: HttpService,
+      Requests: Encodable<S::RequestBody>,
+      S::ResponseBody: Body<Data = Data>,
+{
+    /// Resolve `target`, an `xds:///name` URI, using the management server
+    /// reachable through `service`.
+    ///
+    /// `node_id` identifies this client to the management server.
+    pub fn new(service: S, server: Uri, target: &Uri, node_id: &str)
+        -> Result<Self, Error<S::Error>>
+    {
+        let listener = listener_name(target)
+            .ok_or(Error::InvalidTarget)?;
+
+        let mut grpc = client::Builder::new()
+            .uri(server)
+            .build(service)
+            .map_err(|_| Error::InvalidTarget)?;
+
+        let (tx, rx) = mpsc::unbounded();
+        let rx: Requests = rx.map_err(never as fn(()) -> ::Error);
+
+        let path = PathAndQuery::from_static(ADS_PATH);
+        let response = grpc.streaming(Request::new(rx), path);
+
+        let mut xds = Xds {
+            grpc,
+            node: Node {
+                id: node_id.to_string(),
+                cluster: String::new(),
+                user_agent_name: "tower-grpc".to_string(),
+            },
+            listener: listener.clone(),
+            tx,
+            state: State::Connecting(response),
+            route_config: None,
+            cluster: None,
+            assignment: None,
+            lb_policy: None,
+            versions: HashMap::new(),
+        };
+
+        xds.subscribe(LISTENER_TYPE, listener, "", "");
+
+        Ok(xds)
+    }
+
+    /// Send a discovery request for a single resource.
+    ///
+    /// A request with the version and nonce of a response acknowledges it.
+    fn subscribe(&mut self, type_url: &str, name: String, version: &str, nonce: &str) {
+        trace!("xds subscribe; type={}; name={}", type_url, name);
+
+        let request = DiscoveryRequest {
+            version_info: version.to_string(),
+            node: Some(self.node.clone()),
+            resource_names: vec![name],
+            type_url: type_url.to_string(),
+            response_nonce: nonce.to_string(),
+            error_detail: None,
+        };
+
+        // If the receiver is gone, the stream has failed and the error is
+        // reported when it is next polled.
+        let _ = self.tx.unbounded_send(request);
+    }
+
+    /// Reject a response, keeping the last version accepted for its type.
+    fn nack(&mut self, response: &DiscoveryResponse, error: String) {
+        debug!("xds response rejected; type={}; version={}; error={}",
+               response.type_url, response.version_info, error);
+
+        let name = match &response.type_url[..] {
+            LISTENER_TYPE => Some(self.listener.clone()),
+            ROUTE_CONFIGURATION_TYPE => self.route_config.clone(),
+            CLUSTER_TYPE => self.cluster.clone(),
+            CLUSTER_LOAD_ASSIGNMENT_TYPE => self.assignment.clone(),
+            _ => None,
+        };
+
+        let request = DiscoveryRequest {
+            version_info: self.versions.get(&response.type_url).cloned().unwrap_or_default(),
+            node: Some(self.node.clone()),
+            resource_names: name.into_iter().collect(),
+            type_url: response.type_url.clone(),
+            response_nonce: response.nonce.clone(),
+            error_detail: Some(RpcStatus {
+                code: Code::INVALID_ARGUMENT.as_i32(),
+                message: error,
+            }),
+        };
+
+        let _ = self.tx.unbounded_send(request);
+    }
+
+    /// Process a discovery response, returning an update once endpoints are
+    /// known.
+    fn process(&mut self, response: DiscoveryResponse) -> Option<Update> {
+        if let Some(error) = invalid(&response) {
+            self.nack(&response, error);
+            return None;
+        }
+
+        let version = response.version_info.clone();
+        let nonce = response.nonce.clone();
+        let mut update = None;
+
+        self.versions.insert(response.type_url.clone(), version.clone());
+
+        match &response.type_url[..] {
+            LISTENER_TYPE => {
+                let listener = response.resources.iter()
+                    .filter_map(|r| unpack::<Listener>(r))
+                    .find(|l| l.name == self.listener);
+
+                let hcm = listener
+                    .and_then(|l| l.api_listener)
+                    .and_then(|a| a.api_listener)
+                    .and_then(|any| unpack::<HttpConnectionManager>(&any));
+
+                if let Some(hcm) = hcm {
+                    if let Some(config) = hcm.route_config {
+                        self.route(config);
+                    } else if let Some(rds) = hcm.rds {
+                        if self.route_config.as_ref() != Some(&rds.route_config_name) {
+                            self.route_config = Some(rds.route_config_name.clone());
+                            self.subscribe(ROUTE_CONFIGURATION_TYPE, rds.route_config_name, "", "");
+                        }
+                    }
+                }
+
+                let listener = self.listener.clone();
+                self.subscribe(LISTENER_TYPE, listener, &version, &nonce);
+            }
+            ROUTE_CONFIGURATION_TYPE => {
+                let name = self.route_config.clone().unwrap_or_default();
+                let config = response.resources.iter()
+                    .filter_map(|r| unpack::<RouteConfiguration>(r))
+                    .find(|c| c.name == name);
+
+                if let Some(config) = config {
+                    self.route(config);
+                }
+
+                self.subscribe(ROUTE_CONFIGURATION_TYPE, name, &version, &nonce);
+            }
+            CLUSTER_TYPE => {
+                let name = self.cluster.clone().unwrap_or_default();
+                let cluster = response.resources.iter()
+                    .filter_map(|r| unpack::<Cluster>(r))
+                    .find(|c| c.name == name);
+
+                if let Some(cluster) = cluster {
+                    self.lb_policy = match LbPolicy::from_i32(cluster.lb_policy) {
+                        Some(LbPolicy::RoundRobin) => Some("weighted_round_robin".to_string()),
+                        policy => {
+                            debug!("xds cluster policy not supported; policy={:?}", policy);
+                            None
+                        }
+                    };
+
+                    let assignment = cluster.eds_cluster_config
+                        .map(|c| c.service_name)
+                        .and_then(|n| if n.is_empty() { None } else { Some(n) })
+                        .unwrap_or(cluster.name);
+
+                    if self.assignment.as_ref() != Some(&assignment) {
+                        self.assignment = Some(assignment.clone());
+                        self.subscribe(CLUSTER_LOAD_ASSIGNMENT_TYPE, assignment, "", "");
+                    }
+                }
+
+                self.subscribe(CLUSTER_TYPE, name, &version, &nonce);
+            }
+            CLUSTER_LOAD_ASSIGNMENT_TYPE => {
+                let name = self.assignment.clone().unwrap_or_default();
+                let assignment = response.resources.iter()
+                    .filter_map(|r| unpack::<ClusterLoadAssignment>(r))
+                    .find(|a| a.cluster_name == name);
+
+                if let Some(assignment) = assignment {
+                    update = Some(self.endpoints(assignment));
+                }
+
+                self.subscribe(CLUSTER_LOAD_ASSIGNMENT_TYPE, name, &version, &nonce);
+            }
+            other => {
+                debug!("xds response of unexpected type; type={}", other);
+            }
+        }
+
+        update
+    }
+
+    /// Select the cluster from a route configuration.
+    fn route(&mut self, config: RouteConfiguration) {
+        let listener = self.listener.clone();
+
+        let vhost = config.virtual_hosts.iter()
+            .find(|v| v.domains.iter().any(|d| *d == listener))
+            .or_else(|| config.virtual_hosts.iter().find(|v| v.domains.iter().any(|d| d == "*")));
+
+        // The last route must be the default route, matching every path.
+        let cluster = vhost
+            .and_then(|v| v.routes.last())
+            .and_then(|r| r.route.as_ref())
+            .map(|a| a.cluster.clone());
+
+        match cluster {
+            Some(cluster) => {
+                if self.cluster.as_ref() != Some(&cluster) {
+                    self.cluster = Some(cluster.clone());
+                    self.subscribe(CLUSTER_TYPE, cluster, "", "");
+                }
+            }
+            None => debug!("xds route configuration has no route; name={}", config.name),
+        }
+    }
+
+    /// Convert an endpoint assignment into a resolver update.
+    fn endpoints(&self, assignment: ClusterLoadAssignment) -> Update {
+        let healthy = |e: &LbEndpoint| {
+            match HealthStatus::from_i32(e.health_status) {
+                Some(HealthStatus::Unknown) | Some(HealthStatus::Healthy) => true,
+                _ => false,
+            }
+        };
+
+        let priority = assignment.endpoints.iter()
+            .filter(|l| l.lb_endpoints.iter().any(&healthy))
+            .map(|l| l.priority)
+            .min();
+
+        let mut endpoints = vec![];
+
+        for locality in &assignment.endpoints {
+            if Some(locality.priority) != priority {
+                continue;
+            }
+
+            let locality_weight = locality.load_balancing_weight.as_ref()
+                .map(|w| w.value)
+                .unwrap_or(1);
+
+            for lb_endpoint in locality.lb_endpoints.iter().filter(|e| healthy(e)) {
+                let addr = lb_endpoint.endpoint.as_ref()
+                    .and_then(|e| e.address.as_ref())
+                    .and_then(|a| a.socket_address.as_ref())
+                    .and_then(|a| {
+                        let ip: IpAddr = a.address.parse().ok()?;
+                        Some(SocketAddr::new(ip, a.port_value as u16))
+                    });
+
+                let weight = lb_endpoint.load_balancing_weight.as_ref()
+                    .map(|w| w.value)
+                    .unwrap_or(1);
+
+                match addr {
+                    Some(addr) => {
+                        let endpoint = super::Endpoint::new(addr)
+                            .with_weight(weight * locality_weight);
+                        endpoints.push(endpoint);
+                    }
+                    None => debug!("xds endpoint without a socket address"),
+                }
+            }
+        }
+
+        debug!("xds endpoints updated; cluster={}; endpoints={}",
+               assignment.cluster_name, endpoints.len());
+
+        let config = ServiceConfig {
+            load_balancing_policy: self.lb_policy.clone(),
+            ..ServiceConfig::default()
+        };
+
+        Update::new(endpoints).with_service_config(config)
+    }
+}
+
+impl<S> Resolve for Xds<S>
+where S: HttpService,
+      S::Error: fmt::Debug,
+      Requests: Encodable<S::RequestBody>,
+      S::ResponseBody: Body<Data = Data>,
+{
+    type Error = Error<S::Error>;
+
+    fn poll(&mut self) -> Poll<Update, Self::Error> {
+        loop {
+            let connected = match self.state {
+                State::Connecting(ref mut fut) => {
+                    let response = try_ready!(fut.poll().map_err(Error::Stream));
+                    Some(response.into_inner())
+                }
+                State::Streaming(_) => None,
+            };
+
+            if let Some(stream) = connected {
+                debug!("xds stream established");
+                self.state = State::Streaming(stream);
+            }
+
+            let response = match self.state {
+                State::Streaming(ref mut stream) => {
+                    try_ready!(stream.poll().map_err(|e| {
+                        match e {
+                            ::Error::Grpc(status) => Error::Stream(::Error::Grpc(status)),
+                            ::Error::Inner(()) => Error::Closed,
+                        }
+                    }))
+                }
+                State::Connecting(_) => unreachable!(),
+            };
+
+            let response = match response {
+                Some(response) => response,
+                None => return Err(Error::Closed),
+            };
+
+            trace!("xds response; type={}; version={}",
+                   response.type_url, response.version_info);
+
+            if let Some(update) = self.process(response) {
+                return Ok(Async::Ready(update));
+            }
+        }
+    }
+}
+
+impl<S> fmt::Debug for Xds<S>
+where S: HttpService,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Xds")
+            .field("listener", &self.listener)
+            .field("route_config", &self.route_config)
+            .field("cluster", &self.cluster)
+            .field("assignment", &self.assignment)
+            .finish()
+    }
+}
+
+// ===== utility fns =====
+
+/// Returns the listener name of an `xds:///name` target.
+fn listener_name(target: &Uri) -> Option<String> {
+    if target.scheme_part().map(|s| s.as_str()) != Some("xds") {
+        return None;
+    }
+
+    let name = target.path().trim_start_matches('/');
+
+    if name.is_empty() {
+        None
+    } else {
+        Some(name.to_string())
+    }
+}
+
+/// Returns why the resources of `response` cannot be used, if they can't.
+fn invalid(response: &DiscoveryResponse) -> Option<String> {
+    for resource in &response.resources {
+        if resource.type_url != response.type_url {
+            return Some(format!("resource of type {} in response of type {}",
+                                resource.type_url, response.type_url));
+        }
+
+        let decoded = match &response.type_url[..] {
+            LISTENER_TYPE => unpack::<Listener>(resource).is_some(),
+            ROUTE_CONFIGURATION_TYPE => unpack::<RouteConfiguration>(resource).is_some(),
+            CLUSTER_TYPE => unpack::<Cluster>(resource).is_some(),
+            CLUSTER_LOAD_ASSIGNMENT_TYPE => unpack::<ClusterLoadAssignment>(resource).is_some(),
+            _ => true,
+        };
+
+        if !decoded {
+            return Some(format!("resource of type {} could not be decoded", response.type_url));
+        }
+    }
+
+    None
+}
+
+fn never(_: ()) -> ::Error {
+    unreachable!("unbounded receivers never fail")
+}
+
+#[cfg(all(test, feature = "in-process"))]
+mod tests {
+    use super::*;
+    use codec::{Decoder, Encoder};
+    use generic::FrameEncoder;
+    use inprocess::{self, Harness};
+    use Status;
+
+    use bytes::Bytes;
+    use futures::future::{self, FutureResult};
+    use h2;
+    use http::{self, header, HeaderMap};
+    use http::header::HeaderValue;
+    use prost::Message;
+    use tower::{NewService, Service};
+    use tower_h2::RecvBody;
+
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    /// A management server answering the ADS stream with the responses
+    /// given to `respond`, and recording the requests it receives.
+    #[derive(Clone)]
+    struct MockAds {
+        requests: Arc<Mutex<Vec<DiscoveryRequest>>>,
+        tx: mpsc::UnboundedSender<DiscoveryResponse>,
+        rx: Arc<Mutex<Option<mpsc::UnboundedReceiver<DiscoveryResponse>>>>,
+    }
+
+    /// The response body of the ADS stream, which also reads its requests.
+    struct AdsBody {
+        requests: Streaming<DiscoveryRequest, RecvBody>,
+        received: Arc<Mutex<Vec<DiscoveryRequest>>>,
+        responses: mpsc::UnboundedReceiver<DiscoveryResponse>,
+        frames: FrameEncoder<Encoder<DiscoveryResponse>>,
+    }
+
+    fn pack<M: Message>(type_url: &str, message: &M) -> Any {
+        let mut value = vec![];
+        message.encode(&mut value).unwrap();
+
+        Any {
+            type_url: type_url.to_string(),
+            value,
+        }
+    }
+
+    fn response(type_url: &str, version: &str, nonce: &str, resources: Vec<Any>) -> DiscoveryResponse {
+        DiscoveryResponse {
+            version_info: version.to_string(),
+            resources,
+            type_url: type_url.to_string(),
+            nonce: nonce.to_string(),
+        }
+    }
+
+    /// A listener whose inline route configuration sends everything to
+    /// `cluster`.
+    fn listener(version: &str, nonce: &str) -> DiscoveryResponse {
+        let route_config = RouteConfiguration {
+            name: "route".to_string(),
+            virtual_hosts: vec![VirtualHost {
+                name: "vhost".to_string(),
+                domains: vec!["*".to_string()],
+                routes: vec![Route {
+                    match_: Some(RouteMatch { prefix: String::new(), path: String::new() }),
+                    route: Some(RouteAction { cluster: "cluster".to_string() }),
+                }],
+            }],
+        };
+
+        let hcm = HttpConnectionManager {
+            rds: None,
+            route_config: Some(route_config),
+        };
+
+        let listener = Listener {
+            name: "listener".to_string(),
+            api_listener: Some(ApiListener {
+                api_listener: Some(pack(HTTP_CONNECTION_MANAGER_TYPE, &hcm)),
+            }),
+        };
+
+        response(LISTENER_TYPE, version, nonce, vec![pack(LISTENER_TYPE, &listener)])
+    }
+
+    /// A round robin cluster whose endpoints are assigned to `assignment`.
+    fn cluster(version: &str, nonce: &str) -> DiscoveryResponse {
+        let cluster = Cluster {
+            name: "cluster".to_string(),
+            eds_cluster_config: Some(EdsClusterConfig { service_name: "assignment".to_string() }),
+            lb_policy: LbPolicy::RoundRobin as i32,
+        };
+
+        response(CLUSTER_TYPE, version, nonce, vec![pack(CLUSTER_TYPE, &cluster)])
+    }
+
+    fn lb_endpoint(addr: &str, port: u32, health: HealthStatus, weight: Option<u32>) -> LbEndpoint {
+        LbEndpoint {
+            endpoint: Some(Endpoint {
+                address: Some(Address {
+                    socket_address: Some(SocketAddress {
+                        address: addr.to_string(),
+                        port_value: port,
+                    }),
+                }),
+            }),
+            health_status: health as i32,
+            load_balancing_weight: weight.map(|value| UInt32Value { value }),
+        }
+    }
+
+    /// Two localities at priority 0, of which one has a weight of 2, and a
+    /// failover locality at priority 1.
+    fn assignment(version: &str, nonce: &str) -> DiscoveryResponse {
+        let assignment = ClusterLoadAssignment {
+            cluster_name: "assignment".to_string(),
+            endpoints: vec![
+                LocalityLbEndpoints {
+                    lb_endpoints: vec![
+                        lb_endpoint("10.0.0.1", 80, HealthStatus::Healthy, Some(3)),
+                        lb_endpoint("10.0.0.2", 80, HealthStatus::Unhealthy, Some(3)),
+                    ],
+                    load_balancing_weight: Some(UInt32Value { value: 2 }),
+                    priority: 0,
+                },
+                LocalityLbEndpoints {
+                    lb_endpoints: vec![
+                        lb_endpoint("10.0.0.4", 81, HealthStatus::Unknown, None),
+                    ],
+                    load_balancing_weight: None,
+                    priority: 0,
+                },
+                LocalityLbEndpoints {
+                    lb_endpoints: vec![
+                        lb_endpoint("10.0.0.3", 80, HealthStatus::Healthy, None),
+                    ],
+                    load_balancing_weight: None,
+                    priority: 1,
+                },
+            ],
+        };
+
+        response(CLUSTER_LOAD_ASSIGNMENT_TYPE, version, nonce,
+                 vec![pack(CLUSTER_LOAD_ASSIGNMENT_TYPE, &assignment)])
+    }
+
+    /// Returns the type, resource names, version and nonce of `request`.
+    fn parts(request: &DiscoveryRequest) -> (&str, Vec<&str>, &str, &str) {
+        let names = request.resource_names.iter().map(|n| &n[..]).collect();
+        (&request.type_url[..], names, &request.version_info[..], &request.response_nonce[..])
+    }
+
+    fn resolve(ads: &MockAds, harness: &mut Harness) -> Update {
+        let conn = harness.connect(ads.clone()).unwrap();
+        let target = "xds:///listener".parse().unwrap();
+        let mut xds = Xds::new(conn, inprocess::uri(), &target, "node").unwrap();
+
+        let update = harness.run(future::poll_fn(|| xds.poll())).unwrap();
+
+        // Let the last acknowledgement reach the server.
+        harness.advance(Duration::from_secs(0));
+
+        update
+    }
+
+    // ===== impl MockAds =====
+
+    impl MockAds {
+        fn new() -> Self {
+            let (tx, rx) = mpsc::unbounded();
+
+            MockAds {
+                requests: Arc::new(Mutex::new(vec![])),
+                tx,
+                rx: Arc::new(Mutex::new(Some(rx))),
+            }
+        }
+
+        fn respond(&self, response: DiscoveryResponse) {
+            self.tx.unbounded_send(response).unwrap();
+        }
+
+        fn requests(&self) -> Vec<DiscoveryRequest> {
+            self.requests.lock().unwrap().clone()
+        }
+    }
+
+    impl Service for MockAds {
+        type Request = http::Request<RecvBody>;
+        type Response = http::Response<AdsBody>;
+        type Error = h2::Error;
+        type Future = FutureResult<Self::Response, h2::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), h2::Error> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, request: Self::Request) -> Self::Future {
+            assert_eq!(request.uri().path(), ADS_PATH);
+
+            let body = AdsBody {
+                requests: Streaming::new(Decoder::new(), request.into_body(), false),
+                received: self.requests.clone(),
+                responses: self.rx.lock().unwrap().take().expect("one ADS stream"),
+                frames: FrameEncoder::new(Encoder::new()),
+            };
+
+            let mut response = http::Response::new(body);
+            response.headers_mut()
+                .insert(header::CONTENT_TYPE, HeaderValue::from_static("application/grpc+proto"));
+
+            future::ok(response)
+        }
+    }
+
+    impl NewService for MockAds {
+        type Request = http::Request<RecvBody>;
+        type Response = http::Response<AdsBody>;
+        type Error = h2::Error;
+        type Service = Self;
+        type InitError = h2::Error;
+        type Future = FutureResult<Self, h2::Error>;
+
+        fn new_service(&self) -> Self::Future {
+            future::ok(self.clone())
+        }
+    }
+
+    // ===== impl AdsBody =====
+
+    impl Body for AdsBody {
+        type Data = Bytes;
+
+        fn is_end_stream(&self) -> bool {
+            false
+        }
+
+        fn poll_data(&mut self) -> Poll<Option<Bytes>, h2::Error> {
+            // The client keeps the stream open, so its requests are read
+            // whenever the body is polled.
+            while let Ok(Async::Ready(Some(request))) = self.requests.poll() {
+                self.received.lock().unwrap().push(request);
+            }
+
+            match self.responses.poll() {
+                Ok(Async::Ready(Some(response))) => {
+                    let frame = self.frames.encode(response)
+                        .expect("responses are framed");
+                    Ok(Async::Ready(Some(frame)))
+                }
+                Ok(Async::Ready(None)) | Err(()) => Ok(Async::Ready(None)),
+                Ok(Async::NotReady) => Ok(Async::NotReady),
+            }
+        }
+
+        fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, h2::Error> {
+            let mut trailers = HeaderMap::new();
+            trailers.insert("grpc-status", Status::OK.to_header_value());
+            Ok(Async::Ready(Some(trailers)))
+        }
+    }
+
+    #[test]
+    fn target_names_listener() {
+        let name = |target: &str| listener_name(&target.parse().unwrap());
+
+        assert_eq!(name("xds:///listener"), Some("listener".to_string()));
+        assert_eq!(name("xds:///"), None);
+        assert_eq!(name("dns:///listener"), None);
+    }
+
+    #[test]
+    fn resources_requested_and_acknowledged_in_turn() {
+        let mut harness = Harness::new().unwrap();
+        let ads = MockAds::new();
+
+        ads.respond(listener("1", "a"));
+        ads.respond(cluster("1", "b"));
+        ads.respond(assignment("1", "c"));
+        resolve(&ads, &mut harness);
+
+        let requests = ads.requests();
+        let parts: Vec<_> = requests.iter().map(parts).collect();
+        assert_eq!(parts, vec![
+            (LISTENER_TYPE, vec!["listener"], "", ""),
+            (CLUSTER_TYPE, vec!["cluster"], "", ""),
+            (LISTENER_TYPE, vec!["listener"], "1", "a"),
+            (CLUSTER_LOAD_ASSIGNMENT_TYPE, vec!["assignment"], "", ""),
+            (CLUSTER_TYPE, vec!["cluster"], "1", "b"),
+            (CLUSTER_LOAD_ASSIGNMENT_TYPE, vec!["assignment"], "1", "c"),
+        ]);
+
+        for request in &requests {
+            assert_eq!(request.node.as_ref().map(|n| &n.id[..]), Some("node"));
+            assert!(request.error_detail.is_none());
+        }
+    }
+
+    #[test]
+    fn undecodable_response_rejected_with_last_accepted_version() {
+        let mut harness = Harness::new().unwrap();
+        let ads = MockAds::new();
+
+        let garbage = Any {
+            type_url: LISTENER_TYPE.to_string(),
+            value: vec![0xff, 0xff],
+        };
+
+        ads.respond(listener("1", "a"));
+        ads.respond(response(LISTENER_TYPE, "2", "b", vec![garbage]));
+        ads.respond(cluster("1", "c"));
+        ads.respond(assignment("1", "d"));
+        resolve(&ads, &mut harness);
+
+        let requests = ads.requests();
+        assert_eq!(parts(&requests[2]), (LISTENER_TYPE, vec!["listener"], "1", "a"));
+
+        // The rejected version is not acknowledged, but its nonce is.
+        let nack = &requests[3];
+        assert_eq!(parts(nack), (LISTENER_TYPE, vec!["listener"], "1", "b"));
+        assert_eq!(nack.error_detail.as_ref().map(|e| e.code), Some(Code::INVALID_ARGUMENT.as_i32()));
+
+        assert_eq!(requests.iter().filter(|r| r.error_detail.is_some()).count(), 1);
+    }
+
+    #[test]
+    fn mismatched_resource_type_rejected() {
+        let mut harness = Harness::new().unwrap();
+        let ads = MockAds::new();
+
+        let mut wrong = cluster("1", "a");
+        wrong.type_url = LISTENER_TYPE.to_string();
+
+        ads.respond(wrong);
+        ads.respond(listener("1", "b"));
+        ads.respond(cluster("1", "c"));
+        ads.respond(assignment("1", "d"));
+        resolve(&ads, &mut harness);
+
+        let requests = ads.requests();
+        let nack = &requests[1];
+        assert_eq!(parts(nack), (LISTENER_TYPE, vec!["listener"], "", "a"));
+        assert!(nack.error_detail.is_some());
+    }
+
+    #[test]
+    fn load_assignment_translated_into_endpoints() {
+        let mut harness = Harness::new().unwrap();
+        let ads = MockAds::new();
+
+        ads.respond(listener("1", "a"));
+        ads.respond(cluster("1", "b"));
+        ads.respond(assignment("1", "c"));
+        let update = resolve(&ads, &mut harness);
+
+        // Only healthy endpoints of the highest priority are used, weighted
+        // by their own and their locality's weight.
+        let endpoints: Vec<_> = update.endpoints().iter()
+            .map(|e| (*e.addr(), e.weight()))
+            .collect();
+        assert_eq!(endpoints, vec![
+            ("10.0.0.1:80".parse::<SocketAddr>().unwrap(), 6),
+            ("10.0.0.4:81".parse::<SocketAddr>().unwrap(), 1),
+        ]);
+
+        let config = update.service_config().unwrap();
+        assert_eq!(config.load_balancing_policy, Some("weighted_round_robin".to_string()));
+    }
+}
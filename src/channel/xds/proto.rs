@@ -0,0 +1,249 @@
+//! The subset of the Envoy v3 xDS API used by the resolver.
+//!
+//! Only the fields the resolver reads are declared; protobuf decoding skips
+//! the rest.
+
+#![allow(missing_docs)]
+
+use prost::Message;
+
+pub const LISTENER_TYPE: &'static str =
+    "type.googleapis.com/envoy.config.listener.v3.Listener";
+pub const ROUTE_CONFIGURATION_TYPE: &'static str =
+    "type.googleapis.com/envoy.config.route.v3.RouteConfiguration";
+pub const CLUSTER_TYPE: &'static str =
+    "type.googleapis.com/envoy.config.cluster.v3.Cluster";
+pub const CLUSTER_LOAD_ASSIGNMENT_TYPE: &'static str =
+    "type.googleapis.com/envoy.config.endpoint.v3.ClusterLoadAssignment";
+pub const HTTP_CONNECTION_MANAGER_TYPE: &'static str =
+    "type.googleapis.com/envoy.extensions.filters.network.http_connection_manager.v3.HttpConnectionManager";
+
+pub const ADS_PATH: &'static str =
+    "/envoy.service.discovery.v3.AggregatedDiscoveryService/StreamAggregatedResources";
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Any {
+    #[prost(string, tag="1")]
+    pub type_url: String,
+    #[prost(bytes, tag="2")]
+    pub value: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct UInt32Value {
+    #[prost(uint32, tag="1")]
+    pub value: u32,
+}
+
+/// A `google.rpc.Status`.
+#[derive(Clone, PartialEq, Message)]
+pub struct RpcStatus {
+    #[prost(int32, tag="1")]
+    pub code: i32,
+    #[prost(string, tag="2")]
+    pub message: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Node {
+    #[prost(string, tag="1")]
+    pub id: String,
+    #[prost(string, tag="2")]
+    pub cluster: String,
+    #[prost(string, tag="6")]
+    pub user_agent_name: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct DiscoveryRequest {
+    #[prost(string, tag="1")]
+    pub version_info: String,
+    #[prost(message, optional, tag="2")]
+    pub node: Option<Node>,
+    #[prost(string, repeated, tag="3")]
+    pub resource_names: Vec<String>,
+    #[prost(string, tag="4")]
+    pub type_url: String,
+    #[prost(string, tag="5")]
+    pub response_nonce: String,
+    #[prost(message, optional, tag="6")]
+    pub error_detail: Option<RpcStatus>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct DiscoveryResponse {
+    #[prost(string, tag="1")]
+    pub version_info: String,
+    #[prost(message, repeated, tag="2")]
+    pub resources: Vec<Any>,
+    #[prost(string, tag="4")]
+    pub type_url: String,
+    #[prost(string, tag="5")]
+    pub nonce: String,
+}
+
+// ===== LDS =====
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Listener {
+    #[prost(string, tag="1")]
+    pub name: String,
+    #[prost(message, optional, tag="19")]
+    pub api_listener: Option<ApiListener>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ApiListener {
+    #[prost(message, optional, tag="1")]
+    pub api_listener: Option<Any>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct HttpConnectionManager {
+    #[prost(message, optional, tag="3")]
+    pub rds: Option<Rds>,
+    #[prost(message, optional, tag="4")]
+    pub route_config: Option<RouteConfiguration>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Rds {
+    #[prost(string, tag="2")]
+    pub route_config_name: String,
+}
+
+// ===== RDS =====
+
+#[derive(Clone, PartialEq, Message)]
+pub struct RouteConfiguration {
+    #[prost(string, tag="1")]
+    pub name: String,
+    #[prost(message, repeated, tag="2")]
+    pub virtual_hosts: Vec<VirtualHost>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct VirtualHost {
+    #[prost(string, tag="1")]
+    pub name: String,
+    #[prost(string, repeated, tag="2")]
+    pub domains: Vec<String>,
+    #[prost(message, repeated, tag="3")]
+    pub routes: Vec<Route>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Route {
+    #[prost(message, optional, tag="1")]
+    pub match_: Option<RouteMatch>,
+    #[prost(message, optional, tag="2")]
+    pub route: Option<RouteAction>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct RouteMatch {
+    #[prost(string, tag="1")]
+    pub prefix: String,
+    #[prost(string, tag="2")]
+    pub path: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct RouteAction {
+    #[prost(string, tag="1")]
+    pub cluster: String,
+}
+
+// ===== CDS =====
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Cluster {
+    #[prost(string, tag="1")]
+    pub name: String,
+    #[prost(message, optional, tag="3")]
+    pub eds_cluster_config: Option<EdsClusterConfig>,
+    #[prost(enumeration="LbPolicy", tag="6")]
+    pub lb_policy: i32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct EdsClusterConfig {
+    #[prost(string, tag="2")]
+    pub service_name: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Enumeration)]
+pub enum LbPolicy {
+    RoundRobin = 0,
+    LeastRequest = 1,
+    RingHash = 2,
+    Random = 3,
+    Maglev = 5,
+}
+
+// ===== EDS =====
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ClusterLoadAssignment {
+    #[prost(string, tag="1")]
+    pub cluster_name: String,
+    #[prost(message, repeated, tag="2")]
+    pub endpoints: Vec<LocalityLbEndpoints>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct LocalityLbEndpoints {
+    #[prost(message, repeated, tag="2")]
+    pub lb_endpoints: Vec<LbEndpoint>,
+    #[prost(message, optional, tag="3")]
+    pub load_balancing_weight: Option<UInt32Value>,
+    #[prost(uint32, tag="5")]
+    pub priority: u32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct LbEndpoint {
+    #[prost(message, optional, tag="1")]
+    pub endpoint: Option<Endpoint>,
+    #[prost(enumeration="HealthStatus", tag="2")]
+    pub health_status: i32,
+    #[prost(message, optional, tag="4")]
+    pub load_balancing_weight: Option<UInt32Value>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Endpoint {
+    #[prost(message, optional, tag="1")]
+    pub address: Option<Address>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Address {
+    #[prost(message, optional, tag="1")]
+    pub socket_address: Option<SocketAddress>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct SocketAddress {
+    #[prost(string, tag="2")]
+    pub address: String,
+    #[prost(uint32, tag="3")]
+    pub port_value: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Enumeration)]
+pub enum HealthStatus {
+    Unknown = 0,
+    Healthy = 1,
+    Unhealthy = 2,
+    Draining = 3,
+    Timeout = 4,
+    Degraded = 5,
+}
+
+/// Decode a resource packed in an `Any`.
+pub fn unpack<T>(any: &Any) -> Option<T>
+where T: Message + Default,
+{
+    T::decode(&any.value[..]).ok()
+}
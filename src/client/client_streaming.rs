@@ -1,10 +1,11 @@
 use super::streaming;
 use codec::Streaming;
 
+use bytes::Bytes;
 use futures::{Future, Stream, Poll};
 use http::{response, Response};
 use prost::Message;
-use tower_h2::{Body, Data};
+use tower_h2::Body;
 
 #[derive(Debug)]
 pub struct ResponseFuture<T, U, B> {
@@ -16,6 +17,7 @@ enum State<T, U, B> {
     WaitResponse(streaming::ResponseFuture<T, U>),
     WaitMessage {
         head: Option<response::Parts>,
+        message: Option<T>,
         stream: Streaming<T, B>,
     },
 }
@@ -31,7 +33,8 @@ impl<T, U, B> ResponseFuture<T, U, B> {
 impl<T, U, B> Future for ResponseFuture<T, U, B>
 where T: Message + Default,
       U: Future<Item = Response<B>>,
-      B: Body<Data = Data>,
+      B: Body,
+      B::Data: Into<Bytes>,
 {
     type Item = ::Response<T>;
     type Error = ::Error<U::Error>;
@@ -44,16 +47,29 @@ where T: Message + Default,
                 WaitResponse(ref mut inner) => {
                     try_ready!(inner.poll())
                 }
-                WaitMessage { ref mut head, ref mut stream } => {
-                    let res = stream.poll()
-                        .map_err(|e| {
-                            match e {
-                                ::Error::Grpc(s) => ::Error::Grpc(s),
-                                _ => ::Error::Grpc(::Status::INTERNAL),
+                WaitMessage { ref mut head, ref mut message, ref mut stream } => {
+                    // Read the stream to its end, so the call ends with the
+                    // status in the trailers.
+                    loop {
+                        let res = stream.poll()
+                            .map_err(|e| {
+                                match e {
+                                    ::Error::Grpc(s) => ::Error::Grpc(s),
+                                    _ => ::Error::Grpc(::Status::INTERNAL),
+                                }
+                            });
+
+                        match try_ready!(res) {
+                            Some(next) => {
+                                if message.is_none() {
+                                    *message = Some(next);
+                                }
                             }
-                        });
+                            None => break,
+                        }
+                    }
 
-                    let message = match try_ready!(res) {
+                    let message = match message.take() {
                         Some(message) => message,
                         // TODO: handle missing message
                         None => unimplemented!(),
@@ -72,6 +88,7 @@ where T: Message + Default,
 
             self.state = WaitMessage {
                 head: Some(head),
+                message: None,
                 stream: body,
             };
         }
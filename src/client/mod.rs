@@ -3,7 +3,14 @@ pub mod client_streaming;
 pub mod server_streaming;
 pub mod streaming;
 
+// Shared with servers; re-exported for the clients that use it here.
+pub use sender;
+
 use Status;
+use headers;
+use limit::SendLimit;
+use sender::Sender;
+use stats::{CallStats, Handler, Side, StatsHandler};
 
 use futures::{stream, Stream, Poll};
 use http::{uri, HeaderMap, Uri};
@@ -20,12 +27,18 @@ pub struct Grpc<T> {
 
     /// The service's authority.
     authority: uri::Authority,
+
+    /// The handler calls are reported to, if any.
+    stats: Option<Handler>,
 }
 
 #[derive(Debug, Default)]
 pub struct Builder {
     /// The service's URI
     uri: Option<uri::Uri>,
+
+    /// The handler calls are reported to, if any.
+    stats: Option<Handler>,
 }
 
 #[derive(Debug)]
@@ -33,11 +46,60 @@ pub struct BuilderError {
     _p: (),
 }
 
+/// A client generated from a service definition.
+///
+/// Generated clients implement this with their `new` function, so helpers
+/// such as `testing::loopback` can build any of them.
+pub trait GeneratedClient<T>: Sized {
+    /// Build a client sending calls to the service at `uri` through `inner`.
+    fn from_service(inner: T, uri: Uri) -> Result<Self, BuilderError>;
+}
+
+/// Request extension describing whether a method may safely be called more
+/// than once.
+///
+/// Generated clients set this from the method's `idempotency_level` option.
+/// Channels use it to decide which calls are retried without a retry policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Idempotency {
+    /// The method may have side effects.
+    Unknown,
+
+    /// The method has no side effects, such as a read.
+    NoSideEffects,
+
+    /// Calling the method more than once has the same effect as calling it
+    /// once.
+    Idempotent,
+}
+
 /// Convert a stream of protobuf messages to an HTTP body payload.
 ///
 /// TODO: Rename to `IntoEncode` or something...
 pub trait Encodable<T> {
     fn into_encode(self) -> T;
+
+    /// Like `into_encode`, but failing the request on a message larger than
+    /// `limit` allows.
+    ///
+    /// By default, the limit is ignored.
+    fn into_encode_limited(self, limit: SendLimit) -> T
+    where Self: Sized,
+    {
+        let _ = limit;
+        self.into_encode()
+    }
+
+    /// Like `into_encode_limited`, but also reporting each message sent to
+    /// `stats`.
+    ///
+    /// By default, messages are not reported.
+    fn into_encode_observed(self, limit: SendLimit, stats: Option<CallStats>) -> T
+    where Self: Sized,
+    {
+        let _ = stats;
+        self.into_encode_limited(limit)
+    }
 }
 
 // ===== impl Grpc =====
@@ -45,6 +107,16 @@ pub trait Encodable<T> {
 impl<T> Grpc<T>
 where T: HttpService,
 {
+    /// Get a reference to the inner HTTP/2.0 service.
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the inner HTTP/2.0 service.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
     pub fn poll_ready(&mut self) -> Poll<(), ::Error<T::Error>> {
         self.inner.poll_ready()
             .map_err(::Error::Inner)
@@ -72,6 +144,25 @@ where T: HttpService,
         client_streaming::ResponseFuture::new(response)
     }
 
+    /// Initiate a client-streaming gRPC request whose messages are sent with
+    /// the returned `Sender`.
+    ///
+    /// The request's metadata is taken from `request`. The request stream
+    /// ends once the `Sender` and its clones are dropped. See
+    /// `sender::channel` for the meaning of `buffer`.
+    pub fn client_streaming_sender<M1, M2>(&mut self,
+                                           request: ::Request<()>,
+                                           path: uri::PathAndQuery,
+                                           buffer: usize)
+        -> (Sender<M1>, client_streaming::ResponseFuture<M2, T::Future, T::ResponseBody>)
+    where sender::Receiver<M1>: Encodable<T::RequestBody>,
+    {
+        let (tx, rx) = sender::channel(buffer);
+        let response = self.client_streaming(request.map(|()| rx), path);
+
+        (tx, response)
+    }
+
     pub fn server_streaming<M1, M2>(&mut self,
                                     request: ::Request<M1>,
                                     path: uri::PathAndQuery)
@@ -84,6 +175,26 @@ where T: HttpService,
         server_streaming::ResponseFuture::new(response)
     }
 
+    /// Initiate a full streaming gRPC request, split into the `Sender` of
+    /// the request messages and the future of the response stream.
+    ///
+    /// The halves are independent, so sending and receiving can be driven
+    /// by separate tasks. The request's metadata is taken from `request`,
+    /// and the request stream ends once the `Sender` and its clones are
+    /// dropped. See `sender::channel` for the meaning of `buffer`.
+    pub fn streaming_sender<M1, M2>(&mut self,
+                                    request: ::Request<()>,
+                                    path: uri::PathAndQuery,
+                                    buffer: usize)
+        -> (Sender<M1>, streaming::ResponseFuture<M2, T::Future>)
+    where sender::Receiver<M1>: Encodable<T::RequestBody>,
+    {
+        let (tx, rx) = sender::channel(buffer);
+        let response = self.streaming(request.map(|()| rx), path);
+
+        (tx, response)
+    }
+
     /// Initiate a full streaming gRPC request
     ///
     /// # Generics
@@ -96,10 +207,14 @@ where T: HttpService,
         -> streaming::ResponseFuture<M, T::Future>
     where B: Encodable<T::RequestBody>,
     {
-        use http::header::{self, HeaderValue};
+        use http::header;
 
         // TODO: validate the path
 
+        // Start reporting the call, if the client is instrumented
+        let stats = self.stats.as_ref()
+            .map(|handler| CallStats::new(handler, Side::Client, path.path()));
+
         // Get the gRPC's method URI
         let mut parts = uri::Parts::default();
         parts.scheme = Some(self.scheme.clone());
@@ -112,27 +227,35 @@ where T: HttpService,
             Err(_) => unimplemented!(),
         };
 
-        // Convert the request body
-        let request = request.map(Encodable::into_encode);
+        // Convert the request body, whose messages a channel may limit
+        let limit = SendLimit::new();
+        let request = request.map(|body| body.into_encode_observed(limit.clone(), stats.clone()));
 
         // Convert to an HTTP request
         let mut request = request.into_http(uri);
+        request.extensions_mut().insert(limit.clone());
 
         // Add the gRPC related HTTP headers
+        request.headers_mut().reserve(2);
         request.headers_mut()
-            .insert(header::TE, HeaderValue::from_static("trailers"));
+            .insert(header::TE, headers::TRAILERS.clone());
 
         // Set the content type
         // TODO: Don't hard code this here
-        let content_type = "application/grpc+proto";
         request.headers_mut().insert(
             header::CONTENT_TYPE,
-            HeaderValue::from_static(content_type));
+            headers::APPLICATION_GRPC_PROTO.clone());
+
+        if let Some(ref stats) = stats {
+            stats.headers_sent(request.headers());
+        }
 
         // Call the inner HTTP service
         let response = self.inner.call(request);
 
         streaming::ResponseFuture::new(response)
+            .send_limit(limit)
+            .stats(stats)
     }
 }
 
@@ -152,6 +275,14 @@ impl Builder {
         self
     }
 
+    /// Report the calls of the client to `handler`.
+    pub fn stats_handler<H>(&mut self, handler: H) -> &mut Self
+    where H: StatsHandler + 'static,
+    {
+        self.stats = Some(Handler::new(handler));
+        self
+    }
+
     pub fn build<T>(&mut self, inner: T) -> Result<Grpc<T>, BuilderError>
     where T: HttpService,
     {
@@ -171,10 +302,30 @@ impl Builder {
             inner,
             scheme,
             authority,
+            stats: self.stats.clone(),
         })
     }
 }
 
+// ===== impl Idempotency =====
+
+impl Idempotency {
+    /// Returns the idempotency of a protobuf `IdempotencyLevel` value.
+    pub fn from_i32(level: i32) -> Self {
+        match level {
+            1 => Idempotency::NoSideEffects,
+            2 => Idempotency::Idempotent,
+            _ => Idempotency::Unknown,
+        }
+    }
+}
+
+impl Default for Idempotency {
+    fn default() -> Self {
+        Idempotency::Unknown
+    }
+}
+
 // ===== impl BuilderError =====
 
 impl BuilderError {
@@ -196,12 +347,26 @@ where T: Stream<Item = U, Error = ::Error> + Send + 'static,
         let encode = Encode::new(Encoder::new(), self, false);
         BoxBody::new(Box::new(encode))
     }
+
+    fn into_encode_limited(self, limit: SendLimit) -> BoxBody {
+        self.into_encode_observed(limit, None)
+    }
+
+    fn into_encode_observed(self, limit: SendLimit, stats: Option<CallStats>) -> BoxBody {
+        use codec::Encoder;
+        use generic::Encode;
+
+        let encode = Encode::new(Encoder::new(), self, false)
+            .send_limit(limit)
+            .stats(stats);
+        BoxBody::new(Box::new(encode))
+    }
 }
 
 // ===== utility fns =====
 
 fn check_grpc_status(trailers: &HeaderMap) -> Option<Status> {
-    trailers.get("grpc-status").map(|s| {
+    trailers.get(&*headers::GRPC_STATUS).map(|s| {
         Status::from_bytes(s.as_ref())
     })
 }
@@ -1,10 +1,11 @@
 use super::streaming;
 use codec::Streaming;
 
+use bytes::Bytes;
 use futures::{Future, Poll};
 use http::Response;
 use prost::Message;
-use tower_h2::{Body, Data};
+use tower_h2::Body;
 
 #[derive(Debug)]
 pub struct ResponseFuture<T, U> {
@@ -21,7 +22,8 @@ impl<T, U> ResponseFuture<T, U> {
 impl<T, U, B> Future for ResponseFuture<T, U>
 where T: Message + Default,
       U: Future<Item = Response<B>>,
-      B: Body<Data = Data>,
+      B: Body,
+      B::Data: Into<Bytes>,
 {
     type Item = ::Response<Streaming<T, B>>;
     type Error = ::Error<U::Error>;
@@ -1,15 +1,22 @@
+use Code;
 use codec::Streaming;
+use limit::{ReceiveLimit, SendLimit};
+use stats::CallStats;
+use strictness::Strictness;
 
-use futures::{Future, Poll};
+use bytes::Bytes;
+use futures::{Future, Poll, Async};
 use http::Response;
 use prost::Message;
-use tower_h2::{Body, Data};
+use tower_h2::Body;
 
 use std::marker::PhantomData;
 
 #[derive(Debug)]
 pub struct ResponseFuture<T, U> {
     inner: U,
+    send_limit: Option<SendLimit>,
+    stats: Option<CallStats>,
     _m: PhantomData<T>,
 }
 
@@ -18,15 +25,31 @@ impl<T, U> ResponseFuture<T, U> {
     pub(crate) fn new(inner: U) -> Self {
         ResponseFuture {
             inner,
+            send_limit: None,
+            stats: None,
             _m: PhantomData,
         }
     }
+
+    /// Fail with the status encoding the request failed with, if any,
+    /// rather than with the error of the reset stream.
+    pub(crate) fn send_limit(mut self, limit: SendLimit) -> Self {
+        self.send_limit = Some(limit);
+        self
+    }
+
+    /// Report the response to `stats`.
+    pub(crate) fn stats(mut self, stats: Option<CallStats>) -> Self {
+        self.stats = stats;
+        self
+    }
 }
 
 impl<T, U, B> Future for ResponseFuture<T, U>
 where T: Message + Default,
       U: Future<Item = Response<B>>,
-      B: Body<Data = Data>,
+      B: Body,
+      B::Data: Into<Bytes>,
 {
     type Item = ::Response<Streaming<T, B>>;
     type Error = ::Error<U::Error>;
@@ -35,20 +58,46 @@ where T: Message + Default,
         use codec::Decoder;
         use generic::Streaming;
 
-        let response = self.inner.poll()
-            .map_err(::Error::Inner);
-
         // Get the response
-        let response = try_ready!(response);
+        let response = match self.inner.poll() {
+            Ok(Async::Ready(response)) => response,
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Err(e) => {
+                let failure = self.send_limit.as_ref()
+                    .and_then(SendLimit::failure);
+
+                if let Some(ref stats) = self.stats {
+                    stats.end(failure.as_ref().map_or(Code::UNKNOWN, |status| status.code()));
+                }
+
+                return match failure {
+                    Some(status) => Err(::Error::Grpc(status)),
+                    None => Err(::Error::Inner(e)),
+                };
+            }
+        };
 
         // Destructure into the head / body
         let (head, body) = response.into_parts();
 
+        if let Some(ref stats) = self.stats {
+            stats.headers_received(&head.headers);
+        }
+
         if let Some(status) = super::check_grpc_status(&head.headers) {
+            if let Some(ref stats) = self.stats {
+                stats.end(status.code());
+            }
+
             return Err(::Error::Grpc(status));
         }
 
-        let body = Streaming::new(Decoder::new(), body, true);
+        let strictness = head.extensions.get::<Strictness>().cloned();
+        let max_message_size = head.extensions.get::<ReceiveLimit>().map(|limit| limit.0);
+        let body = Streaming::new(Decoder::new(), body, true)
+            .strictness(strictness)
+            .max_message_size(max_message_size)
+            .stats(self.stats.take());
         let response = Response::from_parts(head, body);
 
         Ok(::Response::from_http(response).into())
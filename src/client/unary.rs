@@ -1,9 +1,10 @@
 use super::client_streaming;
 
+use bytes::Bytes;
 use futures::{stream, Future, Poll};
 use http::{Response};
 use prost::Message;
-use tower_h2::{Body, Data};
+use tower_h2::Body;
 
 #[derive(Debug)]
 pub struct ResponseFuture<T, U, B> {
@@ -22,7 +23,8 @@ impl<T, U, B> ResponseFuture<T, U, B> {
 impl<T, U, B> Future for ResponseFuture<T, U, B>
 where T: Message + Default,
       U: Future<Item = Response<B>>,
-      B: Body<Data = Data>,
+      B: Body,
+      B::Data: Into<Bytes>,
 {
     type Item = ::Response<T>;
     type Error = ::Error<U::Error>;
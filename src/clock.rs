@@ -0,0 +1,251 @@
+//! Clocks measuring deadlines, keepalives and backoffs.
+//!
+//! Everything in this crate that waits takes a `Clock`, which is either a
+//! `tokio_timer::Timer` or a `MockClock`. The builders that accept a clock
+//! also accept a `Timer`, so code written against timers keeps working.
+//!
+//! A `MockClock` only moves when it is advanced, so deadline, keepalive and
+//! backoff logic can be tested deterministically without real sleeps:
+//!
+//! ```ignore
+//! let clock = MockClock::new();
+//! let channel = Channel::new(resolver, connect, PickFirst::new())
+//!     .timer(clock.clone());
+//!
+//! // ... start a call with a one second deadline ...
+//!
+//! clock.advance(Duration::from_secs(1));
+//! ```
+//!
+//! `inprocess::Harness` combines a mock clock with the in-process
+//! transport.
+
+use futures::{Future, Poll, Async};
+use futures::task::{self, Task};
+use tokio_timer::{self, Timer, TimerError};
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Measures time, either for real or with a `MockClock`.
+#[derive(Clone)]
+pub struct Clock {
+    kind: Kind,
+}
+
+/// A clock that only moves when advanced.
+#[derive(Clone)]
+pub struct MockClock {
+    shared: Arc<Mutex<Shared>>,
+}
+
+/// A future completing once a clock reaches a point in time.
+pub struct Sleep {
+    kind: SleepKind,
+}
+
+#[derive(Clone)]
+enum Kind {
+    Real(Timer),
+    Mock(MockClock),
+}
+
+enum SleepKind {
+    Real(tokio_timer::Sleep),
+    Mock(MockClock, Instant),
+}
+
+struct Shared {
+    now: Instant,
+
+    /// The tasks waiting for the clock to reach a point in time.
+    sleepers: Vec<(Instant, Task)>,
+}
+
+// ===== impl Clock =====
+
+impl Clock {
+    /// Returns the current time.
+    pub fn now(&self) -> Instant {
+        match self.kind {
+            Kind::Real(_) => Instant::now(),
+            Kind::Mock(ref clock) => clock.now(),
+        }
+    }
+
+    /// Returns a future completing after `duration`.
+    pub fn sleep(&self, duration: Duration) -> Sleep {
+        let kind = match self.kind {
+            Kind::Real(ref timer) => SleepKind::Real(timer.sleep(duration)),
+            Kind::Mock(ref clock) => SleepKind::Mock(clock.clone(), clock.now() + duration),
+        };
+
+        Sleep { kind }
+    }
+
+    /// Returns true if this is a `MockClock`.
+    pub fn is_mock(&self) -> bool {
+        match self.kind {
+            Kind::Real(_) => false,
+            Kind::Mock(_) => true,
+        }
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Clock::from(Timer::default())
+    }
+}
+
+impl From<Timer> for Clock {
+    fn from(timer: Timer) -> Self {
+        Clock { kind: Kind::Real(timer) }
+    }
+}
+
+impl<'a> From<&'a Timer> for Clock {
+    fn from(timer: &'a Timer) -> Self {
+        Clock::from(timer.clone())
+    }
+}
+
+impl From<MockClock> for Clock {
+    fn from(clock: MockClock) -> Self {
+        Clock { kind: Kind::Mock(clock) }
+    }
+}
+
+impl<'a> From<&'a MockClock> for Clock {
+    fn from(clock: &'a MockClock) -> Self {
+        Clock::from(clock.clone())
+    }
+}
+
+impl<'a> From<&'a Clock> for Clock {
+    fn from(clock: &'a Clock) -> Self {
+        clock.clone()
+    }
+}
+
+impl fmt::Debug for Clock {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            Kind::Real(_) => fmt.debug_tuple("Clock").field(&"Timer").finish(),
+            Kind::Mock(ref clock) => fmt.debug_tuple("Clock").field(clock).finish(),
+        }
+    }
+}
+
+// ===== impl MockClock =====
+
+impl MockClock {
+    /// A clock starting at the current time.
+    pub fn new() -> Self {
+        MockClock {
+            shared: Arc::new(Mutex::new(Shared {
+                now: Instant::now(),
+                sleepers: vec![],
+            })),
+        }
+    }
+
+    /// Returns the clock's time.
+    pub fn now(&self) -> Instant {
+        self.shared().now
+    }
+
+    /// Move the clock forward by `duration`, waking the sleeps that are
+    /// then complete.
+    pub fn advance(&self, duration: Duration) {
+        let woken = {
+            let mut shared = self.shared();
+            shared.now += duration;
+
+            let now = shared.now;
+            let (woken, sleeping) = shared.sleepers.drain(..)
+                .partition::<Vec<_>, _>(|&(until, _)| until <= now);
+            shared.sleepers = sleeping;
+
+            woken
+        };
+
+        // Tasks are woken outside the lock, as they may poll the clock.
+        for (_, task) in woken {
+            task.notify();
+        }
+    }
+
+    /// Returns the time until the earliest incomplete sleep, if any.
+    ///
+    /// Advancing by this duration completes the next sleep.
+    pub fn next_sleep(&self) -> Option<Duration> {
+        let shared = self.shared();
+
+        shared.sleepers.iter()
+            .map(|&(until, _)| until - shared.now)
+            .min()
+    }
+
+    fn shared(&self) -> ::std::sync::MutexGuard<Shared> {
+        self.shared.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        MockClock::new()
+    }
+}
+
+impl fmt::Debug for MockClock {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let shared = self.shared();
+
+        fmt.debug_struct("MockClock")
+            .field("now", &shared.now)
+            .field("sleepers", &shared.sleepers.len())
+            .finish()
+    }
+}
+
+// ===== impl Sleep =====
+
+impl Future for Sleep {
+    type Item = ();
+    type Error = TimerError;
+
+    fn poll(&mut self) -> Poll<(), TimerError> {
+        match self.kind {
+            SleepKind::Real(ref mut sleep) => sleep.poll(),
+            SleepKind::Mock(ref clock, until) => {
+                let mut shared = clock.shared();
+
+                if shared.now >= until {
+                    return Ok(Async::Ready(()));
+                }
+
+                let registered = shared.sleepers.iter()
+                    .any(|&(at, ref task)| at == until && task.will_notify_current());
+
+                if !registered {
+                    shared.sleepers.push((until, task::current()));
+                }
+
+                Ok(Async::NotReady)
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Sleep {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            SleepKind::Real(_) => fmt.debug_struct("clock::Sleep").finish(),
+            SleepKind::Mock(_, until) => fmt.debug_struct("clock::Sleep")
+                .field("until", &until)
+                .finish(),
+        }
+    }
+}
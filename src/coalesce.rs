@@ -0,0 +1,227 @@
+//! Coalescing of small response messages into fewer DATA frames.
+//!
+//! Each message of a response stream is normally sent in a DATA frame of
+//! its own. Streams responding with many small messages spend more on the
+//! frames than on the messages. `CoalesceWrites` batches the messages a
+//! handler produces in the same poll into a single DATA frame:
+//!
+//! ```ignore
+//! let new_service = CoalesceWrites::new(new_service)
+//!     .max_bytes(32 * 1024)
+//!     .linger(Duration::from_millis(1));
+//!
+//! let h2 = Server::new(new_service, Default::default(), handle.clone());
+//! ```
+//!
+//! By default, a batch is sent as soon as the handler has no message ready.
+//! With a linger, the batch waits that long for more messages, trading some
+//! latency for fewer, fuller frames.
+
+use clock::{Clock, Sleep};
+
+use futures::{Future, Poll, Async};
+use http;
+use tower::{NewService, Service};
+
+use std::fmt;
+use std::time::Duration;
+
+/// The default size at which a batch is sent.
+///
+/// This is the default maximum frame size of HTTP/2.0.
+const DEFAULT_MAX_BYTES: usize = 16 * 1024;
+
+/// Coalesces the response messages of the inner service.
+///
+/// `CoalesceWrites` may wrap either a `Service` or the `NewService` given
+/// to `tower_h2::Server`.
+#[derive(Debug, Clone)]
+pub struct CoalesceWrites<S> {
+    inner: S,
+    coalesce: Coalesce,
+}
+
+/// Creates `CoalesceWrites` services.
+#[derive(Debug)]
+pub struct NewServiceFuture<F> {
+    inner: F,
+    coalesce: Coalesce,
+}
+
+/// Request extension holding how the server's codec coalesces response
+/// messages.
+#[derive(Clone)]
+pub(crate) struct Coalesce {
+    max_bytes: usize,
+    linger: Option<Duration>,
+    timer: Clock,
+}
+
+/// The frames of a response stream waiting to be sent together.
+pub(crate) struct Batch {
+    coalesce: Coalesce,
+    sleep: Option<Sleep>,
+}
+
+// ===== impl CoalesceWrites =====
+
+impl<S> CoalesceWrites<S> {
+    /// Send batches of up to 16 KiB, without lingering.
+    pub fn new(inner: S) -> Self {
+        CoalesceWrites {
+            inner,
+            coalesce: Coalesce::new(DEFAULT_MAX_BYTES, None, Clock::default()),
+        }
+    }
+
+    /// Send a batch as soon as it holds `max` bytes of frames.
+    pub fn max_bytes(mut self, max: usize) -> Self {
+        self.coalesce.max_bytes = max;
+        self
+    }
+
+    /// Wait up to `linger` for more messages before sending a batch.
+    pub fn linger(mut self, linger: Duration) -> Self {
+        self.coalesce.linger = Some(linger);
+        self
+    }
+
+    /// Measure the linger with `timer`, which may be a `Timer` or a
+    /// `MockClock`.
+    pub fn timer<T: Into<Clock>>(mut self, timer: T) -> Self {
+        self.coalesce.timer = timer.into();
+        self
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner service.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+}
+
+impl<S, A> Service for CoalesceWrites<S>
+where S: Service<Request = http::Request<A>>,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, mut request: Self::Request) -> Self::Future {
+        request.extensions_mut().insert(self.coalesce.clone());
+        self.inner.call(request)
+    }
+}
+
+impl<S, A> NewService for CoalesceWrites<S>
+where S: NewService<Request = http::Request<A>>,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Service = CoalesceWrites<S::Service>;
+    type InitError = S::InitError;
+    type Future = NewServiceFuture<S::Future>;
+
+    fn new_service(&self) -> Self::Future {
+        NewServiceFuture {
+            inner: self.inner.new_service(),
+            coalesce: self.coalesce.clone(),
+        }
+    }
+}
+
+// ===== impl NewServiceFuture =====
+
+impl<F> Future for NewServiceFuture<F>
+where F: Future,
+{
+    type Item = CoalesceWrites<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+
+        Ok(Async::Ready(CoalesceWrites {
+            inner,
+            coalesce: self.coalesce.clone(),
+        }))
+    }
+}
+
+// ===== impl Coalesce =====
+
+impl Coalesce {
+    pub(crate) fn new(max_bytes: usize, linger: Option<Duration>, timer: Clock) -> Self {
+        Coalesce { max_bytes, linger, timer }
+    }
+
+    /// Start batching the frames of a stream.
+    pub(crate) fn batch(&self) -> Batch {
+        Batch {
+            coalesce: self.clone(),
+            sleep: None,
+        }
+    }
+}
+
+impl fmt::Debug for Coalesce {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Coalesce")
+            .field("max_bytes", &self.max_bytes)
+            .field("linger", &self.linger)
+            .finish()
+    }
+}
+
+// ===== impl Batch =====
+
+impl Batch {
+    /// Returns true if `buffered` bytes of frames are enough to send.
+    pub(crate) fn is_full(&self, buffered: usize) -> bool {
+        buffered >= self.coalesce.max_bytes
+    }
+
+    /// Returns true once the batch has waited long enough for more
+    /// messages.
+    pub(crate) fn poll_linger(&mut self) -> bool {
+        let linger = match self.coalesce.linger {
+            Some(linger) => linger,
+            None => return true,
+        };
+
+        let timer = &self.coalesce.timer;
+        let sleep = self.sleep.get_or_insert_with(|| timer.sleep(linger));
+
+        match sleep.poll() {
+            Ok(Async::NotReady) => false,
+            Ok(Async::Ready(())) => true,
+            Err(e) => {
+                debug!("coalescing timer failed; error={:?}", e);
+                true
+            }
+        }
+    }
+
+    /// Start the next batch, as the current one was sent.
+    pub(crate) fn reset(&mut self) {
+        self.sleep = None;
+    }
+}
+
+impl fmt::Debug for Batch {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Batch")
+            .field("coalesce", &self.coalesce)
+            .finish()
+    }
+}
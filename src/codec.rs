@@ -133,7 +133,8 @@ impl<T> Clone for Decoder<T> {
 // ===== impl Encode =====
 
 impl<T> Encode<T>
-where T: Stream<Error = ::Error>,
+where T: Stream,
+      T::Error: Into<::Status>,
       T::Item: ::prost::Message,
 {
     pub(crate) fn new(inner: ::generic::Encode<Encoder<T::Item>, T>) -> Self {
@@ -142,13 +143,14 @@ where T: Stream<Error = ::Error>,
 }
 
 impl<T> ::tower_h2::Body for Encode<T>
-where T: Stream<Error = ::Error>,
+where T: Stream,
+      T::Error: Into<::Status>,
       T::Item: ::prost::Message,
 {
     type Data = ::bytes::Bytes;
 
     fn is_end_stream(&self) -> bool {
-        false
+        self.inner.is_end_stream()
     }
 
     fn poll_data(&mut self) -> Poll<Option<Self::Data>, ::h2::Error> {
@@ -20,8 +20,15 @@ pub mod server {
             Encode,
             Streaming,
         };
+
+        #[cfg(feature = "async-server")]
+        pub use ::compat;
     }
 
+    /// Re-export the attribute of the `async_trait` crate.
+    #[cfg(feature = "async-server")]
+    pub use ::async_trait::async_trait;
+
     /// Re-export types from the `bytes` crate.
     pub mod bytes {
         pub use ::bytes::Bytes;
@@ -62,6 +69,8 @@ pub mod client {
             Builder,
             BuilderError,
             Encodable,
+            GeneratedClient,
+            Idempotency,
             unary,
             client_streaming,
             server_streaming,
@@ -0,0 +1,117 @@
+//! `std::future` support.
+//!
+//! The crate is built on futures 0.1. With the `futures03` feature, handlers
+//! and clients can instead be written with `async fn` and `.await`, through
+//! the compatibility layer of futures 0.3.
+//!
+//! Handlers return a `BoxFuture`, which is a futures 0.1 `Future`, so it can
+//! be used as the response future of generated service traits:
+//!
+//! ```ignore
+//! impl server::Greeter for Greet {
+//!     type SayHelloFuture = compat::BoxFuture<Response<HelloReply>>;
+//!
+//!     fn say_hello(&mut self, request: Request<HelloRequest>) -> Self::SayHelloFuture {
+//!         let store = self.store.clone();
+//!
+//!         compat::boxed(async move {
+//!             let name = store.lookup(request.get_ref().id).compat().await?;
+//!             Ok(Response::new(HelloReply { message: format!("Hello {}!", name) }))
+//!         })
+//!     }
+//! }
+//! ```
+//!
+//! Futures 0.1 futures, such as those of generated clients, are awaited
+//! with `Future01CompatExt::compat`:
+//!
+//! ```ignore
+//! let response = client.say_hello(Request::new(request)).compat().await?;
+//! ```
+//!
+//! `Streaming` request and response streams are also futures 0.3 streams,
+//! so they work with `StreamExt`:
+//!
+//! ```ignore
+//! let mut notes = client.route_chat(Request::new(outbound)).compat().await?.into_inner();
+//!
+//! while let Some(note) = notes.next().await {
+//!     println!("received {:?}", note?);
+//! }
+//! ```
+//!
+//! The futures run on the futures 0.1 executor driving the server or
+//! client, such as a tokio-core reactor.
+//!
+//! With the `tower03` feature, services convert to and from tower 0.3's
+//! `Service<Request>` trait, to be wrapped in tower 0.3 middleware such as
+//! tower-limit, tower-buffer and tower-retry:
+//!
+//! ```ignore
+//! let channel = ServiceBuilder::new()
+//!     .concurrency_limit(64)
+//!     .service(IntoTower03::new(channel));
+//!
+//! let client = Greeter::new(FromTower03::new(channel));
+//! ```
+//!
+//! `NewServiceIntoTower03` and `NewServiceFromTower03` convert the
+//! `NewService` of a server to and from a tower 0.3 make service, so a
+//! codebase can move its services and middleware to tower 0.3 one at a
+//! time, while they are still served by `tower_h2::Server`:
+//!
+//! ```ignore
+//! // `make_greeter` has already been migrated to tower 0.3.
+//! let new_service = NewServiceFromTower03::new(make_greeter());
+//! let h2 = Server::new(new_service, Default::default(), handle.clone());
+//!
+//! // `GreeterServer` is still built on futures 0.1.
+//! let make = NewServiceIntoTower03::new(GreeterServer::new(greeter));
+//! ```
+
+#[cfg(feature = "tower03")]
+mod service;
+mod stream;
+
+#[cfg(feature = "tower03")]
+pub use self::service::{
+    FromTower03,
+    IntoTower03,
+    NewServiceFromTower03,
+    NewServiceIntoTower03,
+};
+
+use futures03::compat::Compat;
+
+use std::future::Future;
+use std::pin::Pin;
+
+pub use futures03::compat::{Future01CompatExt, Stream01CompatExt};
+
+/// A boxed `std::future::Future` that is also a futures 0.1 `Future`.
+pub type BoxFuture<T, E = ::Error> =
+    Compat<Pin<Box<dyn Future<Output = Result<T, E>> + Send>>>;
+
+/// Box `future` for use where a futures 0.1 `Future` is expected.
+pub fn boxed<F, T, E>(future: F) -> BoxFuture<T, E>
+where F: Future<Output = Result<T, E>> + Send + 'static,
+{
+    Compat::new(Box::pin(future))
+}
+
+/// A boxed `std::future::Future` that need not be `Send`, and is also a
+/// futures 0.1 `Future`.
+///
+/// Local futures can hold `Rc` and other non-`Send` values, and run on
+/// single-threaded executors such as a tokio-core reactor (see the `local`
+/// module).
+pub type LocalBoxFuture<T, E = ::Error> =
+    Compat<Pin<Box<dyn Future<Output = Result<T, E>>>>>;
+
+/// Box `future`, which need not be `Send`, for use where a futures 0.1
+/// `Future` is expected.
+pub fn boxed_local<F, T, E>(future: F) -> LocalBoxFuture<T, E>
+where F: Future<Output = Result<T, E>> + 'static,
+{
+    Compat::new(Box::pin(future))
+}
@@ -0,0 +1,300 @@
+//! Adapters between the `Service` trait used by this crate and the
+//! `Service<Request>` trait of tower 0.3.
+
+use super::{boxed_local, LocalBoxFuture};
+
+use futures::{Future as Future01, Poll as Poll01};
+use futures::future::Map;
+use futures03::compat::{Compat, Compat01As03};
+use futures03::future::{poll_fn, TryFutureExt};
+use tower::{NewService, Service as Service01};
+use tower_service03::Service as Service03;
+
+use std::fmt;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Implements tower 0.3's `Service<Request>` for a service of this crate,
+/// such as a channel or a generated server, so it composes with tower 0.3
+/// middleware.
+pub struct IntoTower03<S> {
+    inner: S,
+}
+
+/// Implements the `Service` trait used by this crate for a tower 0.3
+/// service, such as one wrapped in tower 0.3 middleware.
+pub struct FromTower03<S, R> {
+    inner: S,
+    _p: PhantomData<fn(R)>,
+}
+
+/// Implements tower 0.3's `Service<Target>`, the make service pattern, for
+/// a `NewService`, such as the one given to a server.
+///
+/// Each service it makes is an `IntoTower03`.
+pub struct NewServiceIntoTower03<S> {
+    inner: S,
+}
+
+/// Implements `NewService` for a tower 0.3 make service, whose services are
+/// made for the target `()`.
+///
+/// Each service it makes is a `FromTower03`.
+pub struct NewServiceFromTower03<S, R> {
+    inner: S,
+    _p: PhantomData<fn(R)>,
+}
+
+// ===== impl IntoTower03 =====
+
+impl<S> IntoTower03<S> {
+    pub fn new(inner: S) -> Self {
+        IntoTower03 { inner }
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner service.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// Returns the inner service.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S> Service03<S::Request> for IntoTower03<S>
+where S: Service01,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Compat01As03<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), S::Error>> {
+        let inner = &mut self.inner;
+        let mut ready = Compat01As03::new(::futures::future::poll_fn(|| inner.poll_ready()));
+        Pin::new(&mut ready).poll(cx)
+    }
+
+    fn call(&mut self, request: S::Request) -> Self::Future {
+        Compat01As03::new(self.inner.call(request))
+    }
+}
+
+impl<S> Clone for IntoTower03<S>
+where S: Clone,
+{
+    fn clone(&self) -> Self {
+        IntoTower03 { inner: self.inner.clone() }
+    }
+}
+
+impl<S> fmt::Debug for IntoTower03<S>
+where S: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("IntoTower03")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+// ===== impl FromTower03 =====
+
+impl<S, R> FromTower03<S, R> {
+    pub fn new(inner: S) -> Self {
+        FromTower03 {
+            inner,
+            _p: PhantomData,
+        }
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner service.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// Returns the inner service.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S, R> Service01 for FromTower03<S, R>
+where S: Service03<R>,
+{
+    type Request = R;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Compat<Pin<Box<S::Future>>>;
+
+    fn poll_ready(&mut self) -> Poll01<(), S::Error> {
+        let inner = &mut self.inner;
+        Compat::new(poll_fn(|cx| inner.poll_ready(cx))).poll()
+    }
+
+    fn call(&mut self, request: R) -> Self::Future {
+        Compat::new(Box::pin(self.inner.call(request)))
+    }
+}
+
+impl<S, R> Clone for FromTower03<S, R>
+where S: Clone,
+{
+    fn clone(&self) -> Self {
+        FromTower03::new(self.inner.clone())
+    }
+}
+
+impl<S, R> fmt::Debug for FromTower03<S, R>
+where S: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("FromTower03")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+// ===== impl NewServiceIntoTower03 =====
+
+impl<S> NewServiceIntoTower03<S> {
+    pub fn new(inner: S) -> Self {
+        NewServiceIntoTower03 { inner }
+    }
+
+    /// Returns a reference to the inner `NewService`.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner `NewService`.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// Returns the inner `NewService`.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S, T> Service03<T> for NewServiceIntoTower03<S>
+where S: NewService,
+{
+    type Response = IntoTower03<S::Service>;
+    type Error = S::InitError;
+    type Future = Compat01As03<Map<S::Future, fn(S::Service) -> IntoTower03<S::Service>>>;
+
+    fn poll_ready(&mut self, _: &mut Context) -> Poll<Result<(), S::InitError>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _: T) -> Self::Future {
+        Compat01As03::new(self.inner.new_service().map(IntoTower03::new as fn(_) -> _))
+    }
+}
+
+impl<S> Clone for NewServiceIntoTower03<S>
+where S: Clone,
+{
+    fn clone(&self) -> Self {
+        NewServiceIntoTower03 { inner: self.inner.clone() }
+    }
+}
+
+impl<S> fmt::Debug for NewServiceIntoTower03<S>
+where S: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("NewServiceIntoTower03")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+// ===== impl NewServiceFromTower03 =====
+
+impl<S, R> NewServiceFromTower03<S, R> {
+    pub fn new(inner: S) -> Self {
+        NewServiceFromTower03 {
+            inner,
+            _p: PhantomData,
+        }
+    }
+
+    /// Returns a reference to the inner make service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner make service.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// Returns the inner make service.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S, R> NewService for NewServiceFromTower03<S, R>
+where S: Service03<()> + Clone + 'static,
+      S::Response: Service03<R>,
+      S::Future: 'static,
+{
+    type Request = R;
+    type Response = <S::Response as Service03<R>>::Response;
+    type Error = <S::Response as Service03<R>>::Error;
+    type Service = FromTower03<S::Response, R>;
+    type InitError = S::Error;
+    type Future = LocalBoxFuture<Self::Service, S::Error>;
+
+    fn new_service(&self) -> Self::Future {
+        // `new_service` takes `&self`, so each service is made by a clone
+        // that is driven to readiness first.
+        let mut make = Some(self.inner.clone());
+
+        let ready = poll_fn(move |cx| {
+            make.as_mut()
+                .expect("polled after ready")
+                .poll_ready(cx)
+                .map_ok(|()| make.take().expect("polled after ready"))
+        });
+
+        boxed_local(ready
+            .and_then(|mut make| make.call(()))
+            .map_ok(FromTower03::new))
+    }
+}
+
+impl<S, R> Clone for NewServiceFromTower03<S, R>
+where S: Clone,
+{
+    fn clone(&self) -> Self {
+        NewServiceFromTower03::new(self.inner.clone())
+    }
+}
+
+impl<S, R> fmt::Debug for NewServiceFromTower03<S, R>
+where S: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("NewServiceFromTower03")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
@@ -0,0 +1,32 @@
+//! futures 0.3 `Stream` implementations.
+
+use generic::{Decoder, Streaming};
+
+use bytes::Bytes;
+use futures::Stream as Stream01;
+use futures03::compat::Compat01As03;
+use futures03::Stream;
+use tower_h2::Body;
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+impl<T, U> Stream for Streaming<T, U>
+where T: Decoder + Unpin,
+      U: Body + Unpin,
+      U::Data: Into<Bytes>,
+{
+    type Item = Result<T::Item, ::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let mut next = Compat01As03::new(::futures::future::poll_fn(|| this.poll()));
+
+        match Pin::new(&mut next).poll(cx) {
+            Poll::Ready(Ok(msg)) => Poll::Ready(msg.map(Ok)),
+            Poll::Ready(Err(e)) => Poll::Ready(Some(Err(e))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
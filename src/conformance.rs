@@ -0,0 +1,361 @@
+//! Conformance of the gRPC framing.
+//!
+//! The cases of this module feed valid and malformed length-prefixed frames
+//! through the decoder, and check the messages decoded and the status it
+//! fails with against the ones the gRPC specification mandates. Each case
+//! is run through a `FrameDecoder`, and through a `Streaming` whose body
+//! yields the case's chunks as separate DATA frames.
+//!
+//! Run the suite from a test, so changes to the codec can't silently break
+//! wire compatibility:
+//!
+//! ```ignore
+//! #[test]
+//! fn framing() {
+//!     tower_grpc::conformance::assert_framing();
+//! }
+//! ```
+//!
+//! `framing_cases` returns the cases, for transports that frame messages
+//! themselves and want to check their own decoding against the same matrix.
+
+use generic::{BytesCodec, FrameDecoder, Streaming};
+use Code;
+
+use bytes::Bytes;
+use futures::{Async, Poll, Stream};
+use h2;
+use http::HeaderMap;
+use tower_h2::Body;
+
+use std::collections::VecDeque;
+use std::fmt;
+
+/// The largest message accepted by the cases that limit message sizes.
+const MAX_MESSAGE_SIZE: usize = 1024;
+
+/// Bytes received as frames, and how the decoder must handle them.
+#[derive(Debug, Clone)]
+pub struct Case {
+    name: &'static str,
+
+    /// The bytes of each DATA frame.
+    chunks: Vec<Vec<u8>>,
+
+    /// The largest message the decoder accepts, if limited.
+    max_message_size: Option<usize>,
+
+    /// The messages decoded before the end of the stream or the failure.
+    messages: Vec<Vec<u8>>,
+
+    /// The code the decoder fails with, if it must fail.
+    code: Option<Code>,
+}
+
+/// A case whose frames were not decoded as the specification mandates.
+#[derive(Debug)]
+pub struct Failure {
+    case: &'static str,
+
+    /// The decoding path that failed.
+    path: &'static str,
+
+    expected: Outcome,
+    actual: Outcome,
+}
+
+/// The messages decoded from a case's frames, and the code decoding failed
+/// with, if any.
+#[derive(Debug, Clone, PartialEq)]
+struct Outcome {
+    messages: Vec<Vec<u8>>,
+    code: Option<Code>,
+}
+
+/// A request or response body yielding a case's chunks.
+struct Chunks {
+    chunks: VecDeque<Bytes>,
+}
+
+// ===== impl Case =====
+
+impl Case {
+    /// Returns the case's name.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns the bytes of each DATA frame.
+    pub fn chunks(&self) -> &[Vec<u8>] {
+        &self.chunks
+    }
+
+    /// Returns the largest message the decoder must accept, if limited.
+    pub fn max_message_size(&self) -> Option<usize> {
+        self.max_message_size
+    }
+
+    /// Returns the messages the decoder must yield.
+    pub fn messages(&self) -> &[Vec<u8>] {
+        &self.messages
+    }
+
+    /// Returns the code the decoder must fail with, after yielding the
+    /// messages, if any.
+    pub fn code(&self) -> Option<Code> {
+        self.code
+    }
+
+    /// Run the case through a `FrameDecoder` and through a `Streaming`.
+    pub fn run(&self) -> Result<(), Failure> {
+        self.check("FrameDecoder", self.decode_frames())?;
+        self.check("Streaming", self.decode_stream())
+    }
+
+    fn new(name: &'static str, chunks: Vec<Vec<u8>>) -> Self {
+        Case {
+            name,
+            chunks,
+            max_message_size: None,
+            messages: vec![],
+            code: None,
+        }
+    }
+
+    fn max(mut self, max: usize) -> Self {
+        self.max_message_size = Some(max);
+        self
+    }
+
+    fn yields(mut self, message: &[u8]) -> Self {
+        self.messages.push(message.to_vec());
+        self
+    }
+
+    fn fails(mut self, code: Code) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    fn check(&self, path: &'static str, actual: Outcome) -> Result<(), Failure> {
+        let expected = Outcome {
+            messages: self.messages.clone(),
+            code: self.code,
+        };
+
+        if actual == expected {
+            return Ok(());
+        }
+
+        Err(Failure {
+            case: self.name,
+            path,
+            expected,
+            actual,
+        })
+    }
+
+    fn decode_frames(&self) -> Outcome {
+        let mut frames = FrameDecoder::new(BytesCodec);
+
+        if let Some(max) = self.max_message_size {
+            frames = frames.max_message_size(max);
+        }
+
+        let mut messages = vec![];
+
+        for chunk in &self.chunks {
+            frames.push(Bytes::from(&chunk[..]));
+
+            loop {
+                match frames.decode() {
+                    Ok(Some(message)) => messages.push(message),
+                    Ok(None) => break,
+                    Err(status) => return Outcome { messages, code: Some(status.code()) },
+                }
+            }
+        }
+
+        let code = frames.finish().err().map(|status| status.code());
+        Outcome { messages, code }
+    }
+
+    fn decode_stream(&self) -> Outcome {
+        let body = Chunks {
+            chunks: self.chunks.iter().map(|chunk| Bytes::from(&chunk[..])).collect(),
+        };
+        let mut stream = Streaming::new(BytesCodec, body, false)
+            .max_message_size(self.max_message_size);
+
+        let mut messages = vec![];
+
+        loop {
+            // The body is always ready, so the stream never waits.
+            match stream.poll() {
+                Ok(Async::Ready(Some(message))) => messages.push(message),
+                Ok(Async::Ready(None)) => return Outcome { messages, code: None },
+                Ok(Async::NotReady) => unreachable!("in-memory body is always ready"),
+                Err(::Error::Grpc(status)) => {
+                    return Outcome { messages, code: Some(status.code()) };
+                }
+                Err(::Error::Inner(())) => {
+                    return Outcome { messages, code: Some(Code::INTERNAL) };
+                }
+            }
+        }
+    }
+}
+
+// ===== impl Failure =====
+
+impl Failure {
+    /// Returns the name of the case that failed.
+    pub fn case(&self) -> &'static str {
+        self.case
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{} ({}): expected {} message(s) then {:?}, got {} message(s) then {:?}",
+               self.case,
+               self.path,
+               self.expected.messages.len(),
+               self.expected.code,
+               self.actual.messages.len(),
+               self.actual.code)
+    }
+}
+
+// ===== impl Chunks =====
+
+impl Body for Chunks {
+    type Data = Bytes;
+
+    fn is_end_stream(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Bytes>, h2::Error> {
+        Ok(Async::Ready(self.chunks.pop_front()))
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, h2::Error> {
+        Ok(Async::Ready(None))
+    }
+}
+
+// ===== utility fns =====
+
+/// Returns the framing cases.
+pub fn framing_cases() -> Vec<Case> {
+    let hello = frame(0, b"hello");
+    let world = frame(0, b"world");
+
+    vec![
+        Case::new("empty stream", vec![]),
+
+        Case::new("empty message", vec![frame(0, b"")])
+            .yields(b""),
+
+        Case::new("single message", vec![hello.clone()])
+            .yields(b"hello"),
+
+        Case::new("two messages in one DATA frame", vec![concat(&[&hello, &world])])
+            .yields(b"hello")
+            .yields(b"world"),
+
+        Case::new("message split across DATA frames at every byte",
+                  hello.iter().map(|&b| vec![b]).collect())
+            .yields(b"hello"),
+
+        Case::new("prefix split across DATA frames",
+                  vec![hello[..2].to_vec(), hello[2..].to_vec()])
+            .yields(b"hello"),
+
+        Case::new("messages split across DATA frame boundaries",
+                  vec![hello[..7].to_vec(), concat(&[&hello[7..], &world[..3]]), world[3..].to_vec()])
+            .yields(b"hello")
+            .yields(b"world"),
+
+        Case::new("empty DATA frames between messages",
+                  vec![hello.clone(), vec![], vec![], world.clone()])
+            .yields(b"hello")
+            .yields(b"world"),
+
+        Case::new("truncated prefix", vec![hello[..3].to_vec()])
+            .fails(Code::INTERNAL),
+
+        Case::new("truncated message", vec![hello[..7].to_vec()])
+            .fails(Code::INTERNAL),
+
+        Case::new("truncated prefix after a message", vec![hello.clone(), world[..4].to_vec()])
+            .yields(b"hello")
+            .fails(Code::INTERNAL),
+
+        Case::new("length beyond the end of the stream", vec![prefix(0, u32::max_value())])
+            .fails(Code::INTERNAL),
+
+        Case::new("compressed flag without compression", vec![frame(1, b"hello")])
+            .fails(Code::UNIMPLEMENTED),
+
+        Case::new("invalid compressed flag", vec![frame(2, b"hello")])
+            .fails(Code::INTERNAL),
+
+        Case::new("invalid compressed flag after a message", vec![hello.clone(), frame(0x80, b"world")])
+            .yields(b"hello")
+            .fails(Code::INTERNAL),
+
+        Case::new("message at the maximum size", vec![frame(0, &[0; MAX_MESSAGE_SIZE])])
+            .max(MAX_MESSAGE_SIZE)
+            .yields(&[0; MAX_MESSAGE_SIZE]),
+
+        Case::new("message over the maximum size", vec![frame(0, &[0; MAX_MESSAGE_SIZE + 1])])
+            .max(MAX_MESSAGE_SIZE)
+            .fails(Code::RESOURCE_EXHAUSTED),
+
+        // The length alone is enough to refuse the message.
+        Case::new("oversized length without its message", vec![prefix(0, u32::max_value())])
+            .max(MAX_MESSAGE_SIZE)
+            .fails(Code::RESOURCE_EXHAUSTED),
+
+        Case::new("oversized length after a message",
+                  vec![hello.clone(), prefix(0, MAX_MESSAGE_SIZE as u32 + 1)])
+            .max(MAX_MESSAGE_SIZE)
+            .yields(b"hello")
+            .fails(Code::RESOURCE_EXHAUSTED),
+    ]
+}
+
+/// Run every framing case, returning the ones that failed.
+pub fn check_framing() -> Result<(), Vec<Failure>> {
+    let failures: Vec<_> = framing_cases().iter()
+        .filter_map(|case| case.run().err())
+        .collect();
+
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(failures)
+    }
+}
+
+/// Run every framing case, panicking with the ones that failed.
+pub fn assert_framing() {
+    if let Err(failures) = check_framing() {
+        let failures: Vec<_> = failures.iter().map(ToString::to_string).collect();
+        panic!("framing cases failed:\n{}", failures.join("\n"));
+    }
+}
+
+fn prefix(flag: u8, len: u32) -> Vec<u8> {
+    vec![flag, (len >> 24) as u8, (len >> 16) as u8, (len >> 8) as u8, len as u8]
+}
+
+fn frame(flag: u8, message: &[u8]) -> Vec<u8> {
+    concat(&[&prefix(flag, message.len() as u32), message])
+}
+
+fn concat(parts: &[&[u8]]) -> Vec<u8> {
+    parts.iter().flat_map(|part| part.iter().cloned()).collect()
+}
@@ -0,0 +1,1270 @@
+//! The Connect protocol.
+//!
+//! `ConnectServer` answers calls made with the [Connect protocol] by
+//! connect-web and other Connect clients, as well as gRPC calls, by
+//! forwarding them as gRPC calls to an inner HTTP/2.0 client service, such
+//! as an in-process connection to the server:
+//!
+//! ```ignore
+//! let conn = core.run(inprocess::connect(UsersServer::new(users), &handle))?;
+//!
+//! let server = ConnectServer::new(conn, Uri::from_static("http://users"))
+//!     .register_file_descriptor_set(include_bytes!("users.bin"))?;
+//!
+//! let serve = Http::new().serve_connection(sock, HyperServer::new(server));
+//! ```
+//!
+//! The protocol of each request is chosen by its content type:
+//!
+//! * `application/grpc` requests are forwarded as they are.
+//! * `application/proto` and `application/json` requests are unary Connect
+//!   calls, with the message as the body.
+//! * `application/connect+proto` and `application/connect+json` requests
+//!   are streaming Connect calls, with enveloped messages.
+//!
+//! The JSON codec needs the types of the method, from a registered file
+//! descriptor set. Compressed Connect messages and `GET` requests are not
+//! supported.
+//!
+//! [Connect protocol]: https://connectrpc.com/docs/protocol
+
+use {Code, Status};
+use generic::{BytesCodec, Encode, FrameDecoder, FrameEncoder, Streaming};
+use headers;
+use timeout;
+use transcode::{self, DescriptorError};
+use transcode::descriptor::Descriptors;
+use transcode::json;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::{Future, Stream, Poll, Async};
+use h2;
+use http::{self, header, HeaderMap, Uri};
+use http::header::{HeaderName, HeaderValue};
+use serde_json::{self, Map, Value};
+use tower::Service;
+use tower_h2::{Body, BoxBody, HttpService};
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Serves Connect and gRPC calls with an inner gRPC client service.
+#[derive(Clone)]
+pub struct ConnectServer<S> {
+    inner: S,
+    origin: Uri,
+    descriptors: Arc<Descriptors>,
+}
+
+/// The response future returned by `ConnectServer`.
+pub struct ResponseFuture<F, B> {
+    state: State<F, B>,
+    call: Option<Call>,
+}
+
+/// The response body returned by `ConnectServer`.
+pub struct ResponseBody<B> {
+    kind: Kind<B>,
+}
+
+enum Kind<B> {
+    /// The body of a gRPC call.
+    Grpc(B),
+
+    /// The message or error of a unary Connect call.
+    Full(Option<Bytes>),
+
+    /// The enveloped messages of a streaming Connect call.
+    Streaming(Envelopes<B>),
+}
+
+/// Envelopes the response messages of a streaming call, followed by the
+/// end of stream message.
+struct Envelopes<B> {
+    messages: Option<Streaming<BytesCodec, B>>,
+    frames: FrameEncoder<BytesCodec>,
+    call: Call,
+    end: Option<Bytes>,
+}
+
+enum State<F, B> {
+    /// Waiting for the response head.
+    Calling(F),
+
+    /// Reading the response message of a unary call.
+    Reading(Streaming<BytesCodec, B>, HeaderMap, Option<Vec<u8>>),
+
+    /// The request was answered without calling the inner service.
+    Done(Option<http::Response<ResponseBody<B>>>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Protocol {
+    Grpc,
+    Unary(Format),
+    Streaming(Format),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Proto,
+    Json,
+}
+
+/// The codec and types of a Connect call.
+#[derive(Clone)]
+struct Call {
+    format: Format,
+    streaming: bool,
+    descriptors: Arc<Descriptors>,
+    input_type: Option<String>,
+    output_type: Option<String>,
+
+    /// Describes why the request could not be converted, if it failed.
+    error: Arc<Mutex<Option<(Code, String)>>>,
+}
+
+/// Converts the body of a Connect request to the messages of the call.
+struct RequestMessages<B> {
+    body: B,
+    call: Call,
+
+    /// Decodes the envelopes of a streaming call.
+    frames: Option<FrameDecoder<BytesCodec>>,
+
+    /// The message of a unary call.
+    buf: Vec<u8>,
+    done: bool,
+}
+
+/// The body of a gRPC request, forwarded as it is.
+struct Forward<B> {
+    inner: B,
+}
+
+/// Flag of the envelope ending a streaming response.
+const END_STREAM: u8 = 0b10;
+
+// ===== impl ConnectServer =====
+
+impl<S> ConnectServer<S> {
+    /// Forward calls to `inner`, sent to the scheme and authority of
+    /// `origin`.
+    ///
+    /// Only the proto codec is available until a file descriptor set is
+    /// registered.
+    pub fn new(inner: S, origin: Uri) -> Self {
+        ConnectServer {
+            inner,
+            origin,
+            descriptors: Arc::new(Descriptors::default()),
+        }
+    }
+
+    /// Add the types and methods of an encoded `FileDescriptorSet`, for the
+    /// JSON codec.
+    pub fn register_file_descriptor_set(mut self, encoded: &[u8]) -> Result<Self, DescriptorError> {
+        transcode::register(&mut self.descriptors, encoded)?;
+        Ok(self)
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner service.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+}
+
+impl<S, B> Service for ConnectServer<S>
+where S: HttpService<RequestBody = BoxBody>,
+      B: Body + Send + 'static,
+      B::Data: Into<Bytes>,
+{
+    type Request = http::Request<B>;
+    type Response = http::Response<ResponseBody<S::ResponseBody>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future, S::ResponseBody>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, request: Self::Request) -> Self::Future {
+        let protocol = match Protocol::from_headers(request.headers()) {
+            Some(protocol) => protocol,
+            None => {
+                let mut response = http::Response::new(ResponseBody::full(None));
+                *response.status_mut() = http::StatusCode::UNSUPPORTED_MEDIA_TYPE;
+                return ResponseFuture::done(response);
+            }
+        };
+
+        let (head, body) = request.into_parts();
+
+        if protocol == Protocol::Grpc {
+            let request = http::Request::from_parts(head, BoxBody::new(Box::new(Forward {
+                inner: body,
+            })));
+
+            return ResponseFuture {
+                state: State::Calling(self.inner.call(request)),
+                call: None,
+            };
+        }
+
+        if head.method != http::Method::POST {
+            let mut response = http::Response::new(ResponseBody::full(None));
+            *response.status_mut() = http::StatusCode::METHOD_NOT_ALLOWED;
+            response.headers_mut()
+                .insert(header::ALLOW, HeaderValue::from_static("POST"));
+            return ResponseFuture::done(response);
+        }
+
+        let (format, streaming) = match protocol {
+            Protocol::Unary(format) => (format, false),
+            Protocol::Streaming(format) => (format, true),
+            Protocol::Grpc => unreachable!(),
+        };
+
+        let method = self.descriptors.methods.get(head.uri.path());
+
+        let call = Call {
+            format,
+            streaming,
+            descriptors: self.descriptors.clone(),
+            input_type: method.map(|method| method.input_type.clone()),
+            output_type: method.map(|method| method.output_type.clone()),
+            error: Arc::new(Mutex::new(None)),
+        };
+
+        if let Some(method) = method {
+            if !streaming && (method.client_streaming || method.server_streaming) {
+                let message = "streaming methods are called with application/connect+proto \
+                               or application/connect+json";
+                return ResponseFuture::failed(&call, Code::UNIMPLEMENTED, message);
+            }
+        } else if format == Format::Json {
+            let message = "unknown method for the JSON codec";
+            return ResponseFuture::failed(&call, Code::UNIMPLEMENTED, message);
+        }
+
+        let encoding = head.headers.get(if streaming {
+            "connect-content-encoding"
+        } else {
+            "content-encoding"
+        });
+
+        if encoding.map_or(false, |encoding| encoding != "identity") {
+            let message = "compressed messages are not supported";
+            return ResponseFuture::failed(&call, Code::UNIMPLEMENTED, message);
+        }
+
+        let timeout = head.headers.get("connect-timeout-ms")
+            .and_then(|timeout| timeout.to_str().ok())
+            .and_then(|timeout| timeout.parse().ok())
+            .map(Duration::from_millis);
+
+        let mut parts = http::uri::Parts::default();
+        parts.scheme = self.origin.scheme_part().cloned();
+        parts.authority = self.origin.authority_part().cloned();
+        parts.path_and_query = head.uri.path_and_query().cloned();
+
+        let uri = match Uri::from_parts(parts) {
+            Ok(uri) => uri,
+            Err(_) => return ResponseFuture::failed(&call, Code::INTERNAL, "invalid origin"),
+        };
+
+        let messages = RequestMessages {
+            body,
+            call: call.clone(),
+            frames: if streaming { Some(FrameDecoder::new(BytesCodec)) } else { None },
+            buf: vec![],
+            done: false,
+        };
+
+        let mut request = http::Request::new(BoxBody::new(Box::new(Encode::new(BytesCodec, messages, false))));
+        *request.method_mut() = http::Method::POST;
+        *request.uri_mut() = uri;
+        *request.headers_mut() = metadata(transcode::forward_headers(head.headers));
+        request.headers_mut()
+            .insert(header::TE, HeaderValue::from_static("trailers"));
+        request.headers_mut()
+            .insert(header::CONTENT_TYPE, HeaderValue::from_static("application/grpc+proto"));
+
+        if let Some(timeout) = timeout {
+            request.headers_mut()
+                .insert(headers::GRPC_TIMEOUT.clone(), timeout::encode(timeout));
+        }
+
+        ResponseFuture {
+            state: State::Calling(self.inner.call(request)),
+            call: Some(call),
+        }
+    }
+}
+
+impl<S> fmt::Debug for ConnectServer<S>
+where S: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("ConnectServer")
+            .field("inner", &self.inner)
+            .field("origin", &self.origin)
+            .field("methods", &self.descriptors.methods.len())
+            .finish()
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F, B> ResponseFuture<F, B> {
+    fn done(response: http::Response<ResponseBody<B>>) -> Self {
+        ResponseFuture {
+            state: State::Done(Some(response)),
+            call: None,
+        }
+    }
+
+    fn failed(call: &Call, code: Code, message: &str) -> Self {
+        ResponseFuture::done(call.error_response(code, message, HeaderMap::new()))
+    }
+}
+
+impl<F, B> Future for ResponseFuture<F, B>
+where F: Future<Item = http::Response<B>>,
+      B: Body,
+      B::Data: Into<Bytes>,
+{
+    type Item = http::Response<ResponseBody<B>>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                State::Calling(ref mut future) => {
+                    let response = match future.poll() {
+                        Ok(Async::Ready(response)) => response,
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Err(e) => {
+                            // The call fails when its request is rejected.
+                            if let Some(ref call) = self.call {
+                                if let Some((code, message)) = call.rejected() {
+                                    let response = call.error_response(code, &message, HeaderMap::new());
+                                    return Ok(response.into());
+                                }
+                            }
+
+                            return Err(e);
+                        }
+                    };
+
+                    let (head, body) = response.into_parts();
+
+                    let call = match self.call {
+                        Some(ref call) => call,
+                        None => {
+                            let body = ResponseBody { kind: Kind::Grpc(body) };
+                            return Ok(http::Response::from_parts(head, body).into());
+                        }
+                    };
+
+                    let headers = metadata(head.headers.clone());
+
+                    // A trailers-only response.
+                    if let Some(status) = head.headers.get("grpc-status") {
+                        let status = Status::from_bytes(status.as_ref());
+
+                        if status.code() != Code::OK {
+                            let message = transcode::grpc_message(&head.headers);
+                            return Ok(call.error_response(status.code(), &message, headers).into());
+                        }
+                    }
+
+                    let messages = Streaming::new(BytesCodec, body, true);
+
+                    if call.streaming {
+                        let body = ResponseBody {
+                            kind: Kind::Streaming(Envelopes {
+                                messages: Some(messages),
+                                frames: FrameEncoder::new(BytesCodec),
+                                call: call.clone(),
+                                end: None,
+                            }),
+                        };
+
+                        return Ok(streaming_response(call.format, headers, body).into());
+                    }
+
+                    State::Reading(messages, headers, None)
+                }
+                State::Reading(ref mut messages, ref mut headers, ref mut message) => {
+                    let call = self.call.as_ref().expect("reading without a call");
+
+                    match messages.poll() {
+                        Ok(Async::Ready(Some(m))) => {
+                            *message = Some(m);
+                            continue;
+                        }
+                        Ok(Async::Ready(None)) => {}
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Err(e) => {
+                            if let Some((code, message)) = call.rejected() {
+                                return Ok(error_response(code, &message, headers.clone(), None).into());
+                            }
+
+                            let message = messages.trailers()
+                                .map(transcode::grpc_message)
+                                .unwrap_or_default();
+                            let code = Status::from(e).code();
+                            let trailers = messages.trailers();
+
+                            return Ok(error_response(code, &message, headers.clone(), trailers).into());
+                        }
+                    }
+
+                    let message = message.take().unwrap_or_default();
+
+                    let data = match call.render(message) {
+                        Ok(data) => data,
+                        Err(e) => {
+                            debug!("response message could not be rendered; error={}", e);
+                            return Ok(error_response(Code::INTERNAL, &e, headers.clone(), None).into());
+                        }
+                    };
+
+                    let mut headers = headers.clone();
+                    if let Some(trailers) = messages.trailers() {
+                        prefix_trailers(trailers, &mut headers);
+                    }
+
+                    let content_type = match call.format {
+                        Format::Proto => "application/proto",
+                        Format::Json => "application/json",
+                    };
+
+                    let mut response = http::Response::new(ResponseBody::full(Some(data.into())));
+                    *response.headers_mut() = headers;
+                    response.headers_mut()
+                        .insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+
+                    return Ok(response.into());
+                }
+                State::Done(ref mut response) => {
+                    return Ok(response.take().expect("polled after complete").into());
+                }
+            };
+
+            self.state = next;
+        }
+    }
+}
+
+impl<F, B> fmt::Debug for ResponseFuture<F, B> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let state = match self.state {
+            State::Calling(..) => "Calling",
+            State::Reading(..) => "Reading",
+            State::Done(..) => "Done",
+        };
+
+        fmt.debug_struct("connect::ResponseFuture")
+            .field("state", &state)
+            .finish()
+    }
+}
+
+// ===== impl ResponseBody =====
+
+impl<B> ResponseBody<B> {
+    fn full(data: Option<Bytes>) -> Self {
+        ResponseBody { kind: Kind::Full(data) }
+    }
+}
+
+impl<B> Body for ResponseBody<B>
+where B: Body,
+      B::Data: Into<Bytes>,
+{
+    type Data = Bytes;
+
+    fn is_end_stream(&self) -> bool {
+        match self.kind {
+            Kind::Grpc(ref body) => body.is_end_stream(),
+            Kind::Full(ref data) => data.is_none(),
+            Kind::Streaming(ref envelopes) => {
+                envelopes.messages.is_none() && envelopes.end.is_none()
+            }
+        }
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Bytes>, h2::Error> {
+        match self.kind {
+            Kind::Grpc(ref mut body) => {
+                let data = try_ready!(body.poll_data());
+                Ok(data.map(Into::into).into())
+            }
+            Kind::Full(ref mut data) => Ok(data.take().into()),
+            Kind::Streaming(ref mut envelopes) => envelopes.poll_data(),
+        }
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, h2::Error> {
+        match self.kind {
+            Kind::Grpc(ref mut body) => body.poll_trailers(),
+            _ => Ok(None.into()),
+        }
+    }
+}
+
+impl<B> fmt::Debug for ResponseBody<B>
+where B: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let mut fmt = fmt.debug_struct("connect::ResponseBody");
+
+        match self.kind {
+            Kind::Grpc(ref body) => fmt.field("grpc", body),
+            Kind::Full(ref data) => fmt.field("data", data),
+            Kind::Streaming(ref envelopes) => fmt.field("ended", &envelopes.messages.is_none()),
+        };
+
+        fmt.finish()
+    }
+}
+
+// ===== impl Envelopes =====
+
+impl<B> Envelopes<B>
+where B: Body,
+      B::Data: Into<Bytes>,
+{
+    fn poll_data(&mut self) -> Poll<Option<Bytes>, h2::Error> {
+        let end = match self.messages {
+            Some(ref mut messages) => match messages.poll() {
+                Ok(Async::Ready(Some(message))) => {
+                    match self.call.render(message) {
+                        Ok(message) => {
+                            let frame = self.frames.encode(message)?;
+                            return Ok(Some(frame).into());
+                        }
+                        Err(e) => {
+                            debug!("response message could not be rendered; error={}", e);
+                            end_stream(Some((Code::INTERNAL, &e)), &HeaderMap::new())
+                        }
+                    }
+                }
+                Ok(Async::Ready(None)) => {
+                    let trailers = messages.trailers().cloned().unwrap_or_default();
+                    end_stream(None, &trailers)
+                }
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(e) => {
+                    let trailers = messages.trailers().cloned().unwrap_or_default();
+
+                    match self.call.rejected() {
+                        Some((code, message)) => end_stream(Some((code, &message)), &trailers),
+                        None => {
+                            let message = transcode::grpc_message(&trailers);
+                            end_stream(Some((Status::from(e).code(), &message)), &trailers)
+                        }
+                    }
+                }
+            },
+            None => return Ok(self.end.take().into()),
+        };
+
+        self.messages = None;
+        Ok(Some(end).into())
+    }
+}
+
+// ===== impl Protocol =====
+
+impl Protocol {
+    fn from_headers(headers: &HeaderMap) -> Option<Protocol> {
+        let content_type = headers.get(header::CONTENT_TYPE)?.to_str().ok()?;
+        let content_type = content_type.split(';').next().unwrap_or("").trim();
+
+        let protocol = match content_type {
+            "application/proto" => Protocol::Unary(Format::Proto),
+            "application/json" => Protocol::Unary(Format::Json),
+            "application/connect+proto" => Protocol::Streaming(Format::Proto),
+            "application/connect+json" => Protocol::Streaming(Format::Json),
+            t if t.starts_with("application/grpc") => Protocol::Grpc,
+            _ => return None,
+        };
+
+        Some(protocol)
+    }
+}
+
+// ===== impl Call =====
+
+impl Call {
+    /// The response of a call that failed before its response messages.
+    ///
+    /// Unary calls fail with an HTTP error, while streaming calls end their
+    /// stream with the error.
+    fn error_response<B>(&self, code: Code, message: &str, headers: HeaderMap)
+        -> http::Response<ResponseBody<B>>
+    {
+        if self.streaming {
+            let end = end_stream(Some((code, message)), &HeaderMap::new());
+            streaming_response(self.format, headers, ResponseBody::full(Some(end)))
+        } else {
+            error_response(code, message, headers, None)
+        }
+    }
+
+    /// Returns the code and message of a request that could not be
+    /// converted.
+    fn rejected(&self) -> Option<(Code, String)> {
+        self.error.lock().unwrap().clone()
+    }
+
+    /// Convert a request message from the call's codec to protobuf.
+    fn parse(&self, message: Vec<u8>) -> Result<Vec<u8>, String> {
+        match self.format {
+            Format::Proto => Ok(message),
+            Format::Json => {
+                let input_type = self.input_type.as_ref().expect("JSON calls have types");
+                let value: Value = serde_json::from_slice(&message)
+                    .map_err(|e| format!("invalid JSON message: {}", e))?;
+
+                let mut buf = vec![];
+                json::encode(&self.descriptors, input_type, &value, &mut buf)?;
+                Ok(buf)
+            }
+        }
+    }
+
+    /// Convert a response message from protobuf to the call's codec.
+    fn render(&self, message: Vec<u8>) -> Result<Vec<u8>, String> {
+        match self.format {
+            Format::Proto => Ok(message),
+            Format::Json => {
+                let output_type = self.output_type.as_ref().expect("JSON calls have types");
+                let value = json::decode(&self.descriptors, output_type, &message)?;
+
+                Ok(serde_json::to_vec(&value).expect("JSON values serialize"))
+            }
+        }
+    }
+}
+
+// ===== impl RequestMessages =====
+
+impl<B> RequestMessages<B> {
+    fn reject(&mut self, code: Code, message: String) -> ::Error {
+        debug!("request could not be converted; error={}", message);
+        *self.call.error.lock().unwrap() = Some((code, message));
+        ::Error::Grpc(Status::INVALID_ARGUMENT)
+    }
+}
+
+impl<B> Stream for RequestMessages<B>
+where B: Body,
+      B::Data: Into<Bytes>,
+{
+    type Item = Vec<u8>;
+    type Error = ::Error;
+
+    fn poll(&mut self) -> Poll<Option<Vec<u8>>, ::Error> {
+        loop {
+            let message = match self.frames {
+                Some(ref mut frames) => match frames.decode() {
+                    Ok(message) => message,
+                    Err(status) => {
+                        return Err(self.reject(status.code(), "invalid envelope".to_string()));
+                    }
+                },
+                None if self.done => None,
+                None => {
+                    // A unary message is the whole body.
+                    while let Some(data) = try_ready!(self.body.poll_data().map_err(Status::from)) {
+                        self.buf.extend_from_slice(&data.into());
+                    }
+
+                    self.done = true;
+                    Some(::std::mem::replace(&mut self.buf, vec![]))
+                }
+            };
+
+            if let Some(message) = message {
+                return match self.call.parse(message) {
+                    Ok(message) => Ok(Async::Ready(Some(message))),
+                    Err(e) => Err(self.reject(Code::INVALID_ARGUMENT, e)),
+                };
+            }
+
+            if self.done {
+                return Ok(Async::Ready(None));
+            }
+
+            match try_ready!(self.body.poll_data().map_err(Status::from)) {
+                Some(data) => {
+                    let frames = self.frames.as_mut().expect("streaming call");
+                    frames.push(data.into());
+                }
+                None => {
+                    self.done = true;
+
+                    let frames = self.frames.as_mut().expect("streaming call");
+                    if frames.finish().is_err() {
+                        return Err(self.reject(Code::INVALID_ARGUMENT, "truncated envelope".to_string()));
+                    }
+                }
+            }
+        }
+    }
+}
+
+// ===== impl Forward =====
+
+impl<B> Body for Forward<B>
+where B: Body,
+      B::Data: Into<Bytes>,
+{
+    type Data = Bytes;
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Bytes>, h2::Error> {
+        let data = try_ready!(self.inner.poll_data());
+        Ok(data.map(Into::into).into())
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, h2::Error> {
+        self.inner.poll_trailers()
+    }
+}
+
+// ===== utility fns =====
+
+/// Remove the headers of the protocols from `headers`, leaving the
+/// metadata.
+fn metadata(mut headers: HeaderMap) -> HeaderMap {
+    for name in &[
+        "connect-protocol-version",
+        "connect-timeout-ms",
+        "connect-content-encoding",
+        "connect-accept-encoding",
+        "grpc-status",
+        "grpc-message",
+        "grpc-encoding",
+        "grpc-accept-encoding",
+    ] {
+        headers.remove(*name);
+    }
+
+    headers.remove(header::CONTENT_TYPE);
+    headers.remove(header::CONTENT_LENGTH);
+    headers
+}
+
+/// Add the metadata of `trailers` to the headers of a unary response, as
+/// `trailer-` prefixed headers.
+fn prefix_trailers(trailers: &HeaderMap, headers: &mut HeaderMap) {
+    for (name, value) in metadata(trailers.clone()).iter() {
+        let name = format!("trailer-{}", name);
+
+        if let Ok(name) = HeaderName::from_bytes(name.as_bytes()) {
+            headers.append(name, value.clone());
+        }
+    }
+}
+
+/// The error of a unary call, as JSON.
+fn error_response<B>(code: Code,
+                     message: &str,
+                     mut headers: HeaderMap,
+                     trailers: Option<&HeaderMap>)
+    -> http::Response<ResponseBody<B>>
+{
+    if let Some(trailers) = trailers {
+        prefix_trailers(trailers, &mut headers);
+    }
+
+    let data = serde_json::to_vec(&error_json(code, message)).expect("JSON values serialize");
+
+    let mut response = http::Response::new(ResponseBody::full(Some(data.into())));
+    *response.status_mut() = transcode::http_status(code);
+    *response.headers_mut() = headers;
+    response.headers_mut()
+        .insert(header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+
+    response
+}
+
+fn streaming_response<B>(format: Format, headers: HeaderMap, body: ResponseBody<B>)
+    -> http::Response<ResponseBody<B>>
+{
+    let content_type = match format {
+        Format::Proto => "application/connect+proto",
+        Format::Json => "application/connect+json",
+    };
+
+    let mut response = http::Response::new(body);
+    *response.headers_mut() = headers;
+    response.headers_mut()
+        .insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+
+    response
+}
+
+/// The envelope ending a streaming response, with the error of the call if
+/// it failed, and the metadata of its trailers.
+fn end_stream(error: Option<(Code, &str)>, trailers: &HeaderMap) -> Bytes {
+    let mut end = Map::new();
+
+    if let Some((code, message)) = error {
+        end.insert("error".to_string(), error_json(code, message));
+    }
+
+    let mut metadata_json = Map::new();
+    for (name, value) in metadata(trailers.clone()).iter() {
+        if let Ok(value) = value.to_str() {
+            let values = metadata_json.entry(name.as_str().to_string())
+                .or_insert_with(|| Value::Array(vec![]));
+
+            if let Value::Array(ref mut values) = *values {
+                values.push(Value::String(value.to_string()));
+            }
+        }
+    }
+
+    if !metadata_json.is_empty() {
+        end.insert("metadata".to_string(), Value::Object(metadata_json));
+    }
+
+    let data = serde_json::to_vec(&Value::Object(end)).expect("JSON values serialize");
+
+    let mut buf = BytesMut::with_capacity(data.len() + 5);
+    buf.put_u8(END_STREAM);
+    buf.put_u32_be(data.len() as u32);
+    buf.put_slice(&data);
+    buf.freeze()
+}
+
+fn error_json(code: Code, message: &str) -> Value {
+    let mut error = Map::new();
+    error.insert("code".to_string(), Value::String(code_name(code).to_string()));
+
+    if !message.is_empty() {
+        error.insert("message".to_string(), Value::String(message.to_string()));
+    }
+
+    Value::Object(error)
+}
+
+/// The name of a code in the Connect protocol.
+fn code_name(code: Code) -> &'static str {
+    match code {
+        Code::CANCELED => "canceled",
+        Code::INVALID_ARGUMENT => "invalid_argument",
+        Code::DEADLINE_EXCEEDED => "deadline_exceeded",
+        Code::NOT_FOUND => "not_found",
+        Code::ALREADY_EXISTS => "already_exists",
+        Code::PERMISSION_DENIED => "permission_denied",
+        Code::RESOURCE_EXHAUSTED => "resource_exhausted",
+        Code::FAILED_PRECONDITION => "failed_precondition",
+        Code::ABORTED => "aborted",
+        Code::OUT_OF_RANGE => "out_of_range",
+        Code::UNIMPLEMENTED => "unimplemented",
+        Code::INTERNAL => "internal",
+        Code::UNAVAILABLE => "unavailable",
+        Code::DATA_LOSS => "data_loss",
+        Code::UNAUTHENTICATED => "unauthenticated",
+        _ => "unknown",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use transcode::descriptor::{self, Field, MessageType, Method};
+
+    use futures::future::{self, FutureResult};
+
+    /// Answers the call with its response, recording the request head and
+    /// body.
+    struct MockService {
+        requests: Arc<Mutex<Vec<(http::request::Parts, Bytes)>>>,
+        response: Option<http::Response<MockBody>>,
+    }
+
+    struct MockBody {
+        data: Option<Bytes>,
+        trailers: Option<HeaderMap>,
+    }
+
+    fn mock_server(response: http::Response<MockBody>)
+        -> (Arc<Mutex<Vec<(http::request::Parts, Bytes)>>>, ConnectServer<MockService>)
+    {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let inner = MockService {
+            requests: requests.clone(),
+            response: Some(response),
+        };
+
+        let mut server = ConnectServer::new(inner, Uri::from_static("http://users"));
+        server.descriptors = Arc::new(descriptors());
+
+        (requests, server)
+    }
+
+    /// The types of `test.Service`, with the unary `Method` and the
+    /// server streaming `Watch`.
+    fn descriptors() -> Descriptors {
+        let mut descriptors = Descriptors::default();
+
+        descriptors.messages.insert("test.Request".to_string(), MessageType {
+            fields: vec![field("name", 1)],
+            map_entry: false,
+        });
+        descriptors.messages.insert("test.Reply".to_string(), MessageType {
+            fields: vec![field("message", 1)],
+            map_entry: false,
+        });
+
+        descriptors.methods.insert("/test.Service/Method".to_string(), method(false));
+        descriptors.methods.insert("/test.Service/Watch".to_string(), method(true));
+
+        descriptors
+    }
+
+    fn field(name: &str, number: u32) -> Field {
+        Field {
+            name: name.to_string(),
+            json_name: name.to_string(),
+            number,
+            kind: descriptor::Kind::String,
+            repeated: false,
+        }
+    }
+
+    fn method(server_streaming: bool) -> Method {
+        Method {
+            input_type: "test.Request".to_string(),
+            output_type: "test.Reply".to_string(),
+            client_streaming: false,
+            server_streaming,
+        }
+    }
+
+    fn request(path: &str, content_type: &str, body: &[u8]) -> http::Request<MockBody> {
+        http::Request::builder()
+            .method("POST")
+            .uri(path)
+            .header("content-type", content_type)
+            .body(MockBody { data: Some(Bytes::from(body)), trailers: None })
+            .unwrap()
+    }
+
+    /// A response carrying `message`, ending with `trailers`.
+    fn grpc_response(message: Option<&[u8]>, trailers: &[(&'static str, &'static str)])
+        -> http::Response<MockBody>
+    {
+        let mut map = HeaderMap::new();
+        for &(name, value) in trailers {
+            map.insert(name, HeaderValue::from_static(value));
+        }
+
+        http::Response::new(MockBody {
+            data: message.map(|message| envelope(0, message)),
+            trailers: Some(map),
+        })
+    }
+
+    /// Returns the envelope of `message`, the same as its gRPC frame.
+    fn envelope(flags: u8, message: &[u8]) -> Bytes {
+        let mut buf = BytesMut::with_capacity(5 + message.len());
+        buf.put_u8(flags);
+        buf.put_u32_be(message.len() as u32);
+        buf.put_slice(message);
+        buf.freeze()
+    }
+
+    fn call(server: ConnectServer<MockService>, request: http::Request<MockBody>)
+        -> http::Response<ResponseBody<MockBody>>
+    {
+        let mut server = server;
+        future::lazy(move || server.call(request)).wait().unwrap()
+    }
+
+    /// Read the data of `body` to its end.
+    fn read<B>(body: &mut ResponseBody<B>) -> Vec<Bytes>
+    where B: Body,
+          B::Data: Into<Bytes>,
+    {
+        let mut data = vec![];
+
+        while let Async::Ready(Some(chunk)) = body.poll_data().unwrap() {
+            data.push(chunk);
+        }
+
+        data
+    }
+
+    /// Returns the JSON of the end of stream message `data`.
+    fn end_of_stream(data: &Bytes) -> Value {
+        assert_eq!(data[0], END_STREAM);
+        serde_json::from_slice(&data[5..]).unwrap()
+    }
+
+    fn json(response: http::Response<ResponseBody<MockBody>>) -> Value {
+        let data = read(&mut response.into_body());
+        serde_json::from_slice(&data[0]).unwrap()
+    }
+
+    impl Service for MockService {
+        type Request = http::Request<BoxBody>;
+        type Response = http::Response<MockBody>;
+        type Error = ();
+        type Future = FutureResult<Self::Response, ()>;
+
+        fn poll_ready(&mut self) -> Poll<(), ()> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+            let (head, mut body) = request.into_parts();
+            let mut data = BytesMut::new();
+
+            loop {
+                match body.poll_data() {
+                    Ok(Async::Ready(Some(chunk))) => data.extend_from_slice(&chunk),
+                    Ok(Async::Ready(None)) => break,
+                    Ok(Async::NotReady) => panic!("request bodies are ready"),
+                    Err(_) => return future::err(()),
+                }
+            }
+
+            self.requests.lock().unwrap().push((head, data.freeze()));
+            future::ok(self.response.take().expect("called once"))
+        }
+    }
+
+    impl Body for MockBody {
+        type Data = Bytes;
+
+        fn is_end_stream(&self) -> bool {
+            self.data.is_none() && self.trailers.is_none()
+        }
+
+        fn poll_data(&mut self) -> Poll<Option<Bytes>, h2::Error> {
+            Ok(Async::Ready(self.data.take()))
+        }
+
+        fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, h2::Error> {
+            Ok(Async::Ready(self.trailers.take()))
+        }
+    }
+
+    #[test]
+    fn protocol_chosen_by_content_type() {
+        let cases = [
+            ("application/proto", Some(Protocol::Unary(Format::Proto))),
+            ("application/json; charset=utf-8", Some(Protocol::Unary(Format::Json))),
+            ("application/connect+proto", Some(Protocol::Streaming(Format::Proto))),
+            ("application/connect+json", Some(Protocol::Streaming(Format::Json))),
+            ("application/grpc", Some(Protocol::Grpc)),
+            ("application/grpc+proto", Some(Protocol::Grpc)),
+            ("text/plain", None),
+        ];
+
+        for &(content_type, expected) in &cases {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+
+            assert_eq!(Protocol::from_headers(&headers), expected, "{}", content_type);
+        }
+
+        assert_eq!(Protocol::from_headers(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn unsupported_requests_answered_without_calling() {
+        let (requests, server) = mock_server(grpc_response(None, &[]));
+        let response = call(server, request("/test.Service/Method", "text/plain", b""));
+
+        assert_eq!(response.status(), http::StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert!(requests.lock().unwrap().is_empty());
+
+        let (requests, server) = mock_server(grpc_response(None, &[]));
+        let mut get = request("/test.Service/Method", "application/proto", b"");
+        *get.method_mut() = http::Method::GET;
+        let response = call(server, get);
+
+        assert_eq!(response.status(), http::StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers()[header::ALLOW], "POST");
+        assert!(requests.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn grpc_call_forwarded_as_it_is() {
+        let (requests, server) = mock_server(grpc_response(Some(b"reply"), &[("grpc-status", "0")]));
+
+        let response = call(server, request("/test.Service/Method", "application/grpc", &envelope(0, b"req")));
+
+        let mut body = response.into_body();
+        assert_eq!(read(&mut body), vec![envelope(0, b"reply")]);
+        match body.poll_trailers().unwrap() {
+            Async::Ready(Some(trailers)) => assert_eq!(trailers["grpc-status"], "0"),
+            _ => panic!("trailers not forwarded"),
+        }
+
+        let requests = requests.lock().unwrap();
+        let (ref head, ref data) = requests[0];
+        assert_eq!(head.uri, "/test.Service/Method");
+        assert_eq!(head.headers[header::CONTENT_TYPE], "application/grpc");
+        assert_eq!(*data, envelope(0, b"req"));
+    }
+
+    #[test]
+    fn unary_call_forwarded_with_metadata_and_timeout() {
+        let trailers = [("grpc-status", "0"), ("x-trace", "t")];
+        let (requests, server) = mock_server(grpc_response(Some(b"reply"), &trailers));
+
+        let mut request = request("/test.Service/Method", "application/proto", b"req");
+        request.headers_mut().insert("connect-protocol-version", HeaderValue::from_static("1"));
+        request.headers_mut().insert("connect-timeout-ms", HeaderValue::from_static("250"));
+        request.headers_mut().insert("x-user", HeaderValue::from_static("a"));
+
+        let response = call(server, request);
+
+        assert_eq!(response.status(), http::StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/proto");
+        assert_eq!(response.headers()["trailer-x-trace"], "t");
+        assert!(!response.headers().contains_key("trailer-grpc-status"));
+        assert_eq!(read(&mut response.into_body()), vec![Bytes::from_static(b"reply")]);
+
+        let requests = requests.lock().unwrap();
+        let (ref head, ref data) = requests[0];
+        assert_eq!(head.uri, "http://users/test.Service/Method");
+        assert_eq!(head.headers[header::CONTENT_TYPE], "application/grpc+proto");
+        assert_eq!(head.headers["x-user"], "a");
+        assert_eq!(head.headers["grpc-timeout"], timeout::encode(Duration::from_millis(250)));
+        assert!(!head.headers.contains_key("connect-protocol-version"));
+        assert!(!head.headers.contains_key("connect-timeout-ms"));
+        assert_eq!(*data, envelope(0, b"req"));
+    }
+
+    #[test]
+    fn unary_json_call_transcoded_with_the_method_types() {
+        let (requests, server) = mock_server(grpc_response(Some(b"\x0a\x02hi"), &[("grpc-status", "0")]));
+
+        let response = call(server, request("/test.Service/Method", "application/json", br#"{"name":"x"}"#));
+
+        assert_eq!(response.status(), http::StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        assert_eq!(json(response)["message"], "hi");
+
+        assert_eq!(requests.lock().unwrap()[0].1, envelope(0, b"\x0a\x01x"));
+    }
+
+    #[test]
+    fn unary_error_answered_with_http_status_and_json() {
+        let trailers = [("grpc-status", "5"), ("grpc-message", "no%20such%20user")];
+        let (_, server) = mock_server(grpc_response(None, &trailers));
+
+        let response = call(server, request("/test.Service/Method", "application/proto", b""));
+
+        assert_eq!(response.status(), http::StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+
+        let error = json(response);
+        assert_eq!(error["code"], "not_found");
+        assert_eq!(error["message"], "no such user");
+    }
+
+    #[test]
+    fn unary_calls_of_unsupported_methods_fail() {
+        let cases = [
+            ("/test.Service/Watch", "application/proto", None),
+            ("/test.Service/Other", "application/json", None),
+            ("/test.Service/Method", "application/proto", Some("gzip")),
+        ];
+
+        for &(path, content_type, encoding) in &cases {
+            let (requests, server) = mock_server(grpc_response(None, &[]));
+
+            let mut request = request(path, content_type, b"");
+            if let Some(encoding) = encoding {
+                request.headers_mut().insert("content-encoding", HeaderValue::from_static(encoding));
+            }
+
+            let response = call(server, request);
+
+            assert_eq!(response.status(), http::StatusCode::NOT_IMPLEMENTED, "{}", path);
+            assert_eq!(json(response)["code"], "unimplemented");
+            assert!(requests.lock().unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn streaming_call_ends_with_metadata_of_trailers() {
+        let trailers = [("grpc-status", "0"), ("x-trace", "t")];
+        let (requests, server) = mock_server(grpc_response(Some(b"one"), &trailers));
+
+        let response = call(server, request("/test.Service/Watch", "application/connect+proto", &envelope(0, b"a")));
+
+        assert_eq!(response.status(), http::StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/connect+proto");
+
+        let data = read(&mut response.into_body());
+        assert_eq!(data.len(), 2);
+        assert_eq!(data[0], envelope(0, b"one"));
+
+        let end = end_of_stream(&data[1]);
+        assert_eq!(end["metadata"]["x-trace"][0], "t");
+        assert!(end.get("error").is_none());
+
+        assert_eq!(requests.lock().unwrap()[0].1, envelope(0, b"a"));
+    }
+
+    #[test]
+    fn streaming_call_ends_with_error() {
+        let (_, server) = mock_server(grpc_response(None, &[("grpc-status", "7")]));
+
+        let response = call(server, request("/test.Service/Watch", "application/connect+proto", &envelope(0, b"a")));
+
+        assert_eq!(response.status(), http::StatusCode::OK);
+
+        let data = read(&mut response.into_body());
+        assert_eq!(data.len(), 1);
+        assert_eq!(end_of_stream(&data[0])["error"]["code"], "permission_denied");
+    }
+
+    #[test]
+    fn invalid_request_message_rejected() {
+        let (_, server) = mock_server(grpc_response(None, &[]));
+        let response = call(server, request("/test.Service/Method", "application/json", b"{"));
+
+        assert_eq!(response.status(), http::StatusCode::BAD_REQUEST);
+        assert_eq!(json(response)["code"], "invalid_argument");
+
+        let (_, server) = mock_server(grpc_response(None, &[]));
+        let response = call(server, request("/test.Service/Watch", "application/connect+json", &envelope(0, b"{")));
+
+        assert_eq!(response.status(), http::StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/connect+json");
+
+        let data = read(&mut response.into_body());
+        assert_eq!(end_of_stream(&data[0])["error"]["code"], "invalid_argument");
+    }
+
+    #[test]
+    fn code_names_follow_the_connect_protocol() {
+        assert_eq!(code_name(Code::DATA_LOSS), "data_loss");
+        assert_eq!(code_name(Code::CANCELED), "canceled");
+        assert_eq!(code_name(Code::UNKNOWN), "unknown");
+    }
+}
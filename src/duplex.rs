@@ -0,0 +1,183 @@
+//! An in-memory, bidirectional byte stream.
+//!
+//! `duplex` returns the two ends of a connection that never leaves the
+//! process. Bytes written to one end are read from the other, so HTTP/2.0
+//! clients and servers can run on it exactly as they would on a socket.
+
+use bytes::BytesMut;
+use futures::{Async, Poll};
+use futures::task::{self, Task};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use std::{cmp, fmt, io};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// The number of bytes buffered in each direction before writes wait for
+/// the other end to read.
+const DEFAULT_CAPACITY: usize = 64 * 1024;
+
+/// One end of an in-memory connection.
+///
+/// Dropping or shutting down an end makes reads on the other end return
+/// EOF once the buffered bytes have been read.
+pub struct DuplexStream {
+    read: Arc<Mutex<Pipe>>,
+    write: Arc<Mutex<Pipe>>,
+}
+
+/// Bytes flowing in one direction.
+#[derive(Debug)]
+struct Pipe {
+    buf: BytesMut,
+    capacity: usize,
+    /// The writing end shut down or was dropped.
+    closed: bool,
+    /// The reading end was dropped.
+    abandoned: bool,
+    read_task: Option<Task>,
+    write_task: Option<Task>,
+}
+
+// ===== impl DuplexStream =====
+
+impl DuplexStream {
+    fn read_pipe(&self) -> MutexGuard<Pipe> {
+        lock(&self.read)
+    }
+
+    fn write_pipe(&self) -> MutexGuard<Pipe> {
+        lock(&self.write)
+    }
+}
+
+impl io::Read for DuplexStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut pipe = self.read_pipe();
+
+        if pipe.buf.is_empty() {
+            if pipe.closed || buf.is_empty() {
+                return Ok(0);
+            }
+
+            pipe.read_task = Some(task::current());
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+
+        let n = cmp::min(buf.len(), pipe.buf.len());
+        buf[..n].copy_from_slice(&pipe.buf.split_to(n));
+
+        if let Some(task) = pipe.write_task.take() {
+            task.notify();
+        }
+
+        Ok(n)
+    }
+}
+
+impl io::Write for DuplexStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut pipe = self.write_pipe();
+
+        if pipe.abandoned || pipe.closed {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+
+        let available = pipe.capacity - pipe.buf.len();
+        if available == 0 && !buf.is_empty() {
+            pipe.write_task = Some(task::current());
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+
+        let n = cmp::min(buf.len(), available);
+        pipe.buf.extend_from_slice(&buf[..n]);
+
+        if let Some(task) = pipe.read_task.take() {
+            task.notify();
+        }
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsyncRead for DuplexStream {}
+
+impl AsyncWrite for DuplexStream {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.write_pipe().close();
+        Ok(Async::Ready(()))
+    }
+}
+
+impl Drop for DuplexStream {
+    fn drop(&mut self) {
+        self.write_pipe().close();
+
+        let mut read = self.read_pipe();
+        read.abandoned = true;
+        if let Some(task) = read.write_task.take() {
+            task.notify();
+        }
+    }
+}
+
+impl fmt::Debug for DuplexStream {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("DuplexStream")
+            .field("readable", &self.read_pipe().buf.len())
+            .field("written", &self.write_pipe().buf.len())
+            .finish()
+    }
+}
+
+// ===== impl Pipe =====
+
+impl Pipe {
+    fn new(capacity: usize) -> Arc<Mutex<Self>> {
+        Arc::new(Mutex::new(Pipe {
+            buf: BytesMut::with_capacity(capacity),
+            capacity,
+            closed: false,
+            abandoned: false,
+            read_task: None,
+            write_task: None,
+        }))
+    }
+
+    fn close(&mut self) {
+        self.closed = true;
+        if let Some(task) = self.read_task.take() {
+            task.notify();
+        }
+    }
+}
+
+// ===== utility fns =====
+
+/// Returns the two ends of an in-memory connection.
+pub fn duplex() -> (DuplexStream, DuplexStream) {
+    duplex_with_capacity(DEFAULT_CAPACITY)
+}
+
+/// Like `duplex`, but buffers up to `capacity` bytes in each direction.
+pub fn duplex_with_capacity(capacity: usize) -> (DuplexStream, DuplexStream) {
+    assert!(capacity > 0, "duplex capacity must be positive");
+
+    let a = Pipe::new(capacity);
+    let b = Pipe::new(capacity);
+
+    let one = DuplexStream { read: a.clone(), write: b.clone() };
+    let other = DuplexStream { read: b, write: a };
+
+    (one, other)
+}
+
+fn lock(pipe: &Mutex<Pipe>) -> MutexGuard<Pipe> {
+    match pipe.lock() {
+        Ok(pipe) => pipe,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
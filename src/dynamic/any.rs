@@ -0,0 +1,200 @@
+use super::{DescriptorPool, DynamicMessage, MessageError};
+use base64;
+use rpc::{self, Any, ErrorDetail};
+use rpc::error_details::{
+    BadRequest,
+    DebugInfo,
+    ErrorInfo,
+    Help,
+    LocalizedMessage,
+    PreconditionFailure,
+    QuotaFailure,
+    RequestInfo,
+    ResourceInfo,
+    RetryInfo,
+};
+
+use prost::Message;
+use serde_json::Value;
+
+use std::any;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+type Unpack = Arc<Fn(&[u8]) -> Option<Box<any::Any + Send>> + Send + Sync>;
+
+/// Unpacks `Any` messages, such as error details and `Any` fields, by the
+/// type name of their type URL.
+///
+/// Types registered with a generated message are unpacked to it, and every
+/// other type known to the descriptor pool to a `DynamicMessage`.
+#[derive(Clone)]
+pub struct AnyRegistry {
+    pool: DescriptorPool,
+    generated: HashMap<String, Unpack>,
+}
+
+/// A message unpacked from an `Any`.
+pub enum Unpacked {
+    /// A message of a registered generated type.
+    Generated(Box<any::Any + Send>),
+
+    /// A message of a type of the descriptor pool.
+    Dynamic(DynamicMessage),
+}
+
+// ===== impl AnyRegistry =====
+
+impl AnyRegistry {
+    /// Unpack the message types of `pool` as dynamic messages.
+    pub fn new(pool: DescriptorPool) -> Self {
+        AnyRegistry {
+            pool,
+            generated: HashMap::new(),
+        }
+    }
+
+    /// Unpack messages of type `type_name`, or of the type named by a type
+    /// URL, as `T`.
+    pub fn register<T>(mut self, type_name: &str) -> Self
+    where T: Message + Default + Send + 'static,
+    {
+        let unpack: Unpack = Arc::new(|bytes: &[u8]| {
+            T::decode(bytes).ok().map(|message| Box::new(message) as Box<any::Any + Send>)
+        });
+
+        self.generated.insert(rpc::type_name(type_name).to_string(), unpack);
+        self
+    }
+
+    /// Unpack the `google.rpc` error details as their `rpc::error_details`
+    /// messages.
+    pub fn register_error_details(self) -> Self {
+        self.register_detail::<RetryInfo>()
+            .register_detail::<DebugInfo>()
+            .register_detail::<QuotaFailure>()
+            .register_detail::<ErrorInfo>()
+            .register_detail::<PreconditionFailure>()
+            .register_detail::<BadRequest>()
+            .register_detail::<RequestInfo>()
+            .register_detail::<ResourceInfo>()
+            .register_detail::<Help>()
+            .register_detail::<LocalizedMessage>()
+    }
+
+    fn register_detail<T>(self) -> Self
+    where T: ErrorDetail + Send + 'static,
+    {
+        self.register::<T>(T::TYPE_URL)
+    }
+
+    /// Returns the descriptor pool of the dynamic messages.
+    pub fn pool(&self) -> &DescriptorPool {
+        &self.pool
+    }
+
+    /// Returns true if messages named by `type_url` can be unpacked.
+    pub fn has_type(&self, type_url: &str) -> bool {
+        let type_name = rpc::type_name(type_url);
+        self.generated.contains_key(type_name) || self.pool.has_message(type_name)
+    }
+
+    /// Unpack `any`, preferring its registered generated type.
+    pub fn unpack(&self, any: &Any) -> Result<Unpacked, MessageError> {
+        match self.generated.get(any.type_name()) {
+            Some(unpack) => unpack(&any.value)
+                .map(Unpacked::Generated)
+                .ok_or_else(|| MessageError::new(format!("invalid message of type {}", any.type_name()))),
+            None => self.unpack_dynamic(any).map(Unpacked::Dynamic),
+        }
+    }
+
+    /// Unpack `any` as a dynamic message, whether or not its type is
+    /// registered with a generated message.
+    pub fn unpack_dynamic(&self, any: &Any) -> Result<DynamicMessage, MessageError> {
+        DynamicMessage::decode(&self.pool, any.type_name(), &any.value)
+    }
+
+    /// Unpack an `Any` field of a dynamic message, given as JSON.
+    ///
+    /// `Any` fields are mapped like any other message, as an object with
+    /// `typeUrl` and base64 `value` members.
+    pub fn unpack_json(&self, value: &Value) -> Result<Unpacked, MessageError> {
+        let invalid = || MessageError::new("invalid google.protobuf.Any".to_string());
+
+        let type_url = value.get("typeUrl")
+            .or_else(|| value.get("type_url"))
+            .and_then(Value::as_str)
+            .ok_or_else(invalid)?;
+
+        let value = match value.get("value").and_then(Value::as_str) {
+            Some(value) => base64::decode(value.as_bytes()).ok_or_else(invalid)?,
+            None => vec![],
+        };
+
+        self.unpack(&Any {
+            type_url: type_url.to_string(),
+            value,
+        })
+    }
+
+    /// Unpack the details of `status`.
+    ///
+    /// Details of unknown types, and those that fail to decode, are
+    /// skipped.
+    pub fn details(&self, status: &rpc::Status) -> Vec<Unpacked> {
+        status.details.iter()
+            .filter_map(|detail| self.unpack(detail).ok())
+            .collect()
+    }
+
+    /// Pack `message` in an `Any`, with the `type.googleapis.com/` prefix.
+    pub fn pack(&self, message: &DynamicMessage) -> Result<Any, MessageError> {
+        Ok(Any {
+            type_url: rpc::type_url(message.type_name()),
+            value: message.encode()?,
+        })
+    }
+}
+
+impl fmt::Debug for AnyRegistry {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("AnyRegistry")
+            .field("pool", &self.pool)
+            .field("generated", &self.generated.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+// ===== impl Unpacked =====
+
+impl Unpacked {
+    /// Returns the generated message, if it is a `T`.
+    pub fn downcast<T: 'static>(self) -> Result<T, Self> {
+        match self {
+            Unpacked::Generated(message) => message.downcast::<T>()
+                .map(|message| *message)
+                .map_err(Unpacked::Generated),
+            dynamic => Err(dynamic),
+        }
+    }
+
+    /// Returns the dynamic message, if the type isn't registered with a
+    /// generated message.
+    pub fn as_dynamic(&self) -> Option<&DynamicMessage> {
+        match *self {
+            Unpacked::Dynamic(ref message) => Some(message),
+            Unpacked::Generated(_) => None,
+        }
+    }
+}
+
+impl fmt::Debug for Unpacked {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Unpacked::Generated(_) => fmt.debug_tuple("Generated").field(&"..").finish(),
+            Unpacked::Dynamic(ref message) => fmt.debug_tuple("Dynamic").field(message).finish(),
+        }
+    }
+}
@@ -0,0 +1,452 @@
+use {Code, Request, Response, Status};
+use client::{self, server_streaming, BuilderError};
+use codec;
+use generic::{BytesCodec, Encode, Streaming};
+use reflection::{self, ServerReflectionRequest, ServerReflectionResponse};
+use transcode::DescriptorError;
+use transcode::descriptor::{Descriptors, FileDescriptorProto};
+use transcode::json;
+use super::DescriptorPool;
+
+use bytes::Bytes;
+use futures::{stream, Future, Stream, Poll, Async};
+use http::{self, header, Uri};
+use http::header::HeaderValue;
+use prost::Message;
+use serde_json::{self, Value};
+use tower_h2::{Body, BoxBody, HttpService};
+
+use std::fmt;
+use std::mem;
+use std::sync::Arc;
+
+/// Calls methods by name, with JSON messages.
+pub struct DynamicClient<T> {
+    grpc: client::Grpc<T>,
+    origin: Uri,
+    pool: DescriptorPool,
+}
+
+/// Fetches the descriptors of a server's services from its reflection
+/// service.
+pub struct Reflect<T>
+where T: HttpService,
+{
+    client: Option<DynamicClient<T>>,
+    state: Reflecting<T::Future, T::ResponseBody>,
+
+    /// The services whose files are still to be fetched.
+    services: Vec<String>,
+    descriptors: Descriptors,
+}
+
+/// The response future returned by `DynamicClient::call`.
+pub struct ResponseFuture<F, B> {
+    state: State<F, B>,
+    descriptors: Arc<Descriptors>,
+    output_type: String,
+}
+
+enum Reflecting<F, B> {
+    /// Waiting for the client to be ready to send a request.
+    Ready(Option<ServerReflectionRequest>),
+
+    /// Waiting for the response head.
+    Calling(server_streaming::ResponseFuture<ServerReflectionResponse, F>),
+
+    /// Reading the responses.
+    Reading(codec::Streaming<ServerReflectionResponse, B>),
+}
+
+enum State<F, B> {
+    /// Waiting for the response head.
+    Calling(F),
+
+    /// Reading the response messages.
+    Reading(http::response::Parts, Streaming<BytesCodec, B>, Vec<Value>),
+
+    /// The call failed before it was sent.
+    Failed(Option<Status>),
+}
+
+// ===== impl DynamicClient =====
+
+impl<T> DynamicClient<T>
+where T: HttpService<RequestBody = BoxBody>,
+{
+    /// Call the methods of the server at `origin` over `inner`.
+    ///
+    /// No method can be called until the descriptors are fetched with
+    /// `reflect` or registered.
+    pub fn new(inner: T, origin: Uri) -> Result<Self, BuilderError> {
+        let grpc = client::Builder::new()
+            .uri(origin.clone())
+            .build(inner)?;
+
+        Ok(DynamicClient {
+            grpc,
+            origin,
+            pool: DescriptorPool::new(),
+        })
+    }
+
+    /// Fetch the descriptors of every service of the server from its
+    /// reflection service.
+    ///
+    /// The fetched descriptors replace those known to the client.
+    pub fn reflect(self) -> Reflect<T> {
+        let request = ServerReflectionRequest {
+            list_services: Some(String::new()),
+            ..Default::default()
+        };
+
+        Reflect {
+            client: Some(self),
+            state: Reflecting::Ready(Some(request)),
+            services: vec![],
+            descriptors: Descriptors::default(),
+        }
+    }
+
+    /// Add the types and methods of an encoded `FileDescriptorSet`.
+    pub fn register_file_descriptor_set(self, encoded: &[u8]) -> Result<Self, DescriptorError> {
+        let DynamicClient { grpc, origin, pool } = self;
+        let pool = pool.register_file_descriptor_set(encoded)?;
+
+        Ok(DynamicClient { grpc, origin, pool })
+    }
+
+    /// Returns the descriptors of the known types and methods.
+    pub fn pool(&self) -> &DescriptorPool {
+        &self.pool
+    }
+
+    /// Returns the paths of the known methods, such as
+    /// `/helloworld.Greeter/SayHello`.
+    pub fn methods(&self) -> Vec<&str> {
+        self.pool.methods()
+    }
+
+    /// Returns true if `method` is a known method.
+    pub fn has_method(&self, method: &str) -> bool {
+        self.pool.method(&method_path(method)).is_some()
+    }
+
+    /// Poll the client to be ready to make a call.
+    pub fn poll_ready(&mut self) -> Poll<(), ::Error<T::Error>> {
+        self.grpc.poll_ready()
+    }
+
+    /// Call `method`, named `pkg.Service/Method` or `pkg.Service.Method`,
+    /// with the JSON request message.
+    ///
+    /// The request of a client streaming method is a JSON array of its
+    /// messages.
+    pub fn call(&mut self, method: &str, request: Request<Value>)
+        -> ResponseFuture<T::Future, T::ResponseBody>
+    {
+        let path = method_path(method);
+        let descriptors = self.pool.descriptors().clone();
+
+        let (input_type, output_type, client_streaming) = match descriptors.methods.get(&path) {
+            Some(m) => (m.input_type.clone(), m.output_type.clone(), m.client_streaming),
+            None => {
+                debug!("unknown method; method={}", method);
+                return ResponseFuture::failed(descriptors.clone(), Status::UNIMPLEMENTED);
+            }
+        };
+
+        let mut messages = vec![];
+
+        {
+            let values = match *request.get_ref() {
+                Value::Array(ref values) if client_streaming => values.iter().collect(),
+                _ if client_streaming => {
+                    debug!("client streaming request is not an array; method={}", method);
+                    return ResponseFuture::failed(descriptors.clone(), Status::INVALID_ARGUMENT);
+                }
+                ref value => vec![value],
+            };
+
+            for value in values {
+                let mut buf = vec![];
+
+                if let Err(e) = json::encode(&descriptors, &input_type, value, &mut buf) {
+                    debug!("request message could not be encoded; method={}, error={}", method, e);
+                    return ResponseFuture::failed(descriptors.clone(), Status::INVALID_ARGUMENT);
+                }
+
+                messages.push(buf);
+            }
+        }
+
+        let mut parts = http::uri::Parts::default();
+        parts.scheme = self.origin.scheme_part().cloned();
+        parts.authority = self.origin.authority_part().cloned();
+        parts.path_and_query = Some(match path.parse() {
+            Ok(path) => path,
+            Err(_) => return ResponseFuture::failed(descriptors.clone(), Status::INVALID_ARGUMENT),
+        });
+
+        let uri = match Uri::from_parts(parts) {
+            Ok(uri) => uri,
+            Err(_) => return ResponseFuture::failed(descriptors.clone(), Status::INTERNAL),
+        };
+
+        let body = Encode::new(BytesCodec, stream::iter_ok::<_, ::Error>(messages), false);
+        let mut request = request
+            .map(|_| BoxBody::new(Box::new(body)))
+            .into_http(uri);
+
+        request.headers_mut()
+            .insert(header::TE, HeaderValue::from_static("trailers"));
+        request.headers_mut()
+            .insert(header::CONTENT_TYPE, HeaderValue::from_static("application/grpc+proto"));
+
+        ResponseFuture {
+            state: State::Calling(self.grpc.get_mut().call(request)),
+            descriptors,
+            output_type,
+        }
+    }
+
+    /// Call `method` with a request message given as JSON text.
+    pub fn call_json(&mut self, method: &str, json: &str)
+        -> ResponseFuture<T::Future, T::ResponseBody>
+    {
+        match serde_json::from_str(json) {
+            Ok(value) => self.call(method, Request::new(value)),
+            Err(e) => {
+                debug!("invalid JSON request; method={}, error={}", method, e);
+                ResponseFuture::failed(self.pool.descriptors().clone(), Status::INVALID_ARGUMENT)
+            }
+        }
+    }
+
+    /// Returns a reference to the inner HTTP/2.0 service.
+    pub fn get_ref(&self) -> &T {
+        self.grpc.get_ref()
+    }
+
+    /// Returns a mutable reference to the inner HTTP/2.0 service.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.grpc.get_mut()
+    }
+}
+
+impl<T> fmt::Debug for DynamicClient<T>
+where T: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("DynamicClient")
+            .field("grpc", &self.grpc)
+            .field("origin", &self.origin)
+            .field("pool", &self.pool)
+            .finish()
+    }
+}
+
+// ===== impl Reflect =====
+
+impl<T> Future for Reflect<T>
+where T: HttpService<RequestBody = BoxBody>,
+{
+    type Item = DynamicClient<T>;
+    type Error = ::Error<T::Error>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                Reflecting::Ready(ref mut request) => {
+                    let client = self.client.as_mut().expect("polled after complete");
+                    try_ready!(client.grpc.poll_ready());
+
+                    let request = Request::new(request.take().expect("polled after complete"));
+                    let path = http::uri::PathAndQuery::from_static(reflection::V1_PATH);
+
+                    Reflecting::Calling(client.grpc.server_streaming(request, path))
+                }
+                Reflecting::Calling(ref mut future) => {
+                    Reflecting::Reading(try_ready!(future.poll()).into_inner())
+                }
+                Reflecting::Reading(ref mut responses) => {
+                    let response = try_ready!(responses.poll()
+                        .map_err(|e| ::Error::Grpc(Status::from(e))));
+
+                    if let Some(response) = response {
+                        handle(response, &mut self.services, &mut self.descriptors)?;
+                        continue;
+                    }
+
+                    match self.services.pop() {
+                        Some(service) => Reflecting::Ready(Some(ServerReflectionRequest {
+                            file_containing_symbol: Some(service),
+                            ..Default::default()
+                        })),
+                        None => {
+                            let mut client = self.client.take().expect("polled after complete");
+                            let descriptors = mem::replace(&mut self.descriptors, Descriptors::default());
+                            client.pool = DescriptorPool::from_descriptors(descriptors);
+
+                            return Ok(Async::Ready(client));
+                        }
+                    }
+                }
+            };
+
+            self.state = next;
+        }
+    }
+}
+
+impl<T> fmt::Debug for Reflect<T>
+where T: HttpService + fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Reflect")
+            .field("client", &self.client)
+            .field("services", &self.services)
+            .finish()
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F, B> ResponseFuture<F, B> {
+    fn failed(descriptors: Arc<Descriptors>, status: Status) -> Self {
+        ResponseFuture {
+            state: State::Failed(Some(status)),
+            descriptors,
+            output_type: String::new(),
+        }
+    }
+}
+
+impl<F, B> Future for ResponseFuture<F, B>
+where F: Future<Item = http::Response<B>>,
+      B: Body,
+      B::Data: Into<Bytes>,
+{
+    type Item = Response<Vec<Value>>;
+    type Error = ::Error<F::Error>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                State::Calling(ref mut future) => {
+                    let response = try_ready!(future.poll().map_err(::Error::Inner));
+                    let (head, body) = response.into_parts();
+
+                    // A trailers-only response.
+                    if let Some(status) = head.headers.get("grpc-status") {
+                        let status = Status::from_bytes(status.as_ref());
+
+                        if status.code() != Code::OK {
+                            return Err(::Error::Grpc(status));
+                        }
+                    }
+
+                    State::Reading(head, Streaming::new(BytesCodec, body, true), vec![])
+                }
+                State::Reading(_, ref mut messages, ref mut values) => {
+                    let message = try_ready!(messages.poll()
+                        .map_err(|e| ::Error::Grpc(Status::from(e))));
+
+                    let message = match message {
+                        Some(message) => message,
+                        None => break,
+                    };
+
+                    let value = json::decode(&self.descriptors, &self.output_type, &message)
+                        .map_err(|e| {
+                            debug!("response message could not be decoded; error={}", e);
+                            ::Error::Grpc(Status::INTERNAL)
+                        })?;
+
+                    values.push(value);
+                    continue;
+                }
+                State::Failed(ref mut status) => {
+                    let status = status.take().expect("polled after complete");
+                    return Err(::Error::Grpc(status));
+                }
+            };
+
+            self.state = next;
+        }
+
+        // The response stream has ended.
+        match mem::replace(&mut self.state, State::Failed(None)) {
+            State::Reading(head, _, values) => {
+                let response = http::Response::from_parts(head, values);
+                Ok(Async::Ready(Response::from_http(response)))
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl<F, B> fmt::Debug for ResponseFuture<F, B> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let state = match self.state {
+            State::Calling(..) => "Calling",
+            State::Reading(..) => "Reading",
+            State::Failed(..) => "Failed",
+        };
+
+        fmt.debug_struct("dynamic::ResponseFuture")
+            .field("state", &state)
+            .field("output_type", &self.output_type)
+            .finish()
+    }
+}
+
+// ===== utility fns =====
+
+/// Handle a reflection response, adding the files it carries to
+/// `descriptors`.
+fn handle<E>(response: ServerReflectionResponse,
+             services: &mut Vec<String>,
+             descriptors: &mut Descriptors)
+    -> Result<(), ::Error<E>>
+{
+    if let Some(error) = response.error_response {
+        debug!("reflection request failed; code={}, message={}",
+               error.error_code, error.error_message);
+        let code = Code::from_i32(error.error_code).unwrap_or(Code::UNKNOWN);
+        return Err(::Error::Grpc(Status::with_code(code)));
+    }
+
+    if let Some(list) = response.list_services_response {
+        services.extend(list.service.into_iter().map(|service| service.name));
+    }
+
+    if let Some(files) = response.file_descriptor_response {
+        for encoded in files.file_descriptor_proto {
+            let file = FileDescriptorProto::decode(encoded)
+                .map_err(|_| ::Error::Grpc(Status::INTERNAL))?;
+
+            descriptors.add(file).map_err(|e| {
+                debug!("invalid file descriptor; error={}", e);
+                ::Error::Grpc(Status::INTERNAL)
+            })?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The path of `method`, named `pkg.Service/Method`, `pkg.Service.Method`
+/// or by its path.
+fn method_path(method: &str) -> String {
+    let method = method.trim_start_matches('/');
+
+    if method.contains('/') {
+        return format!("/{}", method);
+    }
+
+    match method.rfind('.') {
+        Some(i) => format!("/{}/{}", &method[..i], &method[i + 1..]),
+        None => format!("/{}", method),
+    }
+}
@@ -0,0 +1,297 @@
+use transcode::{self, DescriptorError};
+use transcode::descriptor::{Descriptors, Method};
+use transcode::json;
+
+use serde_json::{Map, Value};
+
+use std::{error, fmt};
+use std::sync::Arc;
+
+/// The message types, enums and methods of file descriptor sets loaded at
+/// runtime.
+///
+/// Pools are cheap to clone, as the descriptors are shared.
+#[derive(Clone, Default)]
+pub struct DescriptorPool {
+    descriptors: Arc<Descriptors>,
+}
+
+/// The types of a method known to a `DescriptorPool`.
+#[derive(Debug, Clone, Copy)]
+pub struct MethodDescriptor<'a> {
+    path: &'a str,
+    method: &'a Method,
+}
+
+/// A message of a type known only at runtime.
+///
+/// The fields are held as JSON values, following the proto3 JSON mapping,
+/// and keyed by their JSON names.
+#[derive(Clone)]
+pub struct DynamicMessage {
+    pool: DescriptorPool,
+    type_name: String,
+    fields: Map<String, Value>,
+}
+
+/// Error returned when a dynamic message can't be built, encoded or
+/// decoded.
+#[derive(Debug)]
+pub struct MessageError {
+    message: String,
+}
+
+// ===== impl DescriptorPool =====
+
+impl DescriptorPool {
+    /// Create a pool without descriptors.
+    pub fn new() -> Self {
+        DescriptorPool::default()
+    }
+
+    pub(crate) fn from_descriptors(descriptors: Descriptors) -> Self {
+        DescriptorPool {
+            descriptors: Arc::new(descriptors),
+        }
+    }
+
+    /// Add the types and methods of an encoded `FileDescriptorSet`.
+    ///
+    /// Files already added by name are skipped, so sets sharing imports may
+    /// be added in any order.
+    ///
+    /// # Panics
+    ///
+    /// If the pool was cloned, or messages were built from it.
+    pub fn register_file_descriptor_set(mut self, encoded: &[u8]) -> Result<Self, DescriptorError> {
+        transcode::register(&mut self.descriptors, encoded)?;
+        Ok(self)
+    }
+
+    /// Returns the fully qualified names of the known message types.
+    pub fn messages(&self) -> Vec<&str> {
+        let mut messages: Vec<_> = self.descriptors.messages.keys()
+            .map(|name| &name[..])
+            .collect();
+
+        messages.sort();
+        messages
+    }
+
+    /// Returns true if `type_name`, such as `helloworld.HelloRequest`, is a
+    /// known message type.
+    pub fn has_message(&self, type_name: &str) -> bool {
+        self.descriptors.messages.contains_key(type_name)
+    }
+
+    /// Returns the paths of the known methods, such as
+    /// `/helloworld.Greeter/SayHello`.
+    pub fn methods(&self) -> Vec<&str> {
+        let mut methods: Vec<_> = self.descriptors.methods.keys()
+            .map(|path| &path[..])
+            .collect();
+
+        methods.sort();
+        methods
+    }
+
+    /// Returns the method at `path`, such as `/helloworld.Greeter/SayHello`.
+    pub fn method(&self, path: &str) -> Option<MethodDescriptor> {
+        self.descriptors.methods.iter()
+            .find(|&(p, _)| p == path)
+            .map(|(path, method)| MethodDescriptor { path, method })
+    }
+
+    pub(crate) fn descriptors(&self) -> &Arc<Descriptors> {
+        &self.descriptors
+    }
+}
+
+impl fmt::Debug for DescriptorPool {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("DescriptorPool")
+            .field("messages", &self.descriptors.messages.len())
+            .field("methods", &self.descriptors.methods.len())
+            .finish()
+    }
+}
+
+// ===== impl MethodDescriptor =====
+
+impl<'a> MethodDescriptor<'a> {
+    /// Returns the path of the method.
+    pub fn path(&self) -> &'a str {
+        self.path
+    }
+
+    /// Returns the fully qualified name of the request message type.
+    pub fn input_type(&self) -> &'a str {
+        &self.method.input_type
+    }
+
+    /// Returns the fully qualified name of the response message type.
+    pub fn output_type(&self) -> &'a str {
+        &self.method.output_type
+    }
+
+    /// Returns true if the client sends a stream of messages.
+    pub fn client_streaming(&self) -> bool {
+        self.method.client_streaming
+    }
+
+    /// Returns true if the server sends a stream of messages.
+    pub fn server_streaming(&self) -> bool {
+        self.method.server_streaming
+    }
+}
+
+// ===== impl DynamicMessage =====
+
+impl DynamicMessage {
+    /// Create an empty message of type `type_name`.
+    pub fn new(pool: &DescriptorPool, type_name: &str) -> Result<Self, MessageError> {
+        if !pool.has_message(type_name) {
+            return Err(MessageError::new(format!("unknown message type {}", type_name)));
+        }
+
+        Ok(DynamicMessage {
+            pool: pool.clone(),
+            type_name: type_name.to_string(),
+            fields: Map::new(),
+        })
+    }
+
+    /// Decode `bytes`, an encoded message of type `type_name`.
+    ///
+    /// Unknown fields are dropped.
+    pub fn decode(pool: &DescriptorPool, type_name: &str, bytes: &[u8]) -> Result<Self, MessageError> {
+        let fields = match json::decode(&pool.descriptors, type_name, bytes)? {
+            Value::Object(fields) => fields,
+            _ => unreachable!("messages are decoded as objects"),
+        };
+
+        Ok(DynamicMessage {
+            pool: pool.clone(),
+            type_name: type_name.to_string(),
+            fields,
+        })
+    }
+
+    /// Create a message of type `type_name` from its JSON representation.
+    ///
+    /// Fields may be named by either their JSON or proto names.
+    pub fn from_json(pool: &DescriptorPool, type_name: &str, value: &Value) -> Result<Self, MessageError> {
+        let mut buf = vec![];
+        json::encode(&pool.descriptors, type_name, value, &mut buf)?;
+
+        // Decoding the message normalizes the names and values of fields.
+        DynamicMessage::decode(pool, type_name, &buf)
+    }
+
+    /// Returns the fully qualified name of the message type.
+    pub fn type_name(&self) -> &str {
+        &self.type_name
+    }
+
+    /// Returns the descriptor pool of the message type.
+    pub fn pool(&self) -> &DescriptorPool {
+        &self.pool
+    }
+
+    /// Returns the value of `field`, by either its JSON or proto name, if
+    /// it is set.
+    pub fn get(&self, field: &str) -> Option<&Value> {
+        self.json_name(field).and_then(|name| self.fields.get(name))
+    }
+
+    /// Set `field`, by either its JSON or proto name, to `value`.
+    ///
+    /// Setting a field to `null` clears it.
+    pub fn set(&mut self, field: &str, value: Value) -> Result<(), MessageError> {
+        let name = self.json_name(field)
+            .ok_or_else(|| MessageError::new(format!("unknown field {:?} of {}", field, self.type_name)))?
+            .to_string();
+
+        if value.is_null() {
+            self.fields.remove(&name);
+            return Ok(());
+        }
+
+        let mut object = Map::new();
+        object.insert(name.clone(), value);
+
+        let mut message = DynamicMessage::from_json(&self.pool, &self.type_name, &Value::Object(object))?;
+
+        match message.fields.remove(&name) {
+            Some(value) => self.fields.insert(name, value),
+            // Empty repeated and map fields aren't encoded.
+            None => self.fields.remove(&name),
+        };
+
+        Ok(())
+    }
+
+    /// Clear `field`, returning its previous value.
+    pub fn clear(&mut self, field: &str) -> Option<Value> {
+        let name = self.json_name(field)?.to_string();
+        self.fields.remove(&name)
+    }
+
+    /// Encode the message, for example to send it with `BytesCodec`.
+    pub fn encode(&self) -> Result<Vec<u8>, MessageError> {
+        let mut buf = vec![];
+        json::encode(&self.pool.descriptors, &self.type_name, &self.to_json(), &mut buf)?;
+        Ok(buf)
+    }
+
+    /// Returns the JSON representation of the message.
+    pub fn to_json(&self) -> Value {
+        Value::Object(self.fields.clone())
+    }
+
+    /// Consume the message, returning its JSON representation.
+    pub fn into_json(self) -> Value {
+        Value::Object(self.fields)
+    }
+
+    fn json_name(&self, field: &str) -> Option<&str> {
+        self.pool.descriptors.messages.get(&self.type_name)
+            .and_then(|message| message.field_by_name(field))
+            .map(|field| &field.json_name[..])
+    }
+}
+
+impl fmt::Debug for DynamicMessage {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("DynamicMessage")
+            .field("type_name", &self.type_name)
+            .field("fields", &self.fields)
+            .finish()
+    }
+}
+
+// ===== impl MessageError =====
+
+impl MessageError {
+    pub(crate) fn new(message: String) -> Self {
+        MessageError { message }
+    }
+}
+
+impl From<String> for MessageError {
+    fn from(message: String) -> Self {
+        MessageError::new(message)
+    }
+}
+
+impl fmt::Display for MessageError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(&self.message)
+    }
+}
+
+impl error::Error for MessageError {
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
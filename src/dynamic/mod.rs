@@ -0,0 +1,65 @@
+//! Messages and calls of types known only at runtime.
+//!
+//! A `DescriptorPool` holds the message types and methods of file
+//! descriptor sets loaded at runtime, and `DynamicMessage` is a message of
+//! any of its types. Dynamic messages are encoded to and decoded from
+//! bytes, to be sent with `generic::BytesCodec`, and converted to and from
+//! JSON, so gateways, recorders and test tools can handle any message
+//! without generated code:
+//!
+//! ```ignore
+//! let pool = DescriptorPool::new()
+//!     .register_file_descriptor_set(include_bytes!("helloworld.bin"))?;
+//!
+//! let mut request = DynamicMessage::new(&pool, "helloworld.HelloRequest")?;
+//! request.set("name", json!("world"))?;
+//!
+//! let bytes = request.encode()?;
+//! let request = DynamicMessage::decode(&pool, "helloworld.HelloRequest", &bytes)?;
+//! assert_eq!(request.to_json(), json!({ "name": "world" }));
+//! ```
+//!
+//! `DynamicClient` invokes methods by name, with messages given as JSON
+//! values, such as a map of fields. The types of the methods are fetched
+//! from the server's reflection service, or registered from a file
+//! descriptor set, so generic tools and admin consoles can call any server:
+//!
+//! ```ignore
+//! let client = core.run(DynamicClient::new(conn, uri)?.reflect())?;
+//!
+//! for method in client.methods() {
+//!     println!("{}", method);
+//! }
+//!
+//! let request = Request::new(json!({ "name": "world" }));
+//! let response = core.run(client.call("helloworld.Greeter/SayHello", request))?;
+//! ```
+//!
+//! Messages follow the proto3 JSON mapping, as in `transcode`. A call
+//! responds with the list of its response messages, and a client streaming
+//! call is given a JSON array of its request messages.
+//!
+//! With the `google-rpc` feature, `AnyRegistry` unpacks `Any` messages,
+//! such as the details of a `rpc::Status`, to generated messages of
+//! registered types or to dynamic messages:
+//!
+//! ```ignore
+//! let registry = AnyRegistry::new(pool).register_error_details();
+//!
+//! for detail in registry.details(&status) {
+//!     match detail.downcast::<RetryInfo>() {
+//!         Ok(retry) => println!("retry after {:?}", retry.retry_delay),
+//!         Err(detail) => println!("{:?}", detail),
+//!     }
+//! }
+//! ```
+
+#[cfg(feature = "google-rpc")]
+mod any;
+mod client;
+mod message;
+
+#[cfg(feature = "google-rpc")]
+pub use self::any::{AnyRegistry, Unpacked};
+pub use self::client::{DynamicClient, Reflect, ResponseFuture};
+pub use self::message::{DescriptorPool, DynamicMessage, MessageError, MethodDescriptor};
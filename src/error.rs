@@ -12,6 +12,12 @@ impl<T> From<T> for Error<T> {
     }
 }
 
+impl From<::Status> for Error {
+    fn from(status: ::Status) -> Self {
+        Error::Grpc(status)
+    }
+}
+
 impl From<Error<()>> for h2::Error {
     fn from(_err: Error<()>) -> Self {
         // TODO: implement
@@ -0,0 +1,316 @@
+//! Plain HTTP handlers served next to gRPC methods.
+//!
+//! `Fallback` wraps a server's service and answers requests for a few
+//! registered paths, such as `/metrics`, `/healthz` or webhook endpoints,
+//! with plain HTTP handlers, so a single port serves both gRPC and the
+//! endpoints around it. Every other request is passed to the inner service:
+//!
+//! ```ignore
+//! let new_service = Fallback::new(GreeterServer::new(greeter))
+//!     .route("/healthz", |_request| {
+//!         future::ok(http::Response::new(fallback::full("ok")))
+//!     });
+//!
+//! let h2 = Server::new(new_service, Default::default(), reactor);
+//! ```
+//!
+//! Routes match the request path exactly, whatever the HTTP method.
+
+use bytes::Bytes;
+use futures::{Future, IntoFuture, Poll, Async};
+use h2;
+use http::{self, HeaderMap};
+use tower::{NewService, Service};
+use tower_h2::{Body, BoxBody};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// The future returned by a plain HTTP handler.
+pub type HandlerFuture = Box<Future<Item = http::Response<BoxBody>, Error = h2::Error> + Send>;
+
+type Handler<A> = Arc<Fn(http::Request<A>) -> HandlerFuture + Send + Sync>;
+
+/// Serves registered paths with plain HTTP handlers, and every other path
+/// with the inner service.
+///
+/// `Fallback` may wrap either a `Service` or the `NewService` given to
+/// `tower_h2::Server`.
+pub struct Fallback<S, A> {
+    inner: S,
+    routes: Arc<HashMap<String, Handler<A>>>,
+}
+
+/// Creates `Fallback` services.
+pub struct NewServiceFuture<F, A> {
+    inner: F,
+    routes: Arc<HashMap<String, Handler<A>>>,
+}
+
+/// The response future returned by `Fallback`.
+pub struct ResponseFuture<F> {
+    kind: Kind<F, HandlerFuture>,
+}
+
+/// The response body returned by `Fallback`.
+pub struct ResponseBody<B> {
+    kind: Kind<B, BoxBody>,
+}
+
+enum Kind<T, U> {
+    Inner(T),
+    Handler(U),
+}
+
+/// A body sent in a single chunk.
+#[derive(Debug)]
+struct Full {
+    data: Option<Bytes>,
+}
+
+/// Returns a body made of `data`, for the responses of handlers.
+pub fn full<T>(data: T) -> BoxBody
+where T: Into<Bytes>,
+{
+    BoxBody::new(Box::new(Full { data: Some(data.into()) }))
+}
+
+// ===== impl Fallback =====
+
+impl<S, A> Fallback<S, A> {
+    /// Serve the requests not routed to a handler with `inner`.
+    pub fn new(inner: S) -> Self {
+        Fallback {
+            inner,
+            routes: Arc::new(HashMap::new()),
+        }
+    }
+
+    /// Answer requests for `path` with `handler`.
+    ///
+    /// A handler registered earlier for the same path is replaced.
+    pub fn route<F, R>(mut self, path: &str, handler: F) -> Self
+    where F: Fn(http::Request<A>) -> R + Send + Sync + 'static,
+          R: IntoFuture<Item = http::Response<BoxBody>, Error = h2::Error>,
+          R::Future: Send + 'static,
+    {
+        let handler: Handler<A> = Arc::new(move |request| {
+            Box::new(handler(request).into_future()) as HandlerFuture
+        });
+
+        Arc::make_mut(&mut self.routes).insert(path.to_string(), handler);
+        self
+    }
+
+    /// Returns true if requests for `path` are answered by a handler.
+    pub fn handles(&self, path: &str) -> bool {
+        self.routes.contains_key(path)
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner service.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+}
+
+impl<S, A, B> Service for Fallback<S, A>
+where S: Service<Request = http::Request<A>, Response = http::Response<B>, Error = h2::Error>,
+      B: Body,
+      B::Data: Into<Bytes>,
+{
+    type Request = http::Request<A>;
+    type Response = http::Response<ResponseBody<B>>;
+    type Error = h2::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, request: Self::Request) -> Self::Future {
+        let kind = match self.routes.get(request.uri().path()) {
+            Some(handler) => Kind::Handler(handler(request)),
+            None => Kind::Inner(self.inner.call(request)),
+        };
+
+        ResponseFuture { kind }
+    }
+}
+
+impl<S, A, B> NewService for Fallback<S, A>
+where S: NewService<Request = http::Request<A>, Response = http::Response<B>, Error = h2::Error>,
+      B: Body,
+      B::Data: Into<Bytes>,
+{
+    type Request = http::Request<A>;
+    type Response = http::Response<ResponseBody<B>>;
+    type Error = h2::Error;
+    type Service = Fallback<S::Service, A>;
+    type InitError = S::InitError;
+    type Future = NewServiceFuture<S::Future, A>;
+
+    fn new_service(&self) -> Self::Future {
+        NewServiceFuture {
+            inner: self.inner.new_service(),
+            routes: self.routes.clone(),
+        }
+    }
+}
+
+impl<S, A> Clone for Fallback<S, A>
+where S: Clone,
+{
+    fn clone(&self) -> Self {
+        Fallback {
+            inner: self.inner.clone(),
+            routes: self.routes.clone(),
+        }
+    }
+}
+
+impl<S, A> fmt::Debug for Fallback<S, A>
+where S: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Fallback")
+            .field("inner", &self.inner)
+            .field("routes", &self.routes.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+// ===== impl NewServiceFuture =====
+
+impl<F, A> Future for NewServiceFuture<F, A>
+where F: Future,
+{
+    type Item = Fallback<F::Item, A>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        Ok(Async::Ready(Fallback {
+            inner,
+            routes: self.routes.clone(),
+        }))
+    }
+}
+
+impl<F, A> fmt::Debug for NewServiceFuture<F, A>
+where F: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("fallback::NewServiceFuture")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F, B> Future for ResponseFuture<F>
+where F: Future<Item = http::Response<B>, Error = h2::Error>,
+{
+    type Item = http::Response<ResponseBody<B>>;
+    type Error = h2::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let response = match self.kind {
+            Kind::Inner(ref mut future) => {
+                try_ready!(future.poll()).map(|body| ResponseBody { kind: Kind::Inner(body) })
+            }
+            Kind::Handler(ref mut future) => {
+                try_ready!(future.poll()).map(|body| ResponseBody { kind: Kind::Handler(body) })
+            }
+        };
+
+        Ok(Async::Ready(response))
+    }
+}
+
+impl<F> fmt::Debug for ResponseFuture<F>
+where F: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let mut fmt = fmt.debug_struct("fallback::ResponseFuture");
+
+        match self.kind {
+            Kind::Inner(ref future) => fmt.field("inner", future),
+            Kind::Handler(_) => fmt.field("handler", &"HandlerFuture"),
+        };
+
+        fmt.finish()
+    }
+}
+
+// ===== impl ResponseBody =====
+
+impl<B> Body for ResponseBody<B>
+where B: Body,
+      B::Data: Into<Bytes>,
+{
+    type Data = Bytes;
+
+    fn is_end_stream(&self) -> bool {
+        match self.kind {
+            Kind::Inner(ref body) => body.is_end_stream(),
+            Kind::Handler(ref body) => body.is_end_stream(),
+        }
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Bytes>, h2::Error> {
+        match self.kind {
+            Kind::Inner(ref mut body) => {
+                let data = try_ready!(body.poll_data());
+                Ok(data.map(Into::into).into())
+            }
+            Kind::Handler(ref mut body) => body.poll_data(),
+        }
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, h2::Error> {
+        match self.kind {
+            Kind::Inner(ref mut body) => body.poll_trailers(),
+            Kind::Handler(ref mut body) => body.poll_trailers(),
+        }
+    }
+}
+
+impl<B> fmt::Debug for ResponseBody<B>
+where B: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let mut fmt = fmt.debug_struct("fallback::ResponseBody");
+
+        match self.kind {
+            Kind::Inner(ref body) => fmt.field("inner", body),
+            Kind::Handler(_) => fmt.field("handler", &"BoxBody"),
+        };
+
+        fmt.finish()
+    }
+}
+
+// ===== impl Full =====
+
+impl Body for Full {
+    type Data = Bytes;
+
+    fn is_end_stream(&self) -> bool {
+        self.data.is_none()
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Bytes>, h2::Error> {
+        Ok(self.data.take().into())
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, h2::Error> {
+        Ok(None.into())
+    }
+}
@@ -0,0 +1,343 @@
+//! Fault injection, for chaos tests.
+//!
+//! `FaultInjection` wraps a server's service and fails or delays a share of
+//! its calls, so tests can check how clients retry and respect deadlines
+//! without touching the service's implementation:
+//!
+//! ```ignore
+//! let new_service = FaultInjection::new(GreeterServer::new(greeter))
+//!     .fault(Fault::new().abort(10.0, Status::with_code(Code::UNAVAILABLE)))
+//!     .method("/helloworld.Greeter/SayHello", Fault::new()
+//!         .delay(50.0, Duration::from_secs(2)));
+//!
+//! let h2 = Server::new(new_service, Default::default(), reactor);
+//! ```
+//!
+//! Aborted calls are answered with a trailers-only response and never reach
+//! the inner service. Delayed calls are passed to the inner service at
+//! once, and their response is held back for the delay.
+
+use Status;
+use clock::{Clock, Sleep};
+
+use futures::{Future, Poll, Async};
+use h2;
+use http::{self, HeaderMap};
+use rand;
+use tower::{NewService, Service};
+use tower_h2::Body;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Fails or delays a share of the calls to the inner service.
+///
+/// `FaultInjection` may wrap either a `Service` or the `NewService` given
+/// to `tower_h2::Server`.
+#[derive(Clone)]
+pub struct FaultInjection<S> {
+    inner: S,
+    faults: Arc<Faults>,
+    timer: Clock,
+}
+
+/// The faults injected into calls.
+#[derive(Debug, Clone, Default)]
+pub struct Fault {
+    /// The percentage of calls aborted, and their status.
+    abort: Option<(f64, Status)>,
+
+    /// The percentage of calls delayed, and the delay.
+    delay: Option<(f64, Duration)>,
+}
+
+/// Creates `FaultInjection` services.
+pub struct NewServiceFuture<F> {
+    inner: F,
+    faults: Arc<Faults>,
+    timer: Clock,
+}
+
+/// The response future returned by `FaultInjection`.
+pub struct ResponseFuture<F> {
+    kind: Kind<F>,
+}
+
+/// The response body returned by `FaultInjection`.
+pub struct ResponseBody<B> {
+    inner: Option<B>,
+}
+
+enum Kind<F> {
+    /// The call was passed to the inner service, and its response is held
+    /// back until the delay, if any, has elapsed.
+    Inner(F, Option<Sleep>),
+
+    Aborted(Status),
+}
+
+#[derive(Debug, Clone, Default)]
+struct Faults {
+    default: Fault,
+
+    /// Faults overriding the default for calls to a method, by path.
+    methods: HashMap<String, Fault>,
+}
+
+// ===== impl FaultInjection =====
+
+impl<S> FaultInjection<S> {
+    /// No fault is injected until one is configured.
+    pub fn new(inner: S) -> Self {
+        FaultInjection {
+            inner,
+            faults: Arc::new(Faults::default()),
+            timer: Clock::default(),
+        }
+    }
+
+    /// Inject `fault` into the calls to methods without their own faults.
+    pub fn fault(mut self, fault: Fault) -> Self {
+        Arc::make_mut(&mut self.faults).default = fault;
+        self
+    }
+
+    /// Inject `fault` into the calls to the method at `path`, such as
+    /// `/helloworld.Greeter/SayHello`, instead of the default fault.
+    pub fn method(mut self, path: &str, fault: Fault) -> Self {
+        Arc::make_mut(&mut self.faults).methods.insert(path.to_string(), fault);
+        self
+    }
+
+    /// Use the provided timer, or a `MockClock`, for the delays.
+    pub fn timer<T: Into<Clock>>(mut self, timer: T) -> Self {
+        self.timer = timer.into();
+        self
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner service.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+}
+
+impl<S, A, B> Service for FaultInjection<S>
+where S: Service<Request = http::Request<A>, Response = http::Response<B>>,
+{
+    type Request = http::Request<A>;
+    type Response = http::Response<ResponseBody<B>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, request: Self::Request) -> Self::Future {
+        let fault = self.faults.get(request.uri().path());
+
+        if let Some((percent, ref status)) = fault.abort {
+            if sampled(percent) {
+                debug!("aborting call; path={}, status={:?}", request.uri().path(), status);
+                return ResponseFuture { kind: Kind::Aborted(status.clone()) };
+            }
+        }
+
+        let delay = match fault.delay {
+            Some((percent, delay)) if sampled(percent) => Some(delay),
+            _ => None,
+        };
+
+        let sleep = delay.map(|delay| self.timer.sleep(delay));
+
+        ResponseFuture {
+            kind: Kind::Inner(self.inner.call(request), sleep),
+        }
+    }
+}
+
+impl<S> fmt::Debug for FaultInjection<S>
+where S: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("FaultInjection")
+            .field("inner", &self.inner)
+            .field("faults", &self.faults)
+            .finish()
+    }
+}
+
+impl<S, A, B> NewService for FaultInjection<S>
+where S: NewService<Request = http::Request<A>, Response = http::Response<B>>,
+{
+    type Request = http::Request<A>;
+    type Response = http::Response<ResponseBody<B>>;
+    type Error = S::Error;
+    type Service = FaultInjection<S::Service>;
+    type InitError = S::InitError;
+    type Future = NewServiceFuture<S::Future>;
+
+    fn new_service(&self) -> Self::Future {
+        NewServiceFuture {
+            inner: self.inner.new_service(),
+            faults: self.faults.clone(),
+            timer: self.timer.clone(),
+        }
+    }
+}
+
+// ===== impl Fault =====
+
+impl Fault {
+    /// A fault injecting nothing.
+    pub fn new() -> Self {
+        Fault::default()
+    }
+
+    /// Abort `percent` percent of the calls with `status`.
+    pub fn abort(mut self, percent: f64, status: Status) -> Self {
+        self.abort = Some((percent, status));
+        self
+    }
+
+    /// Delay the responses to `percent` percent of the calls by `delay`.
+    pub fn delay(mut self, percent: f64, delay: Duration) -> Self {
+        self.delay = Some((percent, delay));
+        self
+    }
+}
+
+// ===== impl Faults =====
+
+impl Faults {
+    fn get(&self, path: &str) -> &Fault {
+        self.methods.get(path).unwrap_or(&self.default)
+    }
+}
+
+// ===== impl NewServiceFuture =====
+
+impl<F> Future for NewServiceFuture<F>
+where F: Future,
+{
+    type Item = FaultInjection<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+
+        Ok(Async::Ready(FaultInjection {
+            inner,
+            faults: self.faults.clone(),
+            timer: self.timer.clone(),
+        }))
+    }
+}
+
+impl<F> fmt::Debug for NewServiceFuture<F>
+where F: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("fault::NewServiceFuture")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F, B> Future for ResponseFuture<F>
+where F: Future<Item = http::Response<B>>,
+{
+    type Item = http::Response<ResponseBody<B>>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.kind {
+            Kind::Inner(ref mut inner, ref mut delay) => {
+                if let Some(ref mut sleep) = *delay {
+                    // A failing timer only cuts the delay short.
+                    if let Ok(Async::NotReady) = sleep.poll() {
+                        return Ok(Async::NotReady);
+                    }
+                }
+
+                *delay = None;
+
+                let response = try_ready!(inner.poll());
+                Ok(Async::Ready(response.map(|body| ResponseBody { inner: Some(body) })))
+            }
+            Kind::Aborted(ref status) => {
+                let body = ResponseBody { inner: None };
+                Ok(Async::Ready(::Response::trailers_only(status, body).into_http()))
+            }
+        }
+    }
+}
+
+impl<F> fmt::Debug for ResponseFuture<F>
+where F: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let mut fmt = fmt.debug_struct("fault::ResponseFuture");
+
+        match self.kind {
+            Kind::Inner(ref inner, ref delay) => fmt.field("inner", inner)
+                .field("delayed", &delay.is_some()),
+            Kind::Aborted(ref status) => fmt.field("aborted", status),
+        };
+
+        fmt.finish()
+    }
+}
+
+// ===== impl ResponseBody =====
+
+impl<B> Body for ResponseBody<B>
+where B: Body,
+{
+    type Data = B::Data;
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.as_ref().map_or(true, Body::is_end_stream)
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, h2::Error> {
+        match self.inner {
+            Some(ref mut inner) => inner.poll_data(),
+            None => Ok(Async::Ready(None)),
+        }
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, h2::Error> {
+        match self.inner {
+            Some(ref mut inner) => inner.poll_trailers(),
+            // The status of aborted calls was sent in the headers.
+            None => Ok(Async::Ready(None)),
+        }
+    }
+}
+
+impl<B> fmt::Debug for ResponseBody<B>
+where B: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("fault::ResponseBody")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+// ===== utility fns =====
+
+/// Returns true for `percent` percent of the calls.
+fn sampled(percent: f64) -> bool {
+    rand::random::<f64>() * 100.0 < percent
+}
@@ -0,0 +1,103 @@
+use super::{Codec, Decoder, DecodeBuf, Encoder, EncodeBuf};
+
+use bytes::{Buf, BufMut, Bytes};
+
+/// Codec of messages that are already encoded.
+///
+/// Messages are passed through as bytes, so calls can be made or proxied
+/// without knowing the message types at compile time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BytesCodec;
+
+/// Codec of messages that are already encoded, as shared `Bytes`.
+///
+/// Unlike `BytesCodec`, decoded messages are taken with
+/// `DecodeBuf::take_bytes`, so they are carved from the stream's arena when
+/// decoding into one.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SharedBytesCodec;
+
+// ===== impl BytesCodec =====
+
+impl Codec for BytesCodec {
+    const CONTENT_TYPE: &'static str = "application/grpc+proto";
+
+    type Encode = Vec<u8>;
+    type Encoder = BytesCodec;
+    type Decode = Vec<u8>;
+    type Decoder = BytesCodec;
+
+    fn encoder(&mut self) -> Self::Encoder {
+        BytesCodec
+    }
+
+    fn decoder(&mut self) -> Self::Decoder {
+        BytesCodec
+    }
+}
+
+impl Encoder for BytesCodec {
+    type Item = Vec<u8>;
+
+    fn encode(&mut self, item: Vec<u8>, buf: &mut EncodeBuf) -> Result<(), ::Error> {
+        buf.reserve(item.len());
+        buf.put_slice(&item);
+        Ok(())
+    }
+}
+
+impl Decoder for BytesCodec {
+    type Item = Vec<u8>;
+
+    fn decode(&mut self, buf: &mut DecodeBuf) -> Result<Vec<u8>, ::Error> {
+        let mut out = Vec::with_capacity(buf.remaining());
+
+        while buf.has_remaining() {
+            let n = {
+                let bytes = buf.bytes();
+                out.extend_from_slice(bytes);
+                bytes.len()
+            };
+            buf.advance(n);
+        }
+
+        Ok(out)
+    }
+}
+
+// ===== impl SharedBytesCodec =====
+
+impl Codec for SharedBytesCodec {
+    const CONTENT_TYPE: &'static str = "application/grpc+proto";
+
+    type Encode = Bytes;
+    type Encoder = SharedBytesCodec;
+    type Decode = Bytes;
+    type Decoder = SharedBytesCodec;
+
+    fn encoder(&mut self) -> Self::Encoder {
+        SharedBytesCodec
+    }
+
+    fn decoder(&mut self) -> Self::Decoder {
+        SharedBytesCodec
+    }
+}
+
+impl Encoder for SharedBytesCodec {
+    type Item = Bytes;
+
+    fn encode(&mut self, item: Bytes, buf: &mut EncodeBuf) -> Result<(), ::Error> {
+        buf.reserve(item.len());
+        buf.put_slice(&item);
+        Ok(())
+    }
+}
+
+impl Decoder for SharedBytesCodec {
+    type Item = Bytes;
+
+    fn decode(&mut self, buf: &mut DecodeBuf) -> Result<Bytes, ::Error> {
+        Ok(buf.take_bytes())
+    }
+}
@@ -1,10 +1,18 @@
-use Status;
+use {Code, Status};
+use arena::{self, Arena};
+use coalesce::{Batch, Coalesce};
+use headers;
+use super::counter::MessageCounters;
+use keepalive::{Idle, Keepalive};
+use limit::SendLimit;
+use stats::CallStats;
+use strictness::Strictness;
 
 use bytes::{Buf, BufMut, BytesMut, Bytes, BigEndian};
-use futures::{Stream, Poll, Async};
+use futures::{Future, Stream, Poll, Async};
 use h2;
 use http::HeaderMap;
-use tower_h2::{self, Body, Data};
+use tower_h2::{self, Body};
 
 use std::collections::VecDeque;
 
@@ -62,43 +70,141 @@ pub trait Decoder {
 pub struct Encode<T, U> {
     inner: EncodeInner<T, U>,
 
-    /// Destination buffer
-    buf: BytesMut,
-
     /// Set to true when trailers should be generated.
     return_trailers: bool,
+
+    /// The number of messages that may be encoded
+    limit: Option<usize>,
+
+    /// The number of messages encoded so far
+    encoded: usize,
+
+    /// Limits the size of request messages set by a channel
+    send_limit: Option<SendLimit>,
+
+    /// Keeps the stream alive while no message is ready
+    idle: Option<Idle>,
+
+    /// Batches the frames of messages ready together
+    batch: Option<Batch>,
+
+    /// How the stream ends, once the batched frames have been sent
+    end: Option<End>,
+
+    /// Reports the messages sent, and the status in the trailers
+    stats: Option<CallStats>,
 }
 
 #[derive(Debug)]
 enum EncodeInner<T, U> {
     Ok {
-        /// The encoder
-        encoder: T,
+        /// Frames the encoded messages
+        frames: FrameEncoder<T>,
 
         /// The source of messages to encode
         inner: U,
     },
     Err(Status),
+
+    /// The status was sent in the headers of a trailers-only response.
+    Empty,
+}
+
+#[derive(Debug)]
+enum End {
+    Done,
+    Failed(Status),
 }
 
 /// An stream of inbound gRPC messages
 #[must_use = "futures do nothing unless polled"]
 #[derive(Debug)]
 pub struct Streaming<T, U = tower_h2::RecvBody> {
-    /// The decoder
-    decoder: T,
+    /// Decodes the frames of the body
+    frames: FrameDecoder<T>,
 
     /// The source of encoded messages
     inner: U,
 
+    /// Set to true when expecting trailers
+    expect_trailers: bool,
+
+    /// The trailers, once received
+    trailers: Option<HeaderMap>,
+
+    /// Reports the status the stream ends with
+    stats: Option<CallStats>,
+}
+
+/// Encodes messages into gRPC length-prefixed frames.
+///
+/// `FrameEncoder` is the low-level layer of `Encode`, which frames the
+/// messages of a stream into an HTTP/2.0 body. It performs no IO and never
+/// blocks, so the frames can be written by a custom event loop, or by
+/// transports other than h2.
+#[derive(Debug)]
+pub struct FrameEncoder<T> {
+    /// The encoder
+    encoder: T,
+
+    /// Destination buffer
+    buf: BytesMut,
+
+    /// The largest message that may be sent
+    max_message_size: Option<usize>,
+}
+
+/// Decodes messages from gRPC length-prefixed frames.
+///
+/// `FrameDecoder` is the low-level layer of `Streaming`, which decodes the
+/// messages of an HTTP/2.0 body. It performs no IO: bytes are pushed as
+/// they are read, and messages are decoded once their frame is complete.
+///
+/// ```ignore
+/// let mut frames = FrameDecoder::new(decoder);
+///
+/// frames.push(data);
+///
+/// while let Some(msg) = frames.decode()? {
+///     handle(msg);
+/// }
+/// ```
+#[derive(Debug)]
+pub struct FrameDecoder<T> {
+    /// The decoder
+    decoder: T,
+
     /// buffer
     bufs: BytesList,
 
     /// Decoding state
     state: State,
 
-    /// Set to true when expecting trailers
-    expect_trailers: bool,
+    /// Counts decoded messages for server statistics
+    received: Option<MessageCounters>,
+
+    /// The number of messages that may be decoded
+    limit: Option<usize>,
+
+    /// The number of messages decoded so far
+    decoded: usize,
+
+    /// The largest message that may be received
+    max_message_size: Option<usize>,
+
+    /// How deviations from the specification are handled
+    strictness: Strictness,
+
+    /// The storage messages are decoded into, if any
+    arena: Option<Arena>,
+}
+
+/// Reads the rest of a stream, then resolves to its final status and
+/// trailers.
+#[must_use = "futures do nothing unless polled"]
+#[derive(Debug)]
+pub struct Trailers<T, U = tower_h2::RecvBody> {
+    inner: Option<Streaming<T, U>>,
 }
 
 #[derive(Debug)]
@@ -121,6 +227,7 @@ pub struct EncodeBuf<'a> {
 #[derive(Debug)]
 pub struct DecodeBuf<'a> {
     bufs: &'a mut BytesList,
+    arena: Option<&'a mut Arena>,
     len: usize,
 }
 
@@ -137,17 +244,166 @@ where T: Encoder<Item = U::Item>,
 {
     pub(crate) fn new(encoder: T, inner: U, return_trailers: bool) -> Self {
         Encode {
-            inner: EncodeInner::Ok { encoder, inner },
-            buf: BytesMut::new(),
+            inner: EncodeInner::Ok { frames: FrameEncoder::new(encoder), inner },
             return_trailers,
+            limit: None,
+            encoded: 0,
+            send_limit: None,
+            idle: None,
+            batch: None,
+            end: None,
+            stats: None,
         }
     }
 
-    pub(crate) fn error(status: Status) -> Self {
+    /// Fail the stream with `RESOURCE_EXHAUSTED` once more than `limit`
+    /// messages are to be encoded.
+    pub(crate) fn limit_sent(mut self, limit: Option<usize>) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Fail the stream with `RESOURCE_EXHAUSTED` on a message larger than
+    /// `limit` allows once it is encoded.
+    pub(crate) fn send_limit(mut self, limit: SendLimit) -> Self {
+        self.send_limit = Some(limit);
+        self
+    }
+
+    /// Send an empty data frame whenever no message has been ready for the
+    /// keepalive interval.
+    pub(crate) fn keepalive(mut self, keepalive: Option<&Keepalive>) -> Self {
+        self.idle = keepalive.map(Keepalive::idle);
+        self
+    }
+
+    /// Send the frames of messages that are ready together in one DATA
+    /// frame.
+    pub(crate) fn coalesce(mut self, coalesce: Option<&Coalesce>) -> Self {
+        self.batch = coalesce.map(Coalesce::batch);
+        self
+    }
+
+    /// Report each message sent to `stats`, and the status once the
+    /// trailers are sent.
+    pub(crate) fn stats(mut self, stats: Option<CallStats>) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    /// Returns the body of a trailers-only response.
+    pub(crate) fn empty() -> Self {
         Encode {
-            inner: EncodeInner::Err(status),
-            buf: BytesMut::new(),
-            return_trailers: true,
+            inner: EncodeInner::Empty,
+            return_trailers: false,
+            limit: None,
+            encoded: 0,
+            send_limit: None,
+            idle: None,
+            batch: None,
+            end: None,
+            stats: None,
+        }
+    }
+}
+
+impl<T, U> Encode<T, U>
+where T: Encoder<Item = U::Item>,
+      U: Stream,
+      U::Error: Into<Status>,
+{
+    /// Encode the messages that are ready, returning their frames.
+    fn poll_encode(&mut self) -> Poll<Option<Bytes>, Status> {
+        match self.end.take() {
+            Some(End::Done) => return Ok(Async::Ready(None)),
+            Some(End::Failed(status)) => return Err(status),
+            None => {}
+        }
+
+        let (inner, frames) = match self.inner {
+            EncodeInner::Ok { ref mut inner, ref mut frames } => (inner, frames),
+            EncodeInner::Err(_) | EncodeInner::Empty => return Ok(Async::Ready(None)),
+        };
+
+        loop {
+            let item = match inner.poll() {
+                Ok(Async::Ready(Some(item))) => item,
+                Ok(Async::Ready(None)) => return end_batch(frames, &mut self.end, End::Done),
+                Ok(Async::NotReady) => {
+                    // The idle interval runs while a batch lingers, so that
+                    // a long linger cannot hold back the keepalive.
+                    let idle = self.idle.as_mut().map_or(false, Idle::poll_elapsed);
+
+                    if frames.buffered() > 0 {
+                        // An idle stream sends its batch as the keepalive.
+                        if !idle && !self.batch.as_mut().map_or(true, Batch::poll_linger) {
+                            return Ok(Async::NotReady);
+                        }
+
+                        if let Some(ref mut batch) = self.batch {
+                            batch.reset();
+                        }
+
+                        return Ok(Async::Ready(Some(frames.flush())));
+                    }
+
+                    if idle {
+                        trace!("stream idle; sending keepalive");
+                        return Ok(Async::Ready(Some(Bytes::new())));
+                    }
+
+                    return Ok(Async::NotReady);
+                }
+                Err(e) => return end_batch(frames, &mut self.end, End::Failed(e.into())),
+            };
+
+            self.encoded += 1;
+
+            if let Some(ref mut idle) = self.idle {
+                idle.reset();
+            }
+
+            if self.limit.map_or(false, |limit| self.encoded > limit) {
+                debug!("too many messages sent; limit={:?}", self.limit);
+                let end = End::Failed(Status::RESOURCE_EXHAUSTED);
+                return end_batch(frames, &mut self.end, end);
+            }
+
+            if let Some(ref limit) = self.send_limit {
+                frames.max_message_size = limit.get();
+            }
+
+            match frames.push(item) {
+                Ok(len) => {
+                    if let Some(ref stats) = self.stats {
+                        stats.message_sent(len);
+                    }
+                }
+                Err(status) => {
+                    // Requests have no trailers, so the client learns the
+                    // status from the limit rather than from the reset
+                    // stream.
+                    if let Some(ref limit) = self.send_limit {
+                        limit.fail(status.clone());
+                    }
+
+                    return end_batch(frames, &mut self.end, End::Failed(status));
+                }
+            }
+
+            // Without coalescing, each message is sent on its own.
+            let full = match self.batch {
+                Some(ref batch) => batch.is_full(frames.buffered()),
+                None => true,
+            };
+
+            if full {
+                if let Some(ref mut batch) = self.batch {
+                    batch.reset();
+                }
+
+                return Ok(Async::Ready(Some(frames.flush())));
+            }
         }
     }
 }
@@ -155,40 +411,32 @@ where T: Encoder<Item = U::Item>,
 impl<T, U> tower_h2::Body for Encode<T, U>
 where T: Encoder<Item = U::Item>,
       U: Stream,
+      U::Error: Into<Status>,
 {
     type Data = Bytes;
 
     fn is_end_stream(&self) -> bool {
-        false
+        match self.inner {
+            EncodeInner::Empty => true,
+            _ => false,
+        }
     }
 
     fn poll_data(&mut self) -> Poll<Option<Self::Data>, h2::Error> {
-        match self.inner {
-            EncodeInner::Ok { ref mut inner, ref mut encoder } => {
-                let item = try_ready!(inner.poll().map_err(|_| h2_err()));
-
-                if let Some(item) = item {
-                    self.buf.reserve(5);
-                    unsafe { self.buf.advance_mut(5); }
-                    encoder.encode(item, &mut EncodeBuf {
-                        bytes: &mut self.buf,
-                    }).map_err(|_| h2_err())?;
-
-                    // now that we know length, we can write the header
-                    let len = self.buf.len() - 5;
-                    assert!(len <= ::std::u32::MAX as usize);
-                    {
-                        let mut cursor = ::std::io::Cursor::new(&mut self.buf[..5]);
-                        cursor.put_u8(0); // byte must be 0, reserve doesn't auto-zero
-                        cursor.put_u32::<BigEndian>(len as u32);
-                    }
-
-                    Ok(Async::Ready(Some(self.buf.split_to(len + 5).freeze())))
-                } else {
-                    Ok(Async::Ready(None))
+        match self.poll_encode() {
+            Ok(poll) => Ok(poll),
+            Err(status) => {
+                debug!("encoding stream failed; status={:?}", status);
+
+                // Requests have no trailers to carry the status in.
+                if !self.return_trailers {
+                    return Err(status.into());
                 }
+
+                // End the body, and send the status in the trailers.
+                self.inner = EncodeInner::Err(status);
+                Ok(Async::Ready(None))
             }
-            _ => Ok(Async::Ready(None)),
         }
     }
 
@@ -197,15 +445,21 @@ where T: Encoder<Item = U::Item>,
             return Ok(Async::Ready(None));
         }
 
-        let mut map = HeaderMap::new();
+        // Only grpc-status is sent, so the map never grows.
+        let mut map = HeaderMap::with_capacity(1);
 
         let status = match self.inner {
-            EncodeInner::Ok { .. } => Status::OK.to_header_value(),
-            EncodeInner::Err(ref status) => status.to_header_value(),
+            EncodeInner::Ok { .. } => Status::OK,
+            EncodeInner::Err(ref status) => status.clone(),
+            EncodeInner::Empty => return Ok(Async::Ready(None)),
         };
 
+        if let Some(ref stats) = self.stats {
+            stats.end(status.code());
+        }
+
         // Success
-        map.insert("grpc-status", status);
+        map.insert(headers::GRPC_STATUS.clone(), status.to_header_value());
 
         Ok(Some(map).into())
     }
@@ -215,27 +469,330 @@ where T: Encoder<Item = U::Item>,
 
 impl<T, U> Streaming<T, U>
 where T: Decoder,
-      U: Body<Data = Data>,
+      U: Body,
+      U::Data: Into<Bytes>,
 {
     pub(crate) fn new(decoder: T, inner: U, expect_trailers: bool) -> Self {
         Streaming {
-            decoder,
+            frames: FrameDecoder::new(decoder),
             inner,
+            expect_trailers,
+            trailers: None,
+            stats: None,
+        }
+    }
+
+    /// Returns the trailers, once the stream has ended.
+    ///
+    /// Trailers are received after the last message, so they are available
+    /// once the stream yields `None`, or fails with the status they carry.
+    pub fn trailers(&self) -> Option<&HeaderMap> {
+        self.trailers.as_ref()
+    }
+
+    /// Returns a future of the stream's final status and trailers.
+    ///
+    /// Messages that have not been read yet are discarded.
+    pub fn into_trailers(self) -> Trailers<T, U> {
+        Trailers { inner: Some(self) }
+    }
+
+    /// Count each decoded message with `counters`.
+    pub(crate) fn count_received(mut self, counters: Option<MessageCounters>) -> Self {
+        self.frames.received = counters;
+        self
+    }
+
+    /// Fail the stream with `RESOURCE_EXHAUSTED` once more than `limit`
+    /// messages are received.
+    pub(crate) fn limit_received(mut self, limit: Option<usize>) -> Self {
+        self.frames.limit = limit;
+        self
+    }
+
+    /// Fail the stream with `RESOURCE_EXHAUSTED` on a message larger than
+    /// `max` bytes.
+    pub(crate) fn max_message_size(mut self, max: Option<usize>) -> Self {
+        self.frames.max_message_size = max;
+        self
+    }
+
+    /// Decode the frames with `strictness`, if set.
+    pub(crate) fn strictness(mut self, strictness: Option<Strictness>) -> Self {
+        if let Some(strictness) = strictness {
+            self.frames.strictness = strictness;
+        }
+        self
+    }
+
+    /// Decode messages into `arena`, if set.
+    pub(crate) fn arena(mut self, arena: Option<Arena>) -> Self {
+        self.frames.arena = arena;
+        self
+    }
+
+    /// Report each message received to `stats`, and the status once the
+    /// trailers are received.
+    ///
+    /// The counters set by `count_received` are replaced.
+    pub(crate) fn stats(mut self, stats: Option<CallStats>) -> Self {
+        self.frames.received = stats.as_ref().map(|stats| MessageCounters::new(stats.counter()));
+        self.stats = stats;
+        self
+    }
+
+    fn poll_messages(&mut self) -> Poll<Option<T::Item>, ::Error> {
+        loop {
+            if self.frames.is_done() {
+                break;
+            }
+
+            match self.frames.decode() {
+                Ok(Some(val)) => {
+                    return Ok(Async::Ready(Some(val)));
+                }
+                Ok(None) => (),
+                Err(status) => return Err(::Error::Grpc(status)),
+            }
+
+            let chunk = try_ready!(self.inner.poll_data());
+
+            if let Some(data) = chunk {
+                self.frames.push(data.into());
+            } else {
+                self.frames.finish().map_err(::Error::Grpc)?;
+                break;
+            }
+        }
+
+        if self.expect_trailers && self.trailers.is_none() {
+            if let Some(trailers) = try_ready!(self.inner.poll_trailers()) {
+                let status = grpc_status(&trailers);
+                self.trailers = Some(trailers);
+
+                status.map_err(::Error::Grpc)?;
+                Ok(Async::Ready(None))
+            } else {
+                trace!("receive body ended without trailers");
+                Err(::Error::Grpc(Status::UNKNOWN))
+            }
+        } else {
+            Ok(Async::Ready(None))
+        }
+    }
+}
+
+impl<T, U> Stream for Streaming<T, U>
+where T: Decoder,
+      U: Body,
+      U::Data: Into<Bytes>,
+{
+    type Item = T::Item;
+    type Error = ::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let poll = self.poll_messages();
+
+        if let Some(ref stats) = self.stats {
+            match poll {
+                Ok(Async::Ready(None)) => stats.end(Code::OK),
+                Err(::Error::Grpc(ref status)) => stats.end(status.code()),
+                _ => {}
+            }
+        }
+
+        poll
+    }
+}
+
+// ===== impl Trailers =====
+
+impl<T, U> Future for Trailers<T, U>
+where T: Decoder,
+      U: Body,
+      U::Data: Into<Bytes>,
+{
+    type Item = (Status, HeaderMap);
+    type Error = ::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        {
+            let stream = self.inner.as_mut().expect("polled after complete");
+
+            loop {
+                match stream.poll() {
+                    Ok(Async::Ready(Some(_))) => {}
+                    Ok(Async::Ready(None)) => break,
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    // The trailers carried an error status.
+                    Err(::Error::Grpc(_)) if stream.trailers.is_some() => break,
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        let stream = self.inner.take().expect("polled after complete");
+        let trailers = stream.trailers.unwrap_or_else(HeaderMap::new);
+        let status = grpc_status(&trailers).err().unwrap_or(Status::OK);
+
+        Ok(Async::Ready((status, trailers)))
+    }
+}
+
+// ===== impl FrameEncoder =====
+
+impl<T> FrameEncoder<T>
+where T: Encoder,
+{
+    /// Frame the messages encoded by `encoder`.
+    pub fn new(encoder: T) -> Self {
+        FrameEncoder {
+            encoder,
+            buf: BytesMut::new(),
+            max_message_size: None,
+        }
+    }
+
+    /// Fail to encode messages larger than `max` bytes, with
+    /// `RESOURCE_EXHAUSTED`.
+    pub fn max_message_size(mut self, max: usize) -> Self {
+        self.max_message_size = Some(max);
+        self
+    }
+
+    /// Encode `item` into a frame.
+    pub fn encode(&mut self, item: T::Item) -> Result<Bytes, Status> {
+        self.push(item)?;
+        Ok(self.flush())
+    }
+
+    /// Encode `item` into a frame, buffered after the frames not flushed
+    /// yet, returning the length of the message.
+    pub(crate) fn push(&mut self, item: T::Item) -> Result<usize, Status> {
+        let buf = &mut self.buf;
+        let start = buf.len();
+
+        buf.reserve(5);
+        unsafe { buf.advance_mut(5); }
+
+        if let Err(e) = self.encoder.encode(item, &mut EncodeBuf {
+            bytes: buf,
+        }) {
+            // Drop the partial frame, keeping the frames before it.
+            buf.truncate(start);
+            return Err(e.into());
+        }
+
+        // now that we know length, we can write the header
+        let len = buf.len() - start - 5;
+        assert!(len <= ::std::u32::MAX as usize);
+
+        if self.max_message_size.map_or(false, |max| len > max) {
+            debug!("message too large; len={}, max={:?}", len, self.max_message_size);
+            buf.truncate(start);
+            return Err(Status::RESOURCE_EXHAUSTED);
+        }
+        {
+            let mut cursor = ::std::io::Cursor::new(&mut buf[start..start + 5]);
+            cursor.put_u8(0); // byte must be 0, reserve doesn't auto-zero
+            cursor.put_u32::<BigEndian>(len as u32);
+        }
+
+        Ok(len)
+    }
+
+    /// Returns the number of bytes of frames buffered.
+    pub(crate) fn buffered(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Take the buffered frames.
+    pub(crate) fn flush(&mut self) -> Bytes {
+        let len = self.buf.len();
+        self.buf.split_to(len).freeze()
+    }
+
+    /// Returns a reference to the encoder.
+    pub fn get_ref(&self) -> &T {
+        &self.encoder
+    }
+
+    /// Returns a mutable reference to the encoder.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.encoder
+    }
+}
+
+// ===== impl FrameDecoder =====
+
+impl<T> FrameDecoder<T>
+where T: Decoder,
+{
+    /// Decode the framed messages with `decoder`.
+    pub fn new(decoder: T) -> Self {
+        FrameDecoder {
+            decoder,
             bufs: BytesList {
                 bufs: VecDeque::new(),
             },
             state: State::ReadHeader,
-            expect_trailers,
+            received: None,
+            limit: None,
+            decoded: 0,
+            max_message_size: None,
+            strictness: Strictness::default(),
+            arena: None,
         }
     }
 
-    fn decode(&mut self) -> Result<Option<T::Item>, Status> {
+    /// Decode the frames with `strictness`.
+    ///
+    /// Defaults to `Strictness::Strict`.
+    pub fn strictness(mut self, strictness: Strictness) -> Self {
+        self.strictness = strictness;
+        self
+    }
+
+    /// Decode messages into `arena`.
+    ///
+    /// By default, decoders that take the bytes of their messages allocate
+    /// each message on its own.
+    pub fn arena(mut self, arena: Arena) -> Self {
+        self.arena = Some(arena);
+        self
+    }
+
+    /// Fail with `RESOURCE_EXHAUSTED` on a frame whose message is larger
+    /// than `max` bytes, as soon as its length prefix is read.
+    ///
+    /// By default, messages of any size are decoded.
+    pub fn max_message_size(mut self, max: usize) -> Self {
+        self.max_message_size = Some(max);
+        self
+    }
+
+    /// Buffer `data`, the next bytes of the frames.
+    pub fn push(&mut self, data: Bytes) {
+        self.bufs.bufs.push_back(data);
+    }
+
+    /// Decode the next message, if its frame has been pushed in full.
+    ///
+    /// Returns `None` if more bytes are needed.
+    pub fn decode(&mut self) -> Result<Option<T::Item>, Status> {
         if let State::ReadHeader = self.state {
             if self.bufs.remaining() < 5 {
                 return Ok(None);
             }
 
-            let is_compressed = match self.bufs.get_u8() {
+            let mut flag = self.bufs.get_u8();
+
+            if flag > 1 && self.strictness == Strictness::Lenient {
+                trace!("ignoring unknown compression flag bits; flag={:#x}", flag);
+                flag &= 1;
+            }
+
+            let is_compressed = match flag {
                 0 => false,
                 1 => {
                     trace!("message compressed, compression not supported yet");
@@ -243,11 +800,25 @@ where T: Decoder,
                 },
                 _ => {
                     trace!("unexpected compression flag");
-                    return Err(Status::UNKNOWN);
+                    return Err(Status::INTERNAL);
                 }
             };
             let len = self.bufs.get_u32::<BigEndian>() as usize;
 
+            if self.max_message_size.map_or(false, |max| len > max) {
+                debug!("message too large; len={}, max={:?}", len, self.max_message_size);
+                self.state = State::Done;
+                return Err(Status::RESOURCE_EXHAUSTED);
+            }
+
+            self.decoded += 1;
+
+            if self.limit.map_or(false, |limit| self.decoded > limit) {
+                debug!("too many messages received; limit={:?}", self.limit);
+                self.state = State::Done;
+                return Err(Status::RESOURCE_EXHAUSTED);
+            }
+
             self.state = State::ReadBody {
                 compression: is_compressed,
                 len,
@@ -261,68 +832,62 @@ where T: Decoder,
 
             match self.decoder.decode(&mut DecodeBuf {
                 bufs: &mut self.bufs,
+                arena: self.arena.as_mut(),
                 len,
             }) {
                 Ok(msg) => {
+                    if let Some(ref received) = self.received {
+                        received.message(len);
+                    }
+
                     self.state = State::ReadHeader;
                     return Ok(Some(msg));
                 },
                 Err(e) => {
                     debug!("decoder error; err={:?}", e);
-                    return Err(Status::UNKNOWN);
+                    return Err(Status::INTERNAL);
                 }
             }
         }
 
         Ok(None)
     }
-}
 
-impl<T, U> Stream for Streaming<T, U>
-where T: Decoder,
-      U: Body<Data = Data>,
-{
-    type Item = T::Item;
-    type Error = ::Error;
-
-    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
-        loop {
-            if let State::Done = self.state {
-                break;
+    /// Signal that no more bytes will be pushed.
+    ///
+    /// Fails if a frame was only partially pushed, unless decoding
+    /// leniently, which discards it.
+    pub fn finish(&mut self) -> Result<(), Status> {
+        if self.bufs.has_remaining() {
+            if self.strictness == Strictness::Strict {
+                trace!("unexpected EOF decoding stream");
+                return Err(Status::INTERNAL);
             }
 
-            match self.decode() {
-                Ok(Some(val)) => return Ok(Async::Ready(Some(val))),
-                Ok(None) => (),
-                Err(status) => return Err(::Error::Grpc(status)),
-            }
+            debug!("discarding incomplete frame; len={}", self.bufs.remaining());
+        }
 
-            let chunk = try_ready!(self.inner.poll_data());
+        self.state = State::Done;
+        Ok(())
+    }
 
-            if let Some(data) = chunk {
-                self.bufs.bufs.push_back(data.into());
-            } else {
-                if self.bufs.has_remaining() {
-                    trace!("unexpected EOF decoding stream");
-                    return Err(::Error::Grpc(Status::UNKNOWN))
-                } else {
-                    self.state = State::Done;
-                    break;
-                }
-            }
+    /// Returns true once no more messages will be decoded, after `finish`
+    /// or once the message limit has been exceeded.
+    pub fn is_done(&self) -> bool {
+        match self.state {
+            State::Done => true,
+            _ => false,
         }
+    }
 
-        if self.expect_trailers {
-            if let Some(trailers) = try_ready!(self.inner.poll_trailers()) {
-                grpc_status(&trailers).map_err(::Error::Grpc)?;
-                Ok(Async::Ready(None))
-            } else {
-                trace!("receive body ended without trailers");
-                Err(::Error::Grpc(Status::UNKNOWN))
-            }
-        } else {
-            Ok(Async::Ready(None))
-        }
+    /// Returns a reference to the decoder.
+    pub fn get_ref(&self) -> &T {
+        &self.decoder
+    }
+
+    /// Returns a mutable reference to the decoder.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.decoder
     }
 }
 
@@ -354,6 +919,26 @@ impl<'a> BufMut for EncodeBuf<'a> {
 
 // ===== impl DecodeBuf =====
 
+impl<'a> DecodeBuf<'a> {
+    /// Take the rest of the message's bytes.
+    ///
+    /// The bytes are copied into the stream's arena when decoding into one,
+    /// and into a buffer of their own otherwise.
+    pub fn take_bytes(&mut self) -> Bytes {
+        let len = self.len;
+        self.len = 0;
+
+        match self.arena {
+            Some(ref mut arena) => arena.copy(&mut *self.bufs, len),
+            None => {
+                let mut bytes = BytesMut::with_capacity(len);
+                arena::copy_into(&mut bytes, &mut *self.bufs, len);
+                bytes.freeze()
+            }
+        }
+    }
+}
+
 impl<'a> Buf for DecodeBuf<'a> {
     #[inline]
     fn remaining(&self) -> usize {
@@ -426,12 +1011,25 @@ impl Buf for BytesList {
 
 // ===== impl utils =====
 
-fn h2_err() -> h2::Error {
-    unimplemented!("EncodingBody map_err")
+/// Returns the buffered frames, if any, and ends the stream with `end` once
+/// they have been sent.
+fn end_batch<T>(frames: &mut FrameEncoder<T>, pending: &mut Option<End>, end: End)
+    -> Poll<Option<Bytes>, Status>
+where T: Encoder,
+{
+    if frames.buffered() > 0 {
+        *pending = Some(end);
+        return Ok(Async::Ready(Some(frames.flush())));
+    }
+
+    match end {
+        End::Done => Ok(Async::Ready(None)),
+        End::Failed(status) => Err(status),
+    }
 }
 
 fn grpc_status(trailers: &HeaderMap) -> Result<(), Status> {
-    if let Some(status) = trailers.get("grpc-status") {
+    if let Some(status) = trailers.get(&*headers::GRPC_STATUS) {
         let status = Status::from_bytes(status.as_ref());
         if status.code() == ::Code::OK {
             Ok(())
@@ -443,3 +1041,242 @@ fn grpc_status(trailers: &HeaderMap) -> Result<(), Status> {
         Err(Status::UNKNOWN)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clock::{Clock, MockClock};
+    use generic::BytesCodec;
+
+    use futures::future;
+    use futures::sync::mpsc;
+
+    use std::time::Duration;
+
+    /// The messages to encode, sent by a test as it goes.
+    struct Messages(mpsc::UnboundedReceiver<Vec<u8>>);
+
+    /// A request body holding `data`, without trailers.
+    struct MockBody {
+        data: VecDeque<Bytes>,
+    }
+
+    fn encode(return_trailers: bool)
+        -> (mpsc::UnboundedSender<Vec<u8>>, Encode<BytesCodec, Messages>)
+    {
+        let (tx, rx) = mpsc::unbounded();
+        (tx, Encode::new(BytesCodec, Messages(rx), return_trailers))
+    }
+
+    /// Returns the gRPC frame of `message`.
+    fn frame(message: &[u8]) -> Bytes {
+        let mut buf = BytesMut::with_capacity(5 + message.len());
+        buf.put_u8(0);
+        buf.put_u32::<BigEndian>(message.len() as u32);
+        buf.put_slice(message);
+        buf.freeze()
+    }
+
+    fn frames(messages: &[&[u8]]) -> Bytes {
+        let mut buf = BytesMut::new();
+        for message in messages {
+            buf.extend_from_slice(&frame(message));
+        }
+        buf.freeze()
+    }
+
+    /// Returns the data polled from `encode`, if it is ready.
+    fn poll_data(encode: &mut Encode<BytesCodec, Messages>) -> Option<Option<Bytes>> {
+        match encode.poll_data().unwrap() {
+            Async::Ready(data) => Some(data),
+            Async::NotReady => None,
+        }
+    }
+
+    impl Stream for Messages {
+        type Item = Vec<u8>;
+        type Error = Status;
+
+        fn poll(&mut self) -> Poll<Option<Vec<u8>>, Status> {
+            Ok(self.0.poll().expect("unbounded receivers do not fail"))
+        }
+    }
+
+    impl Body for MockBody {
+        type Data = Bytes;
+
+        fn is_end_stream(&self) -> bool {
+            self.data.is_empty()
+        }
+
+        fn poll_data(&mut self) -> Poll<Option<Bytes>, h2::Error> {
+            Ok(Async::Ready(self.data.pop_front()))
+        }
+
+        fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, h2::Error> {
+            Ok(Async::Ready(None))
+        }
+    }
+
+    #[test]
+    fn keepalive_sent_once_stream_is_idle_for_the_interval() {
+        let clock = MockClock::new();
+        let keepalive = Keepalive::new(Duration::from_secs(10), Clock::from(&clock));
+
+        let (tx, encode) = encode(true);
+        let mut encode = encode.keepalive(Some(&keepalive));
+
+        future::lazy(move || {
+            assert_eq!(poll_data(&mut encode), None);
+
+            clock.advance(Duration::from_secs(9));
+            assert_eq!(poll_data(&mut encode), None);
+
+            clock.advance(Duration::from_secs(1));
+            assert_eq!(poll_data(&mut encode), Some(Some(Bytes::new())));
+            assert_eq!(poll_data(&mut encode), None);
+
+            // A message restarts the interval.
+            clock.advance(Duration::from_secs(5));
+            tx.unbounded_send(b"hello".to_vec()).unwrap();
+            assert_eq!(poll_data(&mut encode), Some(Some(frame(b"hello"))));
+            assert_eq!(poll_data(&mut encode), None);
+
+            clock.advance(Duration::from_secs(9));
+            assert_eq!(poll_data(&mut encode), None);
+
+            clock.advance(Duration::from_secs(1));
+            assert_eq!(poll_data(&mut encode), Some(Some(Bytes::new())));
+
+            drop(tx);
+            assert_eq!(poll_data(&mut encode), Some(None));
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn keepalive_not_held_back_by_a_lingering_batch() {
+        let clock = MockClock::new();
+        let keepalive = Keepalive::new(Duration::from_secs(10), Clock::from(&clock));
+        let coalesce = Coalesce::new(1024, Some(Duration::from_secs(60)), Clock::from(&clock));
+
+        let (tx, encode) = encode(true);
+        let mut encode = encode
+            .keepalive(Some(&keepalive))
+            .coalesce(Some(&coalesce));
+
+        future::lazy(move || {
+            tx.unbounded_send(b"hello".to_vec()).unwrap();
+            assert_eq!(poll_data(&mut encode), None);
+
+            // The batch is sent once the stream has been idle for the
+            // keepalive interval, rather than after the linger.
+            clock.advance(Duration::from_secs(10));
+            assert_eq!(poll_data(&mut encode), Some(Some(frame(b"hello"))));
+            assert_eq!(poll_data(&mut encode), None);
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn coalesced_messages_sent_when_batch_is_full_or_has_lingered() {
+        let clock = MockClock::new();
+        let coalesce = Coalesce::new(16, Some(Duration::from_millis(5)), Clock::from(&clock));
+
+        let (tx, encode) = encode(true);
+        let mut encode = encode.coalesce(Some(&coalesce));
+
+        future::lazy(move || {
+            // Two 6 byte frames wait for more.
+            tx.unbounded_send(b"a".to_vec()).unwrap();
+            tx.unbounded_send(b"b".to_vec()).unwrap();
+            assert_eq!(poll_data(&mut encode), None);
+
+            clock.advance(Duration::from_millis(4));
+            assert_eq!(poll_data(&mut encode), None);
+
+            clock.advance(Duration::from_millis(1));
+            assert_eq!(poll_data(&mut encode), Some(Some(frames(&[b"a", b"b"]))));
+
+            // A full batch is sent right away; the rest lingers again.
+            for message in &[b"one", b"two", b"six"] {
+                tx.unbounded_send(message.to_vec()).unwrap();
+            }
+            assert_eq!(poll_data(&mut encode), Some(Some(frames(&[b"one", b"two"]))));
+            assert_eq!(poll_data(&mut encode), None);
+
+            // The end of the stream sends what remains.
+            drop(tx);
+            assert_eq!(poll_data(&mut encode), Some(Some(frame(b"six"))));
+            assert_eq!(poll_data(&mut encode), Some(None));
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn sending_more_messages_than_the_limit_fails_in_trailers() {
+        let (tx, encode) = encode(true);
+        let mut encode = encode.limit_sent(Some(2));
+
+        future::lazy(move || {
+            for message in &[b"one", b"two", b"six"] {
+                tx.unbounded_send(message.to_vec()).unwrap();
+            }
+
+            assert_eq!(poll_data(&mut encode), Some(Some(frame(b"one"))));
+            assert_eq!(poll_data(&mut encode), Some(Some(frame(b"two"))));
+            assert_eq!(poll_data(&mut encode), Some(None));
+
+            let trailers = encode.poll_trailers().unwrap();
+            match trailers {
+                Async::Ready(Some(ref trailers)) => {
+                    assert_eq!(headers::status_code(trailers), Some(Code::RESOURCE_EXHAUSTED));
+                }
+                _ => panic!("no trailers"),
+            }
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn sending_more_messages_than_the_limit_ends_a_coalesced_batch() {
+        let clock = MockClock::new();
+        let coalesce = Coalesce::new(1024, Some(Duration::from_secs(1)), Clock::from(&clock));
+
+        let (tx, encode) = encode(false);
+        let mut encode = encode
+            .limit_sent(Some(1))
+            .coalesce(Some(&coalesce));
+
+        future::lazy(move || {
+            tx.unbounded_send(b"one".to_vec()).unwrap();
+            tx.unbounded_send(b"two".to_vec()).unwrap();
+
+            // The message within the limit is sent before the stream fails.
+            assert_eq!(poll_data(&mut encode), Some(Some(frame(b"one"))));
+            assert!(encode.poll_data().is_err());
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn receiving_more_messages_than_the_limit_fails() {
+        let body = MockBody {
+            data: vec![frames(&[b"one", b"two"])].into_iter().collect(),
+        };
+        let mut streaming = Streaming::new(BytesCodec, body, false)
+            .limit_received(Some(1));
+
+        assert_eq!(streaming.poll().unwrap(), Async::Ready(Some(b"one".to_vec())));
+
+        match streaming.poll() {
+            Err(::Error::Grpc(status)) => assert_eq!(status.code(), Code::RESOURCE_EXHAUSTED),
+            other => panic!("second message received; ok={:?}", other.is_ok()),
+        }
+    }
+}
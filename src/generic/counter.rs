@@ -0,0 +1,108 @@
+//! Message counting shared by the instrumentation layers.
+
+use http;
+
+use std::{cmp, fmt};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Request extension holding the counters incremented for each message the
+/// server decodes from the request.
+#[derive(Clone, Default)]
+pub(crate) struct MessageCounters(Vec<Arc<Count>>);
+
+/// A counter of decoded messages.
+pub(crate) trait Count: Send + Sync {
+    /// Count a decoded message of `len` bytes.
+    fn message(&self, len: usize);
+}
+
+/// Counts the messages in a stream of gRPC frames.
+#[derive(Debug, Default)]
+pub(crate) struct Frames {
+    header: [u8; 5],
+    header_len: usize,
+    remaining: usize,
+}
+
+// ===== impl MessageCounters =====
+
+impl MessageCounters {
+    /// Returns counters holding only `counter`.
+    pub(crate) fn new(counter: Arc<Count>) -> Self {
+        MessageCounters(vec![counter])
+    }
+
+    /// Increment `counter` for each message decoded from `request`'s body.
+    pub(crate) fn register<B>(request: &mut http::Request<B>, counter: Arc<Count>) {
+        if let Some(counters) = request.extensions_mut().get_mut::<MessageCounters>() {
+            counters.0.push(counter);
+            return;
+        }
+
+        request.extensions_mut().insert(MessageCounters::new(counter));
+    }
+
+    /// Count a decoded message of `len` bytes.
+    pub(crate) fn message(&self, len: usize) {
+        for counter in &self.0 {
+            counter.message(len);
+        }
+    }
+}
+
+impl fmt::Debug for MessageCounters {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("MessageCounters")
+            .field("len", &self.0.len())
+            .finish()
+    }
+}
+
+impl Count for AtomicUsize {
+    fn message(&self, _: usize) {
+        self.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// ===== impl Frames =====
+
+impl Frames {
+    /// Returns the number of messages completed by `data`.
+    pub(crate) fn count(&mut self, mut data: &[u8]) -> usize {
+        let mut messages = 0;
+
+        while !data.is_empty() {
+            if self.remaining > 0 {
+                let n = cmp::min(self.remaining, data.len());
+                self.remaining -= n;
+                data = &data[n..];
+
+                if self.remaining == 0 {
+                    messages += 1;
+                }
+
+                continue;
+            }
+
+            let n = cmp::min(5 - self.header_len, data.len());
+            self.header[self.header_len..self.header_len + n].copy_from_slice(&data[..n]);
+            self.header_len += n;
+            data = &data[n..];
+
+            if self.header_len == 5 {
+                self.header_len = 0;
+                self.remaining = (self.header[1] as usize) << 24
+                    | (self.header[2] as usize) << 16
+                    | (self.header[3] as usize) << 8
+                    | (self.header[4] as usize);
+
+                if self.remaining == 0 {
+                    messages += 1;
+                }
+            }
+        }
+
+        messages
+    }
+}
@@ -1,15 +1,31 @@
 //! gRPC generic over encoder / decoder.
+//!
+//! The crate has two layers. The high-level layer, made of `client::Grpc`,
+//! `server::Grpc` and the code generated from protos, runs calls over
+//! HTTP/2.0 services, with `Streaming` and `Encode` as the message streams
+//! and bodies.
+//!
+//! The low-level layer is the gRPC framing itself. `FrameEncoder` and
+//! `FrameDecoder` convert between messages and the bytes of length-prefixed
+//! frames without performing any IO, for custom event loops and transports
+//! that are driven by hand.
 
 pub mod server;
 
+mod bytes;
 mod codec;
+pub(crate) mod counter;
 
+pub use self::bytes::{BytesCodec, SharedBytesCodec};
 pub use self::codec::{
     Codec,
     Encoder,
     Decoder,
     Streaming,
+    Trailers,
     Encode,
     EncodeBuf,
     DecodeBuf,
+    FrameEncoder,
+    FrameDecoder,
 };
@@ -2,6 +2,7 @@ use {Response};
 use super::streaming;
 use super::unary::Once;
 use generic::{Encoder, Encode};
+use stats::CallStats;
 
 use {h2, http};
 use futures::{Future, Poll};
@@ -27,6 +28,11 @@ where T: Future<Item = Response<E::Item>, Error = ::Error>,
         let inner = streaming::ResponseFuture::new(inner, encoder);
         ResponseFuture { inner }
     }
+
+    /// Report the response to `stats`.
+    pub(crate) fn stats(self, stats: Option<CallStats>) -> Self {
+        ResponseFuture { inner: self.inner.stats(stats) }
+    }
 }
 
 impl<T, E> Future for ResponseFuture<T, E>
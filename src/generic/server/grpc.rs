@@ -2,9 +2,17 @@ use ::Request;
 use super::{streaming, server_streaming, client_streaming, unary};
 use generic::{Codec, Streaming};
 use generic::server::{StreamingService, ServerStreamingService, ClientStreamingService, UnaryService};
+use generic::counter::MessageCounters;
+use arena::ArenaConfig;
+use coalesce::Coalesce;
+use keepalive::Keepalive;
+use limit::Limits;
+use stats::{CallStats, Handler, Side};
+use strictness::Strictness;
 
+use bytes::Bytes;
 use http;
-use tower_h2::{Body, Data};
+use tower_h2::Body;
 
 #[derive(Debug, Clone)]
 pub struct Grpc<T> {
@@ -26,7 +34,8 @@ where T: Codec,
         -> unary::ResponseFuture<S, T::Encoder, Streaming<T::Decoder, B>>
     where S: UnaryService<Request = T::Decode,
                          Response = T::Encode>,
-          B: Body<Data = Data>,
+          B: Body,
+          B::Data: Into<Bytes>,
     {
         let request = self.map_request(request);
         unary::ResponseFuture::new(service, request, self.codec.encoder())
@@ -39,10 +48,15 @@ where T: Codec,
     where S: ClientStreamingService<Request = T::Decode,
                               RequestStream = Streaming<T::Decoder, B>,
                                    Response = T::Encode>,
-          B: Body<Data = Data>,
+          B: Body,
+          B::Data: Into<Bytes>,
     {
-        let response = service.call(self.map_request(request));
+        let request = self.map_request(request);
+        let stats = request.extensions().get::<CallStats>().cloned();
+        let response = service.call(request);
+
         client_streaming::ResponseFuture::new(response, self.codec.encoder())
+            .stats(stats)
     }
 
     pub fn server_streaming<S, B>(&mut self,
@@ -51,7 +65,8 @@ where T: Codec,
         -> server_streaming::ResponseFuture<S, T::Encoder, Streaming<T::Decoder, B>>
     where S: ServerStreamingService<Request = T::Decode,
                                    Response = T::Encode>,
-          B: Body<Data = Data>,
+          B: Body,
+          B::Data: Into<Bytes>,
     {
         let request = self.map_request(request);
         server_streaming::ResponseFuture::new(service, request, self.codec.encoder())
@@ -64,22 +79,52 @@ where T: Codec,
     where S: StreamingService<Request = T::Decode,
                         RequestStream = Streaming<T::Decoder, B>,
                              Response = T::Encode>,
-          B: Body<Data = Data>,
+          B: Body,
+          B::Data: Into<Bytes>,
     {
-        let response = service.call(self.map_request(request));
+        let limit = request.extensions().get::<Limits>().and_then(|limits| limits.sent);
+        let keepalive = request.extensions().get::<Keepalive>().cloned();
+        let coalesce = request.extensions().get::<Coalesce>().cloned();
+        let request = self.map_request(request);
+        let stats = request.extensions().get::<CallStats>().cloned();
+        let response = service.call(request);
+
         streaming::ResponseFuture::new(response, self.codec.encoder())
+            .limit_sent(limit)
+            .keepalive(keepalive)
+            .coalesce(coalesce)
+            .stats(stats)
     }
 
     /// Map an inbound HTTP request to a streaming decoded request
-    fn map_request<B>(&mut self, request: http::Request<B>)
+    fn map_request<B>(&mut self, mut request: http::Request<B>)
         -> Request<Streaming<T::Decoder, B>>
-    where B: Body<Data = Data>,
+    where B: Body,
+          B::Data: Into<Bytes>,
     {
+        // Start reporting the call, if the server is instrumented
+        let handler = request.extensions().get::<Handler>().cloned();
+        if let Some(handler) = handler {
+            let stats = CallStats::new(&handler, Side::Server, request.uri().path());
+            stats.headers_received(request.headers());
+
+            MessageCounters::register(&mut request, stats.counter());
+            request.extensions_mut().insert(stats);
+        }
+
         // Map the request body
         let (head, body) = request.into_parts();
 
         // Wrap the body stream with a decoder
-        let body = Streaming::new(self.codec.decoder(), body, false);
+        let received = head.extensions.get::<MessageCounters>().cloned();
+        let limit = head.extensions.get::<Limits>().and_then(|limits| limits.received);
+        let strictness = head.extensions.get::<Strictness>().cloned();
+        let arena = head.extensions.get::<ArenaConfig>().map(ArenaConfig::arena);
+        let body = Streaming::new(self.codec.decoder(), body, false)
+            .count_received(received)
+            .limit_received(limit)
+            .strictness(strictness)
+            .arena(arena);
 
         // Reconstruct the HTTP request
         let request = http::Request::from_parts(head, body);
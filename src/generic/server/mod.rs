@@ -7,7 +7,7 @@ pub mod unary;
 
 pub use self::grpc::Grpc;
 
-use {Request, Response};
+use {Request, Response, Status};
 
 use futures::{Future, Stream};
 use tower::ReadyService;
@@ -27,9 +27,19 @@ pub trait StreamingService {
     type Response;
 
     /// Stream of outbound response messages
-    type ResponseStream: Stream<Item = Self::Response, Error = ::Error>;
+    type ResponseStream: Stream<Item = Self::Response, Error = Self::ResponseError>;
+
+    /// Error of the response stream, sent to the client as the status in
+    /// the response trailers
+    type ResponseError: Into<Status>;
 
     /// Response future
+    ///
+    /// The response headers, the initial metadata, are sent as soon as the
+    /// future resolves, without waiting for the first message. Long-lived
+    /// streams that produce messages later, such as the `Receiver` of a
+    /// `sender::channel`, can resolve it right away to confirm to
+    /// the client that the call was accepted.
     type Future: Future<Item = ::Response<Self::ResponseStream>, Error = ::Error>;
 
     /// Call the service
@@ -41,12 +51,14 @@ where T: ReadyService<Request = Request<S1>,
                      Response = Response<S2>,
                         Error = ::Error>,
       S1: Stream<Error = ::Error>,
-      S2: Stream<Error = ::Error>,
+      S2: Stream,
+      S2::Error: Into<Status>,
 {
     type Request = S1::Item;
     type RequestStream = S1;
     type Response = S2::Item;
     type ResponseStream = S2;
+    type ResponseError = S2::Error;
     type Future = T::Future;
 
     fn call(&mut self, request: T::Request) -> Self::Future {
@@ -135,9 +147,19 @@ pub trait ServerStreamingService {
     type Response;
 
     /// Stream of outbound response messages
-    type ResponseStream: Stream<Item = Self::Response, Error = ::Error>;
+    type ResponseStream: Stream<Item = Self::Response, Error = Self::ResponseError>;
+
+    /// Error of the response stream, sent to the client as the status in
+    /// the response trailers
+    type ResponseError: Into<Status>;
 
     /// Response future
+    ///
+    /// The response headers, the initial metadata, are sent as soon as the
+    /// future resolves, without waiting for the first message. Long-lived
+    /// streams that produce messages later, such as the `Receiver` of a
+    /// `sender::channel`, can resolve it right away to confirm to
+    /// the client that the call was accepted.
     type Future: Future<Item = ::Response<Self::ResponseStream>, Error = ::Error>;
 
     /// Call the service
@@ -148,11 +170,13 @@ impl<T, M, S> ServerStreamingService for T
 where T: ReadyService<Request = Request<M>,
                      Response = Response<S>,
                         Error = ::Error>,
-      S: Stream<Error = ::Error>,
+      S: Stream,
+      S::Error: Into<Status>,
 {
     type Request = M;
     type Response = S::Item;
     type ResponseStream = S;
+    type ResponseError = S::Error;
     type Future = T::Future;
 
     fn call(&mut self, request: T::Request) -> Self::Future {
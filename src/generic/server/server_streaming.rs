@@ -2,6 +2,10 @@ use {Request, Response};
 use super::streaming;
 use generic::{Encoder, Encode};
 use generic::server::ServerStreamingService;
+use coalesce::Coalesce;
+use keepalive::Keepalive;
+use limit::Limits;
+use stats::CallStats;
 
 use {h2, http};
 use futures::{Future, Stream, Poll};
@@ -39,12 +43,21 @@ where T: ServerStreamingService<Request = S::Item, Response = E::Item>,
       S: Stream<Error = ::Error>,
 {
     pub fn new(inner: T, request: Request<S>, encoder: E) -> Self {
+        let limit = request.extensions().get::<Limits>().and_then(|limits| limits.sent);
+        let keepalive = request.extensions().get::<Keepalive>().cloned();
+        let coalesce = request.extensions().get::<Coalesce>().cloned();
+        let stats = request.extensions().get::<CallStats>().cloned();
+
         let inner = Inner {
             inner,
             state: Some(State::Requesting(request)),
         };
 
-        let inner = streaming::ResponseFuture::new(inner, encoder);
+        let inner = streaming::ResponseFuture::new(inner, encoder)
+            .limit_sent(limit)
+            .keepalive(keepalive)
+            .coalesce(coalesce)
+            .stats(stats);
         ResponseFuture { inner }
     }
 }
@@ -1,5 +1,8 @@
-use {Response};
+use {Code, Response};
+use coalesce::Coalesce;
 use generic::{Encoder, Encode};
+use keepalive::Keepalive;
+use stats::CallStats;
 
 use {http, h2};
 use futures::{Future, Stream, Poll, Async};
@@ -8,6 +11,10 @@ use futures::{Future, Stream, Poll, Async};
 pub struct ResponseFuture<T, E> {
     inner: T,
     encoder: Option<E>,
+    limit: Option<usize>,
+    keepalive: Option<Keepalive>,
+    coalesce: Option<Coalesce>,
+    stats: Option<CallStats>,
 }
 
 // ===== impl ResponseFuture =====
@@ -22,8 +29,37 @@ where T: Future<Item = Response<S>,
         ResponseFuture {
             inner,
             encoder: Some(encoder),
+            limit: None,
+            keepalive: None,
+            coalesce: None,
+            stats: None,
         }
     }
+
+    /// Fail the response with `RESOURCE_EXHAUSTED` once more than `limit`
+    /// messages are sent.
+    pub(crate) fn limit_sent(mut self, limit: Option<usize>) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Keep the response stream alive while it is idle.
+    pub(crate) fn keepalive(mut self, keepalive: Option<Keepalive>) -> Self {
+        self.keepalive = keepalive;
+        self
+    }
+
+    /// Coalesce the messages of the response stream.
+    pub(crate) fn coalesce(mut self, coalesce: Option<Coalesce>) -> Self {
+        self.coalesce = coalesce;
+        self
+    }
+
+    /// Report the response to `stats`.
+    pub(crate) fn stats(mut self, stats: Option<CallStats>) -> Self {
+        self.stats = stats;
+        self
+    }
 }
 
 impl<T, E, S> Future for ResponseFuture<T, E>
@@ -43,11 +79,24 @@ where T: Future<Item = Response<S>,
             Err(e) => {
                 match e {
                     ::Error::Grpc(status) => {
-                        let response = Response::new(Encode::error(status));
-                        return Ok(response.into_http().into());
+                        let response = Response::trailers_only(&status, Encode::empty())
+                            .into_http();
+
+                        if let Some(ref stats) = self.stats {
+                            stats.headers_sent(response.headers());
+                            stats.end(status.code());
+                        }
+
+                        return Ok(response.into());
                     }
                     // TODO: Is this correct?
-                    _ => return Err(h2::Reason::INTERNAL_ERROR.into()),
+                    _ => {
+                        if let Some(ref stats) = self.stats {
+                            stats.end(Code::UNKNOWN);
+                        }
+
+                        return Err(h2::Reason::INTERNAL_ERROR.into());
+                    }
                 }
             }
         };
@@ -55,6 +104,10 @@ where T: Future<Item = Response<S>,
         // Convert to an HTTP response
         let response = response.into_http();
 
+        if let Some(ref stats) = self.stats {
+            stats.headers_sent(response.headers());
+        }
+
         // Map the response body
         let (head, body) = response.into_parts();
 
@@ -62,7 +115,11 @@ where T: Future<Item = Response<S>,
         let encoder = self.encoder.take().expect("encoder consumed");
 
         // Encode the body
-        let body = Encode::new(encoder, body, true);
+        let body = Encode::new(encoder, body, true)
+            .limit_sent(self.limit)
+            .keepalive(self.keepalive.as_ref())
+            .coalesce(self.coalesce.as_ref())
+            .stats(self.stats.take());
 
         // Success
         Ok(http::Response::from_parts(head, body).into())
@@ -0,0 +1,818 @@
+//! Cleartext HTTP/2.0 (h2c), for deployments that terminate TLS elsewhere.
+//!
+//! Clients dial h2c with prior knowledge, sending the HTTP/2.0 connection
+//! preface right away, which is what `tcp::TcpConnect` does for a
+//! `Channel`. Servers accept such connections as they are, and `accept`
+//! additionally lets clients start with an HTTP/1.1 request carrying
+//! `Upgrade: h2c`, as described in RFC 7540, section 3.2:
+//!
+//! ```ignore
+//! let serve = listener.incoming().for_each(move |(sock, _)| {
+//!     let h2 = h2.clone();
+//!     let serve = h2c::accept(sock)
+//!         .map_err(|e| error!("h2c error: {:?}", e))
+//!         .and_then(move |conn| h2.serve(conn).map_err(|e| error!("h2 error: {:?}", e)));
+//!
+//!     handle.spawn(serve);
+//!     Ok(())
+//! });
+//! ```
+//!
+//! The request that carried the upgrade is served as the first stream of
+//! the HTTP/2.0 connection, so its body must fit in the initial flow
+//! control window of 65,535 bytes. Upgrade requests with larger or chunked
+//! bodies, and HTTP/1.1 requests that don't ask for an upgrade, are refused
+//! with an HTTP/1.1 error response.
+//!
+//! The `HTTP2-Settings` header of an upgrade request is required but not
+//! decoded. After switching protocols, the client must still send its
+//! preface and a SETTINGS frame, which are handed to HTTP/2.0 ahead of the
+//! upgraded request, so the client's settings are applied from that frame
+//! before the request is answered.
+
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::{Future, Poll, Async};
+use http::Uri;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use std::{cmp, fmt, io};
+use std::io::{Read, Write};
+
+/// The connection preface sent by HTTP/2.0 clients.
+const PREFACE: &'static [u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// The largest HTTP/1.1 request head accepted.
+const MAX_HEAD_LEN: usize = 16 * 1024;
+
+/// The largest body of an upgrade request, the initial flow control window.
+const MAX_BODY_LEN: usize = 65_535;
+
+/// The default maximum HTTP/2.0 frame size.
+const MAX_FRAME_LEN: usize = 16_384;
+
+const FRAME_DATA: u8 = 0x0;
+const FRAME_HEADERS: u8 = 0x1;
+const FRAME_SETTINGS: u8 = 0x4;
+const FRAME_CONTINUATION: u8 = 0x9;
+
+const FLAG_END_STREAM: u8 = 0x1;
+const FLAG_END_HEADERS: u8 = 0x4;
+
+const SWITCHING_PROTOCOLS: &'static [u8] =
+    b"HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: h2c\r\n\r\n";
+
+/// Accept a cleartext HTTP/2.0 connection on `io`, with prior knowledge or
+/// upgraded from HTTP/1.1.
+pub fn accept<T>(io: T) -> Accept<T>
+where T: AsyncRead + AsyncWrite,
+{
+    Accept {
+        io: Some(io),
+        buf: BytesMut::with_capacity(PREFACE.len()),
+        state: State::Detecting,
+    }
+}
+
+/// Accepts a cleartext HTTP/2.0 connection.
+///
+/// Resolves to the connection once the client has sent, or been made to
+/// send, the HTTP/2.0 connection preface.
+pub struct Accept<T> {
+    io: Option<T>,
+
+    /// The bytes read from the client and not yet handed to HTTP/2.0.
+    buf: BytesMut,
+
+    state: State,
+}
+
+/// A connection speaking HTTP/2.0, to be served by `tower_h2::Server`.
+pub struct Connection<T> {
+    io: T,
+
+    /// Bytes read by `Accept`, or made up for the upgrade request, that are
+    /// read before those of `io`.
+    buf: Bytes,
+}
+
+enum State {
+    /// Reading the connection preface, or the head of an HTTP/1.1 request.
+    Detecting,
+
+    /// Reading the body of an upgrade request.
+    ReadingBody {
+        head: Head,
+        head_len: usize,
+        body_len: usize,
+    },
+
+    /// Writing an HTTP/1.1 response.
+    Writing {
+        response: Bytes,
+
+        /// The frames of the upgrade request, once protocols are switched.
+        frames: Option<Bytes>,
+    },
+
+    /// Reading the preface and settings sent after switching protocols.
+    ReadingSettings {
+        frames: Bytes,
+    },
+
+    /// The client speaks HTTP/2.0.
+    Done,
+}
+
+/// The head of an HTTP/1.1 request.
+struct Head {
+    method: String,
+    target: String,
+    headers: Vec<(String, String)>,
+}
+
+// ===== impl Accept =====
+
+impl<T> Accept<T>
+where T: AsyncRead + AsyncWrite,
+{
+    /// Read more bytes from the client into `buf`.
+    fn read(&mut self) -> Poll<(), io::Error> {
+        let io = self.io.as_mut().expect("polled after complete");
+        let mut chunk = [0; 4096];
+
+        match io.read(&mut chunk) {
+            Ok(0) => Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => {
+                self.buf.reserve(n);
+                self.buf.put_slice(&chunk[..n]);
+                Ok(Async::Ready(()))
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(Async::NotReady),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Returns the next state once the client is known to speak HTTP/1.1
+    /// or HTTP/2.0, or `None` if more bytes are needed.
+    fn detect(&self) -> Option<State> {
+        if self.buf.starts_with(PREFACE) {
+            return Some(State::Done);
+        }
+
+        if PREFACE.starts_with(&self.buf[..]) {
+            return None;
+        }
+
+        let head_len = match self.buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            Some(i) => i + 4,
+            None if self.buf.len() > MAX_HEAD_LEN => {
+                return Some(refuse("431 Request Header Fields Too Large", ""));
+            }
+            None => return None,
+        };
+
+        let head = match Head::parse(&self.buf[..head_len]) {
+            Some(head) => head,
+            None => return Some(refuse("400 Bad Request", "")),
+        };
+
+        if !head.is_upgrade() {
+            debug!("HTTP/1.1 request without h2c upgrade; method={}, target={}",
+                   head.method, head.target);
+            return Some(refuse("426 Upgrade Required", "Upgrade: h2c\r\n"));
+        }
+
+        if head.header("transfer-encoding").is_some() {
+            return Some(refuse("411 Length Required", ""));
+        }
+
+        let body_len = match head.header("content-length") {
+            Some(len) => match len.trim().parse::<usize>() {
+                Ok(len) => len,
+                Err(_) => return Some(refuse("400 Bad Request", "")),
+            },
+            None => 0,
+        };
+
+        if body_len > MAX_BODY_LEN {
+            return Some(refuse("413 Payload Too Large", ""));
+        }
+
+        Some(State::ReadingBody { head, head_len, body_len })
+    }
+}
+
+impl<T> Future for Accept<T>
+where T: AsyncRead + AsyncWrite,
+{
+    type Item = Connection<T>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                State::Detecting => match self.detect() {
+                    Some(State::Done) => break,
+                    Some(next) => next,
+                    None => {
+                        try_ready!(self.read());
+                        continue;
+                    }
+                },
+                State::ReadingBody { ref head, head_len, body_len } => {
+                    if self.buf.len() < head_len + body_len {
+                        try_ready!(self.read());
+                        continue;
+                    }
+
+                    let request = self.buf.split_to(head_len + body_len);
+
+                    match head.frames(&request[head_len..]) {
+                        Some(frames) => State::Writing {
+                            response: Bytes::from_static(SWITCHING_PROTOCOLS),
+                            frames: Some(frames),
+                        },
+                        None => refuse("431 Request Header Fields Too Large", ""),
+                    }
+                }
+                State::Writing { ref mut response, ref mut frames } => {
+                    let io = self.io.as_mut().expect("polled after complete");
+
+                    while !response.is_empty() {
+                        let n = match io.write(response) {
+                            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                            Ok(n) => n,
+                            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                                return Ok(Async::NotReady);
+                            }
+                            Err(e) => return Err(e),
+                        };
+
+                        response.split_to(n);
+                    }
+
+                    match io.flush() {
+                        Ok(()) => {}
+                        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                            return Ok(Async::NotReady);
+                        }
+                        Err(e) => return Err(e),
+                    }
+
+                    match frames.take() {
+                        Some(frames) => State::ReadingSettings { frames },
+                        None => {
+                            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                                      "HTTP/1.1 request refused"));
+                        }
+                    }
+                }
+                State::ReadingSettings { ref frames } => {
+                    // The upgrade request is the first stream, and may only
+                    // follow the client's preface and initial settings.
+                    let len = PREFACE.len() + 9;
+
+                    if self.buf.len() < len {
+                        if !PREFACE.starts_with(&self.buf[..cmp::min(self.buf.len(), PREFACE.len())]) {
+                            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                                      "invalid HTTP/2.0 preface"));
+                        }
+
+                        try_ready!(self.read());
+                        continue;
+                    }
+
+                    if !self.buf.starts_with(PREFACE) {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                                  "invalid HTTP/2.0 preface"));
+                    }
+
+                    let settings = &self.buf[PREFACE.len()..len];
+
+                    if settings[3] != FRAME_SETTINGS {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                                  "HTTP/2.0 preface not followed by SETTINGS"));
+                    }
+
+                    let settings_len = (settings[0] as usize) << 16
+                        | (settings[1] as usize) << 8
+                        | settings[2] as usize;
+
+                    if self.buf.len() < len + settings_len {
+                        try_ready!(self.read());
+                        continue;
+                    }
+
+                    let rest = self.buf.split_off(len + settings_len);
+                    self.buf.reserve(frames.len() + rest.len());
+                    self.buf.put_slice(frames);
+                    self.buf.put_slice(&rest);
+                    break;
+                }
+                State::Done => unreachable!(),
+            };
+
+            self.state = next;
+        }
+
+        let buf = self.buf.take().freeze();
+        let io = self.io.take().expect("polled after complete");
+
+        Ok(Async::Ready(Connection { io, buf }))
+    }
+}
+
+impl<T> fmt::Debug for Accept<T>
+where T: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let state = match self.state {
+            State::Detecting => "Detecting",
+            State::ReadingBody { .. } => "ReadingBody",
+            State::Writing { .. } => "Writing",
+            State::ReadingSettings { .. } => "ReadingSettings",
+            State::Done => "Done",
+        };
+
+        fmt.debug_struct("h2c::Accept")
+            .field("io", &self.io)
+            .field("state", &state)
+            .finish()
+    }
+}
+
+// ===== impl Connection =====
+
+impl<T> Connection<T> {
+    /// Returns a reference to the underlying connection.
+    pub fn get_ref(&self) -> &T {
+        &self.io
+    }
+
+    /// Returns a mutable reference to the underlying connection.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.io
+    }
+}
+
+impl<T> io::Read for Connection<T>
+where T: io::Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.buf.is_empty() {
+            return self.io.read(buf);
+        }
+
+        let n = cmp::min(buf.len(), self.buf.len());
+        buf[..n].copy_from_slice(&self.buf.split_to(n));
+        Ok(n)
+    }
+}
+
+impl<T> io::Write for Connection<T>
+where T: io::Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.io.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+impl<T> AsyncRead for Connection<T>
+where T: AsyncRead,
+{
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        self.io.prepare_uninitialized_buffer(buf)
+    }
+}
+
+impl<T> AsyncWrite for Connection<T>
+where T: AsyncWrite,
+{
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.io.shutdown()
+    }
+}
+
+impl<T> fmt::Debug for Connection<T>
+where T: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("h2c::Connection")
+            .field("io", &self.io)
+            .field("buffered", &self.buf.len())
+            .finish()
+    }
+}
+
+// ===== impl Head =====
+
+impl Head {
+    fn parse(bytes: &[u8]) -> Option<Head> {
+        let text = ::std::str::from_utf8(bytes).ok()?;
+        let mut lines = text.split("\r\n");
+
+        let mut request_line = lines.next()?.split(' ');
+        let method = request_line.next()?.to_string();
+        let target = request_line.next()?.to_string();
+
+        if request_line.next()? != "HTTP/1.1" {
+            return None;
+        }
+
+        let mut headers = vec![];
+
+        for line in lines.filter(|line| !line.is_empty()) {
+            let i = line.find(':')?;
+            let name = line[..i].trim().to_lowercase();
+            let value = line[i + 1..].trim().to_string();
+            headers.push((name, value));
+        }
+
+        Some(Head { method, target, headers })
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|&&(ref n, _)| n == name)
+            .map(|&(_, ref value)| &value[..])
+    }
+
+    /// Returns true if the header `name` lists `token`.
+    fn lists(&self, name: &str, token: &str) -> bool {
+        self.headers.iter()
+            .filter(|&&(ref n, _)| n == name)
+            .flat_map(|&(_, ref value)| value.split(','))
+            .any(|t| t.trim().eq_ignore_ascii_case(token))
+    }
+
+    fn is_upgrade(&self) -> bool {
+        self.lists("upgrade", "h2c")
+            && self.lists("connection", "upgrade")
+            && self.header("http2-settings").is_some()
+    }
+
+    /// Returns the HTTP/2.0 frames of the request on the first stream, or
+    /// `None` if its headers don't fit in the frames' size limits.
+    fn frames(&self, body: &[u8]) -> Option<Bytes> {
+        let path = match self.target.parse::<Uri>() {
+            Ok(ref uri) if uri.authority_part().is_some() => {
+                uri.path_and_query().map_or("/", |p| p.as_str()).to_string()
+            }
+            _ => self.target.clone(),
+        };
+
+        let mut block = vec![];
+        put_header(&mut block, ":method", &self.method);
+        put_header(&mut block, ":scheme", "http");
+        put_header(&mut block, ":path", &path);
+
+        if let Some(host) = self.header("host") {
+            put_header(&mut block, ":authority", host);
+        }
+
+        for &(ref name, ref value) in &self.headers {
+            let hop_by_hop = match &name[..] {
+                "connection" | "upgrade" | "http2-settings" | "keep-alive" |
+                "proxy-connection" | "transfer-encoding" | "host" => true,
+                "te" => value != "trailers",
+                name => self.lists("connection", name),
+            };
+
+            if !hop_by_hop {
+                put_header(&mut block, name, value);
+            }
+        }
+
+        // Headers beyond a frame and a few continuations are refused.
+        if block.len() > 4 * MAX_FRAME_LEN {
+            return None;
+        }
+
+        let mut frames = BytesMut::with_capacity(block.len() + body.len() + 64);
+        let mut chunks = block.chunks(MAX_FRAME_LEN).peekable();
+        let mut kind = FRAME_HEADERS;
+
+        while let Some(chunk) = chunks.next() {
+            let mut flags = 0;
+
+            if kind == FRAME_HEADERS && body.is_empty() {
+                flags |= FLAG_END_STREAM;
+            }
+
+            if chunks.peek().is_none() {
+                flags |= FLAG_END_HEADERS;
+            }
+
+            put_frame(&mut frames, kind, flags, chunk);
+            kind = FRAME_CONTINUATION;
+        }
+
+        let mut chunks = body.chunks(MAX_FRAME_LEN).peekable();
+
+        while let Some(chunk) = chunks.next() {
+            let flags = if chunks.peek().is_none() { FLAG_END_STREAM } else { 0 };
+            put_frame(&mut frames, FRAME_DATA, flags, chunk);
+        }
+
+        Some(frames.freeze())
+    }
+}
+
+// ===== utility fns =====
+
+/// Returns the state writing an HTTP/1.1 error response.
+fn refuse(status: &str, headers: &str) -> State {
+    debug!("refusing HTTP/1.1 request; status={}", status);
+
+    let response = format!("HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n{}\r\n",
+                           status, headers);
+
+    State::Writing {
+        response: response.into(),
+        frames: None,
+    }
+}
+
+/// Write a frame on the first stream.
+fn put_frame(buf: &mut BytesMut, kind: u8, flags: u8, payload: &[u8]) {
+    buf.reserve(9 + payload.len());
+    buf.put_uint_be(payload.len() as u64, 3);
+    buf.put_u8(kind);
+    buf.put_u8(flags);
+    buf.put_u32_be(1);
+    buf.put_slice(payload);
+}
+
+/// Write a header as an HPACK literal without indexing, so the encoder's
+/// table is left empty.
+fn put_header(buf: &mut Vec<u8>, name: &str, value: &str) {
+    buf.push(0);
+    put_string(buf, name.as_bytes());
+    put_string(buf, value.as_bytes());
+}
+
+fn put_string(buf: &mut Vec<u8>, s: &[u8]) {
+    // A 7 bit prefix integer, without Huffman coding.
+    let mut len = s.len();
+
+    if len < 127 {
+        buf.push(len as u8);
+    } else {
+        buf.push(127);
+        len -= 127;
+
+        while len >= 128 {
+            buf.push((len % 128) as u8 | 0x80);
+            len /= 128;
+        }
+
+        buf.push(len as u8);
+    }
+
+    buf.extend_from_slice(s);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use duplex::{duplex, DuplexStream};
+
+    use futures::future;
+
+    /// An empty SETTINGS frame.
+    const SETTINGS: &'static [u8] = &[0, 0, 0, FRAME_SETTINGS, 0, 0, 0, 0, 0];
+
+    /// The head of an upgrade request, without its final empty line.
+    const UPGRADE: &'static str = "POST /test.Service/Method HTTP/1.1\r\n\
+                                   Host: example.com\r\n\
+                                   Connection: Upgrade, HTTP2-Settings\r\n\
+                                   Upgrade: h2c\r\n\
+                                   HTTP2-Settings: AAMAAABkAAQAAP__\r\n";
+
+    /// Read everything buffered on `io`.
+    fn read_available<R: Read>(io: &mut R) -> Vec<u8> {
+        let mut bytes = vec![];
+        let mut chunk = [0; 1024];
+
+        loop {
+            match io.read(&mut chunk) {
+                Ok(0) => return bytes,
+                Ok(n) => bytes.extend_from_slice(&chunk[..n]),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return bytes,
+                Err(e) => panic!("read failed: {:?}", e),
+            }
+        }
+    }
+
+    fn accepted(accept: &mut Accept<DuplexStream>) -> Connection<DuplexStream> {
+        match accept.poll().unwrap() {
+            Async::Ready(conn) => conn,
+            Async::NotReady => panic!("connection not accepted"),
+        }
+    }
+
+    /// Returns the type, flags, stream and payload of each frame.
+    fn frames(mut bytes: &[u8]) -> Vec<(u8, u8, u32, Vec<u8>)> {
+        let mut frames = vec![];
+
+        while !bytes.is_empty() {
+            let len = (bytes[0] as usize) << 16 | (bytes[1] as usize) << 8 | bytes[2] as usize;
+            let stream = (bytes[5] as u32 & 0x7f) << 24
+                | (bytes[6] as u32) << 16
+                | (bytes[7] as u32) << 8
+                | bytes[8] as u32;
+
+            frames.push((bytes[3], bytes[4], stream, bytes[9..9 + len].to_vec()));
+            bytes = &bytes[9 + len..];
+        }
+
+        frames
+    }
+
+    /// Returns true if the header block holds the field `name: value`.
+    fn has_header(block: &[u8], name: &str, value: &str) -> bool {
+        let mut field = vec![];
+        put_header(&mut field, name, value);
+
+        block.windows(field.len()).any(|w| w == &field[..])
+    }
+
+    /// Returns the HTTP/1.1 response an HTTP/1.1 request is refused with.
+    fn refused(request: &str) -> String {
+        let (mut client, server) = duplex();
+
+        future::lazy(move || {
+            client.write_all(request.as_bytes()).unwrap();
+
+            let mut accept = accept(server);
+            match accept.poll() {
+                Err(ref e) if e.kind() == io::ErrorKind::InvalidData => {}
+                other => panic!("request not refused; ok={:?}", other.is_ok()),
+            }
+
+            let response = read_available(&mut client);
+            Ok::<_, ()>(String::from_utf8(response).unwrap())
+        }).wait().unwrap()
+    }
+
+    #[test]
+    fn prior_knowledge_passes_through() {
+        let (mut client, server) = duplex();
+
+        future::lazy(move || {
+            client.write_all(PREFACE).unwrap();
+            client.write_all(SETTINGS).unwrap();
+
+            let mut conn = accepted(&mut accept(server));
+
+            let mut expected = PREFACE.to_vec();
+            expected.extend_from_slice(SETTINGS);
+            assert_eq!(read_available(&mut conn), expected);
+
+            // Nothing is written to a client with prior knowledge.
+            assert!(read_available(&mut client).is_empty());
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn preface_split_across_reads() {
+        let (mut client, server) = duplex();
+
+        future::lazy(move || {
+            let mut accept = accept(server);
+
+            client.write_all(&PREFACE[..5]).unwrap();
+            assert!(accept.poll().unwrap().is_not_ready());
+
+            client.write_all(&PREFACE[5..]).unwrap();
+            let mut conn = accepted(&mut accept);
+            assert_eq!(read_available(&mut conn), PREFACE);
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn upgrade_without_body() {
+        let (mut client, server) = duplex();
+
+        future::lazy(move || {
+            let mut accept = accept(server);
+
+            client.write_all(UPGRADE.as_bytes()).unwrap();
+            client.write_all(b"\r\n").unwrap();
+            assert!(accept.poll().unwrap().is_not_ready());
+            assert_eq!(read_available(&mut client), SWITCHING_PROTOCOLS);
+
+            client.write_all(PREFACE).unwrap();
+            client.write_all(SETTINGS).unwrap();
+
+            let mut conn = accepted(&mut accept);
+            let bytes = read_available(&mut conn);
+            assert!(bytes.starts_with(PREFACE));
+
+            let frames = frames(&bytes[PREFACE.len()..]);
+            assert_eq!(frames.len(), 2);
+            assert_eq!(frames[0].0, FRAME_SETTINGS);
+
+            let (kind, flags, stream, ref block) = frames[1];
+            assert_eq!((kind, flags, stream), (FRAME_HEADERS, FLAG_END_STREAM | FLAG_END_HEADERS, 1));
+            assert!(has_header(block, ":method", "POST"));
+            assert!(has_header(block, ":scheme", "http"));
+            assert!(has_header(block, ":path", "/test.Service/Method"));
+            assert!(has_header(block, ":authority", "example.com"));
+            assert!(!has_header(block, "upgrade", "h2c"));
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn upgrade_with_body() {
+        let (mut client, server) = duplex();
+
+        future::lazy(move || {
+            let mut accept = accept(server);
+
+            client.write_all(UPGRADE.as_bytes()).unwrap();
+            client.write_all(b"Content-Length: 5\r\n\r\nhel").unwrap();
+            assert!(accept.poll().unwrap().is_not_ready());
+            assert!(read_available(&mut client).is_empty());
+
+            client.write_all(b"lo").unwrap();
+            assert!(accept.poll().unwrap().is_not_ready());
+            assert_eq!(read_available(&mut client), SWITCHING_PROTOCOLS);
+
+            // The preface after the upgrade may also arrive in pieces.
+            client.write_all(&PREFACE[..10]).unwrap();
+            assert!(accept.poll().unwrap().is_not_ready());
+            client.write_all(&PREFACE[10..]).unwrap();
+            client.write_all(SETTINGS).unwrap();
+
+            let mut conn = accepted(&mut accept);
+            let bytes = read_available(&mut conn);
+            let frames = frames(&bytes[PREFACE.len()..]);
+            assert_eq!(frames.len(), 3);
+
+            let (kind, flags, stream, ref block) = frames[1];
+            assert_eq!((kind, flags, stream), (FRAME_HEADERS, FLAG_END_HEADERS, 1));
+            assert!(has_header(block, "content-length", "5"));
+
+            let (kind, flags, stream, ref data) = frames[2];
+            assert_eq!((kind, flags, stream), (FRAME_DATA, FLAG_END_STREAM, 1));
+            assert_eq!(&data[..], b"hello");
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn chunked_upgrade_refused() {
+        let request = format!("{}Transfer-Encoding: chunked\r\n\r\n", UPGRADE);
+        assert!(refused(&request).starts_with("HTTP/1.1 411 "));
+    }
+
+    #[test]
+    fn oversized_upgrade_refused() {
+        let request = format!("{}Content-Length: {}\r\n\r\n", UPGRADE, MAX_BODY_LEN + 1);
+        assert!(refused(&request).starts_with("HTTP/1.1 413 "));
+    }
+
+    #[test]
+    fn request_without_upgrade_refused() {
+        let response = refused("GET / HTTP/1.1\r\nHost: example.com\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 426 "));
+        assert!(response.contains("\r\nUpgrade: h2c\r\n"));
+    }
+
+    #[test]
+    fn upgraded_preface_without_settings_fails() {
+        let (mut client, server) = duplex();
+
+        future::lazy(move || {
+            let mut accept = accept(server);
+
+            client.write_all(UPGRADE.as_bytes()).unwrap();
+            client.write_all(b"\r\n").unwrap();
+            assert!(accept.poll().unwrap().is_not_ready());
+
+            // A PING frame in place of the client's settings.
+            client.write_all(PREFACE).unwrap();
+            client.write_all(&[0, 0, 8, 0x6, 0, 0, 0, 0, 0]).unwrap();
+            client.write_all(&[0; 8]).unwrap();
+
+            match accept.poll() {
+                Err(ref e) if e.kind() == io::ErrorKind::InvalidData => {}
+                other => panic!("preface accepted; ok={:?}", other.is_ok()),
+            }
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+}
@@ -0,0 +1,319 @@
+//! Custom security handshakes.
+//!
+//! A `Handshaker` runs a handshake over each connection before HTTP/2.0 is
+//! negotiated on it, such as workload attestation or a proprietary
+//! authentication protocol, and may wrap the connection, for instance to
+//! encrypt it. The handshake produces the connection's auth info, which
+//! `AddAuthInfo` inserts into the extensions of every request received on
+//! it.
+//!
+//! Channels dial with `HandshakeConnect`:
+//!
+//! ```ignore
+//! let connect = HandshakeConnect::new(handshaker.clone(), handle.clone());
+//! let channel = Channel::new(resolver, connect, policy);
+//! ```
+//!
+//! Servers run the handshake on each accepted connection, then build a
+//! server for it:
+//!
+//! ```ignore
+//! let serve = listener.incoming().for_each(move |(sock, _)| {
+//!     let new_service = new_service.clone();
+//!     let h = handle.clone();
+//!     let serve = handshaker.server(sock)
+//!         .map_err(|e| error!("handshake failed; error={:?}", e))
+//!         .and_then(move |(sock, info)| {
+//!             let new_service = AddAuthInfo::new(new_service, info);
+//!             let h2 = Server::new(new_service, Default::default(), h);
+//!             h2.serve(sock).map_err(|e| error!("h2 error: {:?}", e))
+//!         });
+//!
+//!     handle.spawn(serve);
+//!     Ok(())
+//! });
+//! ```
+
+use channel::{self, ChannelStats, CountedIo, Endpoint};
+use http2::Http2Settings;
+use tcp::{Dial, TcpOptions};
+
+use futures::{Future, Poll, Async};
+use http;
+use tokio_core::net::TcpStream;
+use tokio_core::reactor::Handle;
+use tokio_io::{AsyncRead, AsyncWrite};
+use tower::{NewService, Service};
+use tower_h2::BoxBody;
+use tower_h2::client::{Connection, Handshake as H2Handshake, HandshakeError};
+
+use std::{fmt, io};
+
+/// Runs a security handshake over connections of type `T`.
+pub trait Handshaker<T> {
+    /// The connection HTTP/2.0 is negotiated on once the handshake is done.
+    type Io: AsyncRead + AsyncWrite;
+
+    /// What the handshake established about the peer.
+    type Info: Clone + Send + Sync + 'static;
+
+    /// Error produced when the handshake fails.
+    type Error: fmt::Debug;
+
+    /// The handshake future.
+    type Future: Future<Item = (Self::Io, Self::Info), Error = Self::Error>;
+
+    /// Run the client side of the handshake on `io`, a connection dialed to
+    /// `endpoint`.
+    fn client(&mut self, endpoint: &Endpoint, io: T) -> Self::Future;
+
+    /// Run the server side of the handshake on `io`, an accepted connection.
+    fn server(&mut self, io: T) -> Self::Future;
+}
+
+/// Connects a `Channel` to its endpoints, running a handshake on each
+/// connection.
+///
+/// Each connection is a TCP connection to the endpoint's address, on which
+/// the handshake runs, followed by the HTTP/2.0 handshake. The handshake's
+/// auth info is dropped; the handshaker fails connections to peers it does
+/// not trust.
+#[derive(Debug, Clone)]
+pub struct HandshakeConnect<H> {
+    handshaker: H,
+    handle: Handle,
+    stats: ChannelStats,
+    tcp: TcpOptions,
+    http2: Http2Settings,
+}
+
+/// Future returned by `HandshakeConnect`.
+pub struct ConnectFuture<H>
+where H: Handshaker<CountedIo<TcpStream>>,
+{
+    state: State<H>,
+    endpoint: Endpoint,
+    connect: HandshakeConnect<H>,
+}
+
+/// Error produced by `HandshakeConnect`.
+#[derive(Debug)]
+pub enum ConnectError<E> {
+    /// The TCP connection failed.
+    Connect(io::Error),
+
+    /// The security handshake failed.
+    Handshake(E),
+
+    /// The HTTP/2.0 handshake failed.
+    Http2(HandshakeError),
+}
+
+/// Inserts a connection's auth info into the extensions of its requests.
+#[derive(Debug, Clone)]
+pub struct AddAuthInfo<S, I> {
+    inner: S,
+    info: I,
+}
+
+/// Creates `AddAuthInfo` services.
+#[derive(Debug)]
+pub struct NewServiceFuture<F, I> {
+    inner: F,
+    info: I,
+}
+
+enum State<H>
+where H: Handshaker<CountedIo<TcpStream>>,
+{
+    Connecting(Dial),
+    Handshake(H::Future),
+    Http2(H2Handshake<H::Io, Handle, BoxBody>),
+}
+
+// ===== impl HandshakeConnect =====
+
+impl<H> HandshakeConnect<H> {
+    /// Run `handshaker` on each connection.
+    ///
+    /// Connections run on the reactor of `handle`.
+    pub fn new(handshaker: H, handle: Handle) -> Self {
+        HandshakeConnect {
+            handshaker,
+            handle,
+            stats: ChannelStats::new(),
+            tcp: TcpOptions::new(),
+            http2: Http2Settings::new(),
+        }
+    }
+
+    /// Count the bytes of each connection in `stats`.
+    ///
+    /// Pass the same `ChannelStats` to `Channel::with_stats`.
+    pub fn with_stats(mut self, stats: ChannelStats) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    /// Set `options` on each TCP connection.
+    pub fn tcp_options(mut self, options: TcpOptions) -> Self {
+        self.tcp = options;
+        self
+    }
+
+    /// Negotiate each connection with `settings`.
+    pub fn http2_settings(mut self, settings: Http2Settings) -> Self {
+        self.http2 = settings;
+        self
+    }
+
+    /// Returns a reference to the handshaker.
+    pub fn get_ref(&self) -> &H {
+        &self.handshaker
+    }
+
+    /// Returns a mutable reference to the handshaker.
+    pub fn get_mut(&mut self) -> &mut H {
+        &mut self.handshaker
+    }
+}
+
+impl<H> channel::Connect for HandshakeConnect<H>
+where H: Handshaker<CountedIo<TcpStream>> + Clone,
+      H::Io: 'static,
+{
+    type Service = Connection<H::Io, Handle, BoxBody>;
+    type Error = ConnectError<H::Error>;
+    type Future = ConnectFuture<H>;
+
+    fn connect(&mut self, endpoint: &Endpoint) -> Self::Future {
+        ConnectFuture {
+            state: State::Connecting(self.tcp.dial(endpoint, &self.handle)),
+            endpoint: endpoint.clone(),
+            connect: self.clone(),
+        }
+    }
+}
+
+// ===== impl ConnectFuture =====
+
+impl<H> Future for ConnectFuture<H>
+where H: Handshaker<CountedIo<TcpStream>>,
+      H::Io: 'static,
+{
+    type Item = Connection<H::Io, Handle, BoxBody>;
+    type Error = ConnectError<H::Error>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                State::Connecting(ref mut tcp) => {
+                    let tcp = try_ready!(tcp.poll().map_err(ConnectError::Connect));
+                    self.connect.tcp.apply(&tcp).map_err(ConnectError::Connect)?;
+
+                    let io = self.connect.stats.io(tcp);
+                    State::Handshake(self.connect.handshaker.client(&self.endpoint, io))
+                }
+                State::Handshake(ref mut handshake) => {
+                    let (io, _) = try_ready!(handshake.poll().map_err(ConnectError::Handshake));
+                    let builder = self.connect.http2.client_builder();
+                    State::Http2(H2Handshake::new(io, self.connect.handle.clone(), &builder))
+                }
+                State::Http2(ref mut h2) => {
+                    let conn = try_ready!(h2.poll().map_err(ConnectError::Http2));
+                    return Ok(Async::Ready(conn));
+                }
+            };
+
+            self.state = next;
+        }
+    }
+}
+
+impl<H> fmt::Debug for ConnectFuture<H>
+where H: Handshaker<CountedIo<TcpStream>>,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let state = match self.state {
+            State::Connecting(..) => "Connecting",
+            State::Handshake(..) => "Handshake",
+            State::Http2(..) => "Http2",
+        };
+
+        fmt.debug_struct("ConnectFuture")
+            .field("endpoint", &self.endpoint)
+            .field("state", &state)
+            .finish()
+    }
+}
+
+// ===== impl AddAuthInfo =====
+
+impl<S, I> AddAuthInfo<S, I> {
+    pub fn new(inner: S, info: I) -> Self {
+        AddAuthInfo { inner, info }
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner service.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+}
+
+impl<S, I, A> Service for AddAuthInfo<S, I>
+where S: Service<Request = http::Request<A>>,
+      I: Clone + Send + Sync + 'static,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, mut request: Self::Request) -> Self::Future {
+        request.extensions_mut().insert(self.info.clone());
+        self.inner.call(request)
+    }
+}
+
+impl<S, I, A> NewService for AddAuthInfo<S, I>
+where S: NewService<Request = http::Request<A>>,
+      I: Clone + Send + Sync + 'static,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Service = AddAuthInfo<S::Service, I>;
+    type InitError = S::InitError;
+    type Future = NewServiceFuture<S::Future, I>;
+
+    fn new_service(&self) -> Self::Future {
+        NewServiceFuture {
+            inner: self.inner.new_service(),
+            info: self.info.clone(),
+        }
+    }
+}
+
+// ===== impl NewServiceFuture =====
+
+impl<F, I> Future for NewServiceFuture<F, I>
+where F: Future,
+      I: Clone,
+{
+    type Item = AddAuthInfo<F::Item, I>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        Ok(Async::Ready(AddAuthInfo::new(inner, self.info.clone())))
+    }
+}
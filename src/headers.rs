@@ -0,0 +1,39 @@
+//! Header names and values sent on every call.
+//!
+//! Building a `HeaderName` or `HeaderValue` from a string validates it each
+//! time. These are validated once, and cloning them only bumps a reference
+//! to their static bytes.
+
+use {Code, Status};
+
+use http::HeaderMap;
+use http::header::{HeaderName, HeaderValue};
+
+lazy_static! {
+    pub(crate) static ref GRPC_STATUS: HeaderName = HeaderName::from_static("grpc-status");
+    pub(crate) static ref GRPC_TIMEOUT: HeaderName = HeaderName::from_static("grpc-timeout");
+
+    /// The `te` value gRPC requests must carry.
+    pub(crate) static ref TRAILERS: HeaderValue = HeaderValue::from_static("trailers");
+
+    /// The `content-type` of protobuf messages.
+    pub(crate) static ref APPLICATION_GRPC_PROTO: HeaderValue =
+        HeaderValue::from_static("application/grpc+proto");
+}
+
+/// Returns the code carried in the `grpc-status` of `headers`, if any.
+pub(crate) fn status_code(headers: &HeaderMap) -> Option<Code> {
+    headers.get(&*GRPC_STATUS)
+        .map(|s| Status::from_bytes(s.as_ref()).code())
+}
+
+/// Splits a method path, such as `/helloworld.Greeter/SayHello`, into its
+/// service and method names.
+///
+/// Returns `None` if the path has no method part.
+pub(crate) fn split_method_path(path: &str) -> Option<(&str, &str)> {
+    let mut parts = path.trim_start_matches('/').splitn(2, '/');
+    let service = parts.next().unwrap_or("");
+
+    parts.next().map(|method| (service, method))
+}
@@ -0,0 +1,198 @@
+use super::{CHECK_PATH, WATCH_PATH, HealthCheckRequest, HealthCheckResponse, ServingStatus};
+use client::{self, unary, server_streaming, Encodable};
+use clock::{Clock, Sleep};
+use codec::Streaming;
+use {Request, Status};
+
+use futures::{Future, Stream, Poll, Async};
+use http::Uri;
+use http::uri::PathAndQuery;
+use tower_h2::{Body, Data, HttpService};
+
+use std::fmt;
+use std::time::Duration;
+
+/// A client of the `grpc.health.v1.Health` service.
+#[derive(Debug)]
+pub struct HealthClient<T> {
+    inner: client::Grpc<T>,
+}
+
+/// Future returned by `HealthClient::wait_for_serving`.
+///
+/// Completes with the client once the service reports `SERVING`, or fails
+/// with `DEADLINE_EXCEEDED` if it does not do so in time.
+pub struct WaitForServing<T>
+where T: HttpService,
+{
+    client: Option<HealthClient<T>>,
+    service: String,
+    state: Waiting<T>,
+    deadline: Sleep,
+}
+
+enum Waiting<T>
+where T: HttpService,
+{
+    /// Waiting for the client to accept the watch call.
+    Ready,
+
+    /// Waiting for the watch response head.
+    Pending(server_streaming::ResponseFuture<HealthCheckResponse, T::Future>),
+
+    /// Receiving health updates.
+    Streaming(Streaming<HealthCheckResponse, T::ResponseBody>),
+}
+
+// ===== impl HealthClient =====
+
+impl<T> HealthClient<T>
+where T: HttpService,
+{
+    /// Create a client calling the health service at `uri` on `inner`.
+    pub fn new(inner: T, uri: Uri) -> Result<Self, client::BuilderError> {
+        let inner = client::Builder::new()
+            .uri(uri)
+            .build(inner)?;
+
+        Ok(HealthClient { inner })
+    }
+
+    /// Create a client from an existing gRPC client.
+    pub fn from_grpc(inner: client::Grpc<T>) -> Self {
+        HealthClient { inner }
+    }
+
+    /// Get a reference to the inner HTTP/2.0 service.
+    pub fn get_ref(&self) -> &T {
+        self.inner.get_ref()
+    }
+
+    /// Get a mutable reference to the inner HTTP/2.0 service.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+
+    pub fn poll_ready(&mut self) -> Poll<(), ::Error<T::Error>> {
+        self.inner.poll_ready()
+    }
+
+    /// Check the health of `service`.
+    ///
+    /// An empty service name checks the health of the server as a whole.
+    /// Unknown services fail with `NOT_FOUND`.
+    pub fn check(&mut self, service: &str)
+        -> unary::ResponseFuture<HealthCheckResponse, T::Future, T::ResponseBody>
+    where unary::Once<HealthCheckRequest>: Encodable<T::RequestBody>,
+    {
+        let path = PathAndQuery::from_static(CHECK_PATH);
+        self.inner.unary(request(service), path)
+    }
+
+    /// Watch the health of `service`.
+    ///
+    /// The response streams the current status, and then each change to it.
+    pub fn watch(&mut self, service: &str)
+        -> server_streaming::ResponseFuture<HealthCheckResponse, T::Future>
+    where unary::Once<HealthCheckRequest>: Encodable<T::RequestBody>,
+    {
+        let path = PathAndQuery::from_static(WATCH_PATH);
+        self.inner.server_streaming(request(service), path)
+    }
+
+    /// Wait until `service` reports `SERVING`, for at most `timeout`, as
+    /// measured by `timer`.
+    pub fn wait_for_serving<C>(self, service: &str, timeout: Duration, timer: C)
+        -> WaitForServing<T>
+    where C: Into<Clock>,
+    {
+        WaitForServing {
+            client: Some(self),
+            service: service.to_string(),
+            state: Waiting::Ready,
+            deadline: timer.into().sleep(timeout),
+        }
+    }
+}
+
+// ===== impl WaitForServing =====
+
+impl<T> Future for WaitForServing<T>
+where T: HttpService,
+      unary::Once<HealthCheckRequest>: Encodable<T::RequestBody>,
+      T::ResponseBody: Body<Data = Data>,
+{
+    type Item = HealthClient<T>;
+    type Error = ::Error<T::Error>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Ok(Async::Ready(())) = self.deadline.poll() {
+            debug!("timed out waiting for service to serve; service={:?}", self.service);
+            return Err(::Error::Grpc(Status::DEADLINE_EXCEEDED));
+        }
+
+        loop {
+            let next = match self.state {
+                Waiting::Ready => {
+                    let client = self.client.as_mut().expect("polled after complete");
+                    try_ready!(client.poll_ready());
+                    Waiting::Pending(client.watch(&self.service))
+                }
+                Waiting::Pending(ref mut fut) => {
+                    Waiting::Streaming(try_ready!(fut.poll()).into_inner())
+                }
+                Waiting::Streaming(ref mut stream) => {
+                    let response = match stream.poll() {
+                        Ok(Async::Ready(Some(response))) => response,
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Ok(Async::Ready(None)) => {
+                            return Err(::Error::Grpc(Status::UNAVAILABLE));
+                        }
+                        Err(::Error::Grpc(status)) => return Err(::Error::Grpc(status)),
+                        Err(::Error::Inner(())) => {
+                            return Err(::Error::Grpc(Status::UNAVAILABLE));
+                        }
+                    };
+
+                    let status = response.serving_status();
+                    trace!("health update; service={:?}; status={:?}", self.service, status);
+
+                    if status == ServingStatus::Serving {
+                        let client = self.client.take().expect("polled after complete");
+                        return Ok(Async::Ready(client));
+                    }
+
+                    continue;
+                }
+            };
+
+            self.state = next;
+        }
+    }
+}
+
+impl<T> fmt::Debug for WaitForServing<T>
+where T: HttpService + fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let state = match self.state {
+            Waiting::Ready => "Ready",
+            Waiting::Pending(_) => "Pending",
+            Waiting::Streaming(_) => "Streaming",
+        };
+
+        fmt.debug_struct("WaitForServing")
+            .field("client", &self.client)
+            .field("service", &self.service)
+            .field("state", &state)
+            .finish()
+    }
+}
+
+// ===== utility fns =====
+
+fn request(service: &str) -> Request<HealthCheckRequest> {
+    Request::new(HealthCheckRequest {
+        service: service.to_string(),
+    })
+}
@@ -0,0 +1,54 @@
+//! The `grpc.health.v1` health checking protocol.
+//!
+//! `Health` serves the protocol, with statuses set through its
+//! `HealthReporter`, and `HealthClient` calls it. See the
+//! [health checking protocol][spec] for details.
+//!
+//! [spec]: https://github.com/grpc/grpc/blob/master/doc/health-checking.md
+
+mod client;
+mod server;
+
+pub use self::client::{HealthClient, WaitForServing};
+pub use self::server::{Health, HealthReporter, ResponseFuture, ResponseBody};
+
+/// Path of the `Check` method.
+pub const CHECK_PATH: &'static str = "/grpc.health.v1.Health/Check";
+
+/// Path of the `Watch` method.
+pub const WATCH_PATH: &'static str = "/grpc.health.v1.Health/Watch";
+
+#[derive(Clone, PartialEq, Message)]
+pub struct HealthCheckRequest {
+    #[prost(string, tag="1")]
+    pub service: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct HealthCheckResponse {
+    #[prost(enumeration="health_check_response::ServingStatus", tag="1")]
+    pub status: i32,
+}
+
+pub mod health_check_response {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Enumeration)]
+    pub enum ServingStatus {
+        Unknown = 0,
+        Serving = 1,
+        NotServing = 2,
+        /// Used only by the `Watch` method.
+        ServiceUnknown = 3,
+    }
+}
+
+pub use self::health_check_response::ServingStatus;
+
+impl HealthCheckResponse {
+    /// Returns the response's serving status.
+    ///
+    /// Unrecognized values are reported as `Unknown`.
+    pub fn serving_status(&self) -> ServingStatus {
+        ServingStatus::from_i32(self.status)
+            .unwrap_or(ServingStatus::Unknown)
+    }
+}
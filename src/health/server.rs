@@ -0,0 +1,484 @@
+use super::{CHECK_PATH, WATCH_PATH, HealthCheckRequest, HealthCheckResponse, ServingStatus};
+use codec::Encode;
+use server::{unary, server_streaming, Grpc};
+use {Request, Response, Status};
+
+use bytes::Bytes;
+use futures::{future, Async, Future, Poll, Stream};
+use futures::sync::mpsc;
+use h2;
+use http;
+use tower::{NewService, ReadyService, Service};
+use tower_h2::{Body, RecvBody};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Serves the `grpc.health.v1.Health` service.
+///
+/// Statuses are reported through the service's `HealthReporter`. The
+/// overall health of the server, reported for the empty service name, starts
+/// out as `SERVING`; every other service is unknown until it is reported.
+#[derive(Debug, Clone)]
+pub struct Health {
+    reporter: HealthReporter,
+}
+
+/// Sets the serving status reported by a `Health` service.
+#[derive(Debug, Clone)]
+pub struct HealthReporter {
+    shared: Arc<Mutex<Shared>>,
+}
+
+/// The response future returned by `Health`.
+pub struct ResponseFuture {
+    kind: Result<Kind, Status>,
+}
+
+/// The response body returned by `Health`.
+pub struct ResponseBody {
+    kind: Result<BodyKind, Status>,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    statuses: HashMap<String, ServingStatus>,
+    watchers: HashMap<String, Vec<mpsc::UnboundedSender<ServingStatus>>>,
+}
+
+enum Kind {
+    Check(unary::ResponseFuture<Check, RecvBody>),
+    Watch(server_streaming::ResponseFuture<Watch, RecvBody>),
+}
+
+enum BodyKind {
+    Check(Encode<unary::Once<HealthCheckResponse>>),
+    Watch(Encode<WatchStream>),
+}
+
+/// Handles `Check` calls.
+#[derive(Debug, Clone)]
+struct Check(HealthReporter);
+
+/// Handles `Watch` calls.
+#[derive(Debug, Clone)]
+struct Watch(HealthReporter);
+
+/// Streams the status of a watched service, skipping repeated statuses.
+#[derive(Debug)]
+struct WatchStream {
+    rx: mpsc::UnboundedReceiver<ServingStatus>,
+    last: Option<ServingStatus>,
+}
+
+// ===== impl Health =====
+
+impl Health {
+    /// Create a health service reporting the server as serving.
+    pub fn new() -> Self {
+        let reporter = HealthReporter {
+            shared: Arc::new(Mutex::new(Shared::default())),
+        };
+        reporter.set_serving("");
+
+        Health { reporter }
+    }
+
+    /// Returns a handle used to set the statuses this service reports.
+    pub fn reporter(&self) -> HealthReporter {
+        self.reporter.clone()
+    }
+}
+
+impl Service for Health {
+    type Request = http::Request<RecvBody>;
+    type Response = http::Response<ResponseBody>;
+    type Error = h2::Error;
+    type Future = ResponseFuture;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        Ok(().into())
+    }
+
+    fn call(&mut self, request: Self::Request) -> Self::Future {
+        let kind = match request.uri().path() {
+            CHECK_PATH => {
+                let service = Check(self.reporter.clone());
+                Ok(Kind::Check(Grpc::unary(service, request)))
+            }
+            WATCH_PATH => {
+                let service = Watch(self.reporter.clone());
+                Ok(Kind::Watch(Grpc::server_streaming(service, request)))
+            }
+            _ => Err(Status::UNIMPLEMENTED),
+        };
+
+        ResponseFuture { kind }
+    }
+}
+
+impl NewService for Health {
+    type Request = http::Request<RecvBody>;
+    type Response = http::Response<ResponseBody>;
+    type Error = h2::Error;
+    type Service = Self;
+    type InitError = h2::Error;
+    type Future = future::FutureResult<Self::Service, Self::InitError>;
+
+    fn new_service(&self) -> Self::Future {
+        future::ok(self.clone())
+    }
+}
+
+// ===== impl HealthReporter =====
+
+impl HealthReporter {
+    /// Report `service` as serving.
+    ///
+    /// The empty service name sets the overall health of the server.
+    pub fn set_serving(&self, service: &str) {
+        self.set_status(service, ServingStatus::Serving);
+    }
+
+    /// Report `service` as not serving.
+    pub fn set_not_serving(&self, service: &str) {
+        self.set_status(service, ServingStatus::NotServing);
+    }
+
+    /// Set the status reported for `service`, notifying its watchers.
+    pub fn set_status(&self, service: &str, status: ServingStatus) {
+        let mut shared = self.lock();
+        shared.statuses.insert(service.to_string(), status);
+        shared.notify(service, status);
+    }
+
+    /// Stop reporting a status for `service`.
+    ///
+    /// Checks of the service fail with `NOT_FOUND`, and watchers are told the
+    /// service is unknown.
+    pub fn clear(&self, service: &str) {
+        let mut shared = self.lock();
+        shared.statuses.remove(service);
+        shared.notify(service, ServingStatus::ServiceUnknown);
+    }
+
+    /// Report every service, including the server as a whole, as not
+    /// serving.
+    ///
+    /// This is typically called when the server starts shutting down, so
+    /// that load balancers stop sending it new calls.
+    pub fn shutdown(&self) {
+        let mut shared = self.lock();
+
+        let services: Vec<String> = shared.statuses.keys().cloned().collect();
+
+        for service in services {
+            shared.statuses.insert(service.clone(), ServingStatus::NotServing);
+            shared.notify(&service, ServingStatus::NotServing);
+        }
+    }
+
+    /// Returns the status reported for `service`, if any.
+    pub fn status(&self, service: &str) -> Option<ServingStatus> {
+        self.lock().statuses.get(service).cloned()
+    }
+
+    /// Returns a stream of the statuses of `service`, starting with the
+    /// current one.
+    fn watch(&self, service: &str) -> WatchStream {
+        let (tx, rx) = mpsc::unbounded();
+        let mut shared = self.lock();
+
+        let current = shared.statuses.get(service)
+            .cloned()
+            .unwrap_or(ServingStatus::ServiceUnknown);
+
+        // The receiver is still held, so this can't fail.
+        let _ = tx.unbounded_send(current);
+
+        shared.watchers.entry(service.to_string())
+            .or_insert_with(Vec::new)
+            .push(tx);
+
+        WatchStream { rx, last: None }
+    }
+
+    fn lock(&self) -> MutexGuard<Shared> {
+        match self.shared.lock() {
+            Ok(shared) => shared,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+// ===== impl Shared =====
+
+impl Shared {
+    /// Send `status` to the watchers of `service`, dropping those that have
+    /// gone away.
+    fn notify(&mut self, service: &str, status: ServingStatus) {
+        let empty = match self.watchers.get_mut(service) {
+            Some(watchers) => {
+                watchers.retain(|tx| tx.unbounded_send(status).is_ok());
+                watchers.is_empty()
+            }
+            None => false,
+        };
+
+        if empty {
+            self.watchers.remove(service);
+        }
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl Future for ResponseFuture {
+    type Item = http::Response<ResponseBody>;
+    type Error = h2::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let (head, kind) = match self.kind {
+            Ok(Kind::Check(ref mut fut)) => {
+                let (head, body) = try_ready!(fut.poll()).into_parts();
+                (head, BodyKind::Check(body))
+            }
+            Ok(Kind::Watch(ref mut fut)) => {
+                let (head, body) = try_ready!(fut.poll()).into_parts();
+                (head, BodyKind::Watch(body))
+            }
+            Err(ref status) => {
+                let body = ResponseBody { kind: Err(status.clone()) };
+                return Ok(Response::trailers_only(status, body).into_http().into());
+            }
+        };
+
+        let body = ResponseBody { kind: Ok(kind) };
+        Ok(http::Response::from_parts(head, body).into())
+    }
+}
+
+impl fmt::Debug for ResponseFuture {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            Ok(Kind::Check(_)) => "Check",
+            Ok(Kind::Watch(_)) => "Watch",
+            Err(_) => "Error",
+        };
+
+        fmt.debug_struct("health::ResponseFuture")
+            .field("kind", &kind)
+            .finish()
+    }
+}
+
+// ===== impl ResponseBody =====
+
+impl Body for ResponseBody {
+    type Data = Bytes;
+
+    fn is_end_stream(&self) -> bool {
+        match self.kind {
+            Ok(BodyKind::Check(ref body)) => body.is_end_stream(),
+            Ok(BodyKind::Watch(ref body)) => body.is_end_stream(),
+            Err(_) => true,
+        }
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Bytes>, h2::Error> {
+        match self.kind {
+            Ok(BodyKind::Check(ref mut body)) => body.poll_data(),
+            Ok(BodyKind::Watch(ref mut body)) => body.poll_data(),
+            Err(_) => Ok(None.into()),
+        }
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, h2::Error> {
+        match self.kind {
+            Ok(BodyKind::Check(ref mut body)) => body.poll_trailers(),
+            Ok(BodyKind::Watch(ref mut body)) => body.poll_trailers(),
+            // The status was sent in the headers.
+            Err(_) => Ok(None.into()),
+        }
+    }
+}
+
+impl fmt::Debug for ResponseBody {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            Ok(BodyKind::Check(_)) => "Check",
+            Ok(BodyKind::Watch(_)) => "Watch",
+            Err(_) => "Error",
+        };
+
+        fmt.debug_struct("health::ResponseBody")
+            .field("kind", &kind)
+            .finish()
+    }
+}
+
+// ===== impl Check =====
+
+impl ReadyService for Check {
+    type Request = Request<HealthCheckRequest>;
+    type Response = Response<HealthCheckResponse>;
+    type Error = ::Error;
+    type Future = future::FutureResult<Self::Response, Self::Error>;
+
+    fn call(&mut self, request: Self::Request) -> Self::Future {
+        let service = &request.get_ref().service;
+
+        match self.0.status(service) {
+            Some(status) => {
+                let response = HealthCheckResponse { status: status as i32 };
+                future::ok(Response::new(response))
+            }
+            None => {
+                trace!("health check of unknown service; service={:?}", service);
+                future::err(::Error::Grpc(Status::NOT_FOUND))
+            }
+        }
+    }
+}
+
+// ===== impl Watch =====
+
+impl ReadyService for Watch {
+    type Request = Request<HealthCheckRequest>;
+    type Response = Response<WatchStream>;
+    type Error = ::Error;
+    type Future = future::FutureResult<Self::Response, Self::Error>;
+
+    fn call(&mut self, request: Self::Request) -> Self::Future {
+        let stream = self.0.watch(&request.get_ref().service);
+        future::ok(Response::new(stream))
+    }
+}
+
+// ===== impl WatchStream =====
+
+impl Stream for WatchStream {
+    type Item = HealthCheckResponse;
+    type Error = ::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            let status = match self.rx.poll() {
+                Ok(Async::Ready(Some(status))) => status,
+                Ok(Async::Ready(None)) | Err(()) => return Ok(Async::Ready(None)),
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+            };
+
+            if self.last == Some(status) {
+                continue;
+            }
+
+            self.last = Some(status);
+            return Ok(Async::Ready(Some(HealthCheckResponse { status: status as i32 })));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(reporter: &HealthReporter, service: &str) -> Result<ServingStatus, Status> {
+        let request = Request::new(HealthCheckRequest { service: service.to_string() });
+
+        match Check(reporter.clone()).call(request).wait() {
+            Ok(response) => Ok(response.get_ref().serving_status()),
+            Err(::Error::Grpc(status)) => Err(status),
+            Err(e) => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    /// Returns the statuses the stream has ready.
+    fn statuses(stream: &mut WatchStream) -> Vec<ServingStatus> {
+        future::lazy(|| {
+            let mut statuses = vec![];
+
+            while let Async::Ready(Some(response)) = stream.poll().unwrap() {
+                statuses.push(response.serving_status());
+            }
+
+            Ok::<_, ()>(statuses)
+        }).wait().unwrap()
+    }
+
+    #[test]
+    fn server_starts_out_serving() {
+        let health = Health::new();
+
+        assert_eq!(health.reporter().status(""), Some(ServingStatus::Serving));
+        assert_eq!(check(&health.reporter(), "").unwrap(), ServingStatus::Serving);
+    }
+
+    #[test]
+    fn check_of_unknown_service_is_not_found() {
+        let reporter = Health::new().reporter();
+
+        assert_eq!(check(&reporter, "foo").unwrap_err().code(), ::Code::NOT_FOUND);
+
+        reporter.set_not_serving("foo");
+        assert_eq!(check(&reporter, "foo").unwrap(), ServingStatus::NotServing);
+
+        reporter.clear("foo");
+        assert_eq!(check(&reporter, "foo").unwrap_err().code(), ::Code::NOT_FOUND);
+    }
+
+    #[test]
+    fn watch_streams_status_changes() {
+        let reporter = Health::new().reporter();
+        let mut stream = reporter.watch("foo");
+
+        assert_eq!(statuses(&mut stream), vec![ServingStatus::ServiceUnknown]);
+
+        // Repeated statuses are skipped.
+        reporter.set_serving("foo");
+        reporter.set_serving("foo");
+        reporter.set_not_serving("foo");
+        assert_eq!(statuses(&mut stream), vec![ServingStatus::Serving, ServingStatus::NotServing]);
+
+        reporter.clear("foo");
+        assert_eq!(statuses(&mut stream), vec![ServingStatus::ServiceUnknown]);
+
+        // Other services are not streamed.
+        reporter.set_not_serving("");
+        assert_eq!(statuses(&mut stream), vec![]);
+    }
+
+    #[test]
+    fn watch_starts_with_current_status() {
+        let reporter = Health::new().reporter();
+        let mut stream = reporter.watch("");
+
+        assert_eq!(statuses(&mut stream), vec![ServingStatus::Serving]);
+    }
+
+    #[test]
+    fn shutdown_reports_every_service_not_serving() {
+        let reporter = Health::new().reporter();
+        reporter.set_serving("foo");
+
+        let mut stream = reporter.watch("foo");
+        assert_eq!(statuses(&mut stream), vec![ServingStatus::Serving]);
+
+        reporter.shutdown();
+
+        assert_eq!(reporter.status(""), Some(ServingStatus::NotServing));
+        assert_eq!(reporter.status("foo"), Some(ServingStatus::NotServing));
+        assert_eq!(statuses(&mut stream), vec![ServingStatus::NotServing]);
+    }
+
+    #[test]
+    fn dropped_watchers_are_removed() {
+        let reporter = Health::new().reporter();
+        drop(reporter.watch("foo"));
+
+        reporter.set_serving("foo");
+
+        assert!(reporter.lock().watchers.is_empty());
+    }
+}
@@ -0,0 +1,134 @@
+//! HTTP/2.0 connection settings.
+//!
+//! `Http2Settings` holds the settings a client or server advertises to its
+//! peer. Settings that are not set keep h2's defaults. Connectors take them
+//! with their `http2_settings` builders, and servers with the builder given
+//! to `tower_h2::Server`:
+//!
+//! ```ignore
+//! let settings = Http2Settings::new()
+//!     .initial_stream_window_size(1 << 20)
+//!     .initial_connection_window_size(1 << 24);
+//!
+//! let connect = TcpConnect::new(handle.clone()).http2_settings(settings.clone());
+//! let h2 = Server::new(new_service, settings.server_builder(), handle.clone());
+//! ```
+//!
+//! HTTP/2.0 PING keepalive is not available. h2 can send PINGs from a client
+//! with `client::Connection::ping_pong` and `PingPong::send_ping`, but
+//! tower-h2 owns each `h2` connection and does not expose it, so clients
+//! built on tower-h2 cannot probe idle connections and servers cannot
+//! enforce a minimum PING interval. Idle connections through NATs and load
+//! balancers can be kept alive with TCP keepalive instead, set with
+//! `tcp::TcpOptions::keepalive`, and idle server streams with
+//! `keepalive::StreamKeepalive`.
+
+use h2;
+
+/// HTTP/2.0 settings for clients and servers.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Http2Settings {
+    initial_stream_window_size: Option<u32>,
+    initial_connection_window_size: Option<u32>,
+    max_frame_size: Option<u32>,
+    max_concurrent_streams: Option<u32>,
+    max_header_list_size: Option<u32>,
+    header_table_size: Option<u32>,
+}
+
+// ===== impl Http2Settings =====
+
+impl Http2Settings {
+    pub fn new() -> Self {
+        Http2Settings::default()
+    }
+
+    /// Set the initial flow control window of each stream, in bytes.
+    pub fn initial_stream_window_size(mut self, size: u32) -> Self {
+        self.initial_stream_window_size = Some(size);
+        self
+    }
+
+    /// Set the initial flow control window of the connection, in bytes.
+    pub fn initial_connection_window_size(mut self, size: u32) -> Self {
+        self.initial_connection_window_size = Some(size);
+        self
+    }
+
+    /// Set the largest frame payload that may be received, in bytes.
+    pub fn max_frame_size(mut self, size: u32) -> Self {
+        self.max_frame_size = Some(size);
+        self
+    }
+
+    /// Set the number of streams the peer may open concurrently.
+    pub fn max_concurrent_streams(mut self, max: u32) -> Self {
+        self.max_concurrent_streams = Some(max);
+        self
+    }
+
+    /// Set the largest header list that may be received, in bytes.
+    pub fn max_header_list_size(mut self, size: u32) -> Self {
+        self.max_header_list_size = Some(size);
+        self
+    }
+
+    /// Set the size of the HPACK table used to decode received headers, in
+    /// bytes.
+    pub fn header_table_size(mut self, size: u32) -> Self {
+        self.header_table_size = Some(size);
+        self
+    }
+
+    /// Returns an h2 client builder with these settings.
+    pub fn client_builder(&self) -> h2::client::Builder {
+        let mut builder = h2::client::Builder::default();
+
+        if let Some(size) = self.initial_stream_window_size {
+            builder.initial_window_size(size);
+        }
+        if let Some(size) = self.initial_connection_window_size {
+            builder.initial_connection_window_size(size);
+        }
+        if let Some(size) = self.max_frame_size {
+            builder.max_frame_size(size);
+        }
+        if let Some(max) = self.max_concurrent_streams {
+            builder.max_concurrent_streams(max);
+        }
+        if let Some(size) = self.max_header_list_size {
+            builder.max_header_list_size(size);
+        }
+        if let Some(size) = self.header_table_size {
+            builder.header_table_size(size);
+        }
+
+        builder
+    }
+
+    /// Returns an h2 server builder with these settings.
+    pub fn server_builder(&self) -> h2::server::Builder {
+        let mut builder = h2::server::Builder::default();
+
+        if let Some(size) = self.initial_stream_window_size {
+            builder.initial_window_size(size);
+        }
+        if let Some(size) = self.initial_connection_window_size {
+            builder.initial_connection_window_size(size);
+        }
+        if let Some(size) = self.max_frame_size {
+            builder.max_frame_size(size);
+        }
+        if let Some(max) = self.max_concurrent_streams {
+            builder.max_concurrent_streams(max);
+        }
+        if let Some(size) = self.max_header_list_size {
+            builder.max_header_list_size(size);
+        }
+        if let Some(size) = self.header_table_size {
+            builder.header_table_size(size);
+        }
+
+        builder
+    }
+}
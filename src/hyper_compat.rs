@@ -0,0 +1,278 @@
+//! Adapters between tower-grpc and hyper.
+//!
+//! For applications already built on hyper, gRPC calls may run over hyper's
+//! HTTP/2.0 client and server instead of tower-h2.
+//!
+//! `HyperClient` wraps a `hyper::Client` as an HTTP/2.0 service, which may
+//! be used with `client::Grpc` and generated clients:
+//!
+//! ```ignore
+//! let client = HyperClient::http2(HttpConnector::new(1));
+//! let mut greeter = Greeter::new(client, uri)?;
+//! ```
+//!
+//! `HyperServer` wraps a service as a `hyper::service::Service`, to be
+//! served on an HTTP/2.0 connection:
+//!
+//! ```ignore
+//! let serve = Http::new()
+//!     .http2_only(true)
+//!     .serve_connection(sock, HyperServer::new(service));
+//! ```
+//!
+//! Requests served by hyper have `HyperBody` bodies, which `server::Grpc`
+//! decodes like any other body. Generated servers read `tower_h2::RecvBody`
+//! request bodies directly, so they cannot be served by hyper; their
+//! handlers may be dispatched by a hand-written service instead.
+
+use bytes::{Bytes, IntoBuf};
+use futures::{Future, Poll, Async};
+use h2;
+use http::{self, HeaderMap};
+use hyper;
+use hyper::body::Payload;
+use hyper::client::connect::Connect;
+use tower::Service;
+use tower_h2::Body;
+
+use std::{error, fmt};
+
+/// An HTTP/2.0 service that sends requests with a `hyper::Client`.
+pub struct HyperClient<C, B> {
+    client: hyper::Client<C, HyperPayload<B>>,
+}
+
+/// Future returned by `HyperClient`.
+pub struct ResponseFuture {
+    inner: hyper::client::ResponseFuture,
+}
+
+/// Serves a service to hyper.
+#[derive(Debug, Clone)]
+pub struct HyperServer<S> {
+    inner: S,
+}
+
+/// Future returned by `HyperServer`.
+#[derive(Debug)]
+pub struct ServeFuture<F> {
+    inner: F,
+}
+
+/// A hyper body, read as a tower-h2 body.
+#[derive(Debug)]
+pub struct HyperBody {
+    inner: hyper::Body,
+}
+
+/// A tower-h2 body, sent as a hyper payload.
+#[derive(Debug)]
+pub struct HyperPayload<B> {
+    inner: B,
+}
+
+// ===== impl HyperClient =====
+
+impl<C, B> HyperClient<C, B>
+where C: Connect + Sync + 'static,
+      B: Body + Send + 'static,
+      <B::Data as IntoBuf>::Buf: Send,
+{
+    /// Send requests with `client`.
+    pub fn new(client: hyper::Client<C, HyperPayload<B>>) -> Self {
+        HyperClient { client }
+    }
+
+    /// Send requests over HTTP/2.0 connections opened by `connector`.
+    pub fn http2(connector: C) -> Self {
+        let client = hyper::Client::builder()
+            .http2_only(true)
+            .build(connector);
+
+        HyperClient::new(client)
+    }
+
+    /// Returns a reference to the hyper client.
+    pub fn get_ref(&self) -> &hyper::Client<C, HyperPayload<B>> {
+        &self.client
+    }
+}
+
+impl<C, B> Service for HyperClient<C, B>
+where C: Connect + Sync + 'static,
+      B: Body + Send + 'static,
+      <B::Data as IntoBuf>::Buf: Send,
+{
+    type Request = http::Request<B>;
+    type Response = http::Response<HyperBody>;
+    type Error = hyper::Error;
+    type Future = ResponseFuture;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        // The client opens and pools connections as requests are made.
+        Ok(Async::Ready(()))
+    }
+
+    fn call(&mut self, request: Self::Request) -> Self::Future {
+        let request = request.map(|inner| HyperPayload { inner });
+
+        ResponseFuture {
+            inner: self.client.request(request),
+        }
+    }
+}
+
+impl<C, B> Clone for HyperClient<C, B> {
+    fn clone(&self) -> Self {
+        HyperClient {
+            client: self.client.clone(),
+        }
+    }
+}
+
+impl<C, B> fmt::Debug for HyperClient<C, B> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("HyperClient")
+            .finish()
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl Future for ResponseFuture {
+    type Item = http::Response<HyperBody>;
+    type Error = hyper::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let response = try_ready!(self.inner.poll());
+        Ok(Async::Ready(response.map(|inner| HyperBody { inner })))
+    }
+}
+
+impl fmt::Debug for ResponseFuture {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("ResponseFuture")
+            .finish()
+    }
+}
+
+// ===== impl HyperServer =====
+
+impl<S> HyperServer<S> {
+    pub fn new(inner: S) -> Self {
+        HyperServer { inner }
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner service.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+}
+
+impl<S, B> hyper::service::Service for HyperServer<S>
+where S: Service<Request = http::Request<HyperBody>, Response = http::Response<B>>,
+      S::Error: Into<Box<error::Error + Send + Sync>>,
+      S::Future: Send + 'static,
+      B: Body + Send + 'static,
+      <B::Data as IntoBuf>::Buf: Send,
+{
+    type ReqBody = hyper::Body;
+    type ResBody = HyperPayload<B>;
+    type Error = S::Error;
+    type Future = ServeFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, request: http::Request<hyper::Body>) -> Self::Future {
+        let request = request.map(|inner| HyperBody { inner });
+
+        ServeFuture {
+            inner: self.inner.call(request),
+        }
+    }
+}
+
+// ===== impl ServeFuture =====
+
+impl<F, B> Future for ServeFuture<F>
+where F: Future<Item = http::Response<B>>,
+{
+    type Item = http::Response<HyperPayload<B>>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let response = try_ready!(self.inner.poll());
+        Ok(Async::Ready(response.map(|inner| HyperPayload { inner })))
+    }
+}
+
+// ===== impl HyperBody =====
+
+impl HyperBody {
+    /// Returns the hyper body.
+    pub fn into_inner(self) -> hyper::Body {
+        self.inner
+    }
+}
+
+impl Body for HyperBody {
+    type Data = Bytes;
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, h2::Error> {
+        let chunk = try_ready!(self.inner.poll_data().map_err(hyper_error));
+        Ok(Async::Ready(chunk.map(Bytes::from)))
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, h2::Error> {
+        self.inner.poll_trailers().map_err(hyper_error)
+    }
+}
+
+// ===== impl HyperPayload =====
+
+impl<B> HyperPayload<B> {
+    /// Returns the tower-h2 body.
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+}
+
+impl<B> Payload for HyperPayload<B>
+where B: Body + Send + 'static,
+      <B::Data as IntoBuf>::Buf: Send,
+{
+    type Data = <B::Data as IntoBuf>::Buf;
+    type Error = h2::Error;
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, Self::Error> {
+        let data = try_ready!(self.inner.poll_data());
+        Ok(Async::Ready(data.map(IntoBuf::into_buf)))
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, Self::Error> {
+        self.inner.poll_trailers()
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+}
+
+// ===== utility fns =====
+
+/// hyper errors are reported to tower-h2 as an internal error on the stream.
+fn hyper_error(error: hyper::Error) -> h2::Error {
+    debug!("hyper body error; error={}", error);
+    h2::Reason::INTERNAL_ERROR.into()
+}
@@ -0,0 +1,289 @@
+//! In-process transport.
+//!
+//! `InProcessConnect` connects a `Channel` to a server running in the same
+//! process, such as another module of a modular monolith or a plugin. Each
+//! connection is a `duplex` pipe served by the server on the same reactor,
+//! so calls are framed and encoded exactly as they would be over a socket,
+//! without binding a port:
+//!
+//! ```ignore
+//! let new_service = server::GreeterServer::new(Greeter);
+//! let connect = InProcessConnect::new(new_service, handle.clone());
+//! let channel = Channel::new(resolver, connect, PickFirst::new());
+//! ```
+//!
+//! The channel's resolver only needs to provide a single endpoint, whose
+//! address is ignored.
+//!
+//! In tests, `connect` opens a single connection to a server, ready to be
+//! used by a generated client, so clients and servers can be tested
+//! together on one reactor without binding ports:
+//!
+//! ```ignore
+//! let mut core = Core::new().unwrap();
+//! let handle = core.handle();
+//!
+//! let conn = core.run(inprocess::connect(GreeterServer::new(Greeter), &handle)).unwrap();
+//! let mut client = client::Greeter::new(conn, inprocess::uri()).unwrap();
+//!
+//! let response = core.run(client.say_hello(Request::new(HelloRequest::default()))).unwrap();
+//! ```
+//!
+//! `duplex::duplex` provides the underlying pipe, for tests that drive the
+//! connection's ends directly.
+//!
+//! `Harness` bundles a reactor with a `MockClock`, so deadlines, keepalives
+//! and backoffs can be tested without real sleeps. Give the harness's clock
+//! to every component that takes a timer, start calls with `spawn`, and
+//! move time forward with `advance`:
+//!
+//! ```ignore
+//! let mut harness = Harness::new().unwrap();
+//!
+//! let service = LatencyInjection::new(GreeterServer::new(Greeter))
+//!     .latency(Latency::fixed(Duration::from_secs(5)))
+//!     .timer(harness.clock());
+//! let conn = harness.connect(service).unwrap();
+//! let mut client = client::Greeter::new(conn, inprocess::uri()).unwrap();
+//!
+//! let mut request = Request::new(HelloRequest::default());
+//! request.headers_mut().insert("grpc-timeout", HeaderValue::from_static("1S"));
+//!
+//! let call = harness.spawn(client.say_hello(request));
+//! harness.advance(Duration::from_secs(1));
+//!
+//! // The server answers with DEADLINE_EXCEEDED once the second has passed.
+//! let error = harness.run(call).unwrap_err();
+//! ```
+
+use channel::{self, ChannelStats, CountedIo, Endpoint};
+use clock::MockClock;
+use duplex::{duplex, DuplexStream};
+
+use futures::{Future, Poll, Async};
+use futures::sync::oneshot;
+use http::{self, Uri};
+use tokio_core::reactor::{Core, Handle};
+use tower::{NewService, Service};
+use tower_h2::{Body, BoxBody, RecvBody, Server};
+use tower_h2::client::{Connection, Handshake, HandshakeError};
+
+use std::{fmt, io};
+use std::time::Duration;
+
+/// The number of reactor turns `Harness::advance` takes to run the work
+/// that is ready, such as the hops of a call between client and server.
+const SETTLE_TURNS: usize = 32;
+
+/// Connects a `Channel` to a server in the same process.
+pub struct InProcessConnect<S, B>
+where S: NewService<Request = http::Request<RecvBody>, Response = http::Response<B>>,
+      B: Body,
+{
+    server: Server<S, Handle, B>,
+    handle: Handle,
+    stats: ChannelStats,
+}
+
+/// Runs in-process clients and servers on one reactor, with a `MockClock`.
+pub struct Harness {
+    core: Core,
+    clock: MockClock,
+}
+
+/// A future spawned on a `Harness`.
+pub struct Spawned<T, E> {
+    rx: oneshot::Receiver<Result<T, E>>,
+}
+
+// ===== impl InProcessConnect =====
+
+impl<S, B> InProcessConnect<S, B>
+where S: NewService<Request = http::Request<RecvBody>, Response = http::Response<B>>,
+      B: Body,
+{
+    /// Serve each connection with a service from `new_service`.
+    ///
+    /// Both ends of each connection run on the reactor of `handle`.
+    pub fn new(new_service: S, handle: Handle) -> Self {
+        InProcessConnect {
+            server: Server::new(new_service, Default::default(), handle.clone()),
+            handle,
+            stats: ChannelStats::new(),
+        }
+    }
+
+    /// Count the bytes of each connection in `stats`.
+    ///
+    /// Pass the same `ChannelStats` to `Channel::with_stats`.
+    pub fn with_stats(mut self, stats: ChannelStats) -> Self {
+        self.stats = stats;
+        self
+    }
+}
+
+impl<S, B> channel::Connect for InProcessConnect<S, B>
+where S: NewService<Request = http::Request<RecvBody>, Response = http::Response<B>> + 'static,
+      S::Service: 'static,
+      S::Future: 'static,
+      S::InitError: fmt::Debug,
+      <S::Service as Service>::Future: 'static,
+      B: Body + 'static,
+{
+    type Service = Connection<CountedIo<DuplexStream>, Handle, BoxBody>;
+    type Error = HandshakeError;
+    type Future = Handshake<CountedIo<DuplexStream>, Handle, BoxBody>;
+
+    fn connect(&mut self, _: &Endpoint) -> Self::Future {
+        let (client, server) = duplex();
+
+        let serve = self.server.serve(server)
+            .map_err(|e| debug!("in-process connection failed; error={:?}", e));
+        self.handle.spawn(serve);
+
+        Connection::handshake(self.stats.io(client), self.handle.clone())
+    }
+}
+
+impl<S, B> fmt::Debug for InProcessConnect<S, B>
+where S: NewService<Request = http::Request<RecvBody>, Response = http::Response<B>>,
+      B: Body,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("InProcessConnect")
+            .field("stats", &self.stats)
+            .finish()
+    }
+}
+
+// ===== impl Harness =====
+
+impl Harness {
+    /// Create a reactor and a clock starting at the current time.
+    pub fn new() -> io::Result<Self> {
+        Ok(Harness {
+            core: Core::new()?,
+            clock: MockClock::new(),
+        })
+    }
+
+    /// Returns a handle to the reactor.
+    pub fn handle(&self) -> Handle {
+        self.core.handle()
+    }
+
+    /// Returns the clock, to be given to components that take a timer.
+    pub fn clock(&self) -> &MockClock {
+        &self.clock
+    }
+
+    /// Connect to a server for `new_service`, running on the reactor.
+    pub fn connect<S, B>(&mut self, new_service: S)
+        -> Result<Connection<DuplexStream, Handle, BoxBody>, HandshakeError>
+    where S: NewService<Request = http::Request<RecvBody>, Response = http::Response<B>> + 'static,
+          S::Service: 'static,
+          S::Future: 'static,
+          S::InitError: fmt::Debug,
+          <S::Service as Service>::Future: 'static,
+          B: Body + 'static,
+    {
+        let handshake = connect(new_service, &self.core.handle());
+        self.core.run(handshake)
+    }
+
+    /// Run `future` in the background, and run the reactor until it has
+    /// had the chance to start, such as to set a deadline.
+    pub fn spawn<F>(&mut self, future: F) -> Spawned<F::Item, F::Error>
+    where F: Future + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+
+        self.core.handle().spawn(future.then(|result| {
+            let _ = tx.send(result);
+            Ok(())
+        }));
+        self.settle();
+
+        Spawned { rx }
+    }
+
+    /// Run the reactor until `future` completes.
+    ///
+    /// The clock does not move on its own, so `future` must not wait for a
+    /// sleep that has not been advanced past.
+    pub fn run<F: Future>(&mut self, future: F) -> Result<F::Item, F::Error> {
+        self.core.run(future)
+    }
+
+    /// Move the clock forward by `duration`, and run the reactor until the
+    /// work woken by it has run.
+    pub fn advance(&mut self, duration: Duration) {
+        self.settle();
+        self.clock.advance(duration);
+        self.settle();
+    }
+
+    fn settle(&mut self) {
+        for _ in 0..SETTLE_TURNS {
+            self.core.turn(Some(Duration::from_millis(0)));
+        }
+    }
+}
+
+impl fmt::Debug for Harness {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Harness")
+            .field("clock", &self.clock)
+            .finish()
+    }
+}
+
+// ===== impl Spawned =====
+
+impl<T, E> Future for Spawned<T, E> {
+    type Item = T;
+    type Error = E;
+
+    fn poll(&mut self) -> Poll<T, E> {
+        match self.rx.poll() {
+            Ok(Async::Ready(result)) => result.map(Async::Ready),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(_) => panic!("spawned future was dropped by the reactor"),
+        }
+    }
+}
+
+impl<T, E> fmt::Debug for Spawned<T, E> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("inprocess::Spawned").finish()
+    }
+}
+
+// ===== utility fns =====
+
+/// Connect to a server for `new_service`, running on the reactor of
+/// `handle`.
+///
+/// The connection is served until the returned connection is dropped.
+pub fn connect<S, B>(new_service: S, handle: &Handle) -> Handshake<DuplexStream, Handle, BoxBody>
+where S: NewService<Request = http::Request<RecvBody>, Response = http::Response<B>> + 'static,
+      S::Service: 'static,
+      S::Future: 'static,
+      S::InitError: fmt::Debug,
+      <S::Service as Service>::Future: 'static,
+      B: Body + 'static,
+{
+    let (client, server_io) = duplex();
+
+    let server = Server::new(new_service, Default::default(), handle.clone());
+    let serve = server.serve(server_io)
+        .map_err(|e| debug!("in-process connection failed; error={:?}", e));
+    handle.spawn(serve);
+
+    Connection::handshake(client, handle.clone())
+}
+
+/// The origin of in-process connections, for clients that require one.
+pub fn uri() -> Uri {
+    "http://in-process".parse().expect("in-process URI is valid")
+}
@@ -0,0 +1,202 @@
+//! Keepalive of idle response streams.
+//!
+//! Proxies and load balancers often close HTTP/2.0 streams that carry no
+//! data for a while, which breaks server streams that legitimately go
+//! quiet, such as watch APIs. `StreamKeepalive` sends an empty DATA frame
+//! on each response stream that has been idle for the configured interval:
+//!
+//! ```ignore
+//! let new_service = StreamKeepalive::new(new_service, Duration::from_secs(30));
+//! let h2 = Server::new(new_service, Default::default(), handle.clone());
+//! ```
+//!
+//! Empty DATA frames do not consume flow control windows, and carry no gRPC
+//! message, so clients do not see them. HTTP/2.0 PINGs would be preferable,
+//! but they cannot be sent with h2 (see the `http2` module).
+
+use clock::{Clock, Sleep};
+
+use futures::{Future, Poll, Async};
+use http;
+use tower::{NewService, Service};
+
+use std::fmt;
+use std::time::Duration;
+
+/// Keeps the idle response streams of the inner service alive.
+///
+/// `StreamKeepalive` may wrap either a `Service` or the `NewService` given
+/// to `tower_h2::Server`.
+#[derive(Debug, Clone)]
+pub struct StreamKeepalive<S> {
+    inner: S,
+    keepalive: Keepalive,
+}
+
+/// Creates `StreamKeepalive` services.
+#[derive(Debug)]
+pub struct NewServiceFuture<F> {
+    inner: F,
+    keepalive: Keepalive,
+}
+
+/// Request extension holding the interval at which the server's codec
+/// keeps idle response streams alive.
+#[derive(Clone)]
+pub(crate) struct Keepalive {
+    interval: Duration,
+    timer: Clock,
+}
+
+/// Tracks how long a response stream has been idle.
+pub(crate) struct Idle {
+    keepalive: Keepalive,
+    sleep: Option<Sleep>,
+}
+
+// ===== impl StreamKeepalive =====
+
+impl<S> StreamKeepalive<S> {
+    /// Send an empty DATA frame on response streams idle for `interval`.
+    pub fn new(inner: S, interval: Duration) -> Self {
+        StreamKeepalive::with_timer(inner, interval, Clock::default())
+    }
+
+    /// Like `new`, measuring idle streams with `timer`, which may be a
+    /// `Timer` or a `MockClock`.
+    pub fn with_timer<T: Into<Clock>>(inner: S, interval: Duration, timer: T) -> Self {
+        StreamKeepalive {
+            inner,
+            keepalive: Keepalive::new(interval, timer.into()),
+        }
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner service.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+}
+
+impl<S, A> Service for StreamKeepalive<S>
+where S: Service<Request = http::Request<A>>,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, mut request: Self::Request) -> Self::Future {
+        request.extensions_mut().insert(self.keepalive.clone());
+        self.inner.call(request)
+    }
+}
+
+impl<S, A> NewService for StreamKeepalive<S>
+where S: NewService<Request = http::Request<A>>,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Service = StreamKeepalive<S::Service>;
+    type InitError = S::InitError;
+    type Future = NewServiceFuture<S::Future>;
+
+    fn new_service(&self) -> Self::Future {
+        NewServiceFuture {
+            inner: self.inner.new_service(),
+            keepalive: self.keepalive.clone(),
+        }
+    }
+}
+
+// ===== impl NewServiceFuture =====
+
+impl<F> Future for NewServiceFuture<F>
+where F: Future,
+{
+    type Item = StreamKeepalive<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+
+        Ok(Async::Ready(StreamKeepalive {
+            inner,
+            keepalive: self.keepalive.clone(),
+        }))
+    }
+}
+
+// ===== impl Keepalive =====
+
+impl Keepalive {
+    pub(crate) fn new(interval: Duration, timer: Clock) -> Self {
+        Keepalive { interval, timer }
+    }
+
+    /// Start tracking a stream that has just become idle.
+    pub(crate) fn idle(&self) -> Idle {
+        Idle {
+            keepalive: self.clone(),
+            sleep: None,
+        }
+    }
+}
+
+impl fmt::Debug for Keepalive {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Keepalive")
+            .field("interval", &self.interval)
+            .finish()
+    }
+}
+
+// ===== impl Idle =====
+
+impl Idle {
+    /// Returns true once the stream has been idle for the interval, and
+    /// starts measuring the next one.
+    pub(crate) fn poll_elapsed(&mut self) -> bool {
+        loop {
+            let elapsed = match self.sleep {
+                Some(ref mut sleep) => match sleep.poll() {
+                    Ok(Async::NotReady) => return false,
+                    Ok(Async::Ready(())) => true,
+                    Err(e) => {
+                        debug!("keepalive timer failed; error={:?}", e);
+                        return false;
+                    }
+                },
+                None => false,
+            };
+
+            self.sleep = Some(self.keepalive.timer.sleep(self.keepalive.interval));
+
+            if elapsed {
+                return true;
+            }
+        }
+    }
+
+    /// Restart the interval, as the stream is active.
+    pub(crate) fn reset(&mut self) {
+        self.sleep = None;
+    }
+}
+
+impl fmt::Debug for Idle {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Idle")
+            .field("keepalive", &self.keepalive)
+            .finish()
+    }
+}
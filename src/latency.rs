@@ -0,0 +1,456 @@
+//! Latency injection, for testing timeouts and hedging.
+//!
+//! `LatencyInjection` holds calls back for a fixed or sampled delay before
+//! passing them to the inner service, so tests can see how callers handle
+//! slow and tail-latency responses:
+//!
+//! ```ignore
+//! let new_service = LatencyInjection::new(GreeterServer::new(greeter))
+//!     .latency(Latency::exponential(Duration::from_millis(20)))
+//!     .method("/helloworld.Greeter/SayHello", Latency::fixed(Duration::from_secs(1))
+//!         .percent(5.0));
+//!
+//! let h2 = Server::new(new_service, Default::default(), reactor);
+//! ```
+//!
+//! Delays respect the call's deadline. The `grpc-timeout` passed on is
+//! reduced by the time spent waiting, and a call whose delay would outlast
+//! its deadline is answered with `DEADLINE_EXCEEDED` once the deadline
+//! passes, without reaching the inner service.
+
+use Status;
+use clock::{Clock, Sleep};
+use headers;
+use timeout;
+
+use futures::{Future, Poll, Async};
+use h2;
+use http::{self, HeaderMap};
+use rand;
+use tower::{NewService, Service};
+use tower_h2::Body;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Delays the calls to the inner service.
+///
+/// `LatencyInjection` may wrap either a `Service` or the `NewService` given
+/// to `tower_h2::Server`. The service is cloned for each delayed call.
+#[derive(Clone)]
+pub struct LatencyInjection<S> {
+    inner: S,
+    latencies: Arc<Latencies>,
+    timer: Clock,
+}
+
+/// The latency injected into calls.
+#[derive(Debug, Clone)]
+pub struct Latency {
+    distribution: Distribution,
+
+    /// The percentage of calls delayed.
+    percent: f64,
+}
+
+/// Creates `LatencyInjection` services.
+pub struct NewServiceFuture<F> {
+    inner: F,
+    latencies: Arc<Latencies>,
+    timer: Clock,
+}
+
+/// The response future returned by `LatencyInjection`.
+pub struct ResponseFuture<S, A>
+where S: Service,
+{
+    state: State<S, A>,
+}
+
+/// The response body returned by `LatencyInjection`.
+pub struct ResponseBody<B> {
+    inner: Option<B>,
+}
+
+enum State<S: Service, A> {
+    /// Waiting for the delay to elapse.
+    Delayed(Sleep, Option<Pending<S, A>>),
+
+    /// Waiting for the service to be ready for the call.
+    Ready(Pending<S, A>),
+
+    Calling(S::Future),
+
+    /// The delay outlasted the call's deadline.
+    Expired(Sleep),
+}
+
+struct Pending<S, A> {
+    service: S,
+    request: Option<http::Request<A>>,
+
+    /// When the call's deadline passes.
+    deadline: Option<Instant>,
+    timer: Clock,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Distribution {
+    Fixed(Duration),
+    Uniform(Duration, Duration),
+    Exponential(Duration),
+}
+
+#[derive(Debug, Clone, Default)]
+struct Latencies {
+    default: Option<Latency>,
+
+    /// Latencies overriding the default for calls to a method, by path.
+    methods: HashMap<String, Latency>,
+}
+
+// ===== impl LatencyInjection =====
+
+impl<S> LatencyInjection<S> {
+    /// No latency is injected until some is configured.
+    pub fn new(inner: S) -> Self {
+        LatencyInjection {
+            inner,
+            latencies: Arc::new(Latencies::default()),
+            timer: Clock::default(),
+        }
+    }
+
+    /// Inject `latency` into the calls to methods without their own latency.
+    pub fn latency(mut self, latency: Latency) -> Self {
+        Arc::make_mut(&mut self.latencies).default = Some(latency);
+        self
+    }
+
+    /// Inject `latency` into the calls to the method at `path`, such as
+    /// `/helloworld.Greeter/SayHello`, instead of the default latency.
+    pub fn method(mut self, path: &str, latency: Latency) -> Self {
+        Arc::make_mut(&mut self.latencies).methods.insert(path.to_string(), latency);
+        self
+    }
+
+    /// Use the provided timer, or a `MockClock`, for the delays.
+    pub fn timer<T: Into<Clock>>(mut self, timer: T) -> Self {
+        self.timer = timer.into();
+        self
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner service.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+}
+
+impl<S, A, B> Service for LatencyInjection<S>
+where S: Service<Request = http::Request<A>, Response = http::Response<B>> + Clone,
+{
+    type Request = http::Request<A>;
+    type Response = http::Response<ResponseBody<B>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S, A>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, request: Self::Request) -> Self::Future {
+        let delay = self.latencies.get(request.uri().path())
+            .and_then(Latency::sample);
+
+        let delay = match delay {
+            Some(delay) => delay,
+            None => {
+                return ResponseFuture {
+                    state: State::Calling(self.inner.call(request)),
+                };
+            }
+        };
+
+        let timeout = request.headers()
+            .get(&*headers::GRPC_TIMEOUT)
+            .and_then(timeout::decode);
+
+        if let Some(timeout) = timeout {
+            if timeout <= delay {
+                debug!("delay outlasts deadline; path={}, delay={:?}, timeout={:?}",
+                       request.uri().path(), delay, timeout);
+
+                return ResponseFuture {
+                    state: State::Expired(self.timer.sleep(timeout)),
+                };
+            }
+        }
+
+        let pending = Pending {
+            service: self.inner.clone(),
+            request: Some(request),
+            deadline: timeout.map(|timeout| self.timer.now() + timeout),
+            timer: self.timer.clone(),
+        };
+
+        ResponseFuture {
+            state: State::Delayed(self.timer.sleep(delay), Some(pending)),
+        }
+    }
+}
+
+impl<S> fmt::Debug for LatencyInjection<S>
+where S: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("LatencyInjection")
+            .field("inner", &self.inner)
+            .field("latencies", &self.latencies)
+            .finish()
+    }
+}
+
+impl<S, A, B> NewService for LatencyInjection<S>
+where S: NewService<Request = http::Request<A>, Response = http::Response<B>>,
+      S::Service: Clone,
+{
+    type Request = http::Request<A>;
+    type Response = http::Response<ResponseBody<B>>;
+    type Error = S::Error;
+    type Service = LatencyInjection<S::Service>;
+    type InitError = S::InitError;
+    type Future = NewServiceFuture<S::Future>;
+
+    fn new_service(&self) -> Self::Future {
+        NewServiceFuture {
+            inner: self.inner.new_service(),
+            latencies: self.latencies.clone(),
+            timer: self.timer.clone(),
+        }
+    }
+}
+
+// ===== impl Latency =====
+
+impl Latency {
+    /// Delay calls by `delay`.
+    pub fn fixed(delay: Duration) -> Self {
+        Latency::with_distribution(Distribution::Fixed(delay))
+    }
+
+    /// Delay calls by a duration sampled uniformly between `min` and `max`.
+    pub fn uniform(min: Duration, max: Duration) -> Self {
+        Latency::with_distribution(Distribution::Uniform(min, max))
+    }
+
+    /// Delay calls by a duration sampled from an exponential distribution
+    /// with the given `mean`, giving a long tail of slow calls.
+    pub fn exponential(mean: Duration) -> Self {
+        Latency::with_distribution(Distribution::Exponential(mean))
+    }
+
+    /// Only delay `percent` percent of the calls.
+    pub fn percent(mut self, percent: f64) -> Self {
+        self.percent = percent;
+        self
+    }
+
+    fn with_distribution(distribution: Distribution) -> Self {
+        Latency {
+            distribution,
+            percent: 100.0,
+        }
+    }
+
+    /// Returns the delay for a call, if it is delayed.
+    fn sample(&self) -> Option<Duration> {
+        if rand::random::<f64>() * 100.0 >= self.percent {
+            return None;
+        }
+
+        let delay = match self.distribution {
+            Distribution::Fixed(delay) => delay,
+            Distribution::Uniform(min, max) => {
+                let (min, max) = (secs(min), secs(max));
+                duration(min + (max - min) * rand::random::<f64>())
+            }
+            Distribution::Exponential(mean) => {
+                // `random` may return 0, but never 1.
+                let u = 1.0 - rand::random::<f64>();
+                duration(-u.ln() * secs(mean))
+            }
+        };
+
+        Some(delay)
+    }
+}
+
+// ===== impl Latencies =====
+
+impl Latencies {
+    fn get(&self, path: &str) -> Option<&Latency> {
+        self.methods.get(path).or(self.default.as_ref())
+    }
+}
+
+// ===== impl NewServiceFuture =====
+
+impl<F> Future for NewServiceFuture<F>
+where F: Future,
+{
+    type Item = LatencyInjection<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+
+        Ok(Async::Ready(LatencyInjection {
+            inner,
+            latencies: self.latencies.clone(),
+            timer: self.timer.clone(),
+        }))
+    }
+}
+
+impl<F> fmt::Debug for NewServiceFuture<F>
+where F: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("latency::NewServiceFuture")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<S, A, B> Future for ResponseFuture<S, A>
+where S: Service<Request = http::Request<A>, Response = http::Response<B>>,
+{
+    type Item = http::Response<ResponseBody<B>>;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                State::Delayed(ref mut sleep, ref mut pending) => {
+                    // A failing timer only cuts the delay short.
+                    if let Ok(Async::NotReady) = sleep.poll() {
+                        return Ok(Async::NotReady);
+                    }
+
+                    let mut pending = pending.take().expect("polled after ready");
+
+                    if let Some(deadline) = pending.deadline {
+                        let now = pending.timer.now();
+                        let remaining = if deadline > now {
+                            deadline - now
+                        } else {
+                            Duration::from_secs(0)
+                        };
+
+                        if let Some(ref mut request) = pending.request {
+                            request.headers_mut()
+                                .insert(headers::GRPC_TIMEOUT.clone(), timeout::encode(remaining));
+                        }
+                    }
+
+                    State::Ready(pending)
+                }
+                State::Ready(ref mut pending) => {
+                    try_ready!(pending.service.poll_ready());
+
+                    let request = pending.request.take().expect("polled after ready");
+                    State::Calling(pending.service.call(request))
+                }
+                State::Calling(ref mut inner) => {
+                    let response = try_ready!(inner.poll());
+                    return Ok(Async::Ready(response.map(|body| ResponseBody { inner: Some(body) })));
+                }
+                State::Expired(ref mut sleep) => {
+                    if let Ok(Async::NotReady) = sleep.poll() {
+                        return Ok(Async::NotReady);
+                    }
+
+                    let body = ResponseBody { inner: None };
+                    let response = ::Response::trailers_only(&Status::DEADLINE_EXCEEDED, body);
+                    return Ok(Async::Ready(response.into_http()));
+                }
+            };
+
+            self.state = next;
+        }
+    }
+}
+
+impl<S, A> fmt::Debug for ResponseFuture<S, A>
+where S: Service,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let state = match self.state {
+            State::Delayed(..) => "Delayed",
+            State::Ready(..) => "Ready",
+            State::Calling(..) => "Calling",
+            State::Expired(..) => "Expired",
+        };
+
+        fmt.debug_struct("latency::ResponseFuture")
+            .field("state", &state)
+            .finish()
+    }
+}
+
+// ===== impl ResponseBody =====
+
+impl<B> Body for ResponseBody<B>
+where B: Body,
+{
+    type Data = B::Data;
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.as_ref().map_or(true, Body::is_end_stream)
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, h2::Error> {
+        match self.inner {
+            Some(ref mut inner) => inner.poll_data(),
+            None => Ok(Async::Ready(None)),
+        }
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, h2::Error> {
+        match self.inner {
+            Some(ref mut inner) => inner.poll_trailers(),
+            // The status of expired calls was sent in the headers.
+            None => Ok(Async::Ready(None)),
+        }
+    }
+}
+
+impl<B> fmt::Debug for ResponseBody<B>
+where B: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("latency::ResponseBody")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+// ===== utility fns =====
+
+fn secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1e9
+}
+
+fn duration(secs: f64) -> Duration {
+    let secs = secs.max(0.0);
+    Duration::new(secs.trunc() as u64, (secs.fract() * 1e9) as u32)
+}
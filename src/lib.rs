@@ -1,35 +1,164 @@
 #![deny(warnings, missing_debug_implementations)]
 //#![deny(missing_docs)]
 
+#[cfg(feature = "async-server")]
+extern crate async_trait;
+#[cfg(feature = "simd-base64")]
+extern crate base64_simd;
 extern crate bytes;
 #[macro_use]
 extern crate futures;
 extern crate http;
 extern crate h2;
 #[macro_use]
+extern crate lazy_static;
+#[macro_use]
 extern crate log;
+extern crate rand;
+extern crate tokio_io;
+extern crate tokio_timer;
 extern crate tower;
 extern crate tower_h2;
 
 #[cfg(feature = "protobuf")]
 extern crate prost;
+#[cfg(feature = "protobuf")]
+#[macro_use]
+extern crate prost_derive;
+#[cfg(any(feature = "service-config", feature = "oauth2", feature = "jwt", feature = "transcoding"))]
+extern crate serde_json;
+#[cfg(feature = "tracing")]
+extern crate tracing;
+#[cfg(feature = "prometheus")]
+extern crate prometheus;
+#[cfg(feature = "futures03")]
+extern crate futures03;
+#[cfg(feature = "hyper")]
+extern crate hyper;
+#[cfg(feature = "jwt")]
+extern crate ring;
+#[cfg(feature = "tls")]
+extern crate rustls;
+#[cfg(feature = "unix")]
+extern crate libc;
+#[cfg(feature = "tls-native")]
+extern crate native_tls;
+#[cfg(any(feature = "in-process", feature = "tcp"))]
+extern crate tokio_core;
+#[cfg(feature = "tls")]
+extern crate tokio_rustls;
+#[cfg(feature = "tower03")]
+extern crate tower_service03;
+#[cfg(feature = "tls-native")]
+extern crate tokio_tls;
+#[cfg(feature = "unix")]
+extern crate tokio_uds;
+#[cfg(feature = "jwt")]
+extern crate untrusted;
+#[cfg(feature = "tls")]
+extern crate webpki;
 
+pub mod accesslog;
+pub mod arena;
+pub mod auth;
+pub mod channel;
 pub mod client;
+pub mod clock;
+pub mod coalesce;
+pub mod conformance;
+pub mod duplex;
+pub mod fallback;
+pub mod fault;
 pub mod generic;
+pub mod h2c;
+pub mod http2;
+pub mod keepalive;
+pub mod latency;
+pub mod limit;
+pub mod local;
+pub mod propagation;
+pub mod proxy;
+pub mod scope;
+pub mod sender;
+pub mod stats;
+pub mod stream;
+pub mod strictness;
+pub mod testing;
 
+mod base64;
 mod error;
+mod headers;
+mod redact;
 mod request;
 mod response;
 mod status;
+mod timeout;
 
 pub use error::Error;
 pub use status::{Code, Status};
 pub use request::Request;
 pub use response::Response;
 
+#[cfg(feature = "protobuf")]
+pub mod admin;
+
+#[cfg(feature = "protobuf")]
+pub mod binarylog;
+
+#[cfg(feature = "futures03")]
+pub mod compat;
+
+#[cfg(feature = "connect")]
+pub mod connect;
+
+#[cfg(feature = "dynamic")]
+pub mod dynamic;
+
+#[cfg(feature = "handshake")]
+pub mod handshake;
+
+#[cfg(feature = "protobuf")]
+pub mod health;
+
+#[cfg(feature = "hyper")]
+pub mod hyper_compat;
+
+#[cfg(feature = "in-process")]
+pub mod inprocess;
+
+#[cfg(feature = "prometheus")]
+pub mod metrics;
+
+#[cfg(feature = "protobuf")]
+pub mod mock;
+
+#[cfg(feature = "protobuf")]
+pub mod reflection;
+
+#[cfg(feature = "google-rpc")]
+pub mod rpc;
+
 #[cfg(feature = "protobuf")]
 pub mod server;
 
+#[cfg(feature = "tcp")]
+pub mod tcp;
+
+#[cfg(any(feature = "tls", feature = "tls-native"))]
+pub mod tls;
+
+#[cfg(feature = "tracing")]
+pub mod trace;
+
+#[cfg(feature = "transcoding")]
+pub mod transcode;
+
+#[cfg(feature = "twirp")]
+pub mod twirp;
+
+#[cfg(all(unix, feature = "unix"))]
+pub mod unix;
+
 /// Type re-exports used by generated code
 #[cfg(feature = "protobuf")]
 pub mod codegen;
@@ -0,0 +1,201 @@
+//! Limits on the messages of each call.
+//!
+//! `MessageLimit` guards a server against runaway streams. A call fails with
+//! `RESOURCE_EXHAUSTED` once its client has sent more than `max_received`
+//! messages, or its handler has responded with more than `max_sent`:
+//!
+//! ```ignore
+//! let new_service = MessageLimit::new(new_service)
+//!     .max_received(10_000)
+//!     .max_sent(100_000);
+//!
+//! let h2 = Server::new(new_service, Default::default(), handle.clone());
+//! ```
+//!
+//! Unary calls carry a single message each way, so only limits of zero
+//! affect them.
+//!
+//! On the client side, a `Channel` limits the size of each message of the
+//! methods whose service config sets `maxRequestMessageBytes` or
+//! `maxResponseMessageBytes`. A call fails with `RESOURCE_EXHAUSTED` once a
+//! larger message is sent or received.
+
+use Status;
+
+use futures::{Future, Poll, Async};
+use http;
+use tower::{NewService, Service};
+
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Limits the number of messages of each call to the inner service.
+///
+/// `MessageLimit` may wrap either a `Service` or the `NewService` given to
+/// `tower_h2::Server`.
+#[derive(Debug, Clone)]
+pub struct MessageLimit<S> {
+    inner: S,
+    limits: Limits,
+}
+
+/// Creates `MessageLimit` services.
+#[derive(Debug)]
+pub struct NewServiceFuture<F> {
+    inner: F,
+    limits: Limits,
+}
+
+/// Request extension holding the limits enforced by the server's codec.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Limits {
+    /// The number of messages a client may send.
+    pub(crate) received: Option<usize>,
+
+    /// The number of messages a handler may respond with.
+    pub(crate) sent: Option<usize>,
+}
+
+/// Request extension through which a channel limits the size of a client
+/// call's request messages.
+///
+/// The client adds one to each of its requests, and its codec reads the
+/// limit as it encodes each message, after the channel has applied the
+/// method's service config.
+#[derive(Debug, Clone, Default)]
+pub struct SendLimit {
+    shared: Arc<Mutex<SendShared>>,
+}
+
+#[derive(Debug, Default)]
+struct SendShared {
+    /// The largest message that may be sent.
+    max: Option<usize>,
+
+    /// The status encoding a request message failed with.
+    failure: Option<Status>,
+}
+
+/// Response extension holding the largest message a client call may
+/// receive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ReceiveLimit(pub(crate) usize);
+
+// ===== impl MessageLimit =====
+
+impl<S> MessageLimit<S> {
+    /// Calls are not limited until `max_received` or `max_sent` is set.
+    pub fn new(inner: S) -> Self {
+        MessageLimit {
+            inner,
+            limits: Limits::default(),
+        }
+    }
+
+    /// Fail calls whose client sends more than `max` messages.
+    pub fn max_received(mut self, max: usize) -> Self {
+        self.limits.received = Some(max);
+        self
+    }
+
+    /// Fail calls whose handler responds with more than `max` messages.
+    pub fn max_sent(mut self, max: usize) -> Self {
+        self.limits.sent = Some(max);
+        self
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner service.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+}
+
+impl<S, A> Service for MessageLimit<S>
+where S: Service<Request = http::Request<A>>,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, mut request: Self::Request) -> Self::Future {
+        request.extensions_mut().insert(self.limits);
+        self.inner.call(request)
+    }
+}
+
+impl<S, A> NewService for MessageLimit<S>
+where S: NewService<Request = http::Request<A>>,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Service = MessageLimit<S::Service>;
+    type InitError = S::InitError;
+    type Future = NewServiceFuture<S::Future>;
+
+    fn new_service(&self) -> Self::Future {
+        NewServiceFuture {
+            inner: self.inner.new_service(),
+            limits: self.limits,
+        }
+    }
+}
+
+// ===== impl NewServiceFuture =====
+
+impl<F> Future for NewServiceFuture<F>
+where F: Future,
+{
+    type Item = MessageLimit<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+
+        Ok(Async::Ready(MessageLimit {
+            inner,
+            limits: self.limits,
+        }))
+    }
+}
+
+// ===== impl SendLimit =====
+
+impl SendLimit {
+    pub(crate) fn new() -> Self {
+        SendLimit::default()
+    }
+
+    /// Limit request messages to `max` bytes.
+    pub(crate) fn set(&self, max: usize) {
+        self.lock().max = Some(max);
+    }
+
+    /// Returns the largest message that may be sent, if limited.
+    pub(crate) fn get(&self) -> Option<usize> {
+        self.lock().max
+    }
+
+    /// Record the status encoding a request message failed with.
+    pub(crate) fn fail(&self, status: Status) {
+        self.lock().failure = Some(status);
+    }
+
+    /// Returns the status encoding a request message failed with, if any.
+    pub(crate) fn failure(&self) -> Option<Status> {
+        self.lock().failure.clone()
+    }
+
+    fn lock(&self) -> MutexGuard<SendShared> {
+        self.shared.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
@@ -0,0 +1,129 @@
+//! Execution without `Send`.
+//!
+//! A tokio-core reactor runs every task on the thread that drives it, so
+//! servers and channels do not require their handlers, response futures or
+//! streams to be `Send`. Handlers may hold `Rc`, `RefCell` and other
+//! thread-local values, as is common in GUI applications.
+//!
+//! Two parts of the crate are `Send` by default: channels box request
+//! bodies as `tower_h2::BoxBody`, and `compat::boxed` boxes `Send` futures.
+//! This module provides their local variants. A `LocalChannel` is created
+//! from an `Http2Connect::local` connector, and its request streams need
+//! not be `Send`:
+//!
+//! ```ignore
+//! let connect = Http2Connect::local(connector, handle.clone());
+//! let channel: LocalChannel<_, _, _> = Channel::new(resolver, connect, policy);
+//!
+//! let mut client = client::Greeter::new(channel, uri)?;
+//! ```
+//!
+//! A `LocalServer` serves connections on the reactor:
+//!
+//! ```ignore
+//! let new_service = server::GreeterServer::new(Greeter { cache: Rc::new(RefCell::new(cache)) });
+//! let h2 = LocalServer::new(new_service, Default::default(), handle.clone());
+//! ```
+//!
+//! Handlers written with `async fn` return a `compat::LocalBoxFuture`, and
+//! `tower_grpc_build::Config::local_server` generates async service traits
+//! whose futures need not be `Send`.
+
+#[cfg(any(feature = "in-process", feature = "tcp"))]
+use channel::{Channel, Http2Connect};
+#[cfg(feature = "protobuf")]
+use client::Encodable;
+#[cfg(feature = "protobuf")]
+use limit::SendLimit;
+#[cfg(feature = "protobuf")]
+use stats::CallStats;
+
+use bytes::Bytes;
+use futures::Poll;
+#[cfg(feature = "protobuf")]
+use futures::Stream;
+use h2;
+use http::HeaderMap;
+#[cfg(feature = "protobuf")]
+use prost::Message;
+#[cfg(any(feature = "in-process", feature = "tcp"))]
+use tokio_core::reactor::Handle;
+use tower_h2::Body;
+#[cfg(any(feature = "in-process", feature = "tcp"))]
+use tower_h2::Server;
+
+use std::fmt;
+
+/// A channel whose request bodies need not be `Send`.
+#[cfg(any(feature = "in-process", feature = "tcp"))]
+pub type LocalChannel<R, C, P> = Channel<R, Http2Connect<C, LocalBoxBody>, P>;
+
+/// A server spawning its connections, and their handlers, on the reactor of
+/// a `Handle`.
+#[cfg(any(feature = "in-process", feature = "tcp"))]
+pub type LocalServer<S, B> = Server<S, Handle, B>;
+
+/// A boxed request body that need not be `Send`.
+pub struct LocalBoxBody {
+    inner: Box<Body<Data = Bytes>>,
+}
+
+// ===== impl LocalBoxBody =====
+
+impl LocalBoxBody {
+    /// Box `inner`.
+    pub fn new(inner: Box<Body<Data = Bytes>>) -> Self {
+        LocalBoxBody { inner }
+    }
+}
+
+impl Body for LocalBoxBody {
+    type Data = Bytes;
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Bytes>, h2::Error> {
+        self.inner.poll_data()
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, h2::Error> {
+        self.inner.poll_trailers()
+    }
+}
+
+impl fmt::Debug for LocalBoxBody {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("LocalBoxBody")
+            .finish()
+    }
+}
+
+#[cfg(feature = "protobuf")]
+impl<T, U> Encodable<LocalBoxBody> for T
+where T: Stream<Item = U, Error = ::Error> + 'static,
+      U: Message + 'static,
+{
+    fn into_encode(self) -> LocalBoxBody {
+        use codec::Encoder;
+        use generic::Encode;
+
+        let encode = Encode::new(Encoder::new(), self, false);
+        LocalBoxBody::new(Box::new(encode))
+    }
+
+    fn into_encode_limited(self, limit: SendLimit) -> LocalBoxBody {
+        self.into_encode_observed(limit, None)
+    }
+
+    fn into_encode_observed(self, limit: SendLimit, stats: Option<CallStats>) -> LocalBoxBody {
+        use codec::Encoder;
+        use generic::Encode;
+
+        let encode = Encode::new(Encoder::new(), self, false)
+            .send_limit(limit)
+            .stats(stats);
+        LocalBoxBody::new(Box::new(encode))
+    }
+}
@@ -0,0 +1,519 @@
+//! Prometheus metrics.
+//!
+//! `ServerMetrics` and `ClientMetrics` wrap a server's or client's HTTP/2.0
+//! service and record the conventional gRPC metrics in a `Metrics`:
+//!
+//! - `grpc_{server,client}_started_total`
+//! - `grpc_{server,client}_handled_total`, also labeled by `grpc_code`
+//! - `grpc_{server,client}_msg_received_total`
+//! - `grpc_{server,client}_msg_sent_total`
+//! - `grpc_{server,client}_handling_seconds`, a histogram of call latency
+//!
+//! Every metric is labeled by `grpc_service` and `grpc_method`. The
+//! `grpc_type` label is left out, as the kind of a method is not known
+//! outside of its generated code.
+
+use generic::counter::{Count, Frames, MessageCounters};
+use headers;
+use Code;
+
+use futures::{Future, Poll, Async};
+use h2;
+use http::{self, HeaderMap};
+use prometheus::{self, Counter, CounterVec, HistogramOpts, HistogramVec, Opts, Registry};
+use tower::{NewService, Service};
+use tower_h2::{Body, BoxBody, HttpService};
+
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+/// The gRPC metrics of a server and its clients.
+#[derive(Clone)]
+pub struct Metrics {
+    server: Arc<Set>,
+    client: Arc<Set>,
+}
+
+/// Records the metrics of the calls made through the inner service.
+#[derive(Debug, Clone)]
+pub struct ClientMetrics<S> {
+    inner: S,
+    metrics: Arc<Set>,
+}
+
+/// Records the metrics of the calls served by the inner service.
+///
+/// `ServerMetrics` may wrap either a `Service` or the `NewService` given to
+/// `tower_h2::Server`.
+#[derive(Debug, Clone)]
+pub struct ServerMetrics<S> {
+    inner: S,
+    metrics: Arc<Set>,
+}
+
+/// The response future returned by `ClientMetrics` and `ServerMetrics`.
+pub struct ResponseFuture<F> {
+    inner: F,
+    call: Arc<Call>,
+}
+
+/// A request or response body whose messages are counted.
+pub struct MeasuredBody<B> {
+    inner: B,
+    call: Arc<Call>,
+    direction: Direction,
+    frames: Frames,
+}
+
+/// Creates `ServerMetrics` services.
+#[derive(Debug)]
+pub struct NewServiceFuture<F> {
+    inner: F,
+    metrics: Arc<Set>,
+}
+
+/// The metrics of one side of a call.
+struct Set {
+    started: CounterVec,
+    handled: CounterVec,
+    received: CounterVec,
+    sent: CounterVec,
+    handling: HistogramVec,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Side {
+    Client,
+    Server,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Direction {
+    Request,
+    Response,
+}
+
+/// A call being measured; completed as `CANCELED` if dropped before its
+/// status is known.
+struct Call {
+    metrics: Arc<Set>,
+    side: Side,
+    service: String,
+    method: String,
+    sent: Counter,
+    received: Counter,
+    start: Instant,
+    finished: AtomicBool,
+}
+
+// ===== impl Metrics =====
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics {
+            server: Arc::new(Set::new("server")),
+            client: Arc::new(Set::new("client")),
+        }
+    }
+
+    /// Register every metric with `registry`.
+    pub fn register(&self, registry: &Registry) -> prometheus::Result<()> {
+        self.server.register(registry)?;
+        self.client.register(registry)
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics::new()
+    }
+}
+
+impl fmt::Debug for Metrics {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Metrics").finish()
+    }
+}
+
+// ===== impl ClientMetrics =====
+
+impl<S> ClientMetrics<S> {
+    /// Record the metrics of the calls made through `inner` in `metrics`.
+    pub fn new(inner: S, metrics: &Metrics) -> Self {
+        ClientMetrics {
+            inner,
+            metrics: metrics.client.clone(),
+        }
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner service.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+}
+
+impl<S> Service for ClientMetrics<S>
+where S: HttpService<RequestBody = BoxBody>,
+      <S::ResponseBody as Body>::Data: AsRef<[u8]>,
+{
+    type Request = http::Request<BoxBody>;
+    type Response = http::Response<MeasuredBody<S::ResponseBody>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, request: Self::Request) -> Self::Future {
+        let call = Arc::new(Call::new(self.metrics.clone(), Side::Client, request.uri().path()));
+
+        let (head, body) = request.into_parts();
+        let body = MeasuredBody::new(body, call.clone(), Direction::Request);
+        let request = http::Request::from_parts(head, BoxBody::new(Box::new(body)));
+
+        ResponseFuture {
+            inner: self.inner.call(request),
+            call,
+        }
+    }
+}
+
+// ===== impl ServerMetrics =====
+
+impl<S> ServerMetrics<S> {
+    /// Record the metrics of the calls served by `inner` in `metrics`.
+    pub fn new(inner: S, metrics: &Metrics) -> Self {
+        ServerMetrics {
+            inner,
+            metrics: metrics.server.clone(),
+        }
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner service.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+}
+
+impl<S, A, B> Service for ServerMetrics<S>
+where S: Service<Request = http::Request<A>, Response = http::Response<B>>,
+      B: Body,
+      B::Data: AsRef<[u8]>,
+{
+    type Request = S::Request;
+    type Response = http::Response<MeasuredBody<B>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, mut request: Self::Request) -> Self::Future {
+        let call = Arc::new(Call::new(self.metrics.clone(), Side::Server, request.uri().path()));
+
+        // Received messages are counted as the server decodes them.
+        MessageCounters::register(&mut request, Arc::new(call.received.clone()));
+
+        ResponseFuture {
+            inner: self.inner.call(request),
+            call,
+        }
+    }
+}
+
+impl<S, A, B> NewService for ServerMetrics<S>
+where S: NewService<Request = http::Request<A>, Response = http::Response<B>>,
+      B: Body,
+      B::Data: AsRef<[u8]>,
+{
+    type Request = S::Request;
+    type Response = http::Response<MeasuredBody<B>>;
+    type Error = S::Error;
+    type Service = ServerMetrics<S::Service>;
+    type InitError = S::InitError;
+    type Future = NewServiceFuture<S::Future>;
+
+    fn new_service(&self) -> Self::Future {
+        NewServiceFuture {
+            inner: self.inner.new_service(),
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+// ===== impl NewServiceFuture =====
+
+impl<F> Future for NewServiceFuture<F>
+where F: Future,
+{
+    type Item = ServerMetrics<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        Ok(Async::Ready(ServerMetrics {
+            inner,
+            metrics: self.metrics.clone(),
+        }))
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F, B> Future for ResponseFuture<F>
+where F: Future<Item = http::Response<B>>,
+{
+    type Item = http::Response<MeasuredBody<B>>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let response = match self.inner.poll() {
+            Ok(Async::Ready(response)) => response,
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Err(e) => {
+                self.call.finish(Code::UNKNOWN);
+                return Err(e);
+            }
+        };
+
+        // Trailers-only responses carry the status in the headers.
+        if let Some(code) = headers::status_code(response.headers()) {
+            self.call.finish(code);
+        }
+
+        let (head, body) = response.into_parts();
+        let body = MeasuredBody::new(body, self.call.clone(), Direction::Response);
+
+        Ok(Async::Ready(http::Response::from_parts(head, body)))
+    }
+}
+
+impl<F> fmt::Debug for ResponseFuture<F>
+where F: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("metrics::ResponseFuture")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+// ===== impl MeasuredBody =====
+
+impl<B> MeasuredBody<B> {
+    fn new(inner: B, call: Arc<Call>, direction: Direction) -> Self {
+        MeasuredBody {
+            inner,
+            call,
+            direction,
+            frames: Frames::default(),
+        }
+    }
+}
+
+impl<B> Body for MeasuredBody<B>
+where B: Body,
+      B::Data: AsRef<[u8]>,
+{
+    type Data = B::Data;
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, h2::Error> {
+        let data = try_ready!(self.inner.poll_data());
+
+        if let Some(ref data) = data {
+            let messages = self.frames.count(data.as_ref());
+            self.call.messages(self.direction, messages);
+        }
+
+        Ok(Async::Ready(data))
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, h2::Error> {
+        let trailers = try_ready!(self.inner.poll_trailers());
+
+        if self.direction == Direction::Response {
+            let code = trailers.as_ref()
+                .and_then(headers::status_code)
+                .unwrap_or(Code::UNKNOWN);
+            self.call.finish(code);
+        }
+
+        Ok(Async::Ready(trailers))
+    }
+}
+
+impl<B> fmt::Debug for MeasuredBody<B>
+where B: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("MeasuredBody")
+            .field("inner", &self.inner)
+            .field("direction", &self.direction)
+            .finish()
+    }
+}
+
+// ===== impl Set =====
+
+impl Set {
+    fn new(side: &str) -> Self {
+        let labels = &["grpc_service", "grpc_method"];
+        let opts = |name: &str, help: &str| {
+            Opts::new(format!("grpc_{}_{}", side, name), help.to_string())
+        };
+
+        let started = CounterVec::new(
+            opts("started_total", "Total number of RPCs started."),
+            labels);
+        let handled = CounterVec::new(
+            opts("handled_total", "Total number of RPCs completed, regardless of success or failure."),
+            &["grpc_service", "grpc_method", "grpc_code"]);
+        let received = CounterVec::new(
+            opts("msg_received_total", "Total number of stream messages received."),
+            labels);
+        let sent = CounterVec::new(
+            opts("msg_sent_total", "Total number of stream messages sent."),
+            labels);
+        let handling = HistogramVec::new(
+            HistogramOpts::new(
+                format!("grpc_{}_handling_seconds", side),
+                "Histogram of the latency of RPCs until completion.".to_string()),
+            labels);
+
+        Set {
+            started: started.expect("valid metric"),
+            handled: handled.expect("valid metric"),
+            received: received.expect("valid metric"),
+            sent: sent.expect("valid metric"),
+            handling: handling.expect("valid metric"),
+        }
+    }
+
+    fn register(&self, registry: &Registry) -> prometheus::Result<()> {
+        registry.register(Box::new(self.started.clone()))?;
+        registry.register(Box::new(self.handled.clone()))?;
+        registry.register(Box::new(self.received.clone()))?;
+        registry.register(Box::new(self.sent.clone()))?;
+        registry.register(Box::new(self.handling.clone()))
+    }
+}
+
+impl fmt::Debug for Set {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("metrics::Set").finish()
+    }
+}
+
+// ===== impl Call =====
+
+impl Call {
+    fn new(metrics: Arc<Set>, side: Side, path: &str) -> Self {
+        let (service, method) = headers::split_method_path(path).unwrap_or(("", ""));
+        let (service, method) = (service.to_string(), method.to_string());
+
+        metrics.started.with_label_values(&[&service, &method]).inc();
+        let sent = metrics.sent.with_label_values(&[&service, &method]);
+        let received = metrics.received.with_label_values(&[&service, &method]);
+
+        Call {
+            metrics,
+            side,
+            service,
+            method,
+            sent,
+            received,
+            start: Instant::now(),
+            finished: AtomicBool::new(false),
+        }
+    }
+
+    /// Count messages sent in `direction`.
+    ///
+    /// Request messages are counted by the codec on the server.
+    fn messages(&self, direction: Direction, messages: usize) {
+        if messages == 0 {
+            return;
+        }
+
+        let counter = match (self.side, direction) {
+            (Side::Client, Direction::Request) => &self.sent,
+            (Side::Client, Direction::Response) => &self.received,
+            (Side::Server, Direction::Response) => &self.sent,
+            (Side::Server, Direction::Request) => return,
+        };
+
+        counter.inc_by(messages as f64);
+    }
+
+    fn finish(&self, code: Code) {
+        if self.finished.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        let elapsed = self.start.elapsed();
+        let seconds = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+
+        self.metrics.handled
+            .with_label_values(&[&self.service, &self.method, code_name(code)])
+            .inc();
+        self.metrics.handling
+            .with_label_values(&[&self.service, &self.method])
+            .observe(seconds);
+    }
+}
+
+impl Drop for Call {
+    fn drop(&mut self) {
+        self.finish(Code::CANCELED);
+    }
+}
+
+impl Count for Counter {
+    fn message(&self, _: usize) {
+        self.inc();
+    }
+}
+
+// ===== utility fns =====
+
+/// Returns the name `code` is labeled with, matching other gRPC
+/// implementations.
+fn code_name(code: Code) -> &'static str {
+    match code.as_i32() {
+        0 => "OK",
+        1 => "Canceled",
+        3 => "InvalidArgument",
+        4 => "DeadlineExceeded",
+        5 => "NotFound",
+        6 => "AlreadyExists",
+        7 => "PermissionDenied",
+        8 => "ResourceExhausted",
+        9 => "FailedPrecondition",
+        10 => "Aborted",
+        11 => "OutOfRange",
+        12 => "Unimplemented",
+        13 => "Internal",
+        14 => "Unavailable",
+        15 => "DataLoss",
+        16 => "Unauthenticated",
+        _ => "Unknown",
+    }
+}
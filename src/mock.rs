@@ -0,0 +1,448 @@
+//! Programmable services, for testing clients.
+//!
+//! A `MockService` answers the calls it expects with scripted responses,
+//! so a client can be tested against a server without implementing one.
+//! Each `Expect` names a method, optionally matches the request messages
+//! and metadata, and lists the response messages, delays and final status:
+//!
+//! ```ignore
+//! let mock = MockService::new()
+//!     .expect(Expect::call("/helloworld.Greeter/SayHello")
+//!         .request(|request: &HelloRequest| request.name == "world")
+//!         .metadata("x-request-id", "1")
+//!         .respond(&HelloReply { message: "Hello world".into() }))
+//!     .expect(Expect::call("/routeguide.RouteGuide/ListFeatures")
+//!         .respond(&feature_a)
+//!         .delay(Duration::from_millis(100))
+//!         .respond(&feature_b)
+//!         .fail(Status::with_code(Code::UNAVAILABLE)));
+//!
+//! let conn = core.run(inprocess::connect(mock.clone(), &handle))?;
+//! // Run the client over `conn`...
+//!
+//! mock.verify();
+//! ```
+//!
+//! A call is answered by the first expectation it matches that was called
+//! fewer times than expected, and fails with `UNIMPLEMENTED` if there is
+//! none. Once the last clone of the mock is dropped, it panics if a call
+//! was unexpected or an expectation wasn't met, unless the thread is
+//! already panicking.
+
+use Status;
+use clock::{Clock, Sleep};
+use generic::{BytesCodec, FrameEncoder, Streaming};
+
+use bytes::Bytes;
+use futures::{future, Future, Stream, Poll, Async};
+use h2;
+use http::{self, header, HeaderMap};
+use http::header::HeaderValue;
+use prost::Message;
+use tower::{NewService, Service};
+use tower_h2::Body;
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+type Matcher = Box<Fn(&[u8]) -> bool + Send + Sync>;
+
+/// A service answering expected calls with scripted responses.
+#[derive(Clone)]
+pub struct MockService {
+    shared: Arc<Shared>,
+    clock: Clock,
+}
+
+/// An expected call, and the response to it.
+pub struct Expect {
+    path: String,
+    metadata: Vec<(String, String)>,
+    matchers: Vec<Matcher>,
+    steps: Vec<Step>,
+    status: Status,
+    times: usize,
+
+    /// The number of calls answered.
+    calls: usize,
+}
+
+/// The response future returned by `MockService`.
+pub struct ResponseFuture<B> {
+    shared: Arc<Shared>,
+    clock: Clock,
+    head: http::request::Parts,
+    messages: Streaming<BytesCodec, B>,
+    received: Vec<Vec<u8>>,
+}
+
+/// The response body returned by `MockService`.
+pub struct ResponseBody {
+    steps: VecDeque<Step>,
+    status: Status,
+    frames: FrameEncoder<BytesCodec>,
+    timer: Clock,
+    sleep: Option<Sleep>,
+}
+
+/// The expectations and the calls that matched none, shared by clones.
+struct Shared {
+    expectations: Mutex<Vec<Expect>>,
+    unexpected: Mutex<Vec<String>>,
+}
+
+#[derive(Debug, Clone)]
+enum Step {
+    /// Send an encoded response message.
+    Message(Vec<u8>),
+
+    /// Wait before the next step.
+    Delay(Duration),
+}
+
+// ===== impl MockService =====
+
+impl MockService {
+    /// Create a service expecting no calls.
+    pub fn new() -> Self {
+        MockService {
+            shared: Arc::new(Shared {
+                expectations: Mutex::new(vec![]),
+                unexpected: Mutex::new(vec![]),
+            }),
+            clock: Clock::default(),
+        }
+    }
+
+    /// Use the provided timer, or a `MockClock`, for the delays.
+    pub fn timer<T: Into<Clock>>(mut self, timer: T) -> Self {
+        self.clock = timer.into();
+        self
+    }
+
+    /// Expect the call described by `expect`.
+    pub fn expect(self, expect: Expect) -> Self {
+        lock(&self.shared.expectations).push(expect);
+        self
+    }
+
+    /// Panics if a call was unexpected or an expectation isn't met yet.
+    pub fn verify(&self) {
+        if let Err(e) = self.shared.check() {
+            panic!("{}", e);
+        }
+    }
+}
+
+impl Default for MockService {
+    fn default() -> Self {
+        MockService::new()
+    }
+}
+
+impl<B> Service for MockService
+where B: Body,
+      B::Data: Into<Bytes>,
+{
+    type Request = http::Request<B>;
+    type Response = http::Response<ResponseBody>;
+    type Error = h2::Error;
+    type Future = ResponseFuture<B>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        Ok(Async::Ready(()))
+    }
+
+    fn call(&mut self, request: Self::Request) -> Self::Future {
+        let (head, body) = request.into_parts();
+
+        ResponseFuture {
+            shared: self.shared.clone(),
+            clock: self.clock.clone(),
+            head,
+            messages: Streaming::new(BytesCodec, body, false),
+            received: vec![],
+        }
+    }
+}
+
+impl<B> NewService for MockService
+where B: Body,
+      B::Data: Into<Bytes>,
+{
+    type Request = http::Request<B>;
+    type Response = http::Response<ResponseBody>;
+    type Error = h2::Error;
+    type Service = Self;
+    type InitError = h2::Error;
+    type Future = future::FutureResult<Self::Service, Self::InitError>;
+
+    fn new_service(&self) -> Self::Future {
+        future::ok(self.clone())
+    }
+}
+
+impl fmt::Debug for MockService {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("MockService")
+            .field("expectations", &*lock(&self.shared.expectations))
+            .field("unexpected", &*lock(&self.shared.unexpected))
+            .finish()
+    }
+}
+
+// ===== impl Expect =====
+
+impl Expect {
+    /// Expect a call to the method at `path`, such as
+    /// `/helloworld.Greeter/SayHello`.
+    ///
+    /// The call is expected once, and responds with an `OK` status and no
+    /// messages unless specified otherwise.
+    pub fn call(path: &str) -> Self {
+        Expect {
+            path: path.to_string(),
+            metadata: vec![],
+            matchers: vec![],
+            steps: vec![],
+            status: Status::OK,
+            times: 1,
+            calls: 0,
+        }
+    }
+
+    /// Only match calls whose request messages are all accepted by
+    /// `matcher`.
+    pub fn request<M, F>(mut self, matcher: F) -> Self
+    where M: Message + Default,
+          F: Fn(&M) -> bool + Send + Sync + 'static,
+    {
+        self.matchers.push(Box::new(move |bytes: &[u8]| {
+            M::decode(bytes).map(|message| matcher(&message)).unwrap_or(false)
+        }));
+        self
+    }
+
+    /// Only match calls with the metadata `key` set to `value`.
+    pub fn metadata(mut self, key: &str, value: &str) -> Self {
+        self.metadata.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Expect the call `times` times, answering each with the same
+    /// response.
+    pub fn times(mut self, times: usize) -> Self {
+        self.times = times;
+        self
+    }
+
+    /// Send `message`, after the messages and delays given so far.
+    pub fn respond<M: Message>(mut self, message: &M) -> Self {
+        let mut buf = Vec::with_capacity(message.encoded_len());
+        message.encode(&mut buf).expect("buffer has enough capacity");
+
+        self.steps.push(Step::Message(buf));
+        self
+    }
+
+    /// Wait for `delay` before the next message or the status.
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.steps.push(Step::Delay(delay));
+        self
+    }
+
+    /// End the response with `status`, after the messages and delays.
+    pub fn fail(mut self, status: Status) -> Self {
+        self.status = status;
+        self
+    }
+
+    fn matches(&self, head: &http::request::Parts, messages: &[Vec<u8>]) -> bool {
+        if head.uri.path() != self.path {
+            return false;
+        }
+
+        let metadata = self.metadata.iter().all(|&(ref key, ref value)| {
+            head.headers.get_all(&key[..]).iter().any(|v| v == &value[..])
+        });
+
+        metadata && messages.iter().all(|message| {
+            self.matchers.iter().all(|matcher| matcher(message))
+        })
+    }
+}
+
+impl fmt::Debug for Expect {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Expect")
+            .field("path", &self.path)
+            .field("metadata", &self.metadata)
+            .field("matchers", &self.matchers.len())
+            .field("steps", &self.steps)
+            .field("status", &self.status)
+            .field("times", &self.times)
+            .field("calls", &self.calls)
+            .finish()
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<B> ResponseFuture<B> {
+    fn respond(&self) -> ResponseBody {
+        let mut expectations = lock(&self.shared.expectations);
+
+        let expect = expectations.iter_mut()
+            .find(|e| e.calls < e.times && e.matches(&self.head, &self.received));
+
+        let (steps, status) = match expect {
+            Some(expect) => {
+                expect.calls += 1;
+                (expect.steps.iter().cloned().collect(), expect.status.clone())
+            }
+            None => {
+                debug!("unexpected call; path={}", self.head.uri.path());
+
+                lock(&self.shared.unexpected).push(format!(
+                    "{} with {} message(s)", self.head.uri.path(), self.received.len()));
+
+                (VecDeque::new(), Status::UNIMPLEMENTED)
+            }
+        };
+
+        ResponseBody {
+            steps,
+            status,
+            frames: FrameEncoder::new(BytesCodec),
+            timer: self.clock.clone(),
+            sleep: None,
+        }
+    }
+}
+
+impl<B> Future for ResponseFuture<B>
+where B: Body,
+      B::Data: Into<Bytes>,
+{
+    type Item = http::Response<ResponseBody>;
+    type Error = h2::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        while let Some(message) = try_ready!(self.messages.poll().map_err(|e| {
+            debug!("request message could not be read; error={:?}", e);
+            h2::Error::from(h2::Reason::INTERNAL_ERROR)
+        })) {
+            self.received.push(message);
+        }
+
+        let mut response = http::Response::new(self.respond());
+        response.headers_mut()
+            .insert(header::CONTENT_TYPE, HeaderValue::from_static("application/grpc+proto"));
+
+        Ok(Async::Ready(response))
+    }
+}
+
+impl<B> fmt::Debug for ResponseFuture<B> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("mock::ResponseFuture")
+            .field("path", &self.head.uri.path())
+            .field("received", &self.received.len())
+            .finish()
+    }
+}
+
+// ===== impl ResponseBody =====
+
+impl Body for ResponseBody {
+    type Data = Bytes;
+
+    fn is_end_stream(&self) -> bool {
+        false
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Bytes>, h2::Error> {
+        loop {
+            if let Some(ref mut sleep) = self.sleep {
+                try_ready!(sleep.poll().map_err(|_| h2::Error::from(h2::Reason::INTERNAL_ERROR)));
+            }
+
+            self.sleep = None;
+
+            match self.steps.pop_front() {
+                Some(Step::Message(message)) => {
+                    let frame = self.frames.encode(message)
+                        .expect("encoded messages are framed");
+                    return Ok(Async::Ready(Some(frame)));
+                }
+                Some(Step::Delay(delay)) => {
+                    self.sleep = Some(self.timer.sleep(delay));
+                }
+                None => return Ok(Async::Ready(None)),
+            }
+        }
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, h2::Error> {
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", self.status.to_header_value());
+        Ok(Async::Ready(Some(trailers)))
+    }
+}
+
+impl fmt::Debug for ResponseBody {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("mock::ResponseBody")
+            .field("steps", &self.steps)
+            .field("status", &self.status)
+            .finish()
+    }
+}
+
+// ===== impl Shared =====
+
+impl Shared {
+    /// Describes the unexpected calls and unmet expectations, if any.
+    fn check(&self) -> Result<(), String> {
+        let mut problems = vec![];
+
+        for call in lock(&self.unexpected).iter() {
+            problems.push(format!("unexpected call to {}", call));
+        }
+
+        for expect in lock(&self.expectations).iter() {
+            if expect.calls < expect.times {
+                problems.push(format!("expected {} call(s) to {}, got {}",
+                                      expect.times, expect.path, expect.calls));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("mock expectations not met:\n  {}", problems.join("\n  ")))
+        }
+    }
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            return;
+        }
+
+        if let Err(e) = self.check() {
+            panic!("{}", e);
+        }
+    }
+}
+
+// ===== utility fns =====
+
+/// Lock `mutex`, even if a panicking test poisoned it.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
@@ -0,0 +1,503 @@
+//! Trace context propagation.
+//!
+//! `ExtractContext` reads the trace context of inbound calls from their
+//! `grpc-trace-bin` or W3C `traceparent` header and stores it in the
+//! request's extensions, where handlers can find it with
+//! `Request::extensions`. `InjectContext` does the reverse for outbound
+//! calls, writing the `TraceContext` found in a request's extensions to its
+//! headers.
+//!
+//! To continue a trace across a hop, a handler inserts a child of its call's
+//! context into the extensions of the requests it makes:
+//!
+//! ```ignore
+//! if let Some(context) = request.extensions().get::<TraceContext>() {
+//!     outbound.extensions_mut().insert(context.child());
+//! }
+//! ```
+
+use base64;
+
+use futures::{Future, Poll, Async};
+use http::{self, HeaderMap};
+use http::header::HeaderValue;
+use rand;
+use tower::{NewService, Service};
+
+use std::fmt;
+
+const GRPC_TRACE_BIN: &'static str = "grpc-trace-bin";
+const TRACEPARENT: &'static str = "traceparent";
+
+/// The position of a call in a distributed trace.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TraceContext {
+    trace_id: [u8; 16],
+    span_id: [u8; 8],
+    sampled: bool,
+}
+
+/// The headers trace contexts are written to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// The OpenCensus binary format, in the `grpc-trace-bin` header.
+    GrpcTraceBin,
+
+    /// The W3C Trace Context `traceparent` header.
+    TraceParent,
+
+    /// Both headers.
+    Both,
+}
+
+/// Stores the trace context of inbound calls in their extensions.
+///
+/// `ExtractContext` may wrap either a `Service` or the `NewService` given to
+/// `tower_h2::Server`.
+#[derive(Debug, Clone)]
+pub struct ExtractContext<S> {
+    inner: S,
+}
+
+/// Writes the trace context found in the extensions of outbound calls to
+/// their headers.
+#[derive(Debug, Clone)]
+pub struct InjectContext<S> {
+    inner: S,
+    format: Format,
+}
+
+/// Creates `ExtractContext` services.
+#[derive(Debug)]
+pub struct NewServiceFuture<F> {
+    inner: F,
+}
+
+// ===== impl TraceContext =====
+
+impl TraceContext {
+    pub fn new(trace_id: [u8; 16], span_id: [u8; 8], sampled: bool) -> Self {
+        TraceContext { trace_id, span_id, sampled }
+    }
+
+    /// Start a new trace.
+    pub fn root(sampled: bool) -> Self {
+        TraceContext::new(rand::random(), rand::random(), sampled)
+    }
+
+    /// Returns a context in the same trace, with a new span id.
+    pub fn child(&self) -> Self {
+        TraceContext::new(self.trace_id, rand::random(), self.sampled)
+    }
+
+    pub fn trace_id(&self) -> [u8; 16] {
+        self.trace_id
+    }
+
+    pub fn span_id(&self) -> [u8; 8] {
+        self.span_id
+    }
+
+    /// Returns true if the trace is being recorded.
+    pub fn is_sampled(&self) -> bool {
+        self.sampled
+    }
+
+    /// Read a trace context from `headers`.
+    ///
+    /// `grpc-trace-bin` is preferred over `traceparent` when both are
+    /// present and valid.
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let bin = headers.get(GRPC_TRACE_BIN)
+            .and_then(|value| base64::decode(value.as_bytes()))
+            .and_then(|bytes| TraceContext::from_grpc_trace_bin(&bytes));
+
+        bin.or_else(|| {
+            headers.get(TRACEPARENT)
+                .and_then(|value| value.to_str().ok())
+                .and_then(TraceContext::from_traceparent)
+        })
+    }
+
+    /// Write the trace context to `headers` in `format`.
+    pub fn to_headers(&self, headers: &mut HeaderMap, format: Format) {
+        if format != Format::TraceParent {
+            headers.insert(GRPC_TRACE_BIN, self.grpc_trace_bin_value());
+        }
+
+        if format != Format::GrpcTraceBin {
+            headers.insert(TRACEPARENT, self.traceparent_value());
+        }
+    }
+
+    /// Parse the binary format: a version byte followed by the trace id
+    /// (field 0), span id (field 1) and trace options (field 2).
+    fn from_grpc_trace_bin(bytes: &[u8]) -> Option<Self> {
+        if bytes.first() != Some(&0) {
+            return None;
+        }
+
+        let mut rest = &bytes[1..];
+        let mut trace_id = None;
+        let mut span_id = None;
+        let mut sampled = false;
+
+        while let Some((&field, tail)) = rest.split_first() {
+            match field {
+                0 if tail.len() >= 16 => {
+                    let mut id = [0; 16];
+                    id.copy_from_slice(&tail[..16]);
+                    trace_id = Some(id);
+                    rest = &tail[16..];
+                }
+                1 if tail.len() >= 8 => {
+                    let mut id = [0; 8];
+                    id.copy_from_slice(&tail[..8]);
+                    span_id = Some(id);
+                    rest = &tail[8..];
+                }
+                2 if tail.len() >= 1 => {
+                    sampled = tail[0] & 1 == 1;
+                    rest = &tail[1..];
+                }
+                // Unknown fields end the known ones.
+                _ => break,
+            }
+        }
+
+        match (trace_id, span_id) {
+            (Some(trace_id), Some(span_id)) => Some(TraceContext::new(trace_id, span_id, sampled)),
+            _ => None,
+        }
+    }
+
+    /// Parse `version-trace_id-span_id-flags`, in lower case hex.
+    fn from_traceparent(value: &str) -> Option<Self> {
+        let mut parts = value.trim().split('-');
+        let version = parts.next()?;
+        let trace = parts.next()?;
+        let span = parts.next()?;
+        let flags = parts.next()?;
+
+        // Later versions may only append fields.
+        if version.len() != 2 || version == "ff" || (version == "00" && parts.next().is_some()) {
+            return None;
+        }
+
+        let mut trace_id = [0; 16];
+        let mut span_id = [0; 8];
+        let mut options = [0; 1];
+
+        if !decode_hex(trace, &mut trace_id) || !decode_hex(span, &mut span_id) ||
+            !decode_hex(flags, &mut options) || !decode_hex(version, &mut [0; 1])
+        {
+            return None;
+        }
+
+        if trace_id == [0; 16] || span_id == [0; 8] {
+            return None;
+        }
+
+        Some(TraceContext::new(trace_id, span_id, options[0] & 1 == 1))
+    }
+
+    fn grpc_trace_bin_value(&self) -> HeaderValue {
+        let mut bytes = Vec::with_capacity(29);
+        bytes.push(0);
+        bytes.push(0);
+        bytes.extend_from_slice(&self.trace_id);
+        bytes.push(1);
+        bytes.extend_from_slice(&self.span_id);
+        bytes.push(2);
+        bytes.push(self.sampled as u8);
+
+        HeaderValue::from_str(&base64::encode(&bytes))
+            .expect("base64 is a valid header value")
+    }
+
+    fn traceparent_value(&self) -> HeaderValue {
+        let value = format!("00-{}-{}-{:02x}",
+                            encode_hex(&self.trace_id),
+                            encode_hex(&self.span_id),
+                            self.sampled as u8);
+
+        HeaderValue::from_str(&value)
+            .expect("hex is a valid header value")
+    }
+}
+
+impl fmt::Debug for TraceContext {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("TraceContext")
+            .field("trace_id", &encode_hex(&self.trace_id))
+            .field("span_id", &encode_hex(&self.span_id))
+            .field("sampled", &self.sampled)
+            .finish()
+    }
+}
+
+// ===== impl Format =====
+
+impl Default for Format {
+    fn default() -> Self {
+        Format::Both
+    }
+}
+
+// ===== impl ExtractContext =====
+
+impl<S> ExtractContext<S> {
+    pub fn new(inner: S) -> Self {
+        ExtractContext { inner }
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner service.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+}
+
+impl<S, A> Service for ExtractContext<S>
+where S: Service<Request = http::Request<A>>,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, mut request: Self::Request) -> Self::Future {
+        if let Some(context) = TraceContext::from_headers(request.headers()) {
+            request.extensions_mut().insert(context);
+        }
+
+        self.inner.call(request)
+    }
+}
+
+impl<S, A> NewService for ExtractContext<S>
+where S: NewService<Request = http::Request<A>>,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Service = ExtractContext<S::Service>;
+    type InitError = S::InitError;
+    type Future = NewServiceFuture<S::Future>;
+
+    fn new_service(&self) -> Self::Future {
+        NewServiceFuture {
+            inner: self.inner.new_service(),
+        }
+    }
+}
+
+// ===== impl NewServiceFuture =====
+
+impl<F> Future for NewServiceFuture<F>
+where F: Future,
+{
+    type Item = ExtractContext<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        Ok(Async::Ready(ExtractContext::new(inner)))
+    }
+}
+
+// ===== impl InjectContext =====
+
+impl<S> InjectContext<S> {
+    /// Write trace contexts to both headers.
+    pub fn new(inner: S) -> Self {
+        InjectContext::with_format(inner, Format::default())
+    }
+
+    /// Write trace contexts to the headers of `format`.
+    pub fn with_format(inner: S, format: Format) -> Self {
+        InjectContext { inner, format }
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner service.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+}
+
+impl<S, A> Service for InjectContext<S>
+where S: Service<Request = http::Request<A>>,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, mut request: Self::Request) -> Self::Future {
+        let context = request.extensions().get::<TraceContext>().cloned();
+
+        if let Some(context) = context {
+            context.to_headers(request.headers_mut(), self.format);
+        }
+
+        self.inner.call(request)
+    }
+}
+
+// ===== utility fns =====
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Decode lower case hex into `out`, which must be filled exactly.
+fn decode_hex(value: &str, out: &mut [u8]) -> bool {
+    if value.len() != out.len() * 2 {
+        return false;
+    }
+
+    let digit = |c: u8| match c {
+        b'0'...b'9' => Some(c - b'0'),
+        b'a'...b'f' => Some(c - b'a' + 10),
+        _ => None,
+    };
+
+    for (byte, pair) in out.iter_mut().zip(value.as_bytes().chunks(2)) {
+        match (digit(pair[0]), digit(pair[1])) {
+            (Some(hi), Some(lo)) => *byte = hi << 4 | lo,
+            _ => return false,
+        }
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRACE_ID: [u8; 16] = [
+        0x4b, 0xf9, 0x2f, 0x35, 0x77, 0xb3, 0x4d, 0xa6,
+        0xa3, 0xce, 0x92, 0x9d, 0x0e, 0x0e, 0x47, 0x36,
+    ];
+    const SPAN_ID: [u8; 8] = [0x00, 0xf0, 0x67, 0xaa, 0x0b, 0xa9, 0x02, 0xb7];
+
+    fn headers(name: &'static str, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn traceparent_is_parsed() {
+        let headers = headers(TRACEPARENT, "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01");
+        let context = TraceContext::from_headers(&headers).unwrap();
+
+        assert_eq!(context, TraceContext::new(TRACE_ID, SPAN_ID, true));
+    }
+
+    #[test]
+    fn traceparent_of_later_versions_may_append_fields() {
+        let value = "cc-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00-extra";
+        let context = TraceContext::from_traceparent(value).unwrap();
+
+        assert_eq!(context, TraceContext::new(TRACE_ID, SPAN_ID, false));
+    }
+
+    #[test]
+    fn invalid_traceparent_is_ignored() {
+        let invalid = [
+            // Version 00 has exactly four fields.
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            // Version ff is invalid.
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            // Ids are lower case hex.
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            // All zero ids are invalid.
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            // Fields have fixed lengths.
+            "00-4bf92f3577b34da6a3ce929d0e0e473-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-1",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+        ];
+
+        for value in &invalid {
+            assert_eq!(TraceContext::from_traceparent(value), None, "{}", value);
+        }
+    }
+
+    #[test]
+    fn grpc_trace_bin_round_trips() {
+        let context = TraceContext::new(TRACE_ID, SPAN_ID, true);
+
+        let mut headers = HeaderMap::new();
+        context.to_headers(&mut headers, Format::GrpcTraceBin);
+
+        assert!(headers.get(TRACEPARENT).is_none());
+        assert_eq!(TraceContext::from_headers(&headers), Some(context));
+    }
+
+    #[test]
+    fn grpc_trace_bin_without_span_id_is_ignored() {
+        let mut bytes = vec![0, 0];
+        bytes.extend_from_slice(&TRACE_ID);
+        bytes.extend_from_slice(&[2, 1]);
+
+        assert_eq!(TraceContext::from_grpc_trace_bin(&bytes), None);
+    }
+
+    #[test]
+    fn grpc_trace_bin_stops_at_unknown_fields() {
+        let mut bytes = vec![0, 0];
+        bytes.extend_from_slice(&TRACE_ID);
+        bytes.push(1);
+        bytes.extend_from_slice(&SPAN_ID);
+        bytes.extend_from_slice(&[9, 2, 1]);
+
+        let context = TraceContext::from_grpc_trace_bin(&bytes).unwrap();
+        assert_eq!(context, TraceContext::new(TRACE_ID, SPAN_ID, false));
+    }
+
+    #[test]
+    fn grpc_trace_bin_preferred_over_traceparent() {
+        let bin = TraceContext::new(TRACE_ID, SPAN_ID, true);
+        let parent = TraceContext::new([1; 16], [2; 8], false);
+
+        let mut headers = HeaderMap::new();
+        bin.to_headers(&mut headers, Format::GrpcTraceBin);
+        parent.to_headers(&mut headers, Format::TraceParent);
+
+        assert_eq!(TraceContext::from_headers(&headers), Some(bin));
+
+        // An invalid grpc-trace-bin falls back to traceparent.
+        headers.insert(GRPC_TRACE_BIN, HeaderValue::from_static("AAE"));
+        assert_eq!(TraceContext::from_headers(&headers), Some(parent));
+    }
+
+    #[test]
+    fn child_continues_the_trace() {
+        let context = TraceContext::new(TRACE_ID, SPAN_ID, true);
+        let child = context.child();
+
+        assert_eq!(child.trace_id(), TRACE_ID);
+        assert!(child.is_sampled());
+    }
+}
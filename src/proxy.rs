@@ -0,0 +1,269 @@
+//! Path-based selection of upstreams, for reverse proxies.
+//!
+//! `Upstreams` forwards each call to the upstream registered for the
+//! longest prefix of its path, typically a `/pkg.Service/` prefix, so a
+//! reverse proxy is assembled from a server, the clients of its upstreams,
+//! and optionally a gateway in front:
+//!
+//! ```ignore
+//! let upstreams = Upstreams::new()
+//!     .route("/users.Users/", users_channel)
+//!     .route("/orders.", orders_channel)
+//!     .fallback(default_channel);
+//!
+//! // gRPC clients, over HTTP/2.0.
+//! let h2 = Server::new(upstreams.clone(), Default::default(), reactor);
+//!
+//! // Connect and gRPC clients, over hyper.
+//! let conn = core.run(inprocess::connect(upstreams, &handle))?;
+//! let connect = ConnectServer::new(conn, origin);
+//! ```
+//!
+//! Calls matching no upstream fail with `UNIMPLEMENTED`.
+
+use Status;
+
+use bytes::Bytes;
+use futures::{future, Future, Poll, Async};
+use h2;
+use http::{self, HeaderMap};
+use tower::{NewService, Service};
+use tower_h2::{Body, BoxBody, HttpService};
+
+use std::fmt;
+
+/// Forwards calls to the upstream selected by their path.
+#[derive(Debug, Clone)]
+pub struct Upstreams<S> {
+    /// The upstreams, by path prefix.
+    routes: Vec<(String, S)>,
+
+    /// The upstream of calls matching no prefix.
+    fallback: Option<S>,
+}
+
+/// The response future returned by `Upstreams`.
+pub struct ResponseFuture<F> {
+    kind: Kind<F>,
+}
+
+/// The response body returned by `Upstreams`.
+pub struct ResponseBody<B> {
+    kind: Kind<B>,
+}
+
+#[derive(Debug)]
+enum Kind<T> {
+    /// The call was forwarded.
+    Upstream(T),
+
+    /// No upstream serves the call.
+    Unimplemented,
+}
+
+/// A request body forwarded to an upstream.
+struct Forward<B> {
+    inner: B,
+}
+
+// ===== impl Upstreams =====
+
+impl<S> Upstreams<S> {
+    /// Create a selector without upstreams.
+    pub fn new() -> Self {
+        Upstreams {
+            routes: vec![],
+            fallback: None,
+        }
+    }
+
+    /// Forward calls whose path starts with `prefix` to `upstream`.
+    ///
+    /// Calls matching several prefixes go to the upstream of the longest.
+    pub fn route(mut self, prefix: &str, upstream: S) -> Self {
+        self.routes.push((prefix.to_string(), upstream));
+        self
+    }
+
+    /// Forward calls matching no prefix to `upstream`.
+    pub fn fallback(mut self, upstream: S) -> Self {
+        self.fallback = Some(upstream);
+        self
+    }
+
+    /// Returns the upstream of calls to `path`.
+    pub fn select(&mut self, path: &str) -> Option<&mut S> {
+        let i = self.routes.iter()
+            .enumerate()
+            .filter(|&(_, &(ref prefix, _))| path.starts_with(&prefix[..]))
+            .max_by_key(|&(_, &(ref prefix, _))| prefix.len())
+            .map(|(i, _)| i);
+
+        match i {
+            Some(i) => Some(&mut self.routes[i].1),
+            None => self.fallback.as_mut(),
+        }
+    }
+}
+
+impl<S> Default for Upstreams<S> {
+    fn default() -> Self {
+        Upstreams::new()
+    }
+}
+
+impl<S, B> Service for Upstreams<S>
+where S: HttpService<RequestBody = BoxBody>,
+      B: Body + Send + 'static,
+      B::Data: Into<Bytes>,
+{
+    type Request = http::Request<B>;
+    type Response = http::Response<ResponseBody<S::ResponseBody>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    /// Ready once every upstream is ready, as the next call may be forwarded
+    /// to any of them.
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        let mut ready = true;
+
+        for upstream in self.routes.iter_mut().map(|r| &mut r.1).chain(self.fallback.as_mut()) {
+            ready &= upstream.poll_ready()?.is_ready();
+        }
+
+        if ready {
+            Ok(Async::Ready(()))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+
+    fn call(&mut self, request: Self::Request) -> Self::Future {
+        let kind = match self.select(request.uri().path()) {
+            Some(upstream) => {
+                let (head, body) = request.into_parts();
+                let body = BoxBody::new(Box::new(Forward { inner: body }));
+
+                Kind::Upstream(upstream.call(http::Request::from_parts(head, body)))
+            }
+            None => {
+                debug!("no upstream for call; path={}", request.uri().path());
+                Kind::Unimplemented
+            }
+        };
+
+        ResponseFuture { kind }
+    }
+}
+
+impl<S, B> NewService for Upstreams<S>
+where S: HttpService<RequestBody = BoxBody> + Clone,
+      B: Body + Send + 'static,
+      B::Data: Into<Bytes>,
+{
+    type Request = http::Request<B>;
+    type Response = http::Response<ResponseBody<S::ResponseBody>>;
+    type Error = S::Error;
+    type Service = Self;
+    type InitError = S::Error;
+    type Future = future::FutureResult<Self::Service, Self::InitError>;
+
+    fn new_service(&self) -> Self::Future {
+        future::ok(self.clone())
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F, B> Future for ResponseFuture<F>
+where F: Future<Item = http::Response<B>>,
+{
+    type Item = http::Response<ResponseBody<B>>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.kind {
+            Kind::Upstream(ref mut inner) => {
+                let (head, body) = try_ready!(inner.poll()).into_parts();
+                let body = ResponseBody { kind: Kind::Upstream(body) };
+                Ok(Async::Ready(http::Response::from_parts(head, body)))
+            }
+            Kind::Unimplemented => {
+                let body = ResponseBody { kind: Kind::Unimplemented };
+                Ok(Async::Ready(::Response::trailers_only(&Status::UNIMPLEMENTED, body).into_http()))
+            }
+        }
+    }
+}
+
+impl<F> fmt::Debug for ResponseFuture<F>
+where F: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("proxy::ResponseFuture")
+            .field("kind", &self.kind)
+            .finish()
+    }
+}
+
+// ===== impl ResponseBody =====
+
+impl<B> Body for ResponseBody<B>
+where B: Body,
+{
+    type Data = B::Data;
+
+    fn is_end_stream(&self) -> bool {
+        match self.kind {
+            Kind::Upstream(ref body) => body.is_end_stream(),
+            Kind::Unimplemented => true,
+        }
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, h2::Error> {
+        match self.kind {
+            Kind::Upstream(ref mut body) => body.poll_data(),
+            Kind::Unimplemented => Ok(Async::Ready(None)),
+        }
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, h2::Error> {
+        match self.kind {
+            Kind::Upstream(ref mut body) => body.poll_trailers(),
+            // The status was sent in the headers.
+            Kind::Unimplemented => Ok(Async::Ready(None)),
+        }
+    }
+}
+
+impl<B> fmt::Debug for ResponseBody<B>
+where B: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("proxy::ResponseBody")
+            .field("kind", &self.kind)
+            .finish()
+    }
+}
+
+// ===== impl Forward =====
+
+impl<B> Body for Forward<B>
+where B: Body,
+      B::Data: Into<Bytes>,
+{
+    type Data = Bytes;
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Bytes>, h2::Error> {
+        let data = try_ready!(self.inner.poll_data());
+        Ok(data.map(Into::into).into())
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, h2::Error> {
+        self.inner.poll_trailers()
+    }
+}
@@ -0,0 +1,27 @@
+//! Redaction of sensitive metadata in logs.
+
+use http::header::{HeaderName, HeaderValue};
+
+/// Logged in place of redacted metadata values.
+pub(crate) const REDACTED: &'static str = "<redacted>";
+
+/// Returns true if the value of `key` must not be logged.
+///
+/// Values marked with `HeaderValue::set_sensitive`, such as those added by
+/// `auth::StaticMetadata::insert_sensitive`, are always redacted, as are
+/// credentials and the values of `keys`.
+pub(crate) fn is_redacted(keys: &[HeaderName], key: &HeaderName, value: &HeaderValue) -> bool {
+    value.is_sensitive() || is_credential(key) || keys.contains(key)
+}
+
+/// Returns true if `key` conventionally carries a credential.
+///
+/// Values received from peers are never marked sensitive, so these are
+/// recognized by name: `authorization`, `proxy-authorization`, cookies, and
+/// keys ending in `-token` or `-key`.
+fn is_credential(key: &HeaderName) -> bool {
+    match key.as_str() {
+        "authorization" | "proxy-authorization" | "cookie" | "set-cookie" => true,
+        key => key.ends_with("-token") || key.ends_with("-key"),
+    }
+}
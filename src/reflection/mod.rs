@@ -0,0 +1,176 @@
+//! The `grpc.reflection` server reflection protocol.
+//!
+//! `ServerReflection` answers reflection requests from the file descriptor
+//! sets it is given, so that tools such as `grpcurl` can discover and call
+//! a server's services. Descriptor sets are usually written by
+//! `tower-grpc-build` and embedded with `include_bytes!`. Both the `v1` and
+//! `v1alpha` versions of the protocol are served.
+//!
+//! See the [server reflection protocol][spec] for details.
+//!
+//! [spec]: https://github.com/grpc/grpc/blob/master/doc/server-reflection.md
+
+#![allow(missing_docs)]
+
+mod server;
+
+pub use self::server::{ServerReflection, DecodeError, ResponseFuture, ResponseBody};
+
+/// Path of the `v1` `ServerReflectionInfo` method.
+pub const V1_PATH: &'static str =
+    "/grpc.reflection.v1.ServerReflection/ServerReflectionInfo";
+
+/// Path of the `v1alpha` `ServerReflectionInfo` method.
+pub const V1ALPHA_PATH: &'static str =
+    "/grpc.reflection.v1alpha.ServerReflection/ServerReflectionInfo";
+
+// ===== Protocol =====
+
+// The protocol's `message_request` and `message_response` oneofs are
+// declared as optional fields, which have the same encoding.
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ServerReflectionRequest {
+    #[prost(string, tag="1")]
+    pub host: String,
+    #[prost(string, optional, tag="3")]
+    pub file_by_filename: Option<String>,
+    #[prost(string, optional, tag="4")]
+    pub file_containing_symbol: Option<String>,
+    #[prost(message, optional, tag="5")]
+    pub file_containing_extension: Option<ExtensionRequest>,
+    #[prost(string, optional, tag="6")]
+    pub all_extension_numbers_of_type: Option<String>,
+    #[prost(string, optional, tag="7")]
+    pub list_services: Option<String>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ExtensionRequest {
+    #[prost(string, tag="1")]
+    pub containing_type: String,
+    #[prost(int32, tag="2")]
+    pub extension_number: i32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ServerReflectionResponse {
+    #[prost(string, tag="1")]
+    pub valid_host: String,
+    #[prost(message, optional, tag="2")]
+    pub original_request: Option<ServerReflectionRequest>,
+    #[prost(message, optional, tag="4")]
+    pub file_descriptor_response: Option<FileDescriptorResponse>,
+    #[prost(message, optional, tag="5")]
+    pub all_extension_numbers_response: Option<ExtensionNumberResponse>,
+    #[prost(message, optional, tag="6")]
+    pub list_services_response: Option<ListServiceResponse>,
+    #[prost(message, optional, tag="7")]
+    pub error_response: Option<ErrorResponse>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct FileDescriptorResponse {
+    #[prost(bytes, repeated, tag="1")]
+    pub file_descriptor_proto: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ExtensionNumberResponse {
+    #[prost(string, tag="1")]
+    pub base_type_name: String,
+    #[prost(int32, repeated, tag="2")]
+    pub extension_number: Vec<i32>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ListServiceResponse {
+    #[prost(message, repeated, tag="1")]
+    pub service: Vec<ServiceResponse>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ServiceResponse {
+    #[prost(string, tag="1")]
+    pub name: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ErrorResponse {
+    #[prost(int32, tag="1")]
+    pub error_code: i32,
+    #[prost(string, tag="2")]
+    pub error_message: String,
+}
+
+// ===== Descriptors =====
+
+// Only the parts of `google/protobuf/descriptor.proto` needed to index
+// symbols are declared. Files are kept in their encoded form, so nothing is
+// lost when they are sent to clients.
+
+#[derive(Clone, PartialEq, Message)]
+struct FileDescriptorSet {
+    #[prost(bytes, repeated, tag="1")]
+    file: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct FileDescriptorProto {
+    #[prost(string, tag="1")]
+    name: String,
+    #[prost(string, tag="2")]
+    package: String,
+    #[prost(string, repeated, tag="3")]
+    dependency: Vec<String>,
+    #[prost(message, repeated, tag="4")]
+    message_type: Vec<DescriptorProto>,
+    #[prost(message, repeated, tag="5")]
+    enum_type: Vec<EnumDescriptorProto>,
+    #[prost(message, repeated, tag="6")]
+    service: Vec<ServiceDescriptorProto>,
+    #[prost(message, repeated, tag="7")]
+    extension: Vec<FieldDescriptorProto>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct DescriptorProto {
+    #[prost(string, tag="1")]
+    name: String,
+    #[prost(message, repeated, tag="3")]
+    nested_type: Vec<DescriptorProto>,
+    #[prost(message, repeated, tag="4")]
+    enum_type: Vec<EnumDescriptorProto>,
+    #[prost(message, repeated, tag="6")]
+    extension: Vec<FieldDescriptorProto>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct FieldDescriptorProto {
+    #[prost(string, tag="1")]
+    name: String,
+    #[prost(string, tag="2")]
+    extendee: String,
+    #[prost(int32, tag="3")]
+    number: i32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct EnumDescriptorProto {
+    #[prost(string, tag="1")]
+    name: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct ServiceDescriptorProto {
+    #[prost(string, tag="1")]
+    name: String,
+    #[prost(message, repeated, tag="2")]
+    method: Vec<MethodDescriptorProto>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct MethodDescriptorProto {
+    #[prost(string, tag="1")]
+    name: String,
+}
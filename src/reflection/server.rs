@@ -0,0 +1,445 @@
+use super::{
+    V1_PATH, V1ALPHA_PATH,
+    ServerReflectionRequest, ServerReflectionResponse,
+    FileDescriptorResponse, ExtensionNumberResponse, ListServiceResponse,
+    ServiceResponse, ErrorResponse,
+    FileDescriptorSet, FileDescriptorProto, DescriptorProto, FieldDescriptorProto,
+};
+use codec::{Encode, Streaming};
+use server::{streaming, Grpc};
+use {Code, Request, Response, Status};
+
+use bytes::Bytes;
+use futures::{future, Async, Future, Poll, Stream};
+use h2;
+use http;
+use prost::Message;
+use tower::{NewService, ReadyService, Service};
+use tower_h2::{Body, RecvBody};
+
+use std::collections::{HashMap, HashSet};
+use std::{error, fmt};
+use std::sync::Arc;
+
+/// Serves the `grpc.reflection` `ServerReflection` service.
+#[derive(Debug, Clone, Default)]
+pub struct ServerReflection {
+    index: Arc<Index>,
+}
+
+/// Error returned when a file descriptor set can't be decoded.
+#[derive(Debug)]
+pub struct DecodeError {
+    _p: (),
+}
+
+/// The response future returned by `ServerReflection`.
+pub struct ResponseFuture {
+    kind: Result<streaming::ResponseFuture<Info>, Status>,
+}
+
+/// The response body returned by `ServerReflection`.
+pub struct ResponseBody {
+    kind: Result<Encode<Responses>, Status>,
+}
+
+/// The files, symbols and extensions known to the service.
+#[derive(Debug, Clone, Default)]
+struct Index {
+    /// Encoded files, keyed by file name.
+    files: HashMap<String, File>,
+
+    /// Names of the files defining each fully qualified symbol.
+    symbols: HashMap<String, String>,
+
+    /// Names of the files defining each extension, keyed by the extended
+    /// type and field number.
+    extensions: HashMap<(String, i32), String>,
+
+    /// Fully qualified service names, in registration order.
+    services: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+struct File {
+    encoded: Vec<u8>,
+    dependencies: Vec<String>,
+}
+
+/// Handles `ServerReflectionInfo` calls.
+#[derive(Debug, Clone)]
+struct Info(Arc<Index>);
+
+/// Answers each request of a `ServerReflectionInfo` call.
+#[derive(Debug)]
+struct Responses {
+    requests: Streaming<ServerReflectionRequest>,
+    index: Arc<Index>,
+}
+
+// ===== impl ServerReflection =====
+
+impl ServerReflection {
+    /// Create a reflection service that knows no services.
+    pub fn new() -> Self {
+        ServerReflection::default()
+    }
+
+    /// Add the files of an encoded `FileDescriptorSet`.
+    ///
+    /// The set should include the imports of its files, so that clients can
+    /// resolve every type they reference.
+    pub fn register_file_descriptor_set(mut self, encoded: &[u8]) -> Result<Self, DecodeError> {
+        let set = FileDescriptorSet::decode(encoded)
+            .map_err(|_| DecodeError::new())?;
+
+        {
+            let index = Arc::make_mut(&mut self.index);
+
+            for file in set.file {
+                index.add(file)?;
+            }
+        }
+
+        Ok(self)
+    }
+}
+
+impl Service for ServerReflection {
+    type Request = http::Request<RecvBody>;
+    type Response = http::Response<ResponseBody>;
+    type Error = h2::Error;
+    type Future = ResponseFuture;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        Ok(().into())
+    }
+
+    fn call(&mut self, request: Self::Request) -> Self::Future {
+        let kind = match request.uri().path() {
+            V1_PATH | V1ALPHA_PATH => {
+                let mut service = Info(self.index.clone());
+                Ok(Grpc::streaming(&mut service, request))
+            }
+            _ => Err(Status::UNIMPLEMENTED),
+        };
+
+        ResponseFuture { kind }
+    }
+}
+
+impl NewService for ServerReflection {
+    type Request = http::Request<RecvBody>;
+    type Response = http::Response<ResponseBody>;
+    type Error = h2::Error;
+    type Service = Self;
+    type InitError = h2::Error;
+    type Future = future::FutureResult<Self::Service, Self::InitError>;
+
+    fn new_service(&self) -> Self::Future {
+        future::ok(self.clone())
+    }
+}
+
+// ===== impl Index =====
+
+impl Index {
+    /// Index an encoded `FileDescriptorProto`.
+    fn add(&mut self, encoded: Vec<u8>) -> Result<(), DecodeError> {
+        let file = FileDescriptorProto::decode(&encoded[..])
+            .map_err(|_| DecodeError::new())?;
+
+        if self.files.contains_key(&file.name) {
+            return Ok(());
+        }
+
+        let name = file.name.clone();
+        let package = file.package.clone();
+
+        for message in &file.message_type {
+            self.add_message(&name, &package, message);
+        }
+
+        for enumeration in &file.enum_type {
+            self.add_symbol(&name, &package, &enumeration.name);
+        }
+
+        for extension in &file.extension {
+            self.add_extension(&name, &package, extension);
+        }
+
+        for service in &file.service {
+            let service_name = qualify(&package, &service.name);
+            self.services.push(service_name.clone());
+            self.add_symbol(&name, &package, &service.name);
+
+            for method in &service.method {
+                self.add_symbol(&name, &service_name, &method.name);
+            }
+        }
+
+        self.files.insert(name, File {
+            encoded,
+            dependencies: file.dependency,
+        });
+
+        Ok(())
+    }
+
+    fn add_message(&mut self, file: &str, scope: &str, message: &DescriptorProto) {
+        let name = qualify(scope, &message.name);
+
+        for nested in &message.nested_type {
+            self.add_message(file, &name, nested);
+        }
+
+        for enumeration in &message.enum_type {
+            self.add_symbol(file, &name, &enumeration.name);
+        }
+
+        for extension in &message.extension {
+            self.add_extension(file, &name, extension);
+        }
+
+        self.symbols.insert(name, file.to_string());
+    }
+
+    fn add_extension(&mut self, file: &str, scope: &str, extension: &FieldDescriptorProto) {
+        self.add_symbol(file, scope, &extension.name);
+
+        let extendee = extension.extendee.trim_start_matches('.').to_string();
+        self.extensions.insert((extendee, extension.number), file.to_string());
+    }
+
+    fn add_symbol(&mut self, file: &str, scope: &str, name: &str) {
+        self.symbols.insert(qualify(scope, name), file.to_string());
+    }
+
+    /// Returns the encoded file named `name`, followed by its transitive
+    /// dependencies.
+    fn file_with_dependencies(&self, name: &str) -> Option<Vec<Vec<u8>>> {
+        if !self.files.contains_key(name) {
+            return None;
+        }
+
+        let mut seen = HashSet::new();
+        let mut pending = vec![name.to_string()];
+        let mut files = vec![];
+
+        while let Some(name) = pending.pop() {
+            if !seen.insert(name.clone()) {
+                continue;
+            }
+
+            // Dependencies that weren't registered are left for the client
+            // to request, and fail then.
+            if let Some(file) = self.files.get(&name) {
+                files.push(file.encoded.clone());
+                pending.extend(file.dependencies.iter().cloned());
+            }
+        }
+
+        Some(files)
+    }
+
+    /// Returns the response to `request`.
+    fn respond(&self, request: ServerReflectionRequest) -> ServerReflectionResponse {
+        let mut response = ServerReflectionResponse {
+            valid_host: request.host.clone(),
+            original_request: None,
+            file_descriptor_response: None,
+            all_extension_numbers_response: None,
+            list_services_response: None,
+            error_response: None,
+        };
+
+        let found = |files: Option<Vec<Vec<u8>>>, what: &str| {
+            files.map(|file_descriptor_proto| FileDescriptorResponse { file_descriptor_proto })
+                .ok_or_else(|| (Code::NOT_FOUND, format!("{} not found", what)))
+        };
+
+        let result = if let Some(ref name) = request.file_by_filename {
+            found(self.file_with_dependencies(name), name)
+                .map(|files| response.file_descriptor_response = Some(files))
+        } else if let Some(ref symbol) = request.file_containing_symbol {
+            let file = self.symbols.get(symbol.trim_start_matches('.'))
+                .and_then(|file| self.file_with_dependencies(file));
+            found(file, symbol)
+                .map(|files| response.file_descriptor_response = Some(files))
+        } else if let Some(ref extension) = request.file_containing_extension {
+            let key = (
+                extension.containing_type.trim_start_matches('.').to_string(),
+                extension.extension_number,
+            );
+            let file = self.extensions.get(&key)
+                .and_then(|file| self.file_with_dependencies(file));
+            found(file, &format!("extension {} of {}", key.1, key.0))
+                .map(|files| response.file_descriptor_response = Some(files))
+        } else if let Some(ref base_type_name) = request.all_extension_numbers_of_type {
+            let base_type = base_type_name.trim_start_matches('.');
+
+            if self.symbols.contains_key(base_type) {
+                let mut extension_number: Vec<i32> = self.extensions.keys()
+                    .filter(|&&(ref extendee, _)| extendee == base_type)
+                    .map(|&(_, number)| number)
+                    .collect();
+                extension_number.sort();
+
+                response.all_extension_numbers_response = Some(ExtensionNumberResponse {
+                    base_type_name: base_type_name.clone(),
+                    extension_number,
+                });
+                Ok(())
+            } else {
+                Err((Code::NOT_FOUND, format!("{} not found", base_type_name)))
+            }
+        } else if request.list_services.is_some() {
+            let service = self.services.iter()
+                .map(|name| ServiceResponse { name: name.clone() })
+                .collect();
+
+            response.list_services_response = Some(ListServiceResponse { service });
+            Ok(())
+        } else {
+            Err((Code::UNIMPLEMENTED, "unsupported request".to_string()))
+        };
+
+        if let Err((code, error_message)) = result {
+            trace!("reflection request failed; error={}", error_message);
+
+            response.error_response = Some(ErrorResponse {
+                error_code: code.as_i32(),
+                error_message,
+            });
+        }
+
+        response.original_request = Some(request);
+        response
+    }
+}
+
+// ===== impl DecodeError =====
+
+impl DecodeError {
+    fn new() -> Self {
+        DecodeError { _p: () }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str("invalid file descriptor set")
+    }
+}
+
+impl error::Error for DecodeError {
+    fn description(&self) -> &str {
+        "invalid file descriptor set"
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl Future for ResponseFuture {
+    type Item = http::Response<ResponseBody>;
+    type Error = h2::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.kind {
+            Ok(ref mut fut) => {
+                let (head, body) = try_ready!(fut.poll()).into_parts();
+                let body = ResponseBody { kind: Ok(body) };
+                Ok(http::Response::from_parts(head, body).into())
+            }
+            Err(ref status) => {
+                let body = ResponseBody { kind: Err(status.clone()) };
+                Ok(Response::trailers_only(status, body).into_http().into())
+            }
+        }
+    }
+}
+
+impl fmt::Debug for ResponseFuture {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("reflection::ResponseFuture")
+            .field("unimplemented", &self.kind.is_err())
+            .finish()
+    }
+}
+
+// ===== impl ResponseBody =====
+
+impl Body for ResponseBody {
+    type Data = Bytes;
+
+    fn is_end_stream(&self) -> bool {
+        match self.kind {
+            Ok(ref body) => body.is_end_stream(),
+            Err(_) => true,
+        }
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Bytes>, h2::Error> {
+        match self.kind {
+            Ok(ref mut body) => body.poll_data(),
+            Err(_) => Ok(None.into()),
+        }
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, h2::Error> {
+        match self.kind {
+            Ok(ref mut body) => body.poll_trailers(),
+            // The status was sent in the headers.
+            Err(_) => Ok(None.into()),
+        }
+    }
+}
+
+impl fmt::Debug for ResponseBody {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("reflection::ResponseBody")
+            .field("unimplemented", &self.kind.is_err())
+            .finish()
+    }
+}
+
+// ===== impl Info =====
+
+impl ReadyService for Info {
+    type Request = Request<Streaming<ServerReflectionRequest>>;
+    type Response = Response<Responses>;
+    type Error = ::Error;
+    type Future = future::FutureResult<Self::Response, Self::Error>;
+
+    fn call(&mut self, request: Self::Request) -> Self::Future {
+        future::ok(Response::new(Responses {
+            requests: request.into_inner(),
+            index: self.0.clone(),
+        }))
+    }
+}
+
+// ===== impl Responses =====
+
+impl Stream for Responses {
+    type Item = ServerReflectionResponse;
+    type Error = ::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        match try_ready!(self.requests.poll()) {
+            Some(request) => Ok(Async::Ready(Some(self.index.respond(request)))),
+            None => Ok(Async::Ready(None)),
+        }
+    }
+}
+
+// ===== utility fns =====
+
+/// Returns the fully qualified name of `name` within `scope`.
+fn qualify(scope: &str, name: &str) -> String {
+    if scope.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", scope, name)
+    }
+}
@@ -1,8 +1,14 @@
+use headers;
+use timeout;
+
 use http;
 
+use std::time::Duration;
+
 #[derive(Debug)]
 pub struct Request<T> {
     headers: http::HeaderMap,
+    extensions: http::Extensions,
     message: T,
 }
 
@@ -11,10 +17,16 @@ impl<T> Request<T> {
     pub fn new(message: T) -> Self {
         Request {
             headers: http::HeaderMap::new(),
+            extensions: http::Extensions::new(),
             message,
         }
     }
 
+    /// Returns the timeout the client set with `grpc-timeout`.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.headers.get(&*headers::GRPC_TIMEOUT).and_then(timeout::decode)
+    }
+
     /// Get a reference to the message
     pub fn get_ref(&self) -> &T {
         &self.message
@@ -35,6 +47,16 @@ impl<T> Request<T> {
         &mut self.headers
     }
 
+    /// Get a reference to the request extensions.
+    pub fn extensions(&self) -> &http::Extensions {
+        &self.extensions
+    }
+
+    /// Get a mutable reference to the request extensions.
+    pub fn extensions_mut(&mut self) -> &mut http::Extensions {
+        &mut self.extensions
+    }
+
     /// Consumes `self`, returning the message
     pub fn into_inner(self) -> T {
         self.message
@@ -45,6 +67,7 @@ impl<T> Request<T> {
         let (head, message) = http.into_parts();
         Request {
             headers: head.headers,
+            extensions: head.extensions,
             message,
         }
     }
@@ -56,6 +79,7 @@ impl<T> Request<T> {
         *request.method_mut() = http::Method::POST;
         *request.uri_mut() = uri;
         *request.headers_mut() = self.headers;
+        *request.extensions_mut() = self.extensions;
 
         request
     }
@@ -67,6 +91,7 @@ impl<T> Request<T> {
 
         Request {
             headers: self.headers,
+            extensions: self.extensions,
             message,
         }
     }
@@ -1,3 +1,6 @@
+use Status;
+use headers;
+
 use http;
 
 #[derive(Debug)]
@@ -15,6 +18,16 @@ impl<T> Response<T> {
         }
     }
 
+    /// Returns a trailers-only response, which carries `status` in its
+    /// headers and has no messages.
+    ///
+    /// `body` must end the stream without trailers.
+    pub(crate) fn trailers_only(status: &Status, body: T) -> Self {
+        let mut res = Response::new(body);
+        res.headers_mut().insert(headers::GRPC_STATUS.clone(), status.to_header_value());
+        res
+    }
+
     /// Get a reference to the message
     pub fn get_ref(&self) -> &T {
         self.http.body()
@@ -25,6 +38,16 @@ impl<T> Response<T> {
         self.http.body_mut()
     }
 
+    /// Get a reference to the response headers.
+    pub fn headers(&self) -> &http::HeaderMap {
+        self.http.headers()
+    }
+
+    /// Get a mutable reference to the response headers.
+    pub fn headers_mut(&mut self) -> &mut http::HeaderMap {
+        self.http.headers_mut()
+    }
+
     /// Consumes `self`, returning the message
     pub fn into_inner(self) -> T {
         let (_, body) = self.http.into_parts();
@@ -49,7 +72,4 @@ impl<T> Response<T> {
         let http = http::Response::from_parts(head, body);
         Response::from_http(http)
     }
-
-    // pub fn metadata()
-    // pub fn metadata_bin()
 }
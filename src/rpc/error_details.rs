@@ -0,0 +1,166 @@
+//! The `google.rpc` error detail messages.
+
+use super::Duration;
+
+use prost::Message;
+
+/// An error detail message, which may be packed into a `Status`.
+pub trait ErrorDetail: Message + Default {
+    /// The type URL of the message when packed into an `Any`.
+    const TYPE_URL: &'static str;
+}
+
+/// Describes when the client may retry a failed request.
+#[derive(Clone, PartialEq, Message)]
+pub struct RetryInfo {
+    #[prost(message, optional, tag="1")]
+    pub retry_delay: Option<Duration>,
+}
+
+/// Describes additional debugging info.
+#[derive(Clone, PartialEq, Message)]
+pub struct DebugInfo {
+    #[prost(string, repeated, tag="1")]
+    pub stack_entries: Vec<String>,
+    #[prost(string, tag="2")]
+    pub detail: String,
+}
+
+/// Describes how a quota check failed.
+#[derive(Clone, PartialEq, Message)]
+pub struct QuotaFailure {
+    #[prost(message, repeated, tag="1")]
+    pub violations: Vec<quota_failure::Violation>,
+}
+
+pub mod quota_failure {
+    #[derive(Clone, PartialEq, Message)]
+    pub struct Violation {
+        #[prost(string, tag="1")]
+        pub subject: String,
+        #[prost(string, tag="2")]
+        pub description: String,
+    }
+}
+
+/// Describes the cause of the error with structured details.
+#[derive(Clone, PartialEq, Message)]
+pub struct ErrorInfo {
+    #[prost(string, tag="1")]
+    pub reason: String,
+    #[prost(string, tag="2")]
+    pub domain: String,
+    #[prost(map="string, string", tag="3")]
+    pub metadata: ::std::collections::HashMap<String, String>,
+}
+
+/// Describes what preconditions have failed.
+#[derive(Clone, PartialEq, Message)]
+pub struct PreconditionFailure {
+    #[prost(message, repeated, tag="1")]
+    pub violations: Vec<precondition_failure::Violation>,
+}
+
+pub mod precondition_failure {
+    #[derive(Clone, PartialEq, Message)]
+    pub struct Violation {
+        #[prost(string, tag="1")]
+        pub type_: String,
+        #[prost(string, tag="2")]
+        pub subject: String,
+        #[prost(string, tag="3")]
+        pub description: String,
+    }
+}
+
+/// Describes violations in a client request.
+#[derive(Clone, PartialEq, Message)]
+pub struct BadRequest {
+    #[prost(message, repeated, tag="1")]
+    pub field_violations: Vec<bad_request::FieldViolation>,
+}
+
+pub mod bad_request {
+    #[derive(Clone, PartialEq, Message)]
+    pub struct FieldViolation {
+        #[prost(string, tag="1")]
+        pub field: String,
+        #[prost(string, tag="2")]
+        pub description: String,
+    }
+}
+
+/// Contains metadata about the request that clients can attach when filing
+/// a bug or providing other forms of feedback.
+#[derive(Clone, PartialEq, Message)]
+pub struct RequestInfo {
+    #[prost(string, tag="1")]
+    pub request_id: String,
+    #[prost(string, tag="2")]
+    pub serving_data: String,
+}
+
+/// Describes the resource that is being accessed.
+#[derive(Clone, PartialEq, Message)]
+pub struct ResourceInfo {
+    #[prost(string, tag="1")]
+    pub resource_type: String,
+    #[prost(string, tag="2")]
+    pub resource_name: String,
+    #[prost(string, tag="3")]
+    pub owner: String,
+    #[prost(string, tag="4")]
+    pub description: String,
+}
+
+/// Provides links to documentation or for performing an out of band action.
+#[derive(Clone, PartialEq, Message)]
+pub struct Help {
+    #[prost(message, repeated, tag="1")]
+    pub links: Vec<help::Link>,
+}
+
+pub mod help {
+    #[derive(Clone, PartialEq, Message)]
+    pub struct Link {
+        #[prost(string, tag="1")]
+        pub description: String,
+        #[prost(string, tag="2")]
+        pub url: String,
+    }
+}
+
+/// Provides a localized error message that is safe to return to the user.
+#[derive(Clone, PartialEq, Message)]
+pub struct LocalizedMessage {
+    #[prost(string, tag="1")]
+    pub locale: String,
+    #[prost(string, tag="2")]
+    pub message: String,
+}
+
+// ===== impl ErrorDetail =====
+
+macro_rules! error_details {
+    ($($ty:ident,)*) => {
+        $(
+            impl ErrorDetail for $ty {
+                const TYPE_URL: &'static str =
+                    concat!("type.googleapis.com/google.rpc.", stringify!($ty));
+            }
+        )*
+    }
+}
+
+error_details! {
+    RetryInfo,
+    DebugInfo,
+    QuotaFailure,
+    ErrorInfo,
+    PreconditionFailure,
+    BadRequest,
+    RequestInfo,
+    ResourceInfo,
+    Help,
+    LocalizedMessage,
+}
@@ -0,0 +1,185 @@
+//! The `google.rpc` status model.
+//!
+//! These are the `google.rpc.Status`, `google.rpc.Code` and error detail
+//! messages used by Google APIs and many other gRPC services to describe
+//! errors. Crates that exchange rich errors can share these types instead of
+//! each generating their own.
+//!
+//! Error details are carried as `Any` messages; `Any::pack` and
+//! `Any::unpack` convert them from and to the `ErrorDetail` messages in
+//! `error_details`. Messages are matched by the type name at the end of
+//! their type URL, so `type.googleapis.com/google.rpc.RetryInfo` and
+//! `example.com/google.rpc.RetryInfo` both name a `RetryInfo`.
+//!
+//! `tower_grpc::Status` only holds a status code, so converting it to an
+//! `rpc::Status` leaves the message and details empty, and converting back
+//! keeps only the code.
+
+#![allow(missing_docs)]
+
+pub mod error_details;
+
+pub use self::error_details::ErrorDetail;
+
+use prost::Message;
+
+/// The prefix of type URLs, followed by the fully qualified name of the
+/// packed message type.
+pub const TYPE_URL_PREFIX: &'static str = "type.googleapis.com/";
+
+/// The `google.rpc.Status` message.
+#[derive(Clone, PartialEq, Message)]
+pub struct Status {
+    /// The status code, which should be a `Code` value.
+    #[prost(int32, tag="1")]
+    pub code: i32,
+    /// A developer-facing error message, in English.
+    #[prost(string, tag="2")]
+    pub message: String,
+    /// Messages carrying the error details.
+    #[prost(message, repeated, tag="3")]
+    pub details: Vec<Any>,
+}
+
+/// The `google.rpc.Code` enum.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Enumeration)]
+pub enum Code {
+    Ok = 0,
+    Cancelled = 1,
+    Unknown = 2,
+    InvalidArgument = 3,
+    DeadlineExceeded = 4,
+    NotFound = 5,
+    AlreadyExists = 6,
+    PermissionDenied = 7,
+    Unauthenticated = 16,
+    ResourceExhausted = 8,
+    FailedPrecondition = 9,
+    Aborted = 10,
+    OutOfRange = 11,
+    Unimplemented = 12,
+    Internal = 13,
+    Unavailable = 14,
+    DataLoss = 15,
+}
+
+/// A `google.protobuf.Any`.
+#[derive(Clone, PartialEq, Message)]
+pub struct Any {
+    #[prost(string, tag="1")]
+    pub type_url: String,
+    #[prost(bytes, tag="2")]
+    pub value: Vec<u8>,
+}
+
+/// A `google.protobuf.Duration`.
+#[derive(Clone, PartialEq, Message)]
+pub struct Duration {
+    #[prost(int64, tag="1")]
+    pub seconds: i64,
+    #[prost(int32, tag="2")]
+    pub nanos: i32,
+}
+
+// ===== impl Status =====
+
+impl Status {
+    /// Returns the status code.
+    ///
+    /// Unrecognized values are reported as `Unknown`.
+    pub fn code(&self) -> Code {
+        Code::from_i32(self.code).unwrap_or(Code::Unknown)
+    }
+
+    /// Returns the details of type `T`.
+    ///
+    /// Details of other types, and those that fail to decode, are skipped.
+    pub fn details<T: ErrorDetail>(&self) -> Vec<T> {
+        self.details.iter()
+            .filter_map(|detail| detail.unpack())
+            .collect()
+    }
+}
+
+impl<'a> From<&'a ::Status> for Status {
+    fn from(status: &'a ::Status) -> Self {
+        Status {
+            code: status.code().as_i32(),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<::Status> for Status {
+    fn from(status: ::Status) -> Self {
+        Status::from(&status)
+    }
+}
+
+impl From<Status> for ::Status {
+    fn from(status: Status) -> Self {
+        ::Status::with_code(status.code().into())
+    }
+}
+
+// ===== impl Code =====
+
+impl From<::Code> for Code {
+    fn from(code: ::Code) -> Self {
+        Code::from_i32(code.as_i32()).unwrap_or(Code::Unknown)
+    }
+}
+
+impl From<Code> for ::Code {
+    fn from(code: Code) -> Self {
+        ::Code::from_i32(code as i32).unwrap_or(::Code::UNKNOWN)
+    }
+}
+
+// ===== impl Any =====
+
+impl Any {
+    /// Encode `detail` as an `Any`.
+    pub fn pack<T: ErrorDetail>(detail: &T) -> Self {
+        let mut value = Vec::with_capacity(detail.encoded_len());
+        detail.encode(&mut value).expect("buffer has enough capacity");
+
+        Any {
+            type_url: T::TYPE_URL.to_string(),
+            value,
+        }
+    }
+
+    /// Decode the message, if it is a `T`.
+    pub fn unpack<T: ErrorDetail>(&self) -> Option<T> {
+        if self.type_name() != type_name(T::TYPE_URL) {
+            return None;
+        }
+
+        T::decode(&self.value[..]).ok()
+    }
+
+    /// Returns the fully qualified name of the packed message type.
+    pub fn type_name(&self) -> &str {
+        type_name(&self.type_url)
+    }
+}
+
+// ===== utility fns =====
+
+/// Returns the fully qualified type name at the end of `type_url`, such as
+/// `google.rpc.RetryInfo` for `type.googleapis.com/google.rpc.RetryInfo`.
+///
+/// Type names are returned unchanged.
+pub fn type_name(type_url: &str) -> &str {
+    match type_url.rfind('/') {
+        Some(i) => &type_url[i + 1..],
+        None => type_url,
+    }
+}
+
+/// Returns the type URL of `type_name`, with the `type.googleapis.com/`
+/// prefix.
+pub fn type_url(type_name: &str) -> String {
+    format!("{}{}", TYPE_URL_PREFIX, type_name)
+}
@@ -0,0 +1,219 @@
+//! Execution of handlers within their connection's task.
+//!
+//! `tower_h2::Server` spawns the response future and body of each call on
+//! its executor, detached from the connection, so handlers keep running for
+//! a while after their connection is gone. A `Scope` is instead an executor
+//! whose tasks are driven by the `Scoped` connection future, within the
+//! connection's own task. Dropping the connection drops its calls, which
+//! makes cancellation deterministic, and handlers can never outlive their
+//! connection.
+//!
+//! Each connection is served by a server with a scope of its own:
+//!
+//! ```ignore
+//! let serve = listener.incoming().for_each(move |(sock, _)| {
+//!     let scope = Scope::new();
+//!     let h2 = Server::new(new_service.clone(), Default::default(), scope.clone());
+//!
+//!     handle.spawn(scope.run(h2.serve(sock)).map_err(|e| error!("h2 error: {:?}", e)));
+//!     Ok(())
+//! });
+//! ```
+//!
+//! The calls of a connection are polled one after another on the same
+//! thread, so a call that blocks stalls the others, and the connection
+//! itself. Scopes are not `Send`, and run on single-threaded executors such
+//! as a tokio-core reactor.
+
+use futures::{Future, Stream, Poll, Async};
+use futures::future::{Executor, ExecuteError, ExecuteErrorKind};
+use futures::stream::FuturesUnordered;
+
+use std::cell::RefCell;
+use std::fmt;
+use std::mem;
+use std::rc::Rc;
+
+/// An executor whose tasks run within the task of a `Scoped` future.
+#[derive(Clone)]
+pub struct Scope {
+    inner: Rc<RefCell<Inner>>,
+}
+
+/// A future driving the tasks of a `Scope` along with its own.
+///
+/// Completes once the inner future and every task have completed. Dropping
+/// it drops the tasks.
+#[must_use = "futures do nothing unless polled"]
+pub struct Scoped<F>
+where F: Future,
+{
+    state: State<F>,
+    scope: Scope,
+}
+
+enum State<F>
+where F: Future,
+{
+    /// Polling the future, and the tasks it executes.
+    Running(F),
+
+    /// The future has completed, and the remaining tasks are polled.
+    Draining(Option<F::Item>),
+}
+
+struct Inner {
+    tasks: FuturesUnordered<Box<Future<Item = (), Error = ()>>>,
+
+    /// Set once the `Scoped` future has been dropped.
+    closed: bool,
+}
+
+// ===== impl Scope =====
+
+impl Scope {
+    /// Create a scope with no tasks.
+    pub fn new() -> Self {
+        Scope {
+            inner: Rc::new(RefCell::new(Inner {
+                tasks: FuturesUnordered::new(),
+                closed: false,
+            })),
+        }
+    }
+
+    /// Drive the scope's tasks within the task of `future`, typically the
+    /// connection of the server executing on the scope.
+    pub fn run<F>(&self, future: F) -> Scoped<F>
+    where F: Future,
+    {
+        Scoped {
+            state: State::Running(future),
+            scope: self.clone(),
+        }
+    }
+
+    /// Returns the number of tasks that have not completed.
+    pub fn len(&self) -> usize {
+        self.inner.borrow().tasks.len()
+    }
+
+    /// Returns true if every task has completed.
+    pub fn is_empty(&self) -> bool {
+        self.inner.borrow().tasks.is_empty()
+    }
+
+    /// Poll every task, returning `Ready` once none is left.
+    fn poll_tasks(&self) -> Async<()> {
+        let mut inner = self.inner.borrow_mut();
+
+        loop {
+            match inner.tasks.poll() {
+                Ok(Async::Ready(Some(()))) | Err(()) => {}
+                Ok(Async::Ready(None)) => return Async::Ready(()),
+                Ok(Async::NotReady) => return Async::NotReady,
+            }
+        }
+    }
+}
+
+impl<F> Executor<F> for Scope
+where F: Future<Item = (), Error = ()> + 'static,
+{
+    fn execute(&self, future: F) -> Result<(), ExecuteError<F>> {
+        let mut inner = self.inner.borrow_mut();
+
+        if inner.closed {
+            return Err(ExecuteError::new(ExecuteErrorKind::Shutdown, future));
+        }
+
+        inner.tasks.push(Box::new(future));
+        Ok(())
+    }
+}
+
+impl Default for Scope {
+    fn default() -> Self {
+        Scope::new()
+    }
+}
+
+impl fmt::Debug for Scope {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let inner = self.inner.borrow();
+
+        fmt.debug_struct("Scope")
+            .field("tasks", &inner.tasks.len())
+            .field("closed", &inner.closed)
+            .finish()
+    }
+}
+
+// ===== impl Scoped =====
+
+impl<F> Future for Scoped<F>
+where F: Future,
+{
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<F::Item, F::Error> {
+        // The future is polled first, as it is what executes new tasks.
+        let item = match self.state {
+            State::Running(ref mut future) => match future.poll()? {
+                Async::Ready(item) => Some(item),
+                Async::NotReady => None,
+            },
+            State::Draining(_) => None,
+        };
+
+        if let Some(item) = item {
+            self.state = State::Draining(Some(item));
+        }
+
+        // The tasks are polled even while the future is pending, so that
+        // they are notified.
+        if self.scope.poll_tasks().is_not_ready() {
+            return Ok(Async::NotReady);
+        }
+
+        match self.state {
+            State::Draining(ref mut item) => {
+                Ok(Async::Ready(item.take().expect("polled after complete")))
+            }
+            State::Running(_) => Ok(Async::NotReady),
+        }
+    }
+}
+
+impl<F> Drop for Scoped<F>
+where F: Future,
+{
+    fn drop(&mut self) {
+        let tasks = {
+            let mut inner = self.scope.inner.borrow_mut();
+            inner.closed = true;
+            mem::replace(&mut inner.tasks, FuturesUnordered::new())
+        };
+
+        // The tasks are dropped outside of the borrow, as they may hold the
+        // scope.
+        drop(tasks);
+    }
+}
+
+impl<F> fmt::Debug for Scoped<F>
+where F: Future + fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let future = match self.state {
+            State::Running(ref future) => Some(future),
+            State::Draining(_) => None,
+        };
+
+        fmt.debug_struct("Scoped")
+            .field("future", &future)
+            .field("scope", &self.scope)
+            .finish()
+    }
+}
@@ -0,0 +1,228 @@
+//! Messages sent incrementally on streaming requests and responses.
+//!
+//! On clients, `client::Grpc::client_streaming_sender` starts a client-streaming call whose
+//! request messages are sent with a `Sender`, so they can be produced by the
+//! application as they become available:
+//!
+//! ```ignore
+//! let (tx, response) = grpc.client_streaming_sender(request, path, 16);
+//!
+//! handle.spawn(tx.send_all(points).map(|_| ()).map_err(|_| ()));
+//! let summary = response.wait()?;
+//! ```
+//!
+//! `Grpc::streaming_sender` splits a bidirectional streaming call the same
+//! way. The `Sender` and the response stream are independent, so each can
+//! be driven by its own task:
+//!
+//! ```ignore
+//! let (tx, response) = grpc.streaming_sender(request, path, 16);
+//!
+//! handle.spawn(tx.send_all(notes).map(|_| ()).map_err(|_| ()));
+//! handle.spawn(response.and_then(|response| {
+//!     response.into_inner().for_each(|note| {
+//!         println!("received {:?}", note);
+//!         Ok(())
+//!     })
+//! }).map_err(|e| error!("chat failed: {:?}", e)));
+//! ```
+//!
+//! Server-streaming and bidirectional streaming handlers can respond with
+//! the `Receiver` of a `channel`, and send the response messages from
+//! another task. The response headers are sent as soon as the handler's
+//! future resolves with the `Receiver`, before the first message:
+//!
+//! ```ignore
+//! fn list_features(&mut self, request: Request<Rectangle>) -> Self::ListFeaturesFuture {
+//!     let (tx, rx) = sender::channel(16);
+//!
+//!     self.handle.spawn(self.features_in(request.into_inner(), tx));
+//!     future::ok(Response::new(rx))
+//! }
+//! ```
+//!
+//! A channel holds at most `buffer` messages, and the body of the request or
+//! response is read as the HTTP/2.0 flow control windows allow, so a full
+//! channel means the peer is not keeping up. `Sender::poll_ready` is ready
+//! once a message can be sent without exceeding the buffer, letting
+//! producers wait instead of buffering unboundedly:
+//!
+//! ```ignore
+//! fn poll(&mut self) -> Poll<(), ()> {
+//!     loop {
+//!         // Produce the next message only once it can be sent.
+//!         try_ready!(self.tx.poll_ready().map_err(|_| ()));
+//!         let msg = self.produce();
+//!         self.tx.start_send(msg).map_err(|_| ())?;
+//!     }
+//! }
+//! ```
+
+use futures::{Poll, Async, Sink, StartSend, AsyncSink, Stream};
+use futures::sync::mpsc;
+
+use std::{error, fmt};
+
+/// Sends the messages of a streaming request or response.
+///
+/// The stream ends once the `Sender` and all its clones are dropped, or
+/// closed.
+#[derive(Debug)]
+pub struct Sender<T> {
+    inner: mpsc::Sender<T>,
+}
+
+/// The stream of the messages sent with a `Sender`, used as the body of a
+/// request or response.
+#[derive(Debug)]
+pub struct Receiver<T> {
+    inner: mpsc::Receiver<T>,
+}
+
+/// The message could not be sent because the request or response has
+/// ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SendError<T>(T);
+
+/// Returns a `Sender` and the stream of the messages it sends.
+///
+/// `Sender` applies backpressure once `buffer` messages, plus one per
+/// sender, are waiting to be written to the transport.
+pub fn channel<T>(buffer: usize) -> (Sender<T>, Receiver<T>) {
+    let (tx, rx) = mpsc::channel(buffer);
+    (Sender { inner: tx }, Receiver { inner: rx })
+}
+
+// ===== impl Sender =====
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        Sender { inner: self.inner.clone() }
+    }
+}
+
+impl<T> Sender<T> {
+    /// Returns `Ready` when a message can be sent without waiting for the
+    /// channel's buffer to drain.
+    ///
+    /// Fails once the request or response has ended.
+    pub fn poll_ready(&mut self) -> Poll<(), SendError<()>> {
+        self.inner.poll_ready()
+            .map_err(|_| SendError(()))
+    }
+}
+
+impl<T> Sink for Sender<T> {
+    type SinkItem = T;
+    type SinkError = SendError<T>;
+
+    fn start_send(&mut self, msg: T) -> StartSend<T, SendError<T>> {
+        match self.inner.start_send(msg) {
+            Ok(AsyncSink::Ready) => Ok(AsyncSink::Ready),
+            Ok(AsyncSink::NotReady(msg)) => Ok(AsyncSink::NotReady(msg)),
+            Err(e) => Err(SendError(e.into_inner())),
+        }
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), SendError<T>> {
+        // Completing never fails; only sending to a closed stream does.
+        match self.inner.poll_complete() {
+            Ok(ready) => Ok(ready),
+            Err(_) => Ok(Async::Ready(())),
+        }
+    }
+
+    fn close(&mut self) -> Poll<(), SendError<T>> {
+        match self.inner.close() {
+            Ok(ready) => Ok(ready),
+            Err(_) => Ok(Async::Ready(())),
+        }
+    }
+}
+
+// ===== impl Receiver =====
+
+impl<T> Stream for Receiver<T> {
+    type Item = T;
+    type Error = ::Error;
+
+    fn poll(&mut self) -> Poll<Option<T>, ::Error> {
+        match self.inner.poll() {
+            Ok(ready) => Ok(ready),
+            Err(()) => unreachable!("mpsc receivers do not fail"),
+        }
+    }
+}
+
+// ===== impl SendError =====
+
+impl<T> SendError<T> {
+    /// Returns the message that could not be sent.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "message stream has ended")
+    }
+}
+
+impl<T: fmt::Debug> error::Error for SendError<T> {
+    fn description(&self) -> &str {
+        "message stream has ended"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Response;
+    use generic::BytesCodec;
+    use generic::server::streaming::ResponseFuture;
+
+    use bytes::{BigEndian, BufMut, Bytes, BytesMut};
+    use futures::{future, Future};
+    use http::header::HeaderValue;
+    use tower_h2::Body;
+
+    /// Returns the gRPC frame of `message`.
+    fn frame(message: &[u8]) -> Bytes {
+        let mut buf = BytesMut::with_capacity(5 + message.len());
+        buf.put_u8(0);
+        buf.put_u32::<BigEndian>(message.len() as u32);
+        buf.put_slice(message);
+        buf.freeze()
+    }
+
+    #[test]
+    fn headers_are_sent_before_the_first_message() {
+        future::lazy(|| {
+            let (mut tx, rx) = channel::<Vec<u8>>(1);
+
+            let mut response = Response::new(rx);
+            response.headers_mut()
+                .insert("x-subscribed", HeaderValue::from_static("yes"));
+
+            // The handler resolves with the `Receiver` before sending anything.
+            let mut future = ResponseFuture::new(future::ok(response), BytesCodec);
+            let response = match future.poll().unwrap() {
+                Async::Ready(response) => response,
+                Async::NotReady => panic!("headers held back until the first message"),
+            };
+            assert_eq!(response.headers()["x-subscribed"], "yes");
+
+            let mut body = response.into_body();
+            assert!(body.poll_data().unwrap().is_not_ready());
+
+            assert!(tx.start_send(b"first".to_vec()).unwrap().is_ready());
+            assert_eq!(body.poll_data().unwrap(), Async::Ready(Some(frame(b"first"))));
+
+            drop(tx);
+            assert_eq!(body.poll_data().unwrap(), Async::Ready(None));
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+}
@@ -6,9 +6,10 @@ pub mod unary;
 use codec::{Codec, Streaming};
 use generic::server::{UnaryService, ClientStreamingService, ServerStreamingService, StreamingService};
 
+use bytes::Bytes;
 use http;
 use prost;
-use tower_h2::{Body, Data};
+use tower_h2::Body;
 
 #[derive(Debug, Clone)]
 pub struct Grpc {
@@ -24,7 +25,8 @@ impl Grpc {
     where T: UnaryService,
           T::Request: prost::Message + Default,
           T::Response: prost::Message,
-          B: Body<Data = Data>,
+          B: Body,
+          B::Data: Into<Bytes>,
     {
         use generic::server::Grpc;
 
@@ -39,7 +41,8 @@ impl Grpc {
     where T: ClientStreamingService<Request = R, RequestStream = Streaming<R, B>>,
           T::Request: prost::Message + Default,
           T::Response: prost::Message,
-          B: Body<Data = Data>,
+          B: Body,
+          B::Data: Into<Bytes>,
     {
         use generic::server::Grpc;
 
@@ -54,7 +57,8 @@ impl Grpc {
     where T: ServerStreamingService,
           T::Request: prost::Message + Default,
           T::Response: prost::Message,
-          B: Body<Data = Data>,
+          B: Body,
+          B::Data: Into<Bytes>,
     {
         use generic::server::Grpc;
 
@@ -69,7 +73,8 @@ impl Grpc {
     where T: StreamingService<Request = R, RequestStream = Streaming<R, B>>,
           T::Request: prost::Message + Default,
           T::Response: prost::Message,
-          B: Body<Data = Data>,
+          B: Body,
+          B::Data: Into<Bytes>,
     {
         use generic::server::Grpc;
 
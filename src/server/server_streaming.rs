@@ -4,8 +4,9 @@ use generic::server::{ServerStreamingService, server_streaming};
 use std::fmt;
 
 use {h2, http, prost};
+use bytes::Bytes;
 use futures::{Future, Poll};
-use tower_h2::{Body, Data};
+use tower_h2::Body;
 
 pub struct ResponseFuture<T, B>
 where T: ServerStreamingService
@@ -20,7 +21,8 @@ impl<T, B> ResponseFuture<T, B>
 where T: ServerStreamingService,
       T::Request: prost::Message + Default,
       T::Response: prost::Message,
-      B: Body<Data = Data>,
+      B: Body,
+      B::Data: Into<Bytes>,
 {
     pub(crate) fn new(inner: Inner<T, T::Response, T::Request, B>) -> Self {
         ResponseFuture { inner }
@@ -31,7 +33,8 @@ impl<T, B> Future for ResponseFuture<T, B>
 where T: ServerStreamingService,
       T::Request: prost::Message + Default,
       T::Response: prost::Message,
-      B: Body<Data = Data>,
+      B: Body,
+      B::Data: Into<Bytes>,
 {
     type Item = http::Response<Encode<T::ResponseStream>>;
     type Error = h2::Error;
@@ -5,8 +5,9 @@ use generic::Streaming;
 use generic::server::{UnaryService, unary};
 
 use {h2, http, prost};
+use bytes::Bytes;
 use futures::{Future, Poll};
-use tower_h2::{Body, Data};
+use tower_h2::Body;
 
 use std::fmt;
 
@@ -35,7 +36,8 @@ impl<T, B> Future for ResponseFuture<T, B>
 where T: UnaryService,
       T::Request: prost::Message + Default,
       T::Response: prost::Message,
-      B: Body<Data = Data>,
+      B: Body,
+      B::Data: Into<Bytes>,
 {
     type Item = http::Response<Encode<Once<T::Response>>>;
     type Error = h2::Error;
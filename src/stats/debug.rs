@@ -0,0 +1,383 @@
+use super::{ErrorSample, ErrorSampler, MethodSnapshot, Registry};
+use codec::Encode;
+use server::{unary, Grpc};
+use {Request, Response, Status};
+
+use bytes::Bytes;
+use futures::{future, Future, Poll};
+use h2;
+use http;
+use tower::{NewService, ReadyService, Service};
+use tower_h2::{Body, RecvBody};
+
+use std::fmt;
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Path of the `GetStats` method.
+pub const GET_STATS_PATH: &'static str = "/tower.grpc.debug.v1.ServerStats/GetStats";
+
+/// Path of the `GetErrorSamples` method.
+pub const GET_ERROR_SAMPLES_PATH: &'static str = "/tower.grpc.debug.v1.ServerStats/GetErrorSamples";
+
+/// Serves the statistics of a `Registry` as the
+/// `tower.grpc.debug.v1.ServerStats` service.
+///
+/// `GetErrorSamples` is `UNIMPLEMENTED` unless an `ErrorSampler` is given
+/// with `error_samples`.
+#[derive(Debug, Clone)]
+pub struct StatsService {
+    registry: Registry,
+    errors: Option<ErrorSampler>,
+}
+
+/// The response future returned by `StatsService`.
+pub struct ResponseFuture {
+    kind: Result<Kind<unary::ResponseFuture<GetStats, RecvBody>,
+                      unary::ResponseFuture<GetErrorSamples, RecvBody>>, Status>,
+}
+
+/// The response body returned by `StatsService`.
+pub struct ResponseBody {
+    kind: Result<Kind<Encode<unary::Once<GetStatsResponse>>,
+                      Encode<unary::Once<GetErrorSamplesResponse>>>, Status>,
+}
+
+enum Kind<S, E> {
+    Stats(S),
+    ErrorSamples(E),
+}
+
+/// Handles `GetStats` calls.
+#[derive(Debug, Clone)]
+struct GetStats(Registry);
+
+/// Handles `GetErrorSamples` calls.
+#[derive(Debug, Clone)]
+struct GetErrorSamples(ErrorSampler);
+
+// ===== Protocol =====
+
+#[derive(Clone, PartialEq, Message)]
+pub struct GetStatsRequest {
+    /// The method to return statistics for. Every method is returned if
+    /// empty.
+    #[prost(string, tag="1")]
+    pub method: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct GetStatsResponse {
+    #[prost(message, repeated, tag="1")]
+    pub method: Vec<MethodStats>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct MethodStats {
+    #[prost(string, tag="1")]
+    pub method: String,
+    #[prost(uint64, tag="2")]
+    pub started: u64,
+    #[prost(message, repeated, tag="3")]
+    pub completed: Vec<CodeCount>,
+    #[prost(uint64, tag="4")]
+    pub messages_sent: u64,
+    #[prost(uint64, tag="5")]
+    pub messages_received: u64,
+    #[prost(message, optional, tag="6")]
+    pub latency: Option<Latency>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct CodeCount {
+    #[prost(int32, tag="1")]
+    pub code: i32,
+    #[prost(uint64, tag="2")]
+    pub count: u64,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Latency {
+    #[prost(uint64, tag="1")]
+    pub count: u64,
+    #[prost(uint64, tag="2")]
+    pub min_micros: u64,
+    #[prost(uint64, tag="3")]
+    pub mean_micros: u64,
+    #[prost(uint64, tag="4")]
+    pub max_micros: u64,
+    #[prost(uint64, tag="5")]
+    pub p50_micros: u64,
+    #[prost(uint64, tag="6")]
+    pub p90_micros: u64,
+    #[prost(uint64, tag="7")]
+    pub p99_micros: u64,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct GetErrorSamplesRequest {
+    /// Clear the samples once they are returned.
+    #[prost(bool, tag="1")]
+    pub clear: bool,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct GetErrorSamplesResponse {
+    #[prost(message, repeated, tag="1")]
+    pub sample: Vec<ErrorSampleProto>,
+}
+
+/// A failed call, as sent by `GetErrorSamples`.
+#[derive(Clone, PartialEq, Message)]
+pub struct ErrorSampleProto {
+    #[prost(string, tag="1")]
+    pub method: String,
+    #[prost(uint64, tag="2")]
+    pub start_unix_micros: u64,
+    #[prost(uint64, tag="3")]
+    pub duration_micros: u64,
+    #[prost(int32, tag="4")]
+    pub code: i32,
+    #[prost(string, tag="5")]
+    pub message: String,
+    #[prost(bytes, tag="6")]
+    pub details: Vec<u8>,
+    #[prost(message, repeated, tag="7")]
+    pub metadata: Vec<MetadataEntry>,
+    #[prost(message, repeated, tag="8")]
+    pub trailers: Vec<MetadataEntry>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct MetadataEntry {
+    #[prost(string, tag="1")]
+    pub key: String,
+    #[prost(string, tag="2")]
+    pub value: String,
+}
+
+// ===== impl StatsService =====
+
+impl StatsService {
+    /// Serve the statistics recorded in `registry`.
+    pub fn new(registry: Registry) -> Self {
+        StatsService {
+            registry,
+            errors: None,
+        }
+    }
+
+    /// Also serve the samples kept by `sampler`.
+    pub fn error_samples(mut self, sampler: ErrorSampler) -> Self {
+        self.errors = Some(sampler);
+        self
+    }
+}
+
+impl Service for StatsService {
+    type Request = http::Request<RecvBody>;
+    type Response = http::Response<ResponseBody>;
+    type Error = h2::Error;
+    type Future = ResponseFuture;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        Ok(().into())
+    }
+
+    fn call(&mut self, request: Self::Request) -> Self::Future {
+        let kind = match request.uri().path() {
+            GET_STATS_PATH => {
+                let service = GetStats(self.registry.clone());
+                Ok(Kind::Stats(Grpc::unary(service, request)))
+            }
+            GET_ERROR_SAMPLES_PATH if self.errors.is_some() => {
+                let service = GetErrorSamples(self.errors.clone().unwrap());
+                Ok(Kind::ErrorSamples(Grpc::unary(service, request)))
+            }
+            _ => Err(Status::UNIMPLEMENTED),
+        };
+
+        ResponseFuture { kind }
+    }
+}
+
+impl NewService for StatsService {
+    type Request = http::Request<RecvBody>;
+    type Response = http::Response<ResponseBody>;
+    type Error = h2::Error;
+    type Service = Self;
+    type InitError = h2::Error;
+    type Future = future::FutureResult<Self::Service, Self::InitError>;
+
+    fn new_service(&self) -> Self::Future {
+        future::ok(self.clone())
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl Future for ResponseFuture {
+    type Item = http::Response<ResponseBody>;
+    type Error = h2::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.kind {
+            Ok(Kind::Stats(ref mut fut)) => {
+                let (head, body) = try_ready!(fut.poll()).into_parts();
+                let body = ResponseBody { kind: Ok(Kind::Stats(body)) };
+                Ok(http::Response::from_parts(head, body).into())
+            }
+            Ok(Kind::ErrorSamples(ref mut fut)) => {
+                let (head, body) = try_ready!(fut.poll()).into_parts();
+                let body = ResponseBody { kind: Ok(Kind::ErrorSamples(body)) };
+                Ok(http::Response::from_parts(head, body).into())
+            }
+            Err(ref status) => {
+                let body = ResponseBody { kind: Err(status.clone()) };
+                Ok(Response::trailers_only(status, body).into_http().into())
+            }
+        }
+    }
+}
+
+impl fmt::Debug for ResponseFuture {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("stats::StatsResponseFuture")
+            .field("error", &self.kind.as_ref().err())
+            .finish()
+    }
+}
+
+// ===== impl ResponseBody =====
+
+impl Body for ResponseBody {
+    type Data = Bytes;
+
+    fn is_end_stream(&self) -> bool {
+        match self.kind {
+            Ok(Kind::Stats(ref body)) => body.is_end_stream(),
+            Ok(Kind::ErrorSamples(ref body)) => body.is_end_stream(),
+            Err(_) => true,
+        }
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Bytes>, h2::Error> {
+        match self.kind {
+            Ok(Kind::Stats(ref mut body)) => body.poll_data(),
+            Ok(Kind::ErrorSamples(ref mut body)) => body.poll_data(),
+            Err(_) => Ok(None.into()),
+        }
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, h2::Error> {
+        match self.kind {
+            Ok(Kind::Stats(ref mut body)) => body.poll_trailers(),
+            Ok(Kind::ErrorSamples(ref mut body)) => body.poll_trailers(),
+            // The status was sent in the headers.
+            Err(_) => Ok(None.into()),
+        }
+    }
+}
+
+impl fmt::Debug for ResponseBody {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("stats::StatsResponseBody")
+            .field("error", &self.kind.as_ref().err())
+            .finish()
+    }
+}
+
+// ===== impl GetStats =====
+
+impl ReadyService for GetStats {
+    type Request = Request<GetStatsRequest>;
+    type Response = Response<GetStatsResponse>;
+    type Error = ::Error;
+    type Future = future::FutureResult<Self::Response, Self::Error>;
+
+    fn call(&mut self, request: Self::Request) -> Self::Future {
+        let method = &request.get_ref().method;
+
+        let snapshots = if method.is_empty() {
+            self.0.snapshot()
+        } else {
+            self.0.method(method).into_iter().collect()
+        };
+
+        let response = GetStatsResponse {
+            method: snapshots.iter().map(method_stats).collect(),
+        };
+
+        future::ok(Response::new(response))
+    }
+}
+
+// ===== impl GetErrorSamples =====
+
+impl ReadyService for GetErrorSamples {
+    type Request = Request<GetErrorSamplesRequest>;
+    type Response = Response<GetErrorSamplesResponse>;
+    type Error = ::Error;
+    type Future = future::FutureResult<Self::Response, Self::Error>;
+
+    fn call(&mut self, request: Self::Request) -> Self::Future {
+        let samples = if request.get_ref().clear {
+            self.0.take()
+        } else {
+            self.0.samples()
+        };
+
+        let response = GetErrorSamplesResponse {
+            sample: samples.iter().map(error_sample).collect(),
+        };
+
+        future::ok(Response::new(response))
+    }
+}
+
+// ===== utility fns =====
+
+fn method_stats(snapshot: &MethodSnapshot) -> MethodStats {
+    MethodStats {
+        method: snapshot.method.clone(),
+        started: snapshot.started,
+        completed: snapshot.completed.iter()
+            .map(|&(code, count)| CodeCount { code: code.as_i32(), count })
+            .collect(),
+        messages_sent: snapshot.messages_sent,
+        messages_received: snapshot.messages_received,
+        latency: Some(Latency {
+            count: snapshot.latency.count,
+            min_micros: micros(snapshot.latency.min),
+            mean_micros: micros(snapshot.latency.mean),
+            max_micros: micros(snapshot.latency.max),
+            p50_micros: micros(snapshot.latency.p50),
+            p90_micros: micros(snapshot.latency.p90),
+            p99_micros: micros(snapshot.latency.p99),
+        }),
+    }
+}
+
+fn micros(duration: Duration) -> u64 {
+    duration.as_secs() * 1_000_000 + duration.subsec_nanos() as u64 / 1_000
+}
+
+fn error_sample(sample: &ErrorSample) -> ErrorSampleProto {
+    let entries = |entries: &[(String, String)]| {
+        entries.iter()
+            .map(|&(ref key, ref value)| MetadataEntry { key: key.clone(), value: value.clone() })
+            .collect()
+    };
+
+    ErrorSampleProto {
+        method: sample.method.clone(),
+        start_unix_micros: sample.start.duration_since(UNIX_EPOCH)
+            .map(micros)
+            .unwrap_or(0),
+        duration_micros: micros(sample.duration),
+        code: sample.code.as_i32(),
+        message: sample.message.clone().unwrap_or_default(),
+        details: sample.details.clone().unwrap_or_default(),
+        metadata: entries(&sample.metadata),
+        trailers: entries(&sample.trailers),
+    }
+}
@@ -0,0 +1,378 @@
+use base64;
+use headers;
+use Code;
+
+use futures::{Future, Poll, Async};
+use h2;
+use http::{self, HeaderMap};
+use rand;
+use tower::{NewService, Service};
+use tower_h2::Body;
+
+use std::fmt;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Keeps a sample of the calls that failed, in a ring buffer.
+///
+/// Once the buffer is full, the oldest sample is dropped for each new one.
+#[derive(Clone)]
+pub struct ErrorSampler {
+    inner: Arc<Inner>,
+}
+
+/// A sampled failed call.
+#[derive(Debug, Clone)]
+pub struct ErrorSample {
+    /// The method's path, such as `/helloworld.Greeter/SayHello`.
+    pub method: String,
+
+    /// When the call was received.
+    pub start: SystemTime,
+
+    /// The time from receiving the request to the call failing.
+    pub duration: Duration,
+
+    pub code: Code,
+
+    /// The status message sent in `grpc-message`.
+    pub message: Option<String>,
+
+    /// The encoded `google.rpc.Status` sent in `grpc-status-details-bin`.
+    pub details: Option<Vec<u8>>,
+
+    /// The request's metadata.
+    pub metadata: Vec<(String, String)>,
+
+    /// The metadata sent with the status.
+    pub trailers: Vec<(String, String)>,
+}
+
+/// Samples the calls served by the inner service that fail.
+///
+/// `SampleErrors` may wrap either a `Service` or the `NewService` given to
+/// `tower_h2::Server`. The request metadata of every call is kept until it
+/// completes, so that it can be included in samples.
+#[derive(Debug, Clone)]
+pub struct SampleErrors<S> {
+    inner: S,
+    sampler: ErrorSampler,
+}
+
+/// The response future returned by `SampleErrors`.
+pub struct SampleFuture<F> {
+    inner: F,
+    call: Option<Call>,
+}
+
+/// The response body returned by `SampleErrors`.
+pub struct SampleBody<B> {
+    inner: B,
+    call: Option<Call>,
+}
+
+/// Creates `SampleErrors` services.
+#[derive(Debug)]
+pub struct SampleNewServiceFuture<F> {
+    inner: F,
+    sampler: ErrorSampler,
+}
+
+struct Inner {
+    samples: Mutex<VecDeque<ErrorSample>>,
+    capacity: usize,
+    rate: f64,
+}
+
+/// A call whose outcome is not yet known.
+struct Call {
+    sampler: ErrorSampler,
+    method: String,
+    headers: HeaderMap,
+    start: SystemTime,
+    started: Instant,
+}
+
+// ===== impl ErrorSampler =====
+
+impl ErrorSampler {
+    /// Keep up to `capacity` samples of every failed call.
+    pub fn new(capacity: usize) -> Self {
+        ErrorSampler::with_rate(capacity, 1.0)
+    }
+
+    /// Keep up to `capacity` samples of a `rate` fraction of failed calls,
+    /// between 0.0 and 1.0.
+    pub fn with_rate(capacity: usize, rate: f64) -> Self {
+        ErrorSampler {
+            inner: Arc::new(Inner {
+                samples: Mutex::new(VecDeque::with_capacity(capacity)),
+                capacity,
+                rate: rate.max(0.0).min(1.0),
+            }),
+        }
+    }
+
+    /// Returns the samples kept, oldest first.
+    pub fn samples(&self) -> Vec<ErrorSample> {
+        self.lock().iter().cloned().collect()
+    }
+
+    /// Returns the samples kept, oldest first, and clears the buffer.
+    pub fn take(&self) -> Vec<ErrorSample> {
+        self.lock().drain(..).collect()
+    }
+
+    /// Record `sample` if it is selected by the sampling rate.
+    fn offer(&self, sample: ErrorSample) {
+        if self.inner.capacity == 0 {
+            return;
+        }
+
+        if self.inner.rate < 1.0 && rand::random::<f64>() >= self.inner.rate {
+            return;
+        }
+
+        let mut samples = self.lock();
+
+        if samples.len() == self.inner.capacity {
+            samples.pop_front();
+        }
+
+        samples.push_back(sample);
+    }
+
+    fn lock(&self) -> MutexGuard<VecDeque<ErrorSample>> {
+        match self.inner.samples.lock() {
+            Ok(samples) => samples,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+impl fmt::Debug for ErrorSampler {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("ErrorSampler")
+            .field("capacity", &self.inner.capacity)
+            .field("rate", &self.inner.rate)
+            .field("samples", &self.lock().len())
+            .finish()
+    }
+}
+
+// ===== impl SampleErrors =====
+
+impl<S> SampleErrors<S> {
+    /// Offer the calls served by `inner` that fail to `sampler`.
+    pub fn new(inner: S, sampler: ErrorSampler) -> Self {
+        SampleErrors { inner, sampler }
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner service.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+}
+
+impl<S, A, B> Service for SampleErrors<S>
+where S: Service<Request = http::Request<A>, Response = http::Response<B>>,
+      B: Body,
+{
+    type Request = S::Request;
+    type Response = http::Response<SampleBody<B>>;
+    type Error = S::Error;
+    type Future = SampleFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, request: Self::Request) -> Self::Future {
+        let call = Call {
+            sampler: self.sampler.clone(),
+            method: request.uri().path().to_string(),
+            headers: request.headers().clone(),
+            start: SystemTime::now(),
+            started: Instant::now(),
+        };
+
+        SampleFuture {
+            inner: self.inner.call(request),
+            call: Some(call),
+        }
+    }
+}
+
+impl<S, A, B> NewService for SampleErrors<S>
+where S: NewService<Request = http::Request<A>, Response = http::Response<B>>,
+      B: Body,
+{
+    type Request = S::Request;
+    type Response = http::Response<SampleBody<B>>;
+    type Error = S::Error;
+    type Service = SampleErrors<S::Service>;
+    type InitError = S::InitError;
+    type Future = SampleNewServiceFuture<S::Future>;
+
+    fn new_service(&self) -> Self::Future {
+        SampleNewServiceFuture {
+            inner: self.inner.new_service(),
+            sampler: self.sampler.clone(),
+        }
+    }
+}
+
+// ===== impl SampleNewServiceFuture =====
+
+impl<F> Future for SampleNewServiceFuture<F>
+where F: Future,
+{
+    type Item = SampleErrors<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        Ok(Async::Ready(SampleErrors::new(inner, self.sampler.clone())))
+    }
+}
+
+// ===== impl SampleFuture =====
+
+impl<F, B> Future for SampleFuture<F>
+where F: Future<Item = http::Response<B>>,
+{
+    type Item = http::Response<SampleBody<B>>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let response = match self.inner.poll() {
+            Ok(Async::Ready(response)) => response,
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Err(e) => {
+                if let Some(call) = self.call.take() {
+                    call.finish(Code::UNKNOWN, &HeaderMap::new());
+                }
+                return Err(e);
+            }
+        };
+
+        let mut call = self.call.take();
+
+        // Trailers-only responses carry the status in the headers.
+        if let Some(code) = headers::status_code(response.headers()) {
+            if let Some(call) = call.take() {
+                call.finish(code, response.headers());
+            }
+        }
+
+        let (head, inner) = response.into_parts();
+        let body = SampleBody { inner, call };
+
+        Ok(Async::Ready(http::Response::from_parts(head, body)))
+    }
+}
+
+impl<F> fmt::Debug for SampleFuture<F>
+where F: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("SampleFuture")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+// ===== impl SampleBody =====
+
+impl<B> Body for SampleBody<B>
+where B: Body,
+{
+    type Data = B::Data;
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, h2::Error> {
+        self.inner.poll_data()
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, h2::Error> {
+        let trailers = try_ready!(self.inner.poll_trailers());
+
+        if let Some(call) = self.call.take() {
+            match trailers {
+                Some(ref trailers) => {
+                    let code = headers::status_code(trailers).unwrap_or(Code::UNKNOWN);
+                    call.finish(code, trailers);
+                }
+                None => call.finish(Code::UNKNOWN, &HeaderMap::new()),
+            }
+        }
+
+        Ok(Async::Ready(trailers))
+    }
+}
+
+impl<B> fmt::Debug for SampleBody<B>
+where B: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("SampleBody")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+// ===== impl Call =====
+
+impl Call {
+    fn finish(self, code: Code, trailers: &HeaderMap) {
+        if code == Code::OK {
+            return;
+        }
+
+        let message = trailers.get("grpc-message")
+            .and_then(|s| s.to_str().ok())
+            .map(|s| s.to_string());
+
+        let details = trailers.get("grpc-status-details-bin")
+            .and_then(|s| base64::decode(s.as_bytes()));
+
+        let sample = ErrorSample {
+            method: self.method,
+            start: self.start,
+            duration: self.started.elapsed(),
+            code,
+            message,
+            details,
+            metadata: entries(&self.headers),
+            trailers: entries(trailers),
+        };
+
+        self.sampler.offer(sample);
+    }
+}
+
+// ===== utility fns =====
+
+/// Returns the metadata in `headers`, leaving out values that are not
+/// text and gRPC's reserved headers.
+fn entries(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers.iter()
+        .filter(|&(key, _)| {
+            let key = key.as_str();
+            !key.starts_with("grpc-") && key != "content-type" && key != "te"
+        })
+        .filter_map(|(key, value)| {
+            let value = value.to_str().ok()?;
+            Some((key.as_str().to_string(), value.to_string()))
+        })
+        .collect()
+}
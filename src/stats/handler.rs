@@ -0,0 +1,583 @@
+use generic::counter::Count;
+use {Code, Status};
+
+use futures::{Future, Poll, Async};
+use http::{self, HeaderMap};
+use tower::{NewService, Service};
+
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Instant;
+
+/// Call ids are unique within the process.
+static NEXT_CALL_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Observes the lifecycle of calls.
+///
+/// Clients built with `client::Builder::stats_handler`, and servers wrapped
+/// with `InstrumentServer`, call the hooks as they encode and decode each
+/// call. Every hook has an empty default, so handlers only implement the
+/// events they record. Hooks are called while the call is being polled, so
+/// they should not block for long.
+pub trait StatsHandler: Send + Sync {
+    /// A call has started.
+    fn call_started(&self, _call: &CallInfo) {}
+
+    /// The call's headers have been sent: the request headers on clients,
+    /// and the response headers on servers.
+    fn headers_sent(&self, _call: &CallInfo, _headers: &HeaderMap) {}
+
+    /// The call's headers have been received: the response headers on
+    /// clients, and the request headers on servers.
+    fn headers_received(&self, _call: &CallInfo, _headers: &HeaderMap) {}
+
+    /// A message of `len` bytes, excluding framing, has been sent.
+    fn message_sent(&self, _call: &CallInfo, _len: usize) {}
+
+    /// A message of `len` bytes, excluding framing, has been received.
+    fn message_received(&self, _call: &CallInfo, _len: usize) {}
+
+    /// The call has ended with `status`.
+    ///
+    /// This is called once per call. Calls dropped before their status is
+    /// known end as `CANCELED`.
+    fn call_ended(&self, _call: &CallInfo, _status: &Status) {}
+}
+
+/// Describes the call passed to a `StatsHandler`.
+#[derive(Debug)]
+pub struct CallInfo {
+    id: u64,
+    side: Side,
+    method: String,
+    start: Instant,
+}
+
+/// The side of a call a `StatsHandler` observes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Client,
+    Server,
+}
+
+/// Reports the calls served by the inner service to a `StatsHandler`.
+///
+/// The server's codec calls the hooks as it decodes each request and
+/// encodes each response. `InstrumentServer` may wrap either a `Service` or
+/// the `NewService` given to `tower_h2::Server`.
+#[derive(Debug, Clone)]
+pub struct InstrumentServer<S> {
+    inner: S,
+    handler: Handler,
+}
+
+/// Creates `InstrumentServer` services.
+#[derive(Debug)]
+pub struct InstrumentNewServiceFuture<F> {
+    inner: F,
+    handler: Handler,
+}
+
+/// Reports a call to its `StatsHandler`.
+///
+/// Clients and servers create one for each call, through which their codecs
+/// report the call's messages and its status. A call whose status is not
+/// known once every clone has been dropped ends as `CANCELED`.
+#[derive(Clone)]
+pub struct CallStats {
+    call: Arc<Call>,
+}
+
+/// Request extension holding the handler a server's calls are reported to.
+#[derive(Clone)]
+pub(crate) struct Handler(Arc<StatsHandler>);
+
+struct Call {
+    info: CallInfo,
+    handler: Arc<StatsHandler>,
+    ended: AtomicBool,
+}
+
+// ===== impl CallInfo =====
+
+impl CallInfo {
+    fn new(side: Side, method: &str) -> Self {
+        CallInfo {
+            id: NEXT_CALL_ID.fetch_add(1, Ordering::Relaxed) as u64 + 1,
+            side,
+            method: method.to_string(),
+            start: Instant::now(),
+        }
+    }
+
+    /// Returns an id identifying the call within the process.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn side(&self) -> Side {
+        self.side
+    }
+
+    /// Returns the method's path, such as `/helloworld.Greeter/SayHello`.
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    /// Returns when the call started.
+    pub fn start(&self) -> Instant {
+        self.start
+    }
+}
+
+// ===== impl InstrumentServer =====
+
+impl<S> InstrumentServer<S> {
+    /// Report the calls served by `inner` to `handler`.
+    pub fn new<H>(inner: S, handler: H) -> Self
+    where H: StatsHandler + 'static,
+    {
+        InstrumentServer {
+            inner,
+            handler: Handler::new(handler),
+        }
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner service.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+}
+
+impl<S, A> Service for InstrumentServer<S>
+where S: Service<Request = http::Request<A>>,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, mut request: Self::Request) -> Self::Future {
+        request.extensions_mut().insert(self.handler.clone());
+        self.inner.call(request)
+    }
+}
+
+impl<S, A> NewService for InstrumentServer<S>
+where S: NewService<Request = http::Request<A>>,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Service = InstrumentServer<S::Service>;
+    type InitError = S::InitError;
+    type Future = InstrumentNewServiceFuture<S::Future>;
+
+    fn new_service(&self) -> Self::Future {
+        InstrumentNewServiceFuture {
+            inner: self.inner.new_service(),
+            handler: self.handler.clone(),
+        }
+    }
+}
+
+// ===== impl InstrumentNewServiceFuture =====
+
+impl<F> Future for InstrumentNewServiceFuture<F>
+where F: Future,
+{
+    type Item = InstrumentServer<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        Ok(Async::Ready(InstrumentServer {
+            inner,
+            handler: self.handler.clone(),
+        }))
+    }
+}
+
+// ===== impl CallStats =====
+
+impl CallStats {
+    pub(crate) fn new(handler: &Handler, side: Side, method: &str) -> Self {
+        let info = CallInfo::new(side, method);
+        handler.0.call_started(&info);
+
+        let call = Call {
+            info,
+            handler: handler.0.clone(),
+            ended: AtomicBool::new(false),
+        };
+
+        CallStats { call: Arc::new(call) }
+    }
+
+    pub(crate) fn headers_sent(&self, headers: &HeaderMap) {
+        self.call.handler.headers_sent(&self.call.info, headers);
+    }
+
+    pub(crate) fn headers_received(&self, headers: &HeaderMap) {
+        self.call.handler.headers_received(&self.call.info, headers);
+    }
+
+    pub(crate) fn message_sent(&self, len: usize) {
+        self.call.handler.message_sent(&self.call.info, len);
+    }
+
+    /// Returns the counter through which the codec reports each message it
+    /// decodes.
+    pub(crate) fn counter(&self) -> Arc<Count> {
+        self.call.clone()
+    }
+
+    /// End the call with `code`, unless it has already ended.
+    pub(crate) fn end(&self, code: Code) {
+        self.call.end(code);
+    }
+}
+
+impl fmt::Debug for CallStats {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("CallStats")
+            .field("call", &self.call.info)
+            .finish()
+    }
+}
+
+// ===== impl Handler =====
+
+impl Handler {
+    pub(crate) fn new<H>(handler: H) -> Self
+    where H: StatsHandler + 'static,
+    {
+        Handler(Arc::new(handler))
+    }
+}
+
+impl fmt::Debug for Handler {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Handler").finish()
+    }
+}
+
+// ===== impl Call =====
+
+impl Call {
+    fn end(&self, code: Code) {
+        if self.ended.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        self.handler.call_ended(&self.info, &Status::with_code(code));
+    }
+}
+
+impl Count for Call {
+    fn message(&self, len: usize) {
+        self.handler.message_received(&self.info, len);
+    }
+}
+
+impl Drop for Call {
+    fn drop(&mut self) {
+        self.end(Code::CANCELED);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use client;
+    use generic::BytesCodec;
+    use generic::server::Grpc;
+    use {Request, Response};
+
+    use bytes::{BigEndian, BufMut, Bytes, BytesMut};
+    use futures::Stream;
+    use futures::future::{self, FutureResult};
+    use h2;
+    use prost::Message;
+    use tower::ReadyService;
+    use tower_h2::{Body, BoxBody};
+
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    /// Records the hooks called, in order.
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<Event>>>);
+
+    #[derive(Debug, PartialEq)]
+    enum Event {
+        Started(Side, String),
+        HeadersSent,
+        HeadersReceived,
+        MessageSent(usize),
+        MessageReceived(usize),
+        Ended(Code),
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    struct Hello {
+        #[prost(string, tag="1")]
+        name: String,
+    }
+
+    /// An HTTP/2.0 service sending the request body, and then returning
+    /// `response`.
+    struct MockService {
+        response: Option<http::Response<MockBody>>,
+    }
+
+    struct MockBody {
+        data: VecDeque<Bytes>,
+        trailers: Option<HeaderMap>,
+    }
+
+    /// A server echoing the request message.
+    struct Echo;
+
+    fn frame(message: &[u8]) -> Bytes {
+        let mut buf = BytesMut::with_capacity(5 + message.len());
+        buf.put_u8(0);
+        buf.put_u32::<BigEndian>(message.len() as u32);
+        buf.put_slice(message);
+        buf.freeze()
+    }
+
+    fn ok_trailers() -> HeaderMap {
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", Status::OK.to_header_value());
+        trailers
+    }
+
+    // ===== impl Recorder =====
+
+    impl Recorder {
+        fn events(&self) -> Vec<Event> {
+            self.0.lock().unwrap().drain(..).collect()
+        }
+
+        fn push(&self, event: Event) {
+            self.0.lock().unwrap().push(event);
+        }
+    }
+
+    impl StatsHandler for Recorder {
+        fn call_started(&self, call: &CallInfo) {
+            self.push(Event::Started(call.side(), call.method().to_string()));
+        }
+
+        fn headers_sent(&self, _call: &CallInfo, _headers: &HeaderMap) {
+            self.push(Event::HeadersSent);
+        }
+
+        fn headers_received(&self, _call: &CallInfo, _headers: &HeaderMap) {
+            self.push(Event::HeadersReceived);
+        }
+
+        fn message_sent(&self, _call: &CallInfo, len: usize) {
+            self.push(Event::MessageSent(len));
+        }
+
+        fn message_received(&self, _call: &CallInfo, len: usize) {
+            self.push(Event::MessageReceived(len));
+        }
+
+        fn call_ended(&self, _call: &CallInfo, status: &Status) {
+            self.push(Event::Ended(status.code()));
+        }
+    }
+
+    // ===== impl MockService =====
+
+    impl Service for MockService {
+        type Request = http::Request<BoxBody>;
+        type Response = http::Response<MockBody>;
+        type Error = h2::Error;
+        type Future = FutureResult<Self::Response, h2::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), h2::Error> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, request: Self::Request) -> Self::Future {
+            let mut body = request.into_body();
+            while let Async::Ready(Some(_)) = body.poll_data().unwrap() {}
+
+            future::ok(self.response.take().expect("called once"))
+        }
+    }
+
+    // ===== impl MockBody =====
+
+    impl Body for MockBody {
+        type Data = Bytes;
+
+        fn is_end_stream(&self) -> bool {
+            self.data.is_empty() && self.trailers.is_none()
+        }
+
+        fn poll_data(&mut self) -> Poll<Option<Bytes>, h2::Error> {
+            Ok(Async::Ready(self.data.pop_front()))
+        }
+
+        fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, h2::Error> {
+            Ok(Async::Ready(self.trailers.take()))
+        }
+    }
+
+    // ===== impl Echo =====
+
+    impl ReadyService for Echo {
+        type Request = Request<Vec<u8>>;
+        type Response = Response<Vec<u8>>;
+        type Error = ::Error;
+        type Future = FutureResult<Self::Response, ::Error>;
+
+        fn call(&mut self, request: Self::Request) -> Self::Future {
+            future::ok(Response::new(request.into_inner()))
+        }
+    }
+
+    #[test]
+    fn client_reports_unary_call() {
+        let recorder = Recorder::default();
+
+        let request = Hello { name: "ping".to_string() };
+        let reply = Hello { name: "pong!".to_string() };
+
+        let mut encoded = Vec::new();
+        reply.encode(&mut encoded).unwrap();
+
+        let service = MockService {
+            response: Some(http::Response::new(MockBody {
+                data: vec![frame(&encoded)].into(),
+                trailers: Some(ok_trailers()),
+            })),
+        };
+
+        let mut client = client::Builder::new()
+            .uri("http://example.com".parse().unwrap())
+            .stats_handler(recorder.clone())
+            .build(service)
+            .unwrap();
+
+        let response: ::Response<Hello> = future::lazy(|| {
+            client.unary(Request::new(request.clone()), "/test.Echo/Say".parse().unwrap())
+        }).wait().unwrap();
+
+        assert_eq!(response.into_inner(), reply);
+        assert_eq!(recorder.events(), vec![
+            Event::Started(Side::Client, "/test.Echo/Say".to_string()),
+            Event::HeadersSent,
+            Event::MessageSent(request.encoded_len()),
+            Event::HeadersReceived,
+            Event::MessageReceived(reply.encoded_len()),
+            Event::Ended(Code::OK),
+        ]);
+    }
+
+    #[test]
+    fn client_reports_error_status_in_trailers() {
+        let recorder = Recorder::default();
+
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", Status::NOT_FOUND.to_header_value());
+
+        let service = MockService {
+            response: Some(http::Response::new(MockBody {
+                data: VecDeque::new(),
+                trailers: Some(trailers),
+            })),
+        };
+
+        let mut client = client::Builder::new()
+            .uri("http://example.com".parse().unwrap())
+            .stats_handler(recorder.clone())
+            .build(service)
+            .unwrap();
+
+        let response = future::lazy(|| {
+            client.server_streaming::<Hello, Hello>(Request::new(Hello::default()),
+                                                    "/test.Echo/List".parse().unwrap())
+        }).wait().unwrap();
+
+        let messages = future::lazy(move || response.into_inner().collect()).wait();
+
+        match messages {
+            Err(::Error::Grpc(status)) => assert_eq!(status.code(), Code::NOT_FOUND),
+            _ => panic!("expected NOT_FOUND"),
+        }
+
+        assert_eq!(recorder.events(), vec![
+            Event::Started(Side::Client, "/test.Echo/List".to_string()),
+            Event::HeadersSent,
+            Event::MessageSent(0),
+            Event::HeadersReceived,
+            Event::Ended(Code::NOT_FOUND),
+        ]);
+    }
+
+    #[test]
+    fn server_reports_unary_call() {
+        let recorder = Recorder::default();
+
+        let mut request = http::Request::builder()
+            .uri("/test.Echo/Say")
+            .body(MockBody {
+                data: vec![frame(b"ping")].into(),
+                trailers: None,
+            })
+            .unwrap();
+        request.extensions_mut().insert(Handler::new(recorder.clone()));
+
+        let response = Grpc::new(BytesCodec).unary(Echo, request);
+
+        future::lazy(move || {
+            let mut body = response.wait().unwrap().into_body();
+
+            assert_eq!(body.poll_data().unwrap(), Async::Ready(Some(frame(b"ping"))));
+            assert_eq!(body.poll_data().unwrap(), Async::Ready(None));
+            assert!(body.poll_trailers().unwrap().is_ready());
+
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+
+        assert_eq!(recorder.events(), vec![
+            Event::Started(Side::Server, "/test.Echo/Say".to_string()),
+            Event::HeadersReceived,
+            Event::MessageReceived(4),
+            Event::HeadersSent,
+            Event::MessageSent(4),
+            Event::Ended(Code::OK),
+        ]);
+    }
+
+    #[test]
+    fn dropped_call_ends_as_canceled() {
+        let recorder = Recorder::default();
+
+        let stats = CallStats::new(&Handler::new(recorder.clone()), Side::Client, "/test.Echo/Say");
+        stats.message_sent(3);
+        drop(stats);
+
+        assert_eq!(recorder.events(), vec![
+            Event::Started(Side::Client, "/test.Echo/Say".to_string()),
+            Event::MessageSent(3),
+            Event::Ended(Code::CANCELED),
+        ]);
+    }
+}
@@ -0,0 +1,170 @@
+use std::{cmp, fmt};
+use std::time::Duration;
+
+/// Bits of each value's magnitude kept exactly; values are grouped into
+/// buckets within about 3% of each other.
+const SUB_BITS: u32 = 5;
+const SUB_BUCKETS: usize = 1 << SUB_BITS;
+
+/// Enough buckets for every `u64` value.
+const BUCKETS: usize = SUB_BUCKETS * (64 - SUB_BITS as usize + 1);
+
+/// A latency histogram with microsecond resolution and buckets of bounded
+/// relative width, in the manner of HDR histograms.
+///
+/// Latencies below 32µs are recorded exactly; larger ones are recorded
+/// within about 3% of their value.
+#[derive(Clone)]
+pub struct Histogram {
+    counts: Vec<u64>,
+    count: u64,
+    total: u64,
+    min: u64,
+    max: u64,
+}
+
+// ===== impl Histogram =====
+
+impl Histogram {
+    pub fn new() -> Self {
+        Histogram {
+            counts: vec![0; BUCKETS],
+            count: 0,
+            total: 0,
+            min: u64::max_value(),
+            max: 0,
+        }
+    }
+
+    /// Record a latency.
+    pub fn record(&mut self, latency: Duration) {
+        let micros = micros(latency);
+
+        self.counts[index(micros)] += 1;
+        self.count += 1;
+        self.total = self.total.saturating_add(micros);
+        self.min = cmp::min(self.min, micros);
+        self.max = cmp::max(self.max, micros);
+    }
+
+    /// Add the latencies recorded in `other`.
+    pub fn merge(&mut self, other: &Histogram) {
+        for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+            *count += *other;
+        }
+
+        self.count += other.count;
+        self.total = self.total.saturating_add(other.total);
+        self.min = cmp::min(self.min, other.min);
+        self.max = cmp::max(self.max, other.max);
+    }
+
+    /// Forget every recorded latency.
+    pub fn reset(&mut self) {
+        *self = Histogram::new();
+    }
+
+    /// Returns the number of latencies recorded.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn min(&self) -> Duration {
+        if self.count == 0 {
+            return Duration::default();
+        }
+
+        duration(self.min)
+    }
+
+    pub fn max(&self) -> Duration {
+        duration(self.max)
+    }
+
+    pub fn mean(&self) -> Duration {
+        if self.count == 0 {
+            return Duration::default();
+        }
+
+        duration(self.total / self.count)
+    }
+
+    /// Returns the latency below which `percentile` percent of the recorded
+    /// latencies fall, such as 99.0 for the p99.
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        if self.count == 0 {
+            return Duration::default();
+        }
+
+        let percentile = percentile.max(0.0).min(100.0);
+        let rank = ((percentile / 100.0) * self.count as f64).ceil() as u64;
+        let rank = cmp::max(rank, 1);
+
+        let mut seen = 0;
+        for (i, &count) in self.counts.iter().enumerate() {
+            seen += count;
+
+            if seen >= rank {
+                let value = cmp::max(self.min, cmp::min(self.max, value(i)));
+                return duration(value);
+            }
+        }
+
+        duration(self.max)
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram::new()
+    }
+}
+
+impl fmt::Debug for Histogram {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Histogram")
+            .field("count", &self.count)
+            .field("min", &self.min())
+            .field("p50", &self.percentile(50.0))
+            .field("p99", &self.percentile(99.0))
+            .field("max", &self.max())
+            .finish()
+    }
+}
+
+// ===== utility fns =====
+
+/// Returns the bucket `value` is counted in.
+fn index(value: u64) -> usize {
+    if value < SUB_BUCKETS as u64 {
+        return value as usize;
+    }
+
+    let shift = 63 - value.leading_zeros() - SUB_BITS;
+    let sub = (value >> shift) as usize - SUB_BUCKETS;
+
+    SUB_BUCKETS + shift as usize * SUB_BUCKETS + sub
+}
+
+/// Returns the value in the middle of bucket `index`.
+fn value(index: usize) -> u64 {
+    if index < SUB_BUCKETS {
+        return index as u64;
+    }
+
+    let shift = (index - SUB_BUCKETS) / SUB_BUCKETS;
+    let sub = (index - SUB_BUCKETS) % SUB_BUCKETS;
+    let lower = ((SUB_BUCKETS + sub) as u64) << shift;
+
+    lower + ((1u64 << shift) >> 1)
+}
+
+fn micros(duration: Duration) -> u64 {
+    duration.as_secs()
+        .saturating_mul(1_000_000)
+        .saturating_add(duration.subsec_nanos() as u64 / 1_000)
+}
+
+fn duration(micros: u64) -> Duration {
+    Duration::new(micros / 1_000_000, (micros % 1_000_000) as u32 * 1_000)
+}
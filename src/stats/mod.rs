@@ -0,0 +1,263 @@
+//! Live per-method server statistics.
+//!
+//! `ServerStats` wraps a server's HTTP/2.0 service and counts, for each
+//! method, the calls started and completed (by status code), the messages
+//! sent and received, and a histogram of the latency of completed calls,
+//! from which percentiles such as the p99 can be read. The counters are
+//! read through a `Registry`, either directly or with the `StatsService`
+//! debug service, and do not depend on any metrics backend.
+//!
+//! Every path requested is tracked, so servers exposed to untrusted clients
+//! should only wrap the services they route to.
+//!
+//! Other observability backends can implement `StatsHandler`, whose hooks
+//! are called at each step of a call's lifecycle by the codecs of clients
+//! built with `client::Builder::stats_handler` and of servers wrapped with
+//! `InstrumentServer`.
+//!
+//! `SampleErrors` keeps the metadata, status and timing of a sample of the
+//! calls that fail in an `ErrorSampler`, to help diagnose rare failures.
+
+mod errors;
+mod handler;
+mod histogram;
+mod track;
+#[cfg(feature = "protobuf")]
+mod debug;
+
+pub use self::errors::{
+    ErrorSampler,
+    ErrorSample,
+    SampleErrors,
+    SampleFuture,
+    SampleBody,
+    SampleNewServiceFuture,
+};
+pub use self::handler::{
+    StatsHandler,
+    CallInfo,
+    CallStats,
+    Side,
+    InstrumentServer,
+    InstrumentNewServiceFuture,
+};
+pub(crate) use self::handler::Handler;
+pub use self::histogram::Histogram;
+pub use self::track::{ServerStats, ResponseFuture, ResponseBody, NewServiceFuture};
+#[cfg(feature = "protobuf")]
+pub use self::debug::{
+    StatsService,
+    ResponseFuture as StatsResponseFuture,
+    ResponseBody as StatsResponseBody,
+    GET_STATS_PATH,
+    GET_ERROR_SAMPLES_PATH,
+    GetStatsRequest,
+    GetStatsResponse,
+    MethodStats,
+    CodeCount,
+    Latency,
+    GetErrorSamplesRequest,
+    GetErrorSamplesResponse,
+    ErrorSampleProto,
+    MetadataEntry,
+};
+
+use Code;
+
+use std::collections::BTreeMap;
+use std::mem;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// The number of status codes defined by gRPC.
+const CODES: usize = 17;
+
+/// Holds the statistics of every method called on a server.
+#[derive(Debug, Clone, Default)]
+pub struct Registry {
+    methods: Arc<Mutex<BTreeMap<String, Arc<Method>>>>,
+}
+
+/// The statistics of a method at a point in time.
+#[derive(Debug, Clone)]
+pub struct MethodSnapshot {
+    /// The method's path, such as `/helloworld.Greeter/SayHello`.
+    pub method: String,
+
+    /// Calls started.
+    pub started: u64,
+
+    /// Calls completed, by status code. Codes with no calls are left out.
+    pub completed: Vec<(Code, u64)>,
+
+    /// Response messages sent.
+    pub messages_sent: u64,
+
+    /// Request messages received.
+    pub messages_received: u64,
+
+    /// Latency of completed calls.
+    pub latency: LatencySnapshot,
+
+    /// Histogram of the latency of completed calls.
+    pub histogram: Histogram,
+}
+
+/// Latency of completed calls at a point in time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LatencySnapshot {
+    pub count: u64,
+    pub min: Duration,
+    pub mean: Duration,
+    pub max: Duration,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+}
+
+/// The counters of a single method.
+#[derive(Debug, Default)]
+struct Method {
+    started: AtomicUsize,
+    completed: [AtomicUsize; CODES],
+    sent: AtomicUsize,
+    received: Arc<AtomicUsize>,
+    latency: Mutex<Histogram>,
+}
+
+// ===== impl Registry =====
+
+impl Registry {
+    pub fn new() -> Self {
+        Registry::default()
+    }
+
+    /// Returns the statistics of every method called so far, ordered by
+    /// path.
+    pub fn snapshot(&self) -> Vec<MethodSnapshot> {
+        self.lock()
+            .iter()
+            .map(|(path, method)| method.snapshot(path))
+            .collect()
+    }
+
+    /// Returns the statistics of the method at `path`, if it has been
+    /// called.
+    pub fn method(&self, path: &str) -> Option<MethodSnapshot> {
+        self.lock()
+            .get(path)
+            .map(|method| method.snapshot(path))
+    }
+
+    /// Forget the statistics of every method.
+    ///
+    /// Calls in flight when the registry is reset are not recorded.
+    pub fn reset(&self) {
+        self.lock().clear();
+    }
+
+    /// Returns the statistics of every method called so far, and resets
+    /// the registry.
+    pub fn take(&self) -> Vec<MethodSnapshot> {
+        let methods = mem::replace(&mut *self.lock(), BTreeMap::new());
+
+        methods.iter()
+            .map(|(path, method)| method.snapshot(path))
+            .collect()
+    }
+
+    /// Returns the counters of `path`, creating them if needed.
+    fn get(&self, path: &str) -> Arc<Method> {
+        let mut methods = self.lock();
+
+        if let Some(method) = methods.get(path) {
+            return method.clone();
+        }
+
+        let method = Arc::new(Method::default());
+        methods.insert(path.to_string(), method.clone());
+        method
+    }
+
+    fn lock(&self) -> MutexGuard<BTreeMap<String, Arc<Method>>> {
+        match self.methods.lock() {
+            Ok(methods) => methods,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+// ===== impl MethodSnapshot =====
+
+impl MethodSnapshot {
+    /// Returns the number of calls that have started but not completed.
+    pub fn in_flight(&self) -> u64 {
+        let completed: u64 = self.completed.iter().map(|&(_, n)| n).sum();
+        self.started.saturating_sub(completed)
+    }
+}
+
+// ===== impl Method =====
+
+impl Method {
+    fn start(&self) {
+        self.started.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn finish(&self, code: Code, latency: Duration) {
+        let index = code.as_i32() as usize;
+        let index = if index < CODES { index } else { Code::UNKNOWN.as_i32() as usize };
+        self.completed[index].fetch_add(1, Ordering::Relaxed);
+
+        let mut histogram = match self.latency.lock() {
+            Ok(histogram) => histogram,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        histogram.record(latency);
+    }
+
+    fn sent(&self, messages: usize) {
+        self.sent.fetch_add(messages, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self, path: &str) -> MethodSnapshot {
+        let completed = self.completed.iter()
+            .enumerate()
+            .filter_map(|(code, count)| {
+                let count = count.load(Ordering::Relaxed) as u64;
+                if count == 0 {
+                    return None;
+                }
+
+                Code::from_i32(code as i32).map(|code| (code, count))
+            })
+            .collect();
+
+        let histogram = match self.latency.lock() {
+            Ok(histogram) => histogram.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        };
+
+        let latency = LatencySnapshot {
+            count: histogram.count(),
+            min: histogram.min(),
+            mean: histogram.mean(),
+            max: histogram.max(),
+            p50: histogram.percentile(50.0),
+            p90: histogram.percentile(90.0),
+            p99: histogram.percentile(99.0),
+        };
+
+        MethodSnapshot {
+            method: path.to_string(),
+            started: self.started.load(Ordering::Relaxed) as u64,
+            completed,
+            messages_sent: self.sent.load(Ordering::Relaxed) as u64,
+            messages_received: self.received.load(Ordering::Relaxed) as u64,
+            latency,
+            histogram,
+        }
+    }
+}
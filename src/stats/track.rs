@@ -0,0 +1,260 @@
+use super::{Method, Registry};
+use generic::counter::{Frames, MessageCounters};
+use headers;
+use Code;
+
+use futures::{Future, Poll, Async};
+use h2;
+use http::{self, HeaderMap};
+use tower::{NewService, Service};
+use tower_h2::Body;
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Records the statistics of the calls served by the inner service.
+///
+/// `ServerStats` may wrap either a `Service` or the `NewService` given to
+/// `tower_h2::Server`, in which case every connection shares its registry.
+#[derive(Debug, Clone)]
+pub struct ServerStats<S> {
+    inner: S,
+    registry: Registry,
+}
+
+/// The response future returned by `ServerStats`.
+pub struct ResponseFuture<F> {
+    inner: F,
+    call: Option<Call>,
+}
+
+/// The response body returned by `ServerStats`.
+pub struct ResponseBody<B> {
+    inner: B,
+    call: Call,
+    frames: Frames,
+}
+
+/// Creates `ServerStats` services sharing a registry.
+#[derive(Debug)]
+pub struct NewServiceFuture<F> {
+    inner: F,
+    registry: Registry,
+}
+
+/// A call being served; completed as `CANCELED` if dropped before its
+/// status is known.
+#[derive(Debug)]
+struct Call {
+    method: Arc<Method>,
+    start: Instant,
+    done: bool,
+}
+
+// ===== impl ServerStats =====
+
+impl<S> ServerStats<S> {
+    /// Record the statistics of `inner` in a new registry.
+    pub fn new(inner: S) -> Self {
+        ServerStats::with_registry(inner, Registry::new())
+    }
+
+    /// Record the statistics of `inner` in `registry`.
+    pub fn with_registry(inner: S, registry: Registry) -> Self {
+        ServerStats { inner, registry }
+    }
+
+    /// Returns the registry statistics are recorded in.
+    pub fn registry(&self) -> Registry {
+        self.registry.clone()
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner service.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+}
+
+impl<S, A, B> Service for ServerStats<S>
+where S: Service<Request = http::Request<A>, Response = http::Response<B>>,
+      B: Body,
+      B::Data: AsRef<[u8]>,
+{
+    type Request = S::Request;
+    type Response = http::Response<ResponseBody<B>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, mut request: Self::Request) -> Self::Future {
+        let method = self.registry.get(request.uri().path());
+        method.start();
+
+        // Received messages are counted as the server decodes them.
+        MessageCounters::register(&mut request, method.received.clone());
+
+        ResponseFuture {
+            inner: self.inner.call(request),
+            call: Some(Call {
+                method,
+                start: Instant::now(),
+                done: false,
+            }),
+        }
+    }
+}
+
+impl<S, A, B> NewService for ServerStats<S>
+where S: NewService<Request = http::Request<A>, Response = http::Response<B>>,
+      B: Body,
+      B::Data: AsRef<[u8]>,
+{
+    type Request = S::Request;
+    type Response = http::Response<ResponseBody<B>>;
+    type Error = S::Error;
+    type Service = ServerStats<S::Service>;
+    type InitError = S::InitError;
+    type Future = NewServiceFuture<S::Future>;
+
+    fn new_service(&self) -> Self::Future {
+        NewServiceFuture {
+            inner: self.inner.new_service(),
+            registry: self.registry.clone(),
+        }
+    }
+}
+
+// ===== impl NewServiceFuture =====
+
+impl<F> Future for NewServiceFuture<F>
+where F: Future,
+{
+    type Item = ServerStats<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        Ok(Async::Ready(ServerStats::with_registry(inner, self.registry.clone())))
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F, B> Future for ResponseFuture<F>
+where F: Future<Item = http::Response<B>>,
+{
+    type Item = http::Response<ResponseBody<B>>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let response = match self.inner.poll() {
+            Ok(Async::Ready(response)) => response,
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Err(e) => {
+                if let Some(mut call) = self.call.take() {
+                    call.finish(Code::UNKNOWN);
+                }
+                return Err(e);
+            }
+        };
+
+        let mut call = self.call.take().expect("polled after complete");
+
+        // Trailers-only responses carry the status in the headers.
+        if let Some(code) = headers::status_code(response.headers()) {
+            call.finish(code);
+        }
+
+        let (head, inner) = response.into_parts();
+        let body = ResponseBody {
+            inner,
+            call,
+            frames: Frames::default(),
+        };
+
+        Ok(Async::Ready(http::Response::from_parts(head, body)))
+    }
+}
+
+impl<F> fmt::Debug for ResponseFuture<F>
+where F: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("stats::ResponseFuture")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+// ===== impl ResponseBody =====
+
+impl<B> Body for ResponseBody<B>
+where B: Body,
+      B::Data: AsRef<[u8]>,
+{
+    type Data = B::Data;
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, h2::Error> {
+        let data = try_ready!(self.inner.poll_data());
+
+        if let Some(ref data) = data {
+            let messages = self.frames.count(data.as_ref());
+            self.call.method.sent(messages);
+        }
+
+        Ok(Async::Ready(data))
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, h2::Error> {
+        let trailers = try_ready!(self.inner.poll_trailers());
+
+        let code = trailers.as_ref()
+            .and_then(headers::status_code)
+            .unwrap_or(Code::UNKNOWN);
+        self.call.finish(code);
+
+        Ok(Async::Ready(trailers))
+    }
+}
+
+impl<B> fmt::Debug for ResponseBody<B>
+where B: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("stats::ResponseBody")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+// ===== impl Call =====
+
+impl Call {
+    fn finish(&mut self, code: Code) {
+        if self.done {
+            return;
+        }
+
+        self.done = true;
+        self.method.finish(code, self.start.elapsed());
+    }
+}
+
+impl Drop for Call {
+    fn drop(&mut self) {
+        self.finish(Code::CANCELED);
+    }
+}
@@ -12,6 +12,11 @@ pub struct Status {
 pub struct Code(Code_);
 
 impl Status {
+    /// Returns a status with the given code.
+    pub fn with_code(code: Code) -> Status {
+        Status::new(code)
+    }
+
     #[inline]
     pub fn code(&self) -> Code {
         self.code
@@ -164,6 +169,15 @@ impl From<h2::Error> for Status {
     }
 }
 
+impl From<::Error> for Status {
+    fn from(err: ::Error) -> Self {
+        match err {
+            ::Error::Grpc(status) => status,
+            ::Error::Inner(()) => Status::new(Code(Code_::Internal)),
+        }
+    }
+}
+
 impl From<Status> for h2::Error {
     fn from(_status: Status) -> Self {
         // TODO: implement
@@ -173,7 +187,55 @@ impl From<Status> for h2::Error {
 
 impl Code {
     pub const OK: Code = Code(Code_::Ok);
-    //TODO: the rest...
+    pub const CANCELED: Code = Code(Code_::Canceled);
+    pub const UNKNOWN: Code = Code(Code_::Unknown);
+    pub const INVALID_ARGUMENT: Code = Code(Code_::InvalidArgument);
+    pub const DEADLINE_EXCEEDED: Code = Code(Code_::DeadlineExceeded);
+    pub const NOT_FOUND: Code = Code(Code_::NotFound);
+    pub const ALREADY_EXISTS: Code = Code(Code_::AlreadyExists);
+    pub const PERMISSION_DENIED: Code = Code(Code_::PermissionDenied);
+    pub const RESOURCE_EXHAUSTED: Code = Code(Code_::ResourceExhausted);
+    pub const FAILED_PRECONDITION: Code = Code(Code_::FailedPrecondition);
+    pub const ABORTED: Code = Code(Code_::Aborted);
+    pub const OUT_OF_RANGE: Code = Code(Code_::OutOfRange);
+    pub const UNIMPLEMENTED: Code = Code(Code_::Unimplemented);
+    pub const INTERNAL: Code = Code(Code_::Internal);
+    pub const UNAVAILABLE: Code = Code(Code_::Unavailable);
+    pub const DATA_LOSS: Code = Code(Code_::DataLoss);
+    pub const UNAUTHENTICATED: Code = Code(Code_::Unauthenticated);
+
+    /// Returns the code with the given numeric value.
+    pub fn from_i32(value: i32) -> Option<Code> {
+        use self::Code_::*;
+
+        let code = match value {
+            0 => Ok,
+            1 => Canceled,
+            2 => Unknown,
+            3 => InvalidArgument,
+            4 => DeadlineExceeded,
+            5 => NotFound,
+            6 => AlreadyExists,
+            7 => PermissionDenied,
+            8 => ResourceExhausted,
+            9 => FailedPrecondition,
+            10 => Aborted,
+            11 => OutOfRange,
+            12 => Unimplemented,
+            13 => Internal,
+            14 => Unavailable,
+            15 => DataLoss,
+            16 => Unauthenticated,
+            _ => return None,
+        };
+
+        Some(Code(code))
+    }
+
+    /// Returns the code's numeric value.
+    pub fn as_i32(&self) -> i32 {
+        self.0 as i32
+    }
 }
 
 impl fmt::Debug for Code {
@@ -0,0 +1,209 @@
+//! Combinators for streams of messages.
+//!
+//! `MessageStream` adds combinators to the `Streaming` request and response
+//! types, and to the streams that handlers respond with:
+//!
+//! ```ignore
+//! let deadline = request.timeout().map(|timeout| Instant::now() + timeout);
+//!
+//! let updates = watch(request.into_inner())
+//!     .map_message(Update::from)
+//!     .inspect_message(|update| debug!("sending update; update={:?}", update))
+//!     .take_until_deadline(deadline);
+//! ```
+
+use Status;
+
+use futures::{Future, Stream, Poll, Async};
+use tokio_timer::{Sleep, Timer};
+
+use std::fmt;
+use std::time::Instant;
+
+/// Combinators for streams of gRPC messages.
+pub trait MessageStream: Stream {
+    /// Converts each message with `f`.
+    fn map_message<F, U>(self, f: F) -> MapMessage<Self, F>
+    where F: FnMut(Self::Item) -> U,
+          Self: Sized,
+    {
+        MapMessage { inner: self, f }
+    }
+
+    /// Calls `f` with a reference to each message.
+    fn inspect_message<F>(self, f: F) -> InspectMessage<Self, F>
+    where F: FnMut(&Self::Item),
+          Self: Sized,
+    {
+        InspectMessage { inner: self, f }
+    }
+
+    /// Fails the stream with `DEADLINE_EXCEEDED` once `deadline` passes.
+    ///
+    /// The stream is not limited if there is no deadline.
+    fn take_until_deadline(self, deadline: Option<Instant>) -> TakeUntil<Self, Sleep>
+    where Self::Error: From<Status>,
+          Self: Sized,
+    {
+        let sleep = deadline.map(|deadline| {
+            let now = Instant::now();
+            let timeout = if deadline > now { deadline - now } else { Default::default() };
+
+            Timer::default().sleep(timeout)
+        });
+
+        TakeUntil {
+            inner: self,
+            signal: sleep,
+            status: Status::DEADLINE_EXCEEDED,
+            done: false,
+        }
+    }
+
+    /// Fails the stream with `CANCELED` once `cancel` completes.
+    ///
+    /// Any future can serve as a cancellation token, such as the receiving
+    /// end of a `futures::sync::oneshot` channel.
+    fn take_until_canceled<F>(self, cancel: F) -> TakeUntil<Self, F>
+    where F: Future,
+          Self::Error: From<Status>,
+          Self: Sized,
+    {
+        TakeUntil {
+            inner: self,
+            signal: Some(cancel),
+            status: Status::CANCELED,
+            done: false,
+        }
+    }
+}
+
+impl<S: Stream> MessageStream for S {}
+
+/// Stream returned by `MessageStream::map_message`.
+#[must_use = "streams do nothing unless polled"]
+pub struct MapMessage<S, F> {
+    inner: S,
+    f: F,
+}
+
+/// Stream returned by `MessageStream::inspect_message`.
+#[must_use = "streams do nothing unless polled"]
+pub struct InspectMessage<S, F> {
+    inner: S,
+    f: F,
+}
+
+/// Stream returned by `MessageStream::take_until_deadline` and
+/// `MessageStream::take_until_canceled`.
+#[must_use = "streams do nothing unless polled"]
+pub struct TakeUntil<S, F> {
+    inner: S,
+    signal: Option<F>,
+    status: Status,
+    done: bool,
+}
+
+// ===== impl MapMessage =====
+
+impl<S, F, U> Stream for MapMessage<S, F>
+where S: Stream,
+      F: FnMut(S::Item) -> U,
+{
+    type Item = U;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<U>, S::Error> {
+        let msg = try_ready!(self.inner.poll());
+        Ok(Async::Ready(msg.map(&mut self.f)))
+    }
+}
+
+impl<S, F> fmt::Debug for MapMessage<S, F>
+where S: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("MapMessage")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+// ===== impl InspectMessage =====
+
+impl<S, F> Stream for InspectMessage<S, F>
+where S: Stream,
+      F: FnMut(&S::Item),
+{
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<S::Item>, S::Error> {
+        let msg = try_ready!(self.inner.poll());
+
+        if let Some(ref msg) = msg {
+            (self.f)(msg);
+        }
+
+        Ok(Async::Ready(msg))
+    }
+}
+
+impl<S, F> fmt::Debug for InspectMessage<S, F>
+where S: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("InspectMessage")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+// ===== impl TakeUntil =====
+
+impl<S, F> Stream for TakeUntil<S, F>
+where S: Stream,
+      S::Error: From<Status>,
+      F: Future,
+{
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<S::Item>, S::Error> {
+        if self.done {
+            return Ok(Async::Ready(None));
+        }
+
+        let fired = match self.signal {
+            Some(ref mut signal) => match signal.poll() {
+                Ok(Async::NotReady) => false,
+                Ok(Async::Ready(_)) => true,
+                Err(_) => {
+                    debug!("stream signal failed; ending stream");
+                    true
+                }
+            },
+            None => false,
+        };
+
+        if fired {
+            self.done = true;
+            self.signal = None;
+            return Err(self.status.clone().into());
+        }
+
+        self.inner.poll()
+    }
+}
+
+impl<S, F> fmt::Debug for TakeUntil<S, F>
+where S: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("TakeUntil")
+            .field("inner", &self.inner)
+            .field("status", &self.status)
+            .field("done", &self.done)
+            .finish()
+    }
+}
@@ -0,0 +1,137 @@
+//! Strictness of the decoding of received messages.
+//!
+//! By default, received frames are decoded strictly: any deviation from the
+//! gRPC specification, such as unknown bits in the compressed flag or bytes
+//! left over after the last frame, fails the call with `INTERNAL`. Older or
+//! buggy peers sometimes make such benign mistakes, and decoding leniently
+//! tolerates them instead.
+//!
+//! Servers set the strictness of their calls by wrapping their service
+//! with `DecodeStrictness`:
+//!
+//! ```ignore
+//! let new_service = DecodeStrictness::new(new_service, Strictness::Lenient);
+//! let h2 = Server::new(new_service, Default::default(), handle.clone());
+//! ```
+//!
+//! Channels set the strictness of the responses they receive with
+//! `Channel::strictness`.
+
+use futures::{Future, Poll, Async};
+use http;
+use tower::{NewService, Service};
+
+/// How strictly received frames are decoded.
+///
+/// This is also a request and response extension, read by the codec.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strictness {
+    /// Fail on any deviation from the specification.
+    Strict,
+
+    /// Tolerate benign deviations: only the lowest bit of the compressed
+    /// flag is read, and an incomplete frame at the end of the stream is
+    /// discarded.
+    Lenient,
+}
+
+/// Sets the strictness of the decoding of the inner service's requests.
+///
+/// `DecodeStrictness` may wrap either a `Service` or the `NewService` given
+/// to `tower_h2::Server`.
+#[derive(Debug, Clone)]
+pub struct DecodeStrictness<S> {
+    inner: S,
+    strictness: Strictness,
+}
+
+/// Creates `DecodeStrictness` services.
+#[derive(Debug)]
+pub struct NewServiceFuture<F> {
+    inner: F,
+    strictness: Strictness,
+}
+
+// ===== impl Strictness =====
+
+impl Default for Strictness {
+    fn default() -> Self {
+        Strictness::Strict
+    }
+}
+
+// ===== impl DecodeStrictness =====
+
+impl<S> DecodeStrictness<S> {
+    /// Decode the requests of `inner` with `strictness`.
+    pub fn new(inner: S, strictness: Strictness) -> Self {
+        DecodeStrictness {
+            inner,
+            strictness,
+        }
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner service.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+}
+
+impl<S, A> Service for DecodeStrictness<S>
+where S: Service<Request = http::Request<A>>,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, mut request: Self::Request) -> Self::Future {
+        request.extensions_mut().insert(self.strictness);
+        self.inner.call(request)
+    }
+}
+
+impl<S, A> NewService for DecodeStrictness<S>
+where S: NewService<Request = http::Request<A>>,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Service = DecodeStrictness<S::Service>;
+    type InitError = S::InitError;
+    type Future = NewServiceFuture<S::Future>;
+
+    fn new_service(&self) -> Self::Future {
+        NewServiceFuture {
+            inner: self.inner.new_service(),
+            strictness: self.strictness,
+        }
+    }
+}
+
+// ===== impl NewServiceFuture =====
+
+impl<F> Future for NewServiceFuture<F>
+where F: Future,
+{
+    type Item = DecodeStrictness<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+
+        Ok(Async::Ready(DecodeStrictness {
+            inner,
+            strictness: self.strictness,
+        }))
+    }
+}
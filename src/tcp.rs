@@ -0,0 +1,387 @@
+//! TCP connections.
+//!
+//! `TcpOptions` configures the sockets of client and server connections.
+//! The defaults suit gRPC: Nagle's algorithm is disabled, and keepalive and
+//! buffer sizes are left to the operating system.
+//!
+//! Clients dial an endpoint's alternative addresses with happy eyeballs
+//! (RFC 8305): attempts alternate between IPv6 and IPv4, each started when
+//! the previous one fails or after a short delay, and the first connection
+//! to succeed is used.
+//!
+//! `TcpConnect` connects a `Channel` to its endpoints over plaintext TCP,
+//! speaking HTTP/2.0 with prior knowledge (h2c, see `h2c`).
+//! The TLS and handshake connectors accept the same options with their
+//! `tcp_options` builders. Servers apply the options to each accepted
+//! connection:
+//!
+//! ```ignore
+//! let options = TcpOptions::new().keepalive(Some(Duration::from_secs(60)));
+//!
+//! let serve = listener.incoming().for_each(move |(sock, _)| {
+//!     options.apply(&sock)?;
+//!     handle.spawn(h2.serve(sock).map_err(|e| error!("h2 error: {:?}", e)));
+//!     Ok(())
+//! });
+//! ```
+
+use channel::{self, ChannelStats, CountedIo, Endpoint};
+use http2::Http2Settings;
+
+use futures::{Future, Poll, Async};
+use tokio_core::net::{TcpStream, TcpStreamNew};
+use tokio_core::reactor::Handle;
+use tokio_timer::{Sleep, Timer};
+use tower_h2::BoxBody;
+use tower_h2::client::{Connection, Handshake, HandshakeError};
+
+use std::{fmt, io};
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// The delay before dialing the next address, recommended by RFC 8305.
+const DEFAULT_ATTEMPT_DELAY_MILLIS: u64 = 250;
+
+/// Options set on TCP sockets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TcpOptions {
+    nodelay: bool,
+    keepalive: Option<Duration>,
+    send_buffer_size: Option<usize>,
+    recv_buffer_size: Option<usize>,
+    attempt_delay: Duration,
+}
+
+/// Dials an endpoint's addresses, using the first connection to succeed.
+pub struct Dial {
+    addrs: VecDeque<SocketAddr>,
+    attempts: Vec<TcpStreamNew>,
+    delay: Option<Sleep>,
+    attempt_delay: Duration,
+    handle: Handle,
+    timer: Option<Timer>,
+    error: Option<io::Error>,
+}
+
+/// Connects a `Channel` to its endpoints over plaintext TCP.
+#[derive(Debug, Clone)]
+pub struct TcpConnect {
+    options: TcpOptions,
+    handle: Handle,
+    stats: ChannelStats,
+    http2: Http2Settings,
+}
+
+/// Future returned by `TcpConnect`.
+pub struct ConnectFuture {
+    state: State,
+    connect: TcpConnect,
+}
+
+/// Error produced by `TcpConnect`.
+#[derive(Debug)]
+pub enum ConnectError {
+    /// The TCP connection failed, or its options could not be set.
+    Connect(io::Error),
+
+    /// The HTTP/2.0 handshake failed.
+    Http2(HandshakeError),
+}
+
+enum State {
+    Connecting(Dial),
+    Http2(Handshake<CountedIo<TcpStream>, Handle, BoxBody>),
+}
+
+// ===== impl TcpOptions =====
+
+impl TcpOptions {
+    pub fn new() -> Self {
+        TcpOptions {
+            nodelay: true,
+            keepalive: None,
+            send_buffer_size: None,
+            recv_buffer_size: None,
+            attempt_delay: Duration::from_millis(DEFAULT_ATTEMPT_DELAY_MILLIS),
+        }
+    }
+
+    /// Set whether `TCP_NODELAY` is set, disabling Nagle's algorithm.
+    ///
+    /// Enabled by default.
+    pub fn nodelay(mut self, nodelay: bool) -> Self {
+        self.nodelay = nodelay;
+        self
+    }
+
+    /// Set `SO_KEEPALIVE`, probing connections that have been idle for
+    /// `keepalive`, or disable it with `None`.
+    pub fn keepalive(mut self, keepalive: Option<Duration>) -> Self {
+        self.keepalive = keepalive;
+        self
+    }
+
+    /// Set the size of the socket's send buffer, `SO_SNDBUF`.
+    pub fn send_buffer_size(mut self, size: usize) -> Self {
+        self.send_buffer_size = Some(size);
+        self
+    }
+
+    /// Set the size of the socket's receive buffer, `SO_RCVBUF`.
+    pub fn recv_buffer_size(mut self, size: usize) -> Self {
+        self.recv_buffer_size = Some(size);
+        self
+    }
+
+    /// Set how long to wait for an address to connect before also dialing
+    /// the endpoint's next address.
+    ///
+    /// 250 milliseconds by default.
+    pub fn attempt_delay(mut self, delay: Duration) -> Self {
+        self.attempt_delay = delay;
+        self
+    }
+
+    /// Connect to `endpoint`, on the reactor of `handle`.
+    ///
+    /// The options are not set on the connection; use `apply`.
+    pub fn dial(&self, endpoint: &Endpoint, handle: &Handle) -> Dial {
+        Dial::new(endpoint, self.attempt_delay, handle.clone())
+    }
+
+    /// Set the options on `sock`.
+    pub fn apply(&self, sock: &TcpStream) -> io::Result<()> {
+        sock.set_nodelay(self.nodelay)?;
+        sock.set_keepalive(self.keepalive)?;
+
+        if let Some(size) = self.send_buffer_size {
+            sock.set_send_buffer_size(size)?;
+        }
+
+        if let Some(size) = self.recv_buffer_size {
+            sock.set_recv_buffer_size(size)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for TcpOptions {
+    fn default() -> Self {
+        TcpOptions::new()
+    }
+}
+
+// ===== impl Dial =====
+
+impl Dial {
+    fn new(endpoint: &Endpoint, attempt_delay: Duration, handle: Handle) -> Self {
+        let mut dial = Dial {
+            addrs: interleave(*endpoint.addr(), endpoint.alternatives()),
+            attempts: vec![],
+            delay: None,
+            attempt_delay,
+            handle,
+            timer: None,
+            error: None,
+        };
+
+        dial.attempt();
+        dial
+    }
+
+    /// Dial the next address, and wait before dialing the one after it.
+    fn attempt(&mut self) {
+        let addr = match self.addrs.pop_front() {
+            Some(addr) => addr,
+            None => return,
+        };
+
+        trace!("dialing; addr={}", addr);
+        self.attempts.push(TcpStream::connect(&addr, &self.handle));
+
+        self.delay = if self.addrs.is_empty() {
+            None
+        } else {
+            let delay = self.attempt_delay;
+            Some(self.timer.get_or_insert_with(Timer::default).sleep(delay))
+        };
+    }
+}
+
+impl Future for Dial {
+    type Item = TcpStream;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<TcpStream, io::Error> {
+        loop {
+            let mut failed = false;
+            let mut i = 0;
+
+            while i < self.attempts.len() {
+                match self.attempts[i].poll() {
+                    Ok(Async::Ready(sock)) => return Ok(Async::Ready(sock)),
+                    Ok(Async::NotReady) => i += 1,
+                    Err(e) => {
+                        debug!("dial failed; error={}", e);
+                        self.attempts.swap_remove(i);
+                        self.error = Some(e);
+                        failed = true;
+                    }
+                }
+            }
+
+            if self.addrs.is_empty() {
+                if self.attempts.is_empty() {
+                    let error = self.error.take()
+                        .unwrap_or_else(|| io::Error::new(io::ErrorKind::Other, "no address to dial"));
+                    return Err(error);
+                }
+
+                return Ok(Async::NotReady);
+            }
+
+            // An attempt failed, or the previous attempt has been waited
+            // on for long enough: dial the next address.
+            let elapsed = match self.delay {
+                Some(ref mut delay) => match delay.poll() {
+                    Ok(Async::NotReady) => false,
+                    Ok(Async::Ready(())) => true,
+                    Err(e) => {
+                        debug!("dial delay failed; error={:?}", e);
+                        true
+                    }
+                },
+                None => true,
+            };
+
+            if !failed && !elapsed {
+                return Ok(Async::NotReady);
+            }
+
+            self.attempt();
+        }
+    }
+}
+
+impl fmt::Debug for Dial {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Dial")
+            .field("remaining", &self.addrs)
+            .field("attempts", &self.attempts.len())
+            .finish()
+    }
+}
+
+// ===== impl TcpConnect =====
+
+impl TcpConnect {
+    /// Connections run on the reactor of `handle`.
+    pub fn new(handle: Handle) -> Self {
+        TcpConnect {
+            options: TcpOptions::new(),
+            handle,
+            stats: ChannelStats::new(),
+            http2: Http2Settings::new(),
+        }
+    }
+
+    /// Set `options` on each connection.
+    pub fn tcp_options(mut self, options: TcpOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Count the bytes of each connection in `stats`.
+    ///
+    /// Pass the same `ChannelStats` to `Channel::with_stats`.
+    pub fn with_stats(mut self, stats: ChannelStats) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    /// Negotiate each connection with `settings`.
+    pub fn http2_settings(mut self, settings: Http2Settings) -> Self {
+        self.http2 = settings;
+        self
+    }
+}
+
+impl channel::Connect for TcpConnect {
+    type Service = Connection<CountedIo<TcpStream>, Handle, BoxBody>;
+    type Error = ConnectError;
+    type Future = ConnectFuture;
+
+    fn connect(&mut self, endpoint: &Endpoint) -> Self::Future {
+        ConnectFuture {
+            state: State::Connecting(self.options.dial(endpoint, &self.handle)),
+            connect: self.clone(),
+        }
+    }
+}
+
+// ===== impl ConnectFuture =====
+
+impl Future for ConnectFuture {
+    type Item = Connection<CountedIo<TcpStream>, Handle, BoxBody>;
+    type Error = ConnectError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                State::Connecting(ref mut tcp) => {
+                    let tcp = try_ready!(tcp.poll().map_err(ConnectError::Connect));
+                    self.connect.options.apply(&tcp).map_err(ConnectError::Connect)?;
+
+                    let io = self.connect.stats.io(tcp);
+                    let builder = self.connect.http2.client_builder();
+                    State::Http2(Handshake::new(io, self.connect.handle.clone(), &builder))
+                }
+                State::Http2(ref mut h2) => {
+                    let conn = try_ready!(h2.poll().map_err(ConnectError::Http2));
+                    return Ok(Async::Ready(conn));
+                }
+            };
+
+            self.state = next;
+        }
+    }
+}
+
+impl fmt::Debug for ConnectFuture {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let state = match self.state {
+            State::Connecting(..) => "Connecting",
+            State::Http2(..) => "Http2",
+        };
+
+        fmt.debug_struct("ConnectFuture")
+            .field("state", &state)
+            .finish()
+    }
+}
+
+// ===== utility fns =====
+
+/// Order `addr` and its alternatives for dialing, alternating between
+/// address families, starting with the family of `addr`.
+fn interleave(addr: SocketAddr, alternatives: &[SocketAddr]) -> VecDeque<SocketAddr> {
+    let (mut same, mut other): (VecDeque<_>, VecDeque<_>) = alternatives.iter()
+        .cloned()
+        .filter(|a| *a != addr)
+        .partition(|a| a.is_ipv6() == addr.is_ipv6());
+
+    let mut addrs = VecDeque::with_capacity(alternatives.len() + 1);
+    addrs.push_back(addr);
+
+    loop {
+        match (other.pop_front(), same.pop_front()) {
+            (None, None) => return addrs,
+            (a, b) => {
+                addrs.extend(a);
+                addrs.extend(b);
+            }
+        }
+    }
+}
@@ -0,0 +1,522 @@
+//! Assertions for client tests.
+//!
+//! `assert_status!` checks the status a call ended with, whether it is a
+//! response, an error or a bare `Status`:
+//!
+//! ```ignore
+//! let result = core.run(client.get_feature(Request::new(point)));
+//! assert_status!(result, Code::NOT_FOUND);
+//! ```
+//!
+//! `MetadataMatcher` checks the metadata of requests and responses, and
+//! `CallRecorder` wraps a client's service to capture the calls it sends,
+//! so tests can assert on them afterwards:
+//!
+//! ```ignore
+//! let recorder = CallRecorder::new(conn);
+//! let mut client = client::Greeter::new(recorder.clone(), uri).unwrap();
+//!
+//! core.run(client.say_hello(request)).unwrap();
+//!
+//! let calls = recorder.calls();
+//! assert_eq!(calls[0].path(), "/helloworld.Greeter/SayHello");
+//! MetadataMatcher::new()
+//!     .value("authorization", "Bearer token")
+//!     .absent("x-debug")
+//!     .assert(calls[0].headers());
+//! ```
+//!
+//! With the `in-process` feature, `loopback` serves a service in memory and
+//! returns a generated client connected to it, with the `Harness` running
+//! both:
+//!
+//! ```ignore
+//! let (mut harness, mut client) = loopback::<_, _, client::Greeter<_>>(
+//!     server::GreeterServer::new(Greeter))?;
+//!
+//! let response = harness.run(client.say_hello(Request::new(HelloRequest::default())))?;
+//! ```
+
+use generic::{BytesCodec, FrameDecoder};
+#[cfg(feature = "in-process")]
+use client::{BuilderError, GeneratedClient};
+#[cfg(feature = "in-process")]
+use duplex::DuplexStream;
+#[cfg(feature = "in-process")]
+use inprocess::{self, Harness};
+use {Code, Status};
+
+use bytes::Bytes;
+use futures::Poll;
+use h2;
+use http::{self, HeaderMap};
+use tower::Service;
+use tower_h2::{Body, HttpService};
+#[cfg(feature = "in-process")]
+use tokio_core::reactor::Handle;
+#[cfg(feature = "in-process")]
+use tower::NewService;
+#[cfg(feature = "in-process")]
+use tower_h2::{BoxBody, RecvBody};
+#[cfg(feature = "in-process")]
+use tower_h2::client::{Connection, HandshakeError};
+
+use std::fmt;
+#[cfg(feature = "in-process")]
+use std::io;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Asserts that a call ended with the given status code.
+///
+/// The first argument may be a `Result` of a call, a `tower_grpc::Error` or
+/// a `Status`. Successful results have the code `OK`.
+#[macro_export]
+macro_rules! assert_status {
+    ($result:expr, $code:expr) => {
+        $crate::testing::assert_status(&$result, $code, None)
+    };
+    ($result:expr, $code:expr, $($arg:tt)+) => {
+        $crate::testing::assert_status(&$result, $code, Some(format_args!($($arg)+)))
+    };
+}
+
+/// The outcome of a call, as checked by `assert_status!`.
+pub trait CallStatus {
+    /// Returns the code the call ended with, or a description of the error
+    /// that ended it without a status.
+    fn code(&self) -> Result<Code, String>;
+}
+
+/// Checks the metadata of a request or response.
+#[derive(Debug, Clone, Default)]
+pub struct MetadataMatcher {
+    checks: Vec<Check>,
+}
+
+/// Records the calls sent through a client's service.
+///
+/// Clones share the recorded calls, so a clone may be kept to inspect the
+/// calls made through the client's copy.
+#[derive(Clone)]
+pub struct CallRecorder<S> {
+    inner: S,
+    calls: Arc<Mutex<Vec<RecordedCall>>>,
+}
+
+/// A call sent through a `CallRecorder`.
+#[derive(Debug, Clone)]
+pub struct RecordedCall {
+    path: String,
+    headers: HeaderMap,
+    messages: Vec<Vec<u8>>,
+
+    /// Set once the request stream ended.
+    ended: bool,
+}
+
+/// The request body sent by `CallRecorder`, recording each message.
+pub struct RecordBody<B> {
+    inner: B,
+    calls: Arc<Mutex<Vec<RecordedCall>>>,
+    index: usize,
+    frames: FrameDecoder<BytesCodec>,
+}
+
+/// The connection of a client returned by `loopback`.
+#[cfg(feature = "in-process")]
+pub type LoopbackConnection = Connection<DuplexStream, Handle, BoxBody>;
+
+/// The reasons `loopback` may fail.
+#[cfg(feature = "in-process")]
+#[derive(Debug)]
+pub enum LoopbackError {
+    /// The reactor could not be created.
+    Io(io::Error),
+
+    /// The HTTP/2.0 handshake failed.
+    Handshake(HandshakeError),
+
+    /// The client could not be built.
+    Client(BuilderError),
+}
+
+#[derive(Debug, Clone)]
+enum Check {
+    Present(String),
+    Absent(String),
+    Value(String, String),
+    Contains(String, String),
+}
+
+// ===== impl CallStatus =====
+
+impl CallStatus for Status {
+    fn code(&self) -> Result<Code, String> {
+        Ok(Status::code(self))
+    }
+}
+
+impl<E> CallStatus for ::Error<E>
+where E: fmt::Debug,
+{
+    fn code(&self) -> Result<Code, String> {
+        match *self {
+            ::Error::Grpc(ref status) => Ok(status.code()),
+            ::Error::Inner(ref e) => Err(format!("transport error {:?}", e)),
+        }
+    }
+}
+
+impl<T, E> CallStatus for Result<T, E>
+where E: CallStatus,
+{
+    fn code(&self) -> Result<Code, String> {
+        match *self {
+            Ok(_) => Ok(Code::OK),
+            Err(ref e) => e.code(),
+        }
+    }
+}
+
+impl<'a, T: CallStatus + ?Sized> CallStatus for &'a T {
+    fn code(&self) -> Result<Code, String> {
+        (**self).code()
+    }
+}
+
+// ===== impl MetadataMatcher =====
+
+impl MetadataMatcher {
+    /// A matcher accepting any metadata.
+    pub fn new() -> Self {
+        MetadataMatcher::default()
+    }
+
+    /// Require `key` to be present.
+    pub fn present(mut self, key: &str) -> Self {
+        self.checks.push(Check::Present(key.to_string()));
+        self
+    }
+
+    /// Require `key` to be absent.
+    pub fn absent(mut self, key: &str) -> Self {
+        self.checks.push(Check::Absent(key.to_string()));
+        self
+    }
+
+    /// Require a value of `key` to be `value`.
+    pub fn value(mut self, key: &str, value: &str) -> Self {
+        self.checks.push(Check::Value(key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Require a value of `key` to contain `part`, such as a product in a
+    /// `user-agent`.
+    pub fn contains(mut self, key: &str, part: &str) -> Self {
+        self.checks.push(Check::Contains(key.to_string(), part.to_string()));
+        self
+    }
+
+    /// Returns the checks `headers` fails, described.
+    pub fn check(&self, headers: &HeaderMap) -> Result<(), Vec<String>> {
+        let failures: Vec<_> = self.checks.iter()
+            .filter_map(|check| check.failure(headers))
+            .collect();
+
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(failures)
+        }
+    }
+
+    /// Returns true if `headers` passes every check.
+    pub fn matches(&self, headers: &HeaderMap) -> bool {
+        self.check(headers).is_ok()
+    }
+
+    /// Panics with the checks `headers` fails.
+    pub fn assert(&self, headers: &HeaderMap) {
+        if let Err(failures) = self.check(headers) {
+            panic!("metadata does not match:\n{}\nmetadata: {:?}", failures.join("\n"), headers);
+        }
+    }
+}
+
+// ===== impl Check =====
+
+impl Check {
+    /// Returns a description of the failure, if `headers` fails the check.
+    fn failure(&self, headers: &HeaderMap) -> Option<String> {
+        let values = |key: &str| -> Vec<String> {
+            headers.get_all(key).iter()
+                .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+                .collect()
+        };
+
+        match *self {
+            Check::Present(ref key) => {
+                if headers.contains_key(key.as_str()) {
+                    return None;
+                }
+
+                Some(format!("expected {} to be present", key))
+            }
+            Check::Absent(ref key) => {
+                if !headers.contains_key(key.as_str()) {
+                    return None;
+                }
+
+                Some(format!("expected {} to be absent, got {:?}", key, values(key)))
+            }
+            Check::Value(ref key, ref value) => {
+                let values = values(key);
+
+                if values.iter().any(|v| v == value) {
+                    return None;
+                }
+
+                Some(format!("expected {} to be {:?}, got {:?}", key, value, values))
+            }
+            Check::Contains(ref key, ref part) => {
+                let values = values(key);
+
+                if values.iter().any(|v| v.contains(part.as_str())) {
+                    return None;
+                }
+
+                Some(format!("expected {} to contain {:?}, got {:?}", key, part, values))
+            }
+        }
+    }
+}
+
+// ===== impl CallRecorder =====
+
+impl<S> CallRecorder<S> {
+    /// Record the calls sent through `inner`.
+    pub fn new(inner: S) -> Self {
+        CallRecorder {
+            inner,
+            calls: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    /// Returns the calls sent so far, in order.
+    pub fn calls(&self) -> Vec<RecordedCall> {
+        self.lock().clone()
+    }
+
+    /// Returns the calls sent so far to the method at `path`, such as
+    /// `/helloworld.Greeter/SayHello`.
+    pub fn calls_to(&self, path: &str) -> Vec<RecordedCall> {
+        self.lock().iter()
+            .filter(|call| call.path == path)
+            .cloned()
+            .collect()
+    }
+
+    /// Forget the calls sent so far.
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner service.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    fn lock(&self) -> MutexGuard<Vec<RecordedCall>> {
+        lock(&self.calls)
+    }
+}
+
+impl<S, B> Service for CallRecorder<S>
+where S: HttpService<RequestBody = RecordBody<B>>,
+      B: Body<Data = Bytes>,
+{
+    type Request = http::Request<B>;
+    type Response = http::Response<S::ResponseBody>;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, request: Self::Request) -> Self::Future {
+        let (head, body) = request.into_parts();
+
+        let index = {
+            let mut calls = self.lock();
+
+            calls.push(RecordedCall {
+                path: head.uri.path().to_string(),
+                headers: head.headers.clone(),
+                messages: vec![],
+                ended: body.is_end_stream(),
+            });
+
+            calls.len() - 1
+        };
+
+        let body = RecordBody {
+            inner: body,
+            calls: self.calls.clone(),
+            index,
+            frames: FrameDecoder::new(BytesCodec),
+        };
+
+        self.inner.call(http::Request::from_parts(head, body))
+    }
+}
+
+impl<S> fmt::Debug for CallRecorder<S>
+where S: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("CallRecorder")
+            .field("inner", &self.inner)
+            .field("calls", &self.lock().len())
+            .finish()
+    }
+}
+
+// ===== impl RecordedCall =====
+
+impl RecordedCall {
+    /// Returns the path of the method called.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Returns the request headers, including the custom metadata.
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// Returns the encoded request messages sent so far.
+    pub fn messages(&self) -> &[Vec<u8>] {
+        &self.messages
+    }
+
+    /// Returns the request messages sent so far, decoded as `M`.
+    #[cfg(feature = "protobuf")]
+    pub fn decode<M>(&self) -> Result<Vec<M>, ::prost::DecodeError>
+    where M: ::prost::Message + Default,
+    {
+        self.messages.iter()
+            .map(|message| M::decode(&message[..]))
+            .collect()
+    }
+
+    /// Returns true once the request stream has ended.
+    pub fn is_ended(&self) -> bool {
+        self.ended
+    }
+}
+
+// ===== impl RecordBody =====
+
+impl<B> RecordBody<B> {
+    fn record(&mut self, data: &Bytes) {
+        self.frames.push(data.clone());
+
+        loop {
+            match self.frames.decode() {
+                Ok(Some(message)) => lock(&self.calls)[self.index].messages.push(message),
+                Ok(None) => break,
+                Err(status) => {
+                    debug!("recorded request is not framed; code={:?}", status.code());
+                    break;
+                }
+            }
+        }
+    }
+}
+
+impl<B> Body for RecordBody<B>
+where B: Body<Data = Bytes>,
+{
+    type Data = Bytes;
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Bytes>, h2::Error> {
+        let data = try_ready!(self.inner.poll_data());
+
+        match data {
+            Some(ref data) => self.record(data),
+            None => lock(&self.calls)[self.index].ended = true,
+        }
+
+        Ok(data.into())
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, h2::Error> {
+        self.inner.poll_trailers()
+    }
+}
+
+impl<B> fmt::Debug for RecordBody<B>
+where B: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("testing::RecordBody")
+            .field("inner", &self.inner)
+            .field("index", &self.index)
+            .finish()
+    }
+}
+
+// ===== utility fns =====
+
+/// Serve `new_service` over an in-memory connection, and return a client
+/// `C` connected to it, with the `Harness` whose reactor runs them both.
+///
+/// `C` is usually a generated client, such as `client::Greeter<_>`.
+#[cfg(feature = "in-process")]
+pub fn loopback<S, B, C>(new_service: S) -> Result<(Harness, C), LoopbackError>
+where S: NewService<Request = http::Request<RecvBody>, Response = http::Response<B>> + 'static,
+      S::Service: 'static,
+      S::Future: 'static,
+      S::InitError: fmt::Debug,
+      <S::Service as Service>::Future: 'static,
+      B: Body + 'static,
+      C: GeneratedClient<LoopbackConnection>,
+{
+    let mut harness = Harness::new().map_err(LoopbackError::Io)?;
+    let conn = harness.connect(new_service).map_err(LoopbackError::Handshake)?;
+    let client = C::from_service(conn, inprocess::uri()).map_err(LoopbackError::Client)?;
+
+    Ok((harness, client))
+}
+
+/// Panics unless `result` ended with `expected`. Used by `assert_status!`.
+#[doc(hidden)]
+pub fn assert_status<R>(result: &R, expected: Code, message: Option<fmt::Arguments>)
+where R: CallStatus + ?Sized,
+{
+    let actual = match result.code() {
+        Ok(code) if code == expected => return,
+        Ok(code) => format!("{:?}", code),
+        Err(error) => error,
+    };
+
+    match message {
+        Some(message) => panic!("assertion failed: expected status {:?}, got {}: {}",
+                                expected, actual, message),
+        None => panic!("assertion failed: expected status {:?}, got {}", expected, actual),
+    }
+}
+
+fn lock(calls: &Mutex<Vec<RecordedCall>>) -> MutexGuard<Vec<RecordedCall>> {
+    calls.lock().unwrap_or_else(|e| e.into_inner())
+}
@@ -0,0 +1,82 @@
+//! Encoding and decoding of the `grpc-timeout` header.
+
+use bytes::BytesMut;
+use http::header::HeaderValue;
+
+use std::fmt::Write;
+use std::time::Duration;
+
+/// The largest value that may be sent in the header.
+const MAX_VALUE: u64 = 99_999_999;
+
+/// Encode `duration` as a `grpc-timeout` header value.
+///
+/// The most precise unit that fits in the header's eight digits is used.
+pub(crate) fn encode(duration: Duration) -> HeaderValue {
+    let nanos = duration.as_secs()
+        .saturating_mul(1_000_000_000)
+        .saturating_add(duration.subsec_nanos() as u64);
+
+    let units: [(u64, &str); 6] = [
+        (1, "n"),
+        (1_000, "u"),
+        (1_000_000, "m"),
+        (1_000_000_000, "S"),
+        (60 * 1_000_000_000, "M"),
+        (60 * 60 * 1_000_000_000, "H"),
+    ];
+
+    for &(size, unit) in &units {
+        let value = nanos / size;
+
+        if value <= MAX_VALUE {
+            return header_value(value, unit);
+        }
+    }
+
+    header_value(MAX_VALUE, "H")
+}
+
+/// Decode a `grpc-timeout` header value.
+pub(crate) fn decode(value: &HeaderValue) -> Option<Duration> {
+    // The digits are parsed from the raw bytes, so the value needn't be
+    // validated as a string first.
+    let bytes = value.as_bytes();
+
+    if bytes.len() < 2 || bytes.len() > 9 {
+        return None;
+    }
+
+    let (digits, unit) = bytes.split_at(bytes.len() - 1);
+    let mut value = 0u64;
+
+    for &digit in digits {
+        if digit < b'0' || digit > b'9' {
+            return None;
+        }
+
+        value = value * 10 + u64::from(digit - b'0');
+    }
+
+    let duration = match unit[0] {
+        b'H' => Duration::from_secs(value * 60 * 60),
+        b'M' => Duration::from_secs(value * 60),
+        b'S' => Duration::from_secs(value),
+        b'm' => Duration::from_millis(value),
+        b'u' => Duration::new(value / 1_000_000, (value % 1_000_000) as u32 * 1_000),
+        b'n' => Duration::new(value / 1_000_000_000, (value % 1_000_000_000) as u32),
+        _ => return None,
+    };
+
+    Some(duration)
+}
+
+/// Write `value` and `unit` into a header value.
+///
+/// At most nine bytes are written, which `BytesMut` stores inline, so no
+/// allocation is made.
+fn header_value(value: u64, unit: &str) -> HeaderValue {
+    let mut buf = BytesMut::with_capacity(9);
+    write!(buf, "{}{}", value, unit).expect("timeout fits in the buffer");
+    HeaderValue::from_shared(buf.freeze()).expect("timeout is a valid header")
+}
@@ -0,0 +1,156 @@
+use futures::{Future, Poll, Async};
+use http;
+use tower::{NewService, Service};
+
+use std::fmt;
+use std::sync::Arc;
+
+/// The certificates a peer authenticated with.
+///
+/// Inserted into the extensions of each request received on a connection
+/// whose client presented a certificate, where handlers can use it to
+/// authorize calls:
+///
+/// ```ignore
+/// match request.extensions().get::<PeerIdentity>() {
+///     Some(peer) if allowed(peer.certificate()) => { ... }
+///     _ => return Err(Status::with_code(Code::PERMISSION_DENIED)),
+/// }
+/// ```
+#[derive(Clone, PartialEq, Eq)]
+pub struct PeerIdentity {
+    chain: Arc<Vec<Vec<u8>>>,
+}
+
+/// Inserts a connection's `PeerIdentity` into the extensions of its requests.
+///
+/// TLS is negotiated before a connection is served, so a server is built
+/// for each connection:
+///
+/// ```ignore
+/// tls.accept(sock).and_then(move |sock| {
+///     let new_service = AddPeerIdentity::new(new_service, peer_identity(&sock));
+///     let h2 = Server::new(new_service, Default::default(), handle);
+///     h2.serve(sock).map_err(|e| error!("h2 error: {:?}", e))
+/// })
+/// ```
+#[derive(Debug, Clone)]
+pub struct AddPeerIdentity<S> {
+    inner: S,
+    identity: Option<PeerIdentity>,
+}
+
+/// Creates `AddPeerIdentity` services.
+#[derive(Debug)]
+pub struct NewServiceFuture<F> {
+    inner: F,
+    identity: Option<PeerIdentity>,
+}
+
+// ===== impl PeerIdentity =====
+
+impl PeerIdentity {
+    /// `chain` holds DER encoded certificates, leaf first.
+    ///
+    /// Returns `None` when the chain is empty.
+    pub fn new(chain: Vec<Vec<u8>>) -> Option<Self> {
+        if chain.is_empty() {
+            return None;
+        }
+
+        Some(PeerIdentity {
+            chain: Arc::new(chain),
+        })
+    }
+
+    /// Returns the peer's own certificate, DER encoded.
+    pub fn certificate(&self) -> &[u8] {
+        &self.chain[0]
+    }
+
+    /// Returns the certificates the peer presented, leaf first.
+    ///
+    /// Some TLS backends only expose the leaf.
+    pub fn chain(&self) -> &[Vec<u8>] {
+        &self.chain
+    }
+}
+
+impl fmt::Debug for PeerIdentity {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("PeerIdentity")
+            .field("chain", &self.chain.len())
+            .finish()
+    }
+}
+
+// ===== impl AddPeerIdentity =====
+
+impl<S> AddPeerIdentity<S> {
+    pub fn new(inner: S, identity: Option<PeerIdentity>) -> Self {
+        AddPeerIdentity { inner, identity }
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner service.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+}
+
+impl<S, A> Service for AddPeerIdentity<S>
+where S: Service<Request = http::Request<A>>,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, mut request: Self::Request) -> Self::Future {
+        if let Some(ref identity) = self.identity {
+            request.extensions_mut().insert(identity.clone());
+        }
+
+        self.inner.call(request)
+    }
+}
+
+impl<S, A> NewService for AddPeerIdentity<S>
+where S: NewService<Request = http::Request<A>>,
+{
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Service = AddPeerIdentity<S::Service>;
+    type InitError = S::InitError;
+    type Future = NewServiceFuture<S::Future>;
+
+    fn new_service(&self) -> Self::Future {
+        NewServiceFuture {
+            inner: self.inner.new_service(),
+            identity: self.identity.clone(),
+        }
+    }
+}
+
+// ===== impl NewServiceFuture =====
+
+impl<F> Future for NewServiceFuture<F>
+where F: Future,
+{
+    type Item = AddPeerIdentity<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        Ok(Async::Ready(AddPeerIdentity::new(inner, self.identity.take())))
+    }
+}
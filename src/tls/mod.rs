@@ -0,0 +1,145 @@
+//! TLS for channels and servers.
+//!
+//! The `tls` feature provides a backend using rustls, whose types are at the
+//! root of this module. `ClientTlsConfig` and `ServerTlsConfig` wrap the
+//! rustls configs, always negotiating `h2` with ALPN as gRPC requires.
+//!
+//! The `tls-native` feature provides the same types in `native`, using the
+//! platform's TLS stack through native-tls: SChannel on Windows, Security
+//! Framework on macOS and OpenSSL elsewhere. Use it where certificates must
+//! be verified against the operating system's trust store, or TLS must be
+//! provided by a certified library.
+//!
+//! A `Channel` connects over TLS with `TlsConnect`:
+//!
+//! ```ignore
+//! let tls = ClientTlsConfig::new()
+//!     .ca_certificates(include_bytes!("ca.pem"))?;
+//! let connect = TlsConnect::new("example.com", tls, handle.clone());
+//! let channel = Channel::new(resolver, connect, policy);
+//! ```
+//!
+//! A server accepts TLS connections before serving them with
+//! `tower_h2::Server`:
+//!
+//! ```ignore
+//! let tls = ServerTlsConfig::new(include_bytes!("cert.pem"), include_bytes!("key.pem"))?;
+//! let h2 = Server::new(new_service, Default::default(), handle.clone());
+//!
+//! let serve = listener.incoming().for_each(move |(sock, _)| {
+//!     let h2 = h2.clone();
+//!     let serve = tls.accept(sock)
+//!         .map_err(|e| error!("TLS handshake failed; error={:?}", e))
+//!         .and_then(move |sock| h2.serve(sock).map_err(|e| error!("h2 error: {:?}", e)));
+//!
+//!     handle.spawn(serve);
+//!     Ok(())
+//! });
+//! ```
+//!
+//! For mutual TLS, clients set their certificate with
+//! `ClientTlsConfig::identity`, and servers require one with
+//! `ServerTlsConfig::with_client_auth`. `AddPeerIdentity` then makes each
+//! client's verified `PeerIdentity` available to handlers in the request
+//! extensions.
+//!
+//! Servers whose certificates are renewed while they run, such as
+//! short-lived certificates issued by cert-manager or Let's Encrypt, accept
+//! connections with a `ReloadableServerTlsConfig`, kept up to date by
+//! `WatchFiles` or by calling `set` directly.
+
+#[cfg(feature = "tls")]
+mod client;
+mod identity;
+#[cfg(feature = "tls")]
+mod reload;
+#[cfg(feature = "tls")]
+mod server;
+
+#[cfg(feature = "tls-native")]
+pub mod native;
+
+#[cfg(feature = "tls")]
+pub use self::client::{ClientTlsConfig, ClientTlsStream, ConnectError, ConnectFuture, Handshake, TlsConnect};
+#[cfg(feature = "tls")]
+pub use self::reload::{ReloadableServerTlsConfig, WatchFiles};
+#[cfg(feature = "tls")]
+pub use self::server::{Accept, ServerTlsConfig, ServerTlsStream, peer_identity};
+pub use self::identity::{AddPeerIdentity, NewServiceFuture, PeerIdentity};
+
+#[cfg(feature = "tls")]
+use rustls::{Certificate, PrivateKey};
+#[cfg(feature = "tls")]
+use rustls::internal::pemfile;
+
+use std::{error, fmt};
+#[cfg(feature = "tls")]
+use std::io::Cursor;
+
+/// The ALPN protocol gRPC is served over.
+const ALPN_H2: &'static str = "h2";
+
+/// Error produced when building a TLS config.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The PEM held no certificates, or could not be parsed.
+    InvalidCertificate,
+
+    /// The PEM held no PKCS#8 or RSA private key, or could not be parsed.
+    InvalidKey,
+
+    /// The TLS backend rejected the config.
+    Backend(String),
+}
+
+// ===== impl Error =====
+
+impl fmt::Display for Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(error::Error::description(self))
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::InvalidCertificate => "invalid PEM certificate",
+            Error::InvalidKey => "invalid PEM private key",
+            Error::Backend(ref message) => message,
+        }
+    }
+}
+
+// ===== utility fns =====
+
+#[cfg(feature = "tls")]
+fn alpn_protocols() -> Vec<String> {
+    vec![ALPN_H2.to_string()]
+}
+
+/// Parse every certificate in `pem`.
+#[cfg(feature = "tls")]
+fn certificates(pem: &[u8]) -> Result<Vec<Certificate>, Error> {
+    match pemfile::certs(&mut Cursor::new(pem)) {
+        Ok(ref certs) if certs.is_empty() => Err(Error::InvalidCertificate),
+        Ok(certs) => Ok(certs),
+        Err(()) => Err(Error::InvalidCertificate),
+    }
+}
+
+/// Parse the first private key in `pem`, preferring PKCS#8 keys.
+#[cfg(feature = "tls")]
+fn private_key(pem: &[u8]) -> Result<PrivateKey, Error> {
+    let pkcs8 = pemfile::pkcs8_private_keys(&mut Cursor::new(pem))
+        .map_err(|()| Error::InvalidKey)?;
+
+    if let Some(key) = pkcs8.into_iter().next() {
+        return Ok(key);
+    }
+
+    pemfile::rsa_private_keys(&mut Cursor::new(pem))
+        .map_err(|()| Error::InvalidKey)?
+        .into_iter()
+        .next()
+        .ok_or(Error::InvalidKey)
+}
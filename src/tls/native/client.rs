@@ -0,0 +1,353 @@
+use super::{io_error, pem_blocks};
+use tls::{ALPN_H2, Error};
+use channel::{self, ChannelStats, CountedIo, Endpoint};
+use http2::Http2Settings;
+use tcp::{Dial, TcpOptions};
+
+use futures::{Future, Poll, Async};
+use native_tls::{self, Certificate, Identity};
+use tokio_core::net::TcpStream;
+use tokio_core::reactor::Handle;
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_tls::{self, TlsStream};
+use tower_h2::BoxBody;
+use tower_h2::client::{Connection, Handshake as H2Handshake, HandshakeError};
+
+use std::{fmt, io};
+
+/// A TLS stream opened by a client.
+pub type ClientTlsStream<T> = TlsStream<T>;
+
+/// Configures the TLS of client connections.
+///
+/// Servers' certificates are verified against the operating system's trust
+/// store, and any roots added with `ca_certificates`.
+#[derive(Clone)]
+pub struct ClientTlsConfig {
+    roots: Vec<Certificate>,
+    identity: Option<Identity>,
+    domain: Option<String>,
+}
+
+/// Performs the TLS handshake of a client connection.
+pub struct Handshake<T> {
+    inner: Result<tokio_tls::Connect<T>, Option<io::Error>>,
+}
+
+/// Connects a `Channel` to its endpoints over TLS.
+///
+/// Each connection is a TCP connection to the endpoint's address, on which
+/// TLS is negotiated for the target's domain name, followed by the HTTP/2.0
+/// handshake.
+///
+/// Servers that select a protocol with ALPN must select `h2`. Servers that
+/// select none are assumed to speak HTTP/2.0, as native-tls servers can't
+/// negotiate a protocol.
+#[derive(Debug, Clone)]
+pub struct TlsConnect {
+    config: ClientTlsConfig,
+    domain: String,
+    handle: Handle,
+    stats: ChannelStats,
+    tcp: TcpOptions,
+    http2: Http2Settings,
+}
+
+/// Future returned by `TlsConnect`.
+pub struct ConnectFuture {
+    state: State,
+    connect: TlsConnect,
+}
+
+/// Error produced by `TlsConnect`.
+#[derive(Debug)]
+pub enum ConnectError {
+    /// The TCP connection failed.
+    Connect(io::Error),
+
+    /// The TLS handshake failed.
+    Tls(io::Error),
+
+    /// The server selected a protocol other than `h2` with ALPN.
+    Alpn(String),
+
+    /// The HTTP/2.0 handshake failed.
+    Http2(HandshakeError),
+}
+
+type Io = ClientTlsStream<CountedIo<TcpStream>>;
+
+enum State {
+    Connecting(Dial),
+    Tls(Handshake<CountedIo<TcpStream>>),
+    Http2(H2Handshake<Io, Handle, BoxBody>),
+}
+
+// ===== impl ClientTlsConfig =====
+
+impl ClientTlsConfig {
+    /// A config that trusts the operating system's roots.
+    pub fn new() -> Self {
+        ClientTlsConfig {
+            roots: Vec::new(),
+            identity: None,
+            domain: None,
+        }
+    }
+
+    /// Also trust the certificates in `pem` as roots.
+    pub fn ca_certificates(mut self, pem: &[u8]) -> Result<Self, Error> {
+        let blocks = pem_blocks(pem);
+
+        if blocks.is_empty() {
+            return Err(Error::InvalidCertificate);
+        }
+
+        for block in blocks {
+            let cert = Certificate::from_pem(block)
+                .map_err(|_| Error::InvalidCertificate)?;
+            self.roots.push(cert);
+        }
+
+        Ok(self)
+    }
+
+    /// Authenticate to servers that require client certificates with the
+    /// certificate chain in `cert_chain`, leaf first, and the private key in
+    /// `key`. Both are PEM encoded, and the key must be PKCS#8.
+    pub fn identity(mut self, cert_chain: &[u8], key: &[u8]) -> Result<Self, Error> {
+        let identity = Identity::from_pkcs8(cert_chain, key)
+            .map_err(|_| Error::InvalidKey)?;
+
+        self.identity = Some(identity);
+        Ok(self)
+    }
+
+    /// Verify servers' certificates against `domain`, instead of the domain
+    /// name that was connected to.
+    ///
+    /// Useful when connecting by IP address, or in tests.
+    pub fn domain_name(mut self, domain: &str) -> Self {
+        self.domain = Some(domain.to_string());
+        self
+    }
+
+    /// Negotiate TLS on `io`, a connection to `domain`.
+    pub fn connect<T>(&self, domain: &str, io: T) -> Handshake<T>
+    where T: AsyncRead + AsyncWrite,
+    {
+        let domain = self.domain.as_ref().map(|d| &d[..]).unwrap_or(domain);
+
+        let inner = match self.connector() {
+            Ok(connector) => Ok(connector.connect(domain, io)),
+            Err(e) => Err(Some(io_error(e))),
+        };
+
+        Handshake { inner }
+    }
+
+    /// The connector is built for each connection, as native-tls
+    /// connectors can't be changed once built.
+    fn connector(&self) -> Result<tokio_tls::TlsConnector, native_tls::Error> {
+        let mut builder = native_tls::TlsConnector::builder();
+        builder.request_alpns(&[ALPN_H2]);
+
+        for root in &self.roots {
+            builder.add_root_certificate(root.clone());
+        }
+
+        if let Some(ref identity) = self.identity {
+            builder.identity(identity.clone());
+        }
+
+        builder.build().map(tokio_tls::TlsConnector::from)
+    }
+}
+
+impl Default for ClientTlsConfig {
+    fn default() -> Self {
+        ClientTlsConfig::new()
+    }
+}
+
+impl fmt::Debug for ClientTlsConfig {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("ClientTlsConfig")
+            .field("domain", &self.domain)
+            .field("roots", &self.roots.len())
+            .field("identity", &self.identity.is_some())
+            .finish()
+    }
+}
+
+// ===== impl Handshake =====
+
+impl<T> Future for Handshake<T>
+where T: AsyncRead + AsyncWrite,
+{
+    type Item = ClientTlsStream<T>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.inner {
+            Ok(ref mut connect) => connect.poll().map_err(io_error),
+            Err(ref mut error) => Err(error.take().expect("polled after error")),
+        }
+    }
+}
+
+impl<T> fmt::Debug for Handshake<T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Handshake")
+            .field("failed", &self.inner.is_err())
+            .finish()
+    }
+}
+
+// ===== impl TlsConnect =====
+
+impl TlsConnect {
+    /// Connect to endpoints of the target named `domain`.
+    ///
+    /// Connections run on the reactor of `handle`.
+    pub fn new(domain: &str, config: ClientTlsConfig, handle: Handle) -> Self {
+        TlsConnect {
+            config,
+            domain: domain.to_string(),
+            handle,
+            stats: ChannelStats::new(),
+            tcp: TcpOptions::new(),
+            http2: Http2Settings::new(),
+        }
+    }
+
+    /// Count the bytes of each connection in `stats`.
+    ///
+    /// Pass the same `ChannelStats` to `Channel::with_stats`.
+    pub fn with_stats(mut self, stats: ChannelStats) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    /// Set `options` on each TCP connection.
+    pub fn tcp_options(mut self, options: TcpOptions) -> Self {
+        self.tcp = options;
+        self
+    }
+
+    /// Negotiate each connection with `settings`.
+    pub fn http2_settings(mut self, settings: Http2Settings) -> Self {
+        self.http2 = settings;
+        self
+    }
+}
+
+impl channel::Connect for TlsConnect {
+    type Service = Connection<Io, Handle, BoxBody>;
+    type Error = ConnectError;
+    type Future = ConnectFuture;
+
+    fn connect(&mut self, endpoint: &Endpoint) -> Self::Future {
+        ConnectFuture {
+            state: State::Connecting(self.tcp.dial(endpoint, &self.handle)),
+            connect: self.clone(),
+        }
+    }
+}
+
+// ===== impl ConnectFuture =====
+
+impl Future for ConnectFuture {
+    type Item = Connection<Io, Handle, BoxBody>;
+    type Error = ConnectError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                State::Connecting(ref mut tcp) => {
+                    let tcp = try_ready!(tcp.poll().map_err(ConnectError::Connect));
+                    self.connect.tcp.apply(&tcp).map_err(ConnectError::Connect)?;
+
+                    let io = self.connect.stats.io(tcp);
+                    State::Tls(self.connect.config.connect(&self.connect.domain, io))
+                }
+                State::Tls(ref mut tls) => {
+                    let io = try_ready!(tls.poll().map_err(ConnectError::Tls));
+
+                    let protocol = io.get_ref().negotiated_alpn()
+                        .map_err(|e| ConnectError::Tls(io_error(e)))?;
+                    if let Some(protocol) = protocol {
+                        if protocol != ALPN_H2.as_bytes() {
+                            let protocol = String::from_utf8_lossy(&protocol).into_owned();
+                            debug!("TLS server did not select h2; protocol={:?}", protocol);
+                            return Err(ConnectError::Alpn(protocol));
+                        }
+                    }
+
+                    let builder = self.connect.http2.client_builder();
+                    State::Http2(H2Handshake::new(io, self.connect.handle.clone(), &builder))
+                }
+                State::Http2(ref mut h2) => {
+                    let conn = try_ready!(h2.poll().map_err(ConnectError::Http2));
+                    return Ok(Async::Ready(conn));
+                }
+            };
+
+            self.state = next;
+        }
+    }
+}
+
+impl fmt::Debug for ConnectFuture {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let state = match self.state {
+            State::Connecting(..) => "Connecting",
+            State::Tls(..) => "Tls",
+            State::Http2(..) => "Http2",
+        };
+
+        fmt.debug_struct("ConnectFuture")
+            .field("domain", &self.connect.domain)
+            .field("state", &state)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use channel::Connect;
+    use tls::native::ServerTlsConfig;
+
+    use futures::Stream;
+    use h2;
+    use tokio_core::net::TcpListener;
+    use tokio_core::reactor::Core;
+
+    // A CA, and a certificate it issued for `localhost`.
+    const CA: &'static [u8] = include_bytes!("../testdata/ca.pem");
+    const SERVER_CERT: &'static [u8] = include_bytes!("../testdata/server.pem");
+    const SERVER_KEY: &'static [u8] = include_bytes!("../testdata/server.key");
+
+    #[test]
+    fn connects_to_server_without_alpn() {
+        let mut core = Core::new().unwrap();
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+        let endpoint = Endpoint::new(listener.local_addr().unwrap());
+
+        let tls = ServerTlsConfig::new(SERVER_CERT, SERVER_KEY).unwrap();
+        let serve = listener.incoming().into_future()
+            .map_err(|(e, _)| debug!("accept failed; error={:?}", e))
+            .and_then(move |(sock, _)| {
+                tls.accept(sock.expect("accepted").0)
+                    .map_err(|e| debug!("TLS handshake failed; error={:?}", e))
+            })
+            .and_then(|sock| h2::server::handshake(sock).map_err(|_| ()))
+            .and_then(|conn| conn.for_each(|_| Ok(())).map_err(|_| ()));
+        core.handle().spawn(serve);
+
+        let config = ClientTlsConfig::new().ca_certificates(CA).unwrap();
+        let mut connect = TlsConnect::new("localhost", config, core.handle());
+        assert!(core.run(connect.connect(&endpoint)).is_ok());
+    }
+}
@@ -0,0 +1,55 @@
+//! TLS using the platform's TLS stack, through native-tls.
+//!
+//! The types here mirror those of the rustls backend. native-tls can't
+//! select a protocol with ALPN on the server side, so servers using it must
+//! be reached by clients that speak HTTP/2.0 without negotiating `h2`, as
+//! the `TlsConnect` here does. The rustls `TlsConnect` requires `h2`.
+
+mod client;
+mod server;
+
+pub use self::client::{ClientTlsConfig, ClientTlsStream, ConnectError, ConnectFuture, Handshake, TlsConnect};
+pub use self::server::{Accept, ServerTlsConfig, ServerTlsStream, peer_identity};
+
+use native_tls;
+
+use std::io;
+
+// ===== utility fns =====
+
+/// Split `pem` into its `-----BEGIN ...-----` blocks.
+fn pem_blocks(pem: &[u8]) -> Vec<&[u8]> {
+    const BEGIN: &'static [u8] = b"-----BEGIN ";
+    const END: &'static [u8] = b"-----END ";
+
+    let mut blocks = Vec::new();
+    let mut rest = pem;
+
+    while let Some(start) = find(rest, BEGIN) {
+        let block = &rest[start..];
+
+        let end = match find(block, END) {
+            Some(end) => end,
+            None => break,
+        };
+
+        // Include the rest of the `-----END ...-----` line.
+        let len = block[end..].iter()
+            .position(|&b| b == b'\n')
+            .map(|n| end + n + 1)
+            .unwrap_or(block.len());
+
+        blocks.push(&block[..len]);
+        rest = &block[len..];
+    }
+
+    blocks
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn io_error(error: native_tls::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, error)
+}
@@ -0,0 +1,98 @@
+use super::io_error;
+use tls::{Error, PeerIdentity};
+
+use futures::{Future, Poll};
+use native_tls::{self, Identity};
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_tls::{self, TlsStream};
+
+use std::{fmt, io};
+
+/// A TLS stream accepted by a server.
+pub type ServerTlsStream<T> = TlsStream<T>;
+
+/// Configures the TLS of server connections.
+///
+/// native-tls can't request certificates from clients, so servers using it
+/// can't require mutual TLS.
+#[derive(Clone)]
+pub struct ServerTlsConfig {
+    acceptor: tokio_tls::TlsAcceptor,
+}
+
+/// Performs the TLS handshake of a server connection.
+pub struct Accept<T> {
+    inner: tokio_tls::Accept<T>,
+}
+
+// ===== impl ServerTlsConfig =====
+
+impl ServerTlsConfig {
+    /// Serve the certificate chain in `cert_chain`, leaf first, with the
+    /// private key in `key`. Both are PEM encoded, and the key must be
+    /// PKCS#8.
+    pub fn new(cert_chain: &[u8], key: &[u8]) -> Result<Self, Error> {
+        let identity = Identity::from_pkcs8(cert_chain, key)
+            .map_err(|_| Error::InvalidKey)?;
+
+        let acceptor = native_tls::TlsAcceptor::new(identity)
+            .map_err(|e| Error::Backend(e.to_string()))?;
+
+        Ok(ServerTlsConfig {
+            acceptor: acceptor.into(),
+        })
+    }
+
+    /// Negotiate TLS on `io`, an accepted connection.
+    ///
+    /// The resulting stream is ready to be passed to
+    /// `tower_h2::Server::serve`.
+    pub fn accept<T>(&self, io: T) -> Accept<T>
+    where T: AsyncRead + AsyncWrite,
+    {
+        Accept {
+            inner: self.acceptor.accept(io),
+        }
+    }
+}
+
+impl fmt::Debug for ServerTlsConfig {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("ServerTlsConfig")
+            .finish()
+    }
+}
+
+// ===== impl Accept =====
+
+impl<T> Future for Accept<T>
+where T: AsyncRead + AsyncWrite,
+{
+    type Item = ServerTlsStream<T>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.inner.poll().map_err(io_error)
+    }
+}
+
+impl<T> fmt::Debug for Accept<T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Accept")
+            .finish()
+    }
+}
+
+// ===== utility fns =====
+
+/// Returns the certificate the client of `stream` authenticated with.
+///
+/// Only the leaf certificate is available from native-tls.
+pub fn peer_identity<T>(stream: &ServerTlsStream<T>) -> Option<PeerIdentity>
+where T: io::Read + io::Write,
+{
+    let cert = stream.get_ref().peer_certificate().ok()??;
+    let der = cert.to_der().ok()?;
+
+    PeerIdentity::new(vec![der])
+}
@@ -0,0 +1,192 @@
+use super::{Accept, Error, ServerTlsConfig};
+
+use futures::{Future, Poll, Async, Stream};
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_timer::{Interval, Timer, TimerError};
+
+use std::{fmt, fs, io};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+/// A server TLS config that can be replaced while serving.
+///
+/// Connections accepted after `set` use the new config; connections that are
+/// already established keep theirs. `WatchFiles` sets the config whenever
+/// the certificate or key files change, as they do when short-lived
+/// certificates are renewed:
+///
+/// ```ignore
+/// let tls = ReloadableServerTlsConfig::new(ServerTlsConfig::new(&cert, &key)?);
+/// handle.spawn(WatchFiles::new(tls.clone(), "cert.pem", "key.pem", Duration::from_secs(60))
+///     .map_err(|e| error!("certificate watch failed; error={:?}", e)));
+///
+/// let serve = listener.incoming().for_each(move |(sock, _)| {
+///     let serve = tls.accept(sock)...;
+///     ...
+/// });
+/// ```
+#[derive(Debug, Clone)]
+pub struct ReloadableServerTlsConfig {
+    inner: Arc<RwLock<ServerTlsConfig>>,
+}
+
+/// Reloads a `ReloadableServerTlsConfig` when its PEM files change.
+///
+/// The files' modification times are checked once every period. When
+/// either changed, both are read and the config is rebuilt; if that fails,
+/// the error is logged and the current config is kept.
+pub struct WatchFiles {
+    config: ReloadableServerTlsConfig,
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    build: Box<FnMut(&[u8], &[u8]) -> Result<ServerTlsConfig, Error> + Send>,
+    interval: Interval,
+    modified: Option<(SystemTime, SystemTime)>,
+}
+
+// ===== impl ReloadableServerTlsConfig =====
+
+impl ReloadableServerTlsConfig {
+    /// Start with `config`.
+    pub fn new(config: ServerTlsConfig) -> Self {
+        ReloadableServerTlsConfig {
+            inner: Arc::new(RwLock::new(config)),
+        }
+    }
+
+    /// Returns the current config.
+    pub fn get(&self) -> ServerTlsConfig {
+        match self.inner.read() {
+            Ok(config) => config.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    /// Replace the current config.
+    pub fn set(&self, config: ServerTlsConfig) {
+        match self.inner.write() {
+            Ok(mut current) => *current = config,
+            Err(poisoned) => *poisoned.into_inner() = config,
+        }
+    }
+
+    /// Negotiate TLS on `io`, an accepted connection, with the current
+    /// config.
+    pub fn accept<T>(&self, io: T) -> Accept<T>
+    where T: AsyncRead + AsyncWrite,
+    {
+        self.get().accept(io)
+    }
+}
+
+// ===== impl WatchFiles =====
+
+impl WatchFiles {
+    /// Reload `config` from the certificate chain at `cert_path` and the
+    /// private key at `key_path`, checking for changes every `period`.
+    ///
+    /// Configs are built with `ServerTlsConfig::new`, unless `build` is set.
+    pub fn new<P, Q>(config: ReloadableServerTlsConfig, cert_path: P, key_path: Q, period: Duration)
+        -> Self
+    where P: AsRef<Path>,
+          Q: AsRef<Path>,
+    {
+        WatchFiles::with_timer(config, cert_path, key_path, period, &Timer::default())
+    }
+
+    /// Like `new`, but uses the provided timer.
+    pub fn with_timer<P, Q>(config: ReloadableServerTlsConfig,
+                            cert_path: P,
+                            key_path: Q,
+                            period: Duration,
+                            timer: &Timer)
+        -> Self
+    where P: AsRef<Path>,
+          Q: AsRef<Path>,
+    {
+        let mut watch = WatchFiles {
+            config,
+            cert_path: cert_path.as_ref().to_path_buf(),
+            key_path: key_path.as_ref().to_path_buf(),
+            build: Box::new(ServerTlsConfig::new),
+            interval: timer.interval(period),
+            modified: None,
+        };
+
+        // The config was loaded from the files as they are now.
+        watch.modified = watch.modified().ok();
+        watch
+    }
+
+    /// Build configs from the certificate chain and key with `build`, for
+    /// instance to keep requiring client certificates.
+    pub fn build<F>(mut self, build: F) -> Self
+    where F: FnMut(&[u8], &[u8]) -> Result<ServerTlsConfig, Error> + Send + 'static,
+    {
+        self.build = Box::new(build);
+        self
+    }
+
+    fn modified(&self) -> io::Result<(SystemTime, SystemTime)> {
+        let cert = fs::metadata(&self.cert_path)?.modified()?;
+        let key = fs::metadata(&self.key_path)?.modified()?;
+        Ok((cert, key))
+    }
+
+    fn reload(&mut self) -> Result<(), String> {
+        let cert = fs::read(&self.cert_path)
+            .map_err(|e| format!("reading {}: {}", self.cert_path.display(), e))?;
+        let key = fs::read(&self.key_path)
+            .map_err(|e| format!("reading {}: {}", self.key_path.display(), e))?;
+
+        let config = (self.build)(&cert, &key)
+            .map_err(|e| e.to_string())?;
+
+        self.config.set(config);
+        Ok(())
+    }
+}
+
+impl Future for WatchFiles {
+    type Item = ();
+    type Error = TimerError;
+
+    fn poll(&mut self) -> Poll<(), TimerError> {
+        loop {
+            match try_ready!(self.interval.poll()) {
+                Some(()) => {}
+                None => return Ok(Async::Ready(())),
+            }
+
+            let modified = match self.modified() {
+                Ok(modified) => modified,
+                Err(e) => {
+                    warn!("failed to check TLS certificate files; error={}", e);
+                    continue;
+                }
+            };
+
+            if self.modified == Some(modified) {
+                continue;
+            }
+
+            match self.reload() {
+                Ok(()) => {
+                    debug!("reloaded TLS certificate; path={}", self.cert_path.display());
+                    self.modified = Some(modified);
+                }
+                Err(e) => warn!("failed to reload TLS certificate; error={}", e),
+            }
+        }
+    }
+}
+
+impl fmt::Debug for WatchFiles {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("WatchFiles")
+            .field("cert_path", &self.cert_path)
+            .field("key_path", &self.key_path)
+            .finish()
+    }
+}
@@ -0,0 +1,215 @@
+use super::{Error, PeerIdentity, alpn_protocols, certificates, private_key};
+
+use futures::{Future, Poll};
+use rustls::{AllowAnyAuthenticatedClient, NoClientAuth, RootCertStore, ServerConfig, ServerSession, Session};
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_rustls::{AcceptAsync, ServerConfigExt, TlsStream};
+
+use std::{fmt, io};
+use std::sync::Arc;
+
+/// A TLS stream accepted by a server.
+pub type ServerTlsStream<T> = TlsStream<T, ServerSession>;
+
+/// Configures the TLS of server connections.
+#[derive(Clone)]
+pub struct ServerTlsConfig {
+    config: Arc<ServerConfig>,
+}
+
+/// Performs the TLS handshake of a server connection.
+pub struct Accept<T> {
+    inner: AcceptAsync<T>,
+}
+
+// ===== impl ServerTlsConfig =====
+
+impl ServerTlsConfig {
+    /// Serve the certificate chain in `cert_chain`, leaf first, with the
+    /// private key in `key`. Both are PEM encoded.
+    pub fn new(cert_chain: &[u8], key: &[u8]) -> Result<Self, Error> {
+        let mut config = ServerConfig::new(NoClientAuth::new());
+        config.set_single_cert(certificates(cert_chain)?, private_key(key)?);
+
+        Ok(ServerTlsConfig::from_rustls(config))
+    }
+
+    /// Like `new`, but require clients to authenticate with a certificate
+    /// issued by one of the PEM encoded roots in `client_ca`.
+    ///
+    /// The verified certificates of each connection are returned by
+    /// `peer_identity`.
+    pub fn with_client_auth(cert_chain: &[u8], key: &[u8], client_ca: &[u8])
+        -> Result<Self, Error>
+    {
+        let mut roots = RootCertStore::empty();
+
+        for cert in &certificates(client_ca)? {
+            roots.add(cert)
+                .map_err(|_| Error::InvalidCertificate)?;
+        }
+
+        let mut config = ServerConfig::new(AllowAnyAuthenticatedClient::new(roots));
+        config.set_single_cert(certificates(cert_chain)?, private_key(key)?);
+
+        Ok(ServerTlsConfig::from_rustls(config))
+    }
+
+    /// Use `config`, which is changed to negotiate `h2`.
+    pub fn from_rustls(mut config: ServerConfig) -> Self {
+        config.set_protocols(&alpn_protocols());
+
+        ServerTlsConfig {
+            config: Arc::new(config),
+        }
+    }
+
+    /// Returns the rustls config.
+    pub fn get_ref(&self) -> &ServerConfig {
+        &self.config
+    }
+
+    /// Negotiate TLS on `io`, an accepted connection.
+    ///
+    /// The resulting stream is ready to be passed to
+    /// `tower_h2::Server::serve`.
+    pub fn accept<T>(&self, io: T) -> Accept<T>
+    where T: AsyncRead + AsyncWrite,
+    {
+        Accept {
+            inner: self.config.accept_async(io),
+        }
+    }
+}
+
+impl fmt::Debug for ServerTlsConfig {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("ServerTlsConfig")
+            .finish()
+    }
+}
+
+// ===== impl Accept =====
+
+impl<T> Future for Accept<T>
+where T: AsyncRead + AsyncWrite,
+{
+    type Item = ServerTlsStream<T>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.inner.poll()
+    }
+}
+
+impl<T> fmt::Debug for Accept<T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Accept")
+            .finish()
+    }
+}
+
+// ===== utility fns =====
+
+/// Returns the certificates the client of `stream` authenticated with.
+pub fn peer_identity<T>(stream: &ServerTlsStream<T>) -> Option<PeerIdentity> {
+    let (_, session) = stream.get_ref();
+    let certs = session.get_peer_certificates()?;
+
+    PeerIdentity::new(certs.into_iter().map(|cert| cert.0).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tls::{ClientTlsConfig, ClientTlsStream};
+
+    use futures::Stream;
+    use rustls::ClientSession;
+    use tokio_core::net::{TcpListener, TcpStream};
+    use tokio_core::reactor::Core;
+
+    // A CA, and certificates it issued for `localhost` and for a client.
+    const CA: &'static [u8] = include_bytes!("testdata/ca.pem");
+    const SERVER_CERT: &'static [u8] = include_bytes!("testdata/server.pem");
+    const SERVER_KEY: &'static [u8] = include_bytes!("testdata/server.key");
+    const CLIENT_CERT: &'static [u8] = include_bytes!("testdata/client.pem");
+    const CLIENT_KEY: &'static [u8] = include_bytes!("testdata/client.key");
+
+    type Handshakes = (
+        io::Result<ServerTlsStream<TcpStream>>,
+        io::Result<ClientTlsStream<TcpStream>>,
+    );
+
+    /// Accept a loopback connection with `server` while `client` opens it.
+    fn handshake(server: ServerTlsConfig, client: ClientTlsConfig) -> Handshakes {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &handle).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let accept = listener.incoming().into_future()
+            .map_err(|(e, _)| e)
+            .and_then(move |(sock, _)| server.accept(sock.expect("accepted").0))
+            .then(Ok::<_, ()>);
+
+        let connect = TcpStream::connect(&addr, &handle)
+            .and_then(move |sock| client.connect("localhost", sock))
+            .then(Ok::<_, ()>);
+
+        core.run(accept.join(connect)).unwrap()
+    }
+
+    fn alpn(stream: &ClientTlsStream<TcpStream>) -> Option<String> {
+        let session: &ClientSession = stream.get_ref().1;
+        session.get_alpn_protocol().map(|p| p.to_string())
+    }
+
+    #[test]
+    fn handshake_negotiates_h2() {
+        let server = ServerTlsConfig::new(SERVER_CERT, SERVER_KEY).unwrap();
+        let client = ClientTlsConfig::new().ca_certificates(CA).unwrap();
+
+        let (accepted, connected) = handshake(server, client);
+        let accepted = accepted.unwrap();
+        let connected = connected.unwrap();
+
+        assert_eq!(alpn(&connected), Some("h2".to_string()));
+        assert_eq!(peer_identity(&accepted), None);
+    }
+
+    #[test]
+    fn handshake_fails_with_untrusted_server() {
+        let server = ServerTlsConfig::new(SERVER_CERT, SERVER_KEY).unwrap();
+        let client = ClientTlsConfig::new();
+
+        let (_, connected) = handshake(server, client);
+        assert!(connected.is_err());
+    }
+
+    #[test]
+    fn client_auth_identifies_client() {
+        let server = ServerTlsConfig::with_client_auth(SERVER_CERT, SERVER_KEY, CA).unwrap();
+        let client = ClientTlsConfig::new()
+            .ca_certificates(CA).unwrap()
+            .identity(CLIENT_CERT, CLIENT_KEY).unwrap();
+
+        let (accepted, connected) = handshake(server, client);
+        assert!(connected.is_ok());
+
+        let peer = peer_identity(&accepted.unwrap()).expect("client identified");
+        assert_eq!(peer.certificate(), &certificates(CLIENT_CERT).unwrap()[0].0[..]);
+    }
+
+    #[test]
+    fn client_auth_rejects_unauthenticated_client() {
+        let server = ServerTlsConfig::with_client_auth(SERVER_CERT, SERVER_KEY, CA).unwrap();
+        let client = ClientTlsConfig::new().ca_certificates(CA).unwrap();
+
+        // Depending on the TLS version, the client may only learn of the
+        // rejection once it reads from the connection.
+        let (accepted, _) = handshake(server, client);
+        assert!(accepted.is_err());
+    }
+}
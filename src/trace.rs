@@ -0,0 +1,389 @@
+//! Per-call `tracing` spans.
+//!
+//! `ClientTrace` and `ServerTrace` wrap a client's or server's HTTP/2.0
+//! service and open a span for each call, carrying the call's service and
+//! method. Once the call completes, the span records its status code and
+//! the number of messages sent and received.
+//!
+//! The span is entered whenever the call is polled, so events emitted by
+//! inner layers are attributed to it. It is also inserted into the request's
+//! extensions: servers can read it from `Request::extensions` to parent the
+//! work done while handling the call. On the client, the call's span is a
+//! child of the span current when the call is made.
+
+use generic::counter::{Frames, MessageCounters};
+use headers;
+use Code;
+
+use futures::{Future, Poll, Async};
+use h2;
+use http::{self, HeaderMap};
+use tower::{NewService, Service};
+use tower_h2::{Body, BoxBody, HttpService};
+use tracing::{field, Span};
+
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Opens a span for each call made through the inner service.
+#[derive(Debug, Clone)]
+pub struct ClientTrace<S> {
+    inner: S,
+}
+
+/// Opens a span for each call served by the inner service.
+///
+/// `ServerTrace` may wrap either a `Service` or the `NewService` given to
+/// `tower_h2::Server`.
+#[derive(Debug, Clone)]
+pub struct ServerTrace<S> {
+    inner: S,
+}
+
+/// The response future returned by `ClientTrace` and `ServerTrace`.
+pub struct ResponseFuture<F> {
+    inner: F,
+    call: Arc<Call>,
+}
+
+/// A request or response body whose messages are counted.
+pub struct TracedBody<B> {
+    inner: B,
+    call: Arc<Call>,
+    direction: Direction,
+    frames: Frames,
+}
+
+/// Creates `ServerTrace` services.
+#[derive(Debug)]
+pub struct NewServiceFuture<F> {
+    inner: F,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Side {
+    Client,
+    Server,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Direction {
+    Request,
+    Response,
+}
+
+/// The state of a single traced call; completed as `CANCELED` if dropped
+/// before its status is known.
+#[derive(Debug)]
+struct Call {
+    span: Span,
+    side: Side,
+    sent: AtomicUsize,
+    received: Arc<AtomicUsize>,
+    finished: AtomicBool,
+}
+
+// ===== impl ClientTrace =====
+
+impl<S> ClientTrace<S> {
+    /// Trace the calls made through `inner`.
+    pub fn new(inner: S) -> Self {
+        ClientTrace { inner }
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner service.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+}
+
+impl<S> Service for ClientTrace<S>
+where S: HttpService<RequestBody = BoxBody>,
+      <S::ResponseBody as Body>::Data: AsRef<[u8]>,
+{
+    type Request = http::Request<BoxBody>;
+    type Response = http::Response<TracedBody<S::ResponseBody>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, mut request: Self::Request) -> Self::Future {
+        let call = Arc::new(Call::new(Side::Client, request.uri().path()));
+        request.extensions_mut().insert(call.span.clone());
+
+        let (head, body) = request.into_parts();
+        let body = TracedBody::new(body, call.clone(), Direction::Request);
+        let request = http::Request::from_parts(head, BoxBody::new(Box::new(body)));
+
+        let inner = {
+            let _enter = call.span.enter();
+            self.inner.call(request)
+        };
+
+        ResponseFuture { inner, call }
+    }
+}
+
+// ===== impl ServerTrace =====
+
+impl<S> ServerTrace<S> {
+    /// Trace the calls served by `inner`.
+    pub fn new(inner: S) -> Self {
+        ServerTrace { inner }
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner service.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+}
+
+impl<S, A, B> Service for ServerTrace<S>
+where S: Service<Request = http::Request<A>, Response = http::Response<B>>,
+      B: Body,
+      B::Data: AsRef<[u8]>,
+{
+    type Request = S::Request;
+    type Response = http::Response<TracedBody<B>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, mut request: Self::Request) -> Self::Future {
+        let call = Arc::new(Call::new(Side::Server, request.uri().path()));
+        request.extensions_mut().insert(call.span.clone());
+
+        // Received messages are counted as the server decodes them.
+        MessageCounters::register(&mut request, call.received.clone());
+
+        let inner = {
+            let _enter = call.span.enter();
+            self.inner.call(request)
+        };
+
+        ResponseFuture { inner, call }
+    }
+}
+
+impl<S, A, B> NewService for ServerTrace<S>
+where S: NewService<Request = http::Request<A>, Response = http::Response<B>>,
+      B: Body,
+      B::Data: AsRef<[u8]>,
+{
+    type Request = S::Request;
+    type Response = http::Response<TracedBody<B>>;
+    type Error = S::Error;
+    type Service = ServerTrace<S::Service>;
+    type InitError = S::InitError;
+    type Future = NewServiceFuture<S::Future>;
+
+    fn new_service(&self) -> Self::Future {
+        NewServiceFuture {
+            inner: self.inner.new_service(),
+        }
+    }
+}
+
+// ===== impl NewServiceFuture =====
+
+impl<F> Future for NewServiceFuture<F>
+where F: Future,
+{
+    type Item = ServerTrace<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        Ok(Async::Ready(ServerTrace::new(inner)))
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F, B> Future for ResponseFuture<F>
+where F: Future<Item = http::Response<B>>,
+{
+    type Item = http::Response<TracedBody<B>>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let _enter = self.call.span.enter();
+
+        let response = match self.inner.poll() {
+            Ok(Async::Ready(response)) => response,
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Err(e) => {
+                self.call.finish(Code::UNKNOWN);
+                return Err(e);
+            }
+        };
+
+        // Trailers-only responses carry the status in the headers.
+        if let Some(code) = headers::status_code(response.headers()) {
+            self.call.finish(code);
+        }
+
+        let (head, body) = response.into_parts();
+        let body = TracedBody::new(body, self.call.clone(), Direction::Response);
+
+        Ok(Async::Ready(http::Response::from_parts(head, body)))
+    }
+}
+
+impl<F> fmt::Debug for ResponseFuture<F>
+where F: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("trace::ResponseFuture")
+            .field("inner", &self.inner)
+            .field("span", &self.call.span)
+            .finish()
+    }
+}
+
+// ===== impl TracedBody =====
+
+impl<B> TracedBody<B> {
+    fn new(inner: B, call: Arc<Call>, direction: Direction) -> Self {
+        TracedBody {
+            inner,
+            call,
+            direction,
+            frames: Frames::default(),
+        }
+    }
+}
+
+impl<B> Body for TracedBody<B>
+where B: Body,
+      B::Data: AsRef<[u8]>,
+{
+    type Data = B::Data;
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, h2::Error> {
+        let _enter = self.call.span.enter();
+        let data = try_ready!(self.inner.poll_data());
+
+        if let Some(ref data) = data {
+            let messages = self.frames.count(data.as_ref());
+            self.call.messages(self.direction, messages);
+        }
+
+        Ok(Async::Ready(data))
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, h2::Error> {
+        let _enter = self.call.span.enter();
+        let trailers = try_ready!(self.inner.poll_trailers());
+
+        if self.direction == Direction::Response {
+            let code = trailers.as_ref()
+                .and_then(headers::status_code)
+                .unwrap_or(Code::UNKNOWN);
+            self.call.finish(code);
+        }
+
+        Ok(Async::Ready(trailers))
+    }
+}
+
+impl<B> fmt::Debug for TracedBody<B>
+where B: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("TracedBody")
+            .field("inner", &self.inner)
+            .field("span", &self.call.span)
+            .field("direction", &self.direction)
+            .finish()
+    }
+}
+
+// ===== impl Call =====
+
+impl Call {
+    fn new(side: Side, path: &str) -> Self {
+        let (service, method) = headers::split_method_path(path).unwrap_or(("", ""));
+
+        let span = match side {
+            Side::Client => ::tracing::info_span!(
+                "grpc.client",
+                rpc.service = service,
+                rpc.method = method,
+                grpc.status_code = field::Empty,
+                grpc.messages_sent = field::Empty,
+                grpc.messages_received = field::Empty,
+            ),
+            Side::Server => ::tracing::info_span!(
+                "grpc.server",
+                rpc.service = service,
+                rpc.method = method,
+                grpc.status_code = field::Empty,
+                grpc.messages_sent = field::Empty,
+                grpc.messages_received = field::Empty,
+            ),
+        };
+
+        Call {
+            span,
+            side,
+            sent: AtomicUsize::new(0),
+            received: Arc::new(AtomicUsize::new(0)),
+            finished: AtomicBool::new(false),
+        }
+    }
+
+    /// Count messages sent in `direction`.
+    ///
+    /// Request messages are counted by the codec on the server.
+    fn messages(&self, direction: Direction, messages: usize) {
+        let counter = match (self.side, direction) {
+            (Side::Client, Direction::Request) => &self.sent,
+            (Side::Client, Direction::Response) => &*self.received,
+            (Side::Server, Direction::Response) => &self.sent,
+            (Side::Server, Direction::Request) => return,
+        };
+
+        counter.fetch_add(messages, Ordering::Relaxed);
+    }
+
+    fn finish(&self, code: Code) {
+        if self.finished.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        let sent = self.sent.load(Ordering::Relaxed) as u64;
+        let received = self.received.load(Ordering::Relaxed) as u64;
+
+        self.span.record("grpc.status_code", &code.as_i32());
+        self.span.record("grpc.messages_sent", &sent);
+        self.span.record("grpc.messages_received", &received);
+    }
+}
+
+impl Drop for Call {
+    fn drop(&mut self) {
+        self.finish(Code::CANCELED);
+    }
+}
@@ -0,0 +1,461 @@
+//! The descriptors of the messages, enums and methods that are transcoded.
+
+use super::template::Template;
+
+use http;
+use prost::Message;
+
+use std::collections::{HashMap, HashSet};
+
+/// The message types, enums and HTTP rules of a set of files.
+#[derive(Debug, Default)]
+pub(crate) struct Descriptors {
+    /// Message types, keyed by fully qualified name.
+    pub(crate) messages: HashMap<String, MessageType>,
+
+    /// Enums, keyed by fully qualified name.
+    pub(crate) enums: HashMap<String, EnumType>,
+
+    /// The HTTP rules of every method, in registration order.
+    pub(crate) rules: Vec<Rule>,
+
+    /// Every method, keyed by path.
+    pub(crate) methods: HashMap<String, Method>,
+
+    /// The names of the files added.
+    files: HashSet<String>,
+}
+
+#[derive(Debug)]
+pub(crate) struct MessageType {
+    pub(crate) fields: Vec<Field>,
+
+    /// Set for the entries of map fields.
+    pub(crate) map_entry: bool,
+}
+
+#[derive(Debug)]
+pub(crate) struct Field {
+    pub(crate) name: String,
+    pub(crate) json_name: String,
+    pub(crate) number: u32,
+    pub(crate) kind: Kind,
+    pub(crate) repeated: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Kind {
+    Double,
+    Float,
+    Int64,
+    Uint64,
+    Int32,
+    Fixed64,
+    Fixed32,
+    Bool,
+    String,
+    Message(String),
+    Bytes,
+    Uint32,
+    Enum(String),
+    Sfixed32,
+    Sfixed64,
+    Sint32,
+    Sint64,
+}
+
+#[derive(Debug)]
+pub(crate) struct EnumType {
+    pub(crate) values: Vec<(String, i32)>,
+}
+
+/// The message types of a gRPC method.
+#[derive(Debug)]
+pub(crate) struct Method {
+    pub(crate) input_type: String,
+    pub(crate) output_type: String,
+    pub(crate) client_streaming: bool,
+    pub(crate) server_streaming: bool,
+}
+
+/// Maps requests matching an HTTP method and path template to a gRPC
+/// method.
+#[derive(Debug)]
+pub(crate) struct Rule {
+    pub(crate) method: http::Method,
+    pub(crate) template: Template,
+
+    /// The field the request body is decoded into, `*` for the whole
+    /// request message, or empty if the body is ignored.
+    pub(crate) body: String,
+
+    /// The field of the response message rendered as the response body, or
+    /// empty for the whole message.
+    pub(crate) response_body: String,
+
+    /// The path of the gRPC method, such as `/pkg.Service/Method`.
+    pub(crate) path: String,
+
+    pub(crate) input_type: String,
+    pub(crate) output_type: String,
+    pub(crate) client_streaming: bool,
+    pub(crate) server_streaming: bool,
+}
+
+// ===== Descriptors =====
+
+// Only the parts of `google/protobuf/descriptor.proto` and
+// `google/api/http.proto` needed to transcode messages are declared.
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct FileDescriptorSet {
+    #[prost(message, repeated, tag="1")]
+    pub(crate) file: Vec<FileDescriptorProto>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct FileDescriptorProto {
+    #[prost(string, tag="1")]
+    name: String,
+    #[prost(string, tag="2")]
+    package: String,
+    #[prost(message, repeated, tag="4")]
+    message_type: Vec<DescriptorProto>,
+    #[prost(message, repeated, tag="5")]
+    enum_type: Vec<EnumDescriptorProto>,
+    #[prost(message, repeated, tag="6")]
+    service: Vec<ServiceDescriptorProto>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct DescriptorProto {
+    #[prost(string, tag="1")]
+    name: String,
+    #[prost(message, repeated, tag="2")]
+    field: Vec<FieldDescriptorProto>,
+    #[prost(message, repeated, tag="3")]
+    nested_type: Vec<DescriptorProto>,
+    #[prost(message, repeated, tag="4")]
+    enum_type: Vec<EnumDescriptorProto>,
+    #[prost(message, optional, tag="7")]
+    options: Option<MessageOptions>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct MessageOptions {
+    #[prost(bool, tag="7")]
+    map_entry: bool,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct FieldDescriptorProto {
+    #[prost(string, tag="1")]
+    name: String,
+    #[prost(int32, tag="3")]
+    number: i32,
+    #[prost(int32, tag="4")]
+    label: i32,
+    #[prost(int32, tag="5")]
+    kind: i32,
+    #[prost(string, tag="6")]
+    type_name: String,
+    #[prost(string, tag="10")]
+    json_name: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct EnumDescriptorProto {
+    #[prost(string, tag="1")]
+    name: String,
+    #[prost(message, repeated, tag="2")]
+    value: Vec<EnumValueDescriptorProto>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct EnumValueDescriptorProto {
+    #[prost(string, tag="1")]
+    name: String,
+    #[prost(int32, tag="2")]
+    number: i32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct ServiceDescriptorProto {
+    #[prost(string, tag="1")]
+    name: String,
+    #[prost(message, repeated, tag="2")]
+    method: Vec<MethodDescriptorProto>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct MethodDescriptorProto {
+    #[prost(string, tag="1")]
+    name: String,
+    #[prost(string, tag="2")]
+    input_type: String,
+    #[prost(string, tag="3")]
+    output_type: String,
+    #[prost(message, optional, tag="4")]
+    options: Option<MethodOptions>,
+    #[prost(bool, tag="5")]
+    client_streaming: bool,
+    #[prost(bool, tag="6")]
+    server_streaming: bool,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct MethodOptions {
+    /// The `google.api.http` extension.
+    #[prost(message, optional, tag="72295728")]
+    http: Option<HttpRule>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct HttpRule {
+    #[prost(string, optional, tag="2")]
+    get: Option<String>,
+    #[prost(string, optional, tag="3")]
+    put: Option<String>,
+    #[prost(string, optional, tag="4")]
+    post: Option<String>,
+    #[prost(string, optional, tag="5")]
+    delete: Option<String>,
+    #[prost(string, optional, tag="6")]
+    patch: Option<String>,
+    #[prost(string, tag="7")]
+    body: String,
+    #[prost(message, optional, tag="8")]
+    custom: Option<CustomHttpPattern>,
+    #[prost(message, repeated, tag="11")]
+    additional_bindings: Vec<HttpRule>,
+    #[prost(string, tag="12")]
+    response_body: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct CustomHttpPattern {
+    #[prost(string, tag="1")]
+    kind: String,
+    #[prost(string, tag="2")]
+    path: String,
+}
+
+const LABEL_REPEATED: i32 = 3;
+
+// ===== impl Descriptors =====
+
+impl Descriptors {
+    /// Index the types, methods and HTTP rules of `file`.
+    ///
+    /// Fails with a description of the first invalid path template.
+    pub(crate) fn add(&mut self, file: FileDescriptorProto) -> Result<(), String> {
+        // Files shared by several sets are only added once.
+        if !self.files.insert(file.name.clone()) {
+            return Ok(());
+        }
+
+        let package = file.package;
+
+        for message in file.message_type {
+            self.add_message(&package, message);
+        }
+
+        for e in file.enum_type {
+            self.add_enum(&package, e);
+        }
+
+        for service in file.service {
+            let service_name = qualify(&package, &service.name);
+
+            for method in service.method {
+                let path = format!("/{}/{}", service_name, method.name);
+
+                self.methods.insert(path.clone(), Method {
+                    input_type: method.input_type.trim_start_matches('.').to_string(),
+                    output_type: method.output_type.trim_start_matches('.').to_string(),
+                    client_streaming: method.client_streaming,
+                    server_streaming: method.server_streaming,
+                });
+
+                let rule = match method.options.as_ref().and_then(|o| o.http.as_ref()) {
+                    Some(rule) => rule.clone(),
+                    None => continue,
+                };
+
+                self.add_rule(&path, &method, &rule)?;
+
+                for binding in &rule.additional_bindings {
+                    self.add_rule(&path, &method, binding)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn add_message(&mut self, scope: &str, message: DescriptorProto) {
+        let name = qualify(scope, &message.name);
+
+        for nested in message.nested_type {
+            self.add_message(&name, nested);
+        }
+
+        for e in message.enum_type {
+            self.add_enum(&name, e);
+        }
+
+        let fields = message.field.into_iter()
+            .filter_map(|field| {
+                let kind = match Kind::from_descriptor(field.kind, &field.type_name) {
+                    Some(kind) => kind,
+                    None => {
+                        debug!("unsupported field type; field={}, type={}", field.name, field.kind);
+                        return None;
+                    }
+                };
+
+                let json_name = if field.json_name.is_empty() {
+                    json_name(&field.name)
+                } else {
+                    field.json_name
+                };
+
+                Some(Field {
+                    name: field.name,
+                    json_name,
+                    number: field.number as u32,
+                    kind,
+                    repeated: field.label == LABEL_REPEATED,
+                })
+            })
+            .collect();
+
+        let map_entry = message.options.map_or(false, |options| options.map_entry);
+
+        self.messages.insert(name, MessageType { fields, map_entry });
+    }
+
+    fn add_enum(&mut self, scope: &str, e: EnumDescriptorProto) {
+        let values = e.value.into_iter()
+            .map(|value| (value.name, value.number))
+            .collect();
+
+        self.enums.insert(qualify(scope, &e.name), EnumType { values });
+    }
+
+    fn add_rule(&mut self, path: &str, method: &MethodDescriptorProto, rule: &HttpRule)
+        -> Result<(), String>
+    {
+        let (http_method, template) = if let Some(ref t) = rule.get {
+            (http::Method::GET, t)
+        } else if let Some(ref t) = rule.put {
+            (http::Method::PUT, t)
+        } else if let Some(ref t) = rule.post {
+            (http::Method::POST, t)
+        } else if let Some(ref t) = rule.delete {
+            (http::Method::DELETE, t)
+        } else if let Some(ref t) = rule.patch {
+            (http::Method::PATCH, t)
+        } else if let Some(ref custom) = rule.custom {
+            let m = http::Method::from_bytes(custom.kind.as_bytes())
+                .map_err(|_| format!("invalid HTTP method {:?} for {}", custom.kind, path))?;
+            (m, &custom.path)
+        } else {
+            return Ok(());
+        };
+
+        let template = Template::parse(template)
+            .map_err(|e| format!("invalid path template for {}: {}", path, e))?;
+
+        self.rules.push(Rule {
+            method: http_method,
+            template,
+            body: rule.body.clone(),
+            response_body: rule.response_body.clone(),
+            path: path.to_string(),
+            input_type: method.input_type.trim_start_matches('.').to_string(),
+            output_type: method.output_type.trim_start_matches('.').to_string(),
+            client_streaming: method.client_streaming,
+            server_streaming: method.server_streaming,
+        });
+
+        Ok(())
+    }
+}
+
+// ===== impl MessageType =====
+
+impl MessageType {
+    /// Returns the field named `name`, by either its JSON or proto name.
+    pub(crate) fn field_by_name(&self, name: &str) -> Option<&Field> {
+        self.fields.iter()
+            .find(|field| field.json_name == name || field.name == name)
+    }
+
+    pub(crate) fn field_by_number(&self, number: u32) -> Option<&Field> {
+        self.fields.iter()
+            .find(|field| field.number == number)
+    }
+}
+
+// ===== impl Kind =====
+
+impl Kind {
+    fn from_descriptor(kind: i32, type_name: &str) -> Option<Kind> {
+        let type_name = type_name.trim_start_matches('.').to_string();
+
+        let kind = match kind {
+            1 => Kind::Double,
+            2 => Kind::Float,
+            3 => Kind::Int64,
+            4 => Kind::Uint64,
+            5 => Kind::Int32,
+            6 => Kind::Fixed64,
+            7 => Kind::Fixed32,
+            8 => Kind::Bool,
+            9 => Kind::String,
+            // Groups are not supported.
+            11 => Kind::Message(type_name),
+            12 => Kind::Bytes,
+            13 => Kind::Uint32,
+            14 => Kind::Enum(type_name),
+            15 => Kind::Sfixed32,
+            16 => Kind::Sfixed64,
+            17 => Kind::Sint32,
+            18 => Kind::Sint64,
+            _ => return None,
+        };
+
+        Some(kind)
+    }
+}
+
+// ===== utility fns =====
+
+fn qualify(scope: &str, name: &str) -> String {
+    if scope.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", scope, name)
+    }
+}
+
+/// The lowerCamelCase JSON name of a field, for descriptors compiled
+/// without JSON names.
+fn json_name(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    let mut upper = false;
+
+    for c in name.chars() {
+        if c == '_' {
+            upper = true;
+        } else if upper {
+            out.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            out.push(c);
+        }
+    }
+
+    out
+}
@@ -0,0 +1,677 @@
+//! Conversion between JSON and encoded protobuf messages, following the
+//! proto3 JSON mapping.
+//!
+//! Well-known types, such as `google.protobuf.Timestamp`, are mapped like
+//! any other message rather than to their special JSON representations.
+
+use super::descriptor::{Descriptors, Field, Kind};
+use base64;
+
+use serde_json::{Map, Number, Value};
+
+const VARINT: u8 = 0;
+const FIXED64: u8 = 1;
+const LENGTH_DELIMITED: u8 = 2;
+const FIXED32: u8 = 5;
+
+/// Encode the JSON `value` as a message of type `type_name`.
+pub(crate) fn encode(descriptors: &Descriptors,
+                     type_name: &str,
+                     value: &Value,
+                     buf: &mut Vec<u8>)
+    -> Result<(), String>
+{
+    let message = descriptors.messages.get(type_name)
+        .ok_or_else(|| format!("unknown message type {}", type_name))?;
+
+    let object = match *value {
+        Value::Object(ref object) => object,
+        Value::Null => return Ok(()),
+        _ => return Err(format!("expected an object for {}", type_name)),
+    };
+
+    for (key, value) in object {
+        let field = message.field_by_name(key)
+            .ok_or_else(|| format!("unknown field {:?} of {}", key, type_name))?;
+
+        if value.is_null() {
+            continue;
+        }
+
+        if let Some((key_field, value_field)) = map_fields(descriptors, field) {
+            let entries = value.as_object()
+                .ok_or_else(|| format!("expected an object for map field {}", field.name))?;
+
+            for (k, v) in entries {
+                let mut entry = vec![];
+                encode_value(descriptors, key_field, &Value::String(k.clone()), &mut entry)?;
+                encode_value(descriptors, value_field, v, &mut entry)?;
+
+                put_key(field.number, LENGTH_DELIMITED, buf);
+                put_varint(entry.len() as u64, buf);
+                buf.extend_from_slice(&entry);
+            }
+        } else if field.repeated {
+            match *value {
+                Value::Array(ref values) => {
+                    for value in values {
+                        encode_value(descriptors, field, value, buf)?;
+                    }
+                }
+                // A single query parameter sets a repeated field.
+                ref value => encode_value(descriptors, field, value, buf)?,
+            }
+        } else {
+            if value.is_array() {
+                return Err(format!("field {} is not repeated", field.name));
+            }
+
+            encode_value(descriptors, field, value, buf)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Decode `bytes`, a message of type `type_name`, as JSON.
+pub(crate) fn decode(descriptors: &Descriptors, type_name: &str, mut bytes: &[u8])
+    -> Result<Value, String>
+{
+    let message = descriptors.messages.get(type_name)
+        .ok_or_else(|| format!("unknown message type {}", type_name))?;
+
+    let mut object = Map::new();
+
+    while !bytes.is_empty() {
+        let key = get_varint(&mut bytes)?;
+        let number = (key >> 3) as u32;
+        let wire = (key & 0x7) as u8;
+
+        let field = match message.field_by_number(number) {
+            Some(field) => field,
+            None => {
+                skip(wire, &mut bytes)?;
+                continue;
+            }
+        };
+
+        if let Some((key_field, value_field)) = map_fields(descriptors, field) {
+            let mut entry = take_length_delimited(&mut bytes)?;
+            let mut key = Value::Null;
+            let mut value = Value::Null;
+
+            while !entry.is_empty() {
+                let k = get_varint(&mut entry)?;
+
+                match (k >> 3) as u32 {
+                    1 => key = decode_value(descriptors, key_field, (k & 0x7) as u8, &mut entry)?,
+                    2 => value = decode_value(descriptors, value_field, (k & 0x7) as u8, &mut entry)?,
+                    _ => skip((k & 0x7) as u8, &mut entry)?,
+                }
+            }
+
+            let key = match key {
+                Value::String(s) => s,
+                Value::Null => String::new(),
+                other => other.to_string(),
+            };
+
+            object.entry(field.json_name.clone())
+                .or_insert_with(|| Value::Object(Map::new()))
+                .as_object_mut()
+                .expect("map field is an object")
+                .insert(key, value);
+
+            continue;
+        }
+
+        let mut values = vec![];
+
+        if field.repeated && wire == LENGTH_DELIMITED && wire_type(&field.kind) != LENGTH_DELIMITED {
+            // Packed scalars.
+            let mut packed = take_length_delimited(&mut bytes)?;
+
+            while !packed.is_empty() {
+                values.push(decode_value(descriptors, field, wire_type(&field.kind), &mut packed)?);
+            }
+        } else {
+            values.push(decode_value(descriptors, field, wire, &mut bytes)?);
+        }
+
+        if field.repeated {
+            let array = object.entry(field.json_name.clone())
+                .or_insert_with(|| Value::Array(vec![]));
+
+            if let Value::Array(ref mut array) = *array {
+                array.extend(values);
+            }
+        } else if let Some(value) = values.pop() {
+            object.insert(field.json_name.clone(), value);
+        }
+    }
+
+    Ok(Value::Object(object))
+}
+
+// ===== encoding =====
+
+fn encode_value(descriptors: &Descriptors, field: &Field, value: &Value, buf: &mut Vec<u8>)
+    -> Result<(), String>
+{
+    let number = field.number;
+    let invalid = || format!("invalid value {} for field {}", value, field.name);
+
+    match field.kind {
+        Kind::Int32 => {
+            let v = get_i32(value).ok_or_else(invalid)?;
+            put_key(number, VARINT, buf);
+            put_varint(v as u64, buf);
+        }
+        Kind::Int64 => {
+            let v = get_int(value).ok_or_else(invalid)?;
+            put_key(number, VARINT, buf);
+            put_varint(v as u64, buf);
+        }
+        Kind::Uint32 => {
+            let v = get_u32(value).ok_or_else(invalid)?;
+            put_key(number, VARINT, buf);
+            put_varint(v, buf);
+        }
+        Kind::Uint64 => {
+            let v = get_uint(value).ok_or_else(invalid)?;
+            put_key(number, VARINT, buf);
+            put_varint(v, buf);
+        }
+        Kind::Sint32 => {
+            let v = get_i32(value).ok_or_else(invalid)?;
+            put_key(number, VARINT, buf);
+            put_varint(((v << 1) ^ (v >> 63)) as u64, buf);
+        }
+        Kind::Sint64 => {
+            let v = get_int(value).ok_or_else(invalid)?;
+            put_key(number, VARINT, buf);
+            put_varint(((v << 1) ^ (v >> 63)) as u64, buf);
+        }
+        Kind::Bool => {
+            let v = match *value {
+                Value::Bool(b) => b,
+                Value::String(ref s) if s == "true" => true,
+                Value::String(ref s) if s == "false" => false,
+                _ => return Err(invalid()),
+            };
+            put_key(number, VARINT, buf);
+            put_varint(v as u64, buf);
+        }
+        Kind::Enum(ref name) => {
+            let values = &descriptors.enums.get(name)
+                .ok_or_else(|| format!("unknown enum {}", name))?
+                .values;
+
+            let v = match *value {
+                Value::String(ref s) => values.iter()
+                    .find(|&&(ref n, _)| n == s)
+                    .map(|&(_, v)| v as i64),
+                ref value => get_i32(value),
+            }.ok_or_else(invalid)?;
+
+            put_key(number, VARINT, buf);
+            put_varint(v as u64, buf);
+        }
+        Kind::Fixed32 => {
+            let v = get_u32(value).ok_or_else(invalid)?;
+            put_key(number, FIXED32, buf);
+            put_fixed32(v as u32, buf);
+        }
+        Kind::Sfixed32 => {
+            let v = get_i32(value).ok_or_else(invalid)?;
+            put_key(number, FIXED32, buf);
+            put_fixed32(v as i32 as u32, buf);
+        }
+        Kind::Fixed64 => {
+            let v = get_uint(value).ok_or_else(invalid)?;
+            put_key(number, FIXED64, buf);
+            put_fixed64(v, buf);
+        }
+        Kind::Sfixed64 => {
+            let v = get_int(value).ok_or_else(invalid)?;
+            put_key(number, FIXED64, buf);
+            put_fixed64(v as u64, buf);
+        }
+        Kind::Float => {
+            let v = get_float(value).ok_or_else(invalid)?;
+            put_key(number, FIXED32, buf);
+            put_fixed32((v as f32).to_bits(), buf);
+        }
+        Kind::Double => {
+            let v = get_float(value).ok_or_else(invalid)?;
+            put_key(number, FIXED64, buf);
+            put_fixed64(v.to_bits(), buf);
+        }
+        Kind::String => {
+            let v = value.as_str().ok_or_else(invalid)?;
+            put_key(number, LENGTH_DELIMITED, buf);
+            put_varint(v.len() as u64, buf);
+            buf.extend_from_slice(v.as_bytes());
+        }
+        Kind::Bytes => {
+            // Both the standard and URL-safe alphabets are accepted.
+            let v: Vec<u8> = value.as_str().ok_or_else(invalid)?
+                .bytes()
+                .map(|b| match b {
+                    b'-' => b'+',
+                    b'_' => b'/',
+                    b => b,
+                })
+                .collect();
+            let v = base64::decode(&v).ok_or_else(invalid)?;
+
+            put_key(number, LENGTH_DELIMITED, buf);
+            put_varint(v.len() as u64, buf);
+            buf.extend_from_slice(&v);
+        }
+        Kind::Message(ref name) => {
+            let mut nested = vec![];
+            encode(descriptors, name, value, &mut nested)?;
+
+            put_key(number, LENGTH_DELIMITED, buf);
+            put_varint(nested.len() as u64, buf);
+            buf.extend_from_slice(&nested);
+        }
+    }
+
+    Ok(())
+}
+
+fn get_int(value: &Value) -> Option<i64> {
+    match *value {
+        Value::Number(ref n) => n.as_i64(),
+        Value::String(ref s) => s.parse().ok(),
+        _ => None,
+    }
+}
+
+fn get_uint(value: &Value) -> Option<u64> {
+    match *value {
+        Value::Number(ref n) => n.as_u64(),
+        Value::String(ref s) => s.parse().ok(),
+        _ => None,
+    }
+}
+
+fn get_float(value: &Value) -> Option<f64> {
+    use std::f64;
+
+    match *value {
+        Value::Number(ref n) => n.as_f64(),
+        Value::String(ref s) => match &s[..] {
+            "NaN" => Some(f64::NAN),
+            "Infinity" => Some(f64::INFINITY),
+            "-Infinity" => Some(f64::NEG_INFINITY),
+            s => s.parse().ok(),
+        },
+        _ => None,
+    }
+}
+
+fn get_i32(value: &Value) -> Option<i64> {
+    use std::i32;
+
+    get_int(value).and_then(|v| {
+        if v >= i32::MIN as i64 && v <= i32::MAX as i64 { Some(v) } else { None }
+    })
+}
+
+fn get_u32(value: &Value) -> Option<u64> {
+    use std::u32;
+
+    get_uint(value).and_then(|v| if v <= u32::MAX as u64 { Some(v) } else { None })
+}
+
+fn put_key(number: u32, wire: u8, buf: &mut Vec<u8>) {
+    put_varint((number as u64) << 3 | wire as u64, buf);
+}
+
+fn put_varint(mut v: u64, buf: &mut Vec<u8>) {
+    while v >= 0x80 {
+        buf.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+
+    buf.push(v as u8);
+}
+
+fn put_fixed32(v: u32, buf: &mut Vec<u8>) {
+    for i in 0..4 {
+        buf.push((v >> (8 * i)) as u8);
+    }
+}
+
+fn put_fixed64(v: u64, buf: &mut Vec<u8>) {
+    for i in 0..8 {
+        buf.push((v >> (8 * i)) as u8);
+    }
+}
+
+// ===== decoding =====
+
+fn decode_value(descriptors: &Descriptors, field: &Field, wire: u8, bytes: &mut &[u8])
+    -> Result<Value, String>
+{
+    if wire != wire_type(&field.kind) {
+        return Err(format!("unexpected wire type {} for field {}", wire, field.name));
+    }
+
+    let value = match field.kind {
+        Kind::Int32 => Value::from(get_varint(bytes)? as i32),
+        Kind::Int64 => Value::String((get_varint(bytes)? as i64).to_string()),
+        Kind::Uint32 => Value::from(get_varint(bytes)? as u32),
+        Kind::Uint64 => Value::String(get_varint(bytes)?.to_string()),
+        Kind::Sint32 => {
+            let v = get_varint(bytes)?;
+            Value::from(((v >> 1) as i64 ^ -((v & 1) as i64)) as i32)
+        }
+        Kind::Sint64 => {
+            let v = get_varint(bytes)?;
+            Value::String(((v >> 1) as i64 ^ -((v & 1) as i64)).to_string())
+        }
+        Kind::Bool => Value::Bool(get_varint(bytes)? != 0),
+        Kind::Enum(ref name) => {
+            let v = get_varint(bytes)? as i32;
+
+            descriptors.enums.get(name)
+                .and_then(|e| e.values.iter().find(|&&(_, n)| n == v))
+                .map(|&(ref name, _)| Value::String(name.clone()))
+                .unwrap_or_else(|| Value::from(v))
+        }
+        Kind::Fixed32 => Value::from(get_fixed32(bytes)?),
+        Kind::Sfixed32 => Value::from(get_fixed32(bytes)? as i32),
+        Kind::Fixed64 => Value::String(get_fixed64(bytes)?.to_string()),
+        Kind::Sfixed64 => Value::String((get_fixed64(bytes)? as i64).to_string()),
+        Kind::Float => float(f32::from_bits(get_fixed32(bytes)?) as f64),
+        Kind::Double => float(f64::from_bits(get_fixed64(bytes)?)),
+        Kind::String => {
+            let v = take_length_delimited(bytes)?;
+            let v = String::from_utf8(v.to_vec())
+                .map_err(|_| format!("invalid UTF-8 in field {}", field.name))?;
+            Value::String(v)
+        }
+        Kind::Bytes => Value::String(base64::encode_padded(take_length_delimited(bytes)?)),
+        Kind::Message(ref name) => decode(descriptors, name, take_length_delimited(bytes)?)?,
+    };
+
+    Ok(value)
+}
+
+fn float(v: f64) -> Value {
+    if v.is_nan() {
+        Value::String("NaN".to_string())
+    } else if v.is_infinite() && v > 0.0 {
+        Value::String("Infinity".to_string())
+    } else if v.is_infinite() {
+        Value::String("-Infinity".to_string())
+    } else {
+        Number::from_f64(v).map_or(Value::Null, Value::Number)
+    }
+}
+
+fn get_varint(bytes: &mut &[u8]) -> Result<u64, String> {
+    let mut v = 0u64;
+
+    for i in 0..10 {
+        let b = *bytes.get(i).ok_or_else(truncated)?;
+        v |= ((b & 0x7f) as u64) << (7 * i);
+
+        if b < 0x80 {
+            *bytes = &bytes[i + 1..];
+            return Ok(v);
+        }
+    }
+
+    Err("invalid varint".to_string())
+}
+
+fn get_fixed32(bytes: &mut &[u8]) -> Result<u32, String> {
+    let v = take(bytes, 4)?;
+    Ok(v.iter().rev().fold(0, |n, &b| n << 8 | b as u32))
+}
+
+fn get_fixed64(bytes: &mut &[u8]) -> Result<u64, String> {
+    let v = take(bytes, 8)?;
+    Ok(v.iter().rev().fold(0, |n, &b| n << 8 | b as u64))
+}
+
+fn take_length_delimited<'a>(bytes: &mut &'a [u8]) -> Result<&'a [u8], String> {
+    let len = get_varint(bytes)? as usize;
+    take(bytes, len)
+}
+
+fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Result<&'a [u8], String> {
+    if bytes.len() < len {
+        return Err(truncated());
+    }
+
+    let (v, rest) = bytes.split_at(len);
+    *bytes = rest;
+    Ok(v)
+}
+
+fn skip(wire: u8, bytes: &mut &[u8]) -> Result<(), String> {
+    match wire {
+        VARINT => get_varint(bytes).map(|_| ()),
+        FIXED64 => take(bytes, 8).map(|_| ()),
+        LENGTH_DELIMITED => take_length_delimited(bytes).map(|_| ()),
+        FIXED32 => take(bytes, 4).map(|_| ()),
+        _ => Err(format!("unsupported wire type {}", wire)),
+    }
+}
+
+fn truncated() -> String {
+    "truncated message".to_string()
+}
+
+// ===== utility fns =====
+
+fn wire_type(kind: &Kind) -> u8 {
+    match *kind {
+        Kind::Int32 | Kind::Int64 | Kind::Uint32 | Kind::Uint64 |
+        Kind::Sint32 | Kind::Sint64 | Kind::Bool | Kind::Enum(_) => VARINT,
+        Kind::Fixed64 | Kind::Sfixed64 | Kind::Double => FIXED64,
+        Kind::Fixed32 | Kind::Sfixed32 | Kind::Float => FIXED32,
+        Kind::String | Kind::Bytes | Kind::Message(_) => LENGTH_DELIMITED,
+    }
+}
+
+/// Returns the key and value fields of a map field's entries.
+fn map_fields<'a>(descriptors: &'a Descriptors, field: &Field) -> Option<(&'a Field, &'a Field)> {
+    if !field.repeated {
+        return None;
+    }
+
+    let entry = match field.kind {
+        Kind::Message(ref name) => descriptors.messages.get(name)?,
+        _ => return None,
+    };
+
+    if !entry.map_entry {
+        return None;
+    }
+
+    Some((entry.field_by_number(1)?, entry.field_by_number(2)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::descriptor::{EnumType, MessageType};
+
+    use serde_json;
+
+    /// The types of `test.Everything`, with a field of most kinds.
+    fn descriptors() -> Descriptors {
+        let mut descriptors = Descriptors::default();
+
+        descriptors.messages.insert("test.Everything".to_string(), MessageType {
+            fields: vec![
+                field("count", 1, Kind::Int32, false),
+                field("total", 2, Kind::Int64, false),
+                field("enabled", 3, Kind::Bool, false),
+                field("color", 4, Kind::Enum("test.Color".to_string()), false),
+                field("data", 5, Kind::Bytes, false),
+                field("names", 6, Kind::String, true),
+                field("tags", 7, Kind::Message("test.Everything.TagsEntry".to_string()), true),
+                field("inner", 8, Kind::Message("test.Inner".to_string()), false),
+                field("delta", 9, Kind::Sint32, false),
+                field("ratio", 10, Kind::Double, false),
+                field("ids", 11, Kind::Int32, true),
+                Field {
+                    name: "user_name".to_string(),
+                    json_name: "userName".to_string(),
+                    number: 12,
+                    kind: Kind::String,
+                    repeated: false,
+                },
+            ],
+            map_entry: false,
+        });
+        descriptors.messages.insert("test.Everything.TagsEntry".to_string(), MessageType {
+            fields: vec![
+                field("key", 1, Kind::String, false),
+                field("value", 2, Kind::Int32, false),
+            ],
+            map_entry: true,
+        });
+        descriptors.messages.insert("test.Inner".to_string(), MessageType {
+            fields: vec![field("name", 1, Kind::String, false)],
+            map_entry: false,
+        });
+
+        descriptors.enums.insert("test.Color".to_string(), EnumType {
+            values: vec![("RED".to_string(), 0), ("BLUE".to_string(), 1)],
+        });
+
+        descriptors
+    }
+
+    fn field(name: &str, number: u32, kind: Kind, repeated: bool) -> Field {
+        Field {
+            name: name.to_string(),
+            json_name: name.to_string(),
+            number,
+            kind,
+            repeated,
+        }
+    }
+
+    fn parse(json: &str) -> Value {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn encode_json(json: &str) -> Result<Vec<u8>, String> {
+        let mut buf = vec![];
+        encode(&descriptors(), "test.Everything", &parse(json), &mut buf)?;
+        Ok(buf)
+    }
+
+    fn decode_bytes(bytes: &[u8]) -> Result<Value, String> {
+        decode(&descriptors(), "test.Everything", bytes)
+    }
+
+    #[test]
+    fn round_trips_every_kind() {
+        let json = r#"{
+            "count": -3,
+            "total": "-5",
+            "enabled": true,
+            "color": "BLUE",
+            "data": "aGk=",
+            "names": ["a", "b"],
+            "tags": {"x": 1, "y": 2},
+            "inner": {"name": "n"},
+            "delta": -2,
+            "ratio": 0.5,
+            "ids": [1, 2],
+            "userName": "ann"
+        }"#;
+
+        let encoded = encode_json(json).unwrap();
+        assert_eq!(decode_bytes(&encoded).unwrap(), parse(json));
+    }
+
+    #[test]
+    fn scalars_encoded_as_protobuf() {
+        assert_eq!(encode_json(r#"{"count": 1}"#).unwrap(), vec![0x08, 0x01]);
+        assert_eq!(encode_json(r#"{"count": -1}"#).unwrap(),
+                   vec![0x08, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]);
+        assert_eq!(encode_json(r#"{"delta": -1}"#).unwrap(), vec![0x48, 0x01]);
+        assert_eq!(encode_json(r#"{"color": "BLUE"}"#).unwrap(), vec![0x20, 0x01]);
+        assert_eq!(encode_json(r#"{"data": "aGk="}"#).unwrap(), vec![0x2a, 0x02, b'h', b'i']);
+    }
+
+    #[test]
+    fn lenient_inputs_accepted() {
+        // Numbers as strings, enum numbers, proto names and the URL-safe
+        // base64 alphabet.
+        assert_eq!(encode_json(r#"{"count": "7"}"#).unwrap(), vec![0x08, 0x07]);
+        assert_eq!(encode_json(r#"{"enabled": "true"}"#).unwrap(), vec![0x18, 0x01]);
+        assert_eq!(encode_json(r#"{"color": 1}"#).unwrap(), vec![0x20, 0x01]);
+        assert_eq!(encode_json(r#"{"user_name": "a"}"#).unwrap(), vec![0x62, 0x01, b'a']);
+        assert_eq!(encode_json(r#"{"data": "-_8="}"#).unwrap(), vec![0x2a, 0x02, 0xfb, 0xff]);
+
+        // A single value sets a repeated field, and null fields are unset.
+        assert_eq!(encode_json(r#"{"names": "a"}"#).unwrap(), vec![0x32, 0x01, b'a']);
+        assert_eq!(encode_json(r#"{"count": null}"#).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn invalid_values_rejected() {
+        for json in &[
+            r#"{"unknown": 1}"#,
+            r#"{"count": "x"}"#,
+            r#"{"count": 4294967296}"#,
+            r#"{"count": [1]}"#,
+            r#"{"color": "GREEN"}"#,
+            r#"{"data": "!"}"#,
+            r#"{"tags": [1]}"#,
+            r#"[]"#,
+        ] {
+            assert!(encode_json(json).is_err(), "{}", json);
+        }
+    }
+
+    #[test]
+    fn special_floats_as_strings() {
+        let encoded = encode_json(r#"{"ratio": "NaN"}"#).unwrap();
+        assert_eq!(decode_bytes(&encoded).unwrap(), parse(r#"{"ratio": "NaN"}"#));
+
+        let encoded = encode_json(r#"{"ratio": "-Infinity"}"#).unwrap();
+        assert_eq!(decode_bytes(&encoded).unwrap(), parse(r#"{"ratio": "-Infinity"}"#));
+    }
+
+    #[test]
+    fn decodes_packed_and_unpacked_repeated_scalars() {
+        assert_eq!(decode_bytes(&[0x5a, 0x02, 0x01, 0x02]).unwrap(), parse(r#"{"ids": [1, 2]}"#));
+        assert_eq!(decode_bytes(&[0x58, 0x01, 0x58, 0x02]).unwrap(), parse(r#"{"ids": [1, 2]}"#));
+    }
+
+    #[test]
+    fn unknown_fields_and_enum_values_kept_by_number_or_skipped() {
+        // Field 15 is unknown, and 7 isn't a value of the enum.
+        let bytes = [0x78, 0x01, 0x20, 0x07];
+        assert_eq!(decode_bytes(&bytes).unwrap(), parse(r#"{"color": 7}"#));
+    }
+
+    #[test]
+    fn invalid_messages_rejected() {
+        // Truncated length-delimited and varint fields.
+        assert!(decode_bytes(&[0x32, 0x05, b'a']).is_err());
+        assert!(decode_bytes(&[0x08, 0xff]).is_err());
+
+        // A string field with the wire type of a varint.
+        assert!(decode_bytes(&[0x30, 0x01]).is_err());
+
+        // Invalid UTF-8.
+        assert!(decode_bytes(&[0x32, 0x01, 0xff]).is_err());
+
+        assert!(decode(&descriptors(), "test.Unknown", &[]).is_err());
+    }
+}
@@ -0,0 +1,1271 @@
+//! gRPC-JSON transcoding.
+//!
+//! `Transcoder` serves REST clients from the `google.api.http` annotations
+//! of a service's methods, so a single implementation answers both gRPC and
+//! REST+JSON calls:
+//!
+//! ```proto
+//! rpc GetUser(GetUserRequest) returns (User) {
+//!   option (google.api.http) = { get: "/v1/users/{id}" };
+//! }
+//! ```
+//!
+//! Each REST request is converted to a gRPC call on an inner HTTP/2.0
+//! client service, such as an in-process connection to the server, and the
+//! response messages are rendered as JSON:
+//!
+//! ```ignore
+//! let conn = core.run(inprocess::connect(UsersServer::new(users), &handle))?;
+//!
+//! let transcoder = Transcoder::new(conn, Uri::from_static("http://users"))
+//!     .register_file_descriptor_set(include_bytes!("users.bin"))?;
+//!
+//! // Served to HTTP/1.1 clients by hyper.
+//! let serve = Http::new().serve_connection(sock, HyperServer::new(transcoder));
+//! ```
+//!
+//! The file descriptor set is written by `tower-grpc-build`, and must
+//! include `google/api/http.proto` along with the imports of the service.
+//!
+//! The request message is built from the request body, as selected by the
+//! rule's `body`, the variables of the path template and the query
+//! parameters. Requests are matched against the rules in registration
+//! order. Unary and server streaming methods are supported. The messages of
+//! a server stream are rendered as a JSON array once the stream has ended.
+//!
+//! Server streaming methods may also be rendered as [server-sent events],
+//! for clients that can't read streamed responses otherwise. Once enabled
+//! with `Transcoder::server_sent_events`, requests accepting
+//! `text/event-stream` receive each message as a JSON event as soon as it
+//! is sent, followed by a `status` event with the status of the call:
+//!
+//! ```text
+//! data: {"id":"1"}
+//!
+//! data: {"id":"2"}
+//!
+//! event: status
+//! data: {"code":0,"message":"","details":[]}
+//! ```
+//!
+//! Failed calls are rendered as `{"code": 5, "message": "...", "details": []}`
+//! with the HTTP status corresponding to the gRPC status code.
+//!
+//! [server-sent events]: https://html.spec.whatwg.org/multipage/server-sent-events.html
+
+pub(crate) mod descriptor;
+pub(crate) mod json;
+mod template;
+
+use self::descriptor::{Descriptors, FileDescriptorSet, Rule};
+use {Code, Status};
+use generic::{BytesCodec, Encode, Streaming};
+
+use bytes::Bytes;
+use futures::{Future, Stream, Poll, Async};
+use h2;
+use http::{self, header, HeaderMap, Uri};
+use http::header::HeaderValue;
+use prost::Message;
+use serde_json::{self, Map, Value};
+use tower::Service;
+use tower_h2::{Body, BoxBody, HttpService};
+
+use std::{error, fmt};
+use std::sync::{Arc, Mutex};
+
+/// Serves REST+JSON requests by transcoding them to gRPC calls on the inner
+/// service.
+#[derive(Clone)]
+pub struct Transcoder<S> {
+    inner: S,
+    origin: Uri,
+    descriptors: Arc<Descriptors>,
+    server_sent_events: bool,
+}
+
+/// Error returned when a file descriptor set can't be registered.
+#[derive(Debug)]
+pub struct DescriptorError {
+    message: String,
+}
+
+/// The response future returned by `Transcoder`.
+pub struct ResponseFuture<F, B> {
+    state: State<F, B>,
+    call: Option<Call>,
+}
+
+/// The JSON response body returned by `Transcoder`.
+pub struct ResponseBody<B> {
+    kind: Kind<B>,
+}
+
+enum Kind<B> {
+    /// A JSON value, sent at once.
+    Full(Option<Bytes>),
+
+    /// The messages of a server stream, sent as events.
+    Events(Events<B>),
+}
+
+/// Renders the messages of a server stream as server-sent events, followed
+/// by the status event.
+struct Events<B> {
+    messages: Option<Streaming<BytesCodec, B>>,
+    call: Call,
+}
+
+enum State<F, B> {
+    /// Waiting for the response head.
+    Calling(F),
+
+    /// Reading the response messages.
+    Reading(Streaming<BytesCodec, B>, Vec<Value>),
+
+    /// The request was answered without calling the inner service.
+    Done(Option<http::Response<ResponseBody<B>>>),
+}
+
+/// The matched rule of a call.
+#[derive(Clone)]
+struct Call {
+    descriptors: Arc<Descriptors>,
+    rule: usize,
+
+    /// Set if the messages are sent as server-sent events.
+    events: bool,
+
+    /// Describes why the request could not be converted, if it failed.
+    error: Arc<Mutex<Option<String>>>,
+}
+
+/// Converts the REST request body to the single message of the call.
+struct RequestMessage<B> {
+    body: B,
+    buf: Vec<u8>,
+
+    /// The fields set by the path and query.
+    fields: Map<String, Value>,
+
+    descriptors: Arc<Descriptors>,
+    rule: usize,
+    error: Arc<Mutex<Option<String>>>,
+    done: bool,
+}
+
+// ===== impl Transcoder =====
+
+impl<S> Transcoder<S> {
+    /// Transcode requests to calls on `inner`, sent to the scheme and
+    /// authority of `origin`.
+    ///
+    /// No request is transcoded until a file descriptor set is registered.
+    pub fn new(inner: S, origin: Uri) -> Self {
+        Transcoder {
+            inner,
+            origin,
+            descriptors: Arc::new(Descriptors::default()),
+            server_sent_events: false,
+        }
+    }
+
+    /// Add the types and HTTP rules of an encoded `FileDescriptorSet`.
+    pub fn register_file_descriptor_set(mut self, encoded: &[u8]) -> Result<Self, DescriptorError> {
+        register(&mut self.descriptors, encoded)?;
+        Ok(self)
+    }
+
+    /// Render server streaming methods as server-sent events for requests
+    /// accepting `text/event-stream`.
+    ///
+    /// Server streams are otherwise rendered as JSON arrays.
+    pub fn server_sent_events(mut self, enabled: bool) -> Self {
+        self.server_sent_events = enabled;
+        self
+    }
+
+    /// Returns true if a request for `method` and `path` matches a rule.
+    pub fn handles(&self, method: &http::Method, path: &str) -> bool {
+        self.route(method, path).is_some()
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner service.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    fn route(&self, method: &http::Method, path: &str) -> Option<(usize, Vec<(Vec<String>, String)>)> {
+        self.descriptors.rules.iter()
+            .enumerate()
+            .filter(|&(_, rule)| rule.method == *method)
+            .filter_map(|(i, rule)| rule.template.matches(path).map(|vars| (i, vars)))
+            .next()
+    }
+}
+
+impl<S, B> Service for Transcoder<S>
+where S: HttpService<RequestBody = BoxBody>,
+      B: Body + Send + 'static,
+      B::Data: Into<Bytes>,
+{
+    type Request = http::Request<B>;
+    type Response = http::Response<ResponseBody<S::ResponseBody>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future, S::ResponseBody>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, request: Self::Request) -> Self::Future {
+        let (i, vars) = match self.route(request.method(), request.uri().path()) {
+            Some(route) => route,
+            None => {
+                debug!("no rule matches request; method={}, path={}",
+                       request.method(), request.uri().path());
+                return ResponseFuture::failed(Code::NOT_FOUND, "no method matches the request");
+            }
+        };
+
+        let rule = &self.descriptors.rules[i];
+
+        if rule.client_streaming {
+            return ResponseFuture::failed(Code::UNIMPLEMENTED,
+                                          "client streaming methods can't be transcoded");
+        }
+
+        // Set the fields bound by the path and the query parameters.
+        let mut fields = Map::new();
+
+        for (path, value) in vars {
+            set_field(&mut fields, &path, Value::String(value));
+        }
+
+        if let Some(query) = request.uri().query() {
+            for (key, value) in parse_query(query) {
+                let path: Vec<String> = key.split('.').map(String::from).collect();
+                append_field(&mut fields, &path, Value::String(value));
+            }
+        }
+
+        let events = self.server_sent_events && rule.server_streaming &&
+            request.headers().get_all(header::ACCEPT).iter()
+                .filter_map(|accept| accept.to_str().ok())
+                .any(|accept| accept.contains("text/event-stream"));
+
+        let error = Arc::new(Mutex::new(None));
+
+        let (head, body) = request.into_parts();
+
+        let message = RequestMessage {
+            body,
+            buf: vec![],
+            fields,
+            descriptors: self.descriptors.clone(),
+            rule: i,
+            error: error.clone(),
+            done: false,
+        };
+
+        let mut parts = http::uri::Parts::default();
+        parts.scheme = self.origin.scheme_part().cloned();
+        parts.authority = self.origin.authority_part().cloned();
+        parts.path_and_query = Some(rule.path.parse().expect("method paths are valid"));
+
+        let uri = match Uri::from_parts(parts) {
+            Ok(uri) => uri,
+            Err(_) => return ResponseFuture::failed(Code::INTERNAL, "invalid origin"),
+        };
+
+        let mut call = http::Request::new(BoxBody::new(Box::new(Encode::new(BytesCodec, message, false))));
+        *call.method_mut() = http::Method::POST;
+        *call.uri_mut() = uri;
+        *call.headers_mut() = forward_headers(head.headers);
+        call.headers_mut()
+            .insert(header::TE, HeaderValue::from_static("trailers"));
+        call.headers_mut()
+            .insert(header::CONTENT_TYPE, HeaderValue::from_static("application/grpc+proto"));
+
+        ResponseFuture {
+            state: State::Calling(self.inner.call(call)),
+            call: Some(Call {
+                descriptors: self.descriptors.clone(),
+                rule: i,
+                events,
+                error,
+            }),
+        }
+    }
+}
+
+impl<S> fmt::Debug for Transcoder<S>
+where S: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Transcoder")
+            .field("inner", &self.inner)
+            .field("origin", &self.origin)
+            .field("rules", &self.descriptors.rules.len())
+            .field("server_sent_events", &self.server_sent_events)
+            .finish()
+    }
+}
+
+// ===== impl DescriptorError =====
+
+impl DescriptorError {
+    fn new(message: String) -> Self {
+        DescriptorError { message }
+    }
+}
+
+impl fmt::Display for DescriptorError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(&self.message)
+    }
+}
+
+impl error::Error for DescriptorError {
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F, B> ResponseFuture<F, B> {
+    fn failed(code: Code, message: &str) -> Self {
+        ResponseFuture {
+            state: State::Done(Some(error_response(code, message))),
+            call: None,
+        }
+    }
+}
+
+impl<F, B> Future for ResponseFuture<F, B>
+where F: Future<Item = http::Response<B>>,
+      B: Body,
+      B::Data: Into<Bytes>,
+{
+    type Item = http::Response<ResponseBody<B>>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                State::Calling(ref mut future) => {
+                    let response = match future.poll() {
+                        Ok(Async::Ready(response)) => response,
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Err(e) => {
+                            // The call fails when its request is rejected.
+                            if let Some(response) = self.call.as_ref().and_then(Call::rejected) {
+                                return Ok(response.into());
+                            }
+
+                            return Err(e);
+                        }
+                    };
+
+                    let (head, body) = response.into_parts();
+
+                    // A trailers-only response.
+                    if let Some(status) = head.headers.get("grpc-status") {
+                        let status = Status::from_bytes(status.as_ref());
+
+                        if status.code() != Code::OK {
+                            let message = grpc_message(&head.headers);
+                            return Ok(error_response(status.code(), &message).into());
+                        }
+                    }
+
+                    let messages = Streaming::new(BytesCodec, body, true);
+
+                    if let Some(ref call) = self.call {
+                        if call.events {
+                            let body = ResponseBody {
+                                kind: Kind::Events(Events {
+                                    messages: Some(messages),
+                                    call: call.clone(),
+                                }),
+                            };
+
+                            let mut response = http::Response::new(body);
+                            response.headers_mut()
+                                .insert(header::CONTENT_TYPE, HeaderValue::from_static("text/event-stream"));
+                            response.headers_mut()
+                                .insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+
+                            return Ok(response.into());
+                        }
+                    }
+
+                    State::Reading(messages, vec![])
+                }
+                State::Reading(ref mut messages, ref mut values) => {
+                    let call = self.call.as_ref().expect("reading without a call");
+
+                    match messages.poll() {
+                        Ok(Async::Ready(Some(message))) => {
+                            match call.render(&message) {
+                                Ok(value) => values.push(value),
+                                Err(e) => {
+                                    debug!("response message could not be rendered; error={}", e);
+                                    return Ok(error_response(Code::INTERNAL, &e).into());
+                                }
+                            }
+                            continue;
+                        }
+                        Ok(Async::Ready(None)) => {}
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Err(e) => {
+                            if let Some(response) = call.rejected() {
+                                return Ok(response.into());
+                            }
+
+                            let message = messages.trailers()
+                                .map(grpc_message)
+                                .unwrap_or_default();
+
+                            return Ok(error_response(Status::from(e).code(), &message).into());
+                        }
+                    }
+
+                    let value = if call.rule().server_streaming {
+                        Value::Array(values.drain(..).collect())
+                    } else {
+                        values.pop().unwrap_or_else(|| Value::Object(Map::new()))
+                    };
+
+                    return Ok(json_response(http::StatusCode::OK, &value).into());
+                }
+                State::Done(ref mut response) => {
+                    return Ok(response.take().expect("polled after complete").into());
+                }
+            };
+
+            self.state = next;
+        }
+    }
+}
+
+impl<F, B> fmt::Debug for ResponseFuture<F, B> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let state = match self.state {
+            State::Calling(..) => "Calling",
+            State::Reading(..) => "Reading",
+            State::Done(..) => "Done",
+        };
+
+        fmt.debug_struct("transcode::ResponseFuture")
+            .field("state", &state)
+            .finish()
+    }
+}
+
+// ===== impl ResponseBody =====
+
+impl<B> Body for ResponseBody<B>
+where B: Body,
+      B::Data: Into<Bytes>,
+{
+    type Data = Bytes;
+
+    fn is_end_stream(&self) -> bool {
+        match self.kind {
+            Kind::Full(ref data) => data.is_none(),
+            Kind::Events(ref events) => events.messages.is_none(),
+        }
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Bytes>, h2::Error> {
+        match self.kind {
+            Kind::Full(ref mut data) => Ok(data.take().into()),
+            Kind::Events(ref mut events) => events.poll_event(),
+        }
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, h2::Error> {
+        Ok(None.into())
+    }
+}
+
+impl<B> fmt::Debug for ResponseBody<B> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let mut fmt = fmt.debug_struct("transcode::ResponseBody");
+
+        match self.kind {
+            Kind::Full(ref data) => fmt.field("data", data),
+            Kind::Events(ref events) => fmt.field("ended", &events.messages.is_none()),
+        };
+
+        fmt.finish()
+    }
+}
+
+// ===== impl Events =====
+
+impl<B> Events<B>
+where B: Body,
+      B::Data: Into<Bytes>,
+{
+    /// Returns the next event, ending with the status event.
+    fn poll_event(&mut self) -> Poll<Option<Bytes>, h2::Error> {
+        let (code, message) = match self.messages {
+            Some(ref mut messages) => match messages.poll() {
+                Ok(Async::Ready(Some(message))) => {
+                    match self.call.render(&message) {
+                        Ok(value) => return Ok(Some(event(None, &value)).into()),
+                        Err(e) => {
+                            debug!("response message could not be rendered; error={}", e);
+                            (Code::INTERNAL, e)
+                        }
+                    }
+                }
+                Ok(Async::Ready(None)) => (Code::OK, String::new()),
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(e) => {
+                    let message = messages.trailers()
+                        .map(grpc_message)
+                        .unwrap_or_default();
+
+                    (Status::from(e).code(), message)
+                }
+            },
+            None => return Ok(None.into()),
+        };
+
+        self.messages = None;
+        Ok(Some(event(Some("status"), &status_json(code, &message))).into())
+    }
+}
+
+// ===== impl Call =====
+
+impl Call {
+    fn rule(&self) -> &Rule {
+        &self.descriptors.rules[self.rule]
+    }
+
+    /// Returns the error response of a request that could not be converted.
+    fn rejected<B>(&self) -> Option<http::Response<ResponseBody<B>>> {
+        let error = self.error.lock().unwrap();
+
+        error.as_ref()
+            .map(|message| error_response(Code::INVALID_ARGUMENT, message))
+    }
+
+    /// Render a response message as JSON.
+    fn render(&self, message: &[u8]) -> Result<Value, String> {
+        let rule = self.rule();
+        let value = json::decode(&self.descriptors, &rule.output_type, message)?;
+
+        if rule.response_body.is_empty() {
+            return Ok(value);
+        }
+
+        let json_name = self.descriptors.messages.get(&rule.output_type)
+            .and_then(|output| output.field_by_name(&rule.response_body))
+            .map(|field| field.json_name.clone())
+            .ok_or_else(|| format!("unknown response body field {}", rule.response_body))?;
+
+        Ok(match value {
+            Value::Object(mut object) => object.remove(&json_name).unwrap_or(Value::Null),
+            _ => Value::Null,
+        })
+    }
+}
+
+// ===== impl RequestMessage =====
+
+impl<B> RequestMessage<B> {
+    /// Build and encode the request message, once the body has been read.
+    fn encode(&mut self) -> Result<Vec<u8>, String> {
+        let rule = &self.descriptors.rules[self.rule];
+
+        let body = if rule.body.is_empty() || self.buf.is_empty() {
+            Value::Object(Map::new())
+        } else {
+            serde_json::from_slice(&self.buf)
+                .map_err(|e| format!("invalid JSON request body: {}", e))?
+        };
+
+        let mut value = match &rule.body[..] {
+            "*" => body,
+            "" => Value::Object(Map::new()),
+            field => {
+                let mut object = Map::new();
+                object.insert(field.to_string(), body);
+                Value::Object(object)
+            }
+        };
+
+        match value {
+            Value::Object(ref mut object) => {
+                for (key, field) in self.fields.iter() {
+                    merge_field(object, key, field);
+                }
+            }
+            _ => return Err("request body must be an object".to_string()),
+        }
+
+        let mut buf = vec![];
+        json::encode(&self.descriptors, &rule.input_type, &value, &mut buf)?;
+        Ok(buf)
+    }
+}
+
+impl<B> Stream for RequestMessage<B>
+where B: Body,
+      B::Data: Into<Bytes>,
+{
+    type Item = Vec<u8>;
+    type Error = ::Error;
+
+    fn poll(&mut self) -> Poll<Option<Vec<u8>>, ::Error> {
+        if self.done {
+            return Ok(Async::Ready(None));
+        }
+
+        while let Some(data) = try_ready!(self.body.poll_data().map_err(Status::from)) {
+            self.buf.extend_from_slice(&data.into());
+        }
+
+        self.done = true;
+
+        match self.encode() {
+            Ok(message) => Ok(Async::Ready(Some(message))),
+            Err(e) => {
+                debug!("request could not be transcoded; error={}", e);
+                *self.error.lock().unwrap() = Some(e);
+                Err(::Error::Grpc(Status::INVALID_ARGUMENT))
+            }
+        }
+    }
+}
+
+// ===== utility fns =====
+
+/// Add the files of an encoded `FileDescriptorSet` to `descriptors`.
+pub(crate) fn register(descriptors: &mut Arc<Descriptors>, encoded: &[u8])
+    -> Result<(), DescriptorError>
+{
+    let set = FileDescriptorSet::decode(encoded)
+        .map_err(|_| DescriptorError::new("invalid file descriptor set".to_string()))?;
+
+    let descriptors = Arc::get_mut(descriptors)
+        .expect("descriptors are registered before the service is cloned");
+
+    for file in set.file {
+        descriptors.add(file).map_err(DescriptorError::new)?;
+    }
+
+    Ok(())
+}
+
+/// Set the field at `path`, creating the messages along the way.
+fn set_field(object: &mut Map<String, Value>, path: &[String], value: Value) {
+    let (last, parents) = path.split_last().expect("field paths are not empty");
+    let mut object = object;
+
+    for name in parents {
+        let next = object.entry(name.clone())
+            .or_insert_with(|| Value::Object(Map::new()));
+
+        object = match *next {
+            Value::Object(ref mut next) => next,
+            _ => return,
+        };
+    }
+
+    object.insert(last.clone(), value);
+}
+
+/// Like `set_field`, but repeated query parameters are collected into an
+/// array.
+fn append_field(object: &mut Map<String, Value>, path: &[String], value: Value) {
+    let existing = path.split_last()
+        .and_then(|(last, parents)| {
+            let mut current = &*object;
+            for name in parents {
+                current = current.get(name)?.as_object()?;
+            }
+            current.get(last).cloned()
+        });
+
+    let value = match existing {
+        None => value,
+        Some(Value::Array(mut values)) => {
+            values.push(value);
+            Value::Array(values)
+        }
+        Some(other) => Value::Array(vec![other, value]),
+    };
+
+    set_field(object, path, value);
+}
+
+/// Set the fields of the path and query in the message built from the
+/// body. Fields of the body take precedence.
+fn merge_field(object: &mut Map<String, Value>, key: &str, value: &Value) {
+    match (object.get_mut(key), value) {
+        (Some(&mut Value::Object(ref mut existing)), &Value::Object(ref value)) => {
+            for (k, v) in value {
+                merge_field(existing, k, v);
+            }
+            return;
+        }
+        (Some(_), _) => return,
+        (None, _) => {}
+    }
+
+    object.insert(key.to_string(), value.clone());
+}
+
+/// Parse the percent-encoded parameters of a query string.
+fn parse_query(query: &str) -> Vec<(String, String)> {
+    query.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let mut kv = pair.splitn(2, '=');
+            let key = kv.next().unwrap_or("").replace('+', " ");
+            let value = kv.next().unwrap_or("").replace('+', " ");
+
+            (template::percent_decode(&key), template::percent_decode(&value))
+        })
+        .collect()
+}
+
+/// Forward the request headers as metadata, except those describing the
+/// HTTP/1.1 request itself.
+pub(crate) fn forward_headers(headers: HeaderMap) -> HeaderMap {
+    let mut metadata = HeaderMap::new();
+
+    for (name, value) in headers.iter() {
+        match *name {
+            header::CONNECTION | header::CONTENT_LENGTH | header::CONTENT_TYPE |
+            header::HOST | header::TE | header::TRANSFER_ENCODING | header::UPGRADE |
+            header::ACCEPT | header::ACCEPT_ENCODING => continue,
+            _ => {}
+        }
+
+        metadata.append(name.clone(), value.clone());
+    }
+
+    metadata
+}
+
+pub(crate) fn grpc_message(headers: &HeaderMap) -> String {
+    headers.get("grpc-message")
+        .and_then(|message| message.to_str().ok())
+        .map(template::percent_decode)
+        .unwrap_or_default()
+}
+
+/// Render an error as JSON, with the HTTP status corresponding to `code`.
+fn error_response<B>(code: Code, message: &str) -> http::Response<ResponseBody<B>> {
+    json_response(http_status(code), &status_json(code, message))
+}
+
+fn status_json(code: Code, message: &str) -> Value {
+    let mut status = Map::new();
+    status.insert("code".to_string(), Value::from(code.as_i32()));
+    status.insert("message".to_string(), Value::String(message.to_string()));
+    status.insert("details".to_string(), Value::Array(vec![]));
+
+    Value::Object(status)
+}
+
+fn json_response<B>(status: http::StatusCode, value: &Value) -> http::Response<ResponseBody<B>> {
+    let data = serde_json::to_vec(value).expect("JSON values serialize");
+
+    let mut response = http::Response::new(ResponseBody { kind: Kind::Full(Some(data.into())) });
+    *response.status_mut() = status;
+    response.headers_mut()
+        .insert(header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+
+    response
+}
+
+/// A server-sent event carrying `value`.
+fn event(name: Option<&str>, value: &Value) -> Bytes {
+    let data = serde_json::to_string(value).expect("JSON values serialize");
+
+    // Serialized JSON has no newlines, so it always fits in a single
+    // `data` field.
+    match name {
+        Some(name) => format!("event: {}\ndata: {}\n\n", name, data).into(),
+        None => format!("data: {}\n\n", data).into(),
+    }
+}
+
+/// The HTTP status corresponding to a gRPC status code.
+pub(crate) fn http_status(code: Code) -> http::StatusCode {
+    let status = match code {
+        Code::OK => 200,
+        Code::CANCELED => 499,
+        Code::INVALID_ARGUMENT | Code::FAILED_PRECONDITION | Code::OUT_OF_RANGE => 400,
+        Code::UNAUTHENTICATED => 401,
+        Code::PERMISSION_DENIED => 403,
+        Code::NOT_FOUND => 404,
+        Code::ALREADY_EXISTS | Code::ABORTED => 409,
+        Code::RESOURCE_EXHAUSTED => 429,
+        Code::UNIMPLEMENTED => 501,
+        Code::UNAVAILABLE => 503,
+        Code::DEADLINE_EXCEEDED => 504,
+        _ => 500,
+    };
+
+    http::StatusCode::from_u16(status).expect("valid status code")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::descriptor::{Field, MessageType};
+    use super::template::Template;
+
+    use bytes::{BufMut, BytesMut};
+    use futures::future::{self, FutureResult};
+
+    /// Answers the call with its response, recording the request head and
+    /// message.
+    struct MockService {
+        requests: Arc<Mutex<Vec<(http::request::Parts, Bytes)>>>,
+        response: Option<http::Response<MockBody>>,
+    }
+
+    struct MockBody {
+        data: Option<Bytes>,
+        trailers: Option<HeaderMap>,
+    }
+
+    fn mock_transcoder(response: http::Response<MockBody>)
+        -> (Arc<Mutex<Vec<(http::request::Parts, Bytes)>>>, Transcoder<MockService>)
+    {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let inner = MockService {
+            requests: requests.clone(),
+            response: Some(response),
+        };
+
+        let mut transcoder = Transcoder::new(inner, Uri::from_static("http://users"));
+        transcoder.descriptors = Arc::new(descriptors());
+
+        (requests, transcoder)
+    }
+
+    /// The types and rules of `test.Users`.
+    fn descriptors() -> Descriptors {
+        let mut descriptors = Descriptors::default();
+
+        descriptors.messages.insert("test.GetUserRequest".to_string(), MessageType {
+            fields: vec![
+                field("id", 1, descriptor::Kind::String, false),
+                field("view", 2, descriptor::Kind::String, false),
+                field("filter", 3, descriptor::Kind::Message("test.Filter".to_string()), false),
+                field("tags", 4, descriptor::Kind::String, true),
+            ],
+            map_entry: false,
+        });
+        descriptors.messages.insert("test.Filter".to_string(), MessageType {
+            fields: vec![field("name", 1, descriptor::Kind::String, false)],
+            map_entry: false,
+        });
+        descriptors.messages.insert("test.UpdateUserRequest".to_string(), MessageType {
+            fields: vec![
+                field("id", 1, descriptor::Kind::String, false),
+                field("user", 2, descriptor::Kind::Message("test.User".to_string()), false),
+            ],
+            map_entry: false,
+        });
+        descriptors.messages.insert("test.User".to_string(), MessageType {
+            fields: vec![
+                field("id", 1, descriptor::Kind::String, false),
+                field("name", 2, descriptor::Kind::String, false),
+            ],
+            map_entry: false,
+        });
+
+        descriptors.rules = vec![
+            rule("GET", "/v1/users/{id}", "", "GetUser", "test.GetUserRequest"),
+            rule("PATCH", "/v1/users/{id}", "user", "UpdateUser", "test.UpdateUserRequest"),
+            rule("POST", "/v1/users", "*", "CreateUser", "test.User"),
+            Rule {
+                server_streaming: true,
+                ..rule("GET", "/v1/users", "", "ListUsers", "test.GetUserRequest")
+            },
+            Rule {
+                response_body: "name".to_string(),
+                ..rule("GET", "/v1/users/{id}/name", "", "GetUser", "test.GetUserRequest")
+            },
+            Rule {
+                client_streaming: true,
+                ..rule("POST", "/v1/users:import", "*", "ImportUsers", "test.User")
+            },
+        ];
+
+        descriptors
+    }
+
+    fn field(name: &str, number: u32, kind: descriptor::Kind, repeated: bool) -> Field {
+        Field {
+            name: name.to_string(),
+            json_name: name.to_string(),
+            number,
+            kind,
+            repeated,
+        }
+    }
+
+    fn rule(method: &str, template: &str, body: &str, name: &str, input_type: &str) -> Rule {
+        Rule {
+            method: http::Method::from_bytes(method.as_bytes()).unwrap(),
+            template: Template::parse(template).unwrap(),
+            body: body.to_string(),
+            response_body: String::new(),
+            path: format!("/test.Users/{}", name),
+            input_type: input_type.to_string(),
+            output_type: "test.User".to_string(),
+            client_streaming: false,
+            server_streaming: false,
+        }
+    }
+
+    fn request(method: &str, uri: &str, body: &str) -> http::Request<MockBody> {
+        http::Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(MockBody { data: Some(Bytes::from(body)), trailers: None })
+            .unwrap()
+    }
+
+    /// A response carrying the `test.User` messages, ending with `code`.
+    fn grpc_response(users: &[&str], code: &'static str) -> http::Response<MockBody> {
+        let mut data = BytesMut::new();
+
+        for user in users {
+            data.extend_from_slice(&frame(&encode_json("test.User", user)));
+        }
+
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", HeaderValue::from_static(code));
+
+        http::Response::new(MockBody {
+            data: if data.is_empty() { None } else { Some(data.freeze()) },
+            trailers: Some(trailers),
+        })
+    }
+
+    fn encode_json(type_name: &str, value: &str) -> Vec<u8> {
+        let mut buf = vec![];
+        json::encode(&descriptors(), type_name, &parse(value), &mut buf).unwrap();
+        buf
+    }
+
+    fn parse(value: &str) -> Value {
+        serde_json::from_str(value).unwrap()
+    }
+
+    /// Returns the gRPC frame of `message`.
+    fn frame(message: &[u8]) -> Bytes {
+        let mut buf = BytesMut::with_capacity(5 + message.len());
+        buf.put_u8(0);
+        buf.put_u32_be(message.len() as u32);
+        buf.put_slice(message);
+        buf.freeze()
+    }
+
+    fn call(transcoder: Transcoder<MockService>, request: http::Request<MockBody>)
+        -> http::Response<ResponseBody<MockBody>>
+    {
+        let mut transcoder = transcoder;
+        future::lazy(move || transcoder.call(request)).wait().unwrap()
+    }
+
+    /// Returns the request message of the call recorded first, as JSON.
+    fn request_message(requests: &Mutex<Vec<(http::request::Parts, Bytes)>>, type_name: &str) -> Value {
+        let requests = requests.lock().unwrap();
+        let data = &requests[0].1;
+
+        json::decode(&descriptors(), type_name, &data[5..]).unwrap()
+    }
+
+    fn json_body(response: http::Response<ResponseBody<MockBody>>) -> Value {
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+
+        let mut body = response.into_body();
+        match body.poll_data().unwrap() {
+            Async::Ready(Some(data)) => serde_json::from_slice(&data).unwrap(),
+            _ => panic!("no response body"),
+        }
+    }
+
+    /// Read the server-sent events of `response`, as their names and JSON
+    /// data.
+    fn events(response: http::Response<ResponseBody<MockBody>>) -> Vec<(Option<String>, Value)> {
+        let mut body = response.into_body();
+        let mut events = vec![];
+
+        while let Async::Ready(Some(data)) = body.poll_data().unwrap() {
+            let event = ::std::str::from_utf8(&data).unwrap();
+            assert!(event.ends_with("\n\n"), "{:?}", event);
+
+            let mut name = None;
+            let mut value = Value::Null;
+
+            for line in event.lines().filter(|line| !line.is_empty()) {
+                if line.starts_with("event: ") {
+                    name = Some(line["event: ".len()..].to_string());
+                } else if line.starts_with("data: ") {
+                    value = parse(&line["data: ".len()..]);
+                } else {
+                    panic!("unexpected line {:?}", line);
+                }
+            }
+
+            events.push((name, value));
+        }
+
+        assert!(body.is_end_stream());
+        events
+    }
+
+    fn accepting_events(uri: &str) -> http::Request<MockBody> {
+        let mut request = request("GET", uri, "");
+        request.headers_mut()
+            .insert(header::ACCEPT, HeaderValue::from_static("text/event-stream"));
+        request
+    }
+
+    impl Service for MockService {
+        type Request = http::Request<BoxBody>;
+        type Response = http::Response<MockBody>;
+        type Error = ();
+        type Future = FutureResult<Self::Response, ()>;
+
+        fn poll_ready(&mut self) -> Poll<(), ()> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+            let (head, mut body) = request.into_parts();
+            let mut data = BytesMut::new();
+
+            loop {
+                match body.poll_data() {
+                    Ok(Async::Ready(Some(chunk))) => data.extend_from_slice(&chunk),
+                    Ok(Async::Ready(None)) => break,
+                    Ok(Async::NotReady) => panic!("request bodies are ready"),
+                    Err(_) => return future::err(()),
+                }
+            }
+
+            self.requests.lock().unwrap().push((head, data.freeze()));
+            future::ok(self.response.take().expect("called once"))
+        }
+    }
+
+    impl Body for MockBody {
+        type Data = Bytes;
+
+        fn is_end_stream(&self) -> bool {
+            self.data.is_none() && self.trailers.is_none()
+        }
+
+        fn poll_data(&mut self) -> Poll<Option<Bytes>, h2::Error> {
+            Ok(Async::Ready(self.data.take()))
+        }
+
+        fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, h2::Error> {
+            Ok(Async::Ready(self.trailers.take()))
+        }
+    }
+
+    #[test]
+    fn handles_requests_matching_a_rule() {
+        let (_, transcoder) = mock_transcoder(grpc_response(&[], "0"));
+
+        assert!(transcoder.handles(&http::Method::GET, "/v1/users/1"));
+        assert!(transcoder.handles(&http::Method::PATCH, "/v1/users/1"));
+        assert!(!transcoder.handles(&http::Method::DELETE, "/v1/users/1"));
+        assert!(!transcoder.handles(&http::Method::GET, "/v2/users/1"));
+    }
+
+    #[test]
+    fn request_message_built_from_path_and_query() {
+        let (requests, transcoder) = mock_transcoder(grpc_response(&[r#"{"id": "7", "name": "ann"}"#], "0"));
+
+        let response = call(transcoder, request("GET", "/v1/users/7?view=full&tags=a&tags=b&filter.name=x+y%21", ""));
+
+        assert_eq!(response.status(), http::StatusCode::OK);
+        assert_eq!(json_body(response), parse(r#"{"id": "7", "name": "ann"}"#));
+
+        assert_eq!(request_message(&requests, "test.GetUserRequest"), parse(r#"{
+            "id": "7",
+            "view": "full",
+            "tags": ["a", "b"],
+            "filter": {"name": "x y!"}
+        }"#));
+
+        let requests = requests.lock().unwrap();
+        let head = &requests[0].0;
+        assert_eq!(head.method, http::Method::POST);
+        assert_eq!(head.uri, "http://users/test.Users/GetUser");
+        assert_eq!(head.headers[header::CONTENT_TYPE], "application/grpc+proto");
+        assert_eq!(head.headers[header::TE], "trailers");
+    }
+
+    #[test]
+    fn request_body_sets_the_body_field() {
+        let (requests, transcoder) = mock_transcoder(grpc_response(&[r#"{"id": "7"}"#], "0"));
+
+        let response = call(transcoder, request("PATCH", "/v1/users/7", r#"{"name": "bob"}"#));
+
+        assert_eq!(response.status(), http::StatusCode::OK);
+        assert_eq!(request_message(&requests, "test.UpdateUserRequest"),
+                   parse(r#"{"id": "7", "user": {"name": "bob"}}"#));
+    }
+
+    #[test]
+    fn whole_body_takes_precedence_over_query() {
+        let (requests, transcoder) = mock_transcoder(grpc_response(&[r#"{"id": "1"}"#], "0"));
+
+        let response = call(transcoder, request("POST", "/v1/users?id=2&name=b", r#"{"id": "1"}"#));
+
+        assert_eq!(response.status(), http::StatusCode::OK);
+        assert_eq!(request_message(&requests, "test.User"), parse(r#"{"id": "1", "name": "b"}"#));
+    }
+
+    #[test]
+    fn response_body_field_rendered() {
+        let (_, transcoder) = mock_transcoder(grpc_response(&[r#"{"id": "7", "name": "ann"}"#], "0"));
+
+        let response = call(transcoder, request("GET", "/v1/users/7/name", ""));
+
+        assert_eq!(json_body(response), Value::String("ann".to_string()));
+    }
+
+    #[test]
+    fn server_stream_rendered_as_array() {
+        let users = [r#"{"id": "1"}"#, r#"{"id": "2"}"#];
+        let (_, transcoder) = mock_transcoder(grpc_response(&users, "0"));
+
+        let response = call(transcoder, request("GET", "/v1/users", ""));
+
+        assert_eq!(response.status(), http::StatusCode::OK);
+        assert_eq!(json_body(response), parse(r#"[{"id": "1"}, {"id": "2"}]"#));
+    }
+
+    #[test]
+    fn unmatched_and_client_streaming_requests_answered_without_calling() {
+        let (requests, transcoder) = mock_transcoder(grpc_response(&[], "0"));
+        let response = call(transcoder, request("DELETE", "/v1/users/7", ""));
+
+        assert_eq!(response.status(), http::StatusCode::NOT_FOUND);
+        assert_eq!(json_body(response)["code"], 5);
+        assert!(requests.lock().unwrap().is_empty());
+
+        let (requests, transcoder) = mock_transcoder(grpc_response(&[], "0"));
+        let response = call(transcoder, request("POST", "/v1/users:import", "{}"));
+
+        assert_eq!(response.status(), http::StatusCode::NOT_IMPLEMENTED);
+        assert_eq!(json_body(response)["code"], 12);
+        assert!(requests.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn invalid_request_rejected() {
+        for body in &["{", r#"{"unknown": 1}"#, "[]"] {
+            let (_, transcoder) = mock_transcoder(grpc_response(&[], "0"));
+            let response = call(transcoder, request("POST", "/v1/users", body));
+
+            assert_eq!(response.status(), http::StatusCode::BAD_REQUEST, "{}", body);
+            assert_eq!(json_body(response)["code"], 3);
+        }
+    }
+
+    #[test]
+    fn status_rendered_with_http_status() {
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", HeaderValue::from_static("5"));
+        trailers.insert("grpc-message", HeaderValue::from_static("no%20such%20user"));
+
+        let response = http::Response::new(MockBody { data: None, trailers: Some(trailers) });
+        let (_, transcoder) = mock_transcoder(response);
+
+        let response = call(transcoder, request("GET", "/v1/users/7", ""));
+
+        assert_eq!(response.status(), http::StatusCode::NOT_FOUND);
+        assert_eq!(json_body(response), parse(r#"{"code": 5, "message": "no such user", "details": []}"#));
+    }
+
+    #[test]
+    fn query_parameters_percent_decoded() {
+        assert_eq!(parse_query("a=1&b=x+y&c=%2F&&d"), vec![
+            ("a".to_string(), "1".to_string()),
+            ("b".to_string(), "x y".to_string()),
+            ("c".to_string(), "/".to_string()),
+            ("d".to_string(), "".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn server_stream_rendered_as_events_when_accepted() {
+        let users = [r#"{"id": "1"}"#, r#"{"id": "2"}"#];
+        let (_, transcoder) = mock_transcoder(grpc_response(&users, "0"));
+
+        let response = call(transcoder.server_sent_events(true), accepting_events("/v1/users"));
+
+        assert_eq!(response.status(), http::StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/event-stream");
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-cache");
+
+        assert_eq!(events(response), vec![
+            (None, parse(r#"{"id": "1"}"#)),
+            (None, parse(r#"{"id": "2"}"#)),
+            (Some("status".to_string()), parse(r#"{"code": 0, "message": "", "details": []}"#)),
+        ]);
+    }
+
+    #[test]
+    fn failed_server_stream_ends_with_status_event() {
+        let mut response = grpc_response(&[r#"{"id": "1"}"#], "13");
+        response.body_mut().trailers.as_mut().unwrap()
+            .insert("grpc-message", HeaderValue::from_static("lost%20it"));
+
+        let (_, transcoder) = mock_transcoder(response);
+
+        let response = call(transcoder.server_sent_events(true), accepting_events("/v1/users"));
+
+        assert_eq!(response.status(), http::StatusCode::OK);
+        assert_eq!(events(response), vec![
+            (None, parse(r#"{"id": "1"}"#)),
+            (Some("status".to_string()), parse(r#"{"code": 13, "message": "lost it", "details": []}"#)),
+        ]);
+    }
+
+    #[test]
+    fn events_only_rendered_when_enabled_accepted_and_streaming() {
+        // Not enabled.
+        let (_, transcoder) = mock_transcoder(grpc_response(&[r#"{"id": "1"}"#], "0"));
+        let response = call(transcoder, accepting_events("/v1/users"));
+        assert_eq!(json_body(response), parse(r#"[{"id": "1"}]"#));
+
+        // Not accepted.
+        let (_, transcoder) = mock_transcoder(grpc_response(&[r#"{"id": "1"}"#], "0"));
+        let response = call(transcoder.server_sent_events(true), request("GET", "/v1/users", ""));
+        assert_eq!(json_body(response), parse(r#"[{"id": "1"}]"#));
+
+        // A unary method.
+        let (_, transcoder) = mock_transcoder(grpc_response(&[r#"{"id": "1"}"#], "0"));
+        let response = call(transcoder.server_sent_events(true), accepting_events("/v1/users/1"));
+        assert_eq!(json_body(response), parse(r#"{"id": "1"}"#));
+    }
+}
@@ -0,0 +1,342 @@
+//! `google.api.http` path templates.
+//!
+//! ```text
+//! Template = "/" Segments [ Verb ] ;
+//! Segments = Segment { "/" Segment } ;
+//! Segment  = "*" | "**" | LITERAL | Variable ;
+//! Variable = "{" FieldPath [ "=" Segments ] "}" ;
+//! FieldPath = IDENT { "." IDENT } ;
+//! Verb     = ":" LITERAL ;
+//! ```
+
+use std::fmt;
+
+/// A parsed path template, such as `/v1/{name=shelves/*}/books`.
+#[derive(Debug, Clone)]
+pub(crate) struct Template {
+    segments: Vec<Segment>,
+    variables: Vec<Variable>,
+    verb: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+
+    /// `*`, matching a single segment.
+    Wildcard,
+
+    /// `**`, matching any number of segments.
+    DoubleWildcard,
+}
+
+/// A variable, bound to the segments `start..end`.
+#[derive(Debug, Clone)]
+struct Variable {
+    field_path: Vec<String>,
+    start: usize,
+    end: usize,
+}
+
+/// Error produced when a template can't be parsed.
+#[derive(Debug)]
+pub(crate) struct ParseError {
+    message: &'static str,
+}
+
+// ===== impl Template =====
+
+impl Template {
+    pub(crate) fn parse(template: &str) -> Result<Template, ParseError> {
+        if !template.starts_with('/') {
+            return Err(ParseError::new("template must start with '/'"));
+        }
+
+        let mut rest = &template[1..];
+        let mut segments = vec![];
+        let mut variables = vec![];
+        let mut verb = None;
+
+        loop {
+            if rest.starts_with('{') {
+                let end = rest.find('}').ok_or(ParseError::new("unterminated variable"))?;
+                let inner = &rest[1..end];
+                rest = &rest[end + 1..];
+
+                let (path, pattern) = match inner.find('=') {
+                    Some(i) => (&inner[..i], &inner[i + 1..]),
+                    None => (inner, "*"),
+                };
+
+                if path.is_empty() {
+                    return Err(ParseError::new("empty variable"));
+                }
+
+                let start = segments.len();
+                for segment in pattern.split('/') {
+                    segments.push(Segment::parse(segment)?);
+                }
+
+                variables.push(Variable {
+                    field_path: path.split('.').map(String::from).collect(),
+                    start,
+                    end: segments.len(),
+                });
+            } else {
+                let end = rest.find(|c| c == '/' || c == ':').unwrap_or(rest.len());
+                segments.push(Segment::parse(&rest[..end])?);
+                rest = &rest[end..];
+            }
+
+            if rest.starts_with('/') {
+                rest = &rest[1..];
+            } else if rest.starts_with(':') {
+                verb = Some(rest[1..].to_string());
+                break;
+            } else if rest.is_empty() {
+                break;
+            } else {
+                return Err(ParseError::new("unexpected character after segment"));
+            }
+        }
+
+        Ok(Template { segments, variables, verb })
+    }
+
+    /// Match `path`, returning the field paths of the variables with their
+    /// percent-decoded values.
+    pub(crate) fn matches(&self, path: &str) -> Option<Vec<(Vec<String>, String)>> {
+        if !path.starts_with('/') {
+            return None;
+        }
+
+        let mut path = &path[1..];
+
+        if let Some(ref verb) = self.verb {
+            let suffix = format!(":{}", verb);
+
+            if !path.ends_with(&suffix) {
+                return None;
+            }
+
+            path = &path[..path.len() - suffix.len()];
+        }
+
+        let parts: Vec<&str> = path.split('/').collect();
+        let mut bounds = vec![];
+
+        if !match_segments(&self.segments, &parts, 0, &mut bounds) {
+            return None;
+        }
+
+        let values = self.variables.iter()
+            .map(|variable| {
+                let start = bounds[variable.start].0;
+                let end = bounds[variable.end - 1].1;
+                let value = percent_decode(&parts[start..end].join("/"));
+
+                (variable.field_path.clone(), value)
+            })
+            .collect();
+
+        Some(values)
+    }
+}
+
+// ===== impl Segment =====
+
+impl Segment {
+    fn parse(segment: &str) -> Result<Segment, ParseError> {
+        match segment {
+            "" => Err(ParseError::new("empty segment")),
+            "*" => Ok(Segment::Wildcard),
+            "**" => Ok(Segment::DoubleWildcard),
+            s if s.contains(|c| c == '{' || c == '}' || c == '=') => {
+                Err(ParseError::new("invalid literal segment"))
+            }
+            s => Ok(Segment::Literal(s.to_string())),
+        }
+    }
+}
+
+// ===== impl ParseError =====
+
+impl ParseError {
+    fn new(message: &'static str) -> Self {
+        ParseError { message }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(self.message)
+    }
+}
+
+// ===== utility fns =====
+
+/// Match `parts` against `segments`, recording the range of parts matched
+/// by each segment in `bounds`.
+fn match_segments(segments: &[Segment],
+                  parts: &[&str],
+                  offset: usize,
+                  bounds: &mut Vec<(usize, usize)>)
+    -> bool
+{
+    let (segment, rest) = match segments.split_first() {
+        Some(split) => split,
+        None => return parts.is_empty(),
+    };
+
+    if let Segment::DoubleWildcard = *segment {
+        // Match as many parts as possible.
+        for n in (0..parts.len() + 1).rev() {
+            bounds.push((offset, offset + n));
+
+            if match_segments(rest, &parts[n..], offset + n, bounds) {
+                return true;
+            }
+
+            bounds.pop();
+        }
+
+        return false;
+    }
+
+    let part = match parts.first() {
+        Some(part) => part,
+        None => return false,
+    };
+
+    let matched = match *segment {
+        Segment::Literal(ref literal) => literal == part,
+        _ => !part.is_empty(),
+    };
+
+    if !matched {
+        return false;
+    }
+
+    bounds.push((offset, offset + 1));
+
+    if match_segments(rest, &parts[1..], offset + 1, bounds) {
+        return true;
+    }
+
+    bounds.pop();
+    false
+}
+
+/// Decode the percent-encoded bytes of `value`. Invalid escapes are kept
+/// as they are.
+pub(crate) fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let (Some(hi), Some(lo)) = (hex(bytes[i + 1]), hex(bytes[i + 2])) {
+                out.push(hi << 4 | lo);
+                i += 3;
+                continue;
+            }
+        }
+
+        out.push(bytes[i]);
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn hex(b: u8) -> Option<u8> {
+    match b {
+        b'0'...b'9' => Some(b - b'0'),
+        b'a'...b'f' => Some(b - b'a' + 10),
+        b'A'...b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(template: &str, path: &str) -> Option<Vec<(String, String)>> {
+        let template = Template::parse(template).unwrap();
+
+        template.matches(path).map(|vars| {
+            vars.into_iter()
+                .map(|(field_path, value)| (field_path.join("."), value))
+                .collect()
+        })
+    }
+
+    fn vars(vars: &[(&str, &str)]) -> Option<Vec<(String, String)>> {
+        Some(vars.iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect())
+    }
+
+    #[test]
+    fn literal_segments_match_exactly() {
+        assert_eq!(matches("/v1/shelves", "/v1/shelves"), vars(&[]));
+        assert_eq!(matches("/v1/shelves", "/v1/books"), None);
+        assert_eq!(matches("/v1/shelves", "/v1/shelves/1"), None);
+        assert_eq!(matches("/v1/shelves", "/v1"), None);
+        assert_eq!(matches("/v1/shelves", "v1/shelves"), None);
+    }
+
+    #[test]
+    fn variables_bind_single_segments_by_default() {
+        assert_eq!(matches("/v1/shelves/{shelf}/books/{book.id}", "/v1/shelves/1/books/2"),
+                   vars(&[("shelf", "1"), ("book.id", "2")]));
+
+        // `*` doesn't match an empty segment.
+        assert_eq!(matches("/v1/shelves/{shelf}", "/v1/shelves/"), None);
+    }
+
+    #[test]
+    fn variables_bind_their_patterns() {
+        assert_eq!(matches("/v1/{name=shelves/*}/books", "/v1/shelves/1/books"),
+                   vars(&[("name", "shelves/1")]));
+        assert_eq!(matches("/v1/{name=shelves/*}/books", "/v1/stacks/1/books"), None);
+
+        assert_eq!(matches("/v1/files/{path=**}", "/v1/files/a/b/c"),
+                   vars(&[("path", "a/b/c")]));
+        assert_eq!(matches("/v1/{path=**}/raw", "/v1/a/b/raw"),
+                   vars(&[("path", "a/b")]));
+    }
+
+    #[test]
+    fn verb_must_be_present() {
+        assert_eq!(matches("/v1/{name}:cancel", "/v1/op:cancel"), vars(&[("name", "op")]));
+        assert_eq!(matches("/v1/{name}:cancel", "/v1/op"), None);
+        assert_eq!(matches("/v1/{name}:cancel", "/v1/op:undo"), None);
+    }
+
+    #[test]
+    fn variable_values_are_percent_decoded() {
+        assert_eq!(matches("/v1/{name}", "/v1/a%2Fb%20c"), vars(&[("name", "a/b c")]));
+    }
+
+    #[test]
+    fn invalid_templates_rejected() {
+        for template in &[
+            "v1/shelves",
+            "/v1/{name",
+            "/v1/{}",
+            "/v1//shelves",
+            "/v1/a}b",
+            "/v1/{name}x",
+        ] {
+            assert!(Template::parse(template).is_err(), "{}", template);
+        }
+    }
+
+    #[test]
+    fn invalid_escapes_kept() {
+        assert_eq!(percent_decode("%zz%2"), "%zz%2");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%41%62"), "Ab");
+    }
+}
@@ -0,0 +1,826 @@
+//! The Twirp protocol.
+//!
+//! `TwirpServer` answers [Twirp] calls by forwarding them as gRPC calls to
+//! an inner HTTP/2.0 client service, such as an in-process connection to
+//! the server, so Twirp clients can move to a tower-grpc backend one at a
+//! time:
+//!
+//! ```ignore
+//! let conn = core.run(inprocess::connect(UsersServer::new(users), &handle))?;
+//!
+//! let server = TwirpServer::new(conn, Uri::from_static("http://users"))
+//!     .register_file_descriptor_set(include_bytes!("users.bin"))?;
+//!
+//! let serve = Http::new().serve_connection(sock, HyperServer::new(server));
+//! ```
+//!
+//! Calls are `POST` requests to `/twirp/pkg.Service/Method`, with an
+//! `application/protobuf` or `application/json` body. The JSON codec needs
+//! the types of the method, from a registered file descriptor set. Twirp
+//! only has unary methods, so streaming methods are not served.
+//!
+//! Failed calls are answered with Twirp's error JSON, such as
+//! `{"code": "not_found", "msg": "..."}`, and its HTTP status codes.
+//!
+//! [Twirp]: https://twitchtv.github.io/twirp/docs/spec_v7.html
+
+use {Code, Status};
+use generic::{BytesCodec, Encode, Streaming};
+use transcode::{self, DescriptorError};
+use transcode::descriptor::Descriptors;
+use transcode::json;
+
+use bytes::Bytes;
+use futures::{Future, Stream, Poll, Async};
+use h2;
+use http::{self, header, HeaderMap, Uri};
+use http::header::HeaderValue;
+use serde_json::{self, Map, Value};
+use tower::Service;
+use tower_h2::{Body, BoxBody, HttpService};
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// Serves Twirp calls with an inner gRPC client service.
+#[derive(Clone)]
+pub struct TwirpServer<S> {
+    inner: S,
+    origin: Uri,
+    prefix: String,
+    descriptors: Arc<Descriptors>,
+}
+
+/// The response future returned by `TwirpServer`.
+pub struct ResponseFuture<F, B> {
+    state: State<F, B>,
+    call: Option<Call>,
+}
+
+/// The response body returned by `TwirpServer`.
+#[derive(Debug)]
+pub struct ResponseBody {
+    data: Option<Bytes>,
+}
+
+enum State<F, B> {
+    /// Waiting for the response head.
+    Calling(F),
+
+    /// Reading the response message.
+    Reading(Streaming<BytesCodec, B>, Option<Vec<u8>>),
+
+    /// The request was answered without calling the inner service.
+    Done(Option<http::Response<ResponseBody>>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Protobuf,
+    Json,
+}
+
+/// The codec and types of a call.
+struct Call {
+    format: Format,
+    descriptors: Arc<Descriptors>,
+    input_type: Option<String>,
+    output_type: Option<String>,
+
+    /// The Twirp error code and message of a request that could not be
+    /// converted, if it failed.
+    error: Arc<Mutex<Option<(&'static str, String)>>>,
+}
+
+/// Converts the request body to the message of the call.
+struct RequestMessage<B> {
+    body: B,
+    buf: Vec<u8>,
+    format: Format,
+    descriptors: Arc<Descriptors>,
+    input_type: Option<String>,
+    error: Arc<Mutex<Option<(&'static str, String)>>>,
+    done: bool,
+}
+
+// ===== impl TwirpServer =====
+
+impl<S> TwirpServer<S> {
+    /// Forward calls to `inner`, sent to the scheme and authority of
+    /// `origin`.
+    ///
+    /// Only the protobuf codec is available until a file descriptor set is
+    /// registered.
+    pub fn new(inner: S, origin: Uri) -> Self {
+        TwirpServer {
+            inner,
+            origin,
+            prefix: "/twirp".to_string(),
+            descriptors: Arc::new(Descriptors::default()),
+        }
+    }
+
+    /// Set the path prefix of the routes, `/twirp` by default.
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Add the types and methods of an encoded `FileDescriptorSet`, for the
+    /// JSON codec.
+    pub fn register_file_descriptor_set(mut self, encoded: &[u8]) -> Result<Self, DescriptorError> {
+        transcode::register(&mut self.descriptors, encoded)?;
+        Ok(self)
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner service.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// Returns the path of the gRPC method routed to by `path`.
+    fn route<'a>(&self, path: &'a str) -> Option<&'a str> {
+        if !path.starts_with(&self.prefix) {
+            return None;
+        }
+
+        let method = &path[self.prefix.len()..];
+
+        // The method path is `/pkg.Service/Method`.
+        if !method.starts_with('/') || method[1..].split('/').count() != 2 {
+            return None;
+        }
+
+        Some(method)
+    }
+}
+
+impl<S, B> Service for TwirpServer<S>
+where S: HttpService<RequestBody = BoxBody>,
+      B: Body + Send + 'static,
+      B::Data: Into<Bytes>,
+{
+    type Request = http::Request<B>;
+    type Response = http::Response<ResponseBody>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future, S::ResponseBody>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, request: Self::Request) -> Self::Future {
+        if request.method() != http::Method::POST {
+            return ResponseFuture::failed("bad_route", "unsupported method (only POST is allowed)");
+        }
+
+        let path = match self.route(request.uri().path()) {
+            Some(path) => path.to_string(),
+            None => return ResponseFuture::failed("bad_route", "no handler for path"),
+        };
+
+        let format = match request.headers().get(header::CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .map(|content_type| content_type.split(';').next().unwrap_or("").trim())
+        {
+            Some("application/protobuf") => Format::Protobuf,
+            Some("application/json") => Format::Json,
+            _ => return ResponseFuture::failed("bad_route", "unexpected Content-Type"),
+        };
+
+        let method = self.descriptors.methods.get(&path);
+
+        match method {
+            Some(method) if method.client_streaming || method.server_streaming => {
+                return ResponseFuture::failed("bad_route", "streaming methods are not supported");
+            }
+            None if format == Format::Json => {
+                return ResponseFuture::failed("bad_route", "no JSON codec for method");
+            }
+            _ => {}
+        }
+
+        let input_type = method.map(|method| method.input_type.clone());
+        let output_type = method.map(|method| method.output_type.clone());
+        let error = Arc::new(Mutex::new(None));
+
+        let mut parts = http::uri::Parts::default();
+        parts.scheme = self.origin.scheme_part().cloned();
+        parts.authority = self.origin.authority_part().cloned();
+        parts.path_and_query = Some(path.parse().expect("routed paths are valid"));
+
+        let uri = match Uri::from_parts(parts) {
+            Ok(uri) => uri,
+            Err(_) => return ResponseFuture::failed("internal", "invalid origin"),
+        };
+
+        let (head, body) = request.into_parts();
+
+        let message = RequestMessage {
+            body,
+            buf: vec![],
+            format,
+            descriptors: self.descriptors.clone(),
+            input_type: input_type.clone(),
+            error: error.clone(),
+            done: false,
+        };
+
+        let mut call = http::Request::new(BoxBody::new(Box::new(Encode::new(BytesCodec, message, false))));
+        *call.method_mut() = http::Method::POST;
+        *call.uri_mut() = uri;
+        *call.headers_mut() = transcode::forward_headers(head.headers);
+        call.headers_mut()
+            .insert(header::TE, HeaderValue::from_static("trailers"));
+        call.headers_mut()
+            .insert(header::CONTENT_TYPE, HeaderValue::from_static("application/grpc+proto"));
+
+        ResponseFuture {
+            state: State::Calling(self.inner.call(call)),
+            call: Some(Call {
+                format,
+                descriptors: self.descriptors.clone(),
+                input_type,
+                output_type,
+                error,
+            }),
+        }
+    }
+}
+
+impl<S> fmt::Debug for TwirpServer<S>
+where S: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("TwirpServer")
+            .field("inner", &self.inner)
+            .field("origin", &self.origin)
+            .field("prefix", &self.prefix)
+            .field("methods", &self.descriptors.methods.len())
+            .finish()
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F, B> ResponseFuture<F, B> {
+    fn failed(code: &'static str, message: &str) -> Self {
+        ResponseFuture {
+            state: State::Done(Some(error_response(code, message))),
+            call: None,
+        }
+    }
+}
+
+impl<F, B> Future for ResponseFuture<F, B>
+where F: Future<Item = http::Response<B>>,
+      B: Body,
+      B::Data: Into<Bytes>,
+{
+    type Item = http::Response<ResponseBody>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                State::Calling(ref mut future) => {
+                    let response = match future.poll() {
+                        Ok(Async::Ready(response)) => response,
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Err(e) => {
+                            // The call fails when its request is rejected.
+                            if let Some(response) = self.call.as_ref().and_then(Call::rejected) {
+                                return Ok(response.into());
+                            }
+
+                            return Err(e);
+                        }
+                    };
+
+                    let (head, body) = response.into_parts();
+
+                    // A trailers-only response.
+                    if let Some(status) = head.headers.get("grpc-status") {
+                        let status = Status::from_bytes(status.as_ref());
+
+                        if status.code() != Code::OK {
+                            let message = transcode::grpc_message(&head.headers);
+                            return Ok(error_response(code_name(status.code()), &message).into());
+                        }
+                    }
+
+                    State::Reading(Streaming::new(BytesCodec, body, true), None)
+                }
+                State::Reading(ref mut messages, ref mut message) => {
+                    let call = self.call.as_ref().expect("reading without a call");
+
+                    match messages.poll() {
+                        Ok(Async::Ready(Some(m))) => {
+                            *message = Some(m);
+                            continue;
+                        }
+                        Ok(Async::Ready(None)) => {}
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Err(e) => {
+                            if let Some(response) = call.rejected() {
+                                return Ok(response.into());
+                            }
+
+                            let message = messages.trailers()
+                                .map(transcode::grpc_message)
+                                .unwrap_or_default();
+
+                            return Ok(error_response(code_name(Status::from(e).code()), &message).into());
+                        }
+                    }
+
+                    let message = message.take().unwrap_or_default();
+
+                    return Ok(match call.render(message) {
+                        Ok(response) => response,
+                        Err(e) => {
+                            debug!("response message could not be rendered; error={}", e);
+                            error_response("internal", &e)
+                        }
+                    }.into());
+                }
+                State::Done(ref mut response) => {
+                    return Ok(response.take().expect("polled after complete").into());
+                }
+            };
+
+            self.state = next;
+        }
+    }
+}
+
+impl<F, B> fmt::Debug for ResponseFuture<F, B> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let state = match self.state {
+            State::Calling(..) => "Calling",
+            State::Reading(..) => "Reading",
+            State::Done(..) => "Done",
+        };
+
+        fmt.debug_struct("twirp::ResponseFuture")
+            .field("state", &state)
+            .finish()
+    }
+}
+
+// ===== impl ResponseBody =====
+
+impl Body for ResponseBody {
+    type Data = Bytes;
+
+    fn is_end_stream(&self) -> bool {
+        self.data.is_none()
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Bytes>, h2::Error> {
+        Ok(self.data.take().into())
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, h2::Error> {
+        Ok(None.into())
+    }
+}
+
+// ===== impl Call =====
+
+impl Call {
+    /// Returns the error response of a request that could not be converted.
+    fn rejected(&self) -> Option<http::Response<ResponseBody>> {
+        let error = self.error.lock().unwrap();
+
+        error.as_ref()
+            .map(|&(code, ref message)| error_response(code, message))
+    }
+
+    /// Render the response message with the call's codec.
+    fn render(&self, message: Vec<u8>) -> Result<http::Response<ResponseBody>, String> {
+        let (data, content_type) = match self.format {
+            Format::Protobuf => (message, "application/protobuf"),
+            Format::Json => {
+                let output_type = self.output_type.as_ref().expect("JSON calls have types");
+                let value = json::decode(&self.descriptors, output_type, &message)?;
+                let data = serde_json::to_vec(&value).expect("JSON values serialize");
+
+                (data, "application/json")
+            }
+        };
+
+        let mut response = http::Response::new(ResponseBody { data: Some(data.into()) });
+        response.headers_mut()
+            .insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+
+        Ok(response)
+    }
+}
+
+impl fmt::Debug for Call {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Call")
+            .field("format", &self.format)
+            .field("input_type", &self.input_type)
+            .field("output_type", &self.output_type)
+            .finish()
+    }
+}
+
+// ===== impl RequestMessage =====
+
+impl<B> RequestMessage<B> {
+    /// Convert the request body to protobuf.
+    fn parse(&mut self) -> Result<Vec<u8>, String> {
+        let body = ::std::mem::replace(&mut self.buf, vec![]);
+
+        match self.format {
+            Format::Protobuf => Ok(body),
+            Format::Json => {
+                let input_type = self.input_type.as_ref().expect("JSON calls have types");
+                let value: Value = serde_json::from_slice(&body)
+                    .map_err(|e| format!("the json request could not be decoded: {}", e))?;
+
+                let mut buf = vec![];
+                json::encode(&self.descriptors, input_type, &value, &mut buf)?;
+                Ok(buf)
+            }
+        }
+    }
+}
+
+impl<B> Stream for RequestMessage<B>
+where B: Body,
+      B::Data: Into<Bytes>,
+{
+    type Item = Vec<u8>;
+    type Error = ::Error;
+
+    fn poll(&mut self) -> Poll<Option<Vec<u8>>, ::Error> {
+        if self.done {
+            return Ok(Async::Ready(None));
+        }
+
+        while let Some(data) = try_ready!(self.body.poll_data().map_err(Status::from)) {
+            self.buf.extend_from_slice(&data.into());
+        }
+
+        self.done = true;
+
+        match self.parse() {
+            Ok(message) => Ok(Async::Ready(Some(message))),
+            Err(e) => {
+                debug!("request could not be converted; error={}", e);
+                *self.error.lock().unwrap() = Some(("malformed", e));
+                Err(::Error::Grpc(Status::INVALID_ARGUMENT))
+            }
+        }
+    }
+}
+
+// ===== utility fns =====
+
+/// Render a Twirp error, with the HTTP status of its code.
+fn error_response(code: &'static str, message: &str) -> http::Response<ResponseBody> {
+    let mut error = Map::new();
+    error.insert("code".to_string(), Value::String(code.to_string()));
+    error.insert("msg".to_string(), Value::String(message.to_string()));
+
+    let data = serde_json::to_vec(&Value::Object(error)).expect("JSON values serialize");
+
+    let mut response = http::Response::new(ResponseBody { data: Some(data.into()) });
+    *response.status_mut() = http_status(code);
+    response.headers_mut()
+        .insert(header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+
+    response
+}
+
+/// The Twirp error code of a gRPC status code.
+fn code_name(code: Code) -> &'static str {
+    match code {
+        Code::CANCELED => "canceled",
+        Code::INVALID_ARGUMENT => "invalid_argument",
+        Code::DEADLINE_EXCEEDED => "deadline_exceeded",
+        Code::NOT_FOUND => "not_found",
+        Code::ALREADY_EXISTS => "already_exists",
+        Code::PERMISSION_DENIED => "permission_denied",
+        Code::RESOURCE_EXHAUSTED => "resource_exhausted",
+        Code::FAILED_PRECONDITION => "failed_precondition",
+        Code::ABORTED => "aborted",
+        Code::OUT_OF_RANGE => "out_of_range",
+        Code::UNIMPLEMENTED => "unimplemented",
+        Code::INTERNAL => "internal",
+        Code::UNAVAILABLE => "unavailable",
+        Code::DATA_LOSS => "dataloss",
+        Code::UNAUTHENTICATED => "unauthenticated",
+        _ => "unknown",
+    }
+}
+
+/// The HTTP status of a Twirp error code.
+fn http_status(code: &str) -> http::StatusCode {
+    let status = match code {
+        "canceled" | "deadline_exceeded" => 408,
+        "invalid_argument" | "malformed" | "out_of_range" => 400,
+        "not_found" | "bad_route" => 404,
+        "already_exists" | "aborted" => 409,
+        "permission_denied" => 403,
+        "unauthenticated" => 401,
+        "resource_exhausted" => 429,
+        "failed_precondition" => 412,
+        "unimplemented" => 501,
+        "unavailable" => 503,
+        _ => 500,
+    };
+
+    http::StatusCode::from_u16(status).expect("valid status code")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use transcode::descriptor::{Field, Kind, MessageType, Method};
+
+    use bytes::{BufMut, BytesMut, BigEndian};
+    use futures::future::{self, FutureResult};
+
+    /// Answers the call with its response, recording the request head and
+    /// the gRPC frames of its body.
+    struct MockService {
+        requests: Arc<Mutex<Vec<(http::request::Parts, Bytes)>>>,
+        response: Option<http::Response<MockBody>>,
+    }
+
+    struct MockBody {
+        data: Option<Bytes>,
+        trailers: Option<HeaderMap>,
+    }
+
+    fn server(response: http::Response<MockBody>)
+        -> (Arc<Mutex<Vec<(http::request::Parts, Bytes)>>>, TwirpServer<MockService>)
+    {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let inner = MockService {
+            requests: requests.clone(),
+            response: Some(response),
+        };
+
+        let mut server = TwirpServer::new(inner, Uri::from_static("http://users"));
+        server.descriptors = Arc::new(descriptors());
+
+        (requests, server)
+    }
+
+    /// The types of `test.Service`, with the unary `Method` and the
+    /// server streaming `Watch`.
+    fn descriptors() -> Descriptors {
+        let mut descriptors = Descriptors::default();
+
+        descriptors.messages.insert("test.Request".to_string(), MessageType {
+            fields: vec![field("name", 1)],
+            map_entry: false,
+        });
+        descriptors.messages.insert("test.Reply".to_string(), MessageType {
+            fields: vec![field("message", 1)],
+            map_entry: false,
+        });
+
+        descriptors.methods.insert("/test.Service/Method".to_string(), method(false));
+        descriptors.methods.insert("/test.Service/Watch".to_string(), method(true));
+
+        descriptors
+    }
+
+    fn field(name: &str, number: u32) -> Field {
+        Field {
+            name: name.to_string(),
+            json_name: name.to_string(),
+            number,
+            kind: Kind::String,
+            repeated: false,
+        }
+    }
+
+    fn method(server_streaming: bool) -> Method {
+        Method {
+            input_type: "test.Request".to_string(),
+            output_type: "test.Reply".to_string(),
+            client_streaming: false,
+            server_streaming,
+        }
+    }
+
+    fn request(method: &str, path: &str, content_type: &str, body: &[u8]) -> http::Request<MockBody> {
+        http::Request::builder()
+            .method(method)
+            .uri(path)
+            .header("content-type", content_type)
+            .body(MockBody { data: Some(Bytes::from(body)), trailers: None })
+            .unwrap()
+    }
+
+    /// A response carrying `message`, ending with `code`.
+    fn grpc_response(message: Option<&[u8]>, code: &'static str) -> http::Response<MockBody> {
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", HeaderValue::from_static(code));
+
+        http::Response::new(MockBody {
+            data: message.map(frame),
+            trailers: Some(trailers),
+        })
+    }
+
+    /// Returns the gRPC frame of `message`.
+    fn frame(message: &[u8]) -> Bytes {
+        let mut buf = BytesMut::with_capacity(5 + message.len());
+        buf.put_u8(0);
+        buf.put_u32::<BigEndian>(message.len() as u32);
+        buf.put_slice(message);
+        buf.freeze()
+    }
+
+    fn call(server: TwirpServer<MockService>, request: http::Request<MockBody>)
+        -> http::Response<ResponseBody>
+    {
+        let mut server = server;
+        future::lazy(move || server.call(request)).wait().unwrap()
+    }
+
+    /// Returns the Twirp error code and message of `response`.
+    fn error(response: http::Response<ResponseBody>) -> (String, String) {
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+
+        let body: Value = serde_json::from_slice(&response.into_body().data.unwrap()).unwrap();
+        (body["code"].as_str().unwrap().to_string(), body["msg"].as_str().unwrap().to_string())
+    }
+
+    impl Service for MockService {
+        type Request = http::Request<BoxBody>;
+        type Response = http::Response<MockBody>;
+        type Error = ();
+        type Future = FutureResult<Self::Response, ()>;
+
+        fn poll_ready(&mut self) -> Poll<(), ()> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+            let (head, mut body) = request.into_parts();
+            let mut data = BytesMut::new();
+
+            loop {
+                match body.poll_data() {
+                    Ok(Async::Ready(Some(chunk))) => data.extend_from_slice(&chunk),
+                    Ok(Async::Ready(None)) => break,
+                    Ok(Async::NotReady) => panic!("request bodies are ready"),
+                    Err(_) => return future::err(()),
+                }
+            }
+
+            self.requests.lock().unwrap().push((head, data.freeze()));
+            future::ok(self.response.take().expect("called once"))
+        }
+    }
+
+    impl Body for MockBody {
+        type Data = Bytes;
+
+        fn is_end_stream(&self) -> bool {
+            self.data.is_none() && self.trailers.is_none()
+        }
+
+        fn poll_data(&mut self) -> Poll<Option<Bytes>, h2::Error> {
+            Ok(Async::Ready(self.data.take()))
+        }
+
+        fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, h2::Error> {
+            Ok(Async::Ready(self.trailers.take()))
+        }
+    }
+
+    #[test]
+    fn routes_method_paths_under_the_prefix() {
+        let (_, server) = server(grpc_response(None, "0"));
+
+        assert_eq!(server.route("/twirp/test.Service/Method"), Some("/test.Service/Method"));
+        assert_eq!(server.route("/twirp/test.Service"), None);
+        assert_eq!(server.route("/twirp/test.Service/Method/more"), None);
+        assert_eq!(server.route("/other/test.Service/Method"), None);
+
+        let server = server.prefix("/api/");
+        assert_eq!(server.route("/api/test.Service/Method"), Some("/test.Service/Method"));
+        assert_eq!(server.route("/twirp/test.Service/Method"), None);
+    }
+
+    #[test]
+    fn protobuf_call_forwarded_as_grpc() {
+        let (requests, server) = server(grpc_response(Some(b"reply"), "0"));
+
+        let response = call(server, request("POST", "/twirp/test.Service/Method", "application/protobuf", b"req"));
+
+        assert_eq!(response.status(), http::StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/protobuf");
+        assert_eq!(response.into_body().data, Some(Bytes::from_static(b"reply")));
+
+        let requests = requests.lock().unwrap();
+        let (ref head, ref body) = requests[0];
+        assert_eq!(head.method, http::Method::POST);
+        assert_eq!(head.uri, "http://users/test.Service/Method");
+        assert_eq!(head.headers[header::CONTENT_TYPE], "application/grpc+proto");
+        assert_eq!(head.headers[header::TE], "trailers");
+        assert_eq!(*body, frame(b"req"));
+    }
+
+    #[test]
+    fn json_call_transcoded_with_the_method_types() {
+        let (requests, server) = server(grpc_response(Some(b"\x0a\x02hi"), "0"));
+
+        let response = call(server, request("POST", "/twirp/test.Service/Method", "application/json", br#"{"name":"x"}"#));
+
+        assert_eq!(response.status(), http::StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+
+        let body: Value = serde_json::from_slice(&response.into_body().data.unwrap()).unwrap();
+        assert_eq!(body["message"], "hi");
+
+        assert_eq!(requests.lock().unwrap()[0].1, frame(b"\x0a\x01x"));
+    }
+
+    #[test]
+    fn malformed_json_rejected() {
+        let (_, server) = server(grpc_response(None, "0"));
+
+        let response = call(server, request("POST", "/twirp/test.Service/Method", "application/json", b"{"));
+
+        assert_eq!(response.status(), http::StatusCode::BAD_REQUEST);
+        assert_eq!(error(response).0, "malformed");
+    }
+
+    #[test]
+    fn bad_routes_answered_without_calling() {
+        let cases = [
+            ("GET", "/twirp/test.Service/Method", "application/protobuf"),
+            ("POST", "/twirp/test.Service", "application/protobuf"),
+            ("POST", "/twirp/test.Service/Method", "text/plain"),
+            ("POST", "/twirp/test.Service/Watch", "application/protobuf"),
+            ("POST", "/twirp/test.Service/Other", "application/json"),
+        ];
+
+        for &(method, path, content_type) in &cases {
+            let (requests, server) = server(grpc_response(None, "0"));
+
+            let response = call(server, request(method, path, content_type, b""));
+
+            assert_eq!(response.status(), http::StatusCode::NOT_FOUND, "{} {}", method, path);
+            assert_eq!(error(response).0, "bad_route");
+            assert!(requests.lock().unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn trailers_only_status_mapped_to_twirp_error() {
+        let response = http::Response::builder()
+            .header("grpc-status", "5")
+            .header("grpc-message", "no%20such%20user")
+            .body(MockBody { data: None, trailers: None })
+            .unwrap();
+
+        let (_, server) = server(response);
+
+        let response = call(server, request("POST", "/twirp/test.Service/Method", "application/protobuf", b""));
+
+        assert_eq!(response.status(), http::StatusCode::NOT_FOUND);
+        assert_eq!(error(response), ("not_found".to_string(), "no such user".to_string()));
+    }
+
+    #[test]
+    fn status_in_trailers_mapped_to_twirp_error() {
+        let (_, server) = server(grpc_response(None, "7"));
+
+        let response = call(server, request("POST", "/twirp/test.Service/Method", "application/protobuf", b""));
+
+        assert_eq!(response.status(), http::StatusCode::FORBIDDEN);
+        assert_eq!(error(response).0, "permission_denied");
+    }
+
+    #[test]
+    fn error_codes_have_twirp_names_and_statuses() {
+        assert_eq!(code_name(Code::DATA_LOSS), "dataloss");
+        assert_eq!(code_name(Code::UNKNOWN), "unknown");
+        assert_eq!(code_name(Code::NOT_FOUND), "not_found");
+
+        assert_eq!(http_status("bad_route"), http::StatusCode::NOT_FOUND);
+        assert_eq!(http_status("malformed"), http::StatusCode::BAD_REQUEST);
+        assert_eq!(http_status("unauthenticated"), http::StatusCode::UNAUTHORIZED);
+        assert_eq!(http_status("resource_exhausted"), http::StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(http_status("dataloss"), http::StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(http_status("unknown"), http::StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
@@ -0,0 +1,267 @@
+//! Unix domain socket transport.
+//!
+//! Useful to reach sidecars and local daemons without exposing a TCP port.
+//! A `Channel` to a `unix:///path` target connects with `UnixConnect`. The
+//! path is the connector's, so the channel's resolver only needs to provide
+//! a single endpoint:
+//!
+//! ```ignore
+//! let connect = UnixConnect::from_target("unix:///run/agent.sock", handle.clone())?;
+//! let channel = Channel::new(unix::resolver(), connect, PickFirst::new());
+//! ```
+//!
+//! Servers accept connections with `tokio_uds::UnixListener`. The
+//! credentials of the process on the other end of each connection are
+//! returned by `peer_cred`, and can be made available to handlers with
+//! `handshake::AddAuthInfo`:
+//!
+//! ```ignore
+//! let listener = UnixListener::bind("/run/agent.sock", &handle)?;
+//!
+//! let serve = listener.incoming().for_each(move |(sock, _)| {
+//!     let cred = unix::peer_cred(&sock)?;
+//!     let new_service = AddAuthInfo::new(new_service.clone(), cred);
+//!     let h2 = Server::new(new_service, Default::default(), handle.clone());
+//!
+//!     handle.spawn(h2.serve(sock).map_err(|e| error!("h2 error: {:?}", e)));
+//!     Ok(())
+//! });
+//! ```
+
+use channel::{self, ChannelStats, CountedIo, Endpoint};
+use channel::resolve::Fixed;
+use http2::Http2Settings;
+
+use futures::{Future, Poll};
+use libc;
+use tokio_core::reactor::Handle;
+use tokio_uds::UnixStream;
+use tower_h2::BoxBody;
+use tower_h2::client::{Connection, Handshake, HandshakeError};
+
+use std::{error, fmt, io, mem};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+/// Connects a `Channel` over a Unix domain socket.
+///
+/// Every endpoint is connected to the same socket path.
+#[derive(Debug, Clone)]
+pub struct UnixConnect {
+    path: PathBuf,
+    handle: Handle,
+    stats: ChannelStats,
+    http2: Http2Settings,
+}
+
+/// Future returned by `UnixConnect`.
+pub struct ConnectFuture {
+    inner: Result<Handshake<CountedIo<UnixStream>, Handle, BoxBody>, Option<io::Error>>,
+}
+
+/// Error produced by `UnixConnect`.
+#[derive(Debug)]
+pub enum ConnectError {
+    /// Connecting to the socket failed.
+    Connect(io::Error),
+
+    /// The HTTP/2.0 handshake failed.
+    Http2(HandshakeError),
+}
+
+/// The target is not a `unix:` URI.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidTarget(String);
+
+/// The credentials of the process on the other end of a Unix domain socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct UCred {
+    uid: u32,
+    gid: u32,
+    pid: Option<i32>,
+}
+
+// ===== impl UnixConnect =====
+
+impl UnixConnect {
+    /// Connect to the socket at `path`.
+    ///
+    /// Connections run on the reactor of `handle`.
+    pub fn new<P: AsRef<Path>>(path: P, handle: Handle) -> Self {
+        UnixConnect {
+            path: path.as_ref().to_path_buf(),
+            handle,
+            stats: ChannelStats::new(),
+            http2: Http2Settings::new(),
+        }
+    }
+
+    /// Connect to the socket named by `target`, either `unix:///absolute`
+    /// or `unix:relative`.
+    pub fn from_target(target: &str, handle: Handle) -> Result<Self, InvalidTarget> {
+        let path = if target.starts_with("unix://") {
+            &target["unix://".len()..]
+        } else if target.starts_with("unix:") {
+            &target["unix:".len()..]
+        } else {
+            return Err(InvalidTarget(target.to_string()));
+        };
+
+        if path.is_empty() || (target.starts_with("unix://") && !path.starts_with('/')) {
+            return Err(InvalidTarget(target.to_string()));
+        }
+
+        Ok(UnixConnect::new(path, handle))
+    }
+
+    /// Count the bytes of each connection in `stats`.
+    ///
+    /// Pass the same `ChannelStats` to `Channel::with_stats`.
+    pub fn with_stats(mut self, stats: ChannelStats) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    /// Negotiate each connection with `settings`.
+    pub fn http2_settings(mut self, settings: Http2Settings) -> Self {
+        self.http2 = settings;
+        self
+    }
+
+    /// Returns the socket path.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl channel::Connect for UnixConnect {
+    type Service = Connection<CountedIo<UnixStream>, Handle, BoxBody>;
+    type Error = ConnectError;
+    type Future = ConnectFuture;
+
+    fn connect(&mut self, _: &Endpoint) -> Self::Future {
+        let inner = match UnixStream::connect(&self.path, &self.handle) {
+            Ok(io) => {
+                let builder = self.http2.client_builder();
+                Ok(Handshake::new(self.stats.io(io), self.handle.clone(), &builder))
+            }
+            Err(e) => Err(Some(e)),
+        };
+
+        ConnectFuture { inner }
+    }
+}
+
+// ===== impl ConnectFuture =====
+
+impl Future for ConnectFuture {
+    type Item = Connection<CountedIo<UnixStream>, Handle, BoxBody>;
+    type Error = ConnectError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.inner {
+            Ok(ref mut h2) => h2.poll().map_err(ConnectError::Http2),
+            Err(ref mut error) => {
+                let error = error.take().expect("polled after error");
+                Err(ConnectError::Connect(error))
+            }
+        }
+    }
+}
+
+impl fmt::Debug for ConnectFuture {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("ConnectFuture")
+            .field("failed", &self.inner.is_err())
+            .finish()
+    }
+}
+
+// ===== impl InvalidTarget =====
+
+impl fmt::Display for InvalidTarget {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "invalid unix target: {:?}", self.0)
+    }
+}
+
+impl error::Error for InvalidTarget {
+    fn description(&self) -> &str {
+        "invalid unix target"
+    }
+}
+
+// ===== impl UCred =====
+
+impl UCred {
+    /// Returns the peer's user id.
+    pub fn uid(&self) -> u32 {
+        self.uid
+    }
+
+    /// Returns the peer's group id.
+    pub fn gid(&self) -> u32 {
+        self.gid
+    }
+
+    /// Returns the peer's process id, on platforms that report it.
+    pub fn pid(&self) -> Option<i32> {
+        self.pid
+    }
+}
+
+// ===== utility fns =====
+
+/// A resolver yielding the single endpoint of a `UnixConnect` channel.
+pub fn resolver() -> Fixed {
+    Fixed::new(Some(SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), 0))))
+}
+
+/// Returns the credentials of the process on the other end of `sock`.
+pub fn peer_cred(sock: &UnixStream) -> io::Result<UCred> {
+    get_peer_cred(sock.as_raw_fd())
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn get_peer_cred(fd: libc::c_int) -> io::Result<UCred> {
+    unsafe {
+        let mut cred: libc::ucred = mem::zeroed();
+        let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+
+        let ret = libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len);
+
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(UCred {
+            uid: cred.uid,
+            gid: cred.gid,
+            pid: Some(cred.pid),
+        })
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn get_peer_cred(fd: libc::c_int) -> io::Result<UCred> {
+    unsafe {
+        let mut uid: libc::uid_t = mem::zeroed();
+        let mut gid: libc::gid_t = mem::zeroed();
+
+        if libc::getpeereid(fd, &mut uid, &mut gid) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(UCred {
+            uid,
+            gid,
+            pid: None,
+        })
+    }
+}
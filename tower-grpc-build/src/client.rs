@@ -28,6 +28,7 @@ impl ServiceGenerator {
         self.import_message_types(service, scope);
         self.define_client_struct(service, scope);
         self.define_client_impl(service, scope);
+        self.define_generated_client_impl(service, scope);
     }
 
     fn import_message_types(&self, 
@@ -55,6 +56,23 @@ impl ServiceGenerator {
             ;
     }
 
+    fn define_generated_client_impl(&self,
+                                    service: &prost_build::Service,
+                                    scope: &mut codegen::Scope)
+    {
+        scope.new_impl(&service.name)
+            .generic("T")
+            .target_generic("T")
+            .impl_trait("grpc::GeneratedClient<T>")
+            .bound("T", "tower_h2::HttpService")
+            .new_fn("from_service")
+            .arg("inner", "T")
+            .arg("uri", "http::Uri")
+            .ret("Result<Self, grpc::BuilderError>")
+            .line("Self::new(inner, uri)")
+            ;
+    }
+
     fn define_client_impl(&self, 
                           service: &prost_build::Service,
                           scope: &mut codegen::Scope) 
@@ -96,6 +114,20 @@ impl ServiceGenerator {
                 .line(format!("let path = http::PathAndQuery::from_static({});", path))
                 ;
 
+            // Tell the channel which calls are safe to retry.
+            let idempotency = match method.options.idempotency_level {
+                Some(1) => Some("NoSideEffects"),
+                Some(2) => Some("Idempotent"),
+                _ => None,
+            };
+
+            if let Some(idempotency) = idempotency {
+                func.line("let mut request = request;")
+                    .line(format!(
+                        "request.extensions_mut().insert(grpc::Idempotency::{});",
+                        idempotency));
+            }
+
             let mut request = codegen::Type::new("grpc::Request");
 
             let req_body = match (method.client_streaming, method.server_streaming) {
@@ -7,7 +7,8 @@ mod server;
 use std::io;
 use std::cell::RefCell;
 use std::fmt::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::process::Command;
 use std::rc::Rc;
 use std::ascii::AsciiExt;
 
@@ -15,11 +16,14 @@ use std::ascii::AsciiExt;
 pub struct Config {
     prost: prost_build::Config,
     inner: Rc<RefCell<Inner>>,
+    file_descriptor_set: Option<PathBuf>,
 }
 
 struct Inner {
     build_client: bool,
     build_server: bool,
+    async_server: bool,
+    local_server: bool,
 }
 
 struct ServiceGenerator {
@@ -40,6 +44,12 @@ impl Config {
 
             // Disable server code gen by default
             build_server: false,
+
+            // Generate server traits with named futures by default
+            async_server: false,
+
+            // Generate `Send` async server traits by default
+            local_server: false,
         }));
 
         let root_scope = RefCell::new(codegen::Scope::new());
@@ -55,6 +65,7 @@ impl Config {
         Config {
             prost,
             inner,
+            file_descriptor_set: None,
         }
     }
 
@@ -75,11 +86,50 @@ impl Config {
         self
     }
 
+    /// Generate server traits whose methods are `async fn`.
+    ///
+    /// The methods take `&self` and return the response, instead of a named
+    /// response future. The generated code requires the `async-server`
+    /// feature of `tower-grpc`, and must be compiled with the 2018 edition.
+    pub fn async_server(&mut self, enable: bool) -> &mut Self {
+        self.inner.borrow_mut().async_server = enable;
+        self
+    }
+
+    /// Generate async server traits whose implementations, and the futures
+    /// of their methods, need not be `Send`.
+    ///
+    /// Such servers can only run on a single-threaded executor, such as a
+    /// tokio-core reactor (see `tower_grpc::local`). This has no effect
+    /// unless `async_server` is enabled.
+    pub fn local_server(&mut self, enable: bool) -> &mut Self {
+        self.inner.borrow_mut().local_server = enable;
+        self
+    }
+
+    /// Write an encoded `FileDescriptorSet` of the compiled protos, and
+    /// everything they import, to `path`.
+    ///
+    /// The set can be embedded with `include_bytes!` and served by
+    /// `tower_grpc::reflection::ServerReflection`.
+    pub fn file_descriptor_set_path<P>(&mut self, path: P) -> &mut Self
+    where P: Into<PathBuf>,
+    {
+        self.file_descriptor_set = Some(path.into());
+        self
+    }
+
     /// Generate code
     pub fn build<P>(&self, protos: &[P], includes: &[P]) -> io::Result<()>
     where P: AsRef<Path>,
     {
-        self.prost.compile_protos(protos, includes)
+        self.prost.compile_protos(protos, includes)?;
+
+        if let Some(ref path) = self.file_descriptor_set {
+            write_file_descriptor_set(path, protos, includes)?;
+        }
+
+        Ok(())
     }
 }
 
@@ -99,7 +149,7 @@ impl prost_build::ServiceGenerator for ServiceGenerator {
         }
 
         if inner.build_server {
-            self.server.generate(&service, &mut root);
+            self.server.generate(&service, inner.async_server, inner.local_server, &mut root);
         }
     }
 
@@ -123,6 +173,36 @@ impl prost_build::ServiceGenerator for ServiceGenerator {
 
 // ===== utility fns =====
 
+fn write_file_descriptor_set<P>(path: &Path, protos: &[P], includes: &[P]) -> io::Result<()>
+where P: AsRef<Path>,
+{
+    let mut cmd = Command::new(prost_build::protoc());
+    cmd.arg("--include_imports")
+        .arg("--include_source_info")
+        .arg("-o").arg(path);
+
+    for include in includes {
+        cmd.arg("-I").arg(include.as_ref());
+    }
+
+    // Well-known types may be imported without being in `includes`.
+    cmd.arg("-I").arg(prost_build::protoc_include());
+
+    for proto in protos {
+        cmd.arg(proto.as_ref());
+    }
+
+    let output = cmd.output()?;
+
+    if !output.status.success() {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("protoc failed: {}", String::from_utf8_lossy(&output.stderr))));
+    }
+
+    Ok(())
+}
+
 fn method_path(service: &prost_build::Service, method: &prost_build::Method) -> String {
     format!("\"/{}.{}/{}\"",
             service.package,
@@ -10,12 +10,16 @@ impl ServiceGenerator {
     /// Generate the gRPC server code
     pub fn generate(&self,
                     service: &prost_build::Service,
+                    async_server: bool,
+                    local_server: bool,
                     scope: &mut codegen::Scope) {
-        self.define(service, scope);
+        self.define(service, async_server, local_server, scope);
     }
 
     fn define(&self, 
               service: &prost_build::Service,
+              async_server: bool,
+              local_server: bool,
               scope: &mut codegen::Scope) {
         // Create scope that contains the generated server code.
         {
@@ -37,7 +41,11 @@ macro_rules! try_ready {
     })
 }");
 
-            self.define_service_trait(service, module.scope());
+            if async_server {
+                self.define_async_service_trait(service, local_server, module.scope());
+            } else {
+                self.define_service_trait(service, module.scope());
+            }
             self.define_server_struct(service, module.scope());
 
             let support = module.new_module(&::lower_name(&service.name))
@@ -65,7 +73,7 @@ macro_rules! try_ready {
                 methods.import(&input_path, input_type);
                 methods.import(&output_path, output_type);
 
-                self.define_service_method(service, method, methods);
+                self.define_service_method(service, method, async_server, local_server, methods);
             }
         }
     }
@@ -130,6 +138,65 @@ macro_rules! try_ready {
         scope.push_trait(service_trait);
     }
 
+    fn define_async_service_trait(&self,
+                                  service: &prost_build::Service,
+                                  local_server: bool,
+                                  scope: &mut codegen::Scope)
+    {
+        use std::fmt::Write;
+
+        // codegen cannot express `async fn`, so the trait is written out.
+        let mut service_trait = String::new();
+
+        // Local traits may hold non-`Send` values, and their futures need
+        // not be `Send`.
+        let (attr, bounds) = if local_server {
+            ("#[async_trait(?Send)]", "Clone + 'static")
+        } else {
+            ("#[async_trait]", "Clone + Send + Sync + 'static")
+        };
+
+        write!(&mut service_trait,
+               "{}\npub trait {}: {} {{\n",
+               attr, service.name, bounds).unwrap();
+
+        for method in &service.methods {
+            if method.server_streaming {
+                write!(&mut service_trait,
+                       "    type {}Stream: futures::Stream<Item = {}, Error = grpc::Error>{} + 'static;\n\n",
+                       method.proto_name, ::unqualified(&method.output_type),
+                       if local_server { "" } else { " + Send" }).unwrap();
+            }
+        }
+
+        for method in &service.methods {
+            let (input_path, input_type) = ::super_import(&method.input_type, 1);
+            let (output_path, output_type) = ::super_import(&method.output_type, 1);
+
+            scope.import(&input_path, input_type);
+            scope.import(&output_path, output_type);
+
+            let request_type = if method.client_streaming {
+                format!("grpc::Request<grpc::Streaming<{}>>", input_type)
+            } else {
+                format!("grpc::Request<{}>", input_type)
+            };
+
+            let response_type = if method.server_streaming {
+                format!("grpc::Response<Self::{}Stream>", method.proto_name)
+            } else {
+                format!("grpc::Response<{}>", output_type)
+            };
+
+            write!(&mut service_trait,
+                   "    async fn {}(&self, request: {}) -> Result<{}, grpc::Error>;\n",
+                   ::lower_name(&method.proto_name), request_type, response_type).unwrap();
+        }
+
+        service_trait.push_str("}");
+        scope.raw(&service_trait);
+    }
+
     fn define_server_struct(&self, 
                             service: &prost_build::Service, 
                             scope: &mut codegen::Scope)                        
@@ -313,9 +380,12 @@ macro_rules! try_ready {
 
                 let mut err = codegen::Block::new("Err(ref status) =>");
 
+                // Send a trailers-only response, with the status in the headers.
                 err
                     .line("let body = ResponseBody { kind: Err(status.clone()) };")
-                    .line("Ok(grpc::Response::new(body).into_http().into())")
+                    .line("let mut response = grpc::Response::new(body);")
+                    .line("response.headers_mut().insert(\"grpc-status\", status.to_header_value());")
+                    .line("Ok(response.into_http().into())")
                     ;
 
                 match_kind.push_block(err);
@@ -362,17 +432,10 @@ macro_rules! try_ready {
                 .line(&format!("Ok({}(ref mut v)) => v.poll_trailers(),", method.proto_name));
         }
 
+        // The status was sent in the headers of a trailers-only response.
         is_end_stream_block.line("Err(_) => true,");
         poll_data_block.line("Err(_) => Ok(None.into()),");
-
-        let mut poll_trailers_catch_all = codegen::Block::new("Err(ref status) =>");
-        poll_trailers_catch_all
-            .line("let mut map = http::HeaderMap::new();")
-            .line("map.insert(\"grpc-status\", status.to_header_value());")
-            .line("Ok(Some(map).into())")
-            ;
-
-        poll_trailers_block.push_block(poll_trailers_catch_all);
+        poll_trailers_block.line("Err(_) => Ok(None.into()),");
 
         {
             imp.new_fn("is_end_stream")
@@ -422,6 +485,8 @@ macro_rules! try_ready {
     fn define_service_method(&self,
                              service: &prost_build::Service,
                              method: &prost_build::Method,
+                             async_server: bool,
+                             local_server: bool,
                              module: &mut codegen::Module)
     {
         module.new_struct(&method.proto_name)
@@ -454,7 +519,7 @@ macro_rules! try_ready {
             }
         }
 
-        module.new_impl(&method.proto_name)
+        let imp = module.new_impl(&method.proto_name)
             .generic("T")
             .target_generic("T")
             .impl_trait("tower::ReadyService")
@@ -462,13 +527,38 @@ macro_rules! try_ready {
             .associate_type("Request", request)
             .associate_type("Response", response)
             .associate_type("Error", "grpc::Error")
-            .associate_type("Future", &format!("T::{}Future", method.proto_name))
-            .new_fn("call")
-            .arg_mut_self()
-            .arg("request", "Self::Request")
-            .ret("Self::Future")
-            .line(&format!("self.0.{}(request)", method.name))
             ;
+
+        if async_server {
+            let message = if method.server_streaming {
+                response_stream.clone()
+            } else {
+                ::unqualified(&method.output_type).to_string()
+            };
+
+            let (future, boxed) = if local_server {
+                ("LocalBoxFuture", "boxed_local")
+            } else {
+                ("BoxFuture", "boxed")
+            };
+
+            imp.associate_type("Future", &format!("grpc::compat::{}<grpc::Response<{}>>", future, message))
+                .new_fn("call")
+                .arg_mut_self()
+                .arg("request", "Self::Request")
+                .ret("Self::Future")
+                .line("let inner = self.0.clone();")
+                .line(&format!("grpc::compat::{}(async move {{ inner.{}(request).await }})", boxed, method.name))
+                ;
+        } else {
+            imp.associate_type("Future", &format!("T::{}Future", method.proto_name))
+                .new_fn("call")
+                .arg_mut_self()
+                .arg("request", "Self::Request")
+                .ret("Self::Future")
+                .line(&format!("self.0.{}(request)", method.name))
+                ;
+        }
     }
 }
 
@@ -1,10 +1,16 @@
 extern crate tower_grpc_build;
 
 fn main() {
-    // Build grpc-interop
+    // Build grpc-interop and the benchmark worker
     tower_grpc_build::Config::new()
         .enable_server(true)
         .enable_client(true)
-        .build(&["proto/grpc/testing/test.proto"], &["proto/grpc/testing"])
+        .build(
+            &[
+                "proto/grpc/testing/test.proto",
+                "proto/grpc/testing/benchmark_service.proto",
+                "proto/grpc/testing/worker_service.proto",
+            ],
+            &["proto/grpc/testing"])
         .unwrap_or_else(|e| panic!("protobuf compilation failed: {}", e));
 }
\ No newline at end of file
@@ -24,10 +24,11 @@ use std::str::FromStr;
 
 use http::header::HeaderValue;
 use http::uri::{self, Uri};
-use futures::{future, Future, stream};
+use futures::{future, Future, Stream, stream};
+use futures::sync::mpsc;
 use tokio_core::reactor;
 use tokio_core::net::TcpStream;
-use tower_grpc::Request;
+use tower_grpc::{Code, Request};
 use tower_h2::client::Connection;
 
 use pb::SimpleRequest;
@@ -45,6 +46,9 @@ mod util;
 const LARGE_REQ_SIZE: usize = 271828;
 const LARGE_RSP_SIZE: i32 = 314159;
 
+const ECHO_INITIAL: &'static str = "x-grpc-test-echo-initial";
+const ECHO_INITIAL_VALUE: &'static str = "test_initial_metadata_value";
+
 arg_enum!{
     #[derive(Debug, Copy, Clone)]
     #[allow(non_camel_case_types)]
@@ -300,6 +304,161 @@ impl Testcase {
                         })
                 )
             },
+            Testcase::ping_pong => {
+                // (request size, response size) for each round trip.
+                let sizes = [(27182, 31415), (8, 9), (1828, 2653), (45904, 58979)];
+
+                let (tx, rx) = mpsc::unbounded();
+                let requests = rx.map_err(|()| -> tower_grpc::Error {
+                    unreachable!("unbounded receivers never fail")
+                });
+
+                tx.unbounded_send(util::ping(sizes[0].0, sizes[0].1))
+                    .expect("request stream dropped");
+
+                let result = core.run(client.full_duplex_call(Request::new(requests)));
+                let mut assertions = vec![
+                    test_assert!(
+                        "call must be successful",
+                        result.is_ok(),
+                        format!("result={:?}", result)
+                    )
+                ];
+
+                let mut responses = match result {
+                    Ok(response) => response.into_inner(),
+                    Err(_) => return Ok(assertions),
+                };
+
+                for (i, &(_, response_size)) in sizes.iter().enumerate() {
+                    if i > 0 {
+                        tx.unbounded_send(util::ping(sizes[i].0, response_size))
+                            .expect("request stream dropped");
+                    }
+
+                    let response = match core.run(responses.into_future()) {
+                        Ok((response, rest)) => {
+                            responses = rest;
+                            response
+                        }
+                        Err((e, _)) => {
+                            assertions.push(test_assert!(
+                                "response must be received",
+                                false,
+                                format!("error={:?}", e)
+                            ));
+                            return Ok(assertions);
+                        }
+                    };
+
+                    let payload_len = response.as_ref()
+                        .and_then(|r| r.payload.as_ref())
+                        .map(|p| p.body.len())
+                        .unwrap_or(0);
+
+                    assertions.push(test_assert!(
+                        "response payload must have the requested size",
+                        payload_len == response_size as usize,
+                        format!("payload_len={:?}; expected={:?}", payload_len, response_size)
+                    ));
+                }
+
+                drop(tx);
+
+                let end = core.run(responses.into_future())
+                    .map(|(response, _)| response.is_none())
+                    .unwrap_or(false);
+                assertions.push(test_assert!(
+                    "response stream must end after the last request",
+                    end
+                ));
+
+                Ok(assertions)
+            },
+            Testcase::custom_metadata => {
+                let req = SimpleRequest {
+                    response_type: pb::PayloadType::Compressable as i32,
+                    response_size: LARGE_RSP_SIZE,
+                    payload: Some(util::client_payload(LARGE_REQ_SIZE)),
+                    ..Default::default()
+                };
+                let mut req = Request::new(req);
+                req.headers_mut()
+                    .insert(ECHO_INITIAL, HeaderValue::from_static(ECHO_INITIAL_VALUE));
+
+                // Response trailers are not yet exposed, so the echoed
+                // trailing metadata can't be checked.
+                core.run(client.unary_call(req)
+                    .then(|result| {
+                        let mut assertions = vec![
+                            test_assert!(
+                                "call must be successful",
+                                result.is_ok(),
+                                format!("result={:?}", result)
+                            )
+                        ];
+                        if let Ok(response) = result {
+                            let echoed = response.headers().get(ECHO_INITIAL).cloned();
+                            assertions.push(test_assert!(
+                                "initial metadata must be echoed",
+                                echoed.as_ref().map(|v| v == ECHO_INITIAL_VALUE).unwrap_or(false),
+                                format!("{}={:?}", ECHO_INITIAL, echoed)
+                            ));
+                        }
+                        future::ok::<Vec<TestAssertion>, Box<Error>>(assertions)
+                    }))
+            },
+            Testcase::status_code_and_message => {
+                let req = SimpleRequest {
+                    response_status: Some(pb::EchoStatus {
+                        code: 2,
+                        message: "test status message".to_string(),
+                    }),
+                    ..Default::default()
+                };
+
+                // Status messages are not yet supported, so only the code is
+                // checked.
+                core.run(client.unary_call(Request::new(req))
+                    .then(|result| {
+                        let code = result.as_ref().err().and_then(status_code);
+                        let assertions = vec![
+                            test_assert!(
+                                "call must fail with UNKNOWN",
+                                code == Some(Code::UNKNOWN),
+                                format!("result={:?}", result)
+                            )
+                        ];
+                        future::ok::<Vec<TestAssertion>, Box<Error>>(assertions)
+                    }))
+            },
+            Testcase::timeout_on_sleeping_server => {
+                let requests = stream::iter_ok(vec![util::ping(27182, 31415)]);
+                let mut req = Request::new(requests);
+                req.headers_mut()
+                    .insert("grpc-timeout", HeaderValue::from_static("1m"));
+
+                let call = client.full_duplex_call(req)
+                    .map_err(|e| status_code(&e))
+                    .and_then(|response| {
+                        response.into_inner()
+                            .into_future()
+                            .map(|_| ())
+                            .map_err(|(e, _)| status_code(&e))
+                    });
+
+                core.run(call
+                    .then(|result| {
+                        let assertions = vec![
+                            test_assert!(
+                                "call must fail with DEADLINE_EXCEEDED",
+                                result == Err(Some(Code::DEADLINE_EXCEEDED)),
+                                format!("result={:?}", result)
+                            )
+                        ];
+                        future::ok::<Vec<TestAssertion>, Box<Error>>(assertions)
+                    }))
+            },
             Testcase::compute_engine_creds
             | Testcase::jwt_token_creds
             | Testcase::oauth2_auth_token
@@ -321,6 +480,14 @@ impl Testcase {
         }
     }
 }
+/// Returns the status code of a failed call, if the server sent one.
+fn status_code<T>(error: &tower_grpc::Error<T>) -> Option<Code> {
+    match *error {
+        tower_grpc::Error::Grpc(ref status) => Some(status.code()),
+        tower_grpc::Error::Inner(_) => None,
+    }
+}
+
 enum TestAssertion {
     Passed { description: &'static str },
     Failed { description: &'static str,
@@ -0,0 +1,913 @@
+//! A worker for the gRPC benchmark harness.
+//!
+//! The benchmark driver connects to the `WorkerService` served here and asks
+//! it to run a benchmark server or client. Servers answer the
+//! `BenchmarkService`; clients run closed-loop unary or streaming ping-pong
+//! calls against them and report their latencies.
+//!
+//! See the [benchmarking docs][docs] for details.
+//!
+//! [docs]: https://grpc.io/docs/guides/benchmarking.html
+
+#[macro_use]
+extern crate clap;
+extern crate env_logger;
+extern crate futures;
+extern crate http;
+extern crate libc;
+#[macro_use]
+extern crate log;
+extern crate num_cpus;
+extern crate prost;
+#[macro_use]
+extern crate prost_derive;
+extern crate tokio_core;
+extern crate tokio_timer;
+extern crate tower;
+extern crate tower_h2;
+extern crate tower_grpc;
+
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use std::mem;
+
+use futures::{future, stream, Future, Poll, Stream};
+use futures::future::Loop;
+use futures::sync::{mpsc, oneshot};
+use http::Uri;
+use tokio_core::net::{TcpListener, TcpStream};
+use tokio_core::reactor::{Core, Handle};
+use tokio_timer::Timer;
+use tower::Service;
+use tower_grpc::{Code, Request, Response, Status, Streaming};
+use tower_h2::{BoxBody, Server};
+use tower_h2::client::Connection;
+
+use pb::{
+    ClientArgs,
+    ClientConfig,
+    ClientStats,
+    ClientStatus,
+    CoreRequest,
+    CoreResponse,
+    HistogramData,
+    HistogramParams,
+    Payload,
+    RequestResultCount,
+    RpcType,
+    ServerArgs,
+    ServerConfig,
+    ServerStats,
+    ServerStatus,
+    SimpleRequest,
+    SimpleResponse,
+    Void,
+};
+use pb::{client, server};
+use pb::{client_args, load_params, payload_config, server_args};
+
+mod pb {
+    #![allow(dead_code)]
+    #![allow(unused_imports)]
+    include!(concat!(env!("OUT_DIR"), "/grpc.testing.rs"));
+}
+
+/// Histogram resolution used when the driver does not specify one.
+const DEFAULT_RESOLUTION: f64 = 0.01;
+
+/// Largest latency, in nanoseconds, recorded when the driver does not
+/// specify one.
+const DEFAULT_MAX_POSSIBLE: f64 = 60e9;
+
+type Error = tower_grpc::Error;
+type BoxFuture<T> = Box<Future<Item = Response<T>, Error = Error>>;
+type BoxStream<T> = Box<Stream<Item = T, Error = Error>>;
+
+type Channel = client::BenchmarkService<Shared<Connection<TcpStream, Handle, BoxBody>>>;
+
+/// Serves the `WorkerService` to the benchmark driver.
+#[derive(Debug, Clone)]
+struct Worker {
+    handle: Handle,
+    quit: mpsc::UnboundedSender<()>,
+}
+
+/// Serves the `BenchmarkService` to benchmark clients.
+#[derive(Debug, Clone)]
+struct Benchmark;
+
+/// A running benchmark client.
+///
+/// The client's call loops stop once it is dropped.
+#[derive(Debug)]
+struct BenchmarkClient {
+    recorder: Rc<RefCell<Recorder>>,
+    running: Rc<Cell<bool>>,
+}
+
+/// Statistics gathered by a benchmark client's call loops.
+#[derive(Debug)]
+struct Recorder {
+    histogram: Histogram,
+    results: BTreeMap<i32, i64>,
+    start: Times,
+}
+
+/// A latency histogram, compatible with the one used by the gRPC core.
+///
+/// Bucket `i` counts the values in
+/// `[(1 + resolution)^i, (1 + resolution)^(i + 1))`.
+#[derive(Debug)]
+struct Histogram {
+    multiplier_ln: f64,
+    max_possible: f64,
+    buckets: Vec<u32>,
+    min_seen: f64,
+    max_seen: f64,
+    sum: f64,
+    sum_of_squares: f64,
+    count: f64,
+}
+
+/// Process times at a point in time.
+#[derive(Debug, Clone, Copy)]
+struct Times {
+    wall: Instant,
+    user: f64,
+    system: f64,
+}
+
+/// Shares one HTTP/2.0 connection between several clients.
+#[derive(Debug)]
+struct Shared<T>(Rc<RefCell<T>>);
+
+// ===== impl Worker =====
+
+impl server::WorkerService for Worker {
+    type RunServerStream = BoxStream<ServerStatus>;
+    type RunServerFuture = BoxFuture<Self::RunServerStream>;
+
+    fn run_server(&mut self, request: Request<Streaming<ServerArgs>>) -> Self::RunServerFuture {
+        let handle = self.handle.clone();
+
+        let response = request.into_inner()
+            .into_future()
+            .map_err(|(e, _)| e)
+            .and_then(move |(args, rest)| {
+                let config = match args.and_then(|args| args.argtype) {
+                    Some(server_args::Argtype::Setup(config)) => config,
+                    _ => {
+                        warn!("the first ServerArgs must set up the server");
+                        return Err(grpc_error(Code::INVALID_ARGUMENT));
+                    }
+                };
+
+                // Dropping `shutdown` stops the server, which happens once the
+                // driver closes the stream.
+                let (port, shutdown) = Benchmark::serve(&config, &handle)?;
+                let cores = num_cpus::get() as i32;
+                let mut start = Times::now();
+
+                let initial = ServerStatus {
+                    stats: Some(server_stats(&start)),
+                    port,
+                    cores,
+                };
+
+                let marks = rest
+                    .filter_map(|args| match args.argtype {
+                        Some(server_args::Argtype::Mark(mark)) => Some(mark),
+                        _ => {
+                            warn!("ignoring ServerArgs that are not a mark");
+                            None
+                        }
+                    })
+                    .map(move |mark| {
+                        let _ = &shutdown;
+                        let stats = server_stats(&start);
+                        if mark.reset {
+                            start = Times::now();
+                        }
+
+                        ServerStatus { stats: Some(stats), port, cores }
+                    });
+
+                let statuses = stream::once(Ok(initial)).chain(marks);
+                Ok(Response::new(Box::new(statuses) as Self::RunServerStream))
+            });
+
+        Box::new(response)
+    }
+
+    type RunClientStream = BoxStream<ClientStatus>;
+    type RunClientFuture = BoxFuture<Self::RunClientStream>;
+
+    fn run_client(&mut self, request: Request<Streaming<ClientArgs>>) -> Self::RunClientFuture {
+        let handle = self.handle.clone();
+
+        let response = request.into_inner()
+            .into_future()
+            .map_err(|(e, _)| e)
+            .and_then(move |(args, rest)| {
+                let config = match args.and_then(|args| args.argtype) {
+                    Some(client_args::Argtype::Setup(config)) => config,
+                    _ => {
+                        warn!("the first ClientArgs must set up the client");
+                        return future::Either::A(future::err(grpc_error(Code::INVALID_ARGUMENT)));
+                    }
+                };
+
+                let response = BenchmarkClient::start(config, &handle).map(move |client| {
+                    let initial = ClientStatus {
+                        stats: Some(client.mark(false)),
+                    };
+
+                    let marks = rest
+                        .filter_map(|args| match args.argtype {
+                            Some(client_args::Argtype::Mark(mark)) => Some(mark),
+                            _ => {
+                                warn!("ignoring ClientArgs that are not a mark");
+                                None
+                            }
+                        })
+                        .map(move |mark| ClientStatus {
+                            stats: Some(client.mark(mark.reset)),
+                        });
+
+                    let statuses = stream::once(Ok(initial)).chain(marks);
+                    Response::new(Box::new(statuses) as Self::RunClientStream)
+                });
+
+                future::Either::B(response)
+            });
+
+        Box::new(response)
+    }
+
+    type CoreCountFuture = future::FutureResult<Response<CoreResponse>, Error>;
+
+    fn core_count(&mut self, _request: Request<CoreRequest>) -> Self::CoreCountFuture {
+        future::ok(Response::new(CoreResponse {
+            cores: num_cpus::get() as i32,
+        }))
+    }
+
+    type QuitWorkerFuture = future::FutureResult<Response<Void>, Error>;
+
+    fn quit_worker(&mut self, _request: Request<Void>) -> Self::QuitWorkerFuture {
+        info!("quit requested");
+        let _ = self.quit.unbounded_send(());
+        future::ok(Response::new(Void {}))
+    }
+}
+
+// ===== impl Benchmark =====
+
+impl Benchmark {
+    /// Serve the benchmark service as described by `config`.
+    ///
+    /// Returns the bound port and a handle that stops the server when
+    /// dropped.
+    fn serve(config: &ServerConfig, handle: &Handle) -> Result<(i32, oneshot::Sender<()>), Error> {
+        if config.security_params.is_some() {
+            warn!("tower-grpc does not currently support TLS");
+            return Err(grpc_error(Code::UNIMPLEMENTED));
+        }
+
+        if config.port < 0 || config.port > u16::max_value() as i32 {
+            return Err(grpc_error(Code::INVALID_ARGUMENT));
+        }
+
+        let addr = SocketAddr::from(([0, 0, 0, 0], config.port as u16));
+        let bind = TcpListener::bind(&addr, handle)
+            .and_then(|bind| bind.local_addr().map(|addr| (bind, addr)));
+        let (bind, addr) = match bind {
+            Ok(bound) => bound,
+            Err(e) => {
+                error!("failed to bind benchmark server; addr={:?}; error={:?}", addr, e);
+                return Err(grpc_error(Code::UNAVAILABLE));
+            }
+        };
+
+        info!("benchmark server listening; addr={:?}", addr);
+
+        let new_service = server::BenchmarkServiceServer::new(Benchmark);
+        let h2 = Server::new(new_service, Default::default(), handle.clone());
+        let reactor = handle.clone();
+
+        let serve = bind.incoming()
+            .for_each(move |(sock, _)| {
+                sock.set_nodelay(true)?;
+
+                let serve = h2.serve(sock);
+                reactor.spawn(serve.map_err(|e| error!("h2 error: {:?}", e)));
+
+                Ok(())
+            })
+            .map_err(|e| error!("benchmark server error: {:?}", e));
+
+        let (shutdown, stop) = oneshot::channel();
+        let serve = serve
+            .select(stop.then(|_| Ok::<(), ()>(())))
+            .then(move |_| {
+                info!("benchmark server stopped; addr={:?}", addr);
+                Ok::<(), ()>(())
+            });
+        handle.spawn(serve);
+
+        Ok((addr.port() as i32, shutdown))
+    }
+}
+
+impl server::BenchmarkService for Benchmark {
+    type UnaryCallFuture = future::FutureResult<Response<SimpleResponse>, Error>;
+
+    fn unary_call(&mut self, request: Request<SimpleRequest>) -> Self::UnaryCallFuture {
+        future::ok(Response::new(simple_response(&request.into_inner())))
+    }
+
+    type StreamingCallStream = BoxStream<SimpleResponse>;
+    type StreamingCallFuture = future::FutureResult<Response<Self::StreamingCallStream>, Error>;
+
+    fn streaming_call(&mut self, request: Request<Streaming<SimpleRequest>>)
+        -> Self::StreamingCallFuture
+    {
+        let responses = request.into_inner()
+            .map(|request| simple_response(&request));
+
+        future::ok(Response::new(Box::new(responses) as Self::StreamingCallStream))
+    }
+
+    type StreamingFromClientFuture = BoxFuture<SimpleResponse>;
+
+    fn streaming_from_client(&mut self, request: Request<Streaming<SimpleRequest>>)
+        -> Self::StreamingFromClientFuture
+    {
+        let response = request.into_inner()
+            .fold(None, |_, request| Ok::<_, Error>(Some(request)))
+            .map(|last| {
+                let response = last
+                    .map(|request| simple_response(&request))
+                    .unwrap_or_default();
+
+                Response::new(response)
+            });
+
+        Box::new(response)
+    }
+
+    type StreamingFromServerStream = BoxStream<SimpleResponse>;
+    type StreamingFromServerFuture =
+        future::FutureResult<Response<Self::StreamingFromServerStream>, Error>;
+
+    fn streaming_from_server(&mut self, request: Request<SimpleRequest>)
+        -> Self::StreamingFromServerFuture
+    {
+        let response = simple_response(&request.into_inner());
+        let responses = stream::repeat(response);
+
+        future::ok(Response::new(Box::new(responses) as Self::StreamingFromServerStream))
+    }
+
+    type StreamingBothWaysStream = BoxStream<SimpleResponse>;
+    type StreamingBothWaysFuture =
+        future::FutureResult<Response<Self::StreamingBothWaysStream>, Error>;
+
+    fn streaming_both_ways(&mut self, request: Request<Streaming<SimpleRequest>>)
+        -> Self::StreamingBothWaysFuture
+    {
+        let responses = request.into_inner()
+            .map(|request| simple_response(&request));
+
+        future::ok(Response::new(Box::new(responses) as Self::StreamingBothWaysStream))
+    }
+}
+
+// ===== impl BenchmarkClient =====
+
+impl BenchmarkClient {
+    /// Connect to the servers in `config` and start its call loops.
+    fn start(config: ClientConfig, handle: &Handle) -> Box<Future<Item = Self, Error = Error>> {
+        let setup = match Setup::from_config(&config) {
+            Ok(setup) => setup,
+            Err(e) => return Box::new(future::err(e)),
+        };
+
+        let channels = (0..config.client_channels.max(1) as usize)
+            .map(|i| {
+                let target = &config.server_targets[i % config.server_targets.len()];
+                connect(target, handle)
+            })
+            .collect::<Vec<_>>();
+
+        let histogram = Histogram::new(config.histogram_params.as_ref());
+        let outstanding = config.outstanding_rpcs_per_channel.max(1);
+        let handle = handle.clone();
+
+        let client = future::join_all(channels).map(move |channels| {
+            let client = BenchmarkClient {
+                recorder: Rc::new(RefCell::new(Recorder::new(histogram))),
+                running: Rc::new(Cell::new(true)),
+            };
+
+            for channel in channels {
+                for _ in 0..outstanding {
+                    let recorder = client.recorder.clone();
+                    let running = client.running.clone();
+                    let request = setup.request.clone();
+
+                    let calls = match setup.rpc_type {
+                        RpcType::Streaming => {
+                            streaming_calls(channel.clone(), request, recorder, running)
+                        }
+                        _ => unary_calls(channel.clone(), request, recorder, running),
+                    };
+
+                    handle.spawn(calls);
+                }
+            }
+
+            client
+        });
+
+        Box::new(client)
+    }
+
+    /// Returns the statistics gathered since the last reset.
+    fn mark(&self, reset: bool) -> ClientStats {
+        self.recorder.borrow_mut().mark(reset)
+    }
+}
+
+impl Drop for BenchmarkClient {
+    fn drop(&mut self) {
+        self.running.set(false);
+    }
+}
+
+/// The validated parts of a `ClientConfig`.
+struct Setup {
+    rpc_type: RpcType,
+    request: SimpleRequest,
+}
+
+impl Setup {
+    fn from_config(config: &ClientConfig) -> Result<Setup, Error> {
+        if config.security_params.is_some() {
+            warn!("tower-grpc does not currently support TLS");
+            return Err(grpc_error(Code::UNIMPLEMENTED));
+        }
+
+        if config.server_targets.is_empty() {
+            warn!("no server targets");
+            return Err(grpc_error(Code::INVALID_ARGUMENT));
+        }
+
+        match config.load_params.as_ref().and_then(|params| params.load.as_ref()) {
+            None | Some(&load_params::Load::ClosedLoop(_)) => {}
+            Some(load) => {
+                warn!("unsupported load; load={:?}", load);
+                return Err(grpc_error(Code::UNIMPLEMENTED));
+            }
+        }
+
+        let rpc_type = match RpcType::from_i32(config.rpc_type) {
+            Some(rpc_type @ RpcType::Unary) |
+            Some(rpc_type @ RpcType::Streaming) => rpc_type,
+            rpc_type => {
+                warn!("unsupported rpc type; rpc_type={:?}", rpc_type);
+                return Err(grpc_error(Code::UNIMPLEMENTED));
+            }
+        };
+
+        let (req_size, resp_size) =
+            match config.payload_config.as_ref().and_then(|config| config.payload.as_ref()) {
+                None => (0, 0),
+                Some(&payload_config::Payload::SimpleParams(ref params)) => {
+                    (params.req_size, params.resp_size)
+                }
+                Some(payload) => {
+                    warn!("unsupported payload; payload={:?}", payload);
+                    return Err(grpc_error(Code::UNIMPLEMENTED));
+                }
+            };
+
+        let request = SimpleRequest {
+            response_size: resp_size,
+            payload: Some(payload(req_size)),
+            ..Default::default()
+        };
+
+        Ok(Setup { rpc_type, request })
+    }
+}
+
+/// Connect to `target`, a `host:port` pair.
+fn connect(target: &str, handle: &Handle) -> Box<Future<Item = Channel, Error = Error>> {
+    let addr = target.to_socket_addrs().ok().and_then(|mut addrs| addrs.next());
+    let uri = format!("http://{}", target).parse::<Uri>();
+
+    let (addr, uri) = match (addr, uri) {
+        (Some(addr), Ok(uri)) => (addr, uri),
+        _ => {
+            warn!("invalid server target; target={:?}", target);
+            return Box::new(future::err(grpc_error(Code::INVALID_ARGUMENT)));
+        }
+    };
+
+    let reactor = handle.clone();
+    let channel = TcpStream::connect(&addr, handle)
+        .and_then(|sock| {
+            sock.set_nodelay(true)?;
+            Ok(sock)
+        })
+        .map_err(move |e| {
+            error!("failed to connect; addr={:?}; error={:?}", addr, e);
+            grpc_error(Code::UNAVAILABLE)
+        })
+        .and_then(move |sock| {
+            Connection::handshake(sock, reactor).map_err(|e| {
+                error!("failed HTTP/2.0 handshake: {:?}", e);
+                grpc_error(Code::UNAVAILABLE)
+            })
+        })
+        .and_then(move |conn| {
+            client::BenchmarkService::new(Shared::new(conn), uri)
+                .map_err(|_| grpc_error(Code::INVALID_ARGUMENT))
+        });
+
+    Box::new(channel)
+}
+
+/// Make unary calls one after the other until `running` is unset.
+fn unary_calls(
+    client: Channel,
+    request: SimpleRequest,
+    recorder: Rc<RefCell<Recorder>>,
+    running: Rc<Cell<bool>>,
+) -> Box<Future<Item = (), Error = ()>> {
+    let calls = future::loop_fn(client, move |mut client| {
+        let recorder = recorder.clone();
+        let running = running.clone();
+        let start = Instant::now();
+
+        let call = client.unary_call(Request::new(request.clone()));
+
+        call.then(move |result| {
+            let code = result.err().map(|e| status_code(&e)).unwrap_or(Code::OK);
+            recorder.borrow_mut().record(start, code);
+
+            if running.get() {
+                Ok(Loop::Continue(client))
+            } else {
+                Ok(Loop::Break(()))
+            }
+        })
+    });
+
+    Box::new(calls)
+}
+
+/// Send requests on a single stream one at a time, waiting for each
+/// response, until `running` is unset.
+fn streaming_calls(
+    mut client: Channel,
+    request: SimpleRequest,
+    recorder: Rc<RefCell<Recorder>>,
+    running: Rc<Cell<bool>>,
+) -> Box<Future<Item = (), Error = ()>> {
+    let (tx, rx) = mpsc::unbounded();
+    let requests = rx.map_err(|()| -> tower_grpc::Error {
+        unreachable!("unbounded receivers never fail")
+    });
+
+    let start = Instant::now();
+    let failed = recorder.clone();
+
+    let calls = client.streaming_call(Request::new(requests))
+        .then(move |result| match result {
+            Ok(response) => Ok(response.into_inner()),
+            Err(e) => {
+                failed.borrow_mut().record(start, status_code(&e));
+                Err(())
+            }
+        })
+        .and_then(move |responses| {
+            future::loop_fn(responses, move |responses| {
+                let recorder = recorder.clone();
+                let running = running.clone();
+                let start = Instant::now();
+
+                if tx.unbounded_send(request.clone()).is_err() {
+                    warn!("request stream dropped");
+                }
+
+                responses.into_future().then(move |result| {
+                    let responses = match result {
+                        Ok((Some(_), responses)) => {
+                            recorder.borrow_mut().record(start, Code::OK);
+                            responses
+                        }
+                        Ok((None, _)) => return Ok(Loop::Break(())),
+                        Err((e, _)) => {
+                            recorder.borrow_mut().record(start, status_code(&e));
+                            return Ok(Loop::Break(()));
+                        }
+                    };
+
+                    if running.get() {
+                        Ok(Loop::Continue(responses))
+                    } else {
+                        Ok(Loop::Break(()))
+                    }
+                })
+            })
+        });
+
+    Box::new(calls)
+}
+
+// ===== impl Recorder =====
+
+impl Recorder {
+    fn new(histogram: Histogram) -> Self {
+        Recorder {
+            histogram,
+            results: BTreeMap::new(),
+            start: Times::now(),
+        }
+    }
+
+    /// Record the result of a call started at `start`.
+    fn record(&mut self, start: Instant, code: Code) {
+        self.histogram.add(nanos(start.elapsed()));
+        *self.results.entry(code.as_i32()).or_insert(0) += 1;
+    }
+
+    fn mark(&mut self, reset: bool) -> ClientStats {
+        let (time_elapsed, time_user, time_system) = self.start.elapsed();
+
+        let stats = ClientStats {
+            latencies: Some(self.histogram.data()),
+            time_elapsed,
+            time_user,
+            time_system,
+            request_results: self.results.iter()
+                .map(|(&status_code, &count)| RequestResultCount { status_code, count })
+                .collect(),
+            cq_poll_count: 0,
+        };
+
+        if reset {
+            self.histogram.reset();
+            self.results.clear();
+            self.start = Times::now();
+        }
+
+        stats
+    }
+}
+
+// ===== impl Histogram =====
+
+impl Histogram {
+    fn new(params: Option<&HistogramParams>) -> Self {
+        let resolution = params
+            .map(|params| params.resolution)
+            .and_then(|resolution| if resolution > 0.0 { Some(resolution) } else { None })
+            .unwrap_or(DEFAULT_RESOLUTION);
+        let max_possible = params
+            .map(|params| params.max_possible)
+            .and_then(|max| if max > 0.0 { Some(max) } else { None })
+            .unwrap_or(DEFAULT_MAX_POSSIBLE);
+
+        let mut histogram = Histogram {
+            multiplier_ln: (1.0 + resolution).ln(),
+            max_possible,
+            buckets: vec![],
+            min_seen: 0.0,
+            max_seen: 0.0,
+            sum: 0.0,
+            sum_of_squares: 0.0,
+            count: 0.0,
+        };
+
+        let len = histogram.bucket_for(max_possible) + 1;
+        histogram.buckets = vec![0; len];
+        histogram.reset();
+        histogram
+    }
+
+    fn add(&mut self, value: f64) {
+        self.sum += value;
+        self.sum_of_squares += value * value;
+        self.count += 1.0;
+        self.min_seen = self.min_seen.min(value);
+        self.max_seen = self.max_seen.max(value);
+
+        let bucket = self.bucket_for(value);
+        self.buckets[bucket] += 1;
+    }
+
+    fn bucket_for(&self, value: f64) -> usize {
+        let value = value.max(1.0).min(self.max_possible);
+        (value.ln() / self.multiplier_ln) as usize
+    }
+
+    fn reset(&mut self) {
+        for bucket in &mut self.buckets {
+            *bucket = 0;
+        }
+
+        self.min_seen = self.max_possible;
+        self.max_seen = 0.0;
+        self.sum = 0.0;
+        self.sum_of_squares = 0.0;
+        self.count = 0.0;
+    }
+
+    fn data(&self) -> HistogramData {
+        HistogramData {
+            bucket: self.buckets.clone(),
+            min_seen: self.min_seen,
+            max_seen: self.max_seen,
+            sum: self.sum,
+            sum_of_squares: self.sum_of_squares,
+            count: self.count,
+        }
+    }
+}
+
+// ===== impl Times =====
+
+impl Times {
+    fn now() -> Self {
+        let (user, system) = cpu_times();
+
+        Times {
+            wall: Instant::now(),
+            user,
+            system,
+        }
+    }
+
+    /// Returns the wall clock, user and system seconds elapsed since `self`.
+    fn elapsed(&self) -> (f64, f64, f64) {
+        let now = Times::now();
+
+        (
+            seconds(now.wall.duration_since(self.wall)),
+            now.user - self.user,
+            now.system - self.system,
+        )
+    }
+}
+
+// ===== impl Shared =====
+
+impl<T> Shared<T> {
+    fn new(inner: T) -> Self {
+        Shared(Rc::new(RefCell::new(inner)))
+    }
+}
+
+impl<T> Clone for Shared<T> {
+    fn clone(&self) -> Self {
+        Shared(self.0.clone())
+    }
+}
+
+impl<T: Service> Service for Shared<T> {
+    type Request = T::Request;
+    type Response = T::Response;
+    type Error = T::Error;
+    type Future = T::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.0.borrow_mut().poll_ready()
+    }
+
+    fn call(&mut self, request: Self::Request) -> Self::Future {
+        self.0.borrow_mut().call(request)
+    }
+}
+
+// ===== utility fns =====
+
+fn simple_response(request: &SimpleRequest) -> SimpleResponse {
+    SimpleResponse {
+        payload: Some(payload(request.response_size)),
+        ..Default::default()
+    }
+}
+
+fn payload(size: i32) -> Payload {
+    Payload {
+        type_: Default::default(),
+        body: vec![0; size.max(0) as usize],
+    }
+}
+
+fn server_stats(start: &Times) -> ServerStats {
+    let (time_elapsed, time_user, time_system) = start.elapsed();
+
+    ServerStats {
+        time_elapsed,
+        time_user,
+        time_system,
+        ..Default::default()
+    }
+}
+
+fn grpc_error(code: Code) -> Error {
+    tower_grpc::Error::Grpc(Status::with_code(code))
+}
+
+fn status_code<T>(error: &tower_grpc::Error<T>) -> Code {
+    match *error {
+        tower_grpc::Error::Grpc(ref status) => status.code(),
+        tower_grpc::Error::Inner(_) => Code::UNAVAILABLE,
+    }
+}
+
+/// Returns the user and system CPU seconds used by this process.
+fn cpu_times() -> (f64, f64) {
+    let mut usage: libc::rusage = unsafe { mem::zeroed() };
+
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } != 0 {
+        return (0.0, 0.0);
+    }
+
+    (timeval_seconds(usage.ru_utime), timeval_seconds(usage.ru_stime))
+}
+
+fn timeval_seconds(time: libc::timeval) -> f64 {
+    time.tv_sec as f64 + time.tv_usec as f64 / 1e6
+}
+
+fn seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1e9
+}
+
+fn nanos(duration: Duration) -> f64 {
+    duration.as_secs() as f64 * 1e9 + duration.subsec_nanos() as f64
+}
+
+fn main() {
+    use clap::{Arg, App};
+    let _ = ::env_logger::init();
+
+    let matches =
+        App::new("qps-worker")
+            .author("Eliza Weisman <eliza@buoyant.io>")
+            .arg(Arg::with_name("driver_port")
+                .long("driver_port")
+                .value_name("PORT")
+                .help("The port the benchmark driver connects to. For example, \"10000\".")
+                .takes_value(true)
+                .default_value("10000")
+            )
+            .get_matches();
+
+    let port = value_t!(matches, "driver_port", u16)
+        .unwrap_or_else(|e| e.exit());
+
+    let mut core = Core::new().expect("could not create reactor core!");
+    let reactor = core.handle();
+
+    let (quit, quitting) = mpsc::unbounded();
+    let new_service = server::WorkerServiceServer::new(Worker {
+        handle: reactor.clone(),
+        quit,
+    });
+
+    let h2 = Server::new(new_service, Default::default(), reactor.clone());
+
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let bind = TcpListener::bind(&addr, &reactor).expect("bind");
+
+    info!("listening; addr={:?}", addr);
+
+    let serve = bind.incoming()
+        .fold((h2, reactor.clone()), |(h2, reactor), (sock, _)| {
+            if let Err(e) = sock.set_nodelay(true) {
+                return Err(e);
+            }
+
+            let serve = h2.serve(sock);
+            reactor.spawn(serve.map_err(|e| error!("h2 error: {:?}", e)));
+
+            Ok((h2, reactor))
+        })
+        .map(|_| ())
+        .map_err(|e| error!("worker server error: {:?}", e));
+    reactor.spawn(serve);
+
+    let _ = core.run(quitting.into_future());
+
+    // Give the `QuitWorker` response a chance to be sent.
+    let _ = core.run(Timer::default().sleep(Duration::from_millis(100)));
+}
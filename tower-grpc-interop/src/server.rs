@@ -1,13 +1,290 @@
 #[macro_use]
+extern crate clap;
+extern crate env_logger;
+extern crate futures;
+extern crate http;
+#[macro_use]
 extern crate log;
 extern crate prost;
 #[macro_use]
 extern crate prost_derive;
 extern crate tokio_core;
+extern crate tokio_timer;
 extern crate tower;
 extern crate tower_h2;
 extern crate tower_grpc;
 
-mod test {
-    include!(concat!(env!("OUT_DIR"), "/test.rs"));
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use futures::{future, stream, Future, Stream};
+use http::HeaderMap;
+use tokio_core::net::TcpListener;
+use tokio_core::reactor::Core;
+use tokio_timer::Timer;
+use tower_grpc::{Code, Request, Response, Status, Streaming};
+use tower_h2::Server;
+
+use pb::{
+    Empty,
+    EchoStatus,
+    Payload,
+    ResponseParameters,
+    SimpleRequest,
+    SimpleResponse,
+    StreamingInputCallRequest,
+    StreamingInputCallResponse,
+    StreamingOutputCallRequest,
+    StreamingOutputCallResponse,
+};
+use pb::server;
+
+mod pb {
+    #![allow(dead_code)]
+    #![allow(unused_imports)]
+    include!(concat!(env!("OUT_DIR"), "/grpc.testing.rs"));
+}
+
+/// Request header echoed back in the response headers.
+const ECHO_INITIAL: &'static str = "x-grpc-test-echo-initial";
+
+type Error = tower_grpc::Error;
+type BoxFuture<T> = Box<Future<Item = Response<T>, Error = Error>>;
+type BoxStream<T> = Box<Stream<Item = T, Error = Error>>;
+
+#[derive(Debug, Clone)]
+struct TestService {
+    timer: Timer,
+}
+
+impl server::TestService for TestService {
+    type EmptyCallFuture = future::FutureResult<Response<Empty>, Error>;
+
+    fn empty_call(&mut self, _request: Request<Empty>) -> Self::EmptyCallFuture {
+        future::ok(Response::new(Empty {}))
+    }
+
+    type UnaryCallFuture = future::FutureResult<Response<SimpleResponse>, Error>;
+
+    fn unary_call(&mut self, request: Request<SimpleRequest>) -> Self::UnaryCallFuture {
+        let headers = request.headers().clone();
+        let request = request.into_inner();
+
+        if let Some(ref status) = request.response_status {
+            if status.code != 0 {
+                return future::err(status_error(status));
+            }
+        }
+
+        let mut response = Response::new(SimpleResponse {
+            payload: Some(payload(request.response_size as usize)),
+            ..Default::default()
+        });
+        echo_metadata(&headers, response.headers_mut());
+
+        future::ok(response)
+    }
+
+    type CacheableUnaryCallFuture = future::FutureResult<Response<SimpleResponse>, Error>;
+
+    fn cacheable_unary_call(&mut self, request: Request<SimpleRequest>)
+        -> Self::CacheableUnaryCallFuture
+    {
+        self.unary_call(request)
+    }
+
+    type StreamingOutputCallStream = BoxStream<StreamingOutputCallResponse>;
+    type StreamingOutputCallFuture =
+        future::FutureResult<Response<Self::StreamingOutputCallStream>, Error>;
+
+    fn streaming_output_call(&mut self, request: Request<StreamingOutputCallRequest>)
+        -> Self::StreamingOutputCallFuture
+    {
+        let headers = request.headers().clone();
+        let responses = self.responses(request.into_inner().response_parameters);
+
+        let mut response = Response::new(responses);
+        echo_metadata(&headers, response.headers_mut());
+
+        future::ok(response)
+    }
+
+    type StreamingInputCallFuture = BoxFuture<StreamingInputCallResponse>;
+
+    fn streaming_input_call(&mut self, request: Request<Streaming<StreamingInputCallRequest>>)
+        -> Self::StreamingInputCallFuture
+    {
+        let response = request.into_inner()
+            .fold(0, |size, request| {
+                let len = request.payload.map(|p| p.body.len()).unwrap_or(0);
+                Ok::<_, Error>(size + len as i32)
+            })
+            .map(|aggregated_payload_size| {
+                Response::new(StreamingInputCallResponse { aggregated_payload_size })
+            });
+
+        Box::new(response)
+    }
+
+    type FullDuplexCallStream = BoxStream<StreamingOutputCallResponse>;
+    type FullDuplexCallFuture = future::FutureResult<Response<Self::FullDuplexCallStream>, Error>;
+
+    fn full_duplex_call(&mut self, request: Request<Streaming<StreamingOutputCallRequest>>)
+        -> Self::FullDuplexCallFuture
+    {
+        let headers = request.headers().clone();
+        let service = self.clone();
+
+        // Errors can't be sent once a stream has started, so a requested
+        // `response_status` is not echoed on streaming calls.
+        let responses = request.into_inner()
+            .map(move |request| service.responses(request.response_parameters))
+            .flatten();
+
+        let mut response = Response::new(Box::new(responses) as Self::FullDuplexCallStream);
+        echo_metadata(&headers, response.headers_mut());
+
+        future::ok(response)
+    }
+
+    type HalfDuplexCallStream = BoxStream<StreamingOutputCallResponse>;
+    type HalfDuplexCallFuture = BoxFuture<Self::HalfDuplexCallStream>;
+
+    fn half_duplex_call(&mut self, request: Request<Streaming<StreamingOutputCallRequest>>)
+        -> Self::HalfDuplexCallFuture
+    {
+        let service = self.clone();
+
+        // Every request is received before the first response is sent.
+        let response = request.into_inner()
+            .collect()
+            .map(move |requests| {
+                let responses = stream::iter_ok(requests)
+                    .map(move |request| service.responses(request.response_parameters))
+                    .flatten();
+
+                Response::new(Box::new(responses) as Self::HalfDuplexCallStream)
+            });
+
+        Box::new(response)
+    }
+
+    type UnimplementedCallFuture = future::FutureResult<Response<Empty>, Error>;
+
+    fn unimplemented_call(&mut self, _request: Request<Empty>) -> Self::UnimplementedCallFuture {
+        future::err(tower_grpc::Error::Grpc(Status::UNIMPLEMENTED))
+    }
+}
+
+impl TestService {
+    /// Returns a stream sending a response for each of `parameters`, after
+    /// the requested interval.
+    fn responses(&self, parameters: Vec<ResponseParameters>) -> BoxStream<StreamingOutputCallResponse> {
+        let timer = self.timer.clone();
+
+        let responses = stream::iter_ok(parameters)
+            .and_then(move |parameters| {
+                let interval = micros(parameters.interval_us);
+
+                timer.sleep(interval)
+                    .map_err(|e| {
+                        error!("timer error: {:?}", e);
+                        tower_grpc::Error::Grpc(Status::INTERNAL)
+                    })
+                    .map(move |()| StreamingOutputCallResponse {
+                        payload: Some(payload(parameters.size as usize)),
+                    })
+            });
+
+        Box::new(responses)
+    }
+}
+
+fn payload(size: usize) -> Payload {
+    Payload {
+        type_: Default::default(),
+        body: vec![0; size],
+    }
+}
+
+fn micros(us: i32) -> Duration {
+    let us = if us > 0 { us as u64 } else { 0 };
+    Duration::new(us / 1_000_000, (us % 1_000_000) as u32 * 1_000)
+}
+
+fn status_error(status: &EchoStatus) -> Error {
+    let code = Code::from_i32(status.code).unwrap_or(Code::UNKNOWN);
+    tower_grpc::Error::Grpc(Status::with_code(code))
+}
+
+/// Copy the metadata the client asked to have echoed into `response`.
+fn echo_metadata(request: &HeaderMap, response: &mut HeaderMap) {
+    if let Some(value) = request.get(ECHO_INITIAL) {
+        response.insert(ECHO_INITIAL, value.clone());
+    }
+}
+
+fn main() {
+    use clap::{Arg, App};
+    let _ = ::env_logger::init();
+
+    let matches =
+        App::new("interop-server")
+            .author("Eliza Weisman <eliza@buoyant.io>")
+            .arg(Arg::with_name("port")
+                .long("port")
+                .value_name("PORT")
+                .help("The server port to listen on. For example, \"8080\".")
+                .takes_value(true)
+                .default_value("10000")
+            )
+            .arg(Arg::with_name("use_tls")
+                .long("use_tls")
+                .help("Whether to use a plaintext or encrypted connection.")
+                .takes_value(true)
+                .value_name("BOOLEAN")
+                .possible_values(&["true", "false"])
+                .default_value("false")
+                .validator(|s|
+                    if s == "true" {
+                        Err(String::from(
+                            "tower-grpc does not currently support TLS."
+                        ))
+                    } else {
+                        Ok(())
+                    }
+                )
+            )
+            .get_matches();
+
+    let port = value_t!(matches, "port", u16)
+        .unwrap_or_else(|e| e.exit());
+
+    let mut core = Core::new().expect("could not create reactor core!");
+    let reactor = core.handle();
+
+    let new_service = server::TestServiceServer::new(TestService {
+        timer: Timer::default(),
+    });
+
+    let h2 = Server::new(new_service, Default::default(), reactor.clone());
+
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let bind = TcpListener::bind(&addr, &reactor).expect("bind");
+
+    info!("listening; addr={:?}", addr);
+
+    let serve = bind.incoming()
+        .fold((h2, reactor), |(h2, reactor), (sock, _)| {
+            if let Err(e) = sock.set_nodelay(true) {
+                return Err(e);
+            }
+
+            let serve = h2.serve(sock);
+            reactor.spawn(serve.map_err(|e| error!("h2 error: {:?}", e)));
+
+            Ok((h2, reactor))
+        });
+
+    core.run(serve).unwrap();
 }
@@ -7,4 +7,14 @@ pub fn client_payload(size: usize) -> pb::Payload {
         type_: default::Default::default(),
         body: iter::repeat(0u8).take(size).collect(),
     }
-}
\ No newline at end of file
+}
+pub fn ping(request_size: usize, response_size: i32) -> pb::StreamingOutputCallRequest {
+    pb::StreamingOutputCallRequest {
+        response_parameters: vec![pb::ResponseParameters {
+            size: response_size,
+            ..Default::default()
+        }],
+        payload: Some(client_payload(request_size)),
+        ..Default::default()
+    }
+}